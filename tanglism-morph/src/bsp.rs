@@ -0,0 +1,169 @@
+//! 买卖点
+//!
+//! 缠论的三类买卖点
+//!
+//! 第一类：至少包含两个中枢的下跌（上涨）走势结束，其终点为第一类买（卖）点。
+//! 第二类：第一类买（卖）点后首次回调（反弹）不破该点，回调的终点为第二类买（卖）点。
+//! 第三类：次级别走势向上（向下）离开中枢后，回调（反弹）不回到中枢区间，
+//! 回调的终点为第三类买（卖）点。
+//! 第二类与第三类可能重合，此时分别输出。
+
+use crate::shape::{Center, CenterElement, Choice, SubTrend, Trend, ValuePoint};
+use bigdecimal::BigDecimal;
+use chrono::NaiveDateTime;
+use serde_derive::*;
+
+/// 买卖点
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BuySellPoint {
+    pub kind: Choice,
+    pub ts: NaiveDateTime,
+    pub price: BigDecimal,
+    // 对应中枢在中枢元素序列中的下标，第一二类为走势的最后一个中枢
+    pub center_ref: usize,
+}
+
+/// 由次级别走势、中枢元素及走势序列识别买卖点，按时刻排序
+///
+/// 中枢的次级别走势下标对应subtrends，走势均为已完成的走势
+pub fn find_buy_sell_points(
+    subtrends: &[SubTrend],
+    centers: &[CenterElement],
+    trends: &[Trend],
+) -> Vec<BuySellPoint> {
+    let mut rst = Vec::new();
+    for tr in trends.iter().filter(|tr| tr.centers >= 2) {
+        let center_ref = match centers
+            .iter()
+            .rposition(|ce| ce.center().is_some() && ce.start().ts <= tr.end.ts)
+        {
+            Some(idx) => idx,
+            None => continue,
+        };
+        let upward = tr.end.value > tr.start.value;
+        let (one, two) = if upward {
+            (Choice::SellOne, Choice::SellTwo)
+        } else {
+            (Choice::BuyOne, Choice::BuyTwo)
+        };
+        let end = extremum(subtrends, &centers[center_ref], &tr.end, upward);
+        rst.push(point(one, end, center_ref));
+        if let Some(pt) = second_point(subtrends, end, upward) {
+            rst.push(point(two, pt, center_ref));
+        }
+    }
+    for (idx, ce) in centers.iter().enumerate() {
+        if let Some(c) = ce.center() {
+            rst.extend(third_points(subtrends, c, idx));
+        }
+    }
+    rst.sort_by_key(|p| p.ts);
+    rst
+}
+
+fn point(kind: Choice, vp: &ValuePoint, center_ref: usize) -> BuySellPoint {
+    BuySellPoint {
+        kind,
+        ts: vp.ts,
+        price: vp.value.clone(),
+        center_ref,
+    }
+}
+
+// 中枢的最高最低点不含延伸段，因此在最后一个中枢的次级别走势及走势终点中取极值
+fn extremum<'a>(
+    subtrends: &'a [SubTrend],
+    ce: &CenterElement,
+    end: &'a ValuePoint,
+    upward: bool,
+) -> &'a ValuePoint {
+    let (start_idx, end_idx) = ce.center().map(|c| c.subtrend_range).unwrap_or_default();
+    let mut rst = end;
+    for st in subtrends.get(start_idx..=end_idx).unwrap_or_default() {
+        if (upward && st.end.value > rst.value) || (!upward && st.end.value < rst.value) {
+            rst = &st.end;
+        }
+    }
+    rst
+}
+
+// 走势终点后的第二段次级别走势即首次回调（反弹），终点不破走势终点时成立
+fn second_point<'a>(
+    subtrends: &'a [SubTrend],
+    end: &ValuePoint,
+    upward: bool,
+) -> Option<&'a ValuePoint> {
+    let idx = subtrends.iter().position(|st| st.end == *end)?;
+    let pt = &subtrends.get(idx + 2)?.end;
+    if (upward && pt.value < end.value) || (!upward && pt.value > end.value) {
+        Some(pt)
+    } else {
+        None
+    }
+}
+
+// 离开段与中枢区间重叠时计入中枢延伸，因此自中枢的最后一段起检查离开段及其后的回调
+fn third_points(subtrends: &[SubTrend], c: &Center, center_ref: usize) -> Vec<BuySellPoint> {
+    let (_, end_idx) = c.subtrend_range;
+    let mut rst = Vec::new();
+    for leave_idx in end_idx..end_idx + 2 {
+        let (leave, back) = match (subtrends.get(leave_idx), subtrends.get(leave_idx + 1)) {
+            (Some(leave), Some(back)) => (leave, back),
+            _ => break,
+        };
+        if leave.end.value > c.shared_high.value
+            && leave.end.value > leave.start.value
+            && back.end.value > c.shared_high.value
+        {
+            rst.push(point(Choice::BuyThree, &back.end, center_ref));
+            break;
+        }
+        if leave.end.value < c.shared_low.value
+            && leave.end.value < leave.start.value
+            && back.end.value < c.shared_low.value
+        {
+            rst.push(point(Choice::SellThree, &back.end, center_ref));
+            break;
+        }
+    }
+    rst
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::center::unify_centers;
+    use crate::shape::new_daily_sts;
+    use crate::trend::unify_trends;
+
+    #[test]
+    fn test_find_buy_sell_points() {
+        // 每段次级别走势间隔一日
+        let subtrends = new_daily_sts(
+            "2020-02-03 15:00",
+            &[
+                "20.0", "18.0", "19.0", "18.2", "18.8", "15.0", "16.0", "15.2", "15.8", "13.0",
+                "16.0", "15.0", "15.9", "15.3", "17.0", "16.2", "16.8", "16.4",
+            ],
+            1,
+        );
+        let centers = unify_centers(&subtrends);
+        let trends = unify_trends(&centers);
+        assert_eq!(1, trends.len());
+        let bsps = find_buy_sell_points(&subtrends, &centers, &trends);
+        let kinds: Vec<(Choice, String, usize)> = bsps
+            .iter()
+            .map(|b| (b.kind, b.price.to_string(), b.center_ref))
+            .collect();
+        // 下跌走势的第二个中枢延伸至13.0，一买取该点而非走势终点15.0
+        assert_eq!(
+            vec![
+                (Choice::SellThree, "16.0".to_owned(), 1),
+                (Choice::BuyOne, "13.0".to_owned(), 3),
+                (Choice::BuyTwo, "15.0".to_owned(), 3),
+                (Choice::BuyThree, "16.2".to_owned(), 3),
+            ],
+            kinds
+        );
+    }
+}
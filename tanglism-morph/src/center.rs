@@ -1,10 +1,14 @@
-use crate::shape::{Center, CenterElement, SemiCenter, SubTrend};
+use crate::shape::{Center, CenterElement, SemiCenter, SubTrend, SubTrendType};
+use crate::stream::{tail_deltas, Accumulator, Delta};
+use crate::subtrend::SubTrendDelta;
+use crate::{Error, Result};
 use bigdecimal::BigDecimal;
+use serde_derive::*;
 
 /// 临时元素
 ///
 /// 用于标记尚未完成的中枢元素序列
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 enum TemporaryElement {
     Center(TemporaryCenter),
     SubTrend(TemporarySubTrend),
@@ -12,7 +16,7 @@ enum TemporaryElement {
     SemiCenter(TemporarySemiCenter),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct TemporaryCenter {
     //起始三段的下标
     start_idx: usize,
@@ -29,14 +33,14 @@ impl TemporaryCenter {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct TemporarySubTrend {
     idx: usize,
     // 是否紧邻一个类中枢
     beside_semi: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct TemporarySemiCenter {
     // 起始三段的下标
     start_idx: usize,
@@ -58,10 +62,29 @@ impl TemporarySemiCenter {
 }
 
 pub fn unify_centers(subtrends: &[SubTrend]) -> Vec<CenterElement> {
-    let standard = Standard::new();
+    unify_centers_with_cfg(subtrends, &CenterConfig::default())
+}
+
+pub fn unify_centers_with_cfg(subtrends: &[SubTrend], cfg: &CenterConfig) -> Vec<CenterElement> {
+    let standard = Standard::new(cfg.clone());
     standard.aggregate(subtrends)
 }
 
+/// 中枢配置
+#[derive(Debug, Clone, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CenterConfig {
+    // 组合次级别走势是否可作为中枢的起始段
+    pub combination_seed: bool,
+}
+
+impl Default for CenterConfig {
+    fn default() -> Self {
+        CenterConfig {
+            combination_seed: true,
+        }
+    }
+}
+
 /// 中枢策略
 ///
 /// 将次级别走势转化为中枢元素序列。
@@ -70,8 +93,10 @@ pub trait CenterStrategy {
     fn aggregate(self, subtrends: &[SubTrend]) -> Vec<CenterElement>;
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 struct Standard {
     tmp: Vec<TemporaryElement>,
+    cfg: CenterConfig,
 }
 
 impl CenterStrategy for Standard {
@@ -84,8 +109,17 @@ impl CenterStrategy for Standard {
 }
 
 impl Standard {
-    fn new() -> Self {
-        Standard { tmp: Vec::new() }
+    fn new(cfg: CenterConfig) -> Self {
+        Standard {
+            tmp: Vec::new(),
+            cfg,
+        }
+    }
+
+    // 次级别走势是否可作为中枢起始段
+    #[inline]
+    fn seedable(&self, subtrend: &SubTrend) -> bool {
+        self.cfg.combination_seed || subtrend.typ != SubTrendType::Combination
     }
 
     #[inline]
@@ -112,30 +146,7 @@ impl Standard {
     }
 
     fn centers(self, subtrends: &[SubTrend]) -> Vec<CenterElement> {
-        self.tmp
-            .into_iter()
-            .map(|te| match te {
-                TemporaryElement::Center(tc) => {
-                    let mut c = center(&subtrends[tc.start_idx..=tc.end_idx]).unwrap();
-                    if tc.extended_subtrends > 0 {
-                        c.end = subtrends[tc.end_idx + tc.extended_subtrends].end.clone();
-                        c.n += tc.extended_subtrends;
-                    }
-                    CenterElement::Center(c)
-                }
-                TemporaryElement::SubTrend(tst) => {
-                    CenterElement::SubTrend(subtrends[tst.idx].clone())
-                }
-                TemporaryElement::SemiCenter(tsc) => {
-                    let sc = semicenter(
-                        &subtrends[tsc.start_idx..=tsc.last_end_idx()],
-                        tsc.shared_start,
-                    )
-                    .unwrap();
-                    CenterElement::SemiCenter(sc)
-                }
-            })
-            .collect()
+        self.tmp.iter().map(|te| element(te, subtrends)).collect()
     }
 
     // 前一个元素是中枢
@@ -160,6 +171,7 @@ impl Standard {
         // 仅以前三段获取中枢区间
         let prev_center = center(center_data).expect("center created from subtrends");
         if tmp_center.extended_subtrends == 0
+            && self.seedable(&subtrends[tmp_center.start_idx + 1])
             && center(&subtrends[tmp_center.start_idx + 1..=idx]).is_some()
         {
             // 当前段和中枢起始段相比，是否更靠近中枢区间
@@ -238,7 +250,7 @@ impl Standard {
                 let subtrend1 = &subtrends[st1.idx];
                 let subtrend2 = &subtrends[st2.idx];
                 if let Some(c) = center3(subtrend1, subtrend2, subtrend) {
-                    if st1.beside_semi {
+                    if st1.beside_semi && self.seedable(subtrend1) {
                         // 起始段紧邻类中枢，该中枢固定不可移动
                         let c = TemporaryCenter {
                             start_idx: st1.idx,
@@ -257,6 +269,9 @@ impl Standard {
                         };
                         self.remove_lastn(2);
                         self.push_semicenter(sc);
+                    } else if !self.seedable(subtrend1) {
+                        // 起始段不可构成中枢
+                        self.push_subtrend(idx, false);
                     } else {
                         let c = TemporaryCenter {
                             start_idx: st1.idx,
@@ -295,7 +310,7 @@ impl Standard {
                         self.push_semicenter(sc);
                     } else {
                         // 若前一中枢存在延伸，可以借取最后一段形成中枢
-                        if c1.extended_subtrends > 0 {
+                        if c1.extended_subtrends > 0 && self.seedable(subtrend1) {
                             let c = TemporaryCenter {
                                 start_idx: st1_idx,
                                 end_idx: idx,
@@ -355,7 +370,7 @@ impl Standard {
         let st1_idx = st2_idx - 1;
         let subtrend1 = &subtrends[st1_idx];
         let subtrend2 = &subtrends[st2_idx];
-        if center3(subtrend1, subtrend2, subtrend).is_some() {
+        if self.seedable(subtrend1) && center3(subtrend1, subtrend2, subtrend).is_some() {
             if tmp_sc.extended_subtrends >= 2 {
                 // case 1-a
                 self.modify_last_semicenter(|sc| {
@@ -450,6 +465,117 @@ impl Standard {
     }
 }
 
+fn element(te: &TemporaryElement, subtrends: &[SubTrend]) -> CenterElement {
+    match te {
+        TemporaryElement::Center(tc) => {
+            let mut c = center(&subtrends[tc.start_idx..=tc.end_idx]).unwrap();
+            if tc.extended_subtrends > 0 {
+                c.end = subtrends[tc.end_idx + tc.extended_subtrends].end.clone();
+                c.n += tc.extended_subtrends;
+            }
+            c.subtrend_range = (tc.start_idx, tc.last_end_idx());
+            c.extended = tc.extended_subtrends;
+            CenterElement::Center(c)
+        }
+        TemporaryElement::SubTrend(tst) => CenterElement::SubTrend(subtrends[tst.idx].clone()),
+        TemporaryElement::SemiCenter(tsc) => {
+            let mut sc = semicenter(
+                &subtrends[tsc.start_idx..=tsc.last_end_idx()],
+                tsc.shared_start,
+            )
+            .unwrap();
+            sc.subtrend_range = (tsc.start_idx, tsc.last_end_idx());
+            sc.extended = tsc.extended_subtrends;
+            CenterElement::SemiCenter(sc)
+        }
+    }
+}
+
+pub type CenterDelta = Delta<CenterElement>;
+
+/// 中枢累加器
+///
+/// 接收次级别走势的变更，输出中枢元素的有序变更，与unify_centers_with_cfg的结果一致。
+/// 每个次级别走势仅影响末尾两个临时元素，累加前保存，更新或删除时据此回退后重新累加
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CenterAccumulator {
+    standard: Standard,
+    subtrends: Vec<SubTrend>,
+    // 累加各次级别走势前的临时元素数及末尾两个元素
+    marks: Vec<(usize, Vec<TemporaryElement>)>,
+    elems: Vec<CenterElement>,
+}
+
+impl CenterAccumulator {
+    pub fn new(cfg: CenterConfig) -> Self {
+        CenterAccumulator {
+            standard: Standard::new(cfg),
+            subtrends: Vec::new(),
+            marks: Vec::new(),
+            elems: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, subtrend: SubTrend) {
+        let tmp = &self.standard.tmp;
+        let len = tmp.len();
+        self.marks
+            .push((len, tmp[len.saturating_sub(2)..].to_vec()));
+        self.subtrends.push(subtrend);
+        self.standard
+            .accumulate(&self.subtrends, self.subtrends.len() - 1);
+    }
+
+    fn pop(&mut self) -> Result<()> {
+        match self.marks.pop() {
+            Some((len, tail)) => {
+                self.standard.tmp.truncate(len.saturating_sub(2));
+                self.standard.tmp.extend(tail);
+                self.subtrends.pop();
+                Ok(())
+            }
+            None => Err(Error::Msg("no subtrend to update or delete".to_owned())),
+        }
+    }
+}
+
+impl Accumulator<SubTrendDelta> for CenterAccumulator {
+    // 一次输入可能产生多个变更，按序作用于末尾元素
+    type Delta = Vec<CenterDelta>;
+    type State = Vec<CenterElement>;
+
+    fn accumulate(&mut self, item: &SubTrendDelta) -> Result<Vec<CenterDelta>> {
+        let mut from = self.standard.tmp.len().saturating_sub(2);
+        match item {
+            Delta::None => return Ok(Vec::new()),
+            Delta::Add(st) => self.push(st.clone()),
+            Delta::Update(st) => {
+                self.pop()?;
+                from = from.min(self.standard.tmp.len().saturating_sub(2));
+                self.push(st.clone());
+            }
+            Delta::Delete(_) => {
+                self.pop()?;
+                from = from.min(self.standard.tmp.len().saturating_sub(2));
+            }
+        }
+        // 之前的临时元素及其引用的次级别走势均未变化
+        let from = from.min(self.elems.len()).min(self.standard.tmp.len());
+        let new: Vec<CenterElement> = self.standard.tmp[from..]
+            .iter()
+            .map(|te| element(te, &self.subtrends))
+            .collect();
+        let deltas = tail_deltas(&self.elems[from..], &new);
+        self.elems.truncate(from);
+        self.elems.extend(new);
+        Ok(deltas)
+    }
+
+    fn state(&self) -> &Self::State {
+        &self.elems
+    }
+}
+
 /// 由连续三段次级别走势构成中枢
 fn center(subtrends: &[SubTrend]) -> Option<Center> {
     if subtrends.len() < 3 {
@@ -497,6 +623,8 @@ fn center3(s1: &SubTrend, s2: &SubTrend, s3: &SubTrend) -> Option<Center> {
         level,
         upward: s1.end.value > s1.start.value,
         n: 3,
+        subtrend_range: (0, 2),
+        extended: 0,
     })
 }
 
@@ -517,6 +645,8 @@ fn semicenter(subtrends: &[SubTrend], shared_start: bool) -> Option<SemiCenter>
         upward,
         n,
         shared_start,
+        subtrend_range: (0, n - 1),
+        extended: n - 3,
     })
 }
 
@@ -543,73 +673,82 @@ fn abs_diff(v1: &BigDecimal, v2: &BigDecimal) -> BigDecimal {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::shape::{SubTrendType, ValuePoint};
+    use crate::shape::{new_sts, new_vp, SubTrendType};
     use bigdecimal::BigDecimal;
     use chrono::NaiveDateTime;
+    use tanglism_utils::price;
 
     #[test]
     fn test_center3_single() {
-        let sts = vec![
-            ("2020-02-10 15:00", 10.0),
-            ("2020-02-11 15:00", 11.0),
-            ("2020-02-12 15:00", 10.5),
-            ("2020-02-13 15:00", 11.5),
-        ]
-        .build(1);
+        let sts = new_sts(
+            &[
+                ("2020-02-10 15:00", "10.0"),
+                ("2020-02-11 15:00", "11.0"),
+                ("2020-02-12 15:00", "10.5"),
+                ("2020-02-13 15:00", "11.5"),
+            ],
+            1,
+        );
         let c = center3(&sts[0], &sts[1], &sts[2]).unwrap();
         assert_eq!(1, c.level);
         assert_eq!(new_ts("2020-02-10 15:00"), c.start.ts);
         assert_eq!(BigDecimal::from(10), c.start.value);
         assert_eq!(new_ts("2020-02-13 15:00"), c.end.ts);
-        assert_eq!(BigDecimal::from(11.5), c.end.value);
-        assert_eq!(BigDecimal::from(10.5), c.shared_low.value);
+        assert_eq!(price!(11.5), c.end.value);
+        assert_eq!(price!(10.5), c.shared_low.value);
         assert_eq!(BigDecimal::from(11), c.shared_high.value);
         assert_eq!(BigDecimal::from(10), c.low.value);
-        assert_eq!(BigDecimal::from(11.5), c.high.value);
+        assert_eq!(price!(11.5), c.high.value);
     }
 
     #[test]
     fn test_center3_narrow() {
-        let sts = vec![
-            ("2020-02-10 15:00", 15.0),
-            ("2020-02-11 15:00", 15.5),
-            ("2020-02-12 15:00", 14.5),
-            ("2020-02-13 15:00", 15.2),
-        ]
-        .build(1);
+        let sts = new_sts(
+            &[
+                ("2020-02-10 15:00", "15.0"),
+                ("2020-02-11 15:00", "15.5"),
+                ("2020-02-12 15:00", "14.5"),
+                ("2020-02-13 15:00", "15.2"),
+            ],
+            1,
+        );
         let c = center3(&sts[0], &sts[1], &sts[2]).unwrap();
         assert_eq!(1, c.level);
         assert_eq!(new_ts("2020-02-10 15:00"), c.start.ts);
         assert_eq!(BigDecimal::from(15), c.start.value);
         assert_eq!(new_ts("2020-02-13 15:00"), c.end.ts);
-        assert_eq!(BigDecimal::from(15.2), c.end.value);
+        assert_eq!(price!(15.2), c.end.value);
         assert_eq!(BigDecimal::from(15), c.shared_low.value);
-        assert_eq!(BigDecimal::from(15.2), c.shared_high.value);
-        assert_eq!(BigDecimal::from(14.5), c.low.value);
-        assert_eq!(BigDecimal::from(15.5), c.high.value);
+        assert_eq!(price!(15.2), c.shared_high.value);
+        assert_eq!(price!(14.5), c.low.value);
+        assert_eq!(price!(15.5), c.high.value);
     }
 
     #[test]
     fn test_center3_none() {
-        let sts = vec![
-            ("2020-02-10 15:00", 10.0),
-            ("2020-02-11 15:00", 10.2),
-            ("2020-02-12 15:00", 9.5),
-            ("2020-02-13 15:00", 9.8),
-        ]
-        .build(1);
+        let sts = new_sts(
+            &[
+                ("2020-02-10 15:00", "10.0"),
+                ("2020-02-11 15:00", "10.2"),
+                ("2020-02-12 15:00", "9.5"),
+                ("2020-02-13 15:00", "9.8"),
+            ],
+            1,
+        );
         assert!(center3(&sts[0], &sts[1], &sts[2]).is_none());
     }
 
     #[test]
     fn test_centers_no_overlap() {
-        let sts = vec![
-            ("2020-02-10 15:00", 11.0),
-            ("2020-02-11 15:00", 11.2),
-            ("2020-02-12 15:00", 10.0),
-            ("2020-02-13 15:00", 10.5),
-        ]
-        .build(1);
+        let sts = new_sts(
+            &[
+                ("2020-02-10 15:00", "11.0"),
+                ("2020-02-11 15:00", "11.2"),
+                ("2020-02-12 15:00", "10.0"),
+                ("2020-02-13 15:00", "10.5"),
+            ],
+            1,
+        );
         let cs = unify_centers(&sts);
         assert_eq!(3, cs.len());
         assert!(cs.iter().all(|c| c.center().is_none()));
@@ -617,14 +756,16 @@ mod tests {
 
     #[test]
     fn test_centers_semi() {
-        let sts = vec![
-            ("2020-02-07 15:00", 10.0),
-            ("2020-02-10 15:00", 11.0),
-            ("2020-02-11 15:00", 10.5),
-            ("2020-02-12 15:00", 11.5),
-            ("2020-02-13 15:00", 11.2),
-        ]
-        .build(1);
+        let sts = new_sts(
+            &[
+                ("2020-02-07 15:00", "10.0"),
+                ("2020-02-10 15:00", "11.0"),
+                ("2020-02-11 15:00", "10.5"),
+                ("2020-02-12 15:00", "11.5"),
+                ("2020-02-13 15:00", "11.2"),
+            ],
+            1,
+        );
         let cs = unify_centers(&sts);
         assert_eq!(2, cs.len());
         assert!(cs[0].semicenter().is_some());
@@ -633,69 +774,102 @@ mod tests {
 
     #[test]
     fn test_centers_single() {
-        let sts = vec![
-            ("2020-02-07 15:00", 13.0),
-            ("2020-02-10 15:00", 10.0),
-            ("2020-02-11 15:00", 11.0),
-            ("2020-02-12 15:00", 10.5),
-            ("2020-02-13 15:00", 11.5),
-        ]
-        .build(1);
+        let sts = new_sts(
+            &[
+                ("2020-02-07 15:00", "13.0"),
+                ("2020-02-10 15:00", "10.0"),
+                ("2020-02-11 15:00", "11.0"),
+                ("2020-02-12 15:00", "10.5"),
+                ("2020-02-13 15:00", "11.5"),
+            ],
+            1,
+        );
         let cs = unify_centers(&sts);
         assert_eq!(2, cs.len());
         assert!(cs[0].subtrend().is_some());
         let c1 = cs[1].center().expect("expect center");
-        assert_eq!(BigDecimal::from(10.5), c1.shared_low.value);
+        assert_eq!(price!(10.5), c1.shared_low.value);
         assert_eq!(BigDecimal::from(11), c1.shared_high.value);
         assert_eq!(3, c1.n);
+        assert_eq!((1, 3), c1.subtrend_range);
+        assert_eq!(0, c1.extended);
+    }
+
+    #[test]
+    fn test_centers_combination_seed() {
+        let mut sts = new_sts(
+            &[
+                ("2020-02-07 15:00", "13.0"),
+                ("2020-02-10 15:00", "10.0"),
+                ("2020-02-11 15:00", "11.0"),
+                ("2020-02-12 15:00", "10.5"),
+                ("2020-02-13 15:00", "11.5"),
+            ],
+            1,
+        );
+        sts[1].typ = SubTrendType::Combination;
+        let cs = unify_centers(&sts);
+        assert_eq!(2, cs.len());
+        // 组合次级别走势不可作为起始段，中枢不迁移
+        let cfg = CenterConfig {
+            combination_seed: false,
+        };
+        let cs = unify_centers_with_cfg(&sts, &cfg);
+        assert_eq!(1, cs.len());
+        let c0 = cs[0].center().expect("expect center");
+        assert_eq!(new_ts("2020-02-07 15:00"), c0.start.ts);
     }
 
     #[test]
     fn test_centers_double() {
-        let sts = vec![
-            ("2020-02-07 15:00", 13.0),
-            ("2020-02-10 15:00", 10.0),
-            ("2020-02-11 15:00", 11.0),
-            ("2020-02-12 15:00", 10.5),
-            ("2020-02-13 15:00", 11.5),
-            ("2020-02-18 15:00", 8.0),
-            ("2020-02-19 15:00", 8.5),
-            ("2020-02-20 15:00", 8.2),
-            ("2020-02-21 15:00", 9.5),
-        ]
-        .build(1);
+        let sts = new_sts(
+            &[
+                ("2020-02-07 15:00", "13.0"),
+                ("2020-02-10 15:00", "10.0"),
+                ("2020-02-11 15:00", "11.0"),
+                ("2020-02-12 15:00", "10.5"),
+                ("2020-02-13 15:00", "11.5"),
+                ("2020-02-18 15:00", "8.0"),
+                ("2020-02-19 15:00", "8.5"),
+                ("2020-02-20 15:00", "8.2"),
+                ("2020-02-21 15:00", "9.5"),
+            ],
+            1,
+        );
         let cs = unify_centers(&sts);
         // todo
         // assert_eq!(4, cs.len());
         assert!(cs[0].subtrend().is_some());
         let c1 = cs[1].center().expect("expect center");
         assert_eq!(new_ts("2020-02-10 15:00"), c1.start.ts);
-        assert_eq!(BigDecimal::from(10.0), c1.start.value);
+        assert_eq!(price!(10.0), c1.start.value);
         assert_eq!(new_ts("2020-02-13 15:00"), c1.end.ts);
-        assert_eq!(BigDecimal::from(11.5), c1.end.value);
+        assert_eq!(price!(11.5), c1.end.value);
         assert!(cs[2].subtrend().is_some());
         let c3 = cs[3].center().expect("expect center");
         assert_eq!(new_ts("2020-02-18 15:00"), c3.start.ts);
         assert_eq!(BigDecimal::from(8), c3.start.value);
         assert_eq!(new_ts("2020-02-21 15:00"), c3.end.ts);
-        assert_eq!(BigDecimal::from(9.5), c3.end.value);
-        assert_eq!(BigDecimal::from(8.2), c3.shared_low.value);
-        assert_eq!(BigDecimal::from(8.5), c3.shared_high.value);
-        assert_eq!(BigDecimal::from(8.0), c3.low.value);
-        assert_eq!(BigDecimal::from(9.5), c3.high.value);
+        assert_eq!(price!(9.5), c3.end.value);
+        assert_eq!(price!(8.2), c3.shared_low.value);
+        assert_eq!(price!(8.5), c3.shared_high.value);
+        assert_eq!(price!(8.0), c3.low.value);
+        assert_eq!(price!(9.5), c3.high.value);
     }
 
     #[test]
     fn test_centers_extension_simple() {
-        let sts = vec![
-            ("2020-02-07 15:00", 13.0),
-            ("2020-02-10 15:00", 10.0),
-            ("2020-02-11 15:00", 11.0),
-            ("2020-02-12 15:00", 10.5),
-            ("2020-02-13 15:00", 11.5),
-            ("2020-02-18 15:00", 10.8),
-        ]
-        .build(1);
+        let sts = new_sts(
+            &[
+                ("2020-02-07 15:00", "13.0"),
+                ("2020-02-10 15:00", "10.0"),
+                ("2020-02-11 15:00", "11.0"),
+                ("2020-02-12 15:00", "10.5"),
+                ("2020-02-13 15:00", "11.5"),
+                ("2020-02-18 15:00", "10.8"),
+            ],
+            1,
+        );
         let cs = unify_centers(&sts);
         assert_eq!(2, cs.len());
         assert!(cs[0].subtrend().is_some());
@@ -706,16 +880,18 @@ mod tests {
 
     #[test]
     fn test_centers_extension_through() {
-        let sts = vec![
-            ("2020-02-07 15:00", 13.0),
-            ("2020-02-10 15:00", 10.0),
-            ("2020-02-11 15:00", 11.0),
-            ("2020-02-12 15:00", 10.5),
-            ("2020-02-13 15:00", 11.5),
-            ("2020-02-18 15:00", 9.0),
-            ("2020-02-19 15:00", 12.0),
-        ]
-        .build(1);
+        let sts = new_sts(
+            &[
+                ("2020-02-07 15:00", "13.0"),
+                ("2020-02-10 15:00", "10.0"),
+                ("2020-02-11 15:00", "11.0"),
+                ("2020-02-12 15:00", "10.5"),
+                ("2020-02-13 15:00", "11.5"),
+                ("2020-02-18 15:00", "9.0"),
+                ("2020-02-19 15:00", "12.0"),
+            ],
+            1,
+        );
         let cs = unify_centers(&sts);
         assert_eq!(2, cs.len());
         assert!(cs[0].subtrend().is_some());
@@ -723,17 +899,21 @@ mod tests {
         assert_eq!(new_ts("2020-02-10 15:00"), c1.start.ts);
         assert_eq!(new_ts("2020-02-19 15:00"), c1.end.ts);
         assert_eq!(5, c1.n);
+        assert_eq!((1, 5), c1.subtrend_range);
+        assert_eq!(2, c1.extended);
     }
 
     #[test]
     fn test_centers_semi_simple() {
-        let sts = vec![
-            ("2020-02-07 15:00", 13.0),
-            ("2020-02-10 15:00", 11.0),
-            ("2020-02-11 15:00", 11.5),
-            ("2020-02-12 15:00", 10.0),
-        ]
-        .build(1);
+        let sts = new_sts(
+            &[
+                ("2020-02-07 15:00", "13.0"),
+                ("2020-02-10 15:00", "11.0"),
+                ("2020-02-11 15:00", "11.5"),
+                ("2020-02-12 15:00", "10.0"),
+            ],
+            1,
+        );
         let cs = unify_centers(&sts);
         assert_eq!(1, cs.len());
         let c0 = cs[0].semicenter().expect("expect semicenter");
@@ -743,52 +923,75 @@ mod tests {
 
     #[test]
     fn test_centers_semi_extension() {
-        let sts = vec![
-            ("2020-02-07 15:00", 13.0),
-            ("2020-02-10 15:00", 11.0),
-            ("2020-02-11 15:00", 11.5),
-            ("2020-02-12 15:00", 10.0),
-            ("2020-02-13 15:00", 10.5),
-            ("2020-02-18 15:00", 9.0),
-        ]
-        .build(1);
+        let sts = new_sts(
+            &[
+                ("2020-02-07 15:00", "13.0"),
+                ("2020-02-10 15:00", "11.0"),
+                ("2020-02-11 15:00", "11.5"),
+                ("2020-02-12 15:00", "10.0"),
+                ("2020-02-13 15:00", "10.5"),
+                ("2020-02-18 15:00", "9.0"),
+            ],
+            1,
+        );
         let cs = unify_centers(&sts);
         assert_eq!(1, cs.len());
         let c0 = cs[0].semicenter().expect("expect semicenter");
         assert_eq!(new_ts("2020-02-07 15:00"), c0.start.ts);
         assert_eq!(new_ts("2020-02-18 15:00"), c0.end.ts);
+        assert_eq!((0, 4), c0.subtrend_range);
+        assert_eq!(2, c0.extended);
     }
 
-    fn new_ts(s: &str) -> NaiveDateTime {
-        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
-    }
-
-    fn new_point(ts: &str, price: f64) -> ValuePoint {
-        ValuePoint {
-            ts: new_ts(ts),
-            value: BigDecimal::from(price),
+    // 增量累加及末尾的更新、删除与批量计算一致
+    #[test]
+    fn test_center_accumulator() -> Result<()> {
+        let mut sts = new_sts(
+            &[
+                ("2020-02-07 15:00", "13.0"),
+                ("2020-02-10 15:00", "10.0"),
+                ("2020-02-11 15:00", "11.0"),
+                ("2020-02-12 15:00", "10.5"),
+                ("2020-02-13 15:00", "11.5"),
+                ("2020-02-14 15:00", "10.8"),
+                ("2020-02-17 15:00", "12.5"),
+                ("2020-02-18 15:00", "12.0"),
+                ("2020-02-19 15:00", "13.0"),
+                ("2020-02-20 15:00", "12.2"),
+            ],
+            1,
+        );
+        let mut acc = CenterAccumulator::new(CenterConfig::default());
+        // 按序应用变更的副本
+        let mut replica = Vec::new();
+        for i in 0..sts.len() {
+            for d in acc.accumulate(&Delta::Add(sts[i].clone()))? {
+                crate::stream::apply_delta(&mut replica, d)?;
+            }
+            assert_eq!(&unify_centers(&sts[..=i]), acc.state());
+            assert_eq!(acc.state(), &replica);
         }
+        // 最后一段延伸至中枢上方
+        let last = sts.last_mut().unwrap();
+        last.end = new_vp("2020-02-20 15:00", "14.0");
+        for d in acc.accumulate(&Delta::Update(last.clone()))? {
+            crate::stream::apply_delta(&mut replica, d)?;
+        }
+        assert_eq!(&unify_centers(&sts), acc.state());
+        assert_eq!(acc.state(), &replica);
+        for _ in 0..4 {
+            let st = sts.pop().unwrap();
+            for d in acc.accumulate(&Delta::Delete(st))? {
+                crate::stream::apply_delta(&mut replica, d)?;
+            }
+            assert_eq!(&unify_centers(&sts), acc.state());
+            assert_eq!(acc.state(), &replica);
+        }
+        assert!(acc.state().iter().any(|e| e.center().is_some()));
+        Ok(())
     }
 
-    trait BuildSubTrendVec {
-        fn build(self, level: i32) -> Vec<SubTrend>;
-    }
-
-    impl<'a> BuildSubTrendVec for Vec<(&'a str, f64)> {
-        fn build(self, level: i32) -> Vec<SubTrend> {
-            self.iter()
-                .zip(self.iter().skip(1))
-                .map(|(start, end)| {
-                    let start = new_point(start.0, start.1);
-                    let end = new_point(end.0, end.1);
-                    SubTrend {
-                        start,
-                        end,
-                        level,
-                        typ: SubTrendType::Normal,
-                    }
-                })
-                .collect()
-        }
+    fn new_ts(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
     }
 }
@@ -0,0 +1,188 @@
+//! 背驰
+//!
+//! 缠论的买卖点依赖背驰判断
+//!
+//! 比较进入中枢与离开中枢的两段同向次级别走势，
+//! 离开段创出新高（新低）而MACD面积小于进入段，即为背驰。
+//! 离开段与中枢区间重叠时被计为中枢的延伸，此时取中枢的最后一段作为离开段。
+//! 该中枢与前一中枢同向且无重叠，即处于趋势中时为趋势背驰，否则为盘整背驰。
+
+use crate::shape::{Center, CenterElement, SubTrend, ValuePoint};
+use bigdecimal::BigDecimal;
+use serde_derive::*;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DivergenceKind {
+    // 趋势背驰
+    Trend,
+    // 盘整背驰
+    Consolidation,
+}
+
+/// 背驰
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Divergence {
+    pub kind: DivergenceKind,
+    // 向上走势的背驰，即顶背驰
+    pub upward: bool,
+    // 中枢在中枢元素序列中的下标
+    pub center_idx: usize,
+    // 进入及离开中枢的次级别走势
+    pub enter: SubTrend,
+    pub leave: SubTrend,
+    // 两段走势对应的MACD面积，仅累计与走势同向的柱
+    pub enter_area: BigDecimal,
+    pub leave_area: BigDecimal,
+}
+
+/// 由中枢前后的次级别走势及MACD柱序列识别背驰
+///
+/// 中枢的次级别走势下标对应subtrends，MACD柱需按时刻升序排列
+pub fn find_divergences(
+    subtrends: &[SubTrend],
+    centers: &[CenterElement],
+    macd: &[ValuePoint],
+) -> Vec<Divergence> {
+    let mut rst = Vec::new();
+    let mut prev: Option<&Center> = None;
+    for (idx, ce) in centers.iter().enumerate() {
+        let c = match ce.center() {
+            Some(c) => c,
+            None => continue,
+        };
+        if let Some(dv) = divergence(subtrends, prev, c, idx, macd) {
+            rst.push(dv);
+        }
+        prev = Some(c);
+    }
+    rst
+}
+
+fn divergence(
+    subtrends: &[SubTrend],
+    prev: Option<&Center>,
+    c: &Center,
+    center_idx: usize,
+    macd: &[ValuePoint],
+) -> Option<Divergence> {
+    let (start_idx, end_idx) = c.subtrend_range;
+    let enter = subtrends.get(start_idx.checked_sub(1)?)?;
+    let upward = enter.end.value > enter.start.value;
+    let same_dir = |st: &SubTrend| (st.end.value > st.start.value) == upward;
+    // 离开段与中枢区间重叠时计入中枢延伸，此时取中枢的最后一段
+    let last = subtrends.get(end_idx)?;
+    let leave = if same_dir(last)
+        && ((upward && last.end.value > c.shared_high.value)
+            || (!upward && last.end.value < c.shared_low.value))
+    {
+        last
+    } else {
+        subtrends.get(end_idx + 1).filter(|st| same_dir(st))?
+    };
+    // 离开段须创新高（新低）
+    if (upward && leave.end.value <= enter.end.value)
+        || (!upward && leave.end.value >= enter.end.value)
+    {
+        return None;
+    }
+    let enter_area = area(enter, upward, macd);
+    let leave_area = area(leave, upward, macd);
+    if leave_area >= enter_area {
+        return None;
+    }
+    let kind = match prev {
+        Some(p)
+            if (upward && c.shared_low.value > p.shared_high.value)
+                || (!upward && c.shared_high.value < p.shared_low.value) =>
+        {
+            DivergenceKind::Trend
+        }
+        _ => DivergenceKind::Consolidation,
+    };
+    Some(Divergence {
+        kind,
+        upward,
+        center_idx,
+        enter: enter.clone(),
+        leave: leave.clone(),
+        enter_area,
+        leave_area,
+    })
+}
+
+// 走势区间内与走势同向的MACD柱面积，取绝对值
+fn area(st: &SubTrend, upward: bool, macd: &[ValuePoint]) -> BigDecimal {
+    let zero = BigDecimal::from(0);
+    let begin = macd.partition_point(|m| m.ts <= st.start.ts);
+    let mut sum = BigDecimal::from(0);
+    for m in macd[begin..].iter().take_while(|m| m.ts <= st.end.ts) {
+        if upward && m.value > zero {
+            sum += &m.value;
+        } else if !upward && m.value < zero {
+            sum -= &m.value;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::center::unify_centers;
+    use crate::shape::{new_sts, new_vp};
+    use tanglism_utils::parse_price;
+
+    #[test]
+    fn test_find_divergences() {
+        // 两个依次抬高的中枢，最后一段创新高但力度减弱
+        let subtrends = new_sts(
+            &[
+                ("2020-02-03 15:00", "10.0"),
+                ("2020-02-04 15:00", "12.0"),
+                ("2020-02-05 15:00", "11.0"),
+                ("2020-02-06 15:00", "11.8"),
+                ("2020-02-07 15:00", "11.2"),
+                ("2020-02-10 15:00", "14.0"),
+                ("2020-02-11 15:00", "13.0"),
+                ("2020-02-12 15:00", "13.8"),
+                ("2020-02-13 15:00", "13.2"),
+                ("2020-02-14 15:00", "15.0"),
+            ],
+            1,
+        );
+        let centers = unify_centers(&subtrends);
+        assert_eq!(2, centers.iter().filter(|c| c.center().is_some()).count());
+        let days = [
+            "2020-02-04",
+            "2020-02-05",
+            "2020-02-06",
+            "2020-02-07",
+            "2020-02-10",
+            "2020-02-11",
+            "2020-02-12",
+            "2020-02-13",
+            "2020-02-14",
+        ];
+        let values = [
+            "0.3", "-0.1", "0.1", "-0.1", "0.8", "-0.2", "0.1", "-0.1", "0.5",
+        ];
+        let macd: Vec<ValuePoint> = days
+            .iter()
+            .zip(values.iter())
+            .map(|(d, v)| new_vp(&format!("{} 15:00", d), v))
+            .collect();
+        let dvs = find_divergences(&subtrends, &centers, &macd);
+        assert_eq!(1, dvs.len());
+        let dv = &dvs[0];
+        assert_eq!(DivergenceKind::Trend, dv.kind);
+        assert!(dv.upward);
+        assert_eq!(new_vp("2020-02-14 15:00", "15.0"), dv.leave.end);
+        assert_eq!(parse_price("0.8").unwrap(), dv.enter_area);
+        assert_eq!(parse_price("0.5").unwrap(), dv.leave_area);
+
+        // 离开段力度更强时不构成背驰
+        let mut macd = macd;
+        macd.last_mut().unwrap().value = parse_price("1.0").unwrap();
+        assert!(find_divergences(&subtrends, &centers, &macd).is_empty());
+    }
+}
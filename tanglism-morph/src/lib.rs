@@ -1,29 +1,58 @@
+mod bsp;
 mod center;
+mod divergence;
 mod error;
 mod parting;
+mod pipeline;
 mod segment;
 mod shape;
 mod stream;
 mod stroke;
 mod subtrend;
 mod trend;
+mod validate;
 
 pub use error::Error;
 pub type Result<T> = std::result::Result<T, Error>;
+pub use bsp::*;
 pub use center::*;
-pub use parting::ks_to_pts;
-pub use segment::sks_to_sgs;
+pub use divergence::*;
+pub use parting::{
+    ks_to_pts, ks_to_pts_partitioned, ks_to_pts_snapshot, ks_to_pts_with_cfg, KDelta,
+    PartingAccSnapshot, PartingConfig, PartingDelta,
+};
+pub use pipeline::{MorphCheckpoint, MorphDelta, MorphPipeline};
+pub use segment::{
+    fill_stroke_ranges, sks_to_sgs, sks_to_sgs_partitioned, sks_to_sgs_snapshot, sks_to_sgs_traced,
+    SegmentAccSnapshot, SegmentDelta, SegmentStageSnapshot,
+};
 pub use shape::*;
+pub use stream::{
+    day_boundaries, Accumulator, Delta, ReplicaClient, ReplicaMessage, ReplicaPublisher,
+    Replicator, Trace,
+};
 pub use stroke::*;
 pub use subtrend::*;
 pub use trend::*;
+pub use validate::{
+    validate_centers, validate_segments, validate_strokes, ShapeWarning, WarningKind,
+};
 
 pub mod prelude {
+    pub use crate::bsp::*;
     pub use crate::center::*;
-    pub use crate::parting::ks_to_pts;
-    pub use crate::segment::sks_to_sgs;
+    pub use crate::divergence::*;
+    pub use crate::parting::{ks_to_pts, ks_to_pts_with_cfg, KDelta, PartingConfig, PartingDelta};
+    pub use crate::pipeline::{MorphCheckpoint, MorphDelta, MorphPipeline};
+    pub use crate::segment::{fill_stroke_ranges, sks_to_sgs, sks_to_sgs_traced, SegmentDelta};
     pub use crate::shape::*;
+    pub use crate::stream::{
+        Accumulator, Delta, ReplicaClient, ReplicaMessage, ReplicaPublisher, Replicator, Trace,
+    };
     pub use crate::stroke::*;
     pub use crate::subtrend::*;
     pub use crate::trend::*;
+    pub use crate::validate::{
+        validate_centers, validate_segments, validate_strokes, ShapeWarning, WarningKind,
+    };
 }
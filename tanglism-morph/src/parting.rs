@@ -1,6 +1,8 @@
 use crate::shape::{Gap, Parting, PriceRange, K};
-use crate::stream::{Accumulator, Aggregator, Delta, Replicator};
-use crate::Result;
+use crate::stream::{
+    aggregate_partitioned, partition_at, tail_deltas, Accumulator, Aggregator, Delta, Replicator,
+};
+use crate::{Error, Result};
 use bigdecimal::BigDecimal;
 use chrono::NaiveDateTime;
 use serde_derive::*;
@@ -65,31 +67,209 @@ pub fn ks_to_pts(ks: &[K]) -> Result<Vec<Parting>> {
     PartingAccumulator::new().aggregate(ks)
 }
 
-/// 暂时留空
-#[derive(Debug, Clone, Default)]
+/// 按配置将K线图解析为分型序列
+pub fn ks_to_pts_with_cfg(ks: &[K], cfg: PartingConfig) -> Result<Vec<Parting>> {
+    PartingAccumulator::new_with_cfg(cfg).aggregate(ks)
+}
+
+/// 按配置将K线图解析为分型序列，在各重置边界重新开始
+pub fn ks_to_pts_partitioned(
+    ks: &[K],
+    cfg: PartingConfig,
+    boundaries: &[NaiveDateTime],
+) -> Result<Vec<Parting>> {
+    let parts = partition_at(ks, boundaries, |k| k.ts);
+    aggregate_partitioned(&parts, || PartingAccumulator::new_with_cfg(cfg.clone()))
+}
+
+/// 将K线序列解析为分型序列，并返回累加器结束时的内部状态
+pub fn ks_to_pts_snapshot(
+    ks: &[K],
+    cfg: PartingConfig,
+) -> Result<(Vec<Parting>, PartingAccSnapshot)> {
+    let mut acc = PartingAccumulator::new_with_cfg(cfg);
+    for k in ks {
+        acc.accumulate_add(k)?;
+    }
+    let snapshot = acc.snapshot();
+    Ok((acc.strict_state(), snapshot))
+}
+
+/// 分型累加器的内部状态，用于诊断
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartingAccSnapshot {
+    pub partings: usize,
+    pub upward: bool,
+    // 暂存的合并K线，尚未确认分型
+    pub tmp: Vec<CK>,
+}
+
+/// 分型配置
+///
+/// 用于过滤噪音分型，如1分钟K线中的微小波动
+#[derive(Debug, Clone, PartialEq, Hash, Serialize, Deserialize)]
 pub struct PartingConfig {
-    pub inclusive_k: bool,
+    // 极值两侧至少需要的原始K线数，1即标准分型
+    pub side_bars: usize,
+    // 极值相对两侧K线的最小振幅比例
+    pub min_amplitude: Option<BigDecimal>,
+}
+
+impl Default for PartingConfig {
+    fn default() -> Self {
+        PartingConfig {
+            side_bars: 1,
+            min_amplitude: None,
+        }
+    }
+}
+
+impl PartingConfig {
+    fn strict(&self) -> bool {
+        self.side_bars > 1 || self.min_amplitude.is_some()
+    }
 }
 
 pub type KDelta = Delta<K>;
 pub type PartingDelta = Delta<Parting>;
 
 /// 实现分型累加器
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PartingAccumulator {
     state: Vec<Parting>,
     /// 暂存K线数组，当数组中存在3根K线时，必定与前一分型对应
     tmp: Vec<CK>,
     upward: bool,
+    cfg: PartingConfig,
+    /// 原始K线，仅在严格模式下保存尚未确认的分型两侧的K线
+    ks: Vec<K>,
+    /// 按严格配置过滤后的分型，仅在严格模式下保存
+    #[serde(default)]
+    strict: Vec<Parting>,
 }
 
 impl PartingAccumulator {
     pub fn new() -> Self {
+        Self::new_with_cfg(PartingConfig::default())
+    }
+
+    pub fn new_with_cfg(cfg: PartingConfig) -> Self {
         PartingAccumulator {
             state: Vec::new(),
             tmp: Vec::new(),
             upward: true,
+            cfg,
+            ks: Vec::new(),
+            strict: Vec::new(),
+        }
+    }
+
+    /// 按严格配置过滤后的分型序列
+    ///
+    /// 右侧K线数不足的分型视为尚未确认，不输出
+    pub fn strict_state(&self) -> Vec<Parting> {
+        self.emitted().clone()
+    }
+
+    /// 当前的内部状态，用于诊断，分型数为过滤前的数量
+    pub fn snapshot(&self) -> PartingAccSnapshot {
+        PartingAccSnapshot {
+            partings: self.state.len(),
+            upward: self.upward,
+            tmp: self.tmp.clone(),
+        }
+    }
+
+    // 输出的分型，严格模式下为过滤后的分型
+    fn emitted(&self) -> &Vec<Parting> {
+        if self.cfg.strict() {
+            &self.strict
+        } else {
+            &self.state
+        }
+    }
+
+    // 严格模式下，K线变更仅影响末尾分型及右侧K线刚好足够的分型，
+    // 自其中较早者起重新过滤，与已输出的分型比较得到变更
+    fn strict_delta(&mut self, last_ts: Option<NaiveDateTime>) -> Result<PartingDelta> {
+        let n = self.cfg.side_bars.max(1);
+        let confirm_ts = self.ks.len().checked_sub(n + 1).map(|idx| self.ks[idx].ts);
+        let new_last_ts = self.state.last().map(|pt| pt.extremum_ts);
+        let cutoff = match [last_ts, new_last_ts, confirm_ts].iter().flatten().min() {
+            Some(ts) => *ts,
+            None => return Ok(PartingDelta::None),
+        };
+        let tail: Vec<Parting> = self
+            .state
+            .iter()
+            .filter(|pt| pt.extremum_ts >= cutoff && self.strict_parting(pt))
+            .cloned()
+            .collect();
+        let idx = self.strict.partition_point(|pt| pt.extremum_ts < cutoff);
+        let mut deltas = tail_deltas(&self.strict[idx..], &tail);
+        self.strict.truncate(idx);
+        self.strict.extend(tail);
+        self.trim_ks();
+        match deltas.len() {
+            0 => Ok(PartingDelta::None),
+            1 => Ok(deltas.pop().unwrap()),
+            _ => Err(Error::Msg(format!(
+                "strict partings changed more than once at {}",
+                cutoff
+            ))),
+        }
+    }
+
+    // 已确认的分型不再变化，仅保留末尾分型、暂存K线及待确认分型左侧所需的K线
+    fn trim_ks(&mut self) {
+        let n = self.cfg.side_bars.max(1);
+        let keep_ts = [
+            self.state.last().map(|pt| pt.extremum_ts),
+            self.tmp.first().map(|ck| ck.start_ts),
+            self.ks.len().checked_sub(n + 1).map(|idx| self.ks[idx].ts),
+        ];
+        if let Some(ts) = keep_ts.iter().flatten().min() {
+            let start = self.ks.partition_point(|k| k.ts < *ts).saturating_sub(n);
+            drop(self.ks.drain(..start));
+        }
+    }
+
+    fn strict_parting(&self, pt: &Parting) -> bool {
+        let idx = match self.ks.binary_search_by_key(&pt.extremum_ts, |k| k.ts) {
+            Ok(idx) => idx,
+            Err(_) => return false,
+        };
+        let n = self.cfg.side_bars.max(1);
+        if idx < n || idx + n >= self.ks.len() {
+            return false;
+        }
+        let left = &self.ks[idx - n..idx];
+        let right = &self.ks[idx + 1..=idx + n];
+        let price = &pt.extremum_price;
+        if pt.top {
+            if left.iter().chain(right).any(|k| &k.high > price) {
+                return false;
+            }
+        } else if left.iter().chain(right).any(|k| &k.low < price) {
+            return false;
+        }
+        if let Some(ref ratio) = self.cfg.min_amplitude {
+            // 两侧均需满足最小振幅
+            let amplitude = |side: &[K]| {
+                if pt.top {
+                    let low = side.iter().map(|k| &k.low).min().unwrap();
+                    price - low
+                } else {
+                    let high = side.iter().map(|k| &k.high).max().unwrap();
+                    high - price
+                }
+            };
+            let base = price * ratio;
+            if amplitude(left) < base || amplitude(right) < base {
+                return false;
+            }
         }
+        true
     }
 
     #[allow(dead_code)]
@@ -101,6 +281,16 @@ impl PartingAccumulator {
     }
 
     fn accumulate_add(&mut self, item: &K) -> Result<PartingDelta> {
+        if !self.cfg.strict() {
+            return self.insert(item);
+        }
+        let last_ts = self.state.last().map(|pt| pt.extremum_ts);
+        self.ks.push(item.clone());
+        self.insert(item)?;
+        self.strict_delta(last_ts)
+    }
+
+    fn insert(&mut self, item: &K) -> Result<PartingDelta> {
         // k1不存在
         if self.tmp.is_empty() {
             return self.insert1(item);
@@ -247,6 +437,18 @@ impl PartingAccumulator {
     // todo
     // update时，对包含关系的处理可能导致不同的结果，需要对CK进行还原
     fn accumulate_update(&mut self, item: &K) -> Result<PartingDelta> {
+        if !self.cfg.strict() {
+            return self.update(item);
+        }
+        let last_ts = self.state.last().map(|pt| pt.extremum_ts);
+        if let Some(k) = self.ks.last_mut() {
+            *k = item.clone();
+        }
+        self.update(item)?;
+        self.strict_delta(last_ts)
+    }
+
+    fn update(&mut self, item: &K) -> Result<PartingDelta> {
         // k1不存在
         if self.tmp.is_empty() {
             panic!("no k to update");
@@ -309,7 +511,7 @@ impl Accumulator<KDelta> for PartingAccumulator {
     }
 
     fn state(&self) -> &Self::State {
-        &self.state
+        self.emitted()
     }
 }
 
@@ -323,7 +525,7 @@ impl Accumulator<K> for PartingAccumulator {
     }
 
     fn state(&self) -> &Self::State {
-        &self.state
+        self.emitted()
     }
 }
 
@@ -333,7 +535,7 @@ impl Aggregator<&[KDelta], Vec<Parting>> for PartingAccumulator {
         for item in input {
             self.accumulate(item)?;
         }
-        Ok(self.state)
+        Ok(self.strict_state())
     }
 }
 
@@ -343,7 +545,7 @@ impl Aggregator<&[K], Vec<Parting>> for PartingAccumulator {
         for item in input {
             self.accumulate(item)?;
         }
-        Ok(self.state)
+        Ok(self.strict_state())
     }
 }
 
@@ -532,17 +734,18 @@ fn inclusive_neighbor_k(k1: &CK, k2: &K, upward: bool) -> Option<CK> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bigdecimal::BigDecimal;
+    use crate::stream::apply_delta;
     use chrono::NaiveDateTime;
+    use tanglism_utils::{parse_price, price};
 
     #[test]
     fn test_parting_none() -> Result<()> {
         let ks = vec![
-            new_k("2020-02-01 10:00", 10.10, 10.00),
-            new_k("2020-02-01 10:01", 10.15, 10.05),
-            new_k("2020-02-01 10:02", 10.20, 10.10),
-            new_k("2020-02-01 10:03", 10.25, 10.15),
-            new_k("2020-02-01 10:04", 10.30, 10.20),
+            new_k("2020-02-01 10:00", "10.10", "10.00"),
+            new_k("2020-02-01 10:01", "10.15", "10.05"),
+            new_k("2020-02-01 10:02", "10.20", "10.10"),
+            new_k("2020-02-01 10:03", "10.25", "10.15"),
+            new_k("2020-02-01 10:04", "10.30", "10.20"),
         ];
         let r = ks_to_pts(&ks)?;
         assert_eq!(0, r.len());
@@ -552,30 +755,51 @@ mod tests {
     #[test]
     fn test_parting_one_simple() -> Result<()> {
         let ks = vec![
-            new_k("2020-02-01 10:00", 10.10, 10.00),
-            new_k("2020-02-01 10:01", 10.15, 10.05),
-            new_k("2020-02-01 10:02", 10.20, 10.10),
-            new_k("2020-02-01 10:03", 10.15, 10.05),
-            new_k("2020-02-01 10:04", 10.10, 10.00),
+            new_k("2020-02-01 10:00", "10.10", "10.00"),
+            new_k("2020-02-01 10:01", "10.15", "10.05"),
+            new_k("2020-02-01 10:02", "10.20", "10.10"),
+            new_k("2020-02-01 10:03", "10.15", "10.05"),
+            new_k("2020-02-01 10:04", "10.10", "10.00"),
         ];
         let r = ks_to_pts(&ks)?;
         assert_eq!(1, r.len());
         assert_eq!(new_ts("2020-02-01 10:01"), r[0].start_ts);
         assert_eq!(new_ts("2020-02-01 10:03"), r[0].end_ts);
         assert_eq!(new_ts("2020-02-01 10:02"), r[0].extremum_ts);
-        assert_eq!(BigDecimal::from(10.20), r[0].extremum_price);
+        assert_eq!(price!(10.20), r[0].extremum_price);
         assert_eq!(true, r[0].top);
         Ok(())
     }
 
+    #[test]
+    fn test_parting_snapshot() -> Result<()> {
+        let ks = vec![
+            new_k("2020-02-01 10:00", "10.10", "10.00"),
+            new_k("2020-02-01 10:01", "10.15", "10.05"),
+            new_k("2020-02-01 10:02", "10.20", "10.10"),
+            new_k("2020-02-01 10:03", "10.15", "10.05"),
+            new_k("2020-02-01 10:04", "10.10", "10.00"),
+        ];
+        let (pts, snapshot) = ks_to_pts_snapshot(&ks, PartingConfig::default())?;
+        assert_eq!(1, pts.len());
+        assert_eq!(1, snapshot.partings);
+        assert!(!snapshot.upward);
+        // 分型之后的K线暂存，等待下一个分型
+        assert_eq!(
+            new_ts("2020-02-01 10:04"),
+            snapshot.tmp.last().unwrap().end_ts
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_parting_one_inclusive() -> Result<()> {
         let ks = vec![
-            new_k("2020-02-01 10:00", 10.10, 10.00),
-            new_k("2020-02-01 10:01", 10.15, 10.05),
-            new_k("2020-02-01 10:02", 10.20, 10.10),
-            new_k("2020-02-01 10:03", 10.15, 10.05),
-            new_k("2020-02-01 10:04", 10.20, 10.00),
+            new_k("2020-02-01 10:00", "10.10", "10.00"),
+            new_k("2020-02-01 10:01", "10.15", "10.05"),
+            new_k("2020-02-01 10:02", "10.20", "10.10"),
+            new_k("2020-02-01 10:03", "10.15", "10.05"),
+            new_k("2020-02-01 10:04", "10.20", "10.00"),
         ];
         let r = ks_to_pts(&ks)?;
         assert_eq!(1, r.len());
@@ -586,11 +810,11 @@ mod tests {
     #[test]
     fn test_parting_two_simple() -> Result<()> {
         let ks = vec![
-            new_k("2020-02-01 10:00", 10.10, 10.00),
-            new_k("2020-02-01 10:01", 10.15, 10.05),
-            new_k("2020-02-01 10:02", 10.20, 10.10),
-            new_k("2020-02-01 10:03", 10.15, 10.05),
-            new_k("2020-02-01 10:04", 10.20, 10.10),
+            new_k("2020-02-01 10:00", "10.10", "10.00"),
+            new_k("2020-02-01 10:01", "10.15", "10.05"),
+            new_k("2020-02-01 10:02", "10.20", "10.10"),
+            new_k("2020-02-01 10:03", "10.15", "10.05"),
+            new_k("2020-02-01 10:04", "10.20", "10.10"),
         ];
         let r = ks_to_pts(&ks)?;
         assert_eq!(2, r.len());
@@ -606,14 +830,14 @@ mod tests {
     #[test]
     fn test_parting_two_indep() -> Result<()> {
         let ks = vec![
-            new_k("2020-02-01 10:00", 10.10, 10.00),
-            new_k("2020-02-01 10:01", 10.15, 10.05),
-            new_k("2020-02-01 10:02", 10.20, 10.10),
-            new_k("2020-02-01 10:03", 10.15, 10.05),
-            new_k("2020-02-01 10:04", 10.10, 10.00),
-            new_k("2020-02-01 10:05", 10.05, 9.95),
-            new_k("2020-02-01 10:06", 10.00, 9.90),
-            new_k("2020-02-01 10:07", 10.05, 9.95),
+            new_k("2020-02-01 10:00", "10.10", "10.00"),
+            new_k("2020-02-01 10:01", "10.15", "10.05"),
+            new_k("2020-02-01 10:02", "10.20", "10.10"),
+            new_k("2020-02-01 10:03", "10.15", "10.05"),
+            new_k("2020-02-01 10:04", "10.10", "10.00"),
+            new_k("2020-02-01 10:05", "10.05", "9.95"),
+            new_k("2020-02-01 10:06", "10.00", "9.90"),
+            new_k("2020-02-01 10:07", "10.05", "9.95"),
         ];
         let r = ks_to_pts(&ks)?;
         assert_eq!(2, r.len());
@@ -624,23 +848,112 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parting_strict() -> Result<()> {
+        let ks = vec![
+            new_k("2020-02-01 10:00", "10.10", "10.00"),
+            new_k("2020-02-01 10:01", "10.15", "10.05"),
+            new_k("2020-02-01 10:02", "10.20", "10.10"),
+            new_k("2020-02-01 10:03", "10.15", "10.05"),
+            new_k("2020-02-01 10:04", "10.10", "10.00"),
+            new_k("2020-02-01 10:05", "10.05", "9.95"),
+            new_k("2020-02-01 10:06", "10.00", "9.90"),
+            new_k("2020-02-01 10:07", "10.05", "9.95"),
+        ];
+        // 底分型右侧K线不足
+        let cfg = PartingConfig {
+            side_bars: 2,
+            min_amplitude: None,
+        };
+        let r = ks_to_pts_with_cfg(&ks, cfg)?;
+        assert_eq!(1, r.len());
+        assert_eq!(new_ts("2020-02-01 10:02"), r[0].extremum_ts);
+        // 顶分型振幅不足
+        let cfg = PartingConfig {
+            side_bars: 2,
+            min_amplitude: Some(price!(0.02)),
+        };
+        let r = ks_to_pts_with_cfg(&ks, cfg)?;
+        assert!(r.is_empty());
+        Ok(())
+    }
+
+    // 严格模式下流式变更与批量计算一致，且仅保留有限的K线
+    #[test]
+    fn test_parting_strict_stream() -> Result<()> {
+        let cfg = PartingConfig {
+            side_bars: 2,
+            min_amplitude: Some(price!(0.01)),
+        };
+        let start = new_ts("2020-02-03 10:00");
+        let waves = [
+            (1000, 1040),
+            (1040, 1010),
+            (1010, 1060),
+            (1060, 1055),
+            (1055, 980),
+            (980, 1020),
+            (1020, 990),
+            (990, 1030),
+            (1030, 1000),
+            (1000, 1050),
+        ];
+        let mut ks: Vec<K> = waves
+            .iter()
+            .flat_map(|(a, b)| (1..=6).map(move |j| a + (b - a) * j / 6))
+            .enumerate()
+            .map(|(i, mid)| K {
+                ts: start + chrono::Duration::minutes(i as i64),
+                high: BigDecimal::from(mid + 3) / 100,
+                low: BigDecimal::from(mid - 3) / 100,
+            })
+            .collect();
+        let mut acc = PartingAccumulator::new_with_cfg(cfg.clone());
+        let mut replica = Vec::new();
+        let mut max_ks = 0;
+        for (i, k) in ks.iter().enumerate() {
+            apply_delta(&mut replica, acc.accumulate(&KDelta::Add(k.clone()))?)?;
+            assert_eq!(ks_to_pts_with_cfg(&ks[..=i], cfg.clone())?, replica);
+            max_ks = max_ks.max(acc.ks.len());
+        }
+        // 最后一根K线更新
+        ks.last_mut().unwrap().low = price!(9.70);
+        let last = ks.last().unwrap().clone();
+        apply_delta(&mut replica, acc.accumulate(&KDelta::Update(last))?)?;
+        assert_eq!(Accumulator::<KDelta>::state(&acc), &replica);
+        // 按全部K线检查原始分型
+        let full = PartingAccumulator {
+            ks: ks.clone(),
+            ..PartingAccumulator::new_with_cfg(cfg.clone())
+        };
+        let expected: Vec<_> = ks_to_pts(&ks)?
+            .into_iter()
+            .filter(|pt| full.strict_parting(pt))
+            .collect();
+        assert!(expected.len() >= 2);
+        assert_eq!(expected, replica);
+        assert_eq!(expected, ks_to_pts_with_cfg(&ks, cfg)?);
+        assert!(max_ks < ks.len() / 3);
+        Ok(())
+    }
+
     #[test]
     fn test_parting_long_inclusive() -> Result<()> {
         let ks = vec![
-            new_k("2020-04-01 10:45", 8.85, 8.77),
-            new_k("2020-04-01 10:50", 8.84, 8.80),
-            new_k("2020-04-01 10:55", 8.83, 8.78),
-            new_k("2020-04-01 11:00", 8.83, 8.80),
-            new_k("2020-04-01 11:05", 8.82, 8.78),
-            new_k("2020-04-01 11:10", 8.81, 8.78),
+            new_k("2020-04-01 10:45", "8.85", "8.77"),
+            new_k("2020-04-01 10:50", "8.84", "8.80"),
+            new_k("2020-04-01 10:55", "8.83", "8.78"),
+            new_k("2020-04-01 11:00", "8.83", "8.80"),
+            new_k("2020-04-01 11:05", "8.82", "8.78"),
+            new_k("2020-04-01 11:10", "8.81", "8.78"),
             // above is one stroke
-            new_k("2020-04-01 11:15", 8.82, 8.78),
-            new_k("2020-04-01 11:20", 8.82, 8.78),
-            new_k("2020-04-01 11:25", 8.82, 8.75),
-            new_k("2020-04-01 11:30", 8.79, 8.77),
-            new_k("2020-04-01 13:05", 8.79, 8.75),
+            new_k("2020-04-01 11:15", "8.82", "8.78"),
+            new_k("2020-04-01 11:20", "8.82", "8.78"),
+            new_k("2020-04-01 11:25", "8.82", "8.75"),
+            new_k("2020-04-01 11:30", "8.79", "8.77"),
+            new_k("2020-04-01 13:05", "8.79", "8.75"),
             // above is one stroke
-            new_k("2020-04-01 13:30", 8.83, 8.78),
+            new_k("2020-04-01 13:30", "8.83", "8.78"),
         ];
         let r = ks_to_pts(&ks)?;
         assert_eq!(1, r.len());
@@ -650,11 +963,11 @@ mod tests {
     #[test]
     fn test_parting_delta_simple() -> Result<()> {
         let deltas = vec![
-            KDelta::Add(new_k("2020-02-01 10:00", 10.10, 10.00)),
-            KDelta::Add(new_k("2020-02-01 10:01", 10.15, 10.05)),
-            KDelta::Add(new_k("2020-02-01 10:02", 10.20, 10.10)),
-            KDelta::Add(new_k("2020-02-01 10:03", 10.15, 10.05)),
-            KDelta::Add(new_k("2020-02-01 10:04", 10.10, 10.00)),
+            KDelta::Add(new_k("2020-02-01 10:00", "10.10", "10.00")),
+            KDelta::Add(new_k("2020-02-01 10:01", "10.15", "10.05")),
+            KDelta::Add(new_k("2020-02-01 10:02", "10.20", "10.10")),
+            KDelta::Add(new_k("2020-02-01 10:03", "10.15", "10.05")),
+            KDelta::Add(new_k("2020-02-01 10:04", "10.10", "10.00")),
         ];
         let pa = PartingAccumulator::new();
         let r: Vec<Parting> = pa.aggregate(deltas.as_slice())?;
@@ -662,7 +975,7 @@ mod tests {
         assert_eq!(new_ts("2020-02-01 10:01"), r[0].start_ts);
         assert_eq!(new_ts("2020-02-01 10:03"), r[0].end_ts);
         assert_eq!(new_ts("2020-02-01 10:02"), r[0].extremum_ts);
-        assert_eq!(BigDecimal::from(10.20), r[0].extremum_price);
+        assert_eq!(price!(10.20), r[0].extremum_price);
         assert_eq!(true, r[0].top);
         Ok(())
     }
@@ -670,12 +983,12 @@ mod tests {
     #[test]
     fn test_parting_delta_update() -> Result<()> {
         let deltas = vec![
-            KDelta::Add(new_k("2020-02-01 10:00", 10.10, 10.00)),
-            KDelta::Add(new_k("2020-02-01 10:01", 10.15, 10.05)),
-            KDelta::Add(new_k("2020-02-01 10:02", 10.10, 10.00)),
-            KDelta::Update(new_k("2020-02-01 10:02", 10.20, 10.10)),
-            KDelta::Add(new_k("2020-02-01 10:03", 10.15, 10.05)),
-            KDelta::Add(new_k("2020-02-01 10:04", 10.10, 10.00)),
+            KDelta::Add(new_k("2020-02-01 10:00", "10.10", "10.00")),
+            KDelta::Add(new_k("2020-02-01 10:01", "10.15", "10.05")),
+            KDelta::Add(new_k("2020-02-01 10:02", "10.10", "10.00")),
+            KDelta::Update(new_k("2020-02-01 10:02", "10.20", "10.10")),
+            KDelta::Add(new_k("2020-02-01 10:03", "10.15", "10.05")),
+            KDelta::Add(new_k("2020-02-01 10:04", "10.10", "10.00")),
         ];
         let pa = PartingAccumulator::new();
         let r: Vec<Parting> = pa.aggregate(deltas.as_slice())?;
@@ -683,7 +996,7 @@ mod tests {
         assert_eq!(new_ts("2020-02-01 10:01"), r[0].start_ts);
         assert_eq!(new_ts("2020-02-01 10:03"), r[0].end_ts);
         assert_eq!(new_ts("2020-02-01 10:02"), r[0].extremum_ts);
-        assert_eq!(BigDecimal::from(10.20), r[0].extremum_price);
+        assert_eq!(price!(10.20), r[0].extremum_price);
         assert_eq!(true, r[0].top);
         Ok(())
     }
@@ -691,14 +1004,14 @@ mod tests {
     #[test]
     fn test_parting_delta_multi_updates() -> Result<()> {
         let deltas = vec![
-            KDelta::Add(new_k("2020-02-01 10:00", 10.10, 10.00)),
-            KDelta::Add(new_k("2020-02-01 10:01", 10.10, 10.05)),
-            KDelta::Update(new_k("2020-02-01 10:01", 10.15, 10.05)),
-            KDelta::Add(new_k("2020-02-01 10:02", 10.10, 10.00)),
-            KDelta::Update(new_k("2020-02-01 10:02", 10.10, 10.05)),
-            KDelta::Update(new_k("2020-02-01 10:02", 10.20, 10.10)),
-            KDelta::Add(new_k("2020-02-01 10:03", 10.15, 10.05)),
-            KDelta::Add(new_k("2020-02-01 10:04", 10.10, 10.00)),
+            KDelta::Add(new_k("2020-02-01 10:00", "10.10", "10.00")),
+            KDelta::Add(new_k("2020-02-01 10:01", "10.10", "10.05")),
+            KDelta::Update(new_k("2020-02-01 10:01", "10.15", "10.05")),
+            KDelta::Add(new_k("2020-02-01 10:02", "10.10", "10.00")),
+            KDelta::Update(new_k("2020-02-01 10:02", "10.10", "10.05")),
+            KDelta::Update(new_k("2020-02-01 10:02", "10.20", "10.10")),
+            KDelta::Add(new_k("2020-02-01 10:03", "10.15", "10.05")),
+            KDelta::Add(new_k("2020-02-01 10:04", "10.10", "10.00")),
         ];
         let pa = PartingAccumulator::new();
         let r: Vec<Parting> = pa.aggregate(deltas.as_slice())?;
@@ -706,16 +1019,16 @@ mod tests {
         assert_eq!(new_ts("2020-02-01 10:01"), r[0].start_ts);
         assert_eq!(new_ts("2020-02-01 10:03"), r[0].end_ts);
         assert_eq!(new_ts("2020-02-01 10:02"), r[0].extremum_ts);
-        assert_eq!(BigDecimal::from(10.20), r[0].extremum_price);
+        assert_eq!(price!(10.20), r[0].extremum_price);
         assert_eq!(true, r[0].top);
         Ok(())
     }
 
-    fn new_k(ts: &str, high: f64, low: f64) -> K {
+    fn new_k(ts: &str, high: &str, low: &str) -> K {
         K {
             ts: new_ts(ts),
-            high: BigDecimal::from(high),
-            low: BigDecimal::from(low),
+            high: parse_price(high).unwrap(),
+            low: parse_price(low).unwrap(),
         }
     }
 
@@ -0,0 +1,294 @@
+//! 增量分析流水线
+//!
+//! 串联分型、笔、线段、次级别走势及中枢的累加器，接收K线变更，
+//! 输出各层的有序变更，供实时推送等场景每个连接维护一个对象。
+
+use crate::center::{CenterAccumulator, CenterConfig, CenterDelta};
+use crate::parting::{KDelta, PartingAccumulator, PartingDelta};
+use crate::segment::{csegment_to_segment, SegmentAccumulator, SegmentDelta};
+use crate::shape::{CenterElement, Parting, Segment, Stroke, SubTrend};
+use crate::stream::Accumulator;
+use crate::stroke::{cstroke_to_stroke, StrokeAccumulator, StrokeConfig, StrokeDelta};
+use crate::subtrend::{SubTrendAccumulator, SubTrendDelta};
+use crate::Result;
+use serde_derive::*;
+use tanglism_utils::{LocalTradingTimestamps, Tick};
+
+/// 一次K线变更引起的各层变更，每层均按序作用于末尾元素
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MorphDelta {
+    pub partings: Vec<PartingDelta>,
+    pub strokes: Vec<StrokeDelta>,
+    pub segments: Vec<SegmentDelta>,
+    pub subtrends: Vec<SubTrendDelta>,
+    pub centers: Vec<CenterDelta>,
+}
+
+impl MorphDelta {
+    pub fn is_empty(&self) -> bool {
+        self.partings.is_empty()
+            && self.strokes.is_empty()
+            && self.segments.is_empty()
+            && self.subtrends.is_empty()
+            && self.centers.is_empty()
+    }
+}
+
+/// 增量分析流水线
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MorphPipeline {
+    partings: PartingAccumulator,
+    strokes: StrokeAccumulator<LocalTradingTimestamps>,
+    segments: SegmentAccumulator,
+    subtrends: SubTrendAccumulator,
+    centers: CenterAccumulator,
+}
+
+/// 流水线的检查点，用于回退至之前的状态
+///
+/// 可序列化后持久保存，重启后恢复，无需重新处理全部历史K线
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MorphCheckpoint(MorphPipeline);
+
+impl MorphPipeline {
+    pub fn new(tick: Tick, stroke_cfg: StrokeConfig) -> Self {
+        Self::new_with_center_cfg(tick, stroke_cfg, CenterConfig::default())
+    }
+
+    pub fn new_with_center_cfg(
+        tick: Tick,
+        stroke_cfg: StrokeConfig,
+        center_cfg: CenterConfig,
+    ) -> Self {
+        MorphPipeline {
+            partings: PartingAccumulator::new(),
+            strokes: StrokeAccumulator::new(tick, stroke_cfg),
+            segments: SegmentAccumulator::new(),
+            subtrends: SubTrendAccumulator::new(tick),
+            centers: CenterAccumulator::new(center_cfg),
+        }
+    }
+
+    /// 处理K线变更，依次传递至各层
+    ///
+    /// 笔的变更同时作用于线段及次级别走势，次级别走势的变更作用于中枢
+    pub fn accumulate(&mut self, item: &KDelta) -> Result<MorphDelta> {
+        let mut delta = MorphDelta::default();
+        let pd = self.partings.accumulate(item)?;
+        if pd.none() {
+            return Ok(delta);
+        }
+        let sd = self.strokes.accumulate(&pd)?;
+        delta.partings.push(pd);
+        if sd.none() {
+            return Ok(delta);
+        }
+        delta.segments = self.segments.accumulate(&sd)?;
+        delta.subtrends = self.subtrends.accumulate(&sd)?;
+        for sgd in &delta.segments {
+            let std = self.subtrends.accumulate(sgd)?;
+            delta.subtrends.extend(std);
+        }
+        for std in &delta.subtrends {
+            let cd = self.centers.accumulate(std)?;
+            delta.centers.extend(cd);
+        }
+        delta.strokes.push(sd);
+        Ok(delta)
+    }
+
+    /// 保存当前状态
+    pub fn checkpoint(&self) -> MorphCheckpoint {
+        MorphCheckpoint(self.clone())
+    }
+
+    /// 恢复至检查点时的状态
+    pub fn restore(&mut self, checkpoint: MorphCheckpoint) {
+        *self = checkpoint.0;
+    }
+
+    pub fn partings(&self) -> &[Parting] {
+        Accumulator::<KDelta>::state(&self.partings)
+    }
+
+    pub fn strokes(&self) -> Vec<Stroke> {
+        Accumulator::<PartingDelta>::state(&self.strokes)
+            .iter()
+            .map(cstroke_to_stroke)
+            .collect()
+    }
+
+    pub fn segments(&self) -> Vec<Segment> {
+        Accumulator::<StrokeDelta>::state(&self.segments)
+            .iter()
+            .map(csegment_to_segment)
+            .collect()
+    }
+
+    pub fn subtrends(&self) -> &[SubTrend] {
+        Accumulator::<StrokeDelta>::state(&self.subtrends)
+    }
+
+    pub fn centers(&self) -> &[CenterElement] {
+        self.centers.state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::center::unify_centers;
+    use crate::parting::ks_to_pts;
+    use crate::segment::sks_to_sgs;
+    use crate::shape::K;
+    use crate::stream::apply_delta;
+    use crate::stroke::pts_to_sks;
+    use crate::subtrend::unify_subtrends;
+    use bigdecimal::BigDecimal;
+    use chrono::NaiveDateTime;
+    use tanglism_utils::TradingTimestamps;
+
+    #[test]
+    fn test_morph_pipeline() -> Result<()> {
+        let ks = sample_ks();
+        let mut pipeline = MorphPipeline::new(Tick::M30, StrokeConfig::default());
+        let mut replica = Replica::default();
+        let mut checkpoint = None;
+        for (i, k) in ks.iter().enumerate() {
+            if i == ks.len() / 2 {
+                checkpoint = Some((pipeline.checkpoint(), replica.clone()));
+            }
+            let delta = pipeline.accumulate(&KDelta::Add(k.clone()))?;
+            apply(&mut replica, delta)?;
+        }
+        assert_batch(&pipeline, &ks)?;
+        assert_eq!(pipeline.strokes(), replica.strokes);
+        assert_eq!(pipeline.segments(), replica.segments);
+        assert_eq!(pipeline.subtrends(), &replica.subtrends[..]);
+        assert_eq!(pipeline.centers(), &replica.centers[..]);
+        assert!(!pipeline.segments().is_empty());
+        assert!(pipeline.centers().iter().any(|e| e.center().is_some()));
+
+        // 最后一根K线更新
+        let mut ks = ks;
+        let last = ks.last_mut().unwrap();
+        last.high = BigDecimal::from(25);
+        let delta = pipeline.accumulate(&KDelta::Update(last.clone()))?;
+        apply(&mut replica, delta)?;
+        assert_batch(&pipeline, &ks)?;
+        assert_eq!(pipeline.strokes(), replica.strokes);
+        assert_eq!(pipeline.segments(), replica.segments);
+
+        // 恢复至检查点后继续累加，结果一致
+        let (cp, _) = checkpoint.unwrap();
+        pipeline.restore(cp);
+        for k in &ks[ks.len() / 2..] {
+            pipeline.accumulate(&KDelta::Add(k.clone()))?;
+        }
+        assert_batch(&pipeline, &ks)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_morph_checkpoint_serde() -> Result<()> {
+        let ks = sample_ks();
+        let mid = ks.len() / 2;
+        let mut pipeline = MorphPipeline::new(Tick::M30, StrokeConfig::default());
+        for k in &ks[..mid] {
+            pipeline.accumulate(&KDelta::Add(k.clone()))?;
+        }
+        // 序列化后恢复，继续累加的结果与全量计算一致
+        let json = serde_json::to_string(&pipeline.checkpoint()).unwrap();
+        let checkpoint: MorphCheckpoint = serde_json::from_str(&json).unwrap();
+        let mut restored = MorphPipeline::new(Tick::M30, StrokeConfig::default());
+        restored.restore(checkpoint);
+        assert_eq!(pipeline.strokes(), restored.strokes());
+        for k in &ks[mid..] {
+            let expected = pipeline.accumulate(&KDelta::Add(k.clone()))?;
+            let delta = restored.accumulate(&KDelta::Add(k.clone()))?;
+            assert_eq!(expected.strokes, delta.strokes);
+            assert_eq!(expected.centers, delta.centers);
+        }
+        assert_batch(&restored, &ks)?;
+        Ok(())
+    }
+
+    fn sample_ks() -> Vec<K> {
+        zigzag_ks(&[
+            (10, 16),
+            (16, 12),
+            (12, 20),
+            (20, 15),
+            (15, 24),
+            (24, 14),
+            (14, 18),
+            (18, 9),
+            (9, 13),
+            (13, 6),
+            (6, 11),
+            (11, 8),
+            (8, 17),
+            (17, 12),
+            (12, 22),
+            (22, 16),
+        ])
+    }
+
+    // 按序应用各层变更的副本
+    #[derive(Debug, Clone, Default)]
+    struct Replica {
+        strokes: Vec<Stroke>,
+        segments: Vec<Segment>,
+        subtrends: Vec<SubTrend>,
+        centers: Vec<CenterElement>,
+    }
+
+    fn apply(replica: &mut Replica, delta: MorphDelta) -> Result<()> {
+        for d in delta.strokes {
+            apply_delta(&mut replica.strokes, d)?;
+        }
+        for d in delta.segments {
+            apply_delta(&mut replica.segments, d)?;
+        }
+        for d in delta.subtrends {
+            apply_delta(&mut replica.subtrends, d)?;
+        }
+        for d in delta.centers {
+            apply_delta(&mut replica.centers, d)?;
+        }
+        Ok(())
+    }
+
+    fn assert_batch(pipeline: &MorphPipeline, ks: &[K]) -> Result<()> {
+        let pts = ks_to_pts(ks)?;
+        let sks = pts_to_sks(&pts, Tick::M30, StrokeConfig::default())?;
+        let sgs = sks_to_sgs(&sks)?;
+        let subtrends = unify_subtrends(&sgs, &sks, Tick::M30)?;
+        assert_eq!(&pts[..], pipeline.partings());
+        assert_eq!(sks, pipeline.strokes());
+        assert_eq!(sgs, pipeline.segments());
+        assert_eq!(&subtrends[..], pipeline.subtrends());
+        assert_eq!(&unify_centers(&subtrends)[..], pipeline.centers());
+        Ok(())
+    }
+
+    // 每段由起止价格线性生成4根30分钟K线
+    fn zigzag_ks(waves: &[(i32, i32)]) -> Vec<K> {
+        let tts = LocalTradingTimestamps::new(Tick::M30);
+        let mut ts = NaiveDateTime::parse_from_str("2020-02-03 10:00", "%Y-%m-%d %H:%M").unwrap();
+        let mut ks = Vec::new();
+        for (start, end) in waves {
+            for i in 1..=4 {
+                let mid = start * (4 - i) + end * i;
+                let step = if end > start { 1 } else { -1 };
+                ks.push(K {
+                    ts,
+                    high: BigDecimal::from(mid + step.max(0) * 2) / 4,
+                    low: BigDecimal::from(mid - (-step).max(0) * 2 - 2) / 4,
+                });
+                ts = tts.next_tick(ts).unwrap();
+            }
+        }
+        ks
+    }
+}
@@ -1,9 +1,13 @@
-use crate::shape::{Parting, Segment, Stroke};
-use crate::stream::{Accumulator, Aggregator, Delta};
+use crate::shape::{Parting, Segment, SegmentGap, Stroke};
+use crate::stream::{
+    aggregate_partitioned, partition_at, tail_deltas, Accumulator, Aggregator, Delta, Trace, Tracer,
+};
 use crate::stroke::{stroke_to_cstroke, CStroke, StrokeDelta};
 use crate::{Error, Result};
 use bigdecimal::BigDecimal;
+use chrono::NaiveDateTime;
 use serde_derive::*;
+use std::collections::VecDeque;
 
 /// 将笔序列解析为线段序列
 pub fn sks_to_sgs(sks: &[Stroke]) -> Result<Vec<Segment>> {
@@ -11,9 +15,95 @@ pub fn sks_to_sgs(sks: &[Stroke]) -> Result<Vec<Segment>> {
     SegmentAccumulator::new().aggregate(sks)
 }
 
+/// 将笔序列解析为线段序列，在各重置边界重新开始
+pub fn sks_to_sgs_partitioned(
+    sks: &[Stroke],
+    boundaries: &[NaiveDateTime],
+) -> Result<Vec<Segment>> {
+    let parts = partition_at(sks, boundaries, |sk| sk.start_pt.start_ts);
+    aggregate_partitioned(&parts, SegmentAccumulator::new)
+}
+
+/// 将笔序列解析为线段序列，并返回每笔触发的规则
+pub fn sks_to_sgs_traced(sks: &[Stroke]) -> Result<(Vec<Segment>, Vec<Trace>)> {
+    let mut acc = SegmentAccumulator::new().traced();
+    for sk in sks {
+        acc.acc_add(sk)?;
+    }
+    let sgs = acc.state.iter().map(csegment_to_segment).collect();
+    Ok((sgs, acc.tracer.take()))
+}
+
+/// 将笔序列解析为线段序列，并返回累加器结束时的当前状态及快照
+pub fn sks_to_sgs_snapshot(sks: &[Stroke]) -> Result<(Vec<Segment>, SegmentAccSnapshot)> {
+    let mut acc = SegmentAccumulator::new();
+    for sk in sks {
+        acc.acc_add(sk)?;
+    }
+    let sgs = acc.state.iter().map(csegment_to_segment).collect();
+    Ok((sgs, acc.snapshot()))
+}
+
+/// 线段累加器的内部状态，用于诊断
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentAccSnapshot {
+    pub segments: usize,
+    pub curr: SegmentStageSnapshot,
+    // 最近一笔加入前的快照，用于回溯
+    pub prev: Option<SegmentStageSnapshot>,
+}
+
+/// 未完成线段的累加阶段及序列
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentStageSnapshot {
+    pub stage: String,
+    pub extremum_idx: usize,
+    pub ms: Vec<Stroke>,
+    pub cs: Vec<CStroke>,
+    pub gap_cs: Vec<CStroke>,
+    pub first_inv_cs: Vec<Stroke>,
+    pub gap: Option<SegmentGap>,
+}
+
+impl From<&SegmentAccState> for SegmentStageSnapshot {
+    fn from(st: &SegmentAccState) -> Self {
+        SegmentStageSnapshot {
+            stage: format!("{:?}", st.stage),
+            extremum_idx: st.extremum_idx,
+            ms: st.ms.clone(),
+            cs: st.cs.clone(),
+            gap_cs: st.gap_cs.clone(),
+            first_inv_cs: st.first_inv_cs.clone(),
+            gap: st.gap.clone(),
+        }
+    }
+}
+
+/// 填充线段包含的笔下标区间
+///
+/// 线段须由输入的笔序列生成，起止点与笔的起止点按时间对应
+pub fn fill_stroke_ranges(sks: &[Stroke], sgs: &mut [Segment]) {
+    let mut ski = 0;
+    for sg in sgs {
+        while ski < sks.len() && sks[ski].start_pt.extremum_ts < sg.start_pt.extremum_ts {
+            ski += 1;
+        }
+        let start = ski;
+        while ski < sks.len() && sks[ski].end_pt.extremum_ts < sg.end_pt.extremum_ts {
+            ski += 1;
+        }
+        sg.stroke_range = if ski < sks.len() && sks[ski].end_pt.extremum_ts == sg.end_pt.extremum_ts
+        {
+            Some((start, ski))
+        } else {
+            None
+        };
+    }
+}
+
 pub type SegmentDelta = Delta<Segment>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CSegment {
     sg: Segment,
     orig: Option<Box<CSegment>>,
@@ -21,7 +111,7 @@ pub struct CSegment {
 
 /// 在累加过程中，存在某些步骤修改了临时变量无法回溯
 /// 保存快照以应对。快照仅保存一份。
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SegmentAccState {
     // 累加器阶段
     stage: AccStage,
@@ -47,6 +137,8 @@ pub struct SegmentAccState {
     // 用于在第一次回调后判断一个不高于最高点分型是否可成段
     // 数组中依次存放回调后的顺势笔
     first_inv_cs: Vec<Stroke>,
+    // 当前线段结束处的特征序列缺口
+    gap: Option<SegmentGap>,
 }
 
 impl SegmentAccState {
@@ -58,13 +150,14 @@ impl SegmentAccState {
             cs: Vec::new(),
             gap_cs: Vec::new(),
             first_inv_cs: Vec::new(),
+            gap: None,
         }
     }
 
     // 线段走向与第一笔走向一致
     fn upward(&self) -> Result<bool> {
         if self.ms.is_empty() {
-            return Err(Error("empty stroke list".to_owned()));
+            return Err(Error::Msg("empty stroke list".to_owned()));
         }
         let first = &self.ms[0];
         Ok(first.end_price() > first.start_price())
@@ -74,7 +167,7 @@ impl SegmentAccState {
         if let Some(sk) = self.ms.get(self.extremum_idx) {
             return Ok(sk.end_price().clone());
         }
-        Err(Error(format!(
+        Err(Error::Msg(format!(
             "extremum index {} not mapped to stroke",
             self.extremum_idx
         )))
@@ -84,7 +177,7 @@ impl SegmentAccState {
         if let Some(sk) = self.ms.first() {
             return Ok(sk.start_price().clone());
         }
-        Err(Error("no stroke in state".to_owned()))
+        Err(Error::Msg("no stroke in state".to_owned()))
     }
 
     fn reset_empty(&mut self) {
@@ -94,6 +187,17 @@ impl SegmentAccState {
         self.cs.clear();
         self.gap_cs.clear();
         self.first_inv_cs.clear();
+        self.gap.take();
+    }
+
+    // 当前线段，终点为极值点
+    fn segment(&self) -> Segment {
+        Segment {
+            start_pt: self.ms[0].start_pt.clone(),
+            end_pt: self.ms[self.extremum_idx].end_pt.clone(),
+            gap: self.gap.clone(),
+            stroke_range: None,
+        }
     }
 
     // 创新高或新低，构建新线段
@@ -104,9 +208,12 @@ impl SegmentAccState {
         self.extremum_idx = self.ms.len() - 1;
         self.gap_cs.clear();
         self.first_inv_cs.clear();
+        self.gap.take();
         MustUse(Segment {
             start_pt: self.ms[0].start_pt.clone(),
             end_pt: item.end_pt.clone(),
+            gap: None,
+            stroke_range: None,
         })
     }
 
@@ -127,6 +234,8 @@ impl SegmentAccState {
         MustUse(Segment {
             start_pt: self.ms[0].start_pt.clone(),
             end_pt: item.end_pt.clone(),
+            gap: None,
+            stroke_range: None,
         })
     }
 
@@ -160,6 +269,8 @@ impl SegmentAccState {
         MustUse(Segment {
             start_pt: self.ms[0].start_pt.clone(),
             end_pt: item.start_pt.clone(),
+            gap: None,
+            stroke_range: None,
         })
     }
 
@@ -170,6 +281,8 @@ impl SegmentAccState {
         MustUse(Segment {
             start_pt: self.ms[0].start_pt.clone(),
             end_pt: item.end_pt.clone(),
+            gap: None,
+            stroke_range: None,
         })
     }
 
@@ -191,6 +304,8 @@ impl SegmentAccState {
         MustUse(Segment {
             start_pt: self.ms[0].start_pt.clone(),
             end_pt: self.ms.last().unwrap().end_pt.clone(),
+            gap: None,
+            stroke_range: None,
         })
     }
 
@@ -216,10 +331,34 @@ impl SegmentAccState {
     }
 
     // 顺势 => 缺口回调
-    fn switch_continue_to_gap_inverse(&mut self, item: &Stroke) {
+    // 记录缺口并返回更新后的当前线段
+    fn switch_continue_to_gap_inverse(
+        &mut self,
+        item: &Stroke,
+        gap_price: &BigDecimal,
+    ) -> MustUse<Segment> {
+        self.gap.replace(SegmentGap {
+            ts: item.start_pt.extremum_ts,
+            start_price: gap_price.clone(),
+            end_price: item.end_price().clone(),
+            filled: false,
+        });
         self.add_main_stroke(item);
         self.add_cs_stroke(item, false);
         self.stage = AccStage::GapInverse;
+        MustUse(self.segment())
+    }
+
+    // 缺口回调中逆势笔回到缺口起点，缺口被回补
+    // 返回更新后的当前线段
+    fn fill_gap(&mut self, item: &Stroke, upward: bool) -> Option<MustUse<Segment>> {
+        match self.gap.as_mut() {
+            Some(gap) if !gap.filled && !cmp_prices(&gap.start_price, item.end_price(), upward) => {
+                gap.filled = true;
+                Some(MustUse(self.segment()))
+            }
+            _ => None,
+        }
     }
 
     // 顺势 => 普通回调
@@ -247,6 +386,8 @@ impl SegmentAccState {
         MustUse(Segment {
             start_pt: self.ms[0].start_pt.clone(),
             end_pt: item.start_pt.clone(),
+            gap: None,
+            stroke_range: None,
         })
     }
 
@@ -329,6 +470,20 @@ pub struct ChStroke {
 #[derive(Debug, Clone)]
 struct MustUse<T>(T);
 
+// 保留的重播起点数
+const REPLAY_BASES: usize = 2;
+
+// 重播起点，即新增线段的笔加入前的状态
+#[derive(Clone, Serialize, Deserialize)]
+struct ReplayBase {
+    curr: SegmentAccState,
+    len: usize,
+    last: Option<CSegment>,
+    // 起点之前的笔数，起点之后的笔为sks[skip..]
+    skip: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SegmentAccumulator {
     // 当前线段状态
     state: Vec<CSegment>,
@@ -337,8 +492,22 @@ pub struct SegmentAccumulator {
     // 快照，用于Stroke更新或删除时进行回溯
     // 快照最多保存一份
     prev: Option<Box<SegmentAccState>>,
+    // 快照时的线段数及最后一段
+    prev_len: usize,
+    prev_last: Option<CSegment>,
+    // 最早的重播起点之后加入的笔，无快照时用于重播
+    sks: Vec<Stroke>,
+    // 最近新增的线段对应的重播起点，已完成的线段不再重播
+    #[serde(default)]
+    bases: VecDeque<ReplayBase>,
+    // 已丢弃起点之前的笔，此后无法从头重播
+    #[serde(default)]
+    sks_dropped: bool,
     // 当前状态
     curr: SegmentAccState,
+    // 决策日志
+    #[serde(skip)]
+    tracer: Tracer,
 }
 
 /// 线段累加器有以下状态
@@ -369,12 +538,122 @@ impl SegmentAccumulator {
             state: Vec::new(),
             state_change: Vec::new(),
             prev: None,
+            prev_len: 0,
+            prev_last: None,
+            sks: Vec::new(),
+            bases: VecDeque::new(),
+            sks_dropped: false,
             curr: SegmentAccState::new(),
+            tracer: Tracer::default(),
+        }
+    }
+
+    /// 开启决策日志
+    pub fn traced(mut self) -> Self {
+        self.tracer = Tracer::enabled();
+        self
+    }
+
+    #[inline]
+    fn trace(&mut self, item: &Stroke, rule: &str) {
+        self.tracer.trace(item.end_pt.extremum_ts, rule);
+    }
+
+    /// 当前的内部状态
+    pub fn snapshot(&self) -> SegmentAccSnapshot {
+        SegmentAccSnapshot {
+            segments: self.state.len(),
+            curr: SegmentStageSnapshot::from(&self.curr),
+            prev: self.prev.as_deref().map(SegmentStageSnapshot::from),
         }
     }
 
+    // 加入新笔前保存快照，仅保留最近一笔的快照
     fn make_snapshot(&mut self) {
         self.prev.replace(Box::new(self.curr.clone()));
+        self.prev_len = self.state.len();
+        self.prev_last = self.state.last().cloned();
+    }
+
+    // 回退至最近一笔加入前的状态，无快照时返回false
+    fn rollback(&mut self) -> bool {
+        match self.prev.take() {
+            Some(prev) => {
+                self.curr = *prev;
+                self.state.truncate(self.prev_len);
+                if let Some(last) = self.prev_last.take() {
+                    if let Some(last_sg) = self.state.last_mut() {
+                        *last_sg = last;
+                    }
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 替换或删除最后一笔后重新计算线段，并生成末尾线段的变更
+    fn replace_last(&mut self, item: Option<&Stroke>) -> Result<()> {
+        if self.sks.pop().is_none() {
+            return Err(Error::Msg("no stroke to update or delete".to_owned()));
+        }
+        let pending = self.state_change.len();
+        let old_len = self.state.len();
+        // 删除至重播起点之前的笔时，该起点失效
+        while matches!(self.bases.back(), Some(b) if b.skip > self.sks.len()) {
+            self.bases.pop_back();
+        }
+        let from = if self.prev.is_some() {
+            self.prev_len.min(old_len).saturating_sub(1)
+        } else {
+            self.bases
+                .back()
+                .map(|b| b.len)
+                .unwrap_or(0)
+                .saturating_sub(1)
+        };
+        let old: Vec<Segment> = self.state[from..].iter().map(csegment_to_segment).collect();
+        if !self.rollback() {
+            // 快照已用于前一次回溯，自最近的重播起点重播其后的笔
+            let skip = match self.bases.back() {
+                Some(base) => {
+                    self.curr = base.curr.clone();
+                    self.state.truncate(base.len);
+                    if let (Some(last), Some(last_sg)) = (&base.last, self.state.last_mut()) {
+                        *last_sg = last.clone();
+                    }
+                    base.skip
+                }
+                None if self.sks_dropped => {
+                    return Err(Error::Msg(
+                        "stroke to replace is before replay base".to_owned(),
+                    ))
+                }
+                None => {
+                    self.state.clear();
+                    self.curr = SegmentAccState::new();
+                    0
+                }
+            };
+            let sks = std::mem::take(&mut self.sks);
+            for sk in &sks[skip..] {
+                self.add_stroke(sk)?;
+            }
+            self.sks = sks;
+        }
+        if let Some(item) = item {
+            self.make_snapshot();
+            self.add_stroke(item)?;
+            self.sks.push(item.clone());
+        }
+        // 回溯及重播过程中的变更以末尾的差异代替
+        self.state_change.truncate(pending);
+        let new: Vec<Segment> = self.state[from.min(self.state.len())..]
+            .iter()
+            .map(csegment_to_segment)
+            .collect();
+        self.state_change.extend(tail_deltas(&old, &new));
+        Ok(())
     }
 
     fn add_segment(&mut self, sg: Segment) {
@@ -454,21 +733,55 @@ impl SegmentAccumulator {
     //     Ok(())
     // }
 
-    fn acc(&mut self, item: &StrokeDelta) -> Result<SegmentDelta> {
+    // 处理笔的变更，返回末尾线段的全部有序变更
+    fn acc(&mut self, item: &StrokeDelta) -> Result<Vec<SegmentDelta>> {
         match item {
             StrokeDelta::None => (),
             StrokeDelta::Add(sk) => self.acc_add(sk)?,
             StrokeDelta::Update(sk) => self.acc_update(sk)?,
             StrokeDelta::Delete(sk) => self.acc_delete(sk)?,
         }
-        self.pop_delta()
+        Ok(self.take_deltas())
     }
 
     fn acc_add(&mut self, item: &Stroke) -> Result<()> {
+        self.make_snapshot();
+        self.sks.push(item.clone());
+        self.add_stroke(item)?;
+        if self.state.len() > self.prev_len {
+            self.push_base();
+        }
+        Ok(())
+    }
+
+    // 以加入最后一笔前的快照为重播起点，并丢弃最早的起点之前的笔
+    fn push_base(&mut self) {
+        let curr = match self.prev {
+            Some(ref prev) => (**prev).clone(),
+            None => return,
+        };
+        self.bases.push_back(ReplayBase {
+            curr,
+            len: self.prev_len,
+            last: self.prev_last.clone(),
+            skip: self.sks.len() - 1,
+        });
+        if self.bases.len() > REPLAY_BASES {
+            self.bases.pop_front();
+            let skip = self.bases[0].skip;
+            drop(self.sks.drain(..skip));
+            for base in self.bases.iter_mut() {
+                base.skip -= skip;
+            }
+            self.sks_dropped = true;
+        }
+    }
+
+    fn add_stroke(&mut self, item: &Stroke) -> Result<()> {
         match &self.curr.stage {
             AccStage::Empty => {
                 // 起始
-                self.make_snapshot();
+                self.trace(item, "Empty→FirstStroke: 起始笔");
                 self.curr.switch_empty_to_first_stroke(item);
                 Ok(())
             }
@@ -477,13 +790,13 @@ impl SegmentAccumulator {
                 let start_price = self.curr.ms[0].start_price();
                 if cmp_prices(start_price, item.end_price(), !upward) {
                     // 第二笔破了第一笔的起点
-                    self.make_snapshot();
+                    self.trace(item, "FirstStroke→Empty: 第二笔越过起点");
                     // 清空第一笔
                     self.curr.reset_empty();
                     // 重播第二笔
-                    return self.acc_add(item);
+                    return self.add_stroke(item);
                 }
-                self.make_snapshot();
+                self.trace(item, "FirstStroke→FirstInverse: 第一次回调");
                 self.curr.switch_first_stroke_to_first_inverse(item);
                 Ok(())
             }
@@ -492,7 +805,7 @@ impl SegmentAccumulator {
                 let extremum_price = self.curr.extremum_price()?;
                 if cmp_prices(&extremum_price, item.end_price(), upward) {
                     // 顺势的新高/新低
-                    self.make_snapshot();
+                    self.trace(item, "FirstInverse→Continue: 顺势创新高/新低");
                     let new_sg = self.curr.switch_inverse_to_continue(item);
                     self.add_segment(new_sg.0);
                     return Ok(());
@@ -504,7 +817,7 @@ impl SegmentAccumulator {
                             && cmp_prices(last_inv_csk.end_price(), item.end_price(), upward)
                         {
                             // 形成顺势两笔递进
-                            self.make_snapshot();
+                            self.trace(item, "FirstInverse→Continue: 顺势两笔递进");
                             let new_sg = self.curr.switch_first_inverse_to_curr_continue(item);
                             self.add_segment(new_sg.0);
                             return Ok(());
@@ -513,6 +826,7 @@ impl SegmentAccumulator {
                     }
 
                     // 顺势的第一笔
+                    self.trace(item, "FirstInverse: 顺势笔未创新高/新低");
                     self.curr.keep_first_inverse_cont(item);
                     return Ok(());
                 }
@@ -520,16 +834,18 @@ impl SegmentAccumulator {
                 let start_price = self.curr.start_price()?;
                 if cmp_prices(&start_price, item.end_price(), !upward) {
                     // 逆势越过起点
-                    self.make_snapshot();
                     if self.curr.ms.len() == 1 {
+                        self.trace(item, "FirstInverse→FirstStroke: 逆势越过起点");
                         self.curr.switch_first_inverse_to_next_first_stroke(item);
                     } else {
+                        self.trace(item, "FirstInverse→Continue: 逆势越过起点，起点后移");
                         let new_sg = self.curr.switch_first_inverse_to_next_continue(item);
                         self.add_segment(new_sg.0);
                     }
                     return Ok(());
                 }
                 // 在逆势状态中，且始终在第一笔的区间内震荡
+                self.trace(item, "FirstInverse: 在第一笔区间内震荡");
                 self.curr.keep_first_inverse_inv(item);
                 Ok(())
             }
@@ -543,20 +859,22 @@ impl SegmentAccumulator {
                     upward,
                 ) {
                     // 在continue状态，只接受逆势笔
-                    return Err(Error("not an inverse stroke".to_owned()));
+                    return Err(Error::Msg("not an inverse stroke".to_owned()));
                 }
                 // 检查是否形成了特征序列的缺口
                 if let Some(last_csk) = self.curr.cs.last() {
                     // 检查缺口
                     if cmp_prices(last_csk.sk.start_price(), &item.end_price(), upward) {
                         // 缺口存在时，进入缺口回调状态
-                        self.make_snapshot();
-                        self.curr.switch_continue_to_gap_inverse(item);
+                        let gap_price = last_csk.sk.start_price().clone();
+                        self.trace(item, "Continue→GapInverse: 缺口存在");
+                        let new_sg = self.curr.switch_continue_to_gap_inverse(item, &gap_price);
+                        self.add_segment(new_sg.0);
                         return Ok(());
                     }
                 }
                 // 无缺口，进入普通回调状态
-                self.make_snapshot();
+                self.trace(item, "Continue→Inverse: 无缺口");
                 self.curr.switch_continue_to_inverse(item);
                 Ok(())
             }
@@ -566,13 +884,14 @@ impl SegmentAccumulator {
                 let extremum_price = self.curr.extremum_price()?;
                 if cmp_prices(&extremum_price, item.end_price(), upward) {
                     // 顺势笔超越极值
-                    self.make_snapshot();
+                    self.trace(item, "Inverse→Continue: 顺势笔超越极值");
                     let new_sg = self.curr.switch_inverse_to_continue(item);
                     self.add_segment(new_sg.0);
                     return Ok(());
                 }
                 if cmp_prices(item.start_price(), item.end_price(), upward) {
                     // 顺势笔没有超过极值
+                    self.trace(item, "Inverse: 顺势笔未超越极值");
                     self.curr.keep_inverse_cont(item);
                     return Ok(());
                 }
@@ -583,7 +902,7 @@ impl SegmentAccumulator {
                     && cmp_prices(sk1.end_price(), item.end_price(), !upward)
                 {
                     // 分型必成立
-                    self.make_snapshot();
+                    self.trace(item, "Inverse→Continue: 特征序列分型成立，前段结束");
                     let new_sg = self.curr.switch_inverse_to_next_continue(item);
                     self.add_segment(new_sg.0);
                     return Ok(());
@@ -596,13 +915,14 @@ impl SegmentAccumulator {
                     && cmp_prices(pre_item.end_price(), item.end_price(), !upward)
                 {
                     // 分型必成立
-                    self.make_snapshot();
+                    self.trace(item, "Inverse→Continue: 特征序列分型成立，前段结束");
                     let new_sg = self.curr.switch_inverse_to_next_continue(item);
                     self.add_segment(new_sg.0);
                     return Ok(());
                 }
 
                 // 分型不成立
+                self.trace(item, "Inverse: 特征序列分型不成立");
                 self.curr.keep_inverse_inv(item);
                 Ok(())
             }
@@ -612,7 +932,7 @@ impl SegmentAccumulator {
                 let extremum_price = self.curr.extremum_price()?;
                 if cmp_prices(&extremum_price, item.end_price(), upward) {
                     // 顺势笔超越极值
-                    self.make_snapshot();
+                    self.trace(item, "GapInverse→Continue: 顺势笔超越极值");
                     let new_sg = self.curr.switch_inverse_to_continue(item);
                     self.add_segment(new_sg.0);
                     return Ok(());
@@ -624,7 +944,7 @@ impl SegmentAccumulator {
                             && cmp_prices(last_gap_csk.sk.end_price(), item.end_price(), !upward)
                         {
                             // 虽然仅两笔，但已必定形成逆分型
-                            self.make_snapshot();
+                            self.trace(item, "GapInverse→Inverse: 缺口后形成逆分型，前段结束");
                             let new_sg = self.curr.switch_gap_inverse_to_next_inverse(item);
                             self.add_segment(new_sg.0);
                             return Ok(());
@@ -632,6 +952,7 @@ impl SegmentAccumulator {
                         // 没有形成逆分型
                     }
                     // 笔数不足
+                    self.trace(item, "GapInverse: 顺势笔未形成逆分型");
                     self.curr.keep_gap_inverse_cont(item);
                     return Ok(());
                 }
@@ -639,31 +960,57 @@ impl SegmentAccumulator {
                 // 逆势笔
                 let start_price = self.curr.start_price()?;
                 if cmp_prices(&start_price, item.end_price(), !upward) {
-                    // 逆势笔越过起点
-                    self.make_snapshot();
+                    // 逆势笔越过起点，缺口必定回补
+                    self.trace(item, "GapInverse→Continue: 逆势笔越过起点，前段结束");
+                    if let Some(filled_sg) = self.curr.fill_gap(item, upward) {
+                        // 回补当前线段的缺口，先于新增的线段产生更新
+                        if let Some(last_sg) = self.state.last_mut() {
+                            last_sg.sg = filled_sg.0.clone();
+                            self.state_change.push(SegmentDelta::Update(filled_sg.0));
+                        }
+                    }
                     let new_sg = self.curr.switch_gap_inverse_to_next_continue(item);
                     self.add_segment(new_sg.0);
                     return Ok(());
                 }
+                if let Some(filled_sg) = self.curr.fill_gap(item, upward) {
+                    self.trace(item, "GapInverse: 逆势笔回补缺口");
+                    self.curr.keep_gap_inverse_inv(item);
+                    self.add_segment(filled_sg.0);
+                    return Ok(());
+                }
+                self.trace(item, "GapInverse: 逆势笔未越过起点");
                 self.curr.keep_gap_inverse_inv(item);
                 Ok(())
             }
         }
     }
 
-    fn acc_update(&mut self, _item: &Stroke) -> Result<()> {
-        unimplemented!()
+    // 最后一笔的终点变化，回溯后重新加入
+    fn acc_update(&mut self, item: &Stroke) -> Result<()> {
+        self.replace_last(Some(item))
     }
 
-    fn acc_delete(&mut self, _item: &Stroke) -> Result<()> {
-        unimplemented!()
+    // 最后一笔被删除，回溯至加入该笔前
+    fn acc_delete(&mut self, item: &Stroke) -> Result<()> {
+        match self.sks.last() {
+            // 起止分型均须匹配最后一笔
+            Some(last)
+                if last.start_pt.start_ts == item.start_pt.start_ts
+                    && last.end_pt.start_ts == item.end_pt.start_ts =>
+            {
+                self.replace_last(None)
+            }
+            _ => Err(Error::Msg(format!(
+                "stroke to delete does not match last stroke: {}",
+                item.end_pt.extremum_ts
+            ))),
+        }
     }
 
-    fn pop_delta(&mut self) -> Result<SegmentDelta> {
-        if let Some(delta) = self.state_change.pop() {
-            return Ok(delta);
-        }
-        Ok(SegmentDelta::None)
+    // 取出处理过程中的全部变更，按发生顺序排列
+    fn take_deltas(&mut self) -> Vec<SegmentDelta> {
+        std::mem::take(&mut self.state_change)
     }
 }
 
@@ -770,7 +1117,7 @@ fn cmp_prices(p1: &BigDecimal, p2: &BigDecimal, upward: bool) -> bool {
     p1 > p2
 }
 
-fn csegment_to_segment(csg: &CSegment) -> Segment {
+pub(crate) fn csegment_to_segment(csg: &CSegment) -> Segment {
     csg.sg.clone()
 }
 /// 状态机转换
@@ -781,7 +1128,7 @@ fn csegment_to_segment(csg: &CSegment) -> Segment {
 /// Continue -> Inverse, GapInverse
 /// Inverse -> Continue, Empty
 /// GapInverse -> Continue, Empty
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 enum AccStage {
     // 起始状态
     Empty,
@@ -799,12 +1146,12 @@ enum AccStage {
 }
 
 impl Accumulator<Stroke> for SegmentAccumulator {
-    type Delta = SegmentDelta;
+    type Delta = Vec<SegmentDelta>;
     type State = Vec<CSegment>;
 
-    fn accumulate(&mut self, item: &Stroke) -> Result<SegmentDelta> {
+    fn accumulate(&mut self, item: &Stroke) -> Result<Vec<SegmentDelta>> {
         self.acc_add(item)?;
-        self.pop_delta()
+        Ok(self.take_deltas())
     }
 
     fn state(&self) -> &Self::State {
@@ -822,10 +1169,10 @@ impl Aggregator<&[Stroke], Vec<Segment>> for SegmentAccumulator {
 }
 
 impl Accumulator<StrokeDelta> for SegmentAccumulator {
-    type Delta = SegmentDelta;
+    type Delta = Vec<SegmentDelta>;
     type State = Vec<CSegment>;
 
-    fn accumulate(&mut self, item: &StrokeDelta) -> Result<SegmentDelta> {
+    fn accumulate(&mut self, item: &StrokeDelta) -> Result<Vec<SegmentDelta>> {
         self.acc(item)
     }
 
@@ -837,17 +1184,18 @@ impl Accumulator<StrokeDelta> for SegmentAccumulator {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bigdecimal::BigDecimal;
+    use crate::stream::apply_delta;
     use chrono::NaiveDateTime;
+    use tanglism_utils::{parse_price, price};
 
     // 未确定线段
     #[test]
     fn test_segment_undetermined() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:20", 10.50),
-            ("2020-02-02 10:40", 10.30),
-            ("2020-02-02 11:00", 11.00),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:20", "10.50"),
+            ("2020-02-02 10:40", "10.30"),
+            ("2020-02-02 11:00", "11.00"),
         ]
         .build();
 
@@ -864,18 +1212,21 @@ mod tests {
     #[test]
     fn test_segment_broken_by_stroke() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:20", 10.50),
-            ("2020-02-02 10:40", 10.30),
-            ("2020-02-02 11:00", 11.00),
-            ("2020-02-02 11:20", 9.00),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:20", "10.50"),
+            ("2020-02-02 10:40", "10.30"),
+            ("2020-02-02 11:00", "11.00"),
+            ("2020-02-02 11:20", "9.00"),
         ]
         .build();
-        let sgs = sks_to_sgs(&sks)?;
+        let mut sgs = sks_to_sgs(&sks)?;
 
         assert!(!sgs.is_empty());
         assert_eq!(new_ts("2020-02-02 10:00"), sgs[0].start_pt.extremum_ts);
         assert_eq!(new_ts("2020-02-02 11:00"), sgs[0].end_pt.extremum_ts);
+        assert_eq!(None, sgs[0].stroke_range);
+        fill_stroke_ranges(&sks, &mut sgs);
+        assert_eq!(Some((0, 2)), sgs[0].stroke_range);
         Ok(())
     }
 
@@ -883,11 +1234,11 @@ mod tests {
     #[test]
     fn test_segment_incomplete_broken_by_stroke() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:10", 10.80),
-            ("2020-02-02 10:20", 10.50),
-            ("2020-02-02 10:30", 10.70),
-            ("2020-02-02 10:40", 9.50),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "10.80"),
+            ("2020-02-02 10:20", "10.50"),
+            ("2020-02-02 10:30", "10.70"),
+            ("2020-02-02 10:40", "9.50"),
         ]
         .build();
         let sgs = sks_to_sgs(&sks)?;
@@ -902,13 +1253,13 @@ mod tests {
     #[test]
     fn test_segment_broken_by_segment() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:10", 10.80),
-            ("2020-02-02 10:20", 10.50),
-            ("2020-02-02 10:30", 11.20),
-            ("2020-02-02 10:40", 10.30),
-            ("2020-02-02 10:50", 10.60),
-            ("2020-02-02 11:00", 9.50),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "10.80"),
+            ("2020-02-02 10:20", "10.50"),
+            ("2020-02-02 10:30", "11.20"),
+            ("2020-02-02 10:40", "10.30"),
+            ("2020-02-02 10:50", "10.60"),
+            ("2020-02-02 11:00", "9.50"),
         ]
         .build();
         let sgs = sks_to_sgs(&sks)?;
@@ -926,14 +1277,14 @@ mod tests {
     #[test]
     fn test_segment_gap_without_parting() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:10", 10.80),
-            ("2020-02-02 10:20", 10.50),
-            ("2020-02-02 10:30", 11.20),
-            ("2020-02-02 10:40", 11.00),
-            ("2020-02-02 10:50", 11.10),
-            ("2020-02-02 11:00", 10.40),
-            ("2020-02-02 11:10", 11.50),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "10.80"),
+            ("2020-02-02 10:20", "10.50"),
+            ("2020-02-02 10:30", "11.20"),
+            ("2020-02-02 10:40", "11.00"),
+            ("2020-02-02 10:50", "11.10"),
+            ("2020-02-02 11:00", "10.40"),
+            ("2020-02-02 11:10", "11.50"),
         ]
         .build();
         let sgs = sks_to_sgs(&sks)?;
@@ -948,14 +1299,14 @@ mod tests {
     #[test]
     fn test_segment_gap_without_parting_but_inclusive() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:10", 10.50),
-            ("2020-02-02 10:20", 10.30),
-            ("2020-02-02 10:30", 11.20),
-            ("2020-02-02 10:40", 10.70),
-            ("2020-02-02 10:50", 11.10),
-            ("2020-02-02 11:00", 10.80),
-            ("2020-02-02 11:10", 11.50),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "10.50"),
+            ("2020-02-02 10:20", "10.30"),
+            ("2020-02-02 10:30", "11.20"),
+            ("2020-02-02 10:40", "10.70"),
+            ("2020-02-02 10:50", "11.10"),
+            ("2020-02-02 11:00", "10.80"),
+            ("2020-02-02 11:10", "11.50"),
         ]
         .build();
         let sgs = sks_to_sgs(&sks)?;
@@ -970,14 +1321,14 @@ mod tests {
     #[test]
     fn test_segment_gap_without_parting_and_exceeding() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:10", 10.50),
-            ("2020-02-02 10:20", 10.30),
-            ("2020-02-02 10:30", 11.20),
-            ("2020-02-02 10:40", 10.70),
-            ("2020-02-02 10:50", 11.10),
-            ("2020-02-02 11:00", 10.80),
-            ("2020-02-02 11:10", 10.90),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "10.50"),
+            ("2020-02-02 10:20", "10.30"),
+            ("2020-02-02 10:30", "11.20"),
+            ("2020-02-02 10:40", "10.70"),
+            ("2020-02-02 10:50", "11.10"),
+            ("2020-02-02 11:00", "10.80"),
+            ("2020-02-02 11:10", "10.90"),
         ]
         .build();
         let sgs = sks_to_sgs(&sks)?;
@@ -992,16 +1343,16 @@ mod tests {
     #[test]
     fn test_segment_gap_with_parting_simple() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:10", 10.50),
-            ("2020-02-02 10:20", 10.30),
-            ("2020-02-02 10:30", 11.20),
-            ("2020-02-02 10:40", 10.90),
-            ("2020-02-02 10:50", 11.10),
-            ("2020-02-02 11:00", 10.20),
-            ("2020-02-02 11:10", 10.90),
-            ("2020-02-02 11:20", 10.80),
-            ("2020-02-02 11:30", 11.40),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "10.50"),
+            ("2020-02-02 10:20", "10.30"),
+            ("2020-02-02 10:30", "11.20"),
+            ("2020-02-02 10:40", "10.90"),
+            ("2020-02-02 10:50", "11.10"),
+            ("2020-02-02 11:00", "10.20"),
+            ("2020-02-02 11:10", "10.90"),
+            ("2020-02-02 11:20", "10.80"),
+            ("2020-02-02 11:30", "11.40"),
         ]
         .build();
         let sgs = sks_to_sgs(&sks)?;
@@ -1020,18 +1371,18 @@ mod tests {
     #[test]
     fn test_segment_gap_with_parting_and_inclusive() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:10", 10.50),
-            ("2020-02-02 10:20", 10.30),
-            ("2020-02-02 10:30", 11.20),
-            ("2020-02-02 10:40", 10.60),
-            ("2020-02-02 10:50", 11.10),
-            ("2020-02-02 11:00", 10.70),
-            ("2020-02-02 11:10", 11.00),
-            ("2020-02-02 11:20", 10.40),
-            ("2020-02-02 11:30", 10.80),
-            ("2020-02-02 13:10", 10.60),
-            ("2020-02-02 13:20", 11.15),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "10.50"),
+            ("2020-02-02 10:20", "10.30"),
+            ("2020-02-02 10:30", "11.20"),
+            ("2020-02-02 10:40", "10.60"),
+            ("2020-02-02 10:50", "11.10"),
+            ("2020-02-02 11:00", "10.70"),
+            ("2020-02-02 11:10", "11.00"),
+            ("2020-02-02 11:20", "10.40"),
+            ("2020-02-02 11:30", "10.80"),
+            ("2020-02-02 13:10", "10.60"),
+            ("2020-02-02 13:20", "11.15"),
         ]
         .build();
 
@@ -1051,16 +1402,16 @@ mod tests {
     #[test]
     fn test_segment_gap_cs_all_inclusive() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:10", 10.50),
-            ("2020-02-02 10:20", 10.30),
-            ("2020-02-02 10:30", 11.20),
-            ("2020-02-02 10:40", 10.60),
-            ("2020-02-02 10:50", 10.80),
-            ("2020-02-02 11:00", 10.40),
-            ("2020-02-02 11:10", 11.00),
-            ("2020-02-02 11:20", 10.70),
-            ("2020-02-02 11:30", 11.30),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "10.50"),
+            ("2020-02-02 10:20", "10.30"),
+            ("2020-02-02 10:30", "11.20"),
+            ("2020-02-02 10:40", "10.60"),
+            ("2020-02-02 10:50", "10.80"),
+            ("2020-02-02 11:00", "10.40"),
+            ("2020-02-02 11:10", "11.00"),
+            ("2020-02-02 11:20", "10.70"),
+            ("2020-02-02 11:30", "11.30"),
         ]
         .build();
 
@@ -1073,13 +1424,13 @@ mod tests {
     #[test]
     fn test_segment_gap_broken_by_stroke() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:10", 10.50),
-            ("2020-02-02 10:20", 10.30),
-            ("2020-02-02 10:30", 11.20),
-            ("2020-02-02 10:40", 10.90),
-            ("2020-02-02 10:50", 11.10),
-            ("2020-02-02 11:00", 9.80),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "10.50"),
+            ("2020-02-02 10:20", "10.30"),
+            ("2020-02-02 10:30", "11.20"),
+            ("2020-02-02 10:40", "10.90"),
+            ("2020-02-02 10:50", "11.10"),
+            ("2020-02-02 11:00", "9.80"),
         ]
         .build();
         let sgs = sks_to_sgs(&sks)?;
@@ -1089,6 +1440,198 @@ mod tests {
         assert_eq!(new_ts("2020-02-02 10:30"), sgs[0].end_pt.extremum_ts);
         assert_eq!(new_ts("2020-02-02 10:30"), sgs[1].start_pt.extremum_ts);
         assert_eq!(new_ts("2020-02-02 11:00"), sgs[1].end_pt.extremum_ts);
+        assert!(sgs[0].gap.as_ref().unwrap().filled);
+        Ok(())
+    }
+
+    // 特征序列缺口及回补
+    #[test]
+    fn test_segment_gap_filled() -> Result<()> {
+        let sks = vec![
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:20", "11.00"),
+            ("2020-02-02 10:40", "10.50"),
+            ("2020-02-02 11:00", "12.00"),
+            ("2020-02-02 11:20", "11.20"),
+            ("2020-02-02 11:40", "11.80"),
+        ]
+        .build();
+        let sgs = sks_to_sgs(&sks)?;
+        assert_eq!(1, sgs.len());
+        let gap = sgs[0].gap.as_ref().expect("segment gap");
+        assert_eq!(new_ts("2020-02-02 11:00"), gap.ts);
+        assert_eq!(price!(11.00), gap.start_price);
+        assert_eq!(price!(11.20), gap.end_price);
+        assert!(!gap.filled);
+
+        let mut sks = sks;
+        sks.push(new_sk(
+            "2020-02-02 11:40",
+            "11.80",
+            "2020-02-02 12:00",
+            "10.80",
+        ));
+        let sgs = sks_to_sgs(&sks)?;
+        assert_eq!(1, sgs.len());
+        assert!(sgs[0].gap.as_ref().unwrap().filled);
+
+        // 创新高后缺口不再属于线段终点
+        sks.push(new_sk(
+            "2020-02-02 12:00",
+            "10.80",
+            "2020-02-02 13:00",
+            "12.50",
+        ));
+        let sgs = sks_to_sgs(&sks)?;
+        assert_eq!(1, sgs.len());
+        assert!(sgs[0].gap.is_none());
+
+        // 逆势笔直接越过起点，回补缺口的同时新增一段
+        let mut sks = sks[..5].to_vec();
+        sks.push(new_sk(
+            "2020-02-02 11:40",
+            "11.80",
+            "2020-02-02 12:00",
+            "9.50",
+        ));
+        let mut acc = SegmentAccumulator::new();
+        let mut replica = Vec::new();
+        for sk in &sks {
+            replay(&mut replica, acc.accumulate(sk)?)?;
+        }
+        assert_replica(&acc, &replica, &sks)?;
+        assert_eq!(2, replica.len());
+        assert!(replica[0].gap.as_ref().unwrap().filled);
+        Ok(())
+    }
+
+    // 笔的更新及删除与批量计算一致
+    #[test]
+    fn test_segment_stroke_update_and_delete() -> Result<()> {
+        let mut sks = vec![
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "10.80"),
+            ("2020-02-02 10:20", "10.50"),
+            ("2020-02-02 10:30", "11.20"),
+            ("2020-02-02 10:40", "10.30"),
+            ("2020-02-02 10:50", "10.60"),
+            ("2020-02-02 11:00", "10.40"),
+        ]
+        .build();
+        let mut acc = SegmentAccumulator::new();
+        let mut replica = Vec::new();
+        for sk in &sks {
+            replay(&mut replica, acc.accumulate(&StrokeDelta::Add(sk.clone()))?)?;
+        }
+        assert_eq!(sks_to_sgs(&sks)?, replica);
+        assert_eq!(1, acc.state.len());
+
+        // 最后一笔延伸后跌破前段起点，前段结束并新增一段
+        let sk = new_sk("2020-02-02 10:50", "10.60", "2020-02-02 11:10", "9.50");
+        *sks.last_mut().unwrap() = sk.clone();
+        let deltas = acc.accumulate(&StrokeDelta::Update(sk))?;
+        assert!(deltas.iter().any(|d| d.add().is_some()));
+        replay(&mut replica, deltas)?;
+        assert_eq!(sks_to_sgs(&sks)?, replica);
+        assert_eq!(2, acc.state.len());
+
+        // 连续更新
+        let sk = new_sk("2020-02-02 10:50", "10.60", "2020-02-02 11:20", "9.20");
+        *sks.last_mut().unwrap() = sk.clone();
+        let deltas = acc.accumulate(&StrokeDelta::Update(sk))?;
+        assert_eq!(
+            new_ts("2020-02-02 11:20"),
+            deltas.last().unwrap().update().unwrap().end_pt.extremum_ts
+        );
+        replay(&mut replica, deltas)?;
+        assert_eq!(sks_to_sgs(&sks)?, replica);
+
+        // 删除最后一笔，新增的线段随之删除
+        let sk = sks.pop().unwrap();
+        let deltas = acc.accumulate(&StrokeDelta::Delete(sk))?;
+        assert!(deltas.iter().any(|d| d.delete().is_some()));
+        replay(&mut replica, deltas)?;
+        assert_eq!(sks_to_sgs(&sks)?, replica);
+
+        // 快照已用，从头重播
+        let sk = sks.pop().unwrap();
+        replay(&mut replica, acc.accumulate(&StrokeDelta::Delete(sk))?)?;
+        assert_eq!(sks_to_sgs(&sks)?, replica);
+
+        // 删除的笔须与最后一笔匹配
+        let sk = new_sk("2020-02-02 10:40", "10.30", "2020-02-02 10:50", "10.60");
+        assert!(acc.accumulate(&StrokeDelta::Delete(sk)).is_err());
+        Ok(())
+    }
+
+    // 缺口回补前后的笔变更，按序应用全部变更后与累加器状态一致
+    #[test]
+    fn test_segment_gap_filled_deltas() -> Result<()> {
+        let mut sks = vec![
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:20", "11.00"),
+            ("2020-02-02 10:40", "10.50"),
+            ("2020-02-02 11:00", "12.00"),
+            ("2020-02-02 11:20", "11.20"),
+            ("2020-02-02 11:40", "11.80"),
+        ]
+        .build();
+        let mut acc = SegmentAccumulator::new();
+        let mut replica = Vec::new();
+        for sk in &sks {
+            replay(&mut replica, acc.accumulate(&StrokeDelta::Add(sk.clone()))?)?;
+        }
+        assert_replica(&acc, &replica, &sks)?;
+        assert!(!replica[0].gap.as_ref().unwrap().filled);
+
+        // 逆势笔回补缺口
+        let sk = new_sk("2020-02-02 11:40", "11.80", "2020-02-02 12:00", "10.80");
+        sks.push(sk.clone());
+        replay(&mut replica, acc.accumulate(&StrokeDelta::Add(sk))?)?;
+        assert_replica(&acc, &replica, &sks)?;
+        assert!(replica[0].gap.as_ref().unwrap().filled);
+
+        // 回补笔延伸越过起点
+        let sk = new_sk("2020-02-02 11:40", "11.80", "2020-02-02 12:10", "9.50");
+        *sks.last_mut().unwrap() = sk.clone();
+        replay(&mut replica, acc.accumulate(&StrokeDelta::Update(sk))?)?;
+        assert_replica(&acc, &replica, &sks)?;
+
+        // 删除回补笔，缺口恢复为未回补
+        let sk = sks.pop().unwrap();
+        replay(&mut replica, acc.accumulate(&StrokeDelta::Delete(sk))?)?;
+        assert_replica(&acc, &replica, &sks)?;
+        assert!(!replica[0].gap.as_ref().unwrap().filled);
+
+        // 再次回补后创新高
+        for sk in [
+            new_sk("2020-02-02 11:40", "11.80", "2020-02-02 12:00", "10.80"),
+            new_sk("2020-02-02 12:00", "10.80", "2020-02-02 13:00", "12.50"),
+        ] {
+            sks.push(sk.clone());
+            replay(&mut replica, acc.accumulate(&StrokeDelta::Add(sk))?)?;
+            assert_replica(&acc, &replica, &sks)?;
+        }
+        assert!(replica[0].gap.is_none());
+        Ok(())
+    }
+
+    // 决策日志
+    #[test]
+    fn test_segment_traced() -> Result<()> {
+        let sks = vec![
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:20", "11.00"),
+            ("2020-02-02 10:40", "10.50"),
+            ("2020-02-02 11:00", "12.00"),
+            ("2020-02-02 11:20", "11.20"),
+        ]
+        .build();
+        let (sgs, traces) = sks_to_sgs_traced(&sks)?;
+        assert_eq!(1, sgs.len());
+        assert_eq!(sks.len(), traces.len());
+        assert_eq!(new_ts("2020-02-02 11:20"), traces[3].ts);
+        assert!(traces[3].rule.starts_with("Continue→GapInverse"));
         Ok(())
     }
 
@@ -1096,12 +1639,12 @@ mod tests {
     #[test]
     fn test_segment_gap_with_exceeding() -> Result<()> {
         let sks = vec![
-            new_sk("2020-02-02 10:00", 10.00, "2020-02-02 10:10", 10.50),
-            new_sk("2020-02-02 10:10", 10.50, "2020-02-02 10:20", 10.30),
-            new_sk("2020-02-02 10:20", 10.30, "2020-02-02 10:30", 11.20),
-            new_sk("2020-02-02 10:30", 11.20, "2020-02-02 10:40", 10.90),
-            new_sk("2020-02-02 10:40", 10.90, "2020-02-02 10:50", 11.50),
-            new_sk("2020-02-02 10:50", 11.50, "2020-02-02 11:00", 11.30),
+            new_sk("2020-02-02 10:00", "10.00", "2020-02-02 10:10", "10.50"),
+            new_sk("2020-02-02 10:10", "10.50", "2020-02-02 10:20", "10.30"),
+            new_sk("2020-02-02 10:20", "10.30", "2020-02-02 10:30", "11.20"),
+            new_sk("2020-02-02 10:30", "11.20", "2020-02-02 10:40", "10.90"),
+            new_sk("2020-02-02 10:40", "10.90", "2020-02-02 10:50", "11.50"),
+            new_sk("2020-02-02 10:50", "11.50", "2020-02-02 11:00", "11.30"),
         ];
         let sgs = sks_to_sgs(&sks)?;
 
@@ -1115,14 +1658,14 @@ mod tests {
     #[test]
     fn test_segment_inclusive_parting_left() -> Result<()> {
         let sks = vec![
-            new_sk("2020-02-02 10:00", 10.00, "2020-02-02 10:10", 11.00),
-            new_sk("2020-02-02 10:10", 11.00, "2020-02-02 10:20", 10.20),
-            new_sk("2020-02-02 10:20", 10.20, "2020-02-02 10:30", 10.80),
-            new_sk("2020-02-02 10:30", 10.80, "2020-02-02 10:40", 10.50),
-            new_sk("2020-02-02 10:40", 10.50, "2020-02-02 10:50", 11.30),
-            new_sk("2020-02-02 10:50", 11.30, "2020-02-02 11:00", 10.40),
-            new_sk("2020-02-02 11:00", 10.40, "2020-02-02 11:10", 10.70),
-            new_sk("2020-02-02 11:10", 10.70, "2020-02-02 11:20", 10.10),
+            new_sk("2020-02-02 10:00", "10.00", "2020-02-02 10:10", "11.00"),
+            new_sk("2020-02-02 10:10", "11.00", "2020-02-02 10:20", "10.20"),
+            new_sk("2020-02-02 10:20", "10.20", "2020-02-02 10:30", "10.80"),
+            new_sk("2020-02-02 10:30", "10.80", "2020-02-02 10:40", "10.50"),
+            new_sk("2020-02-02 10:40", "10.50", "2020-02-02 10:50", "11.30"),
+            new_sk("2020-02-02 10:50", "11.30", "2020-02-02 11:00", "10.40"),
+            new_sk("2020-02-02 11:00", "10.40", "2020-02-02 11:10", "10.70"),
+            new_sk("2020-02-02 11:10", "10.70", "2020-02-02 11:20", "10.10"),
         ];
         let sgs = sks_to_sgs(&sks)?;
 
@@ -1138,17 +1681,17 @@ mod tests {
     #[test]
     fn test_segment_inclusive_parting_right() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:10", 10.50),
-            ("2020-02-02 10:20", 10.30),
-            ("2020-02-02 10:30", 10.80),
-            ("2020-02-02 10:40", 10.40),
-            ("2020-02-02 10:50", 11.30),
-            ("2020-02-02 11:00", 10.30),
-            ("2020-02-02 11:10", 11.00),
-            ("2020-02-02 11:20", 10.70),
-            ("2020-02-02 11:30", 11.00),
-            ("2020-02-02 13:10", 10.10),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "10.50"),
+            ("2020-02-02 10:20", "10.30"),
+            ("2020-02-02 10:30", "10.80"),
+            ("2020-02-02 10:40", "10.40"),
+            ("2020-02-02 10:50", "11.30"),
+            ("2020-02-02 11:00", "10.30"),
+            ("2020-02-02 11:10", "11.00"),
+            ("2020-02-02 11:20", "10.70"),
+            ("2020-02-02 11:30", "11.00"),
+            ("2020-02-02 13:10", "10.10"),
         ]
         .build();
         let sgs = sks_to_sgs(&sks)?;
@@ -1164,13 +1707,13 @@ mod tests {
     #[test]
     fn test_segment_first_inverse_to_inverse() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:10", 12.00),
-            ("2020-02-02 10:20", 10.20),
-            ("2020-02-02 10:30", 11.00),
-            ("2020-02-02 10:40", 10.50),
-            ("2020-02-02 10:50", 11.50),
-            ("2020-02-02 11:00", 10.80),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "12.00"),
+            ("2020-02-02 10:20", "10.20"),
+            ("2020-02-02 10:30", "11.00"),
+            ("2020-02-02 10:40", "10.50"),
+            ("2020-02-02 10:50", "11.50"),
+            ("2020-02-02 11:00", "10.80"),
         ]
         .build();
         let sgs = sks_to_sgs(&sks)?;
@@ -1183,13 +1726,13 @@ mod tests {
     #[test]
     fn test_segment_first_inverse_to_gap_inverse() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:10", 12.00),
-            ("2020-02-02 10:20", 10.20),
-            ("2020-02-02 10:30", 11.00),
-            ("2020-02-02 10:40", 10.50),
-            ("2020-02-02 10:50", 11.50),
-            ("2020-02-02 11:00", 11.20),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "12.00"),
+            ("2020-02-02 10:20", "10.20"),
+            ("2020-02-02 10:30", "11.00"),
+            ("2020-02-02 10:40", "10.50"),
+            ("2020-02-02 10:50", "11.50"),
+            ("2020-02-02 11:00", "11.20"),
         ]
         .build();
         let sgs = sks_to_sgs(&sks)?;
@@ -1202,16 +1745,16 @@ mod tests {
     #[test]
     fn test_segment_inverse_first_long_stroke_not_inclusive() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:10", 11.00),
-            ("2020-02-02 10:20", 10.50),
-            ("2020-02-02 10:30", 12.00),
-            ("2020-02-02 10:40", 10.70),
-            ("2020-02-02 10:50", 11.50),
-            ("2020-02-02 11:00", 11.00),
-            ("2020-02-02 11:10", 11.20),
-            ("2020-02-02 11:20", 10.60),
-            ("2020-02-02 11:30", 11.00),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "11.00"),
+            ("2020-02-02 10:20", "10.50"),
+            ("2020-02-02 10:30", "12.00"),
+            ("2020-02-02 10:40", "10.70"),
+            ("2020-02-02 10:50", "11.50"),
+            ("2020-02-02 11:00", "11.00"),
+            ("2020-02-02 11:10", "11.20"),
+            ("2020-02-02 11:20", "10.60"),
+            ("2020-02-02 11:30", "11.00"),
         ]
         .build();
         let sgs = sks_to_sgs(&sks)?;
@@ -1226,16 +1769,16 @@ mod tests {
     #[test]
     fn test_segment_inverse_first_long_stroke_inclusive() -> Result<()> {
         let sks = vec![
-            ("2020-02-02 10:00", 10.00),
-            ("2020-02-02 10:10", 11.00),
-            ("2020-02-02 10:20", 10.50),
-            ("2020-02-02 10:30", 12.00),
-            ("2020-02-02 10:40", 10.70),
-            ("2020-02-02 10:50", 11.50),
-            ("2020-02-02 11:00", 11.00),
-            ("2020-02-02 11:10", 11.20),
-            ("2020-02-02 11:20", 10.80),
-            ("2020-02-02 11:30", 11.00),
+            ("2020-02-02 10:00", "10.00"),
+            ("2020-02-02 10:10", "11.00"),
+            ("2020-02-02 10:20", "10.50"),
+            ("2020-02-02 10:30", "12.00"),
+            ("2020-02-02 10:40", "10.70"),
+            ("2020-02-02 10:50", "11.50"),
+            ("2020-02-02 11:00", "11.00"),
+            ("2020-02-02 11:10", "11.20"),
+            ("2020-02-02 11:20", "10.80"),
+            ("2020-02-02 11:30", "11.00"),
         ]
         .build();
         let sgs = sks_to_sgs(&sks)?;
@@ -1247,14 +1790,90 @@ mod tests {
         Ok(())
     }
 
-    fn new_sk(start_ts: &str, start_price: f64, end_ts: &str, end_price: f64) -> Stroke {
-        let upward = start_price < end_price;
+    // 多段之后连续更新最后一笔，仅重播最近的笔，结果与批量计算一致
+    #[test]
+    fn test_segment_replay_tail() -> Result<()> {
+        let start = new_ts("2020-02-03 10:00");
+        let mut price = 1000;
+        let mut pts = vec![(start, price)];
+        for (i, step) in [30, -10, 30, -10, 30, -40, 10, -40, 10, -40]
+            .iter()
+            .cycle()
+            .take(60)
+            .enumerate()
+        {
+            price += step;
+            pts.push((
+                start + chrono::Duration::minutes(10 * (i as i64 + 1)),
+                price,
+            ));
+        }
+        let fmt_ts = |ts: &NaiveDateTime| ts.format("%Y-%m-%d %H:%M").to_string();
+        let fmt_price = |price: i32| (BigDecimal::from(price) / BigDecimal::from(100)).to_string();
+        let mut acc = SegmentAccumulator::new();
+        let mut replica = Vec::new();
+        let mut sks = Vec::new();
+        let mut max_sks = 0;
+        for w in pts.windows(2) {
+            let (start_ts, start_price) = (fmt_ts(&w[0].0), fmt_price(w[0].1));
+            let end_ts = fmt_ts(&w[1].0);
+            let new_stroke =
+                |price: i32| new_sk(&start_ts, &start_price, &end_ts, &fmt_price(price));
+            // 先以一半的幅度加入，再两次更新，第二次更新时快照已用
+            let half = (w[0].1 + w[1].1) / 2;
+            let sk = new_stroke(half);
+            replay(&mut replica, acc.accumulate(&StrokeDelta::Add(sk.clone()))?)?;
+            sks.push(sk);
+            for p in &[half, w[1].1] {
+                let sk = new_stroke(*p);
+                *sks.last_mut().unwrap() = sk.clone();
+                replay(&mut replica, acc.accumulate(&StrokeDelta::Update(sk))?)?;
+            }
+            assert_replica(&acc, &replica, &sks)?;
+            max_sks = max_sks.max(acc.sks.len());
+        }
+        assert!(replica.len() >= 4);
+        assert!(acc.sks_dropped);
+        assert!(max_sks < sks.len() / 2);
+
+        // 连续删除，回溯至较早的重播起点
+        for _ in 0..3 {
+            let sk = sks.pop().unwrap();
+            replay(&mut replica, acc.accumulate(&StrokeDelta::Delete(sk))?)?;
+            assert_replica(&acc, &replica, &sks)?;
+        }
+        Ok(())
+    }
+
+    fn replay(replica: &mut Vec<Segment>, deltas: Vec<SegmentDelta>) -> Result<()> {
+        for d in deltas {
+            apply_delta(replica, d)?;
+        }
+        Ok(())
+    }
+
+    // 副本须与累加器状态及批量计算一致
+    fn assert_replica(acc: &SegmentAccumulator, replica: &[Segment], sks: &[Stroke]) -> Result<()> {
+        let sgs: Vec<Segment> = acc.state.iter().map(csegment_to_segment).collect();
+        assert_eq!(sgs, replica);
+        assert_eq!(sks_to_sgs(sks)?, replica);
+        Ok(())
+    }
+
+    fn new_sk(start_ts: &str, start_price: &str, end_ts: &str, end_price: &str) -> Stroke {
+        let upward = parse_price(start_price).unwrap() < parse_price(end_price).unwrap();
         let start_pt = new_pt_fix_width(start_ts, 1, start_price, 3, !upward);
         let end_pt = new_pt_fix_width(end_ts, 1, end_price, 3, upward);
         Stroke { start_pt, end_pt }
     }
 
-    fn new_pt_fix_width(ts: &str, minutes: i64, extremum_price: f64, n: i32, top: bool) -> Parting {
+    fn new_pt_fix_width(
+        ts: &str,
+        minutes: i64,
+        extremum_price: &str,
+        n: i32,
+        top: bool,
+    ) -> Parting {
         let extremum_ts = new_ts(ts);
         let start_ts = extremum_ts - chrono::Duration::minutes(minutes);
         let end_ts = extremum_ts + chrono::Duration::minutes(minutes);
@@ -1262,7 +1881,7 @@ mod tests {
             start_ts,
             extremum_ts,
             end_ts,
-            extremum_price: BigDecimal::from(extremum_price),
+            extremum_price: parse_price(extremum_price).unwrap(),
             n,
             top,
             left_gap: None,
@@ -1278,7 +1897,7 @@ mod tests {
         fn build(self) -> Vec<Stroke>;
     }
 
-    impl BuildStrokeVec for Vec<(&str, f64)> {
+    impl BuildStrokeVec for Vec<(&str, &str)> {
         fn build(self) -> Vec<Stroke> {
             self.iter()
                 .zip(self.iter().skip(1))
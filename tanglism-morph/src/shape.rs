@@ -1,6 +1,7 @@
 use bigdecimal::BigDecimal;
 use chrono::NaiveDateTime;
 use serde_derive::*;
+use tanglism_utils::deserialize_price;
 
 /// K线
 ///
@@ -9,7 +10,9 @@ use serde_derive::*;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct K {
     pub ts: NaiveDateTime,
+    #[serde(deserialize_with = "deserialize_price")]
     pub low: BigDecimal,
+    #[serde(deserialize_with = "deserialize_price")]
     pub high: BigDecimal,
 }
 
@@ -35,7 +38,7 @@ pub struct PriceRange {
 /// 分型实际可由多于3根K线构成，只要两侧的K线满足包含原则。
 /// 按照缠论的严格定义，分型仅适用与最小级别的K线图，即1分钟K线图上，后续分析都由
 /// 1分钟K线图向上递归构成更大的形态。
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Parting {
     // 分型起始时刻，已考虑K线包含关系
     pub start_ts: NaiveDateTime,
@@ -55,12 +58,71 @@ pub struct Parting {
     pub right_gap: Option<Box<Gap>>,
 }
 
+/// 测试用的分型，起止时刻与极值时刻相同，时刻格式为"%Y-%m-%d %H:%M"
+#[cfg(any(test, feature = "test-util"))]
+pub fn new_pt(ts: &str, price: impl Into<BigDecimal>, top: bool) -> Parting {
+    let ts = NaiveDateTime::parse_from_str(ts, "%Y-%m-%d %H:%M").unwrap();
+    Parting {
+        start_ts: ts,
+        end_ts: ts,
+        extremum_ts: ts,
+        extremum_price: price.into(),
+        n: 3,
+        top,
+        left_gap: None,
+        right_gap: None,
+    }
+}
+
+/// 测试用的值点，时刻格式为"%Y-%m-%d %H:%M"
+#[cfg(any(test, feature = "test-util"))]
+pub fn new_vp(ts: &str, value: &str) -> ValuePoint {
+    ValuePoint {
+        ts: NaiveDateTime::parse_from_str(ts, "%Y-%m-%d %H:%M").unwrap(),
+        value: tanglism_utils::parse_price(value).unwrap(),
+    }
+}
+
+/// 测试用的次级别走势序列，相邻两点构成一段普通次级别走势
+#[cfg(any(test, feature = "test-util"))]
+pub fn new_sts(pts: &[(&str, &str)], level: i32) -> Vec<SubTrend> {
+    let vps: Vec<ValuePoint> = pts.iter().map(|(ts, v)| new_vp(ts, v)).collect();
+    vps_to_sts(&vps, level)
+}
+
+/// 测试用的次级别走势序列，自起始时刻起每点间隔一日
+#[cfg(any(test, feature = "test-util"))]
+pub fn new_daily_sts(start_ts: &str, values: &[&str], level: i32) -> Vec<SubTrend> {
+    let start = NaiveDateTime::parse_from_str(start_ts, "%Y-%m-%d %H:%M").unwrap();
+    let vps: Vec<ValuePoint> = values
+        .iter()
+        .enumerate()
+        .map(|(i, v)| ValuePoint {
+            ts: start + chrono::Duration::days(i as i64),
+            value: tanglism_utils::parse_price(v).unwrap(),
+        })
+        .collect();
+    vps_to_sts(&vps, level)
+}
+
+#[cfg(any(test, feature = "test-util"))]
+fn vps_to_sts(vps: &[ValuePoint], level: i32) -> Vec<SubTrend> {
+    vps.windows(2)
+        .map(|w| SubTrend {
+            start: w[0].clone(),
+            end: w[1].clone(),
+            level,
+            typ: SubTrendType::Normal,
+        })
+        .collect()
+}
+
 /// 笔
 ///
 /// 缠论的基础概念
 /// 由相邻的顶分型与底分型构成，不可同底或同顶，同时需满足两分型间有至少1根独立K线，
 /// 即存在1条K线，不属于两侧的分型，且不能因为包含原则属于两侧的分型。
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Stroke {
     pub start_pt: Parting,
     pub end_pt: Parting,
@@ -90,10 +152,16 @@ impl Stroke {
 /// 顶分型的顶即向上线段的结束。
 /// 底分型的底即向下线段的结束。
 /// 当确定线段终点后，该终点后的笔不再归属于该线段。
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Segment {
     pub start_pt: Parting,
     pub end_pt: Parting,
+    /// 线段结束处的特征序列缺口
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gap: Option<SegmentGap>,
+    /// 组成线段的笔在笔序列中的下标区间（首尾均包含）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stroke_range: Option<(usize, usize)>,
 }
 
 impl Segment {
@@ -119,6 +187,19 @@ pub struct Gap {
     pub end_price: BigDecimal,
 }
 
+/// 特征序列缺口
+///
+/// 线段转折点后的第一笔逆势笔与之前的特征序列没有重叠时产生（67课）
+/// 价格区间由前一特征序列笔的起点与转折后第一笔的终点构成
+/// 若其后的逆势笔回到前一特征序列笔的起点，则缺口被回补
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SegmentGap {
+    pub ts: NaiveDateTime,
+    pub start_price: BigDecimal,
+    pub end_price: BigDecimal,
+    pub filled: bool,
+}
+
 /// 中枢元素
 ///
 /// 中枢与分型，笔，线段有很大的不同。
@@ -127,7 +208,7 @@ pub struct Gap {
 /// 因此中枢分析产生的序列既包含中枢，也包含次级别走势乃至缺口
 /// 这里统一使用CenterElement枚举进行建模
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(tag = "type", content = "data")]
 pub enum CenterElement {
     Center(Center),
@@ -237,7 +318,7 @@ impl CenterElement {
 /// 1分钟K线图的笔即可视为1分钟“中枢”，极端如20课所说，
 /// 连续多天开盘封涨停仍只形成1分钟中枢。
 /// 5分钟的中枢由至少3个1分钟级别的线段构成。
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Center {
     // 起始点
     pub start: ValuePoint,
@@ -260,6 +341,12 @@ pub struct Center {
     pub upward: bool,
     // 组成该中枢的次级别走势个数
     pub n: usize,
+    // 组成该中枢的次级别走势下标区间，首尾均包含
+    #[serde(default)]
+    pub subtrend_range: (usize, usize),
+    // 延伸的次级别走势个数，即n - 3
+    #[serde(default)]
+    pub extended: usize,
 }
 
 impl Center {
@@ -283,7 +370,7 @@ impl Center {
 ///
 /// 这里的类中枢与缠论略有不同。
 /// 连接两个中枢间的多段走势未构成标准中枢，则归为类中枢。
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct SemiCenter {
     pub start: ValuePoint,
     pub end: ValuePoint,
@@ -292,15 +379,21 @@ pub struct SemiCenter {
     pub n: usize,
     // 是否与前一个中枢共享起始次级别走势
     pub shared_start: bool,
+    // 组成该类中枢的次级别走势下标区间，首尾均包含
+    #[serde(default)]
+    pub subtrend_range: (usize, usize),
+    // 延伸的次级别走势个数，即n - 3
+    #[serde(default)]
+    pub extended: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ValuePoint {
     pub ts: NaiveDateTime,
     pub value: BigDecimal,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum SubTrendType {
     Normal,
     // 由缺口形成的次级别
@@ -309,12 +402,14 @@ pub enum SubTrendType {
     Divider,
     // 由多条线段组合而成
     Combination,
+    // 由低级别走势升级而成
+    Derived,
 }
 
 /// 次级别走势
 ///
 /// 当前实现使用次级别K线图中的线段和笔（次级别以下）
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct SubTrend {
     pub start: ValuePoint,
     pub end: ValuePoint,
@@ -350,7 +445,7 @@ pub struct Trend {
 }
 
 /// 买卖点
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum Choice {
     BuyOne,
     BuyTwo,
@@ -2,7 +2,9 @@
 //!
 //! 缠论增量处理
 
-use crate::Result;
+use crate::{Error, Result};
+use chrono::NaiveDateTime;
+use serde_derive::*;
 
 /// 累加器
 ///
@@ -36,7 +38,8 @@ pub trait Replicator {
     fn state(&self) -> &Self::State;
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
 pub enum Delta<T> {
     None,
     Add(T),
@@ -73,3 +76,336 @@ impl<T> Delta<T> {
         }
     }
 }
+
+/// 复制消息
+///
+/// 跨网络复制时，先发送一致的快照，其后按序号发送变更
+/// 序号严格递增，快照的序号即其后首个变更序号的前一位
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum ReplicaMessage<T> {
+    Snapshot { seq: u64, state: Vec<T> },
+    Delta { seq: u64, delta: Delta<T> },
+}
+
+/// 复制消息发布器
+///
+/// 记录已发布的状态，新状态与其比较得到有序变更
+/// 变更仅作用于末尾元素，与累加器的输出一致
+#[derive(Debug, Clone, Default)]
+pub struct ReplicaPublisher<T> {
+    seq: u64,
+    published: Option<Vec<T>>,
+}
+
+impl<T: Clone + PartialEq> ReplicaPublisher<T> {
+    pub fn new() -> Self {
+        ReplicaPublisher {
+            seq: 0,
+            published: None,
+        }
+    }
+
+    /// 发布新状态，首次发布或重置后发送快照，否则发送变更
+    pub fn publish(&mut self, state: &[T]) -> Vec<ReplicaMessage<T>> {
+        let published = match self.published.take() {
+            Some(published) => published,
+            None => return vec![self.snapshot(state)],
+        };
+        let msgs = tail_deltas(&published, state)
+            .into_iter()
+            .map(|delta| self.next(delta))
+            .collect();
+        self.published = Some(state.to_vec());
+        msgs
+    }
+
+    /// 重置发布状态，下次发布时重新发送快照
+    pub fn reset(&mut self) {
+        self.published = None;
+    }
+
+    fn snapshot(&mut self, state: &[T]) -> ReplicaMessage<T> {
+        self.published = Some(state.to_vec());
+        ReplicaMessage::Snapshot {
+            seq: self.seq,
+            state: state.to_vec(),
+        }
+    }
+
+    fn next(&mut self, delta: Delta<T>) -> ReplicaMessage<T> {
+        self.seq += 1;
+        ReplicaMessage::Delta {
+            seq: self.seq,
+            delta,
+        }
+    }
+}
+
+/// 比较新旧序列，生成作用于末尾元素的有序变更
+///
+/// 自末尾删除，直至仅剩一个不同元素，可更新则更新，否则删除，其后依次新增
+pub(crate) fn tail_deltas<T: Clone + PartialEq>(old: &[T], new: &[T]) -> Vec<Delta<T>> {
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut deltas = Vec::new();
+    for item in old[prefix..].iter().skip(1).rev() {
+        deltas.push(Delta::Delete(item.clone()));
+    }
+    if let Some(item) = old.get(prefix) {
+        match new.get(prefix) {
+            Some(new) => deltas.push(Delta::Update(new.clone())),
+            None => deltas.push(Delta::Delete(item.clone())),
+        }
+    }
+    let added = if prefix < old.len() {
+        prefix + 1
+    } else {
+        prefix
+    };
+    for item in new.iter().skip(added) {
+        deltas.push(Delta::Add(item.clone()));
+    }
+    deltas
+}
+
+/// 按序将变更作用于序列，变更仅作用于末尾元素
+pub(crate) fn apply_delta<T>(items: &mut Vec<T>, delta: Delta<T>) -> Result<()> {
+    match delta {
+        Delta::None => (),
+        Delta::Add(item) => items.push(item),
+        Delta::Update(item) => match items.last_mut() {
+            Some(last) => *last = item,
+            None => return Err(Error::Msg("update on empty state".to_owned())),
+        },
+        Delta::Delete(_) => {
+            if items.pop().is_none() {
+                return Err(Error::Msg("delete on empty state".to_owned()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 复制消息客户端
+///
+/// 接收快照与有序变更，维护状态副本
+/// 变更序号不连续时返回错误，调用方应重新请求快照
+#[derive(Debug, Clone, Default)]
+pub struct ReplicaClient<T> {
+    seq: Option<u64>,
+    state: Vec<T>,
+}
+
+impl<T> ReplicaClient<T> {
+    pub fn new() -> Self {
+        ReplicaClient {
+            seq: None,
+            state: Vec::new(),
+        }
+    }
+
+    /// 最近一次处理的序号
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+}
+
+impl<T: Clone> Replicator for ReplicaClient<T> {
+    type Delta = ReplicaMessage<T>;
+    type State = Vec<T>;
+
+    fn replicate(&mut self, delta: Self::Delta) -> Result<()> {
+        match delta {
+            ReplicaMessage::Snapshot { seq, state } => {
+                self.seq = Some(seq);
+                self.state = state;
+            }
+            ReplicaMessage::Delta { seq, delta } => {
+                match self.seq {
+                    None => return Err(Error::Msg("replica delta before snapshot".to_owned())),
+                    // 重复的变更直接忽略
+                    Some(last) if seq <= last => return Ok(()),
+                    Some(last) if seq != last + 1 => {
+                        return Err(Error::Msg(format!(
+                            "replica sequence gap: expected {}, got {}",
+                            last + 1,
+                            seq
+                        )))
+                    }
+                    _ => (),
+                }
+                apply_delta(&mut self.state, delta)?;
+                self.seq = Some(seq);
+            }
+        }
+        Ok(())
+    }
+
+    fn state(&self) -> &Self::State {
+        &self.state
+    }
+}
+
+/// 按重置边界切分输入
+///
+/// 边界时刻及之后的元素归入新的分区，边界需升序排列
+pub fn partition_at<'a, T, F>(items: &'a [T], boundaries: &[NaiveDateTime], ts: F) -> Vec<&'a [T]>
+where
+    F: Fn(&T) -> NaiveDateTime,
+{
+    let mut parts = Vec::new();
+    let mut bounds = boundaries.iter().peekable();
+    let mut start = 0;
+    for (i, item) in items.iter().enumerate() {
+        let t = ts(item);
+        let mut crossed = false;
+        while bounds.next_if(|b| **b <= t).is_some() {
+            crossed = true;
+        }
+        if crossed && i > start {
+            parts.push(&items[start..i]);
+            start = i;
+        }
+    }
+    if start < items.len() {
+        parts.push(&items[start..]);
+    }
+    parts
+}
+
+/// 各交易日首个元素的时刻，首日除外，用作日内模式的重置边界
+pub fn day_boundaries<T, F>(items: &[T], ts: F) -> Vec<NaiveDateTime>
+where
+    F: Fn(&T) -> NaiveDateTime,
+{
+    items
+        .windows(2)
+        .filter_map(|w| {
+            let (prev, curr) = (ts(&w[0]), ts(&w[1]));
+            if curr.date() != prev.date() {
+                Some(curr)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// 分区聚合
+///
+/// 每个分区以新的累加器重新开始，结果依次拼接，不同分区的元素不会组成同一形态
+pub fn aggregate_partitioned<'a, T, O, A, F>(parts: &[&'a [T]], mut new_acc: F) -> Result<Vec<O>>
+where
+    A: Aggregator<&'a [T], Vec<O>>,
+    F: FnMut() -> A,
+{
+    let mut rst = Vec::new();
+    for part in parts {
+        rst.extend(new_acc().aggregate(part)?);
+    }
+    Ok(rst)
+}
+
+/// 决策日志
+///
+/// 记录累加器处理每个输入时触发的规则，便于排查笔/段边界的分歧
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trace {
+    // 输入元素的时刻
+    pub ts: NaiveDateTime,
+    // 触发的规则
+    pub rule: String,
+}
+
+/// 决策日志记录器
+///
+/// 默认关闭，关闭时不记录任何内容
+#[derive(Debug, Clone, Default)]
+pub struct Tracer(Option<Vec<Trace>>);
+
+impl Tracer {
+    pub fn enabled() -> Self {
+        Tracer(Some(Vec::new()))
+    }
+
+    #[inline]
+    pub fn trace(&mut self, ts: NaiveDateTime, rule: &str) {
+        if let Some(traces) = self.0.as_mut() {
+            traces.push(Trace {
+                ts,
+                rule: rule.to_owned(),
+            });
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<Trace> {
+        self.0.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replica_publish_and_replicate() -> Result<()> {
+        let mut publisher = ReplicaPublisher::new();
+        let mut client = ReplicaClient::new();
+        for state in &[
+            vec![1, 2, 3],
+            vec![1, 2, 3, 4],
+            vec![1, 2, 5],
+            vec![1, 6, 7, 8],
+            vec![1],
+            vec![1],
+        ] {
+            for msg in publisher.publish(state) {
+                client.replicate(msg)?;
+            }
+            assert_eq!(state, client.state());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_replica_sequence_gap() {
+        let mut publisher = ReplicaPublisher::new();
+        let mut client = ReplicaClient::new();
+        client.replicate(publisher.publish(&[1]).remove(0)).unwrap();
+        let msgs = publisher.publish(&[1, 2, 3]);
+        assert_eq!(2, msgs.len());
+        // 重复消息被忽略，跳过的消息报错
+        client.replicate(msgs[0].clone()).unwrap();
+        client.replicate(msgs[0].clone()).unwrap();
+        assert_eq!(&vec![1, 2], client.state());
+        let msgs = publisher.publish(&[1, 2, 3, 4, 5]);
+        assert!(client.replicate(msgs[1].clone()).is_err());
+    }
+
+    #[test]
+    fn test_partition_at() {
+        let ts = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        let items = vec![
+            ts("2020-02-03 14:59"),
+            ts("2020-02-03 15:00"),
+            ts("2020-02-04 09:31"),
+            ts("2020-02-04 09:32"),
+            ts("2020-02-05 09:31"),
+        ];
+        let boundaries = day_boundaries(&items, |t| *t);
+        assert_eq!(vec![items[2], items[4]], boundaries);
+        let parts = partition_at(&items, &boundaries, |t| *t);
+        assert_eq!(
+            vec![2, 2, 1],
+            parts.iter().map(|p| p.len()).collect::<Vec<_>>()
+        );
+        // 早于首个元素的边界不产生空分区
+        let parts = partition_at(&items, &[ts("2020-02-01 09:31")], |t| *t);
+        assert_eq!(1, parts.len());
+        assert!(partition_at(&items[..0], &boundaries, |t| *t).is_empty());
+    }
+}
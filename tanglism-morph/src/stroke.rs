@@ -1,12 +1,14 @@
 use crate::parting::PartingDelta;
 use crate::shape::{Parting, Stroke};
-use crate::stream::{Accumulator, Aggregator, Delta};
+use crate::stream::{
+    aggregate_partitioned, partition_at, Accumulator, Aggregator, Delta, Trace, Tracer,
+};
 use crate::Result;
 use bigdecimal::BigDecimal;
+use chrono::NaiveDateTime;
 use lazy_static::*;
 use serde_derive::*;
-use std::str::FromStr;
-use tanglism_utils::{LocalTradingTimestamps, TradingTimestamps};
+use tanglism_utils::{price, LocalTradingTimestamps, Tick, TradingTimestamps};
 
 /// 将分型序列解析为笔序列
 ///
@@ -15,14 +17,65 @@ use tanglism_utils::{LocalTradingTimestamps, TradingTimestamps};
 /// 2. 选择下一个点。
 ///    若异型：邻接或交叉则忽略，不邻接则成笔
 ///    若同型：顶更高/底更低则修改当前笔，反之则忽略
-pub fn pts_to_sks(pts: &[Parting], tick: &str, cfg: StrokeConfig) -> Result<Vec<Stroke>> {
-    StrokeAccumulator::new(tick, cfg)?.aggregate(pts)
+pub fn pts_to_sks(pts: &[Parting], tick: Tick, cfg: StrokeConfig) -> Result<Vec<Stroke>> {
+    StrokeAccumulator::new(tick, cfg).aggregate(pts)
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// 将分型序列解析为笔序列，在各重置边界重新开始
+pub fn pts_to_sks_partitioned(
+    pts: &[Parting],
+    tick: Tick,
+    cfg: StrokeConfig,
+    boundaries: &[NaiveDateTime],
+) -> Result<Vec<Stroke>> {
+    let parts = partition_at(pts, boundaries, |pt| pt.start_ts);
+    aggregate_partitioned(&parts, || StrokeAccumulator::new(tick, cfg.clone()))
+}
+
+/// 将分型序列解析为笔序列，并返回累加器结束时的内部状态
+pub fn pts_to_sks_snapshot(
+    pts: &[Parting],
+    tick: Tick,
+    cfg: StrokeConfig,
+) -> Result<(Vec<Stroke>, StrokeAccSnapshot)> {
+    let mut acc = StrokeAccumulator::new(tick, cfg);
+    for pt in pts {
+        acc.accumulate_add(pt)?;
+    }
+    let sks = acc.state.iter().map(cstroke_to_stroke).collect();
+    Ok((sks, acc.snapshot()))
+}
+
+/// 笔累加器的内部状态，用于诊断
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrokeAccSnapshot {
+    pub strokes: usize,
+    // 最后一笔之后尚未成笔的分型
+    pub pending: Vec<Parting>,
+    // 最后一笔及其被修改前的版本
+    pub last: Option<CStroke>,
+}
+
+/// 将分型序列解析为笔序列，并返回每个分型触发的规则
+pub fn pts_to_sks_traced(
+    pts: &[Parting],
+    tick: Tick,
+    cfg: StrokeConfig,
+) -> Result<(Vec<Stroke>, Vec<Trace>)> {
+    let mut acc = StrokeAccumulator::new(tick, cfg).traced();
+    for pt in pts {
+        acc.accumulate_add(pt)?;
+    }
+    let sks = acc.state.iter().map(cstroke_to_stroke).collect();
+    Ok((sks, acc.tracer.take()))
+}
+
+#[derive(Debug, Clone, PartialEq, Hash, Serialize, Deserialize)]
 pub struct StrokeConfig {
     pub indep_k: bool,
     pub judge: StrokeJudge,
+    // 最小笔幅度，小于该幅度的笔并入相邻笔
+    pub min_amplitude: Option<StrokeAmplitude>,
 }
 
 impl Default for StrokeConfig {
@@ -30,21 +83,34 @@ impl Default for StrokeConfig {
         StrokeConfig {
             indep_k: false,
             judge: StrokeJudge::GapOpening(false),
+            min_amplitude: None,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Hash, Serialize, Deserialize)]
+pub enum StrokeAmplitude {
+    // 绝对价差
+    Absolute(BigDecimal),
+    // 相对起点价格的比例
+    Ratio(BigDecimal),
+}
+
+#[derive(Debug, Clone, PartialEq, Hash, Serialize, Deserialize)]
 pub enum StrokeJudge {
     None,
     // 开盘缺口，是否包含下午盘开盘
     GapOpening(bool),
     // 比例缺口
     GapRatio(BigDecimal),
+    // 任一条件满足即成笔，空列表视为不满足
+    Any(Vec<StrokeJudge>),
+    // 所有条件满足才成笔，空列表视为不满足
+    All(Vec<StrokeJudge>),
 }
 
 lazy_static! {
-    static ref GAP_MINIMAL_BASE: BigDecimal = BigDecimal::from_str("0.01").unwrap();
+    static ref GAP_MINIMAL_BASE: BigDecimal = price!("0.01");
     static ref GAP_ZERO: BigDecimal = BigDecimal::from(0);
 }
 
@@ -56,23 +122,25 @@ pub struct CStroke {
     pub orig: Option<Box<CStroke>>,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct StrokeAccumulator<T> {
     tts: T,
     state: Vec<CStroke>,
     pending: Vec<Parting>,
     cfg: StrokeConfig,
+    #[serde(skip)]
+    tracer: Tracer,
 }
 
 impl StrokeAccumulator<LocalTradingTimestamps> {
-    // only 1m, 5m, 30m, 1d are allowed
-    pub fn new(tick: &str, cfg: StrokeConfig) -> Result<Self> {
-        let tts = LocalTradingTimestamps::new(tick)?;
-        Ok(StrokeAccumulator {
-            tts,
+    pub fn new(tick: Tick, cfg: StrokeConfig) -> Self {
+        StrokeAccumulator {
+            tts: LocalTradingTimestamps::new(tick),
             state: Vec::new(),
             pending: Vec::new(),
             cfg,
-        })
+            tracer: Tracer::default(),
+        }
     }
 
     pub fn delta_agg(self) -> StrokeAggregator<LocalTradingTimestamps> {
@@ -84,15 +152,31 @@ impl StrokeAccumulator<LocalTradingTimestamps> {
 }
 
 impl<T: TradingTimestamps> StrokeAccumulator<T> {
+    /// 当前的内部状态
+    pub fn snapshot(&self) -> StrokeAccSnapshot {
+        StrokeAccSnapshot {
+            strokes: self.state.len(),
+            pending: self.pending.clone(),
+            last: self.state.last().cloned(),
+        }
+    }
+
     pub fn new_with_tts(tts: T, cfg: StrokeConfig) -> Result<StrokeAccumulator<T>> {
         Ok(StrokeAccumulator {
             tts,
             state: Vec::new(),
             pending: Vec::new(),
             cfg,
+            tracer: Tracer::default(),
         })
     }
 
+    /// 开启决策日志
+    pub fn traced(mut self) -> Self {
+        self.tracer = Tracer::enabled();
+        self
+    }
+
     fn accumulate_add(&mut self, item: &Parting) -> Result<StrokeDelta> {
         // 存在前一笔时，比较当前的分型是否与前一笔的终点分型类型一致
         // 如果一致，则比较高低，并根据情况修改笔或丢弃
@@ -112,30 +196,42 @@ impl<T: TradingTimestamps> StrokeAccumulator<T> {
                         orig: Some(Box::new(csk)),
                     };
                     self.state.push(new_sk);
+                    self.tracer
+                        .trace(item.extremum_ts, "同向分型创新高/新低，修改前笔终点");
                     return Ok(StrokeDelta::Update(
                         self.state.last().map(cstroke_to_stroke).unwrap(),
                     ));
                 }
                 // 顶比前顶低，或底比前底高，则忽略
+                self.tracer
+                    .trace(item.extremum_ts, "同向分型未创新高/新低，忽略");
                 return Ok(StrokeDelta::None);
             }
             // 异向顶底间满足顶比底高，且符合成笔条件（如存在独立K线）
             if (item.top && item.extremum_price > csk.sk.end_pt.extremum_price)
                 || (!item.top && item.extremum_price < csk.sk.end_pt.extremum_price)
             {
-                if self.stroke_completed(&csk.sk.end_pt, &item) {
+                if self.stroke_completed(&csk.sk.end_pt, item)
+                    && self.amplitude_satisfied(&csk.sk.end_pt, item)
+                {
                     // 成笔
                     let new_sk = Stroke {
                         start_pt: csk.sk.end_pt.clone(),
                         end_pt: item.clone(),
                     };
                     self.state.push(stroke_to_cstroke(&new_sk));
+                    self.tracer
+                        .trace(item.extremum_ts, "异向分型满足成笔条件，成笔");
                     return Ok(StrokeDelta::Add(new_sk));
                 }
                 // 在不成笔时，不考虑回溯，因为回溯将影响之前已经完成的两笔
+                self.tracer
+                    .trace(item.extremum_ts, "异向分型不满足成笔条件，忽略");
                 return Ok(StrokeDelta::None);
             }
             // 不满足任一成笔条件则丢弃
+            self.tracer
+                .trace(item.extremum_ts, "异向分型顶不高于底，忽略");
             return Ok(StrokeDelta::None);
         }
 
@@ -148,7 +244,7 @@ impl<T: TradingTimestamps> StrokeAccumulator<T> {
                     || (!item.top && item.extremum_price < p.extremum_price))
             {
                 // 成笔逻辑
-                if self.stroke_completed(&p, &item) {
+                if self.stroke_completed(p, item) && self.amplitude_satisfied(p, item) {
                     // 成笔
                     let new_sk = CStroke {
                         sk: Stroke {
@@ -164,6 +260,8 @@ impl<T: TradingTimestamps> StrokeAccumulator<T> {
         // 与未成笔序列无法成笔时，加入未成笔序列
         if matches.is_empty() {
             self.pending.push(item.clone());
+            self.tracer
+                .trace(item.extremum_ts, "无法与潜在起点成笔，加入潜在起点序列");
             return Ok(StrokeDelta::None);
         }
         // 在是否成笔的判断中，我们取差距更大的分型作为起点，
@@ -178,6 +276,8 @@ impl<T: TradingTimestamps> StrokeAccumulator<T> {
             }
         }
         self.state.push(r);
+        self.tracer
+            .trace(item.extremum_ts, "与潜在起点成笔，取差距最大的起点");
         // 不删除pending队列，仅第一笔使用
         // 收到分型更新时需要回溯该队列
         Ok(StrokeDelta::Add(
@@ -258,12 +358,28 @@ impl<T: TradingTimestamps> StrokeAccumulator<T> {
         unreachable!()
     }
 
+    // 笔幅度检查
+    // 幅度不足时不成笔，后续同向分型将延伸前一笔，即微小笔并入相邻笔
+    #[inline]
+    fn amplitude_satisfied(&self, p1: &Parting, p2: &Parting) -> bool {
+        let diff = (&p2.extremum_price - &p1.extremum_price).abs();
+        match self.cfg.min_amplitude {
+            None => true,
+            Some(StrokeAmplitude::Absolute(ref amplitude)) => diff >= *amplitude,
+            Some(StrokeAmplitude::Ratio(ref ratio)) => {
+                if p1.extremum_price == *GAP_ZERO {
+                    return diff / &*GAP_MINIMAL_BASE >= *ratio;
+                }
+                diff / &p1.extremum_price >= *ratio
+            }
+        }
+    }
+
     // 成笔逻辑检查
     // p1为前分型，p2为后分型
     // 兜底策略为独立K线
     #[inline]
     fn stroke_completed(&self, p1: &Parting, p2: &Parting) -> bool {
-        use tanglism_utils::{AFTERNOON_END, MORNING_END};
         if self.cfg.indep_k {
             // 必须存在独立K线
             if let Some(indep_ts) = self.tts.next_tick(p1.end_ts) {
@@ -280,8 +396,15 @@ impl<T: TradingTimestamps> StrokeAccumulator<T> {
             }
         }
         // 特殊成笔逻辑
-        match self.cfg.judge {
+        self.judge_matched(&self.cfg.judge, p1, p2)
+    }
+
+    // 特殊成笔条件检查，组合条件递归求值
+    fn judge_matched(&self, judge: &StrokeJudge, p1: &Parting, p2: &Parting) -> bool {
+        use tanglism_utils::{AFTERNOON_END, MORNING_END};
+        match judge {
             StrokeJudge::GapOpening(afternoon) => {
+                let afternoon = *afternoon;
                 if p1.right_gap.is_some() {
                     // 最高/低价恰好收盘
                     if p1.extremum_ts.time() == *AFTERNOON_END {
@@ -304,23 +427,27 @@ impl<T: TradingTimestamps> StrokeAccumulator<T> {
                         }
                     }
                 }
+                false
             }
-            StrokeJudge::GapRatio(ref ratio) => {
+            StrokeJudge::GapRatio(ratio) => {
                 if let Some(ref g1) = p1.right_gap {
-                    let ratio = ratio.clone();
                     let mut diff = &g1.end_price - &g1.start_price;
                     if diff < *GAP_ZERO {
                         diff = -diff;
                     }
                     if g1.start_price == *GAP_ZERO {
-                        return diff / &*GAP_MINIMAL_BASE >= ratio;
+                        return diff / &*GAP_MINIMAL_BASE >= *ratio;
                     }
-                    return diff / &g1.start_price >= ratio;
+                    return diff / &g1.start_price >= *ratio;
                 }
+                false
             }
-            StrokeJudge::None => (),
+            StrokeJudge::Any(judges) => judges.iter().any(|j| self.judge_matched(j, p1, p2)),
+            StrokeJudge::All(judges) => {
+                !judges.is_empty() && judges.iter().all(|j| self.judge_matched(j, p1, p2))
+            }
+            StrokeJudge::None => false,
         }
-        false
     }
 }
 
@@ -378,11 +505,11 @@ pub struct StrokeAggregator<T> {
 }
 
 impl StrokeAggregator<LocalTradingTimestamps> {
-    pub fn new(tick: &str, cfg: StrokeConfig) -> Result<Self> {
-        Ok(StrokeAggregator {
-            acc: StrokeAccumulator::new(tick, cfg)?,
+    pub fn new(tick: Tick, cfg: StrokeConfig) -> Self {
+        StrokeAggregator {
+            acc: StrokeAccumulator::new(tick, cfg),
             ds: Vec::new(),
-        })
+        }
     }
 }
 
@@ -413,16 +540,16 @@ pub fn stroke_to_cstroke(sk: &Stroke) -> CStroke {
 mod tests {
     use super::*;
     use crate::shape::*;
-    use bigdecimal::BigDecimal;
     use chrono::NaiveDateTime;
     use tanglism_utils::TradingTimestamps;
+    use tanglism_utils::{parse_price, price};
 
     #[test]
     fn test_stroke_none() -> Result<()> {
         let sks = pts_to_sks_1_min(vec![
-            new_pt1("2020-01-07 10:00", 10.00, false),
-            new_pt1("2020-01-07 10:01", 10.10, true),
-            new_pt1("2020-01-07 10:03", 9.50, false),
+            new_pt1("2020-01-07 10:00", "10.00", false),
+            new_pt1("2020-01-07 10:01", "10.10", true),
+            new_pt1("2020-01-07 10:03", "9.50", false),
         ]);
         assert!(sks.is_empty());
         Ok(())
@@ -431,9 +558,9 @@ mod tests {
     #[test]
     fn test_stroke_one_simple() -> Result<()> {
         let sks = pts_to_sks_1_min(vec![
-            new_pt1("2020-01-07 10:00", 10.00, false),
-            new_pt1("2020-01-07 10:10", 10.40, true),
-            new_pt1("2020-01-07 10:12", 10.30, false),
+            new_pt1("2020-01-07 10:00", "10.00", false),
+            new_pt1("2020-01-07 10:10", "10.40", true),
+            new_pt1("2020-01-07 10:12", "10.30", false),
         ]);
         assert_eq!(1, sks.len());
         Ok(())
@@ -442,28 +569,22 @@ mod tests {
     #[test]
     fn test_stroke_one_delta_update() -> Result<()> {
         let sds = pds_to_sds_1_min(vec![
-            PartingDelta::Add(new_pt1("2020-01-07 10:00", 10.00, false)),
-            PartingDelta::Add(new_pt1("2020-01-07 10:10", 11.00, true)),
-            PartingDelta::Update(new_pt1("2020-01-07 10:10", 10.50, true)),
+            PartingDelta::Add(new_pt1("2020-01-07 10:00", "10.00", false)),
+            PartingDelta::Add(new_pt1("2020-01-07 10:10", "11.00", true)),
+            PartingDelta::Update(new_pt1("2020-01-07 10:10", "10.50", true)),
         ]);
         assert_eq!(2, sds.len());
-        assert_eq!(
-            BigDecimal::from(11.0),
-            sds[0].add().unwrap().end_pt.extremum_price
-        );
-        assert_eq!(
-            BigDecimal::from(10.5),
-            sds[1].update().unwrap().end_pt.extremum_price
-        );
+        assert_eq!(price!(11.0), sds[0].add().unwrap().end_pt.extremum_price);
+        assert_eq!(price!(10.5), sds[1].update().unwrap().end_pt.extremum_price);
         Ok(())
     }
 
     #[test]
     fn test_stroke_one_delta_delete() -> Result<()> {
         let sds = pds_to_sds_1_min(vec![
-            PartingDelta::Add(new_pt1("2020-01-07 10:00", 10.00, false)),
-            PartingDelta::Add(new_pt1("2020-01-07 10:10", 11.00, true)),
-            PartingDelta::Delete(new_pt1("2020-01-07 10:10", 11.00, true)),
+            PartingDelta::Add(new_pt1("2020-01-07 10:00", "10.00", false)),
+            PartingDelta::Add(new_pt1("2020-01-07 10:10", "11.00", true)),
+            PartingDelta::Delete(new_pt1("2020-01-07 10:10", "11.00", true)),
         ]);
         assert_eq!(2, sds.len());
         sds[0].add().unwrap();
@@ -474,15 +595,15 @@ mod tests {
     #[test]
     fn test_stroke_one_delta_backtrack() -> Result<()> {
         let sds = pds_to_sds_1_min(vec![
-            PartingDelta::Add(new_pt1("2020-01-07 10:00", 10.00, false)),
-            PartingDelta::Add(new_pt1("2020-01-07 10:10", 11.00, true)),
-            PartingDelta::Add(new_pt1("2020-01-07 10:20", 12.00, true)),
-            PartingDelta::Delete(new_pt1("2020-01-07 10:20", 12.00, true)),
+            PartingDelta::Add(new_pt1("2020-01-07 10:00", "10.00", false)),
+            PartingDelta::Add(new_pt1("2020-01-07 10:10", "11.00", true)),
+            PartingDelta::Add(new_pt1("2020-01-07 10:20", "12.00", true)),
+            PartingDelta::Delete(new_pt1("2020-01-07 10:20", "12.00", true)),
         ]);
         assert!(sds[0].add().is_some());
         assert!(sds[1].update().is_some());
         let update = sds[2].update().unwrap();
-        assert_eq!(BigDecimal::from(11.0), update.end_pt.extremum_price);
+        assert_eq!(price!(11.0), update.end_pt.extremum_price);
         Ok(())
     }
 
@@ -490,10 +611,10 @@ mod tests {
     #[test]
     fn test_stroke_one_moving_start() -> Result<()> {
         let sks = pts_to_sks_1_min(vec![
-            new_pt1("2020-01-07 10:00", 10.00, false),
-            new_pt1("2020-01-07 10:02", 10.10, true),
-            new_pt1("2020-01-07 10:04", 9.90, false),
-            new_pt1("2020-01-07 10:10", 10.30, true),
+            new_pt1("2020-01-07 10:00", "10.00", false),
+            new_pt1("2020-01-07 10:02", "10.10", true),
+            new_pt1("2020-01-07 10:04", "9.90", false),
+            new_pt1("2020-01-07 10:10", "10.30", true),
         ]);
         assert_eq!(1, sks.len());
         assert_eq!(new_ts("2020-01-07 10:04"), sks[0].start_pt.extremum_ts);
@@ -505,10 +626,10 @@ mod tests {
     #[test]
     fn test_stroke_one_non_moving_start() -> Result<()> {
         let sks = pts_to_sks_1_min(vec![
-            new_pt1("2020-01-07 10:00", 10.00, false),
-            new_pt1("2020-01-07 10:02", 10.10, true),
-            new_pt1("2020-01-07 10:04", 10.02, false),
-            new_pt1("2020-01-07 10:10", 10.30, true),
+            new_pt1("2020-01-07 10:00", "10.00", false),
+            new_pt1("2020-01-07 10:02", "10.10", true),
+            new_pt1("2020-01-07 10:04", "10.02", false),
+            new_pt1("2020-01-07 10:10", "10.30", true),
         ]);
         assert_eq!(1, sks.len());
         assert_eq!(new_ts("2020-01-07 10:00"), sks[0].start_pt.extremum_ts);
@@ -520,9 +641,9 @@ mod tests {
     #[test]
     fn test_stroke_two_simple() -> Result<()> {
         let sks = pts_to_sks_1_min(vec![
-            new_pt1("2020-01-07 10:00", 10.00, false),
-            new_pt1("2020-01-07 10:10", 10.10, true),
-            new_pt1("2020-01-07 10:20", 10.02, false),
+            new_pt1("2020-01-07 10:00", "10.00", false),
+            new_pt1("2020-01-07 10:10", "10.10", true),
+            new_pt1("2020-01-07 10:20", "10.02", false),
         ]);
         assert_eq!(2, sks.len());
         Ok(())
@@ -532,8 +653,8 @@ mod tests {
     #[test]
     fn test_stroke_one_across_days() -> Result<()> {
         let sks = pts_to_sks_30_min(vec![
-            new_pt30("2020-01-07 10:00", 10.00, true),
-            new_pt30("2020-01-08 10:00", 9.50, false),
+            new_pt30("2020-01-07 10:00", "10.00", true),
+            new_pt30("2020-01-08 10:00", "9.50", false),
         ]);
         assert_eq!(1, sks.len());
         assert_eq!(new_ts("2020-01-07 10:00"), sks[0].start_pt.extremum_ts);
@@ -549,49 +670,49 @@ mod tests {
         let sks = pts_to_sks_30_min(vec![
             ts_pt30(
                 "2020-03-11 13:30",
-                1169.50,
+                "1169.50",
                 true,
                 "2020-03-11 11:00",
                 "2020-03-11 14:00",
             ),
             ts_pt30(
                 "2020-03-11 14:00",
-                1156.70,
+                "1156.70",
                 false,
                 "2020-03-11 11:30",
                 "2020-03-11 14:30",
             ),
             ts_pt30(
                 "2020-03-11 15:00",
-                1167.40,
+                "1167.40",
                 true,
                 "2020-03-11 14:30",
                 "2020-03-12 10:00",
             ),
             ts_pt30(
                 "2020-03-12 10:30",
-                1125.10,
+                "1125.10",
                 false,
                 "2020-03-12 10:00",
                 "2020-03-12 14:00",
             ),
             ts_pt30(
                 "2020-03-12 11:00",
-                1147.98,
+                "1147.98",
                 true,
                 "2020-03-12 10:30",
                 "2020-03-12 15:00",
             ),
             ts_pt30(
                 "2020-03-13 10:00",
-                1080.00,
+                "1080.00",
                 false,
                 "2020-03-12 14:30",
                 "2020-03-13 14:00",
             ),
             ts_pt30(
                 "2020-03-13 13:30",
-                1128.92,
+                "1128.92",
                 true,
                 "2020-03-13 10:00",
                 "2020-03-13 15:00",
@@ -610,98 +731,98 @@ mod tests {
         let pts = vec![
             ts_pt30(
                 "2020-02-10 11:00",
-                1074.56,
+                "1074.56",
                 true,
                 "2020-02-10 10:30",
                 "2020-02-10 11:30",
             ),
             ts_pt30(
                 "2020-02-10 13:30",
-                1061.80,
+                "1061.80",
                 false,
                 "2020-02-10 11:30",
                 "2020-02-10 14:00",
             ),
             ts_pt30(
                 "2020-02-10 14:00",
-                1067.00,
+                "1067.00",
                 true,
                 "2020-02-10 13:30",
                 "2020-02-10 15:00",
             ),
             ts_pt30(
                 "2020-02-10 15:00",
-                1062.01,
+                "1062.01",
                 false,
                 "2020-02-10 14:00",
                 "2020-02-11 10:00",
             ),
             ts_pt30(
                 "2020-02-11 14:00",
-                1099.66,
+                "1099.66",
                 true,
                 "2020-02-11 11:00",
                 "2020-02-12 10:00",
             ),
             ts_pt30(
                 "2020-02-12 10:30",
-                1085.88,
+                "1085.88",
                 false,
                 "2020-02-12 10:00",
                 "2020-02-12 11:00",
             ),
             ts_pt30(
                 "2020-02-12 11:30",
-                1098.79,
+                "1098.79",
                 true,
                 "2020-02-12 11:00",
                 "2020-02-12 14:00",
             ),
             ts_pt30(
                 "2020-02-12 13:30",
-                1090.30,
+                "1090.30",
                 false,
                 "2020-02-12 11:30",
                 "2020-02-12 14:30",
             ),
             ts_pt30(
                 "2020-02-13 10:00",
-                1113.83,
+                "1113.83",
                 true,
                 "2020-02-12 15:00",
                 "2020-02-13 11:00",
             ),
             ts_pt30(
                 "2020-02-13 13:30",
-                1088.21,
+                "1088.21",
                 false,
                 "2020-02-13 11:30",
                 "2020-02-13 15:00",
             ),
             ts_pt30(
                 "2020-02-13 14:30",
-                1093.64,
+                "1093.64",
                 true,
                 "2020-02-13 13:30",
                 "2020-02-14 11:00",
             ),
             ts_pt30(
                 "2020-02-14 10:00",
-                1086.01,
+                "1086.01",
                 false,
                 "2020-02-13 14:30",
                 "2020-02-14 11:30",
             ),
             ts_pt30(
                 "2020-02-14 11:30",
-                1092.00,
+                "1092.00",
                 true,
                 "2020-02-14 10:00",
                 "2020-02-14 13:30",
             ),
             ts_pt30(
                 "2020-02-14 14:30",
-                1083.11,
+                "1083.11",
                 false,
                 "2020-02-14 13:30",
                 "2020-02-14 15:00",
@@ -710,10 +831,11 @@ mod tests {
         // 不回溯
         let sks1 = pts_to_sks(
             &pts,
-            "30m",
+            Tick::M30,
             StrokeConfig {
                 indep_k: true,
                 judge: StrokeJudge::None,
+                min_amplitude: None,
             },
         )?;
         assert_eq!(3, sks1.len());
@@ -724,89 +846,139 @@ mod tests {
         Ok(())
     }
 
+    // 微小笔并入相邻笔
+    #[test]
+    fn test_stroke_min_amplitude() -> Result<()> {
+        let pts = vec![
+            new_pt1("2020-01-07 10:00", "10.00", false),
+            new_pt1("2020-01-07 10:10", "10.40", true),
+            new_pt1("2020-01-07 10:20", "10.35", false),
+            new_pt1("2020-01-07 10:30", "10.60", true),
+        ];
+        assert_eq!(3, pts_to_sks_1_min(pts.clone()).len());
+        for amplitude in &[
+            StrokeAmplitude::Absolute(price!(0.10)),
+            StrokeAmplitude::Ratio(price!(0.01)),
+        ] {
+            let cfg = StrokeConfig {
+                min_amplitude: Some(amplitude.clone()),
+                ..StrokeConfig::default()
+            };
+            let sks = pts_to_sks(&pts, Tick::M1, cfg)?;
+            assert_eq!(1, sks.len());
+            assert_eq!(new_ts("2020-01-07 10:00"), sks[0].start_pt.extremum_ts);
+            assert_eq!(new_ts("2020-01-07 10:30"), sks[0].end_pt.extremum_ts);
+        }
+        Ok(())
+    }
+
     // 测试不同的成笔逻辑选项
     #[test]
     fn test_stroke_one_gap() -> Result<()> {
-        let mut pt1 = new_pt30("2020-02-13 15:00", 10.00, false);
+        let mut pt1 = new_pt30("2020-02-13 15:00", "10.00", false);
         pt1.right_gap = Some(Box::new(Gap {
             ts: new_ts("2020-02-14 10:00"),
-            start_price: BigDecimal::from(10.00),
-            end_price: BigDecimal::from(10.50),
+            start_price: price!(10.00),
+            end_price: price!(10.50),
         }));
-        let mut pt2 = new_pt30("2020-02-14 10:00", 10.50, true);
+        let mut pt2 = new_pt30("2020-02-14 10:00", "10.50", true);
         pt2.left_gap = Some(Box::new(Gap {
             ts: new_ts("2020-02-13 15:00"),
-            start_price: BigDecimal::from(10.00),
-            end_price: BigDecimal::from(10.50),
+            start_price: price!(10.00),
+            end_price: price!(10.50),
         }));
         let pts = vec![pt1, pt2];
         let sks1 = StrokeAccumulator::new(
-            "30m",
+            Tick::M30,
             StrokeConfig {
                 indep_k: true,
                 judge: StrokeJudge::None,
+                min_amplitude: None,
             },
-        )?
+        )
         .aggregate(&pts)
         .unwrap();
         assert_eq!(0, sks1.len());
         let sks2 = StrokeAccumulator::new(
-            "30m",
+            Tick::M30,
             StrokeConfig {
                 indep_k: false,
                 judge: StrokeJudge::None,
+                min_amplitude: None,
             },
-        )?
+        )
         .aggregate(&pts)
         .unwrap();
         assert_eq!(0, sks2.len());
         let sks3 = StrokeAccumulator::new(
-            "30m",
+            Tick::M30,
             StrokeConfig {
                 indep_k: true,
                 judge: StrokeJudge::GapOpening(false),
+                min_amplitude: None,
             },
-        )?
+        )
         .aggregate(&pts)
         .unwrap();
         assert_eq!(1, sks3.len());
         let sks4 = StrokeAccumulator::new(
-            "30m",
+            Tick::M30,
             StrokeConfig {
                 indep_k: true,
-                judge: StrokeJudge::GapRatio(BigDecimal::from(0.01)),
+                judge: StrokeJudge::GapRatio(price!(0.01)),
+                min_amplitude: None,
             },
-        )?
+        )
         .aggregate(&pts)
         .unwrap();
         assert_eq!(1, sks4.len());
         let sks5 = StrokeAccumulator::new(
-            "30m",
+            Tick::M30,
             StrokeConfig {
                 indep_k: true,
-                judge: StrokeJudge::GapRatio(BigDecimal::from(0.08)),
+                judge: StrokeJudge::GapRatio(price!(0.08)),
+                min_amplitude: None,
             },
-        )?
+        )
         .aggregate(&pts)
         .unwrap();
         assert_eq!(0, sks5.len());
+        // 组合条件
+        let any = StrokeJudge::Any(vec![
+            StrokeJudge::GapRatio(price!(0.08)),
+            StrokeJudge::GapOpening(false),
+        ]);
+        let all = StrokeJudge::All(vec![
+            StrokeJudge::GapRatio(price!(0.08)),
+            StrokeJudge::GapOpening(false),
+        ]);
+        for (judge, n) in [(any, 1), (all, 0), (StrokeJudge::All(vec![]), 0)] {
+            let cfg = StrokeConfig {
+                indep_k: true,
+                judge,
+                min_amplitude: None,
+            };
+            let sks = StrokeAccumulator::new(Tick::M30, cfg)
+                .aggregate(&pts)
+                .unwrap();
+            assert_eq!(n, sks.len());
+        }
         Ok(())
     }
 
     fn pts_to_sks_1_min(pts: Vec<Parting>) -> Vec<Stroke> {
-        pts_to_sks(&pts, "1m", StrokeConfig::default()).unwrap()
+        pts_to_sks(&pts, Tick::M1, StrokeConfig::default()).unwrap()
     }
 
     fn pds_to_sds_1_min(pds: Vec<PartingDelta>) -> Vec<StrokeDelta> {
-        StrokeAccumulator::new("1m", StrokeConfig::default())
-            .unwrap()
+        StrokeAccumulator::new(Tick::M1, StrokeConfig::default())
             .delta_agg()
             .aggregate(&pds)
             .unwrap()
     }
 
-    fn new_pt1(ts: &str, price: f64, top: bool) -> Parting {
-        let ts1m = LocalTradingTimestamps::new("1m").unwrap();
+    fn new_pt1(ts: &str, price: &str, top: bool) -> Parting {
+        let ts1m = LocalTradingTimestamps::new(Tick::M1);
         let extremum_ts = new_ts(ts);
         let start_ts = ts1m.prev_tick(extremum_ts).unwrap();
         let end_ts = ts1m.next_tick(extremum_ts).unwrap();
@@ -814,7 +986,7 @@ mod tests {
             start_ts,
             extremum_ts,
             end_ts,
-            extremum_price: BigDecimal::from(price),
+            extremum_price: parse_price(price).unwrap(),
             n: 3,
             top,
             left_gap: None,
@@ -823,11 +995,11 @@ mod tests {
     }
 
     fn pts_to_sks_30_min(pts: Vec<Parting>) -> Vec<Stroke> {
-        pts_to_sks(&pts, "30m", StrokeConfig::default()).unwrap()
+        pts_to_sks(&pts, Tick::M30, StrokeConfig::default()).unwrap()
     }
 
-    fn new_pt30(ts: &str, price: f64, top: bool) -> Parting {
-        let ts30m = LocalTradingTimestamps::new("30m").unwrap();
+    fn new_pt30(ts: &str, price: &str, top: bool) -> Parting {
+        let ts30m = LocalTradingTimestamps::new(Tick::M30);
         let extremum_ts = new_ts(ts);
         let start_ts = ts30m.prev_tick(extremum_ts).unwrap();
         let end_ts = ts30m.next_tick(extremum_ts).unwrap();
@@ -835,7 +1007,7 @@ mod tests {
             start_ts,
             extremum_ts,
             end_ts,
-            extremum_price: BigDecimal::from(price),
+            extremum_price: parse_price(price).unwrap(),
             n: 3,
             top,
             left_gap: None,
@@ -843,13 +1015,13 @@ mod tests {
         }
     }
 
-    fn ts_pt30(ts: &str, price: f64, top: bool, start_ts: &str, end_ts: &str) -> Parting {
+    fn ts_pt30(ts: &str, price: &str, top: bool, start_ts: &str, end_ts: &str) -> Parting {
         let start_ts = new_ts(start_ts);
         let end_ts = new_ts(end_ts);
         let extremum_ts = new_ts(ts);
         let mut start = start_ts;
         let mut n = 1;
-        while let Some(next_ts) = LocalTradingTimestamps::new("30m").unwrap().next_tick(start) {
+        while let Some(next_ts) = LocalTradingTimestamps::new(Tick::M30).next_tick(start) {
             n += 1;
             if next_ts == end_ts {
                 break;
@@ -864,7 +1036,7 @@ mod tests {
             start_ts,
             extremum_ts,
             end_ts,
-            extremum_price: BigDecimal::from(price),
+            extremum_price: parse_price(price).unwrap(),
             n,
             top,
             left_gap: None,
@@ -1,6 +1,11 @@
+use crate::segment::SegmentDelta;
 use crate::shape::{Segment, Stroke, SubTrend, SubTrendType, ValuePoint};
+use crate::stream::{apply_delta, tail_deltas, Accumulator, Delta};
+use crate::stroke::StrokeDelta;
 use crate::{Error, Result};
 use chrono::NaiveDateTime;
+use serde_derive::*;
+use tanglism_utils::Tick;
 
 /// 将线段与笔对齐为某个周期下的次级别走势
 /// 线段直接视为次级别走势
@@ -9,69 +14,195 @@ use chrono::NaiveDateTime;
 ///          如果不存在缺口，因为该笔前后必为段，则检查前后两段是否可合并为同向的段
 ///          如不可以，该笔独立成段，并标记分段。
 /// 连续至少2笔：只可能存在两种可能，与前一段合并为同向段，与后一段合并为同向段。
-pub fn unify_subtrends(sgs: &[Segment], sks: &[Stroke], tick: &str) -> Result<Vec<SubTrend>> {
+pub fn unify_subtrends(sgs: &[Segment], sks: &[Stroke], tick: Tick) -> Result<Vec<SubTrend>> {
     let mut subtrends = Vec::new();
-    let mut strokes = Vec::new();
-    let mut sgi = 0;
     let mut ski = 0;
-    while sgi < sgs.len() {
-        let sg = &sgs[sgi];
-        // 将线段前的笔加入次级别走势
-        strokes.clear();
-        while ski < sks.len() && sks[ski].start_pt.extremum_ts < sg.start_pt.extremum_ts {
-            let sk = &sks[ski];
-            strokes.push(sk.clone());
-            if accumulate_strokes(&mut subtrends, &strokes, tick)? {
-                strokes.clear();
-            }
-            ski += 1;
+    for sg in sgs {
+        ski = unify_segment(&mut subtrends, sg, sks, ski, tick)?;
+    }
+    // todo
+    Ok(subtrends)
+}
+
+// 将线段及其前的笔加入次级别走势，返回线段后首笔的下标
+fn unify_segment(
+    subtrends: &mut Vec<SubTrend>,
+    sg: &Segment,
+    sks: &[Stroke],
+    mut ski: usize,
+    tick: Tick,
+) -> Result<usize> {
+    let mut strokes = Vec::new();
+    // 将线段前的笔加入次级别走势
+    // 线段带有笔下标区间时直接按下标划分
+    while ski < sks.len()
+        && match sg.stroke_range {
+            Some((start, _)) => ski < start,
+            None => sks[ski].start_pt.extremum_ts < sg.start_pt.extremum_ts,
         }
-        if strokes.is_empty() {
-            // 将线段加入次级别走势
-            subtrends.push(segment_as_subtrend(sg, tick)?);
-        } else if strokes.len() == 1 {
-            let sk = strokes.pop().unwrap();
-            subtrends.push(stroke_as_subtrend(&sk, tick, SubTrendType::Divider)?);
-            subtrends.push(segment_as_subtrend(sg, tick)?);
+    {
+        let sk = &sks[ski];
+        strokes.push(sk.clone());
+        if accumulate_strokes(subtrends, &strokes, tick)? {
+            strokes.clear();
+        }
+        ski += 1;
+    }
+    if strokes.is_empty() {
+        // 将线段加入次级别走势
+        subtrends.push(segment_as_subtrend(sg, tick)?);
+    } else if strokes.len() == 1 {
+        let sk = strokes.pop().unwrap();
+        subtrends.push(stroke_as_subtrend(&sk, tick, SubTrendType::Divider)?);
+        subtrends.push(segment_as_subtrend(sg, tick)?);
+    } else {
+        // 大于1笔，判断其与后段是否同向
+        let sk = strokes.first().unwrap();
+        let upward = sk.end_pt.extremum_price > sk.start_pt.extremum_price
+            && sg.end_pt.extremum_price > sg.start_pt.extremum_price
+            && sg.end_pt.extremum_price > sk.start_pt.extremum_price;
+        let downward = sk.end_pt.extremum_price < sk.start_pt.extremum_price
+            && sg.end_pt.extremum_price < sg.start_pt.extremum_price
+            && sg.end_pt.extremum_price < sk.start_pt.extremum_price;
+        if upward || downward {
+            // 合并插入
+            subtrends.push(SubTrend {
+                start: ValuePoint {
+                    ts: align_tick(tick, sk.start_pt.extremum_ts)?,
+                    value: sk.start_pt.extremum_price.clone(),
+                },
+                end: ValuePoint {
+                    ts: align_tick(tick, sg.end_pt.extremum_ts)?,
+                    value: sg.end_pt.extremum_price.clone(),
+                },
+                level: 1,
+                typ: SubTrendType::Combination,
+            });
         } else {
-            // 大于1笔，判断其与后段是否同向
-            let sk = strokes.first().unwrap();
-            let upward = sk.end_pt.extremum_price > sk.start_pt.extremum_price
-                && sg.end_pt.extremum_price > sg.start_pt.extremum_price
-                && sg.end_pt.extremum_price > sk.start_pt.extremum_price;
-            let downward = sk.end_pt.extremum_price < sk.start_pt.extremum_price
-                && sg.end_pt.extremum_price < sg.start_pt.extremum_price
-                && sg.end_pt.extremum_price < sk.start_pt.extremum_price;
-            if upward || downward {
-                // 合并插入
-                subtrends.push(SubTrend {
-                    start: ValuePoint {
-                        ts: align_tick(tick, sk.start_pt.extremum_ts)?,
-                        value: sk.start_pt.extremum_price.clone(),
-                    },
-                    end: ValuePoint {
-                        ts: align_tick(tick, sg.end_pt.extremum_ts)?,
-                        value: sg.end_pt.extremum_price.clone(),
-                    },
-                    level: 1,
-                    typ: SubTrendType::Combination,
-                });
-            } else {
-                // 忽略笔
-                subtrends.push(segment_as_subtrend(sg, tick)?);
+            // 忽略笔
+            subtrends.push(segment_as_subtrend(sg, tick)?);
+        }
+    }
+    // 跳过所有被线段覆盖的笔
+    if let Some((_, end)) = sg.stroke_range {
+        ski = ski.max(end + 1);
+    }
+    while ski < sks.len() && sks[ski].start_pt.extremum_ts < sg.end_pt.extremum_ts {
+        ski += 1;
+    }
+    Ok(ski)
+}
+
+pub type SubTrendDelta = Delta<SubTrend>;
+
+/// 次级别走势累加器
+///
+/// 接收线段及笔的变更，输出次级别走势的有序变更，与unify_subtrends的结果一致。
+/// 变更仅作用于末尾元素，自变化的线段起重新对齐，之前的次级别走势保持不变
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SubTrendAccumulator {
+    tick: Tick,
+    sgs: Vec<Segment>,
+    sks: Vec<Stroke>,
+    state: Vec<SubTrend>,
+    // 对齐各线段前的状态
+    marks: Vec<SubTrendMark>,
+    // 最后一个线段后首笔的下标
+    ski: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SubTrendMark {
+    len: usize,
+    // 后续的笔可能修改最后一个次级别走势
+    last: Option<SubTrend>,
+    ski: usize,
+}
+
+impl SubTrendAccumulator {
+    pub fn new(tick: Tick) -> Self {
+        SubTrendAccumulator {
+            tick,
+            sgs: Vec::new(),
+            sks: Vec::new(),
+            state: Vec::new(),
+            marks: Vec::new(),
+            ski: 0,
+        }
+    }
+
+    // 回退至第from个线段对齐前，重新对齐其后的线段
+    fn realign(&mut self, from: usize) -> Result<Vec<SubTrendDelta>> {
+        let from = from.min(self.marks.len());
+        let (len, ski) = match self.marks.get(from) {
+            Some(mark) => (mark.len, mark.ski),
+            None => (self.state.len(), self.ski),
+        };
+        let diff_from = len.saturating_sub(1);
+        let old = self.state[diff_from..].to_vec();
+        if let Some(mark) = self.marks.get(from) {
+            self.state.truncate(mark.len);
+            if let (Some(last), Some(last_st)) = (mark.last.clone(), self.state.last_mut()) {
+                *last_st = last;
             }
         }
-        sgi += 1;
-        // 跳过所有被线段覆盖的笔
-        while ski < sks.len() && sks[ski].start_pt.extremum_ts < sg.end_pt.extremum_ts {
-            ski += 1;
+        self.marks.truncate(from);
+        let mut ski = ski;
+        for sg in &self.sgs[from..] {
+            self.marks.push(SubTrendMark {
+                len: self.state.len(),
+                last: self.state.last().cloned(),
+                ski,
+            });
+            ski = unify_segment(&mut self.state, sg, &self.sks, ski, self.tick)?;
         }
+        self.ski = ski;
+        Ok(tail_deltas(&old, &self.state[diff_from..]))
     }
-    // todo
-    Ok(subtrends)
 }
 
-fn segment_as_subtrend(sg: &Segment, tick: &str) -> Result<SubTrend> {
+impl Accumulator<SegmentDelta> for SubTrendAccumulator {
+    // 一次输入可能产生多个变更，按序作用于末尾元素
+    type Delta = Vec<SubTrendDelta>;
+    type State = Vec<SubTrend>;
+
+    fn accumulate(&mut self, item: &SegmentDelta) -> Result<Vec<SubTrendDelta>> {
+        if item.none() {
+            return Ok(Vec::new());
+        }
+        apply_delta(&mut self.sgs, item.clone())?;
+        // 删除时自被删除的线段回退，否则自最后一个线段重新对齐
+        let from = match item {
+            Delta::Delete(_) => self.sgs.len(),
+            _ => self.sgs.len() - 1,
+        };
+        self.realign(from)
+    }
+
+    fn state(&self) -> &Self::State {
+        &self.state
+    }
+}
+
+impl Accumulator<StrokeDelta> for SubTrendAccumulator {
+    type Delta = Vec<SubTrendDelta>;
+    type State = Vec<SubTrend>;
+
+    // 笔的变更仅影响最后一个线段及其前的笔
+    fn accumulate(&mut self, item: &StrokeDelta) -> Result<Vec<SubTrendDelta>> {
+        if item.none() {
+            return Ok(Vec::new());
+        }
+        apply_delta(&mut self.sks, item.clone())?;
+        self.realign(self.sgs.len().saturating_sub(1))
+    }
+
+    fn state(&self) -> &Self::State {
+        &self.state
+    }
+}
+
+fn segment_as_subtrend(sg: &Segment, tick: Tick) -> Result<SubTrend> {
     Ok(SubTrend {
         start: ValuePoint {
             ts: align_tick(tick, sg.start_pt.extremum_ts)?,
@@ -86,7 +217,7 @@ fn segment_as_subtrend(sg: &Segment, tick: &str) -> Result<SubTrend> {
     })
 }
 
-fn stroke_as_subtrend(sk: &Stroke, tick: &str, typ: SubTrendType) -> Result<SubTrend> {
+fn stroke_as_subtrend(sk: &Stroke, tick: Tick, typ: SubTrendType) -> Result<SubTrend> {
     Ok(SubTrend {
         start: ValuePoint {
             ts: align_tick(tick, sk.start_pt.extremum_ts)?,
@@ -105,7 +236,7 @@ fn stroke_as_subtrend(sk: &Stroke, tick: &str, typ: SubTrendType) -> Result<SubT
 fn accumulate_strokes(
     subtrends: &mut Vec<SubTrend>,
     strokes: &[Stroke],
-    tick: &str,
+    tick: Tick,
 ) -> Result<bool> {
     if strokes.is_empty() {
         return Ok(false);
@@ -139,9 +270,70 @@ fn accumulate_strokes(
 }
 
 #[inline]
-pub(crate) fn align_tick(tick: &str, ts: NaiveDateTime) -> Result<NaiveDateTime> {
+pub(crate) fn align_tick(tick: Tick, ts: NaiveDateTime) -> Result<NaiveDateTime> {
     use tanglism_utils::{LocalTradingTimestamps, TradingTimestamps};
-    LocalTradingTimestamps::new(tick)?
+    LocalTradingTimestamps::new(tick)
         .aligned_tick(ts)
-        .ok_or_else(|| Error(format!("invalid timestamp: {}", ts)))
+        .ok_or_else(|| Error::Msg(format!("invalid timestamp: {}", ts)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shape::new_pt;
+    use tanglism_utils::{parse_price, price};
+
+    // 增量对齐及末尾的更新、删除与批量计算一致
+    #[test]
+    fn test_subtrend_accumulator() -> Result<()> {
+        let mut sgs = vec![
+            ("2020-02-10 15:00", "10.0"),
+            ("2020-02-12 15:00", "11.0"),
+            ("2020-02-14 15:00", "10.5"),
+            ("2020-02-18 15:00", "11.5"),
+            ("2020-02-20 15:00", "10.8"),
+        ]
+        .build();
+        let tick = Tick::D1;
+        let mut acc = SubTrendAccumulator::new(tick);
+        let mut replica = Vec::new();
+        for i in 0..sgs.len() {
+            for d in acc.accumulate(&SegmentDelta::Add(sgs[i].clone()))? {
+                apply_delta(&mut replica, d)?;
+            }
+            assert_eq!(&unify_subtrends(&sgs[..=i], &[], tick)?, &acc.state);
+        }
+        let last = sgs.last_mut().unwrap();
+        last.end_pt = new_pt("2020-02-21 15:00", price!(10.2), false);
+        for d in acc.accumulate(&SegmentDelta::Update(last.clone()))? {
+            apply_delta(&mut replica, d)?;
+        }
+        assert_eq!(&unify_subtrends(&sgs, &[], tick)?, &acc.state);
+        let sg = sgs.pop().unwrap();
+        for d in acc.accumulate(&SegmentDelta::Delete(sg))? {
+            apply_delta(&mut replica, d)?;
+        }
+        assert_eq!(&unify_subtrends(&sgs, &[], tick)?, &acc.state);
+        assert_eq!(&acc.state, &replica);
+        assert_eq!(3, replica.len());
+        Ok(())
+    }
+
+    trait BuildSegmentVec {
+        fn build(self) -> Vec<Segment>;
+    }
+
+    impl BuildSegmentVec for Vec<(&str, &str)> {
+        fn build(self) -> Vec<Segment> {
+            self.iter()
+                .zip(self.iter().skip(1))
+                .map(|(start, end)| Segment {
+                    start_pt: new_pt(start.0, parse_price(start.1).unwrap(), false),
+                    end_pt: new_pt(end.0, parse_price(end.1).unwrap(), false),
+                    gap: None,
+                    stroke_range: None,
+                })
+                .collect()
+        }
+    }
 }
@@ -12,12 +12,15 @@
 //! 目前的实现是直接使用次级别段作为次级别走势，而次级别笔作为次级别以下走势。
 
 use crate::align_tick;
+use crate::center::CenterConfig;
 use crate::shape::{Center, CenterElement, SubTrend, SubTrendType, Trend, ValuePoint};
 use crate::Result;
+use tanglism_utils::Tick;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Hash)]
 pub struct TrendConfig {
     pub level: i32,
+    pub center: CenterConfig,
 }
 
 pub fn unify_trends(centers: &[CenterElement]) -> Vec<Trend> {
@@ -277,7 +280,7 @@ struct TemporaryCompleted {
     level: i32,
 }
 
-pub fn trend_as_subtrend(trend: &Trend, tick: &str) -> Result<SubTrend> {
+pub fn trend_as_subtrend(trend: &Trend, tick: Tick) -> Result<SubTrend> {
     Ok(SubTrend {
         start: ValuePoint {
             ts: align_tick(tick, trend.start.ts)?,
@@ -288,6 +291,6 @@ pub fn trend_as_subtrend(trend: &Trend, tick: &str) -> Result<SubTrend> {
             value: trend.end.value.clone(),
         },
         level: trend.level + 1,
-        typ: SubTrendType::Normal,
+        typ: SubTrendType::Derived,
     })
 }
@@ -0,0 +1,207 @@
+//! 形态校验
+//!
+//! 检查各层分析结果的基本约束，以结构化的警告返回而非中断分析。
+
+use crate::shape::{CenterElement, Segment, Stroke, SubTrend};
+use serde_derive::*;
+
+/// 校验警告类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WarningKind {
+    // 相邻两笔方向相同
+    StrokeDirection,
+    // 相邻两笔首尾不相接
+    StrokeGap,
+    // 线段的起止点与所含笔不一致，或相邻线段未覆盖连续的笔
+    SegmentCoverage,
+    // 中枢超出其次级别走势的范围
+    CenterRange,
+}
+
+/// 校验警告
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShapeWarning {
+    pub kind: WarningKind,
+    // 出现问题的元素在所属序列中的下标
+    pub index: usize,
+    pub message: String,
+}
+
+impl ShapeWarning {
+    fn new(kind: WarningKind, index: usize, message: String) -> Self {
+        ShapeWarning {
+            kind,
+            index,
+            message,
+        }
+    }
+}
+
+/// 检查笔方向交替且首尾相接
+pub fn validate_strokes(sks: &[Stroke]) -> Vec<ShapeWarning> {
+    let mut warnings = Vec::new();
+    for (i, pair) in sks.windows(2).enumerate() {
+        let (prev, next) = (&pair[0], &pair[1]);
+        if (prev.end_price() > prev.start_price()) == (next.end_price() > next.start_price()) {
+            warnings.push(ShapeWarning::new(
+                WarningKind::StrokeDirection,
+                i + 1,
+                format!(
+                    "stroke {} has same direction as previous one",
+                    next.start_pt.extremum_ts
+                ),
+            ));
+        }
+        if prev.end_pt.extremum_ts != next.start_pt.extremum_ts {
+            warnings.push(ShapeWarning::new(
+                WarningKind::StrokeGap,
+                i + 1,
+                format!(
+                    "stroke starts at {} but previous one ends at {}",
+                    next.start_pt.extremum_ts, prev.end_pt.extremum_ts
+                ),
+            ));
+        }
+    }
+    warnings
+}
+
+/// 检查线段覆盖连续的笔
+///
+/// 线段带有笔下标区间时校验区间与起止点，否则仅校验相邻线段首尾相接
+pub fn validate_segments(sks: &[Stroke], sgs: &[Segment]) -> Vec<ShapeWarning> {
+    let mut warnings = Vec::new();
+    for (i, sg) in sgs.iter().enumerate() {
+        let (s, e) = match sg.stroke_range {
+            Some(range) => range,
+            None => continue,
+        };
+        if s > e || e >= sks.len() {
+            warnings.push(ShapeWarning::new(
+                WarningKind::SegmentCoverage,
+                i,
+                format!("stroke range ({}, {}) out of bounds", s, e),
+            ));
+            continue;
+        }
+        if sks[s].start_pt.extremum_ts != sg.start_pt.extremum_ts
+            || sks[e].end_pt.extremum_ts != sg.end_pt.extremum_ts
+        {
+            warnings.push(ShapeWarning::new(
+                WarningKind::SegmentCoverage,
+                i,
+                format!(
+                    "segment {} ~ {} does not match strokes ({}, {})",
+                    sg.start_pt.extremum_ts, sg.end_pt.extremum_ts, s, e
+                ),
+            ));
+        }
+    }
+    for (i, pair) in sgs.windows(2).enumerate() {
+        let (prev, next) = (&pair[0], &pair[1]);
+        let contiguous = match (prev.stroke_range, next.stroke_range) {
+            (Some((_, e)), Some((s, _))) => e + 1 == s,
+            _ => prev.end_pt.extremum_ts == next.start_pt.extremum_ts,
+        };
+        if !contiguous {
+            warnings.push(ShapeWarning::new(
+                WarningKind::SegmentCoverage,
+                i + 1,
+                format!(
+                    "segment {} is not contiguous with previous one",
+                    next.start_pt.extremum_ts
+                ),
+            ));
+        }
+    }
+    warnings
+}
+
+/// 检查中枢位于其次级别走势的范围内
+pub fn validate_centers(subtrends: &[SubTrend], centers: &[CenterElement]) -> Vec<ShapeWarning> {
+    let mut warnings = Vec::new();
+    for (i, ce) in centers.iter().enumerate() {
+        let c = match ce.center() {
+            Some(c) => c,
+            None => continue,
+        };
+        let (s, e) = c.subtrend_range;
+        if s > e || e >= subtrends.len() {
+            warnings.push(ShapeWarning::new(
+                WarningKind::CenterRange,
+                i,
+                format!("subtrend range ({}, {}) out of bounds", s, e),
+            ));
+            continue;
+        }
+        let sts = &subtrends[s..=e];
+        let low = sts.iter().map(|st| st.sorted().0).min().unwrap();
+        let high = sts.iter().map(|st| st.sorted().1).max().unwrap();
+        let in_range = c.start.ts == sts[0].start.ts
+            && c.end.ts == sts[sts.len() - 1].end.ts
+            && &c.low.value >= low
+            && &c.high.value <= high
+            && c.low.value <= c.shared_low.value
+            && c.shared_low.value <= c.shared_high.value
+            && c.shared_high.value <= c.high.value;
+        if !in_range {
+            warnings.push(ShapeWarning::new(
+                WarningKind::CenterRange,
+                i,
+                format!(
+                    "center {} ~ {} exceeds subtrends ({}, {})",
+                    c.start.ts, c.end.ts, s, e
+                ),
+            ));
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shape::new_pt;
+
+    #[test]
+    fn test_validate_strokes_and_segments() {
+        let sks = vec![
+            new_stroke("2020-02-10 10:00", 10, "2020-02-10 11:00", 11),
+            new_stroke("2020-02-10 11:00", 11, "2020-02-10 13:30", 10),
+            new_stroke("2020-02-10 13:30", 10, "2020-02-10 14:30", 12),
+            new_stroke("2020-02-10 14:30", 12, "2020-02-11 10:30", 13),
+        ];
+        let warnings = validate_strokes(&sks);
+        assert_eq!(1, warnings.len());
+        assert_eq!(WarningKind::StrokeDirection, warnings[0].kind);
+        assert_eq!(3, warnings[0].index);
+
+        let sgs = vec![
+            Segment {
+                start_pt: sks[0].start_pt.clone(),
+                end_pt: sks[2].end_pt.clone(),
+                gap: None,
+                stroke_range: Some((0, 2)),
+            },
+            Segment {
+                start_pt: sks[2].end_pt.clone(),
+                end_pt: sks[3].end_pt.clone(),
+                gap: None,
+                stroke_range: Some((2, 3)),
+            },
+        ];
+        let warnings = validate_segments(&sks, &sgs);
+        assert_eq!(2, warnings.len());
+        assert!(warnings
+            .iter()
+            .all(|w| w.kind == WarningKind::SegmentCoverage && w.index == 1));
+        assert!(validate_segments(&sks, &sgs[..1]).is_empty());
+    }
+
+    fn new_stroke(start_ts: &str, start_price: i32, end_ts: &str, end_price: i32) -> Stroke {
+        Stroke {
+            start_pt: new_pt(start_ts, start_price, start_price > end_price),
+            end_pt: new_pt(end_ts, end_price, end_price > start_price),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! 证券代码补全
+//!
+//! 将6位数字代码或带交易所前后缀的代码（如sh600000、600000.SH）补全为jqdata格式，
+//! 并按代码段区分板块、指数及场内基金。
+//! 000001等代码在沪市为指数、在深市为股票，未指定交易所时按股票处理。
+
+const XSHG: &str = "XSHG";
+const XSHE: &str = "XSHE";
+const BJSE: &str = "BJSE";
+
+/// 补全后的证券代码，均为jqdata格式的完整代码
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecurityCode {
+    // 沪市主板
+    ShanghaiMain(String),
+    // 科创板
+    Star(String),
+    // 深市主板，含原中小板
+    ShenzhenMain(String),
+    // 创业板
+    ChiNext(String),
+    // 北交所
+    Beijing(String),
+    // 沪深指数
+    Index(String),
+    // 场内基金，含ETF及LOF
+    Fund(String),
+}
+
+impl SecurityCode {
+    /// 补全代码，无法识别时返回None
+    pub fn autocomplete(input: &str) -> Option<Self> {
+        let input = input.trim().to_ascii_uppercase();
+        let (digits, exchange) = split_exchange(&input)?;
+        if digits.len() != 6 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let exchange = match exchange {
+            Some(exchange) => exchange,
+            None => infer_exchange(digits)?,
+        };
+        classify(digits, exchange)
+    }
+
+    /// 完整代码
+    pub fn code(&self) -> &str {
+        match self {
+            SecurityCode::ShanghaiMain(c)
+            | SecurityCode::Star(c)
+            | SecurityCode::ShenzhenMain(c)
+            | SecurityCode::ChiNext(c)
+            | SecurityCode::Beijing(c)
+            | SecurityCode::Index(c)
+            | SecurityCode::Fund(c) => c,
+        }
+    }
+
+    /// 是否为股票
+    pub fn is_stock(&self) -> bool {
+        !matches!(self, SecurityCode::Index(_) | SecurityCode::Fund(_))
+    }
+}
+
+// 拆分数字部分及交易所，支持sh/sz/bj前缀及.SH/.SZ/.BJ/.XSHG/.XSHE/.BJSE后缀
+fn split_exchange(input: &str) -> Option<(&str, Option<&'static str>)> {
+    let exchange = |s: &str| match s {
+        "SH" | XSHG => Some(XSHG),
+        "SZ" | XSHE => Some(XSHE),
+        "BJ" | BJSE => Some(BJSE),
+        _ => None,
+    };
+    if let Some((digits, suffix)) = input.split_once('.') {
+        return Some((digits, Some(exchange(suffix)?)));
+    }
+    if input.len() == 8 && input.is_char_boundary(2) {
+        if let Some(ex) = exchange(&input[..2]) {
+            return Some((&input[2..], Some(ex)));
+        }
+    }
+    Some((input, None))
+}
+
+// 未指定交易所时按代码段推断
+fn infer_exchange(digits: &str) -> Option<&'static str> {
+    match digits.as_bytes()[0] {
+        b'5' | b'6' => Some(XSHG),
+        b'0' | b'1' | b'3' => Some(XSHE),
+        b'8' => Some(BJSE),
+        b'4' if digits.starts_with("43") => Some(BJSE),
+        b'9' if digits.starts_with("92") => Some(BJSE),
+        _ => None,
+    }
+}
+
+fn classify(digits: &str, exchange: &'static str) -> Option<SecurityCode> {
+    let code = format!("{}.{}", digits, exchange);
+    let prefix = |p: &[&str]| p.iter().any(|s| digits.starts_with(s));
+    let sc = match exchange {
+        XSHG if prefix(&["688", "689"]) => SecurityCode::Star(code),
+        XSHG if prefix(&["600", "601", "603", "605"]) => SecurityCode::ShanghaiMain(code),
+        XSHG if prefix(&["000", "93", "95"]) => SecurityCode::Index(code),
+        XSHG if prefix(&["50", "51", "52", "56", "58"]) => SecurityCode::Fund(code),
+        XSHE if prefix(&["000", "001", "002", "003"]) => SecurityCode::ShenzhenMain(code),
+        XSHE if prefix(&["300", "301"]) => SecurityCode::ChiNext(code),
+        XSHE if prefix(&["399"]) => SecurityCode::Index(code),
+        XSHE if prefix(&["15", "16", "18"]) => SecurityCode::Fund(code),
+        BJSE if prefix(&["43", "8", "92"]) => SecurityCode::Beijing(code),
+        _ => return None,
+    };
+    Some(sc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_autocomplete() {
+        let ac = |s: &str| SecurityCode::autocomplete(s);
+        assert_eq!(
+            Some(SecurityCode::ShanghaiMain("600000.XSHG".to_owned())),
+            ac("600000")
+        );
+        assert_eq!(
+            Some(SecurityCode::Star("688001.XSHG".to_owned())),
+            ac("688001")
+        );
+        assert_eq!(
+            Some(SecurityCode::ChiNext("300750.XSHE".to_owned())),
+            ac("sz300750")
+        );
+        assert_eq!(
+            Some(SecurityCode::Beijing("830799.BJSE".to_owned())),
+            ac("830799")
+        );
+        // 未指定交易所时000001为平安银行，指定沪市时为上证指数
+        assert_eq!(
+            Some(SecurityCode::ShenzhenMain("000001.XSHE".to_owned())),
+            ac("000001")
+        );
+        assert_eq!(
+            Some(SecurityCode::Index("000001.XSHG".to_owned())),
+            ac("sh000001")
+        );
+        assert_eq!(
+            Some(SecurityCode::Index("000300.XSHG".to_owned())),
+            ac("000300.SH")
+        );
+        assert_eq!(
+            Some(SecurityCode::Index("399006.XSHE".to_owned())),
+            ac("399006")
+        );
+        assert_eq!(
+            Some(SecurityCode::Fund("510300.XSHG".to_owned())),
+            ac("510300")
+        );
+        assert_eq!(
+            Some(SecurityCode::Fund("159915.XSHE".to_owned())),
+            ac("159915")
+        );
+        assert!(!ac("510300").unwrap().is_stock());
+        assert_eq!("601318.XSHG", ac(" 601318.xshg ").unwrap().code());
+        assert_eq!(None, ac("600000.XSHE"));
+        assert_eq!(None, ac("60000"));
+        assert_eq!(None, ac("IF9999.CCFX"));
+        assert_eq!(None, ac("平安银行"));
+    }
+}
@@ -1,28 +1,71 @@
-#[derive(Debug)]
-pub struct Error(pub String);
+use std::fmt::Write;
 
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.0.to_string())
-    }
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Msg(String),
+    #[error(transparent)]
+    Chrono(#[from] chrono::ParseError),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    // 附加上下文的错误，原错误作为source保留
+    #[error("{msg}")]
+    Context {
+        msg: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
 }
 
-impl std::error::Error for Error {}
+/// 为错误附加上下文
+pub trait ResultExt<T> {
+    fn context<C: Into<String>>(self, msg: C) -> Result<T, Error>;
+}
 
-impl From<rusqlite::Error> for Error {
-    fn from(err: rusqlite::Error) -> Error {
-        Error(format!("{}", err))
+impl<T, E> ResultExt<T> for Result<T, E>
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    fn context<C: Into<String>>(self, msg: C) -> Result<T, Error> {
+        self.map_err(|e| Error::Context {
+            msg: msg.into(),
+            source: Box::new(e),
+        })
     }
 }
 
-impl From<chrono::ParseError> for Error {
-    fn from(err: chrono::ParseError) -> Error {
-        Error(format!("{}", err))
+/// 错误及其来源链，以←连接，如"invalid price 1.2x ← invalid digit found in string"
+///
+/// 已包含在上层描述中的来源不重复输出
+pub fn error_chain(err: &(dyn std::error::Error + 'static)) -> String {
+    let mut s = err.to_string();
+    let mut source = err.source();
+    while let Some(e) = source {
+        let msg = e.to_string();
+        if !s.contains(&msg) {
+            let _ = write!(s, " ← {}", msg);
+        }
+        source = e.source();
     }
+    s
 }
 
-impl From<serde_json::Error> for Error {
-    fn from(err: serde_json::Error) -> Error {
-        Error(format!("{}", err))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_price, parse_ts_from_str};
+
+    #[test]
+    fn test_error_chain() {
+        let err = parse_ts_from_str("2020-13-01").unwrap_err();
+        assert_eq!("invalid datetime: 2020-13-01", err.to_string());
+        assert_eq!(
+            "invalid datetime: 2020-13-01 ← input is out of range",
+            error_chain(&err)
+        );
+        let err = Err::<(), _>(parse_price("1.2x").unwrap_err())
+            .context("invalid gap ratio")
+            .unwrap_err();
+        assert_eq!("invalid gap ratio ← invalid price: 1.2x", error_chain(&err));
     }
 }
@@ -1,13 +1,21 @@
+mod code;
 mod error;
+pub mod phase;
+pub mod price;
+mod tick;
 pub mod trading_timestamp;
 
 #[macro_use]
 extern crate lazy_static;
 
 // pub use datetime::*;
-pub use error::Error;
+pub use code::SecurityCode;
+pub use error::{error_chain, Error, ResultExt};
 pub type Result<T> = std::result::Result<T, Error>;
 
+pub use phase::{current_bar_end, market_phase, MarketPhase, PhaseClock};
+pub use price::{deserialize_price, parse_price};
+pub use tick::Tick;
 pub use trading_timestamp::*;
 
 use chrono::{NaiveDate, NaiveDateTime};
@@ -64,6 +72,16 @@ pub trait TradingTimestamps {
     /// 如果该时刻可交易，将对齐到所在tick的结束时刻
     /// 例如，tick="5m", ts="2020-02-17 09:34:00", 将返回"2020-02-17 09-35:00"
     fn aligned_tick(&self, ts: NaiveDateTime) -> Option<NaiveDateTime>;
+
+    /// 两时刻间的交易分钟数
+    ///
+    /// 仅计算交易日的交易时段，a晚于b时返回负数
+    fn trading_minutes_between(&self, a: NaiveDateTime, b: NaiveDateTime) -> i64;
+
+    /// 两时刻间指定周期的K线数，即结束时刻位于(a, b]的K线个数
+    ///
+    /// a晚于b时返回负数
+    fn bars_between(&self, a: NaiveDateTime, b: NaiveDateTime, tick: Tick) -> i64;
 }
 
 /// 当天起始时刻
@@ -84,27 +102,31 @@ pub fn end_of_day_str(dt: NaiveDate) -> String {
 pub fn parse_ts_from_str(s: &str) -> Result<(NaiveDateTime, bool)> {
     match s.len() {
         10 => {
-            let dt = NaiveDateTime::parse_from_str(&format!("{} 00:00", s), "%Y-%m-%d %H:%M")?;
+            let dt = NaiveDateTime::parse_from_str(&format!("{} 00:00", s), "%Y-%m-%d %H:%M")
+                .context(format!("invalid datetime: {}", s))?;
             Ok((dt, true))
         }
         13 => {
-            let dt = NaiveDateTime::parse_from_str(&format!("{}:00", s), "%Y-%m-%d %H:%M")?;
+            let dt = NaiveDateTime::parse_from_str(&format!("{}:00", s), "%Y-%m-%d %H:%M")
+                .context(format!("invalid datetime: {}", s))?;
             Ok((dt, false))
         }
         16 => {
-            let dt = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")?;
+            let dt = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")
+                .context(format!("invalid datetime: {}", s))?;
             Ok((dt, false))
         }
         19 => {
-            let dt = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")?;
+            let dt = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
+                .context(format!("invalid datetime: {}", s))?;
             Ok((dt, false))
         }
-        _ => Err(Error(format!("invalid datetime format: {}", s))),
+        _ => Err(Error::Msg(format!("invalid datetime format: {}", s))),
     }
 }
 
 /// 解析并返回日期
 pub fn parse_date_from_str(s: &str) -> Result<NaiveDate> {
-    let dt = NaiveDate::parse_from_str(s, "%Y-%m-%d")?;
+    let dt = NaiveDate::parse_from_str(s, "%Y-%m-%d").context(format!("invalid date: {}", s))?;
     Ok(dt)
 }
@@ -0,0 +1,157 @@
+//! 市场阶段
+//!
+//! 根据交易日历及交易时刻划分当前所处的市场阶段，供客户端同步，
+//! 集合竞价含9:25至9:30仅接受委托的阶段，不区分收盘集合竞价。
+
+use crate::trading_timestamp::{AFTERNOON_END, AFTERNOON_START, MORNING_END, MORNING_START};
+use crate::{TradingDates, TradingTimestamps};
+use chrono::{Duration, NaiveDateTime, NaiveTime, Timelike};
+use serde_derive::*;
+
+lazy_static! {
+    pub static ref PRE_OPEN_START: NaiveTime = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
+    pub static ref AUCTION_START: NaiveTime = NaiveTime::from_hms_opt(9, 15, 0).unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarketPhase {
+    PreOpen,
+    Auction,
+    MorningSession,
+    LunchBreak,
+    AfternoonSession,
+    Closed,
+}
+
+impl MarketPhase {
+    /// 是否为连续竞价时段
+    pub fn is_trading(self) -> bool {
+        self == MarketPhase::MorningSession || self == MarketPhase::AfternoonSession
+    }
+}
+
+/// 当前阶段及下一次阶段变化
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhaseClock {
+    pub phase: MarketPhase,
+    pub next_phase: MarketPhase,
+    // 超出交易日历时为None
+    pub next_change: Option<NaiveDateTime>,
+}
+
+/// 给定时刻所处的市场阶段
+///
+/// 非交易日及收盘后为休市，下一阶段为下一交易日的开盘前
+pub fn market_phase<T: TradingDates>(dates: &T, now: NaiveDateTime) -> PhaseClock {
+    let dt = now.date();
+    let tm = now.time();
+    let at = |t: NaiveTime| Some(NaiveDateTime::new(dt, t));
+    let (phase, next_phase, next_change) = if !dates.contains_day(dt) || tm >= *AFTERNOON_END {
+        let next_open = dates
+            .next_day(dt)
+            .map(|d| NaiveDateTime::new(d, *PRE_OPEN_START));
+        (MarketPhase::Closed, MarketPhase::PreOpen, next_open)
+    } else if tm < *PRE_OPEN_START {
+        (
+            MarketPhase::Closed,
+            MarketPhase::PreOpen,
+            at(*PRE_OPEN_START),
+        )
+    } else if tm < *AUCTION_START {
+        (
+            MarketPhase::PreOpen,
+            MarketPhase::Auction,
+            at(*AUCTION_START),
+        )
+    } else if tm < *MORNING_START {
+        (
+            MarketPhase::Auction,
+            MarketPhase::MorningSession,
+            at(*MORNING_START),
+        )
+    } else if tm < *MORNING_END {
+        (
+            MarketPhase::MorningSession,
+            MarketPhase::LunchBreak,
+            at(*MORNING_END),
+        )
+    } else if tm < *AFTERNOON_START {
+        (
+            MarketPhase::LunchBreak,
+            MarketPhase::AfternoonSession,
+            at(*AFTERNOON_START),
+        )
+    } else {
+        (
+            MarketPhase::AfternoonSession,
+            MarketPhase::Closed,
+            at(*AFTERNOON_END),
+        )
+    };
+    PhaseClock {
+        phase,
+        next_phase,
+        next_change,
+    }
+}
+
+/// 盘中正在形成的K线的结束时刻，非连续竞价时段返回None
+pub fn current_bar_end<T>(tts: &T, now: NaiveDateTime) -> Option<NaiveDateTime>
+where
+    T: TradingDates + TradingTimestamps,
+{
+    if !market_phase(tts, now).phase.is_trading() {
+        return None;
+    }
+    // 恰好位于K线结束时刻时，该K线已完成
+    let minute = now.date().and_hms_opt(now.hour(), now.minute(), 0)? + Duration::minutes(1);
+    tts.aligned_tick(minute)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_ts_from_str, LocalTradingTimestamps, Tick, LOCAL_DATES};
+
+    #[test]
+    fn test_market_phase() {
+        let ts = |s: &str| parse_ts_from_str(s).unwrap().0;
+        let clock = market_phase(&**LOCAL_DATES, ts("2020-02-03 09:20"));
+        assert_eq!(MarketPhase::Auction, clock.phase);
+        assert_eq!(Some(ts("2020-02-03 09:30")), clock.next_change);
+        let clock = market_phase(&**LOCAL_DATES, ts("2020-02-03 12:00"));
+        assert_eq!(MarketPhase::LunchBreak, clock.phase);
+        assert_eq!(MarketPhase::AfternoonSession, clock.next_phase);
+        // 周五收盘后至下周一开盘前
+        let clock = market_phase(&**LOCAL_DATES, ts("2020-02-07 15:00"));
+        assert_eq!(MarketPhase::Closed, clock.phase);
+        assert_eq!(Some(ts("2020-02-10 09:00")), clock.next_change);
+        assert_eq!(
+            MarketPhase::Closed,
+            market_phase(&**LOCAL_DATES, ts("2020-02-08 10:00")).phase
+        );
+    }
+
+    #[test]
+    fn test_current_bar_end() {
+        let ts = |s: &str| parse_ts_from_str(s).unwrap().0;
+        let tts = LocalTradingTimestamps::new(Tick::M5);
+        assert_eq!(
+            Some(ts("2020-02-03 09:35")),
+            current_bar_end(&tts, ts("2020-02-03 09:30"))
+        );
+        assert_eq!(
+            Some(ts("2020-02-03 09:40")),
+            current_bar_end(&tts, ts("2020-02-03 09:35"))
+        );
+        assert_eq!(
+            Some(ts("2020-02-03 15:00")),
+            current_bar_end(
+                &LocalTradingTimestamps::new(Tick::D1),
+                ts("2020-02-03 14:10")
+            )
+        );
+        assert_eq!(None, current_bar_end(&tts, ts("2020-02-03 11:30")));
+    }
+}
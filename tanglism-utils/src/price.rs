@@ -0,0 +1,111 @@
+//! 精确价格构造及解析
+//!
+//! BigDecimal::from(f64)会引入浮点误差，如10.20实际为10.199999...，
+//! 导致相等断言及缺口比例计算不精确，价格统一由十进制字符串构造。
+
+use crate::{Error, Result, ResultExt};
+use bigdecimal::BigDecimal;
+use serde::de::{self, Deserializer, Visitor};
+use std::fmt;
+use std::str::FromStr;
+
+/// 由字面量构造精确价格，可传入数字或字符串字面量
+///
+/// ```
+/// use tanglism_utils::price;
+/// assert_eq!(price!(10.20), price!("10.2"));
+/// ```
+#[macro_export]
+macro_rules! price {
+    ($p:literal) => {
+        $crate::parse_price(stringify!($p).trim_matches('"')).expect("invalid price literal")
+    };
+}
+
+/// 严格解析价格
+///
+/// 仅接受可选负号、数字及至多一个小数点，不接受指数、空白、NaN等形式
+pub fn parse_price(s: &str) -> Result<BigDecimal> {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let mut parts = digits.splitn(2, '.');
+    let int_part = parts.next().unwrap_or("");
+    let frac_part = parts.next();
+    let valid = !int_part.is_empty()
+        && int_part.bytes().all(|b| b.is_ascii_digit())
+        && frac_part
+            .map(|f| !f.is_empty() && f.bytes().all(|b| b.is_ascii_digit()))
+            .unwrap_or(true);
+    if !valid {
+        return Err(Error::Msg(format!("invalid price: {}", s)));
+    }
+    BigDecimal::from_str(s).context(format!("invalid price: {}", s))
+}
+
+/// 严格反序列化价格，用于serde的deserialize_with
+///
+/// 接受字符串及整数，字符串按parse_price解析，浮点数存在误差因此拒绝
+pub fn deserialize_price<'de, D>(d: D) -> std::result::Result<BigDecimal, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    d.deserialize_any(PriceVisitor)
+}
+
+struct PriceVisitor;
+
+impl<'de> Visitor<'de> for PriceVisitor {
+    type Value = BigDecimal;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a decimal string or an integer")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> std::result::Result<BigDecimal, E> {
+        parse_price(value).map_err(|_| E::custom(format!("invalid price: {}", value)))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> std::result::Result<BigDecimal, E> {
+        Ok(BigDecimal::from(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> std::result::Result<BigDecimal, E> {
+        Ok(BigDecimal::from(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::*;
+
+    #[test]
+    fn test_parse_price() {
+        assert_eq!(BigDecimal::from(1020) / 100, parse_price("10.20").unwrap());
+        assert_eq!(parse_price("-0.5").unwrap(), price!(-0.5));
+        assert_eq!(parse_price("11").unwrap(), price!("11"));
+        for s in &["", "1e3", " 1", "1.", ".5", "1.2.3", "NaN", "+1", "1,000"] {
+            assert!(parse_price(s).is_err(), "{} should be rejected", s);
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Bar {
+        #[serde(deserialize_with = "deserialize_price")]
+        close: BigDecimal,
+    }
+
+    #[test]
+    fn test_deserialize_price() {
+        let bar: Bar = serde_json::from_str(r#"{"close":"10.20"}"#).unwrap();
+        assert_eq!(price!(10.20), bar.close);
+        let bar: Bar = serde_json::from_str(r#"{"close":11}"#).unwrap();
+        assert_eq!(price!(11), bar.close);
+        for json in &[
+            r#"{"close":"1e3"}"#,
+            r#"{"close":10.2}"#,
+            r#"{"close":" 1"}"#,
+        ] {
+            assert!(serde_json::from_str::<Bar>(json).is_err(), "{}", json);
+        }
+    }
+}
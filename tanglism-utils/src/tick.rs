@@ -0,0 +1,84 @@
+use crate::{Error, Result};
+use serde_derive::*;
+use std::fmt;
+use std::str::FromStr;
+
+/// K线周期
+///
+/// 序列化及解析均使用"1m", "5m", "30m", "1d"形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Tick {
+    #[serde(rename = "1m")]
+    M1,
+    #[serde(rename = "5m")]
+    M5,
+    #[serde(rename = "30m")]
+    M30,
+    #[serde(rename = "1d")]
+    D1,
+}
+
+impl Tick {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Tick::M1 => "1m",
+            Tick::M5 => "5m",
+            Tick::M30 => "30m",
+            Tick::D1 => "1d",
+        }
+    }
+
+    /// 周期的分钟数，日线按全天交易时长240分钟计
+    pub fn minutes(self) -> i32 {
+        match self {
+            Tick::M1 => 1,
+            Tick::M5 => 5,
+            Tick::M30 => 30,
+            Tick::D1 => 240,
+        }
+    }
+
+    /// 每个交易日的K线数
+    pub fn bars_per_day(self) -> i32 {
+        240 / self.minutes()
+    }
+}
+
+impl FromStr for Tick {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "1m" => Ok(Tick::M1),
+            "5m" => Ok(Tick::M5),
+            "30m" => Ok(Tick::M30),
+            "1d" => Ok(Tick::D1),
+            _ => Err(Error::Msg(format!("tick {} not supported", s))),
+        }
+    }
+}
+
+impl fmt::Display for Tick {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_parse_and_serde() -> Result<()> {
+        for s in &["1m", "5m", "30m", "1d"] {
+            let tick: Tick = s.parse()?;
+            assert_eq!(*s, tick.to_string());
+            assert_eq!(format!("\"{}\"", s), serde_json::to_string(&tick)?);
+            assert_eq!(tick, serde_json::from_str(&format!("\"{}\"", s))?);
+        }
+        assert_eq!(8, Tick::M30.bars_per_day());
+        assert!("15m".parse::<Tick>().is_err());
+        assert!(serde_json::from_str::<Tick>("\"15m\"").is_err());
+        Ok(())
+    }
+}
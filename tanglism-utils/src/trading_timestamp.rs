@@ -1,6 +1,7 @@
-use crate::{Error, Result};
-use crate::{TradingDates, TradingTimestamps};
+use crate::{Error, Result, ResultExt};
+use crate::{Tick, TradingDates, TradingTimestamps};
 use chrono::prelude::*;
+use serde_derive::*;
 use std::sync::Arc;
 
 // 对交易日的范围进行全局限制
@@ -13,6 +14,9 @@ lazy_static! {
     pub static ref MORNING_END: NaiveTime = NaiveTime::from_hms(11, 30, 0);
     pub static ref AFTERNOON_START: NaiveTime = NaiveTime::from_hms(13, 0, 0);
     pub static ref AFTERNOON_END: NaiveTime = NaiveTime::from_hms(15, 0, 0);
+    // 科创板盘后固定价格交易时段
+    pub static ref AFTER_HOURS_START: NaiveTime = NaiveTime::from_hms_opt(15, 5, 0).unwrap();
+    pub static ref AFTER_HOURS_END: NaiveTime = NaiveTime::from_hms_opt(15, 30, 0).unwrap();
 }
 
 const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M";
@@ -22,23 +26,64 @@ const DATE_FORMAT: &str = "%Y-%m-%d";
 pub fn parse_ts_from_str(s: &str) -> Result<(NaiveDateTime, bool)> {
     match s.len() {
         10 => {
-            let dt = NaiveDateTime::parse_from_str(&format!("{} 00:00", s), DATETIME_FORMAT)?;
+            let dt = NaiveDateTime::parse_from_str(&format!("{} 00:00", s), DATETIME_FORMAT)
+                .context(format!("invalid datetime: {}", s))?;
             Ok((dt, true))
         }
         16 => {
-            let dt = NaiveDateTime::parse_from_str(s, DATETIME_FORMAT)?;
+            let dt = NaiveDateTime::parse_from_str(s, DATETIME_FORMAT)
+                .context(format!("invalid datetime: {}", s))?;
             Ok((dt, false))
         }
-        _ => Err(Error(format!("invalid datetime format: {}", s))),
+        _ => Err(Error::Msg(format!("invalid datetime format: {}", s))),
+    }
+}
+
+/// 解析结束时刻
+///
+/// 未指定或为"latest"时取当前时刻前最后一个已完成的交易时刻
+pub fn resolve_end_ts(s: Option<&str>, tick: Tick, now: NaiveDateTime) -> Result<NaiveDateTime> {
+    match s.map(str::trim) {
+        None | Some("") | Some("latest") => LocalTradingTimestamps::new(tick)
+            .last_completed_tick(now)
+            .ok_or_else(|| Error::Msg(format!("no completed tick before {}", now))),
+        Some(s) => parse_ts_from_str(s).map(|(ts, _)| ts),
     }
 }
 
 /// 解析并返回日期
 pub fn parse_date_from_str(s: &str) -> Result<NaiveDate> {
-    let dt = NaiveDate::parse_from_str(s, DATE_FORMAT)?;
+    let dt = NaiveDate::parse_from_str(s, DATE_FORMAT).context(format!("invalid date: {}", s))?;
     Ok(dt)
 }
 
+// 日内截至给定时刻已交易的分钟数
+fn day_trading_minutes(tm: NaiveTime) -> i64 {
+    if tm <= *MORNING_START {
+        0
+    } else if tm <= *MORNING_END {
+        (tm - *MORNING_START).num_minutes()
+    } else if tm <= *AFTERNOON_START {
+        120
+    } else if tm <= *AFTERNOON_END {
+        120 + (tm - *AFTERNOON_START).num_minutes()
+    } else {
+        240
+    }
+}
+
+/// 是否为科创板代码，包括688及689开头的股票和存托凭证
+pub fn is_star_market(code: &str) -> bool {
+    code.starts_with("688") || code.starts_with("689")
+}
+
+/// 是否为收盘后至盘后交易结束之间的时刻
+///
+/// 数据源可能在15:05前给出盘后交易的集合数据，因此自15:00之后即视为盘后
+pub fn is_after_hours(tm: NaiveTime) -> bool {
+    tm > *AFTERNOON_END && tm <= *AFTER_HOURS_END
+}
+
 /// 判断是否是允许交易的时刻
 fn permit_trade_time(tm: NaiveTime) -> bool {
     (tm >= *MORNING_START && tm <= *MORNING_END) || (tm >= *AFTERNOON_START && tm <= *AFTERNOON_END)
@@ -166,6 +211,21 @@ impl LocalTradingDates {
         idx_iter.next()
     }
 
+    // 下标小于idx的交易日个数，按位图逐桶计数
+    fn count_days_before(&self, idx: i64) -> i64 {
+        if idx <= 0 {
+            return 0;
+        }
+        let idx = (idx as usize).min(self.dates());
+        let full = idx / BITS;
+        let mut count: u32 = self.bm[..full].iter().map(|b| b.count_ones()).sum();
+        let rem = idx % BITS;
+        if rem > 0 {
+            count += (self.bm[full] & ((BITS_ONE << rem) - 1)).count_ones();
+        }
+        count as i64
+    }
+
     fn dates(&self) -> usize {
         self.bm.len() * BITS
     }
@@ -287,7 +347,7 @@ impl TradingDates for LocalTradingDates {
             self.add_day_idx(idx as usize);
             return Ok(());
         }
-        Err(Error("day not in range".to_owned()))
+        Err(Error::Msg("day not in range".to_owned()))
     }
 }
 
@@ -300,10 +360,12 @@ impl TradingDates for LocalTradingDates {
 /// LOCAL_TRADING_TS_1_MIN
 /// LOCAL_TRADING_TS_5_MIN
 /// LOCAL_TRADING_TS_30_MIN
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "TimestampsSpec", into = "TimestampsSpec")]
 pub struct LocalTradingTimestamps {
-    tick: String,
-    tick_minutes: i32,
+    tick: Tick,
+    // 是否包含科创板盘后交易，包含时盘后时刻对齐到收盘时刻
+    after_hours: bool,
     // 只读交易日集合，可多线程共享
     tdbm: Arc<LocalTradingDates>,
 }
@@ -318,34 +380,106 @@ lazy_static! {
     };
 }
 
+// 序列化时仅保存周期及盘后设置，交易日集合使用本地数据
+#[derive(Serialize, Deserialize)]
+struct TimestampsSpec {
+    tick: Tick,
+    after_hours: bool,
+}
+
+impl From<TimestampsSpec> for LocalTradingTimestamps {
+    fn from(spec: TimestampsSpec) -> Self {
+        LocalTradingTimestamps::new(spec.tick).with_after_hours(spec.after_hours)
+    }
+}
+
+impl From<LocalTradingTimestamps> for TimestampsSpec {
+    fn from(tts: LocalTradingTimestamps) -> Self {
+        TimestampsSpec {
+            tick: tts.tick,
+            after_hours: tts.after_hours,
+        }
+    }
+}
+
 impl LocalTradingTimestamps {
-    pub fn new(tick: &str) -> Result<Self> {
-        let tick_minutes = match tick {
-            "1m" => 1,
-            "5m" => 5,
-            "30m" => 30,
-            "1d" => 240,
-            _ => return Err(Error(format!("tick {} not supported", tick))),
-        };
-        Ok(LocalTradingTimestamps {
-            tick: tick.to_owned(),
-            tick_minutes,
+    pub fn new(tick: Tick) -> Self {
+        LocalTradingTimestamps {
+            tick,
+            after_hours: false,
             tdbm: Arc::clone(&LOCAL_DATES),
-        })
+        }
+    }
+
+    /// 设置是否包含科创板盘后交易
+    ///
+    /// 盘后交易以收盘价成交，不单独形成K线，包含时并入收盘K线，否则盘后时刻不可对齐
+    pub fn with_after_hours(mut self, after_hours: bool) -> Self {
+        self.after_hours = after_hours;
+        self
+    }
+}
+
+impl LocalTradingTimestamps {
+    /// 给定时刻前最后一个已完成的交易时刻
+    ///
+    /// 盘中返回最近一根已结束K线的时刻，未开盘或非交易日返回前一交易日收盘
+    pub fn last_completed_tick(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let dt = now.date();
+        let prev_close = || {
+            self.prev_day(dt)
+                .map(|d| NaiveDateTime::new(d, *AFTERNOON_END))
+        };
+        if !self.contains_day(dt) || now.time() < *MORNING_START {
+            return prev_close();
+        }
+        if now.time() >= *AFTERNOON_END {
+            return Some(NaiveDateTime::new(dt, *AFTERNOON_END));
+        }
+        if self.tick == Tick::D1 {
+            return prev_close();
+        }
+        if now.time() >= *MORNING_END && now.time() < *AFTERNOON_START {
+            return Some(NaiveDateTime::new(dt, *MORNING_END));
+        }
+        let minute = now.minute() - now.minute() % self.tick.minutes() as u32;
+        let ts = dt.and_time(NaiveTime::from_hms_opt(now.hour(), minute, 0)?);
+        if ts.time() == *MORNING_START {
+            return prev_close();
+        }
+        if ts.time() == *AFTERNOON_START {
+            return Some(NaiveDateTime::new(dt, *MORNING_END));
+        }
+        Some(ts)
+    }
+}
+
+impl LocalTradingTimestamps {
+    // 自起始日至给定时刻累计的交易分钟数
+    fn cumulative_minutes(&self, ts: NaiveDateTime) -> i64 {
+        let dt = ts.date().max(*FIRST_DAY).min(*LAST_DAY);
+        let idx = day_to_idx_unchecked(dt);
+        let mut minutes = self.tdbm.count_days_before(idx) * 240;
+        if ts.date() > *LAST_DAY {
+            minutes += if self.tdbm.contains_day(dt) { 240 } else { 0 };
+        } else if ts.date() >= *FIRST_DAY && self.tdbm.contains_day(dt) {
+            minutes += day_trading_minutes(ts.time());
+        }
+        minutes
     }
 }
 
 impl TradingTimestamps for LocalTradingTimestamps {
     fn tick(&self) -> String {
-        self.tick.clone()
+        self.tick.to_string()
     }
 
     fn tick_minutes(&self) -> i32 {
-        self.tick_minutes
+        self.tick.minutes()
     }
 
     fn next_tick(&self, ts: NaiveDateTime) -> Option<NaiveDateTime> {
-        if self.tick == "1d" {
+        if self.tick == Tick::D1 {
             return self.tdbm.next_day(ts.date()).map(|d| d.and_hms(15, 0, 0));
         }
         if ts.minute() % self.tick_minutes() as u32 != 0 {
@@ -376,7 +510,7 @@ impl TradingTimestamps for LocalTradingTimestamps {
     }
 
     fn prev_tick(&self, ts: NaiveDateTime) -> Option<NaiveDateTime> {
-        if self.tick == "1d" {
+        if self.tick == Tick::D1 {
             return self.tdbm.prev_day(ts.date()).map(|d| d.and_hms(15, 0, 0));
         }
         if ts.minute() % self.tick_minutes() as u32 != 0 {
@@ -414,9 +548,12 @@ impl TradingTimestamps for LocalTradingTimestamps {
     }
 
     fn aligned_tick(&self, ts: NaiveDateTime) -> Option<NaiveDateTime> {
+        if self.after_hours && self.contains_day(ts.date()) && is_after_hours(ts.time()) {
+            return Some(NaiveDateTime::new(ts.date(), *AFTERNOON_END));
+        }
         if self.contains_day(ts.date()) && permit_trade_time(ts.time()) {
             // 天级别对齐到收盘时间
-            if self.tick == "1d" {
+            if self.tick == Tick::D1 {
                 return Some(ts.date().and_hms(15, 0, 0));
             }
             let rem = ts.minute() as i32 % self.tick_minutes();
@@ -428,6 +565,16 @@ impl TradingTimestamps for LocalTradingTimestamps {
         }
         None
     }
+
+    fn trading_minutes_between(&self, a: NaiveDateTime, b: NaiveDateTime) -> i64 {
+        self.cumulative_minutes(b) - self.cumulative_minutes(a)
+    }
+
+    fn bars_between(&self, a: NaiveDateTime, b: NaiveDateTime, tick: Tick) -> i64 {
+        // 每日分钟数是周期的整数倍，累计分钟数按周期取整即为K线序号
+        let m = tick.minutes() as i64;
+        self.cumulative_minutes(b).div_euclid(m) - self.cumulative_minutes(a).div_euclid(m)
+    }
 }
 
 /// 代理TradingDates方法
@@ -458,7 +605,7 @@ impl TradingDates for LocalTradingTimestamps {
 
     // 禁止向集合内插入日期
     fn add_day(&mut self, _day: NaiveDate) -> Result<()> {
-        Err(Error(
+        Err(Error::Msg(
             "insertion of trading dates forbidden on ts collections".to_owned(),
         ))
     }
@@ -579,13 +726,13 @@ mod tests {
 
     #[test]
     fn test_trading_ts_tick_and_minutes() -> Result<()> {
-        let ltts1 = LocalTradingTimestamps::new("1m")?;
+        let ltts1 = LocalTradingTimestamps::new(Tick::M1);
         assert_eq!("1m".to_owned(), ltts1.tick());
         assert_eq!(1, ltts1.tick_minutes());
-        let ltts2 = LocalTradingTimestamps::new("5m")?;
+        let ltts2 = LocalTradingTimestamps::new(Tick::M5);
         assert_eq!("5m".to_owned(), ltts2.tick());
         assert_eq!(5, ltts2.tick_minutes());
-        let ltts3 = LocalTradingTimestamps::new("30m")?;
+        let ltts3 = LocalTradingTimestamps::new(Tick::M30);
         assert_eq!("30m".to_owned(), ltts3.tick());
         assert_eq!(30, ltts3.tick_minutes());
         Ok(())
@@ -593,7 +740,7 @@ mod tests {
 
     #[test]
     fn test_trading_ts_prev_and_next_tick() -> Result<()> {
-        let ltts = LocalTradingTimestamps::new("30m")?;
+        let ltts = LocalTradingTimestamps::new(Tick::M30);
         let ts_02031500 = NaiveDateTime::from_str("2020-02-03T15:00:00")?;
         let ts_02040800 = NaiveDateTime::from_str("2020-02-04T08:00:00")?;
         let ts_02040930 = NaiveDateTime::from_str("2020-02-04T09:30:00")?;
@@ -629,9 +776,102 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_last_completed_tick() -> Result<()> {
+        let ts = |s: &str| NaiveDateTime::from_str(s).unwrap();
+        let ltts = LocalTradingTimestamps::new(Tick::M30);
+        // 盘前及首根K线未结束时取前一交易日收盘
+        let prev_close = Some(ts("2020-02-03T15:00:00"));
+        assert_eq!(
+            prev_close,
+            ltts.last_completed_tick(ts("2020-02-04T08:00:00"))
+        );
+        assert_eq!(
+            prev_close,
+            ltts.last_completed_tick(ts("2020-02-04T09:45:00"))
+        );
+        assert_eq!(
+            Some(ts("2020-02-04T10:00:00")),
+            ltts.last_completed_tick(ts("2020-02-04T10:29:59"))
+        );
+        assert_eq!(
+            Some(ts("2020-02-04T11:30:00")),
+            ltts.last_completed_tick(ts("2020-02-04T13:10:00"))
+        );
+        assert_eq!(
+            Some(ts("2020-02-04T15:00:00")),
+            ltts.last_completed_tick(ts("2020-02-04T20:00:00"))
+        );
+        // 周末
+        assert_eq!(
+            Some(ts("2020-02-07T15:00:00")),
+            ltts.last_completed_tick(ts("2020-02-09T10:00:00"))
+        );
+        let daily = LocalTradingTimestamps::new(Tick::D1);
+        assert_eq!(
+            prev_close,
+            daily.last_completed_tick(ts("2020-02-04T14:00:00"))
+        );
+        assert_eq!(
+            ts("2020-02-04T10:00:00"),
+            resolve_end_ts(Some("latest"), Tick::M1, ts("2020-02-04T10:00:30"))?
+        );
+        assert_eq!(
+            ts("2020-02-01T00:00:00"),
+            resolve_end_ts(Some("2020-02-01"), Tick::M1, ts("2020-02-04T10:00:30"))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_trading_minutes_and_bars_between() -> Result<()> {
+        let ts = |s: &str| NaiveDateTime::from_str(s).unwrap();
+        let ltts = LocalTradingTimestamps::new(Tick::M1);
+        let a = ts("2020-02-04T10:00:00");
+        assert_eq!(0, ltts.trading_minutes_between(a, a));
+        assert_eq!(
+            120,
+            ltts.trading_minutes_between(a, ts("2020-02-04T13:30:00"))
+        );
+        assert_eq!(
+            -120,
+            ltts.trading_minutes_between(ts("2020-02-04T13:30:00"), a)
+        );
+        // 跨周末：02-04剩余210分钟，02-05至02-07共3天，02-10上午30分钟
+        let b = ts("2020-02-10T10:00:00");
+        assert_eq!(210 + 3 * 240 + 30, ltts.trading_minutes_between(a, b));
+        assert_eq!(
+            0,
+            ltts.trading_minutes_between(ts("2020-02-07T15:00:00"), ts("2020-02-09T20:00:00"))
+        );
+        assert_eq!(960 / 30, ltts.bars_between(a, b, Tick::M30));
+        assert_eq!(
+            2,
+            ltts.bars_between(a, ts("2020-02-04T11:00:00"), Tick::M30)
+        );
+        assert_eq!(
+            1,
+            ltts.bars_between(
+                ts("2020-02-03T15:00:00"),
+                ts("2020-02-04T10:00:00"),
+                Tick::M30
+            )
+        );
+        assert_eq!(4, ltts.bars_between(a, b, Tick::D1));
+        assert_eq!(
+            0,
+            ltts.bars_between(
+                ts("2020-02-04T15:00:00"),
+                ts("2020-02-04T20:00:00"),
+                Tick::D1
+            )
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_trading_dates_align() -> Result<()> {
-        let dates = LocalTradingTimestamps::new("1d")?;
+        let dates = LocalTradingTimestamps::new(Tick::D1);
         let ts1 = NaiveDateTime::from_str("2020-02-17T09:00:00")?;
         assert_eq!(None, dates.aligned_tick(ts1));
         let ts2 = NaiveDateTime::from_str("2020-02-17T09:40:00")?;
@@ -646,9 +886,9 @@ mod tests {
 
     #[test]
     fn test_trading_ts_align() -> Result<()> {
-        let ts1m = LocalTradingTimestamps::new("1m")?;
-        let ts5m = LocalTradingTimestamps::new("5m")?;
-        let ts30m = LocalTradingTimestamps::new("30m")?;
+        let ts1m = LocalTradingTimestamps::new(Tick::M1);
+        let ts5m = LocalTradingTimestamps::new(Tick::M5);
+        let ts30m = LocalTradingTimestamps::new(Tick::M30);
         let ts1 = NaiveDateTime::from_str("2020-02-17T09:00:00")?;
         assert_eq!(None, ts1m.aligned_tick(ts1));
         assert_eq!(None, ts5m.aligned_tick(ts1));
@@ -676,4 +916,19 @@ mod tests {
         assert_eq!(Some(ts4), ts30m.aligned_tick(ts4));
         Ok(())
     }
+
+    #[test]
+    fn test_after_hours_align() -> Result<()> {
+        assert!(is_star_market("688001.XSHG"));
+        assert!(!is_star_market("600001.XSHG"));
+        let ts = NaiveDateTime::from_str("2020-02-17T15:10:00")?;
+        let close = NaiveDateTime::from_str("2020-02-17T15:00:00")?;
+        assert_eq!(None, LocalTradingTimestamps::new(Tick::M5).aligned_tick(ts));
+        let ts5m = LocalTradingTimestamps::new(Tick::M5).with_after_hours(true);
+        assert_eq!(Some(close), ts5m.aligned_tick(ts));
+        assert_eq!(Some(close), ts5m.aligned_tick(close));
+        let ts2 = NaiveDateTime::from_str("2020-02-17T15:31:00")?;
+        assert_eq!(None, ts5m.aligned_tick(ts2));
+        Ok(())
+    }
 }
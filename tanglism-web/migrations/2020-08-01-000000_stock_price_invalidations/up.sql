@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS stock_price_invalidations (
+    id SERIAL PRIMARY KEY,
+    tick VARCHAR(32) NOT NULL,
+    code VARCHAR(32) NOT NULL,
+    start_dt DATE NOT NULL,
+    end_dt DATE NOT NULL,
+    reason VARCHAR(256),
+    created_at TIMESTAMP(0) NOT NULL,
+    replaced_at TIMESTAMP(0),
+    replaced_rows INTEGER,
+    inserted_rows INTEGER
+);
+CREATE INDEX IF NOT EXISTS stock_price_invalidations_pending
+    ON stock_price_invalidations (tick, code) WHERE replaced_at IS NULL;
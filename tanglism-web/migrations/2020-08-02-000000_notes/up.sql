@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS notes (
+    id SERIAL PRIMARY KEY,
+    dt DATE NOT NULL,
+    code VARCHAR(32),
+    signal_id VARCHAR(64),
+    chart VARCHAR(256),
+    tags TEXT[] NOT NULL DEFAULT '{}',
+    content TEXT NOT NULL,
+    created_at TIMESTAMP(0) NOT NULL,
+    updated_at TIMESTAMP(0) NOT NULL
+);
+CREATE INDEX IF NOT EXISTS notes_code_dt ON notes (code, dt);
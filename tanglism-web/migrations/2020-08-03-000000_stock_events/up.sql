@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS stock_events (
+    id SERIAL PRIMARY KEY,
+    code VARCHAR(32) NOT NULL,
+    event_dt DATE NOT NULL,
+    kind VARCHAR(32) NOT NULL,
+    title VARCHAR(256) NOT NULL DEFAULT '',
+    created_at TIMESTAMP(0) NOT NULL
+);
+CREATE INDEX IF NOT EXISTS stock_events_code_dt ON stock_events (code, event_dt);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS reports (
+    id SERIAL PRIMARY KEY,
+    week_start DATE NOT NULL,
+    week_end DATE NOT NULL,
+    format VARCHAR(16) NOT NULL,
+    codes TEXT[] NOT NULL DEFAULT '{}',
+    content TEXT NOT NULL,
+    created_at TIMESTAMP(0) NOT NULL
+);
+CREATE INDEX IF NOT EXISTS reports_week_start ON reports (week_start);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS northbound_holdings;
+DROP TABLE IF EXISTS northbound_flows;
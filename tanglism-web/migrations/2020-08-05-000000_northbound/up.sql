@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS northbound_flows (
+    dt DATE NOT NULL,
+    link_id INTEGER NOT NULL,
+    buy_amount NUMERIC(20, 4) NOT NULL,
+    sell_amount NUMERIC(20, 4) NOT NULL,
+    PRIMARY KEY (dt, link_id)
+);
+CREATE TABLE IF NOT EXISTS northbound_holdings (
+    code VARCHAR(32) NOT NULL,
+    dt DATE NOT NULL,
+    share_number NUMERIC(20, 0) NOT NULL,
+    share_ratio NUMERIC(10, 4) NOT NULL,
+    PRIMARY KEY (code, dt)
+);
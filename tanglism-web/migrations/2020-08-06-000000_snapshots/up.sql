@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS snapshots (
+    id VARCHAR(16) PRIMARY KEY,
+    code VARCHAR(32) NOT NULL,
+    tick VARCHAR(8) NOT NULL,
+    state TEXT NOT NULL,
+    bundle TEXT NOT NULL,
+    created_at TIMESTAMP(0) NOT NULL
+);
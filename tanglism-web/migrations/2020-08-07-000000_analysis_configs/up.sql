@@ -0,0 +1,2 @@
+ALTER TABLE reports ADD COLUMN IF NOT EXISTS config TEXT NOT NULL DEFAULT '';
+ALTER TABLE snapshots ADD COLUMN IF NOT EXISTS config TEXT NOT NULL DEFAULT '';
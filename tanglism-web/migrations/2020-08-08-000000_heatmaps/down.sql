@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS market_heatmaps;
+DROP TABLE IF EXISTS industry_stocks;
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS industry_stocks (
+    scheme VARCHAR(16) NOT NULL,
+    industry VARCHAR(32) NOT NULL,
+    code VARCHAR(32) NOT NULL,
+    industry_name VARCHAR(64) NOT NULL,
+    synced_dt DATE NOT NULL,
+    PRIMARY KEY (scheme, industry, code)
+);
+CREATE TABLE IF NOT EXISTS market_heatmaps (
+    dt DATE NOT NULL,
+    scheme VARCHAR(16) NOT NULL,
+    content TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (dt, scheme)
+);
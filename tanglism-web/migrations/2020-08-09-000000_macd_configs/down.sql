@@ -0,0 +1 @@
+DROP TABLE IF EXISTS macd_configs;
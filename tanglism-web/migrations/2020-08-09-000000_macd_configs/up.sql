@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS macd_configs (
+    code VARCHAR(32) NOT NULL,
+    tick VARCHAR(8) NOT NULL,
+    fast_ema_period INTEGER NOT NULL,
+    slow_ema_period INTEGER NOT NULL,
+    dea_period INTEGER NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (code, tick)
+);
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS jobs (
+    id SERIAL PRIMARY KEY,
+    kind VARCHAR(32) NOT NULL,
+    params TEXT NOT NULL,
+    status VARCHAR(16) NOT NULL,
+    progress INTEGER NOT NULL DEFAULT 0,
+    message TEXT NOT NULL DEFAULT '',
+    result TEXT,
+    created_at TIMESTAMP(0) NOT NULL,
+    started_at TIMESTAMP(0),
+    finished_at TIMESTAMP(0)
+);
+CREATE INDEX IF NOT EXISTS jobs_status ON jobs (status, id);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS metric_caches (
+    code VARCHAR(32) NOT NULL,
+    tick VARCHAR(8) NOT NULL,
+    metric VARCHAR(16) NOT NULL,
+    params VARCHAR(128) NOT NULL,
+    data_end_ts TIMESTAMP NOT NULL,
+    value TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (code, tick, metric, params, data_end_ts)
+);
+CREATE INDEX IF NOT EXISTS metric_caches_expires_at ON metric_caches (expires_at);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS fund_holdings;
+DROP TABLE IF EXISTS fund_net_values;
@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS fund_net_values (
+    code VARCHAR(32) NOT NULL,
+    dt DATE NOT NULL,
+    net_value NUMERIC(20, 6) NOT NULL,
+    sum_value NUMERIC(20, 6) NOT NULL,
+    refactor_net_value NUMERIC(20, 6) NOT NULL,
+    PRIMARY KEY (code, dt)
+);
+CREATE TABLE IF NOT EXISTS fund_holdings (
+    code VARCHAR(32) NOT NULL,
+    period_end DATE NOT NULL,
+    symbol VARCHAR(32) NOT NULL,
+    pub_date DATE NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    rank INTEGER NOT NULL,
+    shares NUMERIC(20, 0) NOT NULL,
+    market_cap NUMERIC(20, 2) NOT NULL,
+    proportion NUMERIC(10, 4) NOT NULL,
+    PRIMARY KEY (code, period_end, symbol)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS security_names (
+    code VARCHAR(32) NOT NULL,
+    display_name VARCHAR(255) NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    valid_from DATE NOT NULL,
+    valid_to DATE NOT NULL,
+    PRIMARY KEY (code, valid_from)
+);
+CREATE INDEX IF NOT EXISTS security_names_display_name ON security_names (display_name);
+INSERT INTO security_names
+SELECT code, display_name, name, start_date, '2200-01-01' FROM securities
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS stock_price_anomalies (
+    id SERIAL PRIMARY KEY,
+    tick VARCHAR(32) NOT NULL,
+    code VARCHAR(32) NOT NULL,
+    ts TIMESTAMP(0) NOT NULL,
+    kind VARCHAR(32) NOT NULL,
+    detail VARCHAR(256) NOT NULL,
+    open NUMERIC NOT NULL,
+    close NUMERIC NOT NULL,
+    high NUMERIC NOT NULL,
+    low NUMERIC NOT NULL,
+    volume NUMERIC NOT NULL,
+    amount NUMERIC NOT NULL,
+    detected_at TIMESTAMP(0) NOT NULL,
+    UNIQUE (tick, code, ts)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS score_weights (
+    user_name VARCHAR(64) PRIMARY KEY,
+    trend NUMERIC NOT NULL,
+    center NUMERIC NOT NULL,
+    divergence NUMERIC NOT NULL,
+    atrp NUMERIC NOT NULL,
+    high_distance NUMERIC NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS annotation_rules (
+    name VARCHAR(64) PRIMARY KEY,
+    rules TEXT NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
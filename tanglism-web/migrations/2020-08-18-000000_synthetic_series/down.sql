@@ -0,0 +1 @@
+DROP TABLE IF EXISTS synthetic_series;
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS synthetic_series (
+    code VARCHAR(32) PRIMARY KEY,
+    op VARCHAR(16) NOT NULL,
+    left_code VARCHAR(32) NOT NULL,
+    right_code VARCHAR(32) NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
@@ -2,12 +2,18 @@
 
 use dotenv::dotenv;
 use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
-use tanglism_web::{server, Result};
+use tanglism_web::handlers::stock_prices;
+use tanglism_web::{
+    models, server, set_http_config, CachePolicy, HttpConfig, QuotaAction, QuotaBudget,
+    RequestLogConfig, Result, ThrottleConfig, TimeoutConfig,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // env::set_var("RUST_LOG", "actix_web=debug,actix_server=info,diesel=debug");
+    // 日志级别通过RUST_LOG设置，如RUST_LOG=tanglism_web=debug,warp=info
     env_logger::init();
 
     let opt = ServerOpt::from_args();
@@ -18,12 +24,93 @@ async fn main() -> Result<()> {
     } else {
         env::var("DATABASE_URL").expect("DATABASE_URL should not be empty")
     };
-    let jqaccount = if let Some(account) = opt.jqaccount {
-        account
+    // 离线或回放模式下不访问jqdata，无需账户
+    let jqaccount = if opt.offline || opt.jqdata_replay_dir.is_some() {
+        None
+    } else if let Some(account) = opt.jqaccount {
+        Some(account)
     } else {
-        env::var("JQDATA_ACCOUNT").expect("JQDATA_ACCOUNT should not be empty")
+        Some(env::var("JQDATA_ACCOUNT").expect("JQDATA_ACCOUNT should not be empty"))
     };
-    server(&opt.host, opt.port, &dburl, &jqaccount).await?;
+    let admin_token = opt.admin_token.or_else(|| env::var("ADMIN_TOKEN").ok());
+    let report_watchlist = opt
+        .report_watchlist
+        .or_else(|| env::var("REPORT_WATCHLIST").ok())
+        .map(|s| {
+            s.split(',')
+                .map(str::trim)
+                .filter(|c| !c.is_empty())
+                .map(str::to_owned)
+                .collect()
+        });
+    let heatmap_scheme = opt
+        .heatmap_scheme
+        .or_else(|| env::var("HEATMAP_SCHEME").ok());
+    let jq_log = RequestLogConfig {
+        enabled: opt.jqdata_log,
+        mirror_dir: opt
+            .jqdata_mirror_dir
+            .or_else(|| env::var("JQDATA_MIRROR_DIR").ok().map(PathBuf::from)),
+        replay_dir: opt.jqdata_replay_dir,
+        record_dir: opt.jqdata_record_dir,
+    };
+    // 0表示不限制
+    let secs = |s: u64| {
+        if s == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(s))
+        }
+    };
+    let timeouts = TimeoutConfig {
+        jqdata: secs(opt.jqdata_timeout),
+        db_statement: secs(opt.db_timeout),
+    };
+    models::set_tick_price_batch_size(opt.db_insert_batch_size);
+    set_http_config(HttpConfig {
+        max_idle_per_host: opt.jqdata_pool_size,
+        idle_timeout_secs: opt.jqdata_idle_timeout,
+        tcp_keepalive_secs: opt.jqdata_keepalive,
+        ..HttpConfig::default()
+    });
+    stock_prices::after_hours::set_include_after_hours(opt.star_after_hours);
+    let throttle = ThrottleConfig {
+        rate: opt.ws_query_rate,
+        burst: opt.ws_query_burst,
+        debounce: Duration::from_millis(opt.ws_query_debounce_ms),
+    };
+    let quota = QuotaBudget {
+        daily_calls: opt.jqdata_daily_calls,
+        reserve: opt.jqdata_reserve,
+        min_interval_ms: opt.jqdata_min_interval_ms,
+        action: if opt.jqdata_quota_pause {
+            QuotaAction::Pause
+        } else {
+            QuotaAction::Error
+        },
+    };
+    let jq_cache = match opt.jqdata_cache_dir {
+        Some(dir) => Some((
+            dir,
+            CachePolicy::parse(opt.jqdata_cache_ttl, &opt.jqdata_cache_ttls)?,
+        )),
+        None => None,
+    };
+    server(
+        &opt.host,
+        opt.port,
+        &dburl,
+        jqaccount.as_deref(),
+        admin_token,
+        report_watchlist,
+        heatmap_scheme,
+        jq_log,
+        timeouts,
+        throttle,
+        quota,
+        jq_cache,
+    )
+    .await?;
     Ok(())
 }
 
@@ -44,8 +131,146 @@ pub struct ServerOpt {
         default_value = "8080"
     )]
     port: u16,
-    #[structopt(short, long, help = "specify dbfile to use")]
+    #[structopt(
+        short,
+        long,
+        help = "specify postgres url, by default env DATABASE_URL"
+    )]
     dburl: Option<String>,
-    #[structopt(short, long, help = "specify jqdata account to use")]
+    #[structopt(
+        short,
+        long,
+        help = "specify jqdata accounts to use, separated by comma, by default env JQDATA_ACCOUNT"
+    )]
     jqaccount: Option<String>,
+    #[structopt(long, help = "forbid upstream fetches and use cached data only")]
+    offline: bool,
+    #[structopt(long, help = "specify admin token to access admin APIs")]
+    admin_token: Option<String>,
+    #[structopt(
+        long,
+        help = "specify stock codes to generate weekly reports for, separated by comma"
+    )]
+    report_watchlist: Option<String>,
+    #[structopt(
+        long,
+        help = "specify industry scheme such as sw_l1 to generate daily market heatmaps for"
+    )]
+    heatmap_scheme: Option<String>,
+    #[structopt(long, help = "log jqdata requests with redacted parameters")]
+    jqdata_log: bool,
+    #[structopt(
+        long,
+        help = "specify directory to mirror jqdata requests and parsed responses"
+    )]
+    jqdata_mirror_dir: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "specify directory of mirrored jqdata requests to replay instead of upstream"
+    )]
+    jqdata_replay_dir: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "specify directory to record raw jqdata requests and responses as test fixtures"
+    )]
+    jqdata_record_dir: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "specify timeout in seconds of each jqdata request, 0 for unlimited",
+        default_value = "30"
+    )]
+    jqdata_timeout: u64,
+    #[structopt(
+        long,
+        help = "specify idle connections kept for jqdata in the shared http client",
+        default_value = "32"
+    )]
+    jqdata_pool_size: usize,
+    #[structopt(
+        long,
+        help = "specify seconds before an idle jqdata connection is closed",
+        default_value = "90"
+    )]
+    jqdata_idle_timeout: u64,
+    #[structopt(
+        long,
+        help = "specify tcp keep-alive interval in seconds of jqdata connections, 0 to disable",
+        default_value = "60"
+    )]
+    jqdata_keepalive: u64,
+    #[structopt(
+        long,
+        help = "specify max jqdata requests per day, 0 for unlimited",
+        default_value = "0"
+    )]
+    jqdata_daily_calls: u64,
+    #[structopt(
+        long,
+        help = "specify remaining query count of an account kept in reserve, 0 to disable",
+        default_value = "0"
+    )]
+    jqdata_reserve: i32,
+    #[structopt(
+        long,
+        help = "specify minimal interval in milliseconds between jqdata requests",
+        default_value = "0"
+    )]
+    jqdata_min_interval_ms: u64,
+    #[structopt(
+        long,
+        help = "wait until next day instead of failing when daily jqdata budget is exhausted"
+    )]
+    jqdata_quota_pause: bool,
+    #[structopt(
+        long,
+        help = "specify directory to cache jqdata responses, disabled by default"
+    )]
+    jqdata_cache_dir: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "specify default seconds before a cached jqdata response expires",
+        default_value = "3600"
+    )]
+    jqdata_cache_ttl: u64,
+    #[structopt(
+        long,
+        help = "specify per-method cache seconds, e.g. get_price_period:86400,get_mtss:0",
+        default_value = ""
+    )]
+    jqdata_cache_ttls: String,
+    #[structopt(
+        long,
+        help = "specify timeout in seconds of each db statement, 0 for unlimited",
+        default_value = "30"
+    )]
+    db_timeout: u64,
+    #[structopt(
+        long,
+        help = "specify rows of each insert statement when saving prices",
+        default_value = "5000"
+    )]
+    db_insert_batch_size: usize,
+    #[structopt(
+        long,
+        help = "merge after-hours fixed-price bars of STAR market stocks into closing bars instead of stripping them"
+    )]
+    star_after_hours: bool,
+    #[structopt(
+        long,
+        help = "specify queries per second allowed in each websocket session, 0 for unlimited",
+        default_value = "2"
+    )]
+    ws_query_rate: f64,
+    #[structopt(
+        long,
+        help = "specify burst of queries allowed in each websocket session",
+        default_value = "5"
+    )]
+    ws_query_burst: usize,
+    #[structopt(
+        long,
+        help = "specify minimum interval in milliseconds between identical websocket queries",
+        default_value = "300"
+    )]
+    ws_query_debounce_ms: u64,
 }
@@ -9,15 +9,23 @@ use dotenv::dotenv;
 use jqdata::*;
 use lazy_static::lazy_static;
 use std::env;
+use std::io::Read;
+use std::path::PathBuf;
 use std::sync::Mutex as StdMutex;
 use std::time::Duration;
 use structopt::StructOpt;
-use tanglism_utils::{parse_ts_from_str, LocalTradingTimestamps, TradingDates};
-use tanglism_web::handlers::metrics;
-use tanglism_web::handlers::stock_prices::ticks;
+use tanglism_utils::{
+    parse_ts_from_str, resolve_end_ts, LocalTradingTimestamps, Tick, TradingDates, LOCAL_DATES,
+};
+use tanglism_web::handlers::metrics::{self, northbound};
+use tanglism_web::handlers::reports::{self, ReportFormat};
+use tanglism_web::handlers::stock_prices::{invalidation, ticks, verify};
 use tanglism_web::handlers::stocks::Stock;
-use tanglism_web::handlers::{stock_prices, stocks};
-use tanglism_web::{parse_jqaccount, DbPool, Result};
+use tanglism_web::handlers::{funds, heatmap, ohlc, stock_prices, stocks, warm};
+use tanglism_web::models::{self, StockTickPrice};
+use tanglism_web::{
+    parse_jqaccounts, DbPool, Error, ErrorKind, JqdataPool, RequestLogConfig, Result,
+};
 use tokio::sync::Mutex;
 
 lazy_static! {
@@ -39,13 +47,24 @@ async fn main() -> Result<()> {
     } else {
         env::var("DATABASE_URL").expect("DATABASE_URL should not be empty")
     };
-    let jqaccount = if let Some(account) = opt.jqaccount {
-        account
+    // 离线或回放模式下不访问jqdata，无需账户
+    let jqaccount = if opt.offline || opt.jqdata_replay_dir.is_some() {
+        None
+    } else if let Some(account) = opt.jqaccount {
+        Some(account)
     } else {
-        env::var("JQDATA_ACCOUNT").expect("JQDATA_ACCOUNT should not be empty")
+        Some(env::var("JQDATA_ACCOUNT").expect("JQDATA_ACCOUNT should not be empty"))
     };
 
-    let mut tool = Tool::new(dburl, jqaccount);
+    models::set_tick_price_batch_size(opt.db_insert_batch_size);
+    stock_prices::after_hours::set_include_after_hours(opt.star_after_hours);
+    let jq_log = RequestLogConfig {
+        enabled: false,
+        mirror_dir: opt.jqdata_mirror_dir,
+        replay_dir: opt.jqdata_replay_dir,
+        record_dir: opt.jqdata_record_dir,
+    };
+    let mut tool = Tool::new(dburl, jqaccount, jq_log);
     tool.exec(opt.cmd).await?;
     Ok(())
 }
@@ -55,8 +74,40 @@ async fn main() -> Result<()> {
 pub struct ToolOpt {
     #[structopt(short, long, help = "specify dbfile to use")]
     dburl: Option<String>,
-    #[structopt(short, long, help = "specify jqdata account to use")]
+    #[structopt(
+        short,
+        long,
+        help = "specify jqdata accounts to use, separated by comma"
+    )]
     jqaccount: Option<String>,
+    #[structopt(long, help = "forbid upstream fetches and use cached data only")]
+    offline: bool,
+    #[structopt(
+        long,
+        help = "specify directory to mirror jqdata requests and parsed responses"
+    )]
+    jqdata_mirror_dir: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "specify directory of mirrored jqdata requests to replay instead of upstream"
+    )]
+    jqdata_replay_dir: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "specify directory to record raw jqdata requests and responses as test fixtures"
+    )]
+    jqdata_record_dir: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "specify rows of each insert statement when saving prices",
+        default_value = "5000"
+    )]
+    db_insert_batch_size: usize,
+    #[structopt(
+        long,
+        help = "merge after-hours fixed-price bars of STAR market stocks into closing bars instead of stripping them"
+    )]
+    star_after_hours: bool,
     #[structopt(subcommand)]
     cmd: ToolCmd,
 }
@@ -66,13 +117,20 @@ pub enum ToolCmd {
     Count,
     Stock {
         code: String,
+        #[structopt(long, help = "show name history of the stock")]
+        history: bool,
     },
+    Securities,
     Price {
         code: String,
-        tick: String,
+        tick: Tick,
         #[structopt(short, long, help = "specify start time of this query")]
         start: String,
-        #[structopt(short, long, help = "specify end time of this query")]
+        #[structopt(
+            short,
+            long,
+            help = "specify end time of this query, by default the latest completed tick"
+        )]
         end: Option<String>,
     },
     Autofill {
@@ -82,7 +140,7 @@ pub enum ToolCmd {
             help = "specify tick for autofill, by default 1m",
             default_value = "1m"
         )]
-        tick: String,
+        tick: Tick,
         #[structopt(
             short,
             long,
@@ -103,32 +161,163 @@ pub enum ToolCmd {
         #[structopt(long, help = "specify the column to sort by, 'max', 'min', 'avg'")]
         sort_by: Option<String>,
     },
+    Report {
+        #[structopt(
+            short,
+            long,
+            help = "specify stock codes to report, separated by comma"
+        )]
+        codes: String,
+        #[structopt(
+            short,
+            long,
+            help = "specify any date in the week to report, by default last complete week"
+        )]
+        week: Option<String>,
+        #[structopt(
+            short,
+            long,
+            help = "specify report format, 'markdown' or 'html'",
+            default_value = "markdown"
+        )]
+        format: ReportFormat,
+    },
+    Northbound {
+        #[structopt(short, long, help = "specify start date of this sync")]
+        start: String,
+        #[structopt(short, long, help = "specify end date of this sync, by default today")]
+        end: Option<String>,
+        #[structopt(
+            short,
+            long,
+            help = "specify stock codes to sync holdings, separated by comma"
+        )]
+        codes: Option<String>,
+    },
+    Fund {
+        #[structopt(short, long, help = "specify fund codes to sync, separated by comma")]
+        codes: String,
+        #[structopt(short, long, help = "specify start date of this sync")]
+        start: String,
+        #[structopt(short, long, help = "specify end date of this sync, by default today")]
+        end: Option<String>,
+    },
+    Heatmap {
+        #[structopt(
+            short,
+            long,
+            help = "specify industry scheme, e.g. 'sw_l1', 'jq_l1', 'zjw'",
+            default_value = "sw_l1"
+        )]
+        scheme: String,
+        #[structopt(
+            short,
+            long,
+            help = "specify trading date of the heatmap, by default the last trading day"
+        )]
+        date: Option<String>,
+    },
+    Verify {
+        code: String,
+        tick: Tick,
+        #[structopt(
+            long,
+            help = "specify number of recent trading days to verify, by default 5",
+            default_value = "5"
+        )]
+        days: usize,
+        #[structopt(long, help = "invalidate and re-download the range when drift found")]
+        fix: bool,
+    },
+    Warm {
+        #[structopt(
+            short,
+            long,
+            help = "specify stock codes or names to warm, separated by comma"
+        )]
+        watchlist: String,
+        #[structopt(
+            short,
+            long,
+            help = "specify ticks to warm, separated by comma",
+            default_value = "1d,30m"
+        )]
+        ticks: String,
+        #[structopt(
+            short,
+            long,
+            help = "specify days to look back, by default 365",
+            default_value = "365"
+        )]
+        days: i64,
+    },
+    BenchInsert {
+        #[structopt(
+            short,
+            long,
+            help = "specify rows of synthetic prices to insert",
+            default_value = "50000"
+        )]
+        rows: usize,
+        #[structopt(
+            short,
+            long,
+            help = "specify batch sizes to compare, separated by comma",
+            default_value = "1,100,1000,5000"
+        )]
+        batch_sizes: String,
+    },
+    Analyze {
+        #[structopt(help = "specify csv or json file of ohlc bars, '-' for stdin")]
+        file: String,
+        tick: Tick,
+        #[structopt(long, help = "specify parting config, e.g. 'side_bars:2'")]
+        parting_cfg: Option<String>,
+        #[structopt(long, help = "specify stroke config, e.g. 'indep_k:true'")]
+        stroke_cfg: Option<String>,
+        #[structopt(long, help = "specify trend config, e.g. 'level:2'")]
+        trend_cfg: Option<String>,
+    },
 }
 
 pub struct Tool {
     dburl: String,
-    jqaccount: String,
+    // 为空时为离线模式
+    jqaccount: Option<String>,
+    // 录制或回放上游请求，回放时忽略账户
+    jq_log: RequestLogConfig,
     db: StdMutex<Option<DbPool>>,
-    jq: Mutex<Option<JqdataClient>>,
+    jq: Mutex<Option<JqdataPool>>,
 }
 
 impl Tool {
-    pub fn new(dburl: String, jqaccount: String) -> Self {
+    pub fn new(dburl: String, jqaccount: Option<String>, jq_log: RequestLogConfig) -> Self {
         Tool {
             dburl,
             jqaccount,
+            jq_log,
             db: StdMutex::new(None),
             jq: Mutex::new(None),
         }
     }
 
-    async fn jq(&self) -> Result<JqdataClient> {
+    async fn jq(&self) -> Result<JqdataPool> {
         let mut lock = self.jq.lock().await;
         match &*lock {
             Some(jq) => Ok(jq.clone()),
             None => {
-                let (jqmob, jqpwd) = parse_jqaccount(&self.jqaccount)?;
-                let jq = JqdataClient::with_credential(jqmob, jqpwd).await?;
+                let jq = match (&self.jq_log.replay_dir, &self.jqaccount) {
+                    (Some(dir), _) => JqdataPool::replay(dir)?,
+                    (None, Some(jqaccount)) => {
+                        JqdataPool::with_transport(
+                            self.jq_log.transport(),
+                            parse_jqaccounts(jqaccount)?,
+                        )
+                        .await?
+                    }
+                    (None, None) => JqdataPool::offline(),
+                };
+                jq.set_request_log(self.jq_log.clone());
                 lock.replace(jq);
                 Ok(lock.as_ref().unwrap().clone())
             }
@@ -152,7 +341,7 @@ impl Tool {
 
     async fn debug_api_capacity(&mut self) -> Result<()> {
         if log::max_level() >= log::LevelFilter::Debug {
-            let count = self.jq().await?.execute(GetQueryCount {}).await?;
+            let count = self.jq().await?.execute(|| GetQueryCount {}).await?;
             log::debug!("Reserved API capacity {}", count);
         }
         Ok(())
@@ -177,7 +366,7 @@ impl Tool {
         let codes = rs.iter().map(|s| s.code.clone()).collect();
         let tick = "1d".to_owned();
         let today = Local::today().naive_local();
-        let tts = LocalTradingTimestamps::new("1d").unwrap();
+        let tts = LocalTradingTimestamps::new(Tick::D1);
         let end_dt = if tts.contains_day(today) {
             today
         } else {
@@ -270,15 +459,30 @@ impl ToolCmdExec for Tool {
     async fn exec(&mut self, cmd: ToolCmd) -> Result<()> {
         match cmd {
             ToolCmd::Count => {
-                let count = self.jq().await?.execute(GetQueryCount {}).await?;
+                let count = self.jq().await?.execute(|| GetQueryCount {}).await?;
                 println!("{}", count);
             }
-            ToolCmd::Stock { code } => {
+            ToolCmd::Stock { code, history } => {
+                if history {
+                    let detail = stocks::get_stock_detail(self.db()?, code).await?;
+                    for n in &detail.names {
+                        println!(
+                            "{:15}{:15}{:15}{:15}",
+                            n.code, n.display_name, n.valid_from, n.valid_to
+                        );
+                    }
+                    return Ok(());
+                }
                 let rs = stocks::search_keyword_stocks(self.db()?, code).await?;
                 for s in &rs {
                     println!("{:15}{:15}{:15}", s.code, s.display_name, s.end_date);
                 }
             }
+            ToolCmd::Securities => {
+                let today = Local::now().naive_local().date();
+                let rs = stocks::sync_securities(&self.db()?, &self.jq().await?, today).await?;
+                println!("{} added, {} renamed", rs.added, rs.renamed);
+            }
             ToolCmd::Msci { atrp_days, sort_by } => {
                 let rs = stocks::search_msci_stocks(self.db()?).await?;
                 if let Some(atrp_days) = atrp_days {
@@ -295,6 +499,240 @@ impl ToolCmdExec for Tool {
                     self.show_stocks(rs)?;
                 }
             }
+            ToolCmd::Report {
+                codes,
+                week,
+                format,
+            } => {
+                let codes: Vec<String> = codes
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|c| !c.is_empty())
+                    .map(str::to_owned)
+                    .collect();
+                let week_start = match week {
+                    Some(ref s) => parse_ts_from_str(s)?.0.date(),
+                    None => reports::last_complete_week(Local::now().naive_local().date()).0,
+                };
+                let db = self.db()?;
+                let jq = self.jq().await?;
+                let report =
+                    reports::generate_weekly_report(&db, &jq, &codes, week_start, format).await?;
+                log::info!("Report {} saved", report.id);
+                println!("{}", report.content);
+            }
+            ToolCmd::Northbound { start, end, codes } => {
+                let start_dt = parse_ts_from_str(&start)?.0.date();
+                let end_dt = match end {
+                    Some(ref s) => parse_ts_from_str(s)?.0.date(),
+                    None => Local::now().naive_local().date(),
+                };
+                let db = self.db()?;
+                let jq = self.jq().await?;
+                let n = northbound::sync_northbound_flows(&db, &jq, start_dt, end_dt).await?;
+                log::info!("{} rows of northbound flows inserted", n);
+                for code in codes.iter().flat_map(|cs| cs.split(',')).map(str::trim) {
+                    if code.is_empty() {
+                        continue;
+                    }
+                    let n = northbound::sync_northbound_holdings(&db, &jq, code, start_dt, end_dt)
+                        .await?;
+                    log::info!("{} rows of northbound holdings of {} inserted", n, code);
+                }
+            }
+            ToolCmd::Fund { codes, start, end } => {
+                let start_dt = parse_ts_from_str(&start)?.0.date();
+                let end_dt = match end {
+                    Some(ref s) => parse_ts_from_str(s)?.0.date(),
+                    None => Local::now().naive_local().date(),
+                };
+                let db = self.db()?;
+                let jq = self.jq().await?;
+                for code in codes.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+                    let n = funds::sync_fund_net_values(&db, &jq, code, start_dt, end_dt).await?;
+                    log::info!("{} rows of net values of {} inserted", n, code);
+                    let n = funds::sync_fund_holdings(&db, &jq, code, start_dt, end_dt).await?;
+                    log::info!("{} rows of holdings of {} inserted", n, code);
+                }
+            }
+            ToolCmd::Heatmap { scheme, date } => {
+                let dt = match date {
+                    Some(ref s) => parse_ts_from_str(s)?.0.date(),
+                    None => LOCAL_DATES
+                        .prev_day(Local::now().naive_local().date())
+                        .unwrap(),
+                };
+                let db = self.db()?;
+                let jq = self.jq().await?;
+                let hm = heatmap::refresh_heatmap(&db, &jq, &scheme, dt).await?;
+                println!(
+                    "heatmap of {} on {}: {} sectors, {} stocks missing",
+                    hm.scheme,
+                    hm.dt,
+                    hm.sectors.len(),
+                    hm.missing
+                );
+                for s in &hm.sectors {
+                    println!(
+                        "{:<10} {:<12} {:>10} {:>5} {:>5} {:>5}",
+                        s.industry, s.name, s.avg_return, s.count, s.advancing, s.declining
+                    );
+                }
+            }
+            ToolCmd::Analyze {
+                file,
+                tick,
+                parting_cfg,
+                stroke_cfg,
+                trend_cfg,
+            } => {
+                let body = if file == "-" {
+                    let mut buf = Vec::new();
+                    std::io::stdin().read_to_end(&mut buf)?;
+                    buf
+                } else {
+                    std::fs::read(&file)?
+                };
+                let param = ohlc::OhlcParam {
+                    tick,
+                    parting_cfg,
+                    stroke_cfg,
+                    trend_cfg,
+                };
+                let prices = ohlc::parse_bars(&body, tick)?;
+                let analysis = ohlc::analyze_bars(&prices, &param)?;
+                println!("{}", serde_json::to_string_pretty(&analysis).unwrap());
+            }
+            ToolCmd::BenchInsert { rows, batch_sizes } => {
+                let db = self.db()?;
+                println!(
+                    "{:<12}{:<12}{:<12}{:<12}",
+                    "batch", "rows", "secs", "rows/sec"
+                );
+                for bs in batch_sizes
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                {
+                    let bs: usize = bs.parse().map_err(|_| {
+                        Error::custom(ErrorKind::BadRequest, format!("invalid batch size {}", bs))
+                    })?;
+                    let db = db.clone();
+                    let elapsed =
+                        tokio::task::spawn_blocking(move || bench_insert(&db, rows, bs)).await??;
+                    let secs = elapsed.as_secs_f64();
+                    println!(
+                        "{:<12}{:<12}{:<12.3}{:<12.0}",
+                        bs,
+                        rows,
+                        secs,
+                        rows as f64 / secs
+                    );
+                }
+            }
+            ToolCmd::Warm {
+                watchlist,
+                ticks,
+                days,
+            } => {
+                let param = warm::WarmParam {
+                    codes: watchlist
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|c| !c.is_empty())
+                        .map(str::to_owned)
+                        .collect(),
+                    ticks: ticks
+                        .split(',')
+                        .map(|t| t.trim().parse::<Tick>())
+                        .collect::<std::result::Result<_, _>>()?,
+                    days,
+                };
+                param.validate()?;
+                let db = self.db()?;
+                let jq = self.jq().await?;
+                let now = Local::now().naive_local();
+                let start_ts = warm::window_start(now, days);
+                let mut summary = warm::WarmSummary::default();
+                println!(
+                    "{:<15}{:<6}{:>8}{:>10}  ERROR",
+                    "CODE", "TICK", "BARS", "MS"
+                );
+                for code in &param.codes {
+                    for tick in &param.ticks {
+                        let item = warm::warm_item(&db, &jq, code, *tick, start_ts, now).await;
+                        println!(
+                            "{:<15}{:<6}{:>8}{:>10}  {}",
+                            item.code,
+                            item.tick.to_string(),
+                            item.bars,
+                            item.elapsed_ms,
+                            item.error.as_deref().unwrap_or("")
+                        );
+                        summary.push(item);
+                    }
+                }
+                println!("{} warmed, {} failed", summary.warmed, summary.failed);
+            }
+            ToolCmd::Verify {
+                code,
+                tick,
+                days,
+                fix,
+            } => {
+                let today = Local::now().naive_local().date();
+                // 当天的数据尚未收盘
+                let end_dt = LOCAL_DATES.prev_day(today).unwrap();
+                let mut start_dt = end_dt;
+                for _ in 1..days {
+                    start_dt = LOCAL_DATES.prev_day(start_dt).unwrap();
+                }
+                let db = self.db()?;
+                let jq = self.jq().await?;
+                let report = verify::verify_prices(&db, &jq, tick, &code, start_dt, end_dt).await?;
+                println!(
+                    "{} {} from {} to {}: {} bars in db, {} bars upstream",
+                    report.code,
+                    report.tick,
+                    report.start_dt,
+                    report.end_dt,
+                    report.db_bars,
+                    report.api_bars
+                );
+                println!("{:<25}{:<15}FIELDS", "TS", "KIND");
+                for d in &report.diffs {
+                    println!(
+                        "{:<25}{:<15}{}",
+                        d.ts.to_string(),
+                        format!("{:?}", d.kind),
+                        d.fields.join(",")
+                    );
+                }
+                if report.diffs.is_empty() {
+                    println!("no drift found");
+                } else if fix {
+                    let inv = invalidation::invalidate_range(
+                        db.clone(),
+                        tick,
+                        code.clone(),
+                        report.start_dt,
+                        report.end_dt,
+                        Some(format!("verify: {} bars differ", report.diffs.len())),
+                    )
+                    .await?;
+                    // 查询时替换失效区间
+                    stock_prices::get_stock_tick_prices(
+                        &db,
+                        &jq,
+                        tick,
+                        &code,
+                        report.start_dt.and_hms_opt(0, 0, 0).unwrap(),
+                        report.end_dt.and_hms_opt(23, 59, 59).unwrap(),
+                    )
+                    .await?;
+                    log::info!("Invalidation {} replaced", inv.id);
+                }
+            }
             ToolCmd::Price {
                 code,
                 tick,
@@ -302,17 +740,11 @@ impl ToolCmdExec for Tool {
                 end,
             } => {
                 let (start_ts, _) = parse_ts_from_str(&start)?;
-                let end_ts: chrono::NaiveDateTime = if let Some(end_str) = end.as_ref() {
-                    let (ts, _) = parse_ts_from_str(&end_str)?;
-                    ts
-                } else {
-                    let local_ts = Local::today().and_hms(0, 0, 0) - chrono::Duration::seconds(1);
-                    local_ts.naive_local()
-                };
+                let end_ts = resolve_end_ts(end.as_deref(), tick, Local::now().naive_local())?;
                 let db = self.db()?;
                 let jq = &self.jq().await?;
                 let prices =
-                    stock_prices::get_stock_tick_prices(&db, &jq, &tick, &code, start_ts, end_ts)
+                    stock_prices::get_stock_tick_prices(&db, &jq, tick, &code, start_ts, end_ts)
                         .await?;
                 for p in &prices {
                     println!(
@@ -324,13 +756,14 @@ impl ToolCmdExec for Tool {
             ToolCmd::Autofill { tick, iteration } => {
                 // 从MSCI成分股中选取最近10天内没有行情的，查询并插入数据库
                 let msci_stocks = stocks::search_prioritized_stocks(self.db()?).await?;
-                let tts = LocalTradingTimestamps::new("1d").unwrap();
+                let tts = LocalTradingTimestamps::new(Tick::D1);
                 let last_trade_day = tts
                     .prev_day(Local::today().naive_local())
                     .expect("last trade day not exists");
                 let mut it = 0;
                 for s in &msci_stocks {
-                    match stock_prices::query_db_period(&self.db()?, &tick, &s.code).await? {
+                    match stock_prices::query_db_period(&self.db()?, tick.as_str(), &s.code).await?
+                    {
                         Some(spt) => {
                             if spt.end_dt < last_trade_day {
                                 log::info!(
@@ -350,7 +783,7 @@ impl ToolCmdExec for Tool {
                                 let mut saf = StockAutofill::new(
                                     self.jq().await?,
                                     self.db()?,
-                                    &tick,
+                                    tick,
                                     &s.code,
                                     start_dt,
                                     last_trade_day,
@@ -360,7 +793,8 @@ impl ToolCmdExec for Tool {
                                         log::info!("Stock {} {} autofill finished", s.code, tick);
                                         break;
                                     }
-                                    let count = self.jq().await?.execute(GetQueryCount {}).await?;
+                                    let count =
+                                        self.jq().await?.execute(|| GetQueryCount {}).await?;
                                     if count < AUTOFILL_RESERVE_API_COUNT {
                                         log::info!("Reached reserved API limit(limit={}, current={}), stop autofill", AUTOFILL_RESERVE_API_COUNT, count);
                                         return Ok(());
@@ -386,7 +820,7 @@ impl ToolCmdExec for Tool {
                             let mut saf = StockAutofill::new(
                                 self.jq().await?,
                                 self.db()?,
-                                &tick,
+                                tick,
                                 &s.code,
                                 start_dt,
                                 last_trade_day,
@@ -396,7 +830,7 @@ impl ToolCmdExec for Tool {
                                     log::info!("Stock {} {} autofill finished", s.code, tick);
                                     break;
                                 }
-                                let count = self.jq().await?.execute(GetQueryCount {}).await?;
+                                let count = self.jq().await?.execute(|| GetQueryCount {}).await?;
                                 if count < AUTOFILL_RESERVE_API_COUNT {
                                     log::info!("Reached reserved API limit(limit={}, current={}), stop autofill", AUTOFILL_RESERVE_API_COUNT, count);
                                     return Ok(());
@@ -418,10 +852,45 @@ impl ToolCmdExec for Tool {
     }
 }
 
+// 在回滚的事务中插入合成的1分钟K线，返回插入耗时
+fn bench_insert(db: &DbPool, rows: usize, batch_size: usize) -> Result<Duration> {
+    use diesel::Connection;
+    let start_ts = AUTOFILL_START_DATE.and_hms_opt(9, 31, 0).unwrap();
+    let prices: Vec<StockTickPrice> = (0..rows)
+        .map(|i| {
+            let p = BigDecimal::from(10 + (i % 7) as i64);
+            StockTickPrice {
+                tick: "1m".to_owned(),
+                code: "BENCH.XSHG".to_owned(),
+                ts: start_ts + chrono::Duration::minutes(i as i64),
+                open: p.clone(),
+                close: p.clone(),
+                high: p.clone(),
+                low: p,
+                volume: BigDecimal::from(100),
+                amount: BigDecimal::from(1000),
+            }
+        })
+        .collect();
+    let conn = db.get()?;
+    let mut elapsed = Duration::default();
+    let rst = conn.transaction::<(), diesel::result::Error, _>(|| {
+        let start = std::time::Instant::now();
+        models::insert_tick_prices_batched(&conn, &prices, batch_size)?;
+        elapsed = start.elapsed();
+        Err(diesel::result::Error::RollbackTransaction)
+    });
+    match rst {
+        Err(diesel::result::Error::RollbackTransaction) => Ok(elapsed),
+        Err(e) => Err(e.into()),
+        Ok(()) => unreachable!(),
+    }
+}
+
 struct StockAutofill {
-    jq: JqdataClient,
+    jq: JqdataPool,
     db: DbPool,
-    tick: String,
+    tick: Tick,
     code: String,
     start_dt: NaiveDate,
     end_dt: NaiveDate,
@@ -429,22 +898,15 @@ struct StockAutofill {
 }
 
 impl StockAutofill {
-    pub fn new<T: Into<String>, C: Into<String>>(
-        jq: JqdataClient,
+    pub fn new<C: Into<String>>(
+        jq: JqdataPool,
         db: DbPool,
-        tick: T,
+        tick: Tick,
         code: C,
         start_dt: NaiveDate,
         end_dt: NaiveDate,
     ) -> Self {
-        let tick = tick.into();
-        let records_per_day = match tick.as_ref() {
-            "1m" => 240,
-            "5m" => 48,
-            "30m" => 8,
-            "1d" => 1,
-            _ => panic!("invalid tick {}", tick),
-        };
+        let records_per_day = tick.bars_per_day();
         StockAutofill {
             jq,
             db,
@@ -462,7 +924,7 @@ impl StockAutofill {
         }
 
         // 插入数据不可超过AUTOFILL_BATCH_SIZE_THRESHOLD，默认5000
-        let tts = LocalTradingTimestamps::new("1d").unwrap();
+        let tts = LocalTradingTimestamps::new(Tick::D1);
         let mut it_end = self.start_dt;
         let mut batch_size = self.records_per_day;
         while it_end < self.end_dt
@@ -475,7 +937,7 @@ impl StockAutofill {
         let rs = stock_prices::get_stock_tick_prices(
             &self.db,
             &self.jq,
-            &self.tick,
+            self.tick,
             &self.code,
             self.start_dt.and_hms(0, 0, 0),
             it_end.and_hms(23, 59, 59),
@@ -498,3 +960,109 @@ impl StockAutofill {
         self.start_dt > self.end_dt
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use tanglism_web::ReplayTransport;
+
+    const REPLAY_FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/replay");
+    const TRANSPORT_FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/transport");
+
+    fn dt(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    // 由环境变量TEST_DATABASE_URL指定已执行迁移的数据库，未设置时跳过
+    fn test_tool(jq_log: RequestLogConfig) -> Option<Tool> {
+        match env::var("TEST_DATABASE_URL") {
+            Ok(url) => Some(Tool::new(url, None, jq_log)),
+            Err(_) => {
+                eprintln!("TEST_DATABASE_URL not set, skip test depending on database");
+                None
+            }
+        }
+    }
+
+    // 清除测试代码的K线及区间，保证可重复执行
+    fn clear_prices(db: &DbPool, input_tick: &str, input_code: &str) {
+        use diesel::prelude::*;
+        let conn = db.get().unwrap();
+        {
+            use tanglism_web::schema::stock_tick_prices::dsl::*;
+            diesel::delete(stock_tick_prices.filter(tick.eq(input_tick).and(code.eq(input_code))))
+                .execute(&conn)
+                .unwrap();
+        }
+        {
+            use tanglism_web::schema::stock_price_ticks::dsl::*;
+            diesel::delete(stock_price_ticks.filter(tick.eq(input_tick).and(code.eq(input_code))))
+                .execute(&conn)
+                .unwrap();
+        }
+    }
+
+    async fn autofill_and_check(tool: &Tool, jq: JqdataPool, code: &str) -> Result<()> {
+        let db = tool.db()?;
+        clear_prices(&db, "1d", code);
+        let mut saf = StockAutofill::new(
+            jq,
+            db.clone(),
+            Tick::D1,
+            code,
+            dt("2020-08-03"),
+            dt("2020-08-07"),
+        );
+        saf.run().await?;
+        assert!(saf.finished());
+        let period = stock_prices::query_db_period(&db, "1d", code)
+            .await?
+            .unwrap();
+        assert_eq!(
+            (dt("2020-08-03"), dt("2020-08-07")),
+            (period.start_dt, period.end_dt)
+        );
+        let prices = ticks::query_db_prices(
+            db.clone(),
+            "1d".to_owned(),
+            code.to_owned(),
+            dt("2020-08-03"),
+            dt("2020-08-07"),
+        )
+        .await?;
+        assert_eq!(5, prices.len());
+        clear_prices(&db, "1d", code);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_autofill_replay() -> Result<()> {
+        let tool = match test_tool(RequestLogConfig {
+            replay_dir: Some(PathBuf::from(REPLAY_FIXTURES)),
+            ..Default::default()
+        }) {
+            Some(tool) => tool,
+            None => return Ok(()),
+        };
+        let jq = tool.jq().await?;
+        autofill_and_check(&tool, jq, "000001.XSHE").await
+    }
+
+    // 回放上游的原始响应，登录及CSV解析照常执行
+    #[tokio::test]
+    async fn test_autofill_transport_replay() -> Result<()> {
+        let tool = match test_tool(RequestLogConfig::default()) {
+            Some(tool) => tool,
+            None => return Ok(()),
+        };
+        let transport = Arc::new(ReplayTransport::load(TRANSPORT_FIXTURES)?);
+        let jq = JqdataPool::with_transport(
+            transport,
+            vec![("13800000000".to_owned(), "pwd".to_owned())],
+        )
+        .await?;
+        autofill_and_check(&tool, jq, "600000.XSHG").await
+    }
+}
@@ -1,13 +1,14 @@
-// use actix_web::error::ResponseError;
-// use actix_web::HttpResponse;
 use derive_more::Display;
 use std::fmt;
+use tanglism_utils::error_chain;
 
 /// the error type for web server
 #[derive(Debug, Clone)]
 pub enum Error {
     Simple(ErrorKind),
     Custom(ErrorKind, String),
+    // 附加上下文的错误，类别与原错误一致
+    Context(String, Box<Error>),
 }
 
 impl Error {
@@ -20,28 +21,67 @@ impl Error {
     pub fn custom(kind: ErrorKind, err: String) -> Error {
         Error::Custom(kind, err)
     }
+
+    // attach context to error
+    pub fn context<C: Into<String>>(self, msg: C) -> Error {
+        Error::Context(msg.into(), Box::new(self))
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Simple(kind) | Error::Custom(kind, _) => *kind,
+            Error::Context(_, err) => err.kind(),
+        }
+    }
+
+    /// 不含类别的错误描述，上下文与原错误以←连接
+    pub fn message(&self) -> String {
+        match self {
+            Error::Simple(_) => String::new(),
+            Error::Custom(_, s) => s.clone(),
+            Error::Context(msg, err) => match err.message() {
+                s if s.is_empty() => format!("{} ← {}", msg, err.kind()),
+                s => format!("{} ← {}", msg, s),
+            },
+        }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::Simple(kind) => write!(fmt, "{}", kind),
-            Error::Custom(kind, s) => write!(fmt, "{}: {}", kind, s),
+            _ => write!(fmt, "{}: {}", self.kind(), self.message()),
         }
     }
 }
 
+// 描述中已包含上下文链，不再提供source
 impl std::error::Error for Error {}
 
+/// 为错误附加上下文，外部错误先转换为本模块的错误
+pub trait ResultExt<T> {
+    fn context<C: Into<String>>(self, msg: C) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for Result<T, E> {
+    fn context<C: Into<String>>(self, msg: C) -> Result<T, Error> {
+        self.map_err(|e| e.into().context(msg))
+    }
+}
+
 #[derive(Debug, Display, Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum ErrorKind {
     BadRequest,
+    Unauthorized,
     NotFound,
     InternalServerError,
     IO,
     Diesel,
     Jqdata,
     DbConn,
+    // 上游请求或数据库查询超时，客户端可重试
+    Timeout,
 }
 
 impl From<std::io::Error> for Error {
@@ -52,19 +92,25 @@ impl From<std::io::Error> for Error {
 
 impl From<diesel::result::Error> for Error {
     fn from(err: diesel::result::Error) -> Error {
+        // 超过statement_timeout的查询被数据库取消
+        if let diesel::result::Error::DatabaseError(_, ref info) = err {
+            if info.message().contains("statement timeout") {
+                return Error::custom(ErrorKind::Timeout, err.to_string());
+            }
+        }
         Error::custom(ErrorKind::Diesel, err.to_string())
     }
 }
 
 impl From<jqdata::Error> for Error {
     fn from(err: jqdata::Error) -> Error {
-        Error::custom(ErrorKind::Jqdata, err.to_string())
+        Error::custom(ErrorKind::Jqdata, error_chain(&err))
     }
 }
 
 impl From<tanglism_utils::Error> for Error {
     fn from(err: tanglism_utils::Error) -> Error {
-        Error::custom(ErrorKind::InternalServerError, err.to_string())
+        Error::custom(ErrorKind::InternalServerError, error_chain(&err))
     }
 }
 
@@ -76,7 +122,7 @@ impl From<r2d2::Error> for Error {
 
 impl From<tanglism_morph::Error> for Error {
     fn from(err: tanglism_morph::Error) -> Error {
-        Error::custom(ErrorKind::InternalServerError, err.to_string())
+        Error::custom(ErrorKind::InternalServerError, error_chain(&err))
     }
 }
 
@@ -87,3 +133,33 @@ impl From<tokio::task::JoinError> for Error {
 }
 
 impl warp::reject::Reject for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_context() {
+        let err: Result<(), Error> = Err(Error::custom(
+            ErrorKind::BadRequest,
+            "unknown judge gapx".to_owned(),
+        ));
+        let err = err
+            .context("invalid stroke cfg")
+            .context("segment analysis failed")
+            .unwrap_err();
+        assert_eq!(ErrorKind::BadRequest, err.kind());
+        assert_eq!(
+            "BadRequest: segment analysis failed ← invalid stroke cfg ← unknown judge gapx",
+            err.to_string()
+        );
+        let err = Error::simple(ErrorKind::NotFound).context("no share");
+        assert_eq!("NotFound: no share ← NotFound", err.to_string());
+        // 外部错误保留来源链
+        let err = Error::from(tanglism_utils::parse_ts_from_str("2020-13-01").unwrap_err());
+        assert_eq!(
+            "invalid datetime: 2020-13-01 ← input is out of range",
+            err.message()
+        );
+    }
+}
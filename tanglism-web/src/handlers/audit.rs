@@ -0,0 +1,95 @@
+//! 分析配置审计
+//!
+//! 持久化的分析结果（周报、快照）同时保存生成时解析后的完整配置、
+//! 数据截止时刻及程序版本，默认值或算法调整后仍可复现当时的结果。
+
+use crate::{Error, ErrorKind, Result};
+use chrono::NaiveDateTime;
+use serde_derive::*;
+use tanglism_morph::{PartingConfig, StrokeConfig, TrendConfig};
+use tanglism_utils::Tick;
+
+/// 生成分析结果时使用的配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnalysisConfig {
+    pub version: String,
+    pub codes: Vec<String>,
+    pub tick: Tick,
+    // 以下为解析后的配置，包含当时的默认值
+    pub parting_cfg: String,
+    pub stroke_cfg: String,
+    pub trend_cfg: String,
+    #[serde(default)]
+    pub metrics_cfg: String,
+    // 参与计算的最后一根K线
+    pub data_end_ts: Option<NaiveDateTime>,
+}
+
+impl AnalysisConfig {
+    pub fn new(
+        codes: Vec<String>,
+        tick: Tick,
+        parting_cfg: &PartingConfig,
+        stroke_cfg: &StrokeConfig,
+        trend_cfg: &TrendConfig,
+    ) -> Self {
+        AnalysisConfig {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            codes,
+            tick,
+            parting_cfg: format!("{:?}", parting_cfg),
+            stroke_cfg: format!("{:?}", stroke_cfg),
+            trend_cfg: format!("{:?}", trend_cfg),
+            metrics_cfg: String::new(),
+            data_end_ts: None,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self)
+            .map_err(|e| Error::custom(ErrorKind::InternalServerError, e.to_string()))
+    }
+
+    /// 解析保存的配置，早于审计功能的记录没有配置
+    pub fn from_json(s: &str) -> Result<Self> {
+        if s.is_empty() {
+            return Err(Error::custom(
+                ErrorKind::NotFound,
+                "no config recorded".to_owned(),
+            ));
+        }
+        serde_json::from_str(s).map_err(|e| {
+            Error::custom(
+                ErrorKind::InternalServerError,
+                format!("corrupted config: {}", e),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tanglism_morph::CenterConfig;
+
+    #[test]
+    fn test_analysis_config_json() -> Result<()> {
+        let mut cfg = AnalysisConfig::new(
+            vec!["600000.XSHG".to_owned()],
+            Tick::M30,
+            &PartingConfig::default(),
+            &StrokeConfig::default(),
+            &TrendConfig {
+                level: 1,
+                center: CenterConfig::default(),
+            },
+        );
+        cfg.data_end_ts =
+            Some(NaiveDateTime::parse_from_str("2020-07-10 15:00", "%Y-%m-%d %H:%M").unwrap());
+        assert_eq!(env!("CARGO_PKG_VERSION"), cfg.version);
+        assert!(cfg.stroke_cfg.starts_with("StrokeConfig"));
+        assert_eq!(cfg, AnalysisConfig::from_json(&cfg.to_json()?)?);
+        assert!(AnalysisConfig::from_json("").is_err());
+        Ok(())
+    }
+}
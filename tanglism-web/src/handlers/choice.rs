@@ -1,10 +1,9 @@
-use crate::handlers::stock_prices::ticks;
-use crate::handlers::{stocks, tanglism};
+use crate::handlers::confirm::{self, Confirmation, Rule};
+use crate::handlers::stocks;
 use crate::{DbPool, Result};
 use chrono::{Local, NaiveDate};
 use serde_derive::*;
-use tanglism_morph::StrokeConfig;
-use tanglism_utils::{LocalTradingTimestamps, TradingDates};
+use tanglism_utils::{LocalTradingTimestamps, Tick, TradingDates};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StockChoice {
@@ -13,6 +12,8 @@ pub struct StockChoice {
     pub msci: bool,
     pub hs300: bool,
     pub choice: ChoiceType,
+    // 确认规则的执行记录
+    pub confirmations: Vec<Confirmation>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,57 +21,39 @@ pub enum ChoiceType {
     BuyOne,
     BuyTwo,
     BuyThree,
+    // 自定义确认规则选出
+    Custom,
 }
 
-// 先实现寻找一买
-pub async fn list_choices(pool: DbPool, days: usize, limit: usize) -> Result<Vec<StockChoice>> {
+// 默认寻找一买，指定规则时按规则确认
+pub async fn list_choices(
+    pool: DbPool,
+    days: usize,
+    limit: usize,
+    rule: Option<Rule>,
+) -> Result<Vec<StockChoice>> {
     let prioritized_stocks = stocks::search_prioritized_stocks(pool.clone()).await?;
     let mut rst = Vec::new();
     let mut n = 0;
     let (start_dt, end_dt) = start_end_dates(days)?;
+    let (rule, choice) = match rule {
+        Some(rule) => (rule, ChoiceType::Custom),
+        None => (Rule::default(), ChoiceType::BuyOne),
+    };
     for ps in prioritized_stocks {
-        let prices = ticks::query_db_prices(
-            pool.clone(),
-            "30m".to_owned(),
-            ps.code.to_owned(),
-            start_dt,
-            end_dt,
-        )
-        .await?;
-        let pts = tanglism::get_tanglism_partings(&prices)?;
-        let sks = tanglism::get_tanglism_strokes(&pts, "30m", StrokeConfig::default())?;
-        let sgs = tanglism::get_tanglism_segments(&sks)?;
-        if let Some(last_sg) = sgs.last() {
-            // 最后一段向下
-            if last_sg.start_price() > last_sg.end_price() {
-                let prices_1m = ticks::query_db_prices(
-                    pool.clone(),
-                    "1m".to_owned(),
-                    ps.code.to_owned(),
-                    last_sg.start_pt.start_ts.date(),
-                    last_sg.end_pt.end_ts.date(),
-                )
-                .await?;
-                let pts_1m = tanglism::get_tanglism_partings(&prices_1m)?;
-                let sks_1m =
-                    tanglism::get_tanglism_strokes(&pts_1m, "1m", StrokeConfig::default())?;
-                let sgs_1m = tanglism::get_tanglism_segments(&sks_1m)?;
-                let sts_1m = tanglism::get_tanglism_subtrends(&sgs_1m, &sks_1m, "1m", 1)?;
-                let cts_1m = tanglism::get_tanglism_centers(&sts_1m)?;
-                // 存在两个中枢
-                if cts_1m.len() >= 2 {
-                    rst.push(StockChoice {
-                        code: ps.code,
-                        display_name: ps.display_name,
-                        msci: ps.msci,
-                        hs300: ps.hs300,
-                        choice: ChoiceType::BuyOne,
-                    });
-                    n += 1;
-                    if n >= limit {
-                        break;
-                    }
-                }
+        let ev = confirm::evaluate(&pool, &ps.code, &rule, (start_dt, end_dt)).await?;
+        if ev.passed {
+            rst.push(StockChoice {
+                code: ps.code,
+                display_name: ps.display_name,
+                msci: ps.msci,
+                hs300: ps.hs300,
+                choice: choice.clone(),
+                confirmations: ev.chain,
+            });
+            n += 1;
+            if n >= limit {
+                break;
             }
         }
     }
@@ -79,7 +62,7 @@ pub async fn list_choices(pool: DbPool, days: usize, limit: usize) -> Result<Vec
 
 fn start_end_dates(days: usize) -> Result<(NaiveDate, NaiveDate)> {
     let yesterday = Local::today().naive_local() - chrono::Duration::days(1);
-    let tts = LocalTradingTimestamps::new("1d").unwrap();
+    let tts = LocalTradingTimestamps::new(Tick::D1);
     let end_dt = if tts.contains_day(yesterday) {
         yesterday
     } else {
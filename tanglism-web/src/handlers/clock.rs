@@ -0,0 +1,79 @@
+//! 交易时钟
+//!
+//! 由服务端的交易日历给出当前市场阶段及各周期正在形成的K线，
+//! 客户端据此同步倒计时及最后一根K线，无需自行维护交易时段。
+
+use chrono::NaiveDateTime;
+use serde_derive::*;
+use tanglism_utils::{
+    current_bar_end, market_phase, LocalTradingTimestamps, MarketPhase, Tick, TradingDates,
+    LOCAL_DATES,
+};
+
+const CLOCK_TICKS: [Tick; 4] = [Tick::M1, Tick::M5, Tick::M30, Tick::D1];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarketClock {
+    pub now: NaiveDateTime,
+    pub trading_day: bool,
+    pub phase: MarketPhase,
+    pub next_phase: MarketPhase,
+    pub next_change_ts: Option<NaiveDateTime>,
+    pub seconds_to_next: Option<i64>,
+    pub bars: Vec<TickClock>,
+}
+
+/// 单个周期的K线时刻
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TickClock {
+    pub tick: Tick,
+    // 正在形成的K线的结束时刻，非连续竞价时段为None
+    pub current_bar_end_ts: Option<NaiveDateTime>,
+    pub last_completed_ts: Option<NaiveDateTime>,
+}
+
+pub fn market_clock(now: NaiveDateTime) -> MarketClock {
+    let clock = market_phase(&**LOCAL_DATES, now);
+    let bars = CLOCK_TICKS
+        .iter()
+        .map(|tick| {
+            let tts = LocalTradingTimestamps::new(*tick);
+            TickClock {
+                tick: *tick,
+                current_bar_end_ts: current_bar_end(&tts, now),
+                last_completed_ts: tts.last_completed_tick(now),
+            }
+        })
+        .collect();
+    MarketClock {
+        now,
+        trading_day: LOCAL_DATES.contains_day(now.date()),
+        phase: clock.phase,
+        next_phase: clock.next_phase,
+        next_change_ts: clock.next_change,
+        seconds_to_next: clock.next_change.map(|ts| (ts - now).num_seconds()),
+        bars,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tanglism_utils::parse_ts_from_str;
+
+    #[test]
+    fn test_market_clock() {
+        let ts = |s: &str| parse_ts_from_str(s).unwrap().0;
+        let clock = market_clock(ts("2020-02-03 10:02"));
+        assert_eq!(MarketPhase::MorningSession, clock.phase);
+        assert_eq!(Some(5280), clock.seconds_to_next);
+        assert_eq!(
+            Some(ts("2020-02-03 10:30")),
+            clock.bars[2].current_bar_end_ts
+        );
+        assert_eq!(
+            Some(ts("2020-02-03 10:00")),
+            clock.bars[2].last_completed_ts
+        );
+    }
+}
@@ -0,0 +1,324 @@
+//! 多级别买卖点确认
+//!
+//! 确认规则可组合，例如"30分钟最后一段向下，且该段内1分钟下跌力度减弱"。
+//! All中的规则依次执行，前一条线段规则通过后，后续规则的分析区间收窄至该线段，
+//! 由此实现高级别定位、低级别确认。
+
+use crate::handlers::stock_prices::ticks::{self, StockPrice};
+use crate::handlers::tanglism;
+use crate::{DbPool, Error, ErrorKind, Result};
+use chrono::NaiveDate;
+use futures::future::{BoxFuture, FutureExt};
+use serde_derive::*;
+use std::collections::HashMap;
+use tanglism_morph::{CenterConfig, PartingConfig, Segment, StrokeConfig};
+use tanglism_utils::{LocalTradingTimestamps, Tick, TradingTimestamps};
+
+/// 确认规则
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Rule {
+    // 最后一条线段向下，通过后分析区间收窄至该线段
+    SegmentDown { tick: Tick },
+    // 区间内的中枢数不少于min
+    Centers { tick: Tick, min: usize },
+    // 最后一条向下线段创新低且跌速慢于前一条向下线段
+    // 仅比较线段斜率的启发式规则，并非基于MACD面积的背驰判断
+    SlopeWeakening { tick: Tick },
+    All { rules: Vec<Rule> },
+    Any { rules: Vec<Rule> },
+}
+
+impl Default for Rule {
+    // 一买：30分钟最后一段向下，且该段内1分钟存在两个中枢
+    fn default() -> Self {
+        Rule::All {
+            rules: vec![
+                Rule::SegmentDown { tick: Tick::M30 },
+                Rule::Centers {
+                    tick: Tick::M1,
+                    min: 2,
+                },
+            ],
+        }
+    }
+}
+
+/// 解析JSON形式的规则
+pub fn parse_rule(s: &str) -> Result<Rule> {
+    serde_json::from_str(s)
+        .map_err(|e| Error::custom(ErrorKind::BadRequest, format!("invalid rule {}: {}", s, e)))
+}
+
+/// 单条规则的确认结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Confirmation {
+    pub rule: String,
+    pub tick: Tick,
+    pub start_dt: NaiveDate,
+    pub end_dt: NaiveDate,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// 规则整体的确认结果，chain按执行顺序记录每条规则
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Evaluation {
+    pub passed: bool,
+    pub chain: Vec<Confirmation>,
+}
+
+/// 对股票在给定日期区间内评估规则
+pub async fn evaluate(
+    pool: &DbPool,
+    code: &str,
+    rule: &Rule,
+    window: (NaiveDate, NaiveDate),
+) -> Result<Evaluation> {
+    let mut ev = Evaluator {
+        pool,
+        code,
+        prices: HashMap::new(),
+        chain: Vec::new(),
+    };
+    let (passed, _) = ev.eval(rule, window).await?;
+    Ok(Evaluation {
+        passed,
+        chain: ev.chain,
+    })
+}
+
+type Window = (NaiveDate, NaiveDate);
+
+struct Evaluator<'a> {
+    pool: &'a DbPool,
+    code: &'a str,
+    // 同一区间的K线仅查询一次
+    prices: HashMap<(Tick, Window), Vec<StockPrice>>,
+    chain: Vec<Confirmation>,
+}
+
+impl<'a> Evaluator<'a> {
+    // 返回是否通过以及后续规则使用的区间
+    fn eval<'b>(
+        &'b mut self,
+        rule: &'b Rule,
+        window: Window,
+    ) -> BoxFuture<'b, Result<(bool, Window)>>
+    where
+        'a: 'b,
+    {
+        async move {
+            match rule {
+                Rule::All { rules } => {
+                    let mut w = window;
+                    for r in rules {
+                        let (passed, nw) = self.eval(r, w).await?;
+                        if !passed {
+                            return Ok((false, window));
+                        }
+                        w = nw;
+                    }
+                    Ok((true, w))
+                }
+                Rule::Any { rules } => {
+                    for r in rules {
+                        let (passed, nw) = self.eval(r, window).await?;
+                        if passed {
+                            return Ok((true, nw));
+                        }
+                    }
+                    Ok((false, window))
+                }
+                Rule::SegmentDown { tick }
+                | Rule::Centers { tick, .. }
+                | Rule::SlopeWeakening { tick } => {
+                    let key = (*tick, window);
+                    if !self.prices.contains_key(&key) {
+                        let prices = ticks::query_db_prices(
+                            self.pool.clone(),
+                            tick.to_string(),
+                            self.code.to_owned(),
+                            window.0,
+                            window.1,
+                        )
+                        .await?;
+                        self.prices.insert(key, prices);
+                    }
+                    let c = check(rule, &self.prices[&key])?;
+                    self.chain.push(Confirmation {
+                        rule: rule.name().to_owned(),
+                        tick: *tick,
+                        start_dt: window.0,
+                        end_dt: window.1,
+                        passed: c.passed,
+                        detail: c.detail,
+                    });
+                    Ok((c.passed, c.span.unwrap_or(window)))
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+impl Rule {
+    fn name(&self) -> &'static str {
+        match self {
+            Rule::SegmentDown { .. } => "segment_down",
+            Rule::Centers { .. } => "centers",
+            Rule::SlopeWeakening { .. } => "slope_weakening",
+            Rule::All { .. } => "all",
+            Rule::Any { .. } => "any",
+        }
+    }
+}
+
+/// 单条规则的检查结果
+#[derive(Debug)]
+pub struct Check {
+    pub passed: bool,
+    pub detail: String,
+    // 收窄后的区间
+    pub span: Option<Window>,
+}
+
+/// 在K线上检查单条规则，组合规则由evaluate处理
+pub fn check(rule: &Rule, prices: &[StockPrice]) -> Result<Check> {
+    let tick = match rule {
+        Rule::SegmentDown { tick } | Rule::Centers { tick, .. } | Rule::SlopeWeakening { tick } => {
+            *tick
+        }
+        _ => {
+            return Err(Error::custom(
+                ErrorKind::InternalServerError,
+                format!("composite rule {} cannot be checked directly", rule.name()),
+            ))
+        }
+    };
+    let pts = tanglism::get_tanglism_partings(prices, &PartingConfig::default())?;
+    let sks = tanglism::get_tanglism_strokes(&pts, tick, StrokeConfig::default())?;
+    let sgs = tanglism::get_tanglism_segments(&sks)?;
+    let check = match rule {
+        Rule::SegmentDown { .. } => match sgs.last() {
+            Some(sg) if sg.start_price() > sg.end_price() => Check {
+                passed: true,
+                detail: format!(
+                    "segment down {} ~ {}",
+                    sg.start_pt.extremum_ts, sg.end_pt.extremum_ts
+                ),
+                span: Some((sg.start_pt.start_ts.date(), sg.end_pt.end_ts.date())),
+            },
+            Some(_) => Check {
+                passed: false,
+                detail: "last segment is upward".to_owned(),
+                span: None,
+            },
+            None => Check {
+                passed: false,
+                detail: "no segment".to_owned(),
+                span: None,
+            },
+        },
+        Rule::Centers { min, .. } => {
+            let center_cfg = CenterConfig::default();
+            let sts = tanglism::get_tanglism_subtrends(&sgs, &sks, tick, 1, &center_cfg)?;
+            let cts = tanglism::get_tanglism_centers(&sts, &center_cfg)?;
+            let n = cts.iter().filter(|ce| ce.center().is_some()).count();
+            Check {
+                passed: n >= *min,
+                detail: format!("{} centers, require {}", n, min),
+                span: None,
+            }
+        }
+        Rule::SlopeWeakening { .. } => {
+            let mut downs = sgs.iter().filter(|sg| sg.start_price() > sg.end_price());
+            let last = downs.next_back();
+            let prev = downs.next_back();
+            match (prev, last) {
+                (Some(prev), Some(last)) => {
+                    let tts = LocalTradingTimestamps::new(tick);
+                    let passed = slope_weakened(&tts, prev, last);
+                    Check {
+                        passed,
+                        detail: format!(
+                            "segment {} vs {}: {}",
+                            last.start_pt.extremum_ts,
+                            prev.start_pt.extremum_ts,
+                            if passed { "weakened" } else { "not weakened" }
+                        ),
+                        span: None,
+                    }
+                }
+                _ => Check {
+                    passed: false,
+                    detail: "less than two downward segments".to_owned(),
+                    span: None,
+                },
+            }
+        }
+        _ => unreachable!(),
+    };
+    Ok(check)
+}
+
+// 后一段创新低，且每交易分钟的跌幅小于前一段
+// 仅以斜率近似力度，不考虑MACD等指标
+fn slope_weakened<T: TradingTimestamps>(tts: &T, prev: &Segment, last: &Segment) -> bool {
+    if last.end_price() >= prev.end_price() {
+        return false;
+    }
+    let minutes = |sg: &Segment| {
+        tts.trading_minutes_between(sg.start_pt.extremum_ts, sg.end_pt.extremum_ts)
+            .max(1)
+    };
+    // 交叉相乘比较斜率，避免除法
+    let prev_drop = prev.start_price() - prev.end_price();
+    let last_drop = last.start_price() - last.end_price();
+    last_drop * bigdecimal::BigDecimal::from(minutes(prev))
+        < prev_drop * bigdecimal::BigDecimal::from(minutes(last))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tanglism_morph::new_pt;
+
+    #[test]
+    fn test_parse_rule() -> Result<()> {
+        let rule = parse_rule(
+            r#"{"type":"all","rules":[{"type":"segment_down","tick":"30m"},{"type":"centers","tick":"1m","min":2}]}"#,
+        )?;
+        assert_eq!(Rule::default(), rule);
+        assert!(parse_rule(r#"{"type":"segment_down","tick":"15m"}"#).is_err());
+        assert_eq!(
+            Rule::SlopeWeakening { tick: Tick::M1 },
+            parse_rule(r#"{"type":"slope_weakening","tick":"1m"}"#)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_slope_weakened() {
+        let tts = LocalTradingTimestamps::new(Tick::M1);
+        // 前一段60分钟跌2元，后一段150分钟跌2元且创新低
+        let prev = new_segment("2020-02-10 10:00", 12, "2020-02-10 11:00", 10);
+        let last = new_segment("2020-02-11 10:00", 11, "2020-02-11 14:00", 9);
+        assert!(slope_weakened(&tts, &prev, &last));
+        // 跌速加快
+        let fast = new_segment("2020-02-11 10:00", 11, "2020-02-11 10:30", 9);
+        assert!(!slope_weakened(&tts, &prev, &fast));
+        // 未创新低
+        let high = new_segment("2020-02-11 10:00", 11, "2020-02-11 14:00", 10);
+        assert!(!slope_weakened(&tts, &prev, &high));
+    }
+
+    fn new_segment(start_ts: &str, start_price: i32, end_ts: &str, end_price: i32) -> Segment {
+        Segment {
+            start_pt: new_pt(start_ts, start_price, start_price > end_price),
+            end_pt: new_pt(end_ts, end_price, end_price > start_price),
+            gap: None,
+            stroke_range: None,
+        }
+    }
+}
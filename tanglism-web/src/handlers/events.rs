@@ -0,0 +1,162 @@
+//! 股票事件及相对事件的提醒
+//!
+//! 事件（如财报发布日）由用户录入，提醒条件以交易日计算，
+//! 例如"财报发布前N个交易日"，节假日不计入。
+
+use crate::models::{StockEvent, StockEventForm};
+use crate::{DbPool, Error, ErrorKind, Result};
+use chrono::{Local, NaiveDate};
+use diesel::prelude::*;
+use serde_derive::*;
+use tanglism_utils::{TradingDates, LOCAL_DATES};
+
+/// 事件查询条件
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventQuery {
+    pub code: Option<String>,
+    pub kind: Option<String>,
+    pub start_dt: Option<NaiveDate>,
+    pub end_dt: Option<NaiveDate>,
+}
+
+/// 提醒条件：距事件不超过指定交易日数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventAlertRule {
+    pub days_before: i64,
+    pub kind: Option<String>,
+    pub code: Option<String>,
+}
+
+/// 触发的提醒
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventAlert {
+    pub event: StockEvent,
+    // 距事件的交易日数，当天为0
+    pub trading_days: i64,
+}
+
+pub async fn create_event(pool: DbPool, form: StockEventForm) -> Result<StockEvent> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::stock_events::dsl::*;
+        let conn = pool.get()?;
+        diesel::insert_into(stock_events)
+            .values((&form, created_at.eq(Local::now().naive_local())))
+            .get_result::<StockEvent>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data)
+}
+
+pub async fn delete_event(pool: DbPool, event_id: i32) -> Result<()> {
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::stock_events::dsl::*;
+        let conn = pool.get()?;
+        diesel::delete(stock_events.find(event_id))
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    if n == 0 {
+        return Err(Error::custom(
+            ErrorKind::NotFound,
+            format!("event {} not found", event_id),
+        ));
+    }
+    Ok(())
+}
+
+/// 按条件查询事件，按日期升序
+pub async fn list_events(pool: DbPool, q: EventQuery) -> Result<Vec<StockEvent>> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::stock_events::dsl::*;
+        let conn = pool.get()?;
+        let mut query = stock_events.into_boxed();
+        if let Some(input_code) = q.code {
+            query = query.filter(code.eq(input_code));
+        }
+        if let Some(input_kind) = q.kind {
+            query = query.filter(kind.eq(input_kind));
+        }
+        if let Some(start_dt) = q.start_dt {
+            query = query.filter(event_dt.ge(start_dt));
+        }
+        if let Some(end_dt) = q.end_dt {
+            query = query.filter(event_dt.le(end_dt));
+        }
+        query
+            .order((event_dt.asc(), id.asc()))
+            .load::<StockEvent>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data)
+}
+
+/// 查询满足提醒条件的未来事件
+pub async fn event_alerts(
+    pool: DbPool,
+    rule: EventAlertRule,
+    today: NaiveDate,
+) -> Result<Vec<EventAlert>> {
+    if rule.days_before < 0 {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            format!("days_before {} < 0", rule.days_before),
+        ));
+    }
+    let events = list_events(
+        pool,
+        EventQuery {
+            code: rule.code.clone(),
+            kind: rule.kind.clone(),
+            start_dt: Some(today),
+            end_dt: None,
+        },
+    )
+    .await?;
+    let alerts = events
+        .into_iter()
+        .map(|event| {
+            let trading_days = trading_days_between(today, event.event_dt);
+            EventAlert {
+                event,
+                trading_days,
+            }
+        })
+        .take_while(|a| a.trading_days <= rule.days_before)
+        .collect();
+    Ok(alerts)
+}
+
+/// 计算两日期间的交易日数，不含起始日，含结束日
+///
+/// 结束日早于起始日时返回0
+pub fn trading_days_between(start_dt: NaiveDate, end_dt: NaiveDate) -> i64 {
+    let mut n = 0;
+    let mut dt = start_dt;
+    while let Some(next_dt) = LOCAL_DATES.next_day(dt) {
+        if next_dt > end_dt {
+            break;
+        }
+        n += 1;
+        dt = next_dt;
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trading_days_between() {
+        let dt = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        assert_eq!(0, trading_days_between(dt("2020-02-03"), dt("2020-02-03")));
+        assert_eq!(4, trading_days_between(dt("2020-02-03"), dt("2020-02-07")));
+        // 跨周末及春节假期
+        assert_eq!(1, trading_days_between(dt("2020-02-07"), dt("2020-02-10")));
+        assert_eq!(1, trading_days_between(dt("2020-01-23"), dt("2020-02-03")));
+        assert_eq!(0, trading_days_between(dt("2020-02-07"), dt("2020-02-03")));
+    }
+}
@@ -0,0 +1,353 @@
+//! 基金数据
+//!
+//! 每日净值取自finance.FUND_NET_VALUE，持仓股票取自finance.FUND_PORTFOLIO_STOCK，
+//! 由同步任务写入数据库。复权净值可转换为日K线，复用形态分析进行基金择时。
+//! finance表中的基金代码不带后缀，查询时忽略代码中的.OF等后缀。
+
+use super::metrics::northbound::{split_range, Rows};
+use super::ohlc::{self, OhlcAnalysis, OhlcParam};
+use super::stock_prices::ticks::StockPrice;
+use crate::models::{FundHolding, FundNetValue};
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result};
+use bigdecimal::{BigDecimal, Zero};
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use jqdata::RunQuery;
+use serde_derive::*;
+use tanglism_utils::AFTERNOON_END;
+
+const MAX_QUERY_COUNT: u32 = 1000;
+
+/// 基金每日净值
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NavPoint {
+    pub ts: NaiveDateTime,
+    pub net_value: BigDecimal,
+    pub sum_value: BigDecimal,
+    pub refactor_net_value: BigDecimal,
+}
+
+/// 单个报告期的持仓
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FundPortfolio {
+    pub period_end: NaiveDate,
+    pub pub_date: NaiveDate,
+    pub stocks: Vec<PortfolioStock>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortfolioStock {
+    pub rank: i32,
+    pub symbol: String,
+    pub name: String,
+    pub shares: BigDecimal,
+    pub market_cap: BigDecimal,
+    pub proportion: BigDecimal,
+}
+
+/// 同步区间内的基金净值，返回新写入的行数
+pub async fn sync_fund_net_values(
+    db: &DbPool,
+    jq: &JqdataPool,
+    code: &str,
+    start_dt: NaiveDate,
+    end_dt: NaiveDate,
+) -> Result<usize> {
+    let mut inserted = 0;
+    for (s, e) in split_range(start_dt, end_dt) {
+        let lines = jq
+            .execute(|| RunQuery {
+                table: "finance.FUND_NET_VALUE".to_owned(),
+                columns: "code,day,net_value,sum_value,refactor_net_value".to_owned(),
+                conditions: Some(format!(
+                    "code#=#{}&day#>=#{}&day#<=#{}",
+                    query_code(code),
+                    s,
+                    e
+                )),
+                count: Some(MAX_QUERY_COUNT),
+            })
+            .await?;
+        let navs = parse_net_values(code, &lines)?;
+        inserted += insert_net_values(db.clone(), navs).await?;
+    }
+    Ok(inserted)
+}
+
+/// 同步区间内披露的基金持仓，返回新写入的行数
+pub async fn sync_fund_holdings(
+    db: &DbPool,
+    jq: &JqdataPool,
+    code: &str,
+    start_dt: NaiveDate,
+    end_dt: NaiveDate,
+) -> Result<usize> {
+    let mut inserted = 0;
+    for (s, e) in split_range(start_dt, end_dt) {
+        let lines = jq
+            .execute(|| RunQuery {
+                table: "finance.FUND_PORTFOLIO_STOCK".to_owned(),
+                columns: "code,period_end,pub_date,rank,symbol,name,shares,market_cap,proportion"
+                    .to_owned(),
+                conditions: Some(format!(
+                    "code#=#{}&pub_date#>=#{}&pub_date#<=#{}",
+                    query_code(code),
+                    s,
+                    e
+                )),
+                count: Some(MAX_QUERY_COUNT),
+            })
+            .await?;
+        let holdings = parse_holdings(code, &lines)?;
+        inserted += insert_holdings(db.clone(), holdings).await?;
+    }
+    Ok(inserted)
+}
+
+/// 区间内的基金净值
+pub async fn get_fund_net_values(
+    pool: DbPool,
+    input_code: String,
+    start_dt: NaiveDate,
+    end_dt: NaiveDate,
+) -> Result<Vec<NavPoint>> {
+    let navs = tokio::task::spawn_blocking(move || {
+        use crate::schema::fund_net_values::dsl::*;
+        let conn = pool.get()?;
+        fund_net_values
+            .filter(code.eq(input_code))
+            .filter(dt.ge(start_dt).and(dt.le(end_dt)))
+            .order(dt)
+            .load::<FundNetValue>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(navs
+        .into_iter()
+        .map(|n| NavPoint {
+            ts: NaiveDateTime::new(n.dt, *AFTERNOON_END),
+            net_value: n.net_value,
+            sum_value: n.sum_value,
+            refactor_net_value: n.refactor_net_value,
+        })
+        .collect())
+}
+
+/// 报告期截止日在区间内的基金持仓，按报告期升序排列
+pub async fn get_fund_portfolios(
+    pool: DbPool,
+    input_code: String,
+    start_dt: NaiveDate,
+    end_dt: NaiveDate,
+) -> Result<Vec<FundPortfolio>> {
+    let holdings = tokio::task::spawn_blocking(move || {
+        use crate::schema::fund_holdings::dsl::*;
+        let conn = pool.get()?;
+        fund_holdings
+            .filter(code.eq(input_code))
+            .filter(period_end.ge(start_dt).and(period_end.le(end_dt)))
+            .order((period_end, rank))
+            .load::<FundHolding>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(group_portfolios(holdings))
+}
+
+/// 以复权净值分析区间内的基金走势，param.tick应为1d
+pub async fn analyze_fund(
+    pool: DbPool,
+    code: String,
+    start_dt: NaiveDate,
+    end_dt: NaiveDate,
+    param: OhlcParam,
+) -> Result<OhlcAnalysis> {
+    let navs = get_fund_net_values(pool, code.clone(), start_dt, end_dt).await?;
+    if navs.is_empty() {
+        return Err(Error::custom(
+            ErrorKind::NotFound,
+            format!(
+                "no net values of fund {} between {} and {}",
+                code, start_dt, end_dt
+            ),
+        ));
+    }
+    let prices = navs_to_prices(&navs);
+    tokio::task::spawn_blocking(move || ohlc::analyze_bars(&prices, &param)).await?
+}
+
+/// 将复权净值转换为日K线，开高低收均为复权净值
+pub fn navs_to_prices(navs: &[NavPoint]) -> Vec<StockPrice> {
+    navs.iter()
+        .map(|n| StockPrice {
+            ts: n.ts,
+            open: n.refactor_net_value.clone(),
+            close: n.refactor_net_value.clone(),
+            high: n.refactor_net_value.clone(),
+            low: n.refactor_net_value.clone(),
+            volume: BigDecimal::zero(),
+            amount: BigDecimal::zero(),
+        })
+        .collect()
+}
+
+fn query_code(code: &str) -> &str {
+    code.split('.').next().unwrap_or(code)
+}
+
+async fn insert_net_values(pool: DbPool, navs: Vec<FundNetValue>) -> Result<usize> {
+    if navs.is_empty() {
+        return Ok(0);
+    }
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::fund_net_values::dsl::*;
+        let conn = pool.get()?;
+        diesel::insert_into(fund_net_values)
+            .values(&navs)
+            .on_conflict_do_nothing()
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(n)
+}
+
+async fn insert_holdings(pool: DbPool, holdings: Vec<FundHolding>) -> Result<usize> {
+    if holdings.is_empty() {
+        return Ok(0);
+    }
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::fund_holdings::dsl::*;
+        let conn = pool.get()?;
+        diesel::insert_into(fund_holdings)
+            .values(&holdings)
+            .on_conflict_do_nothing()
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(n)
+}
+
+// 净值为空的记录（如暂停估值）跳过
+fn parse_net_values(code: &str, lines: &[String]) -> Result<Vec<FundNetValue>> {
+    let rows = Rows::parse(lines);
+    let mut rst = Vec::with_capacity(rows.rows.len());
+    for row in &rows.rows {
+        if rows.get(row, "net_value")?.is_empty() {
+            continue;
+        }
+        let net_value = rows.get_decimal(row, "net_value")?;
+        // 未提供复权净值时使用单位净值
+        let refactor_net_value = match rows.get(row, "refactor_net_value")? {
+            "" => net_value.clone(),
+            _ => rows.get_decimal(row, "refactor_net_value")?,
+        };
+        rst.push(FundNetValue {
+            code: code.to_owned(),
+            dt: rows.get_date(row, "day")?,
+            net_value,
+            sum_value: rows.get_decimal(row, "sum_value")?,
+            refactor_net_value,
+        });
+    }
+    Ok(rst)
+}
+
+fn parse_holdings(code: &str, lines: &[String]) -> Result<Vec<FundHolding>> {
+    let rows = Rows::parse(lines);
+    let mut rst = Vec::with_capacity(rows.rows.len());
+    for row in &rows.rows {
+        let rank = rows.get(row, "rank")?;
+        rst.push(FundHolding {
+            code: code.to_owned(),
+            period_end: rows.get_date(row, "period_end")?,
+            symbol: rows.get(row, "symbol")?.to_owned(),
+            pub_date: rows.get_date(row, "pub_date")?,
+            name: rows.get(row, "name")?.to_owned(),
+            rank: rank.parse().map_err(|_| {
+                Error::custom(
+                    ErrorKind::InternalServerError,
+                    format!("invalid rank {}", rank),
+                )
+            })?,
+            shares: rows.get_decimal(row, "shares")?,
+            market_cap: rows.get_decimal(row, "market_cap")?,
+            proportion: rows.get_decimal(row, "proportion")?,
+        });
+    }
+    Ok(rst)
+}
+
+// 输入按报告期及排名排序
+fn group_portfolios(holdings: Vec<FundHolding>) -> Vec<FundPortfolio> {
+    let mut rst: Vec<FundPortfolio> = Vec::new();
+    for h in holdings {
+        let stock = PortfolioStock {
+            rank: h.rank,
+            symbol: h.symbol,
+            name: h.name,
+            shares: h.shares,
+            market_cap: h.market_cap,
+            proportion: h.proportion,
+        };
+        match rst.last_mut() {
+            Some(p) if p.period_end == h.period_end => {
+                // 同一报告期可能有多次披露，取最新的披露日
+                if h.pub_date > p.pub_date {
+                    p.pub_date = h.pub_date;
+                }
+                p.stocks.push(stock);
+            }
+            _ => rst.push(FundPortfolio {
+                period_end: h.period_end,
+                pub_date: h.pub_date,
+                stocks: vec![stock],
+            }),
+        }
+    }
+    rst
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tanglism_utils::price;
+
+    fn lines(ls: &[&str]) -> Vec<String> {
+        ls.iter().map(|s| (*s).to_owned()).collect()
+    }
+
+    #[test]
+    fn test_parse_fund_data() -> Result<()> {
+        let navs = parse_net_values(
+            "000001.OF",
+            &lines(&[
+                "code,day,net_value,sum_value,refactor_net_value",
+                "000001,2020-07-06,1.2030,3.5140,4.0123",
+                "000001,2020-07-07,,,",
+                "000001,2020-07-08,1.2100,3.5210,",
+            ]),
+        )?;
+        assert_eq!(2, navs.len());
+        assert_eq!("000001.OF", navs[0].code);
+        assert_eq!(price!(1.21), navs[1].refactor_net_value);
+
+        let holdings = parse_holdings(
+            "000001.OF",
+            &lines(&[
+                "code,period_end,pub_date,rank,symbol,name,shares,market_cap,proportion",
+                "000001,2020-03-31,2020-04-22,1,600519,贵州茅台,10000,11000000,5.2",
+                "000001,2020-03-31,2020-04-22,2,000858,五粮液,20000,2500000,1.3",
+                "000001,2020-06-30,2020-07-20,1,600519,贵州茅台,12000,17000000,6.1",
+            ]),
+        )?;
+        let portfolios = group_portfolios(holdings);
+        assert_eq!(2, portfolios.len());
+        assert_eq!(2, portfolios[0].stocks.len());
+        assert_eq!("000858", portfolios[0].stocks[1].symbol);
+        assert_eq!(1, portfolios[1].stocks.len());
+        assert_eq!("000001", query_code("000001.OF"));
+        Ok(())
+    }
+}
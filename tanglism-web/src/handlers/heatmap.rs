@@ -0,0 +1,446 @@
+//! 市场热力图
+//!
+//! 按行业成分股汇总个股日涨跌幅，输出各行业的平均涨跌幅、涨跌家数及领涨领跌股。
+//! 成分股及日K线由定时任务在收盘后同步，热力图生成后写入数据库。
+
+use super::metrics::math::{div_round, round_half_even, sma, METRIC_SCALE};
+use super::stock_prices::get_stock_tick_prices;
+use crate::models::{IndustryStock, MarketHeatmap};
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result};
+use bigdecimal::{BigDecimal, Signed, Zero};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use jqdata::{GetIndustries, GetIndustryStocks};
+use serde_derive::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use tanglism_utils::{Tick, TradingDates, AFTERNOON_END, LOCAL_DATES};
+
+/// 默认的行业分类：申万一级行业
+pub const DEFAULT_SCHEME: &str = "sw_l1";
+// 每个行业输出的领涨及领跌股数
+const TOP_MOVERS: usize = 3;
+// 收盘后等待数据源更新日K线的分钟数
+const CLOSE_DELAY_MINUTES: i64 = 30;
+pub(crate) const HEATMAP_CHECK_INTERVAL_SECS: u64 = 1800;
+
+/// 热力图
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Heatmap {
+    pub dt: NaiveDate,
+    pub scheme: String,
+    // 按平均涨跌幅降序
+    pub sectors: Vec<Sector>,
+    // 缺少日K线而未计入的股票数
+    pub missing: usize,
+}
+
+/// 行业汇总，涨跌幅为相对前一交易日收盘价的比例
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sector {
+    pub industry: String,
+    pub name: String,
+    pub count: usize,
+    pub advancing: usize,
+    pub declining: usize,
+    pub avg_return: BigDecimal,
+    pub top_gainers: Vec<Mover>,
+    pub top_losers: Vec<Mover>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mover {
+    pub code: String,
+    #[serde(rename = "return")]
+    pub ret: BigDecimal,
+}
+
+/// 同步行业分类下各行业的成分股，替换原有记录，返回写入的行数
+pub async fn sync_industry_stocks(
+    pool: &DbPool,
+    jq: &JqdataPool,
+    input_scheme: &str,
+    dt: NaiveDate,
+) -> Result<usize> {
+    let industries = jq
+        .execute(|| GetIndustries {
+            code: input_scheme.to_owned(),
+        })
+        .await?;
+    let mut members = Vec::new();
+    for ind in industries {
+        let codes = jq
+            .execute(|| GetIndustryStocks {
+                code: ind.index.clone(),
+                date: dt.to_string(),
+            })
+            .await?;
+        for c in codes {
+            members.push(IndustryStock {
+                scheme: input_scheme.to_owned(),
+                industry: ind.index.clone(),
+                code: c,
+                industry_name: ind.name.clone(),
+                synced_dt: dt,
+            });
+        }
+    }
+    if members.is_empty() {
+        return Err(Error::custom(
+            ErrorKind::NotFound,
+            format!("no industry members of {}", input_scheme),
+        ));
+    }
+    let pool = pool.clone();
+    let input_scheme = input_scheme.to_owned();
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::industry_stocks::dsl::*;
+        let conn = pool.get()?;
+        conn.transaction::<_, Error, _>(|| {
+            diesel::delete(industry_stocks.filter(scheme.eq(&input_scheme))).execute(&conn)?;
+            let n = diesel::insert_into(industry_stocks)
+                .values(&members)
+                .on_conflict_do_nothing()
+                .execute(&conn)?;
+            Ok(n)
+        })
+    })
+    .await??;
+    Ok(n)
+}
+
+/// 生成指定交易日的热力图，仅使用数据库中已有的日K线
+pub async fn build_heatmap(pool: DbPool, input_scheme: &str, dt: NaiveDate) -> Result<Heatmap> {
+    let prev_dt = prev_trade_day(dt)?;
+    let members = load_members(pool.clone(), input_scheme.to_owned()).await?;
+    let codes: Vec<String> = members
+        .iter()
+        .map(|m| m.code.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let closes = tokio::task::spawn_blocking(move || {
+        use crate::schema::stock_tick_prices::dsl::*;
+        let conn = pool.get()?;
+        stock_tick_prices
+            .select((code, ts, close))
+            .filter(tick.eq(Tick::D1.to_string()))
+            .filter(code.eq_any(codes))
+            .filter(ts.eq_any(vec![
+                NaiveDateTime::new(prev_dt, *AFTERNOON_END),
+                NaiveDateTime::new(dt, *AFTERNOON_END),
+            ]))
+            .load::<(String, NaiveDateTime, BigDecimal)>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    let returns = daily_returns(closes, dt);
+    let (sectors, missing) = aggregate(&members, &returns);
+    Ok(Heatmap {
+        dt,
+        scheme: input_scheme.to_owned(),
+        sectors,
+        missing,
+    })
+}
+
+/// 同步成分股及日K线后生成热力图并保存
+pub async fn refresh_heatmap(
+    pool: &DbPool,
+    jq: &JqdataPool,
+    input_scheme: &str,
+    dt: NaiveDate,
+) -> Result<Heatmap> {
+    let n = sync_industry_stocks(pool, jq, input_scheme, dt).await?;
+    log::info!("{} industry members of {} synced", n, input_scheme);
+    let prev_dt = prev_trade_day(dt)?;
+    let members = load_members(pool.clone(), input_scheme.to_owned()).await?;
+    let codes: BTreeSet<_> = members.into_iter().map(|m| m.code).collect();
+    for c in &codes {
+        // 单只股票失败不影响整体，计入缺失
+        if let Err(e) = get_stock_tick_prices(
+            pool,
+            jq,
+            Tick::D1,
+            c,
+            NaiveDateTime::new(prev_dt, *AFTERNOON_END),
+            NaiveDateTime::new(dt, *AFTERNOON_END),
+        )
+        .await
+        {
+            log::warn!("failed to fetch daily prices of {}: {}", c, e);
+        }
+    }
+    let heatmap = build_heatmap(pool.clone(), input_scheme, dt).await?;
+    save_heatmap(pool.clone(), &heatmap).await?;
+    Ok(heatmap)
+}
+
+/// 查询热力图，未指定日期时取最近保存的热力图，指定日期但未保存时由已有数据生成
+pub async fn get_heatmap(
+    pool: DbPool,
+    input_scheme: String,
+    input_dt: Option<NaiveDate>,
+) -> Result<Heatmap> {
+    let p = pool.clone();
+    let s = input_scheme.clone();
+    let saved = tokio::task::spawn_blocking(move || {
+        use crate::schema::market_heatmaps::dsl::*;
+        let conn = p.get()?;
+        let mut query = market_heatmaps.filter(scheme.eq(s)).into_boxed();
+        if let Some(d) = input_dt {
+            query = query.filter(dt.eq(d));
+        }
+        query
+            .order(dt.desc())
+            .first::<MarketHeatmap>(&conn)
+            .optional()
+            .map_err(Error::from)
+    })
+    .await??;
+    match (saved, input_dt) {
+        (Some(h), _) => serde_json::from_str(&h.content).map_err(|e| {
+            Error::custom(
+                ErrorKind::InternalServerError,
+                format!("corrupted heatmap: {}", e),
+            )
+        }),
+        (None, Some(d)) => build_heatmap(pool, &input_scheme, d).await,
+        (None, None) => Err(Error::custom(
+            ErrorKind::NotFound,
+            format!("no heatmap of {}", input_scheme),
+        )),
+    }
+}
+
+async fn save_heatmap(pool: DbPool, heatmap: &Heatmap) -> Result<()> {
+    let record = MarketHeatmap {
+        dt: heatmap.dt,
+        scheme: heatmap.scheme.clone(),
+        content: serde_json::to_string(heatmap)
+            .map_err(|e| Error::custom(ErrorKind::InternalServerError, e.to_string()))?,
+        created_at: Local::now().naive_local(),
+    };
+    tokio::task::spawn_blocking(move || {
+        use crate::schema::market_heatmaps::dsl::*;
+        let conn = pool.get()?;
+        diesel::insert_into(market_heatmaps)
+            .values(&record)
+            .on_conflict((dt, scheme))
+            .do_update()
+            .set((
+                content.eq(&record.content),
+                created_at.eq(record.created_at),
+            ))
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(())
+}
+
+pub(crate) async fn heatmap_exists(
+    pool: DbPool,
+    input_scheme: String,
+    input_dt: NaiveDate,
+) -> Result<bool> {
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::market_heatmaps::dsl::*;
+        let conn = pool.get()?;
+        market_heatmaps
+            .filter(scheme.eq(input_scheme))
+            .filter(dt.eq(input_dt))
+            .count()
+            .get_result::<i64>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(n > 0)
+}
+
+async fn load_members(pool: DbPool, input_scheme: String) -> Result<Vec<IndustryStock>> {
+    let s = input_scheme.clone();
+    let members = tokio::task::spawn_blocking(move || {
+        use crate::schema::industry_stocks::dsl::*;
+        let conn = pool.get()?;
+        industry_stocks
+            .filter(scheme.eq(s))
+            .load::<IndustryStock>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    if members.is_empty() {
+        return Err(Error::custom(
+            ErrorKind::NotFound,
+            format!("no industry members of {}, sync first", input_scheme),
+        ));
+    }
+    Ok(members)
+}
+
+fn prev_trade_day(dt: NaiveDate) -> Result<NaiveDate> {
+    LOCAL_DATES
+        .prev_day(dt)
+        .ok_or_else(|| Error::custom(ErrorKind::BadRequest, format!("no trade day before {}", dt)))
+}
+
+/// 已收盘且数据源已更新的最近交易日
+pub fn last_closed_day(now: NaiveDateTime) -> Option<NaiveDate> {
+    let today = now.date();
+    if LOCAL_DATES.contains_day(today)
+        && now.time() >= *AFTERNOON_END + Duration::minutes(CLOSE_DELAY_MINUTES)
+    {
+        return Some(today);
+    }
+    LOCAL_DATES.prev_day(today)
+}
+
+// 由前一交易日及当日的收盘价计算涨跌幅，任一缺失时不计入
+fn daily_returns(
+    closes: Vec<(String, NaiveDateTime, BigDecimal)>,
+    dt: NaiveDate,
+) -> HashMap<String, BigDecimal> {
+    let mut pairs: HashMap<String, (Option<BigDecimal>, Option<BigDecimal>)> = HashMap::new();
+    for (c, ts, close) in closes {
+        let e = pairs.entry(c).or_default();
+        if ts.date() == dt {
+            e.1 = Some(close);
+        } else {
+            e.0 = Some(close);
+        }
+    }
+    pairs
+        .into_iter()
+        .filter_map(|(c, pair)| match pair {
+            (Some(prev), Some(curr)) if !prev.is_zero() => {
+                Some((c, div_round(&(curr - &prev), &prev, METRIC_SCALE)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// 按行业汇总涨跌幅，返回行业列表及缺少涨跌幅的股票数
+fn aggregate(
+    members: &[IndustryStock],
+    returns: &HashMap<String, BigDecimal>,
+) -> (Vec<Sector>, usize) {
+    let mut industries: BTreeMap<&str, (&str, Vec<Mover>)> = BTreeMap::new();
+    let mut missing = BTreeSet::new();
+    for m in members {
+        let e = industries
+            .entry(&m.industry)
+            .or_insert_with(|| (&m.industry_name, Vec::new()));
+        match returns.get(&m.code) {
+            Some(r) => e.1.push(Mover {
+                code: m.code.clone(),
+                ret: r.clone(),
+            }),
+            None => {
+                missing.insert(&m.code);
+            }
+        }
+    }
+    let mut sectors: Vec<Sector> = industries
+        .into_iter()
+        .filter_map(|(industry, (name, mut movers))| {
+            let avg_return = sma(movers.iter().map(|m| &m.ret))?;
+            movers.sort_by(|a, b| b.ret.cmp(&a.ret).then_with(|| a.code.cmp(&b.code)));
+            let advancing = movers.iter().filter(|m| m.ret.is_positive()).count();
+            let declining = movers.iter().filter(|m| m.ret.is_negative()).count();
+            let top_gainers = movers
+                .iter()
+                .filter(|m| m.ret.is_positive())
+                .take(TOP_MOVERS)
+                .cloned()
+                .collect();
+            let top_losers = movers
+                .iter()
+                .rev()
+                .filter(|m| m.ret.is_negative())
+                .take(TOP_MOVERS)
+                .cloned()
+                .collect();
+            Some(Sector {
+                industry: industry.to_owned(),
+                name: name.to_owned(),
+                count: movers.len(),
+                advancing,
+                declining,
+                avg_return: round_half_even(&avg_return, METRIC_SCALE),
+                top_gainers,
+                top_losers,
+            })
+        })
+        .collect();
+    sectors.sort_by(|a, b| {
+        b.avg_return
+            .cmp(&a.avg_return)
+            .then_with(|| a.industry.cmp(&b.industry))
+    });
+    (sectors, missing.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tanglism_utils::{parse_price, price};
+
+    fn member(industry: &str, code: &str) -> IndustryStock {
+        IndustryStock {
+            scheme: DEFAULT_SCHEME.to_owned(),
+            industry: industry.to_owned(),
+            code: code.to_owned(),
+            industry_name: format!("{}-name", industry),
+            synced_dt: NaiveDate::from_ymd_opt(2020, 8, 7).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let dt = NaiveDate::from_ymd_opt(2020, 8, 7).unwrap();
+        let prev = NaiveDate::from_ymd_opt(2020, 8, 6).unwrap();
+        let close = |d: NaiveDate, c: &str, p: &str| {
+            (
+                c.to_owned(),
+                NaiveDateTime::new(d, *AFTERNOON_END),
+                parse_price(p).unwrap(),
+            )
+        };
+        let returns = daily_returns(
+            vec![
+                close(prev, "a", "10"),
+                close(dt, "a", "11"),
+                close(prev, "b", "10"),
+                close(dt, "b", "9.5"),
+                close(prev, "c", "10"),
+                close(dt, "c", "10"),
+                close(prev, "d", "10"),
+                close(prev, "e", "20"),
+                close(dt, "e", "21"),
+            ],
+            dt,
+        );
+        // d缺少当日收盘价
+        assert_eq!(4, returns.len());
+        let members = vec![
+            member("801010", "a"),
+            member("801010", "b"),
+            member("801010", "c"),
+            member("801010", "d"),
+            member("801020", "e"),
+            member("801030", "f"),
+        ];
+        let (sectors, missing) = aggregate(&members, &returns);
+        assert_eq!(2, missing);
+        assert_eq!(2, sectors.len());
+        assert_eq!("801020", sectors[0].industry);
+        assert_eq!(price!(0.05), sectors[0].avg_return);
+        let s = &sectors[1];
+        assert_eq!("801010-name", s.name);
+        assert_eq!((3, 1, 1), (s.count, s.advancing, s.declining));
+        assert_eq!(price!(0.016667), s.avg_return);
+        assert_eq!("a", s.top_gainers[0].code);
+        assert_eq!("b", s.top_losers[0].code);
+        assert_eq!(1, s.top_losers.len());
+    }
+}
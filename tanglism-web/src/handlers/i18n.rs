@@ -0,0 +1,240 @@
+//! 响应文本的本地化
+//!
+//! 语言由查询参数lang或Accept-Language头部指定，未指定时保持原有输出：
+//! 错误交由warp默认处理，枚举仅输出变体名。
+//! 指定语言后，错误以JSON返回并附带本地化的错误类别，
+//! 买卖点、次级别走势类型及方向等枚举字段旁追加以_label结尾的本地化名称。
+
+use crate::{Error, ErrorKind, Result};
+use serde_derive::*;
+use serde_json::Value;
+use std::str::FromStr;
+use warp::http::StatusCode;
+
+// 追加本地化名称的枚举字段
+const LABELED_FIELDS: [&str; 5] = ["choice", "typ", "direction", "trend_before", "trend_after"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lang {
+    Zh,
+    En,
+}
+
+impl FromStr for Lang {
+    type Err = Error;
+
+    // 忽略地区，如zh-CN及en-US
+    fn from_str(s: &str) -> Result<Self> {
+        let primary = s.trim().split(['-', '_']).next().unwrap_or_default();
+        match primary.to_ascii_lowercase().as_str() {
+            "zh" => Ok(Lang::Zh),
+            "en" => Ok(Lang::En),
+            _ => Err(Error::custom(
+                ErrorKind::BadRequest,
+                format!("unsupported language: {}", s),
+            )),
+        }
+    }
+}
+
+/// 解析Accept-Language头部，取权重最高的已支持语言
+pub fn parse_accept_language(s: &str) -> Option<Lang> {
+    let mut best: Option<(Lang, f32)> = None;
+    for item in s.split(',') {
+        let mut parts = item.split(';');
+        let lang = match parts.next().and_then(|t| t.parse::<Lang>().ok()) {
+            Some(lang) => lang,
+            None => continue,
+        };
+        let q = parts
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if best.map(|(_, bq)| q > bq).unwrap_or(q > 0.0) {
+            best = Some((lang, q));
+        }
+    }
+    best.map(|(lang, _)| lang)
+}
+
+/// 错误类别的本地化名称
+pub fn error_kind_label(kind: ErrorKind, lang: Lang) -> &'static str {
+    match (kind, lang) {
+        (ErrorKind::BadRequest, Lang::Zh) => "请求无效",
+        (ErrorKind::BadRequest, Lang::En) => "Bad request",
+        (ErrorKind::Unauthorized, Lang::Zh) => "未授权",
+        (ErrorKind::Unauthorized, Lang::En) => "Unauthorized",
+        (ErrorKind::NotFound, Lang::Zh) => "未找到",
+        (ErrorKind::NotFound, Lang::En) => "Not found",
+        (ErrorKind::InternalServerError, Lang::Zh) => "服务器内部错误",
+        (ErrorKind::InternalServerError, Lang::En) => "Internal server error",
+        (ErrorKind::IO, Lang::Zh) => "读写错误",
+        (ErrorKind::IO, Lang::En) => "I/O error",
+        (ErrorKind::Diesel, Lang::Zh) => "数据库错误",
+        (ErrorKind::Diesel, Lang::En) => "Database error",
+        (ErrorKind::Jqdata, Lang::Zh) => "数据源错误",
+        (ErrorKind::Jqdata, Lang::En) => "Data source error",
+        (ErrorKind::DbConn, Lang::Zh) => "数据库连接错误",
+        (ErrorKind::DbConn, Lang::En) => "Database connection error",
+        (ErrorKind::Timeout, Lang::Zh) => "超时",
+        (ErrorKind::Timeout, Lang::En) => "Timeout",
+    }
+}
+
+/// 枚举变体的本地化名称，未收录的变体返回None
+pub fn enum_label(variant: &str, lang: Lang) -> Option<&'static str> {
+    let label = match (variant, lang) {
+        // 买卖点
+        ("BuyOne", Lang::Zh) => "一买",
+        ("BuyOne", Lang::En) => "Buy 1",
+        ("BuyTwo", Lang::Zh) => "二买",
+        ("BuyTwo", Lang::En) => "Buy 2",
+        ("BuyThree", Lang::Zh) => "三买",
+        ("BuyThree", Lang::En) => "Buy 3",
+        ("SellOne", Lang::Zh) => "一卖",
+        ("SellOne", Lang::En) => "Sell 1",
+        ("SellTwo", Lang::Zh) => "二卖",
+        ("SellTwo", Lang::En) => "Sell 2",
+        ("SellThree", Lang::Zh) => "三卖",
+        ("SellThree", Lang::En) => "Sell 3",
+        ("Custom", Lang::Zh) => "自定义规则",
+        ("Custom", Lang::En) => "Custom rule",
+        // 次级别走势类型
+        ("Normal", Lang::Zh) => "普通",
+        ("Normal", Lang::En) => "Normal",
+        ("Gap", Lang::Zh) => "缺口",
+        ("Gap", Lang::En) => "Gap",
+        ("Divider", Lang::Zh) => "分隔",
+        ("Divider", Lang::En) => "Divider",
+        ("Combination", Lang::Zh) => "组合",
+        ("Combination", Lang::En) => "Combination",
+        ("Derived", Lang::Zh) => "升级",
+        ("Derived", Lang::En) => "Derived",
+        // 方向
+        ("up", Lang::Zh) => "向上",
+        ("up", Lang::En) => "Up",
+        ("down", Lang::Zh) => "向下",
+        ("down", Lang::En) => "Down",
+        _ => return None,
+    };
+    Some(label)
+}
+
+/// 本地化的错误信息，未指定语言时与错误的Display一致
+///
+/// 错误描述本身不翻译，仅替换错误类别
+pub fn error_message(err: &Error, lang: Option<Lang>) -> String {
+    let lang = match lang {
+        Some(lang) => lang,
+        None => return err.to_string(),
+    };
+    match err.message() {
+        s if s.is_empty() => error_kind_label(err.kind(), lang).to_owned(),
+        s => format!("{}: {}", error_kind_label(err.kind(), lang), s),
+    }
+}
+
+/// 错误响应
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub kind: String,
+    pub label: String,
+    pub message: String,
+}
+
+/// 指定语言时将本模块的错误转换为JSON响应，其他拒绝原样返回
+pub async fn localize_rejection(
+    lang: Option<Lang>,
+    rej: warp::Rejection,
+) -> std::result::Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let (lang, err) = match (lang, rej.find::<Error>()) {
+        (Some(lang), Some(err)) => (lang, err),
+        _ => return Err(rej),
+    };
+    let (kind, message) = (err.kind(), err.message());
+    let body = ErrorBody {
+        kind: kind.to_string(),
+        label: error_kind_label(kind, lang).to_owned(),
+        message,
+    };
+    Ok(Box::new(warp::reply::with_status(
+        warp::reply::json(&body),
+        status_of(kind),
+    )))
+}
+
+fn status_of(kind: ErrorKind) -> StatusCode {
+    match kind {
+        ErrorKind::BadRequest => StatusCode::BAD_REQUEST,
+        ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+        ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        ErrorKind::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// 在已收录的枚举字段旁追加本地化名称，如choice旁追加choice_label
+pub fn localize(v: &mut Value, lang: Lang) {
+    match v {
+        Value::Object(m) => {
+            m.values_mut().for_each(|v| localize(v, lang));
+            for k in &LABELED_FIELDS {
+                let label = match m.get(*k) {
+                    Some(Value::String(s)) => enum_label(s, lang),
+                    _ => None,
+                };
+                if let Some(label) = label {
+                    m.insert(format!("{}_label", k), Value::String(label.to_owned()));
+                }
+            }
+        }
+        Value::Array(vs) => vs.iter_mut().for_each(|v| localize(v, lang)),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lang() {
+        assert_eq!(Lang::En, "en-US".parse::<Lang>().unwrap());
+        assert!("fr".parse::<Lang>().is_err());
+        assert_eq!(
+            Some(Lang::En),
+            parse_accept_language("fr-CH, fr;q=0.9, en;q=0.8, zh-CN;q=0.7")
+        );
+        assert_eq!(
+            Some(Lang::Zh),
+            parse_accept_language("zh-CN,zh;q=0.9,en;q=0.8")
+        );
+        assert_eq!(None, parse_accept_language("de, *;q=0.5"));
+
+        let err = Error::custom(ErrorKind::NotFound, "no data".to_owned());
+        assert_eq!("NotFound: no data", error_message(&err, None));
+        assert_eq!("未找到: no data", error_message(&err, Some(Lang::Zh)));
+    }
+
+    #[test]
+    fn test_localize() {
+        let mut v = serde_json::json!([
+            {"code": "600000.XSHG", "choice": "BuyOne"},
+            {"subtrends": [{"level": 1, "typ": "Gap"}], "trend_after": "down", "typ": "Unknown"},
+        ]);
+        localize(&mut v, Lang::En);
+        assert_eq!(
+            serde_json::json!([
+                {"code": "600000.XSHG", "choice": "BuyOne", "choice_label": "Buy 1"},
+                {
+                    "subtrends": [{"level": 1, "typ": "Gap", "typ_label": "Gap"}],
+                    "trend_after": "down",
+                    "trend_after_label": "Down",
+                    "typ": "Unknown",
+                },
+            ]),
+            v
+        );
+    }
+}
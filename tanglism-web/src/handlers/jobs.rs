@@ -0,0 +1,768 @@
+//! 后台任务队列
+//!
+//! 耗时的任务提交后连同参数持久化至jobs表，由worker按提交顺序逐个执行，
+//! 执行中更新进度，结果以JSON保存，可通过REST查询，或在websocket中订阅进度推送。
+//! 服务重启时，中断的任务重新排队。
+//! 定时任务（周报、热力图）同样由worker按间隔检查后提交至队列执行。
+
+use super::shape_stats::{self, StatsParam};
+use super::warm::{self, WarmParam};
+use super::{heatmap, reports};
+use crate::models::Job;
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result};
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use futures::future::{abortable, AbortHandle, Aborted};
+use lazy_static::*;
+use serde_derive::*;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, Notify};
+
+// 无新任务时的轮询间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+// 进度事件的缓冲数，订阅方处理过慢时丢弃旧事件
+const EVENT_CAPACITY: usize = 256;
+// 列表默认及最多返回的任务数
+const DEFAULT_LIST_LIMIT: i64 = 50;
+const MAX_LIST_LIMIT: i64 = 500;
+
+lazy_static! {
+    static ref JOB_EVENTS: broadcast::Sender<JobEvent> = broadcast::channel(EVENT_CAPACITY).0;
+    static ref JOB_SUBMITTED: Notify = Notify::new();
+    // 执行中的任务，用于取消
+    static ref RUNNING: Mutex<HashMap<i32, AbortHandle>> = Mutex::new(HashMap::new());
+}
+
+/// 任务类型及参数
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "params", rename_all = "snake_case")]
+pub enum JobSpec {
+    // 生成周报，未指定week时取最近一个完整的周
+    Report {
+        codes: Vec<String>,
+        #[serde(default)]
+        week: Option<NaiveDate>,
+        #[serde(default)]
+        format: Option<reports::ReportFormat>,
+    },
+    // 生成热力图，未指定dt时取最近一个已收盘的交易日
+    Heatmap {
+        scheme: String,
+        #[serde(default)]
+        dt: Option<NaiveDate>,
+    },
+    // 形态统计
+    ShapeStats {
+        code: String,
+        param: StatsParam,
+    },
+    // 预热价格及指标缓存
+    Warm(WarmParam),
+}
+
+impl JobSpec {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            JobSpec::Report { .. } => "report",
+            JobSpec::Heatmap { .. } => "heatmap",
+            JobSpec::ShapeStats { .. } => "shape_stats",
+            JobSpec::Warm(_) => "warm",
+        }
+    }
+
+    /// 仅可经管理接口提交及取消的任务
+    pub fn admin_only(&self) -> bool {
+        matches!(self, JobSpec::Warm(_))
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self {
+            JobSpec::Report { codes, .. } if codes.is_empty() => Err(Error::custom(
+                ErrorKind::BadRequest,
+                "report job requires at least one code".to_owned(),
+            )),
+            JobSpec::Heatmap { scheme, .. } if scheme.is_empty() => Err(Error::custom(
+                ErrorKind::BadRequest,
+                "heatmap job requires scheme".to_owned(),
+            )),
+            JobSpec::Warm(param) => param.validate(),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 公开接口提交的任务，反序列化时拒绝仅限管理接口的任务类型
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "JobSpec")]
+pub struct PublicJobSpec(JobSpec);
+
+impl TryFrom<JobSpec> for PublicJobSpec {
+    type Error = String;
+
+    fn try_from(spec: JobSpec) -> std::result::Result<Self, String> {
+        if spec.admin_only() {
+            return Err(format!("job kind {} requires admin", spec.kind()));
+        }
+        Ok(PublicJobSpec(spec))
+    }
+}
+
+impl From<PublicJobSpec> for JobSpec {
+    fn from(spec: PublicJobSpec) -> Self {
+        spec.0
+    }
+}
+
+/// 定时提交的任务
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobSchedule {
+    // 每周收盘后为自选股生成一份报告，已生成的周不再重复生成
+    WeeklyReport {
+        codes: Vec<String>,
+        format: reports::ReportFormat,
+    },
+    // 每个交易日收盘后生成热力图，已生成的交易日不再重复生成
+    DailyHeatmap {
+        scheme: String,
+    },
+}
+
+impl JobSchedule {
+    fn interval(&self) -> Duration {
+        match self {
+            JobSchedule::WeeklyReport { .. } => {
+                Duration::from_secs(reports::REPORT_CHECK_INTERVAL_SECS)
+            }
+            JobSchedule::DailyHeatmap { .. } => {
+                Duration::from_secs(heatmap::HEATMAP_CHECK_INTERVAL_SECS)
+            }
+        }
+    }
+
+    // 给定时刻应提交的任务
+    fn spec_at(&self, now: NaiveDateTime) -> Option<JobSpec> {
+        match self {
+            JobSchedule::WeeklyReport { codes, format } => Some(JobSpec::Report {
+                codes: codes.clone(),
+                week: Some(reports::last_complete_week(now.date()).0),
+                format: Some(*format),
+            }),
+            JobSchedule::DailyHeatmap { scheme } => {
+                heatmap::last_closed_day(now).map(|dt| JobSpec::Heatmap {
+                    scheme: scheme.clone(),
+                    dt: Some(dt),
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Pending => "pending",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_finished(self) -> bool {
+        !matches!(self, JobStatus::Pending | JobStatus::Running)
+    }
+}
+
+impl FromStr for JobStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pending" => Ok(JobStatus::Pending),
+            "running" => Ok(JobStatus::Running),
+            "succeeded" => Ok(JobStatus::Succeeded),
+            "failed" => Ok(JobStatus::Failed),
+            "cancelled" => Ok(JobStatus::Cancelled),
+            _ => Err(Error::custom(
+                ErrorKind::BadRequest,
+                format!("invalid job status: {}", s),
+            )),
+        }
+    }
+}
+
+/// 任务状态，参数及结果已解析为JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobInfo {
+    pub id: i32,
+    #[serde(flatten)]
+    pub spec: JobSpec,
+    pub status: JobStatus,
+    pub progress: i32,
+    pub message: String,
+    pub result: Option<serde_json::Value>,
+    pub created_at: chrono::NaiveDateTime,
+    pub started_at: Option<chrono::NaiveDateTime>,
+    pub finished_at: Option<chrono::NaiveDateTime>,
+}
+
+impl JobInfo {
+    fn from_job(job: Job) -> Result<Self> {
+        let corrupted =
+            |e: serde_json::Error| Error::custom(ErrorKind::InternalServerError, e.to_string());
+        Ok(JobInfo {
+            id: job.id,
+            spec: serde_json::from_str(&job.params).map_err(corrupted)?,
+            status: job.status.parse()?,
+            progress: job.progress,
+            message: job.message,
+            result: match job.result {
+                Some(ref r) => Some(serde_json::from_str(r).map_err(corrupted)?),
+                None => None,
+            },
+            created_at: job.created_at,
+            started_at: job.started_at,
+            finished_at: job.finished_at,
+        })
+    }
+}
+
+/// 任务进度事件，向websocket订阅方推送
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobEvent {
+    pub id: i32,
+    pub status: JobStatus,
+    pub progress: i32,
+    pub message: String,
+}
+
+/// 订阅全部任务的进度事件
+pub fn subscribe() -> broadcast::Receiver<JobEvent> {
+    JOB_EVENTS.subscribe()
+}
+
+fn publish(event: JobEvent) {
+    // 无订阅方时发送失败，忽略即可
+    let _ = JOB_EVENTS.send(event);
+}
+
+/// 任务查询条件，均为可选
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JobQuery {
+    pub status: Option<JobStatus>,
+    pub kind: Option<String>,
+    pub limit: Option<i64>,
+}
+
+pub async fn submit_job(pool: DbPool, spec: JobSpec) -> Result<JobInfo> {
+    spec.validate()?;
+    let input_params = serde_json::to_string(&spec)
+        .map_err(|e| Error::custom(ErrorKind::InternalServerError, e.to_string()))?;
+    let job = tokio::task::spawn_blocking(move || {
+        use crate::schema::jobs::dsl::*;
+        let conn = pool.get()?;
+        diesel::insert_into(jobs)
+            .values((
+                kind.eq(spec.kind()),
+                params.eq(input_params),
+                status.eq(JobStatus::Pending.as_str()),
+                created_at.eq(Local::now().naive_local()),
+            ))
+            .get_result::<Job>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    let info = JobInfo::from_job(job)?;
+    JOB_SUBMITTED.notify();
+    publish(event_of(&info));
+    Ok(info)
+}
+
+pub async fn get_job(pool: DbPool, job_id: i32) -> Result<JobInfo> {
+    let job = tokio::task::spawn_blocking(move || {
+        use crate::schema::jobs::dsl::*;
+        let conn = pool.get()?;
+        jobs.find(job_id)
+            .first::<Job>(&conn)
+            .optional()
+            .map_err(Error::from)
+    })
+    .await??;
+    match job {
+        Some(job) => JobInfo::from_job(job),
+        None => Err(job_not_found(job_id)),
+    }
+}
+
+/// 按提交时间倒序列出任务
+pub async fn list_jobs(pool: DbPool, q: JobQuery) -> Result<Vec<JobInfo>> {
+    let limit = q
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .clamp(1, MAX_LIST_LIMIT);
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::jobs::dsl::*;
+        let conn = pool.get()?;
+        let mut query = jobs.into_boxed();
+        if let Some(s) = q.status {
+            query = query.filter(status.eq(s.as_str()));
+        }
+        if let Some(k) = q.kind {
+            query = query.filter(kind.eq(k));
+        }
+        query
+            .order(id.desc())
+            .limit(limit)
+            .load::<Job>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    data.into_iter().map(JobInfo::from_job).collect()
+}
+
+/// 取消任务，排队中的任务直接取消，执行中的任务被中止，已结束的任务不可取消
+///
+/// 仅限管理接口的任务须由管理接口取消，admin为false时拒绝
+pub async fn cancel_job(pool: DbPool, job_id: i32, admin: bool) -> Result<JobInfo> {
+    let info = get_job(pool.clone(), job_id).await?;
+    if !admin && info.spec.admin_only() {
+        return Err(Error::custom(
+            ErrorKind::Unauthorized,
+            format!("job {} requires admin to cancel", job_id),
+        ));
+    }
+    if info.status.is_finished() {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            format!("job {} already {}", job_id, info.status.as_str()),
+        ));
+    }
+    if let Some(handle) = RUNNING.lock().unwrap().remove(&job_id) {
+        handle.abort();
+    }
+    finish_job(
+        pool.clone(),
+        job_id,
+        JobStatus::Cancelled,
+        "cancelled".to_owned(),
+        None,
+    )
+    .await?;
+    get_job(pool, job_id).await
+}
+
+/// 任务执行上下文，用于更新进度
+pub struct JobContext {
+    pool: DbPool,
+    id: i32,
+}
+
+impl JobContext {
+    pub async fn progress(&self, pct: i32, note: impl Into<String>) -> Result<()> {
+        let job_id = self.id;
+        let progress_value = pct.clamp(0, 100);
+        let msg = note.into();
+        let pool = self.pool.clone();
+        let m = msg.clone();
+        tokio::task::spawn_blocking(move || {
+            use crate::schema::jobs::dsl::*;
+            let conn = pool.get()?;
+            diesel::update(
+                jobs.find(job_id)
+                    .filter(status.eq(JobStatus::Running.as_str())),
+            )
+            .set((progress.eq(progress_value), message.eq(m)))
+            .execute(&conn)
+            .map_err(Error::from)
+        })
+        .await??;
+        publish(JobEvent {
+            id: job_id,
+            status: JobStatus::Running,
+            progress: progress_value,
+            message: msg,
+        });
+        Ok(())
+    }
+}
+
+/// 常驻任务：逐个执行排队的任务，并按间隔提交到期的定时任务
+pub async fn run_job_worker(pool: DbPool, jq: JqdataPool, schedules: Vec<JobSchedule>) {
+    match requeue_interrupted(pool.clone()).await {
+        Ok(n) if n > 0 => log::info!("{} interrupted jobs requeued", n),
+        Ok(_) => (),
+        Err(e) => log::warn!("failed to requeue interrupted jobs: {}", e),
+    }
+    let mut next_checks = vec![Instant::now(); schedules.len()];
+    loop {
+        let now = Instant::now();
+        for (schedule, next_check) in schedules.iter().zip(next_checks.iter_mut()) {
+            if *next_check > now {
+                continue;
+            }
+            *next_check = now + schedule.interval();
+            match submit_scheduled(pool.clone(), schedule).await {
+                Ok(Some(info)) => {
+                    log::info!("scheduled {} job {} submitted", info.spec.kind(), info.id)
+                }
+                Ok(None) => (),
+                Err(e) => log::warn!("failed to submit scheduled job: {}", e),
+            }
+        }
+        let job = match claim_next(pool.clone()).await {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                let _ = tokio::time::timeout(POLL_INTERVAL, JOB_SUBMITTED.notified()).await;
+                continue;
+            }
+            Err(e) => {
+                log::warn!("failed to claim job: {}", e);
+                tokio::time::delay_for(POLL_INTERVAL).await;
+                continue;
+            }
+        };
+        let job_id = job.id;
+        publish(event_of(&job));
+        let ctx = JobContext {
+            pool: pool.clone(),
+            id: job_id,
+        };
+        let (fut, handle) = abortable(execute(ctx, jq.clone(), job.spec));
+        RUNNING.lock().unwrap().insert(job_id, handle);
+        let rst = fut.await;
+        RUNNING.lock().unwrap().remove(&job_id);
+        let (status, message, result) = match rst {
+            Ok(Ok(v)) => (JobStatus::Succeeded, String::new(), Some(v)),
+            Ok(Err(e)) => (JobStatus::Failed, e.to_string(), None),
+            // 取消时状态已更新
+            Err(Aborted) => continue,
+        };
+        log::info!("job {} {}", job_id, status.as_str());
+        if let Err(e) = finish_job(pool.clone(), job_id, status, message, result).await {
+            log::warn!("failed to save result of job {}: {}", job_id, e);
+        }
+    }
+}
+
+async fn execute(ctx: JobContext, jq: JqdataPool, spec: JobSpec) -> Result<serde_json::Value> {
+    let value = match spec {
+        JobSpec::Report {
+            codes,
+            week,
+            format,
+        } => {
+            let week_start = match week {
+                Some(dt) => dt,
+                None => reports::last_complete_week(Local::now().naive_local().date()).0,
+            };
+            ctx.progress(0, format!("analyzing {} stocks", codes.len()))
+                .await?;
+            let format = format.unwrap_or(reports::ReportFormat::Markdown);
+            let report =
+                reports::generate_weekly_report(&ctx.pool, &jq, &codes, week_start, format).await?;
+            to_value(&reports::ReportInfo {
+                id: report.id,
+                week_start: report.week_start,
+                week_end: report.week_end,
+                format: report.format,
+                codes: report.codes,
+                created_at: report.created_at,
+            })?
+        }
+        JobSpec::Heatmap { scheme, dt } => {
+            let dt = match dt {
+                Some(dt) => dt,
+                None => heatmap::last_closed_day(Local::now().naive_local()).ok_or_else(|| {
+                    Error::custom(ErrorKind::NotFound, "no closed trade day".to_owned())
+                })?,
+            };
+            ctx.progress(0, format!("refreshing heatmap of {} on {}", scheme, dt))
+                .await?;
+            let hm = heatmap::refresh_heatmap(&ctx.pool, &jq, &scheme, dt).await?;
+            to_value(&hm)?
+        }
+        JobSpec::ShapeStats { code, param } => {
+            ctx.progress(0, format!("analyzing {}", code)).await?;
+            let stats = shape_stats::get_shape_stats(&ctx.pool, &jq, &code, param).await?;
+            to_value(&stats)?
+        }
+        JobSpec::Warm(param) => {
+            let now = Local::now().naive_local();
+            let start_ts = warm::window_start(now, param.days);
+            let total = param.codes.len() * param.ticks.len();
+            let mut summary = warm::WarmSummary::default();
+            for code in &param.codes {
+                for tick in &param.ticks {
+                    let item = warm::warm_item(&ctx.pool, &jq, code, *tick, start_ts, now).await;
+                    let note = format!("{} {} warmed", item.code, item.tick);
+                    summary.push(item);
+                    let pct = (summary.items.len() * 100 / total) as i32;
+                    ctx.progress(pct, note).await?;
+                }
+            }
+            to_value(&summary)?
+        }
+    };
+    Ok(value)
+}
+
+// 提交到期的定时任务，产出已存在或同一任务尚未结束时不重复提交
+async fn submit_scheduled(pool: DbPool, schedule: &JobSchedule) -> Result<Option<JobInfo>> {
+    let spec = match schedule.spec_at(Local::now().naive_local()) {
+        Some(spec) => spec,
+        None => return Ok(None),
+    };
+    if output_exists(pool.clone(), &spec).await? || has_unfinished(pool.clone(), &spec).await? {
+        return Ok(None);
+    }
+    submit_job(pool, spec).await.map(Some)
+}
+
+// 任务的产出是否已保存
+async fn output_exists(pool: DbPool, spec: &JobSpec) -> Result<bool> {
+    match spec {
+        JobSpec::Report {
+            week: Some(week),
+            format,
+            ..
+        } => {
+            let format = format.unwrap_or(reports::ReportFormat::Markdown);
+            reports::report_exists(pool, *week, format.as_str()).await
+        }
+        JobSpec::Heatmap {
+            scheme,
+            dt: Some(dt),
+        } => heatmap::heatmap_exists(pool, scheme.clone(), *dt).await,
+        _ => Ok(false),
+    }
+}
+
+// 是否存在参数相同且未结束的任务
+async fn has_unfinished(pool: DbPool, spec: &JobSpec) -> Result<bool> {
+    let input_kind = spec.kind();
+    let input_params = serde_json::to_string(spec)
+        .map_err(|e| Error::custom(ErrorKind::InternalServerError, e.to_string()))?;
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::jobs::dsl::*;
+        let conn = pool.get()?;
+        jobs.filter(kind.eq(input_kind))
+            .filter(params.eq(input_params))
+            .filter(status.eq_any(vec![
+                JobStatus::Pending.as_str(),
+                JobStatus::Running.as_str(),
+            ]))
+            .count()
+            .get_result::<i64>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(n > 0)
+}
+
+// 将上次退出时执行中的任务重新排队
+async fn requeue_interrupted(pool: DbPool) -> Result<usize> {
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::jobs::dsl::*;
+        let conn = pool.get()?;
+        diesel::update(jobs.filter(status.eq(JobStatus::Running.as_str())))
+            .set((
+                status.eq(JobStatus::Pending.as_str()),
+                progress.eq(0),
+                message.eq("requeued"),
+            ))
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(n)
+}
+
+// 按提交顺序领取下一个排队的任务
+async fn claim_next(pool: DbPool) -> Result<Option<JobInfo>> {
+    let job = tokio::task::spawn_blocking(move || {
+        use crate::schema::jobs::dsl::*;
+        let conn = pool.get()?;
+        conn.transaction::<_, Error, _>(|| {
+            let next = jobs
+                .select(id)
+                .filter(status.eq(JobStatus::Pending.as_str()))
+                .order(id.asc())
+                .first::<i32>(&conn)
+                .optional()?;
+            match next {
+                Some(job_id) => diesel::update(
+                    jobs.find(job_id)
+                        .filter(status.eq(JobStatus::Pending.as_str())),
+                )
+                .set((
+                    status.eq(JobStatus::Running.as_str()),
+                    started_at.eq(Local::now().naive_local()),
+                ))
+                .get_result::<Job>(&conn)
+                .optional()
+                .map_err(Error::from),
+                None => Ok(None),
+            }
+        })
+    })
+    .await??;
+    job.map(JobInfo::from_job).transpose()
+}
+
+// 保存结果，已结束的任务不再更新
+async fn finish_job(
+    pool: DbPool,
+    job_id: i32,
+    new_status: JobStatus,
+    msg: String,
+    value: Option<serde_json::Value>,
+) -> Result<()> {
+    let m = msg.clone();
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::jobs::dsl::*;
+        let conn = pool.get()?;
+        diesel::update(jobs.find(job_id).filter(status.eq_any(vec![
+            JobStatus::Pending.as_str(),
+            JobStatus::Running.as_str(),
+        ])))
+        .set((
+            status.eq(new_status.as_str()),
+            progress.eq(if new_status == JobStatus::Succeeded {
+                100
+            } else {
+                0
+            }),
+            message.eq(m),
+            result.eq(value.map(|v| v.to_string())),
+            finished_at.eq(Local::now().naive_local()),
+        ))
+        .execute(&conn)
+        .map_err(Error::from)
+    })
+    .await??;
+    if n > 0 {
+        publish(JobEvent {
+            id: job_id,
+            status: new_status,
+            progress: if new_status == JobStatus::Succeeded {
+                100
+            } else {
+                0
+            },
+            message: msg,
+        });
+    }
+    Ok(())
+}
+
+fn event_of(info: &JobInfo) -> JobEvent {
+    JobEvent {
+        id: info.id,
+        status: info.status,
+        progress: info.progress,
+        message: info.message.clone(),
+    }
+}
+
+fn to_value<T: serde::Serialize>(data: &T) -> Result<serde_json::Value> {
+    serde_json::to_value(data)
+        .map_err(|e| Error::custom(ErrorKind::InternalServerError, e.to_string()))
+}
+
+fn job_not_found(job_id: i32) -> Error {
+    Error::custom(ErrorKind::NotFound, format!("job {} not found", job_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_spec_serde() {
+        let spec: JobSpec = serde_json::from_str(
+            r#"{"kind":"heatmap","params":{"scheme":"sw_l1","dt":"2020-08-07"}}"#,
+        )
+        .unwrap();
+        assert_eq!("heatmap", spec.kind());
+        assert_eq!(
+            JobSpec::Heatmap {
+                scheme: "sw_l1".to_owned(),
+                dt: NaiveDate::from_ymd_opt(2020, 8, 7),
+            },
+            spec
+        );
+        let spec: JobSpec =
+            serde_json::from_str(r#"{"kind":"report","params":{"codes":[]}}"#).unwrap();
+        assert!(spec.validate().is_err());
+        assert_eq!(
+            Ok(JobStatus::Cancelled),
+            "cancelled".parse().map_err(|_| ())
+        );
+        assert!(JobStatus::Failed.is_finished());
+        assert!(!JobStatus::Running.is_finished());
+    }
+
+    #[test]
+    fn test_public_job_spec() {
+        let spec: PublicJobSpec = serde_json::from_str(
+            r#"{"kind":"heatmap","params":{"scheme":"sw_l1","dt":"2020-08-07"}}"#,
+        )
+        .unwrap();
+        assert_eq!("heatmap", JobSpec::from(spec).kind());
+        // 预热任务仅可经管理接口提交
+        let warm = r#"{"kind":"warm","params":{"codes":["600000.XSHG"],"ticks":["1d"],"days":30}}"#;
+        let spec: JobSpec = serde_json::from_str(warm).unwrap();
+        assert!(spec.admin_only());
+        assert!(serde_json::from_str::<PublicJobSpec>(warm).is_err());
+    }
+
+    #[test]
+    fn test_schedule_spec() {
+        let schedule = JobSchedule::WeeklyReport {
+            codes: vec!["600000.XSHG".to_owned()],
+            format: reports::ReportFormat::Html,
+        };
+        // 2020-08-05为周三，提交上一周的周报
+        let now = NaiveDate::from_ymd_opt(2020, 8, 5)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        assert_eq!(
+            Some(JobSpec::Report {
+                codes: vec!["600000.XSHG".to_owned()],
+                week: NaiveDate::from_ymd_opt(2020, 7, 27),
+                format: Some(reports::ReportFormat::Html),
+            }),
+            schedule.spec_at(now)
+        );
+        // 周六生成周五的热力图
+        let schedule = JobSchedule::DailyHeatmap {
+            scheme: "sw_l1".to_owned(),
+        };
+        let now = NaiveDate::from_ymd_opt(2020, 8, 8)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        assert_eq!(
+            Some(JobSpec::Heatmap {
+                scheme: "sw_l1".to_owned(),
+                dt: NaiveDate::from_ymd_opt(2020, 8, 7),
+            }),
+            schedule.spec_at(now)
+        );
+    }
+}
@@ -1,18 +1,24 @@
 pub mod atr;
+pub mod basis;
 mod ema;
 mod ma;
+pub mod macd;
+pub mod math;
+pub mod northbound;
+pub mod store;
+pub mod vwap;
 
-use super::stock_prices::get_stock_tick_prices;
+use super::stock_prices::{get_stock_tick_prices, ticks};
 use crate::models::StockTickPrice;
 use crate::BasicCfg;
-use crate::{DbPool, Error, ErrorKind, Result};
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result};
 use bigdecimal::BigDecimal;
-use chrono::{NaiveDate, NaiveDateTime};
+use chrono::{Local, NaiveDate, NaiveDateTime};
 use ema::approximate_macd;
-use jqdata::JqdataClient;
+use macd::MacdCfgSource;
 use serde_derive::*;
 use std::collections::HashMap;
-use tanglism_utils::{TradingDates, LOCAL_DATES};
+use tanglism_utils::{Tick, TradingDates, AFTERNOON_END, LOCAL_DATES};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Response<T> {
@@ -54,11 +60,14 @@ pub struct MultiMetric {
     pub values: Vec<BigDecimal>,
 }
 
+/// MACD指标，附带生效的参数及其来源
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MacdMetric {
     pub fast_ema_period: u32,
     pub slow_ema_period: u32,
     pub dea_period: u32,
+    #[serde(default)]
+    pub cfg_source: MacdCfgSource,
     pub dif: Vec<Metric>,
     pub dea: Vec<Metric>,
     pub macd: Vec<Metric>,
@@ -66,10 +75,12 @@ pub struct MacdMetric {
 
 impl Default for MacdMetric {
     fn default() -> Self {
+        let cfg = MacdCfg::default();
         MacdMetric {
-            fast_ema_period: 12,
-            slow_ema_period: 26,
-            dea_period: 9,
+            fast_ema_period: cfg.fast_ema_period,
+            slow_ema_period: cfg.slow_ema_period,
+            dea_period: cfg.dea_period,
+            cfg_source: MacdCfgSource::default(),
             dif: Vec::new(),
             dea: Vec::new(),
             macd: Vec::new(),
@@ -77,7 +88,7 @@ impl Default for MacdMetric {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct MacdCfg {
     fast_ema_period: u32,
     slow_ema_period: u32,
@@ -94,30 +105,85 @@ impl Default for MacdCfg {
     }
 }
 
+impl MacdCfg {
+    /// 周期的默认参数，1分钟K线噪音较大，周期加倍
+    pub fn for_tick(tick: Tick) -> Self {
+        match tick {
+            Tick::M1 => MacdCfg {
+                fast_ema_period: 24,
+                slow_ema_period: 52,
+                dea_period: 18,
+            },
+            _ => MacdCfg::default(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.fast_ema_period == 0
+            || self.slow_ema_period < self.fast_ema_period
+            || self.slow_ema_period < self.dea_period
+        {
+            return Err(Error::custom(
+                ErrorKind::BadRequest,
+                format!(
+                    "invalid setting: slow ema {} is no less than fast ema {} or dea {}",
+                    self.slow_ema_period, self.fast_ema_period, self.dea_period
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
 pub async fn get_metrics_macd(
     db: &DbPool,
-    jq: &JqdataClient,
+    jq: &JqdataPool,
+    basic_cfg: BasicCfg,
+    macd_cfg: MacdCfg,
+    cfg_source: MacdCfgSource,
+) -> Result<MacdMetric> {
+    macd_cfg.validate()?;
+    let key = store::MetricKey {
+        code: basic_cfg.code.clone(),
+        tick: basic_cfg.tick,
+        metric: store::METRIC_MACD,
+        params: format!(
+            "fast_ema:{},slow_ema:{},dea:{},start:{}",
+            macd_cfg.fast_ema_period,
+            macd_cfg.slow_ema_period,
+            macd_cfg.dea_period,
+            basic_cfg.start_ts.format("%Y%m%d%H%M")
+        ),
+        data_end_ts: store::data_end_ts(
+            basic_cfg.tick,
+            basic_cfg.end_ts,
+            Local::now().naive_local(),
+        ),
+    };
+    let mut rst = store::read_through(&store::DbMetricStore(db.clone()), key, || {
+        compute_macd(db, jq, basic_cfg, macd_cfg)
+    })
+    .await?;
+    // 参数来源不影响计算结果，不参与缓存
+    rst.cfg_source = cfg_source;
+    Ok(rst)
+}
+
+async fn compute_macd(
+    db: &DbPool,
+    jq: &JqdataPool,
     basic_cfg: BasicCfg,
     macd_cfg: MacdCfg,
 ) -> Result<MacdMetric> {
     let fast_ema_period = macd_cfg.fast_ema_period;
     let slow_ema_period = macd_cfg.slow_ema_period;
     let dea_period = macd_cfg.dea_period;
-    if slow_ema_period < fast_ema_period || slow_ema_period < dea_period {
-        return Err(Error::custom(
-            ErrorKind::BadRequest,
-            format!(
-                "invalid setting: slow ema {} is no less than fast ema {} or dea {}",
-                slow_ema_period, fast_ema_period, dea_period
-            ),
-        ));
-    }
     let search_start_dt =
-        ema_approximate_start(basic_cfg.start_ts.date(), &basic_cfg.tick, slow_ema_period)?;
+        ema_approximate_start(basic_cfg.start_ts.date(), basic_cfg.tick, slow_ema_period)?;
     let prices = get_stock_tick_prices(
         &db,
         &jq,
-        &basic_cfg.tick,
+        basic_cfg.tick,
         &basic_cfg.code,
         search_start_dt.and_hms(0, 0, 0),
         basic_cfg.end_ts,
@@ -147,6 +213,7 @@ pub async fn get_metrics_macd(
         fast_ema_period,
         slow_ema_period,
         dea_period,
+        cfg_source: MacdCfgSource::default(),
         dif,
         dea,
         macd,
@@ -182,23 +249,12 @@ pub fn parse_macd_cfg(s: &str) -> Option<MacdCfg> {
     }
 }
 
-fn ema_approximate_start(start_dt: NaiveDate, tick: &str, period: u32) -> Result<NaiveDate> {
+fn ema_approximate_start(start_dt: NaiveDate, tick: Tick, period: u32) -> Result<NaiveDate> {
     // 计算额外所需的价格序列的起始区间
     // 3.5 * 周期，之前的价格影响很小
     let total_period = (3.50_f64 * period as f64) as i64;
-    let day_factor = match tick {
-        "1m" => 240,
-        "5m" => 48,
-        "30m" => 8,
-        "1d" => 1,
-        _ => {
-            return Err(Error::custom(
-                ErrorKind::BadRequest,
-                format!("invalid tick: {}", tick),
-            ))
-        }
-    };
-    let offset_days = (total_period / day_factor + 1) as i64;
+    let day_factor = tick.bars_per_day() as i64;
+    let offset_days = total_period / day_factor + 1;
 
     let mut dt = start_dt;
     for _i in 0..offset_days {
@@ -215,6 +271,58 @@ fn ema_approximate_start(start_dt: NaiveDate, tick: &str, period: u32) -> Result
     Ok(dt)
 }
 
+/// 多股票日线ATRP统计，优先读取缓存，仅计算未命中的股票
+pub async fn cached_multi_atrp_stats(
+    db: &DbPool,
+    codes: Vec<String>,
+    atrp_days: usize,
+    start_dt: NaiveDate,
+    end_dt: NaiveDate,
+) -> Result<HashMap<String, atr::AtrpStats>> {
+    let metric_store = store::DbMetricStore(db.clone());
+    let keys: Vec<store::MetricKey> = codes
+        .into_iter()
+        .map(|code| store::MetricKey {
+            code,
+            tick: Tick::D1,
+            metric: store::METRIC_ATRP,
+            params: format!("days:{}", atrp_days),
+            data_end_ts: NaiveDateTime::new(end_dt, *AFTERNOON_END),
+        })
+        .collect();
+    let cached = store::load_values::<_, atr::AtrpStats>(&metric_store, &keys).await;
+    let mut rst = HashMap::new();
+    let mut missing = Vec::new();
+    for (key, stats) in keys.into_iter().zip(cached) {
+        match stats {
+            Some(stats) => {
+                rst.insert(key.code, stats);
+            }
+            None => missing.push(key),
+        }
+    }
+    if missing.is_empty() {
+        return Ok(rst);
+    }
+    let data = ticks::query_db_multiple_prices(
+        db.clone(),
+        Tick::D1.to_string(),
+        missing.iter().map(|k| k.code.clone()).collect(),
+        start_dt,
+        end_dt,
+    )
+    .await?;
+    let computed = multi_atrp_stats(&data);
+    let now = Local::now().naive_local();
+    for key in &missing {
+        if let Some(stats) = computed.get(&key.code) {
+            store::save_value(&metric_store, key, stats, now).await;
+        }
+    }
+    rst.extend(computed);
+    Ok(rst)
+}
+
 /// 简化多股票ATRP统计
 ///
 /// 输入必定是按照code和ts升序排列的
@@ -280,3 +388,24 @@ impl AtrpContainer {
         self.rst.insert(code, stats);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macd_cfg() {
+        let cfg = parse_macd_cfg("fast_ema:6,slow_ema:13,dea:5").unwrap();
+        assert_eq!(6, cfg.fast_ema_period);
+        assert!(cfg.validate().is_ok());
+        assert!(parse_macd_cfg("fast_ema:6,dea:5").is_none());
+        assert_eq!(MacdCfg::default(), MacdCfg::for_tick(Tick::D1));
+        assert_eq!(52, MacdCfg::for_tick(Tick::M1).slow_ema_period);
+        let invalid = MacdCfg {
+            fast_ema_period: 26,
+            slow_ema_period: 12,
+            dea_period: 9,
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -1,6 +1,8 @@
+use super::math::{div_round, round_half_even, sma, true_range, METRIC_SCALE};
 use super::Metric;
 use bigdecimal::BigDecimal;
 use chrono::NaiveDateTime;
+use serde_derive::*;
 
 #[derive(Debug, Clone)]
 pub struct AtrInput {
@@ -10,7 +12,7 @@ pub struct AtrInput {
     pub prev_close: BigDecimal,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AtrpStats {
     pub max: BigDecimal,
     pub min: BigDecimal,
@@ -24,20 +26,9 @@ where
 {
     input
         .into_iter()
-        .map(|d| {
-            // 当日振幅
-            let mut p = (&d.curr_high - &d.curr_low).abs();
-            // 当日最高价与昨日收盘价的差价
-            let p2 = (&d.curr_high - &d.prev_close).abs();
-            if p < p2 {
-                p = p2;
-            }
-            // 当日最低价与昨日收盘价的插件
-            let p3 = (&d.curr_low - &d.prev_close).abs();
-            if p < p3 {
-                p = p3;
-            }
-            Metric { ts: d.ts, value: p }
+        .map(|d| Metric {
+            ts: d.ts,
+            value: true_range(&d.curr_high, &d.curr_low, &d.prev_close),
         })
         .collect()
 }
@@ -48,21 +39,14 @@ where
 {
     input
         .into_iter()
-        .map(|d| {
-            let mut p = (&d.curr_high - &d.curr_low).abs();
-            let p2 = (&d.curr_high - &d.prev_close).abs();
-            if p < p2 {
-                p = p2;
-            }
-            let p3 = (&d.curr_low - &d.prev_close).abs();
-            if p < p3 {
-                p = p3;
-            }
-            Metric {
-                ts: d.ts,
-                // 使用ATR除以昨日收盘价作为ATR百分比
-                value: p / &d.prev_close,
-            }
+        .map(|d| Metric {
+            ts: d.ts,
+            // 使用ATR除以昨日收盘价作为ATR百分比
+            value: div_round(
+                &true_range(&d.curr_high, &d.curr_low, &d.prev_close),
+                &d.prev_close,
+                METRIC_SCALE,
+            ),
         })
         .collect()
 }
@@ -72,28 +56,25 @@ where
     I: IntoIterator<Item = AtrInput>,
 {
     let data = atrp(input);
-    let (sum, count, max, min) = data.iter().fold(
-        (
-            BigDecimal::from(0),
-            0,
-            BigDecimal::from(0),
-            BigDecimal::from(u32::max_value()),
-        ),
+    let (max, min) = data.iter().fold(
+        (BigDecimal::from(0), BigDecimal::from(u32::max_value())),
         |acc, d| {
-            let max = if acc.2 > d.value {
-                acc.2
+            let max = if acc.0 > d.value {
+                acc.0
             } else {
                 d.value.clone()
             };
-            let min = if acc.3 < d.value {
-                acc.3
+            let min = if acc.1 < d.value {
+                acc.1
             } else {
                 d.value.clone()
             };
-            (acc.0 + &d.value, acc.1 + 1, max, min)
+            (max, min)
         },
     );
-    let avg = sum / count;
+    let avg = sma(data.iter().map(|d| &d.value))
+        .map(|avg| round_half_even(&avg, METRIC_SCALE))
+        .unwrap_or_default();
     AtrpStats {
         max,
         min,
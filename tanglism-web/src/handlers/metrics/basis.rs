@@ -0,0 +1,165 @@
+//! 期指基差
+//!
+//! 基差为主力期货合约价格与指数价格之差，按交易时刻对齐。
+//! 主力合约按交易日查询，换月后自动切换至新合约。
+
+use crate::handlers::stock_prices::continuous::dominant_contracts;
+use crate::handlers::stock_prices::get_stock_tick_prices;
+use crate::BasicCfg;
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result};
+use bigdecimal::BigDecimal;
+use chrono::NaiveDateTime;
+use jqdata::GetPricePeriod;
+use serde_derive::*;
+use std::collections::HashMap;
+use tanglism_utils::{end_of_day_str, parse_ts_from_str, start_of_day_str, AFTERNOON_END};
+
+/// 指数对应的股指期货品种
+pub fn future_product(index_code: &str) -> Option<&'static str> {
+    match index_code {
+        "000300.XSHG" => Some("IF"),
+        "000016.XSHG" => Some("IH"),
+        "000905.XSHG" => Some("IC"),
+        "000852.XSHG" => Some("IM"),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BasisPoint {
+    pub ts: NaiveDateTime,
+    // 当时的主力合约
+    pub contract: String,
+    pub index: BigDecimal,
+    pub future: BigDecimal,
+    pub basis: BigDecimal,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BasisMetric {
+    pub code: String,
+    pub product: String,
+    pub points: Vec<BasisPoint>,
+}
+
+/// 期货K线收盘价
+#[derive(Debug, Clone)]
+pub struct FuturePrice {
+    pub ts: NaiveDateTime,
+    pub contract: String,
+    pub close: BigDecimal,
+}
+
+pub async fn get_metrics_basis(
+    db: &DbPool,
+    jq: &JqdataPool,
+    basic_cfg: BasicCfg,
+) -> Result<BasisMetric> {
+    let product = future_product(&basic_cfg.code).ok_or_else(|| {
+        Error::custom(
+            ErrorKind::BadRequest,
+            format!("no index future for {}", basic_cfg.code),
+        )
+    })?;
+    let index_prices = get_stock_tick_prices(
+        db,
+        jq,
+        basic_cfg.tick,
+        &basic_cfg.code,
+        basic_cfg.start_ts,
+        basic_cfg.end_ts,
+    )
+    .await?;
+    let mut futures = Vec::new();
+    for (contract, start_dt, end_dt) in dominant_contracts(
+        jq,
+        product,
+        basic_cfg.start_ts.date(),
+        basic_cfg.end_ts.date(),
+    )
+    .await?
+    {
+        let resp = jq
+            .execute(|| GetPricePeriod {
+                code: contract.clone(),
+                unit: basic_cfg.tick.to_string(),
+                date: start_of_day_str(start_dt),
+                end_date: end_of_day_str(end_dt),
+                fq_ref_date: None,
+            })
+            .await?;
+        for p in resp {
+            let (ts, is_day) = parse_ts_from_str(&p.date)?;
+            futures.push(FuturePrice {
+                // 与股票K线一致，日线取收盘时刻
+                ts: if is_day {
+                    NaiveDateTime::new(ts.date(), *AFTERNOON_END)
+                } else {
+                    ts
+                },
+                contract: contract.clone(),
+                close: p.close,
+            });
+        }
+    }
+    let index: Vec<_> = index_prices.into_iter().map(|p| (p.ts, p.close)).collect();
+    Ok(BasisMetric {
+        code: basic_cfg.code,
+        product: product.to_owned(),
+        points: align_basis(&index, &futures),
+    })
+}
+
+/// 按时刻对齐指数与期货价格，缺失任一侧的时刻丢弃
+pub fn align_basis(
+    index: &[(NaiveDateTime, BigDecimal)],
+    futures: &[FuturePrice],
+) -> Vec<BasisPoint> {
+    let futures: HashMap<NaiveDateTime, &FuturePrice> = futures.iter().map(|f| (f.ts, f)).collect();
+    index
+        .iter()
+        .filter_map(|(ts, close)| {
+            futures.get(ts).map(|f| BasisPoint {
+                ts: *ts,
+                contract: f.contract.clone(),
+                index: close.clone(),
+                future: f.close.clone(),
+                basis: &f.close - close,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_align_basis() {
+        let ts = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        let index = vec![
+            (ts("2020-03-19 15:00"), BigDecimal::from(3800)),
+            (ts("2020-03-20 15:00"), BigDecimal::from(3760)),
+            (ts("2020-03-23 15:00"), BigDecimal::from(3600)),
+        ];
+        let futures = vec![
+            FuturePrice {
+                ts: ts("2020-03-19 15:00"),
+                contract: "IF2003.CCFX".to_owned(),
+                close: BigDecimal::from(3780),
+            },
+            FuturePrice {
+                ts: ts("2020-03-23 15:00"),
+                contract: "IF2004.CCFX".to_owned(),
+                close: BigDecimal::from(3610),
+            },
+        ];
+        let points = align_basis(&index, &futures);
+        assert_eq!(2, points.len());
+        assert_eq!(BigDecimal::from(-20), points[0].basis);
+        assert_eq!("IF2004.CCFX", points[1].contract);
+        assert_eq!(BigDecimal::from(10), points[1].basis);
+        assert_eq!(Some("IF"), future_product("000300.XSHG"));
+        assert_eq!(None, future_product("600000.XSHG"));
+    }
+}
@@ -1,3 +1,4 @@
+use super::math::{ema_next, round_half_even, METRIC_SCALE};
 use super::Metric;
 use bigdecimal::BigDecimal;
 use chrono::NaiveDateTime;
@@ -8,6 +9,7 @@ use chrono::NaiveDateTime;
 /// 设周期为T，收盘价P(n)，序列下标n从0开始。
 /// EMA(0) = P(0)
 /// EMA(n) = EMA(n-1) * (T-1) / (T+1) + P(n) * 2 / (T+1)
+/// 递推使用完整精度，输出按METRIC_SCALE舍入
 pub fn approximate_ema<D, P, T>(raw: &[D], period: u32, pf: P, tf: T) -> Vec<Metric>
 where
     P: Fn(&D) -> BigDecimal,
@@ -16,21 +18,18 @@ where
     if raw.is_empty() {
         return Vec::new();
     }
-    let pm1 = BigDecimal::from(period - 1);
-    let pp1 = BigDecimal::from(period + 1);
-    let two = BigDecimal::from(2);
     let mut ema = Vec::with_capacity(raw.len());
     let first = raw.first().unwrap();
+    let mut prev = pf(first);
     ema.push(Metric {
         ts: tf(first),
-        value: pf(first),
+        value: round_half_even(&prev, METRIC_SCALE),
     });
     for r in raw.iter().skip(1) {
-        let ts = tf(r);
-        let price = pf(r);
+        prev = ema_next(&prev, &pf(r), period);
         ema.push(Metric {
-            ts,
-            value: &ema.last().unwrap().value * &pm1 / &pp1 + &price * &two / &pp1,
+            ts: tf(r),
+            value: round_half_even(&prev, METRIC_SCALE),
         });
     }
     ema
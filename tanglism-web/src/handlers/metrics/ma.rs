@@ -1,3 +1,4 @@
+use super::math::{div_round, METRIC_SCALE};
 use super::Metric;
 use bigdecimal::BigDecimal;
 use chrono::NaiveDateTime;
@@ -25,14 +26,14 @@ where
     let mut res = Vec::with_capacity(raw.len() - period + 1);
     res.push(Metric {
         ts: tf(&raw[period]),
-        value: &acc / &pv,
+        value: div_round(&acc, &pv, METRIC_SCALE),
     });
     for (d0, d1) in raw.iter().zip(raw.iter().skip(period)) {
         acc -= (&pf)(d0);
         acc += (&pf)(d1);
         res.push(Metric {
             ts: tf(d1),
-            value: &acc / &pv,
+            value: div_round(&acc, &pv, METRIC_SCALE),
         });
     }
     res
@@ -0,0 +1,122 @@
+//! MACD参数
+//!
+//! 参数按优先级依次取自：请求中的metrics_cfg，数据库中单只股票在该周期上的设置，
+//! 以及周期的默认值。
+
+use super::MacdCfg;
+use crate::models::MacdConfig;
+use crate::{DbPool, Error, ErrorKind, Result};
+use chrono::Local;
+use diesel::prelude::*;
+use serde_derive::*;
+use tanglism_utils::Tick;
+
+/// 生效参数的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MacdCfgSource {
+    Request,
+    Security,
+    #[default]
+    TickDefault,
+}
+
+/// 生效的MACD参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EffectiveMacdCfg {
+    #[serde(flatten)]
+    pub cfg: MacdCfg,
+    pub source: MacdCfgSource,
+}
+
+/// 解析生效的MACD参数
+pub async fn resolve_macd_cfg(
+    pool: &DbPool,
+    code: &str,
+    tick: Tick,
+    requested: Option<MacdCfg>,
+) -> Result<(MacdCfg, MacdCfgSource)> {
+    if let Some(cfg) = requested {
+        return Ok((cfg, MacdCfgSource::Request));
+    }
+    match get_macd_config(pool.clone(), code.to_owned(), tick).await? {
+        Some(cfg) => Ok((cfg, MacdCfgSource::Security)),
+        None => Ok((MacdCfg::for_tick(tick), MacdCfgSource::TickDefault)),
+    }
+}
+
+/// 查询单只股票在指定周期上的MACD参数
+pub async fn get_macd_config(
+    pool: DbPool,
+    input_code: String,
+    input_tick: Tick,
+) -> Result<Option<MacdCfg>> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::macd_configs::dsl::*;
+        let conn = pool.get()?;
+        macd_configs
+            .find((input_code, input_tick.to_string()))
+            .first::<MacdConfig>(&conn)
+            .optional()
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data.map(|c| MacdCfg {
+        fast_ema_period: c.fast_ema_period as u32,
+        slow_ema_period: c.slow_ema_period as u32,
+        dea_period: c.dea_period as u32,
+    }))
+}
+
+/// 保存单只股票在指定周期上的MACD参数
+pub async fn save_macd_config(
+    pool: DbPool,
+    input_code: String,
+    input_tick: Tick,
+    cfg: MacdCfg,
+) -> Result<MacdCfg> {
+    cfg.validate()?;
+    let record = MacdConfig {
+        code: input_code,
+        tick: input_tick.to_string(),
+        fast_ema_period: cfg.fast_ema_period as i32,
+        slow_ema_period: cfg.slow_ema_period as i32,
+        dea_period: cfg.dea_period as i32,
+        updated_at: Local::now().naive_local(),
+    };
+    tokio::task::spawn_blocking(move || {
+        use crate::schema::macd_configs::dsl::*;
+        let conn = pool.get()?;
+        diesel::insert_into(macd_configs)
+            .values(&record)
+            .on_conflict((code, tick))
+            .do_update()
+            .set((
+                fast_ema_period.eq(record.fast_ema_period),
+                slow_ema_period.eq(record.slow_ema_period),
+                dea_period.eq(record.dea_period),
+                updated_at.eq(record.updated_at),
+            ))
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(cfg)
+}
+
+/// 删除单只股票在指定周期上的MACD参数，此后使用周期默认值
+pub async fn delete_macd_config(pool: DbPool, input_code: String, input_tick: Tick) -> Result<()> {
+    let not_found = format!("macd config of {} on {} not found", input_code, input_tick);
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::macd_configs::dsl::*;
+        let conn = pool.get()?;
+        diesel::delete(macd_configs.find((input_code, input_tick.to_string())))
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    if n == 0 {
+        return Err(Error::custom(ErrorKind::NotFound, not_found));
+    }
+    Ok(())
+}
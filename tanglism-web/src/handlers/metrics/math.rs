@@ -0,0 +1,112 @@
+//! 指标计算的公共数值函数
+//!
+//! BigDecimal除法的结果位数很长，指标统一在输出前按银行家舍入法
+//! （四舍六入五成双）保留固定位数，避免各指标舍入方式不一致。
+
+use bigdecimal::{BigDecimal, Signed, Zero};
+use std::cmp::Ordering;
+
+/// 指标默认保留的小数位数
+pub const METRIC_SCALE: i64 = 6;
+
+/// 银行家舍入，恰好为一半时取偶数
+pub fn round_half_even(v: &BigDecimal, scale: i64) -> BigDecimal {
+    // with_scale向零截断
+    let trunc = v.with_scale(scale);
+    let rem = (v - &trunc).abs();
+    let unit = BigDecimal::new(1.into(), scale);
+    let half = &unit / BigDecimal::from(2);
+    let away = match rem.cmp(&half) {
+        Ordering::Greater => true,
+        Ordering::Less => false,
+        Ordering::Equal => {
+            // 末位为奇数时进位
+            let (digits, _) = trunc.as_bigint_and_exponent();
+            !(BigDecimal::new(digits, 0) / BigDecimal::from(2)).is_integer()
+        }
+    };
+    if !away {
+        trunc
+    } else if v.is_negative() {
+        trunc - unit
+    } else {
+        trunc + unit
+    }
+}
+
+/// 除法并按指定位数舍入
+pub fn div_round(a: &BigDecimal, b: &BigDecimal, scale: i64) -> BigDecimal {
+    round_half_even(&(a / b), scale)
+}
+
+/// EMA递推：EMA(n) = EMA(n-1) * (T-1) / (T+1) + P(n) * 2 / (T+1)
+pub fn ema_next(prev: &BigDecimal, price: &BigDecimal, period: u32) -> BigDecimal {
+    let pm1 = BigDecimal::from(period - 1);
+    let pp1 = BigDecimal::from(period + 1);
+    (prev * &pm1 + price * BigDecimal::from(2)) / &pp1
+}
+
+/// 算术平均，序列为空时返回None
+pub fn sma<'a, I>(values: I) -> Option<BigDecimal>
+where
+    I: IntoIterator<Item = &'a BigDecimal>,
+{
+    let (sum, count) = values
+        .into_iter()
+        .fold((BigDecimal::zero(), 0u64), |(sum, count), v| {
+            (sum + v, count + 1)
+        });
+    if count == 0 {
+        return None;
+    }
+    Some(sum / BigDecimal::from(count))
+}
+
+/// 真实波幅：当日振幅、最高价及最低价与昨日收盘价差价中的最大值
+pub fn true_range(high: &BigDecimal, low: &BigDecimal, prev_close: &BigDecimal) -> BigDecimal {
+    let mut tr = (high - low).abs();
+    for p in [(high - prev_close).abs(), (low - prev_close).abs()].iter() {
+        if &tr < p {
+            tr = p.clone();
+        }
+    }
+    tr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tanglism_utils::price;
+
+    #[test]
+    fn test_round_half_even() {
+        assert_eq!(price!(2.12), round_half_even(&price!(2.125), 2));
+        assert_eq!(price!(2.14), round_half_even(&price!(2.135), 2));
+        assert_eq!(price!(2.13), round_half_even(&price!(2.1349), 2));
+        assert_eq!(price!(-2.12), round_half_even(&price!(-2.125), 2));
+        assert_eq!(price!(-2.13), round_half_even(&price!(-2.1251), 2));
+        assert_eq!(price!(2), round_half_even(&price!(2.5), 0));
+        assert_eq!(price!(4), round_half_even(&price!(3.5), 0));
+        assert_eq!(price!(1.5), round_half_even(&price!(1.5), 3));
+        assert_eq!(
+            price!(0.333333),
+            div_round(&price!(1), &price!(3), METRIC_SCALE)
+        );
+    }
+
+    #[test]
+    fn test_ema_sma_true_range() {
+        assert_eq!(price!(10), ema_next(&price!(10), &price!(10), 12));
+        assert_eq!(price!(11), ema_next(&price!(10), &price!(13), 5));
+        assert_eq!(Some(price!(2)), sma(&[price!(1), price!(2), price!(3)]));
+        assert_eq!(None, sma(&[]));
+        assert_eq!(
+            price!(1),
+            true_range(&price!(11), &price!(10), &price!(10.5))
+        );
+        // 跳空高开
+        assert_eq!(price!(2), true_range(&price!(12), &price!(11), &price!(10)));
+        // 跳空低开
+        assert_eq!(price!(3), true_range(&price!(9), &price!(8), &price!(11)));
+    }
+}
@@ -0,0 +1,332 @@
+//! 北向资金（沪深港通）
+//!
+//! 每日成交取自finance.STK_ML_QUOTA，个股持股取自finance.STK_HK_HOLD_INFO，
+//! 由同步任务写入数据库，指标按交易日收盘时刻与日K线对齐。
+
+use crate::models::{NorthboundFlow, NorthboundHolding};
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result};
+use bigdecimal::BigDecimal;
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use jqdata::RunQuery;
+use serde_derive::*;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use tanglism_utils::AFTERNOON_END;
+
+/// 沪股通
+pub const LINK_SH: i32 = 310001;
+/// 深股通
+pub const LINK_SZ: i32 = 310002;
+
+// run_query单次最多返回1000条，每个通道每年约250条记录
+const SYNC_DAYS_PER_QUERY: i64 = 365;
+const MAX_QUERY_COUNT: u32 = 1000;
+
+/// 北向资金每日净买入，金额单位为亿元
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlowPoint {
+    pub ts: NaiveDateTime,
+    pub buy: BigDecimal,
+    pub sell: BigDecimal,
+    pub net: BigDecimal,
+}
+
+/// 北向资金个股每日持股
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HoldingPoint {
+    pub ts: NaiveDateTime,
+    pub share_number: BigDecimal,
+    pub share_ratio: BigDecimal,
+}
+
+/// 同步区间内的北向资金每日成交，返回新写入的行数
+pub async fn sync_northbound_flows(
+    db: &DbPool,
+    jq: &JqdataPool,
+    start_dt: NaiveDate,
+    end_dt: NaiveDate,
+) -> Result<usize> {
+    let mut inserted = 0;
+    for link in &[LINK_SH, LINK_SZ] {
+        for (s, e) in split_range(start_dt, end_dt) {
+            let lines = jq
+                .execute(|| RunQuery {
+                    table: "finance.STK_ML_QUOTA".to_owned(),
+                    columns: "day,link_id,buy_amount,sell_amount".to_owned(),
+                    conditions: Some(format!("link_id#=#{}&day#>=#{}&day#<=#{}", link, s, e)),
+                    count: Some(MAX_QUERY_COUNT),
+                })
+                .await?;
+            let flows = parse_flows(&lines)?;
+            inserted += insert_flows(db.clone(), flows).await?;
+        }
+    }
+    Ok(inserted)
+}
+
+/// 同步区间内的北向资金个股持股，返回新写入的行数
+pub async fn sync_northbound_holdings(
+    db: &DbPool,
+    jq: &JqdataPool,
+    code: &str,
+    start_dt: NaiveDate,
+    end_dt: NaiveDate,
+) -> Result<usize> {
+    let mut inserted = 0;
+    for (s, e) in split_range(start_dt, end_dt) {
+        let lines = jq
+            .execute(|| RunQuery {
+                table: "finance.STK_HK_HOLD_INFO".to_owned(),
+                columns: "day,code,share_number,share_ratio".to_owned(),
+                conditions: Some(format!("code#=#{}&day#>=#{}&day#<=#{}", code, s, e)),
+                count: Some(MAX_QUERY_COUNT),
+            })
+            .await?;
+        let holdings = parse_holdings(&lines)?;
+        inserted += insert_holdings(db.clone(), holdings).await?;
+    }
+    Ok(inserted)
+}
+
+/// 区间内的北向资金每日成交，沪股通与深股通合计
+pub async fn get_northbound_flow(
+    pool: DbPool,
+    start_dt: NaiveDate,
+    end_dt: NaiveDate,
+) -> Result<Vec<FlowPoint>> {
+    let flows = tokio::task::spawn_blocking(move || {
+        use crate::schema::northbound_flows::dsl::*;
+        let conn = pool.get()?;
+        northbound_flows
+            .filter(dt.ge(start_dt).and(dt.le(end_dt)))
+            .order(dt)
+            .load::<NorthboundFlow>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(sum_flows(&flows))
+}
+
+/// 区间内的北向资金个股持股
+pub async fn get_northbound_holdings(
+    pool: DbPool,
+    input_code: String,
+    start_dt: NaiveDate,
+    end_dt: NaiveDate,
+) -> Result<Vec<HoldingPoint>> {
+    let holdings = tokio::task::spawn_blocking(move || {
+        use crate::schema::northbound_holdings::dsl::*;
+        let conn = pool.get()?;
+        northbound_holdings
+            .filter(code.eq(input_code))
+            .filter(dt.ge(start_dt).and(dt.le(end_dt)))
+            .order(dt)
+            .load::<NorthboundHolding>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(holdings
+        .into_iter()
+        .map(|h| HoldingPoint {
+            ts: NaiveDateTime::new(h.dt, *AFTERNOON_END),
+            share_number: h.share_number,
+            share_ratio: h.share_ratio,
+        })
+        .collect())
+}
+
+async fn insert_flows(pool: DbPool, flows: Vec<NorthboundFlow>) -> Result<usize> {
+    if flows.is_empty() {
+        return Ok(0);
+    }
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::northbound_flows::dsl::*;
+        let conn = pool.get()?;
+        diesel::insert_into(northbound_flows)
+            .values(&flows)
+            .on_conflict_do_nothing()
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(n)
+}
+
+async fn insert_holdings(pool: DbPool, holdings: Vec<NorthboundHolding>) -> Result<usize> {
+    if holdings.is_empty() {
+        return Ok(0);
+    }
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::northbound_holdings::dsl::*;
+        let conn = pool.get()?;
+        diesel::insert_into(northbound_holdings)
+            .values(&holdings)
+            .on_conflict_do_nothing()
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(n)
+}
+
+// 按固定天数切分查询区间
+pub(crate) fn split_range(start_dt: NaiveDate, end_dt: NaiveDate) -> Vec<(NaiveDate, NaiveDate)> {
+    let mut rst = Vec::new();
+    let mut s = start_dt;
+    while s <= end_dt {
+        let e = std::cmp::min(s + Duration::days(SYNC_DAYS_PER_QUERY - 1), end_dt);
+        rst.push((s, e));
+        s = e + Duration::days(1);
+    }
+    rst
+}
+
+// 按日期合计各通道的成交
+fn sum_flows(flows: &[NorthboundFlow]) -> Vec<FlowPoint> {
+    let mut days: BTreeMap<NaiveDate, (BigDecimal, BigDecimal)> = BTreeMap::new();
+    for f in flows {
+        let e = days
+            .entry(f.dt)
+            .or_insert_with(|| (BigDecimal::from(0), BigDecimal::from(0)));
+        e.0 += &f.buy_amount;
+        e.1 += &f.sell_amount;
+    }
+    days.into_iter()
+        .map(|(dt, (buy, sell))| FlowPoint {
+            ts: NaiveDateTime::new(dt, *AFTERNOON_END),
+            net: &buy - &sell,
+            buy,
+            sell,
+        })
+        .collect()
+}
+
+/// run_query返回的CSV行，首行为表头，按列名取值
+pub(crate) struct Rows<'a> {
+    header: HashMap<&'a str, usize>,
+    pub(crate) rows: Vec<Vec<&'a str>>,
+}
+
+impl<'a> Rows<'a> {
+    pub(crate) fn parse(lines: &'a [String]) -> Self {
+        let mut it = lines.iter().map(|l| l.trim()).filter(|l| !l.is_empty());
+        let header = it
+            .next()
+            .map(|h| h.split(',').enumerate().map(|(i, c)| (c, i)).collect())
+            .unwrap_or_default();
+        let rows = it.map(|l| l.split(',').collect()).collect();
+        Rows { header, rows }
+    }
+
+    pub(crate) fn get(&self, row: &[&'a str], col: &str) -> Result<&'a str> {
+        self.header
+            .get(col)
+            .and_then(|i| row.get(*i))
+            .copied()
+            .ok_or_else(|| {
+                Error::custom(
+                    ErrorKind::InternalServerError,
+                    format!("column {} missing in run_query response", col),
+                )
+            })
+    }
+
+    pub(crate) fn get_date(&self, row: &[&'a str], col: &str) -> Result<NaiveDate> {
+        let s = self.get(row, col)?;
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|e| {
+            Error::custom(
+                ErrorKind::InternalServerError,
+                format!("invalid date {}: {}", s, e),
+            )
+        })
+    }
+
+    // 空值按0处理
+    pub(crate) fn get_decimal(&self, row: &[&'a str], col: &str) -> Result<BigDecimal> {
+        let s = self.get(row, col)?;
+        if s.is_empty() {
+            return Ok(BigDecimal::from(0));
+        }
+        BigDecimal::from_str(s).map_err(|e| {
+            Error::custom(
+                ErrorKind::InternalServerError,
+                format!("invalid decimal {}: {}", s, e),
+            )
+        })
+    }
+}
+
+fn parse_flows(lines: &[String]) -> Result<Vec<NorthboundFlow>> {
+    let rows = Rows::parse(lines);
+    let mut rst = Vec::with_capacity(rows.rows.len());
+    for row in &rows.rows {
+        let link_id = rows.get(row, "link_id")?;
+        rst.push(NorthboundFlow {
+            dt: rows.get_date(row, "day")?,
+            link_id: link_id.parse().map_err(|_| {
+                Error::custom(
+                    ErrorKind::InternalServerError,
+                    format!("invalid link_id {}", link_id),
+                )
+            })?,
+            buy_amount: rows.get_decimal(row, "buy_amount")?,
+            sell_amount: rows.get_decimal(row, "sell_amount")?,
+        });
+    }
+    Ok(rst)
+}
+
+fn parse_holdings(lines: &[String]) -> Result<Vec<NorthboundHolding>> {
+    let rows = Rows::parse(lines);
+    let mut rst = Vec::with_capacity(rows.rows.len());
+    for row in &rows.rows {
+        rst.push(NorthboundHolding {
+            code: rows.get(row, "code")?.to_owned(),
+            dt: rows.get_date(row, "day")?,
+            share_number: rows.get_decimal(row, "share_number")?,
+            share_ratio: rows.get_decimal(row, "share_ratio")?,
+        });
+    }
+    Ok(rst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_sum_flows() -> Result<()> {
+        let lines: Vec<String> = vec![
+            "day,link_id,buy_amount,sell_amount",
+            "2020-07-06,310001,300.5,200.5",
+            "2020-07-06,310002,250,260",
+            "2020-07-07,310001,,100",
+            "",
+        ]
+        .into_iter()
+        .map(str::to_owned)
+        .collect();
+        let flows = parse_flows(&lines)?;
+        assert_eq!(3, flows.len());
+        assert_eq!(LINK_SZ, flows[1].link_id);
+        let points = sum_flows(&flows);
+        assert_eq!(2, points.len());
+        assert_eq!(*AFTERNOON_END, points[0].ts.time());
+        assert_eq!(BigDecimal::from_str("550.5").unwrap(), points[0].buy);
+        assert_eq!(BigDecimal::from(90), points[0].net);
+        assert_eq!(BigDecimal::from(-100), points[1].net);
+        assert!(parse_holdings(&lines).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_range() {
+        let dt = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        let ranges = split_range(dt("2019-01-01"), dt("2020-07-01"));
+        assert_eq!(2, ranges.len());
+        assert_eq!((dt("2019-01-01"), dt("2019-12-31")), ranges[0]);
+        assert_eq!((dt("2020-01-01"), dt("2020-07-01")), ranges[1]);
+        assert!(split_range(dt("2020-07-02"), dt("2020-07-01")).is_empty());
+    }
+}
@@ -0,0 +1,369 @@
+//! 指标缓存
+//!
+//! 热门股票的MACD、ATRP等指标被反复计算，计算结果以JSON保存，
+//! 键为(code, tick, metric, params, data_end_ts)。
+//! data_end_ts为参与计算的最后一根已完成K线，出现新K线后键随之变化，旧条目不再命中；
+//! 每个条目另有过期时间，包含最新K线的条目过期较快，以容纳数据源的修正。
+//! 存储通过MetricStore抽象，默认使用数据库。
+
+use crate::models::MetricCache;
+use crate::{DbPool, Error, Result};
+use async_trait::async_trait;
+use chrono::{Duration, Local, NaiveDateTime};
+use diesel::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::future::Future;
+use tanglism_utils::{LocalTradingTimestamps, Tick};
+
+pub const METRIC_MACD: &str = "macd";
+pub const METRIC_ATRP: &str = "atrp";
+
+/// 缓存键
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MetricKey {
+    pub code: String,
+    pub tick: Tick,
+    pub metric: &'static str,
+    pub params: String,
+    pub data_end_ts: NaiveDateTime,
+}
+
+/// 指标存储
+#[async_trait]
+pub trait MetricStore: Send + Sync {
+    /// 读取未过期的条目
+    async fn load(&self, key: &MetricKey, now: NaiveDateTime) -> Result<Option<String>>;
+
+    /// 批量读取，返回值与keys一一对应
+    async fn load_many(
+        &self,
+        keys: &[MetricKey],
+        now: NaiveDateTime,
+    ) -> Result<Vec<Option<String>>> {
+        let mut rst = Vec::with_capacity(keys.len());
+        for key in keys {
+            rst.push(self.load(key, now).await?);
+        }
+        Ok(rst)
+    }
+
+    async fn save(&self, key: &MetricKey, value: String, expires_at: NaiveDateTime) -> Result<()>;
+
+    /// 删除指定股票及周期的条目，均为空时清空，返回删除的条目数
+    async fn invalidate(&self, code: Option<String>, tick: Option<Tick>) -> Result<usize>;
+}
+
+/// 以metric_caches表作为存储
+#[derive(Clone)]
+pub struct DbMetricStore(pub DbPool);
+
+#[async_trait]
+impl MetricStore for DbMetricStore {
+    async fn load(&self, key: &MetricKey, now: NaiveDateTime) -> Result<Option<String>> {
+        let pool = self.0.clone();
+        let key = key.clone();
+        let data = tokio::task::spawn_blocking(move || {
+            use crate::schema::metric_caches::dsl::*;
+            let conn = pool.get()?;
+            metric_caches
+                .find((
+                    key.code,
+                    key.tick.to_string(),
+                    key.metric,
+                    key.params,
+                    key.data_end_ts,
+                ))
+                .filter(expires_at.gt(now))
+                .select(value)
+                .first::<String>(&conn)
+                .optional()
+                .map_err(Error::from)
+        })
+        .await??;
+        Ok(data)
+    }
+
+    // 除股票代码外其余部分相同时合并为一次查询
+    async fn load_many(
+        &self,
+        keys: &[MetricKey],
+        now: NaiveDateTime,
+    ) -> Result<Vec<Option<String>>> {
+        let first = match keys.first() {
+            Some(k) => k.clone(),
+            None => return Ok(Vec::new()),
+        };
+        if keys.iter().any(|k| {
+            k.tick != first.tick
+                || k.metric != first.metric
+                || k.params != first.params
+                || k.data_end_ts != first.data_end_ts
+        }) {
+            let mut rst = Vec::with_capacity(keys.len());
+            for key in keys {
+                rst.push(self.load(key, now).await?);
+            }
+            return Ok(rst);
+        }
+        let pool = self.0.clone();
+        let codes: Vec<String> = keys.iter().map(|k| k.code.clone()).collect();
+        let rows = tokio::task::spawn_blocking(move || {
+            use crate::schema::metric_caches::dsl::*;
+            let conn = pool.get()?;
+            metric_caches
+                .filter(tick.eq(first.tick.to_string()))
+                .filter(metric.eq(first.metric))
+                .filter(params.eq(first.params))
+                .filter(data_end_ts.eq(first.data_end_ts))
+                .filter(expires_at.gt(now))
+                .filter(code.eq_any(codes))
+                .select((code, value))
+                .load::<(String, String)>(&conn)
+                .map_err(Error::from)
+        })
+        .await??;
+        let mut rows: std::collections::HashMap<String, String> = rows.into_iter().collect();
+        Ok(keys.iter().map(|k| rows.remove(&k.code)).collect())
+    }
+
+    async fn save(&self, key: &MetricKey, data: String, expires: NaiveDateTime) -> Result<()> {
+        let pool = self.0.clone();
+        let now = Local::now().naive_local();
+        let record = MetricCache {
+            code: key.code.clone(),
+            tick: key.tick.to_string(),
+            metric: key.metric.to_owned(),
+            params: key.params.clone(),
+            data_end_ts: key.data_end_ts,
+            value: data,
+            created_at: now,
+            expires_at: expires,
+        };
+        tokio::task::spawn_blocking(move || {
+            use crate::schema::metric_caches::dsl::*;
+            let conn = pool.get()?;
+            // 顺带清理已过期的条目
+            diesel::delete(metric_caches.filter(expires_at.le(now))).execute(&conn)?;
+            diesel::insert_into(metric_caches)
+                .values(&record)
+                .on_conflict((code, tick, metric, params, data_end_ts))
+                .do_update()
+                .set((
+                    value.eq(&record.value),
+                    created_at.eq(record.created_at),
+                    expires_at.eq(record.expires_at),
+                ))
+                .execute(&conn)
+                .map_err(Error::from)
+        })
+        .await??;
+        Ok(())
+    }
+
+    async fn invalidate(
+        &self,
+        input_code: Option<String>,
+        input_tick: Option<Tick>,
+    ) -> Result<usize> {
+        let pool = self.0.clone();
+        let n = tokio::task::spawn_blocking(move || {
+            use crate::schema::metric_caches::dsl::*;
+            let conn = pool.get()?;
+            let mut query = diesel::delete(metric_caches).into_boxed();
+            if let Some(c) = input_code {
+                query = query.filter(code.eq(c));
+            }
+            if let Some(t) = input_tick {
+                query = query.filter(tick.eq(t.to_string()));
+            }
+            query.execute(&conn).map_err(Error::from)
+        })
+        .await??;
+        Ok(n)
+    }
+}
+
+// 包含最新K线的条目的有效期
+const LIVE_TTL_MINUTES: i64 = 10;
+// 历史条目的有效期
+const HISTORY_TTL_DAYS: i64 = 7;
+
+/// 指标计算实际使用的截止时刻，不晚于最后一根已完成K线
+pub fn data_end_ts(tick: Tick, end_ts: NaiveDateTime, now: NaiveDateTime) -> NaiveDateTime {
+    match LocalTradingTimestamps::new(tick).last_completed_tick(now) {
+        Some(last) if last < end_ts => last,
+        _ => end_ts,
+    }
+}
+
+/// 条目的过期时间
+pub fn expires_at(key: &MetricKey, now: NaiveDateTime) -> NaiveDateTime {
+    let live = LocalTradingTimestamps::new(key.tick)
+        .last_completed_tick(now)
+        .map(|last| key.data_end_ts >= last)
+        .unwrap_or(true);
+    if live {
+        now + Duration::minutes(LIVE_TTL_MINUTES)
+    } else {
+        now + Duration::days(HISTORY_TTL_DAYS)
+    }
+}
+
+/// 优先读取缓存，未命中时计算并写入
+///
+/// 存储的读写失败不影响计算结果，仅记录日志
+pub async fn read_through<S, T, F, Fut>(store: &S, key: MetricKey, compute: F) -> Result<T>
+where
+    S: MetricStore + ?Sized,
+    T: Serialize + DeserializeOwned,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let now = Local::now().naive_local();
+    match store.load(&key, now).await {
+        Ok(Some(s)) => match serde_json::from_str(&s) {
+            Ok(t) => return Ok(t),
+            Err(e) => log::warn!("corrupted metric cache {:?}: {}", key, e),
+        },
+        Ok(None) => (),
+        Err(e) => log::warn!("failed to load metric cache {:?}: {}", key, e),
+    }
+    let t = compute().await?;
+    save_value(store, &key, &t, now).await;
+    Ok(t)
+}
+
+/// 写入单个条目，失败时仅记录日志
+pub async fn save_value<S, T>(store: &S, key: &MetricKey, t: &T, now: NaiveDateTime)
+where
+    S: MetricStore + ?Sized,
+    T: Serialize,
+{
+    let s = match serde_json::to_string(t) {
+        Ok(s) => s,
+        Err(e) => {
+            log::warn!("failed to serialize metric {:?}: {}", key, e);
+            return;
+        }
+    };
+    if let Err(e) = store.save(key, s, expires_at(key, now)).await {
+        log::warn!("failed to save metric cache {:?}: {}", key, e);
+    }
+}
+
+/// 批量读取并解析，读取失败或无法解析的条目视为未命中
+pub async fn load_values<S, T>(store: &S, keys: &[MetricKey]) -> Vec<Option<T>>
+where
+    S: MetricStore + ?Sized,
+    T: DeserializeOwned,
+{
+    let now = Local::now().naive_local();
+    match store.load_many(keys, now).await {
+        Ok(raw) => raw
+            .into_iter()
+            .map(|s| s.and_then(|s| serde_json::from_str(&s).ok()))
+            .collect(),
+        Err(e) => {
+            log::warn!("failed to load metric cache: {}", e);
+            keys.iter().map(|_| None).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MemoryStore(Mutex<HashMap<MetricKey, (String, NaiveDateTime)>>);
+
+    #[async_trait]
+    impl MetricStore for MemoryStore {
+        async fn load(&self, key: &MetricKey, now: NaiveDateTime) -> Result<Option<String>> {
+            let m = self.0.lock().unwrap();
+            Ok(m.get(key)
+                .filter(|(_, exp)| *exp > now)
+                .map(|(v, _)| v.clone()))
+        }
+
+        async fn save(&self, key: &MetricKey, v: String, exp: NaiveDateTime) -> Result<()> {
+            self.0.lock().unwrap().insert(key.clone(), (v, exp));
+            Ok(())
+        }
+
+        async fn invalidate(&self, c: Option<String>, t: Option<Tick>) -> Result<usize> {
+            let mut m = self.0.lock().unwrap();
+            let before = m.len();
+            m.retain(|k, _| {
+                !(c.as_ref().map(|c| c == &k.code).unwrap_or(true)
+                    && t.map(|t| t == k.tick).unwrap_or(true))
+            });
+            Ok(before - m.len())
+        }
+    }
+
+    fn key(code: &str, end: &str) -> MetricKey {
+        MetricKey {
+            code: code.to_owned(),
+            tick: Tick::D1,
+            metric: METRIC_ATRP,
+            params: "days:20".to_owned(),
+            data_end_ts: NaiveDateTime::parse_from_str(end, "%Y-%m-%d %H:%M").unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_through() {
+        let store = MemoryStore::default();
+        let counter = AtomicUsize::new(0);
+        let computed = &counter;
+        let compute = move || async move {
+            computed.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![1, 2, 3])
+        };
+        let k = key("600000.XSHG", "2020-08-07 15:00");
+        let v: Vec<i32> = read_through(&store, k.clone(), compute).await.unwrap();
+        assert_eq!(vec![1, 2, 3], v);
+        let v: Vec<i32> = read_through(&store, k.clone(), compute).await.unwrap();
+        assert_eq!(vec![1, 2, 3], v);
+        assert_eq!(1, computed.load(Ordering::SeqCst));
+        // 新K线对应新的键
+        let k2 = key("600000.XSHG", "2020-08-10 15:00");
+        let _: Vec<i32> = read_through(&store, k2.clone(), compute).await.unwrap();
+        assert_eq!(2, computed.load(Ordering::SeqCst));
+
+        let loaded: Vec<Option<Vec<i32>>> =
+            load_values(&store, &[k, key("000001.XSHE", "2020-08-07 15:00")]).await;
+        assert!(loaded[0].is_some() && loaded[1].is_none());
+        assert_eq!(
+            2,
+            store
+                .invalidate(Some("600000.XSHG".to_owned()), None)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_data_end_ts() {
+        let now = NaiveDateTime::parse_from_str("2020-08-07 10:17", "%Y-%m-%d %H:%M").unwrap();
+        let future = NaiveDateTime::parse_from_str("2020-08-08 00:00", "%Y-%m-%d %H:%M").unwrap();
+        assert_eq!(
+            "2020-08-07 10:00:00",
+            data_end_ts(Tick::M30, future, now).to_string()
+        );
+        let past = NaiveDateTime::parse_from_str("2020-08-06 15:00", "%Y-%m-%d %H:%M").unwrap();
+        assert_eq!(past, data_end_ts(Tick::D1, past, now));
+        let k = key("600000.XSHG", "2020-08-06 15:00");
+        assert_eq!(
+            now + Duration::minutes(LIVE_TTL_MINUTES),
+            expires_at(&k, now)
+        );
+        let k = key("600000.XSHG", "2020-08-05 15:00");
+        assert_eq!(now + Duration::days(HISTORY_TTL_DAYS), expires_at(&k, now));
+    }
+}
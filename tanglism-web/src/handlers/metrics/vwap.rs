@@ -0,0 +1,193 @@
+//! 成交量加权均价
+//!
+//! 以1分钟K线的成交额与成交量计算，日内均价每个交易日重新累计，
+//! 锚定均价从指定时刻（如最后一个中枢的起点）起持续累计。
+//! 结果按显示级别的K线时刻取样。
+
+use super::math::{div_round, METRIC_SCALE};
+use super::Metric;
+use crate::handlers::stock_prices::get_stock_tick_prices;
+use crate::handlers::stock_prices::ticks::StockPrice;
+use crate::BasicCfg;
+use crate::{DbPool, JqdataPool, Result};
+use bigdecimal::BigDecimal;
+use chrono::{NaiveDateTime, NaiveTime};
+use serde_derive::*;
+use tanglism_utils::{parse_ts_from_str, LocalTradingTimestamps, Tick, TradingTimestamps};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VwapMetric {
+    pub anchor: Option<NaiveDateTime>,
+    // 日内均价
+    pub daily: Vec<Metric>,
+    // 锚定均价，未指定锚点时为空
+    pub anchored: Vec<Metric>,
+}
+
+/// 从指标配置中解析锚点，格式为vwap_anchor:2020-07-06 10:30
+///
+/// 取值为center时表示最后一个中枢的起点，由调用方结合中枢解析
+pub fn parse_vwap_anchor(s: &str) -> Result<Option<VwapAnchor>> {
+    for c in s.split(',') {
+        if let Some(v) = c.trim().strip_prefix("vwap_anchor:") {
+            let v = v.trim();
+            if v == "center" {
+                return Ok(Some(VwapAnchor::LastCenter));
+            }
+            let (ts, _) = parse_ts_from_str(v)?;
+            return Ok(Some(VwapAnchor::Ts(ts)));
+        }
+    }
+    Ok(None)
+}
+
+/// 锚点
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VwapAnchor {
+    Ts(NaiveDateTime),
+    // 最后一个中枢的起点
+    LastCenter,
+}
+
+pub async fn get_metrics_vwap(
+    db: &DbPool,
+    jq: &JqdataPool,
+    basic_cfg: BasicCfg,
+    anchor: Option<NaiveDateTime>,
+) -> Result<VwapMetric> {
+    // 锚点早于显示区间时需从锚点所在日开始累计
+    let start_ts = match anchor {
+        Some(a) if a < basic_cfg.start_ts => a.date().and_time(NaiveTime::MIN),
+        _ => basic_cfg.start_ts,
+    };
+    let prices = get_stock_tick_prices(
+        db,
+        jq,
+        Tick::M1,
+        &basic_cfg.code,
+        start_ts,
+        basic_cfg.end_ts,
+    )
+    .await?;
+    let tts = LocalTradingTimestamps::new(basic_cfg.tick);
+    let (daily, anchored) = compute_vwap(&prices, anchor, |ts| {
+        ts >= basic_cfg.start_ts && tts.aligned_tick(ts) == Some(ts)
+    });
+    Ok(VwapMetric {
+        anchor,
+        daily,
+        anchored,
+    })
+}
+
+/// 计算日内及锚定均价，keep决定输出的时刻
+pub fn compute_vwap<F>(
+    prices: &[StockPrice],
+    anchor: Option<NaiveDateTime>,
+    keep: F,
+) -> (Vec<Metric>, Vec<Metric>)
+where
+    F: Fn(NaiveDateTime) -> bool,
+{
+    let zero = BigDecimal::from(0);
+    let mut daily = Vec::new();
+    let mut anchored = Vec::new();
+    let mut day = None;
+    let (mut day_amount, mut day_volume) = (zero.clone(), zero.clone());
+    let (mut anchor_amount, mut anchor_volume) = (zero.clone(), zero.clone());
+    for p in prices {
+        if day != Some(p.ts.date()) {
+            day = Some(p.ts.date());
+            day_amount = zero.clone();
+            day_volume = zero.clone();
+        }
+        day_amount += &p.amount;
+        day_volume += &p.volume;
+        let anchored_now = anchor.map(|a| p.ts >= a).unwrap_or(false);
+        if anchored_now {
+            anchor_amount += &p.amount;
+            anchor_volume += &p.volume;
+        }
+        if !keep(p.ts) {
+            continue;
+        }
+        // 成交量为0时均价无意义
+        if day_volume > zero {
+            daily.push(Metric {
+                ts: p.ts,
+                value: div_round(&day_amount, &day_volume, METRIC_SCALE),
+            });
+        }
+        if anchored_now && anchor_volume > zero {
+            anchored.push(Metric {
+                ts: p.ts,
+                value: div_round(&anchor_amount, &anchor_volume, METRIC_SCALE),
+            });
+        }
+    }
+    (daily, anchored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::stock_prices::ticks::PriceBuilder;
+    use chrono::Timelike;
+
+    #[test]
+    fn test_compute_vwap() -> Result<()> {
+        let prices = vec![
+            PriceBuilder::new("2020-07-06 09:31", 10)
+                .volume(100)
+                .amount(1000)
+                .build(),
+            PriceBuilder::new("2020-07-06 09:32", 12)
+                .volume(300)
+                .amount(3600)
+                .build(),
+            PriceBuilder::new("2020-07-07 09:31", 11)
+                .volume(0)
+                .amount(0)
+                .build(),
+            PriceBuilder::new("2020-07-07 09:32", 11)
+                .volume(200)
+                .amount(2200)
+                .build(),
+        ];
+        let anchor = Some(parse_ts_from_str("2020-07-06 09:32")?.0);
+        let (daily, anchored) = compute_vwap(&prices, anchor, |_| true);
+        assert_eq!(3, daily.len());
+        assert_eq!(BigDecimal::from(10), daily[0].value);
+        assert_eq!(BigDecimal::from(46) / BigDecimal::from(4), daily[1].value);
+        // 次日重新累计，首根K线无成交
+        assert_eq!(prices[3].ts, daily[2].ts);
+        assert_eq!(BigDecimal::from(11), daily[2].value);
+        // 锚定均价跨日累计
+        assert_eq!(3, anchored.len());
+        assert_eq!(BigDecimal::from(12), anchored[0].value);
+        assert_eq!(
+            BigDecimal::from(58) / BigDecimal::from(5),
+            anchored[2].value
+        );
+
+        let (daily, anchored) = compute_vwap(&prices, None, |ts| ts.minute() == 32);
+        assert_eq!(2, daily.len());
+        assert!(anchored.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_vwap_anchor() -> Result<()> {
+        assert_eq!(None, parse_vwap_anchor("fast_ema:12,slow_ema:26,dea:9")?);
+        assert_eq!(
+            Some(VwapAnchor::LastCenter),
+            parse_vwap_anchor("dea:9,vwap_anchor:center")?
+        );
+        assert_eq!(
+            Some(VwapAnchor::Ts(parse_ts_from_str("2020-07-06 10:30")?.0)),
+            parse_vwap_anchor("vwap_anchor:2020-07-06 10:30")?
+        );
+        assert!(parse_vwap_anchor("vwap_anchor:abc").is_err());
+        Ok(())
+    }
+}
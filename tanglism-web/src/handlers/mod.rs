@@ -1,9 +1,26 @@
+pub mod audit;
 pub mod choice;
+pub mod clock;
+pub mod confirm;
+pub mod events;
+pub mod funds;
+pub mod heatmap;
+pub mod i18n;
+pub mod jobs;
 pub mod metrics;
+pub mod notes;
+pub mod ohlc;
+pub mod output;
+pub mod reports;
+pub mod score;
+pub mod shape_stats;
 pub mod stock_prices;
 pub mod stocks;
+pub mod structure_diff;
 pub mod tanglism;
 pub mod trade_days;
+pub mod warm;
+pub mod webhooks;
 
 use diesel::pg::Pg;
 use diesel::prelude::*;
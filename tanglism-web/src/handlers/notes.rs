@@ -0,0 +1,110 @@
+//! 交易笔记
+//!
+//! 按日期记录的笔记，可关联股票、信号及图表快照，便于复盘时对照当时的走势结构
+
+use crate::models::{Note, NoteForm};
+use crate::{DbPool, Error, ErrorKind, Result};
+use chrono::{Local, NaiveDate};
+use diesel::prelude::*;
+use serde_derive::*;
+
+/// 笔记搜索条件，均为可选
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NoteQuery {
+    pub code: Option<String>,
+    pub start_dt: Option<NaiveDate>,
+    pub end_dt: Option<NaiveDate>,
+    pub tag: Option<String>,
+    pub signal_id: Option<String>,
+}
+
+pub async fn create_note(pool: DbPool, form: NoteForm) -> Result<Note> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::notes::dsl::*;
+        let conn = pool.get()?;
+        let now = Local::now().naive_local();
+        diesel::insert_into(notes)
+            .values((&form, created_at.eq(now), updated_at.eq(now)))
+            .get_result::<Note>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data)
+}
+
+pub async fn get_note(pool: DbPool, note_id: i32) -> Result<Note> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::notes::dsl::*;
+        let conn = pool.get()?;
+        notes
+            .find(note_id)
+            .first::<Note>(&conn)
+            .optional()
+            .map_err(Error::from)
+    })
+    .await??;
+    data.ok_or_else(|| note_not_found(note_id))
+}
+
+pub async fn update_note(pool: DbPool, note_id: i32, form: NoteForm) -> Result<Note> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::notes::dsl::*;
+        let conn = pool.get()?;
+        diesel::update(notes.find(note_id))
+            .set((&form, updated_at.eq(Local::now().naive_local())))
+            .get_result::<Note>(&conn)
+            .optional()
+            .map_err(Error::from)
+    })
+    .await??;
+    data.ok_or_else(|| note_not_found(note_id))
+}
+
+pub async fn delete_note(pool: DbPool, note_id: i32) -> Result<()> {
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::notes::dsl::*;
+        let conn = pool.get()?;
+        diesel::delete(notes.find(note_id))
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    if n == 0 {
+        return Err(note_not_found(note_id));
+    }
+    Ok(())
+}
+
+/// 按股票、日期区间、标签及信号搜索笔记，按日期倒序
+pub async fn search_notes(pool: DbPool, q: NoteQuery) -> Result<Vec<Note>> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::notes::dsl::*;
+        let conn = pool.get()?;
+        let mut query = notes.into_boxed();
+        if let Some(input_code) = q.code {
+            query = query.filter(code.eq(input_code));
+        }
+        if let Some(start_dt) = q.start_dt {
+            query = query.filter(dt.ge(start_dt));
+        }
+        if let Some(end_dt) = q.end_dt {
+            query = query.filter(dt.le(end_dt));
+        }
+        if let Some(tag) = q.tag {
+            query = query.filter(tags.contains(vec![tag]));
+        }
+        if let Some(input_signal_id) = q.signal_id {
+            query = query.filter(signal_id.eq(input_signal_id));
+        }
+        query
+            .order((dt.desc(), id.desc()))
+            .load::<Note>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data)
+}
+
+fn note_not_found(note_id: i32) -> Error {
+    Error::custom(ErrorKind::NotFound, format!("note {} not found", note_id))
+}
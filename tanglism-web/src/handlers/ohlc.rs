@@ -0,0 +1,248 @@
+//! 自定义K线分析
+//!
+//! 对用户提交的OHLC序列直接运行完整的形态分析，不访问数据库和jqdata，
+//! 可用于分析系统未收录的品种。时间戳按A股交易时段解释，独立K线及开盘跳空的判断依赖于此。
+//!
+//! 支持两种输入格式：
+//! 1. JSON数组，元素为{ts, open, high, low, close, volume, amount}，成交量及成交额可省略，
+//!    数值为十进制字符串或整数
+//! 2. CSV，每行依次为ts,open,high,low,close[,volume[,amount]]，首行可为表头
+
+use super::stock_prices::ticks::StockPrice;
+use super::tanglism;
+use crate::{Error, ErrorKind, Result, ResultExt};
+use bigdecimal::{BigDecimal, Zero};
+use chrono::NaiveDateTime;
+use serde_derive::*;
+use tanglism_morph::{
+    CenterElement, Parting, PartingConfig, Segment, Stroke, StrokeConfig, SubTrend, Trend,
+    TrendConfig,
+};
+use tanglism_utils::{deserialize_price, parse_price, parse_ts_from_str, Tick, AFTERNOON_END};
+
+/// 单次分析最多的K线数
+pub const MAX_BARS: usize = 20_000;
+
+/// 分析参数，配置格式与websocket查询相同
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OhlcParam {
+    pub tick: Tick,
+    pub parting_cfg: Option<String>,
+    pub stroke_cfg: Option<String>,
+    pub trend_cfg: Option<String>,
+}
+
+/// 用户提交的单根K线
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OhlcBar {
+    pub ts: String,
+    #[serde(deserialize_with = "deserialize_price")]
+    pub open: BigDecimal,
+    #[serde(deserialize_with = "deserialize_price")]
+    pub high: BigDecimal,
+    #[serde(deserialize_with = "deserialize_price")]
+    pub low: BigDecimal,
+    #[serde(deserialize_with = "deserialize_price")]
+    pub close: BigDecimal,
+    #[serde(default, deserialize_with = "deserialize_price")]
+    pub volume: BigDecimal,
+    #[serde(default, deserialize_with = "deserialize_price")]
+    pub amount: BigDecimal,
+}
+
+/// 完整的形态分析结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OhlcAnalysis {
+    pub tick: Tick,
+    pub bars: usize,
+    pub start_ts: NaiveDateTime,
+    pub end_ts: NaiveDateTime,
+    pub partings: Vec<Parting>,
+    pub strokes: Vec<Stroke>,
+    pub segments: Vec<Segment>,
+    pub subtrends: Vec<SubTrend>,
+    pub centers: Vec<CenterElement>,
+    pub trends: Vec<Trend>,
+}
+
+/// 解析请求体，以[开头时按JSON处理，否则按CSV处理
+pub fn parse_bars(body: &[u8], tick: Tick) -> Result<Vec<StockPrice>> {
+    let text = std::str::from_utf8(body)
+        .map_err(|e| Error::custom(ErrorKind::BadRequest, format!("invalid utf-8: {}", e)))?;
+    let bars = if text.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<OhlcBar>>(text)
+            .map_err(|e| Error::custom(ErrorKind::BadRequest, format!("invalid json: {}", e)))?
+    } else {
+        parse_csv(text)?
+    };
+    let mut prices = Vec::with_capacity(bars.len());
+    for bar in bars {
+        prices.push(StockPrice {
+            ts: parse_bar_ts(&bar.ts, tick)?,
+            open: bar.open,
+            close: bar.close,
+            high: bar.high,
+            low: bar.low,
+            volume: bar.volume,
+            amount: bar.amount,
+        });
+    }
+    validate_bars(&prices)?;
+    Ok(prices)
+}
+
+/// 对K线序列运行分型、笔、线段、次级别走势、中枢及走势的分析
+pub fn analyze_bars(prices: &[StockPrice], param: &OhlcParam) -> Result<OhlcAnalysis> {
+    validate_bars(prices)?;
+    let parting_cfg = match param.parting_cfg {
+        Some(ref s) => tanglism::parse_parting_cfg(s).context("invalid parting cfg")?,
+        None => PartingConfig::default(),
+    };
+    let stroke_cfg = match param.stroke_cfg {
+        Some(ref s) => tanglism::parse_stroke_cfg(s).context("invalid stroke cfg")?,
+        None => StrokeConfig::default(),
+    };
+    let trend_cfg = match param.trend_cfg {
+        Some(ref s) => tanglism::parse_trend_cfg(s).context("invalid trend cfg")?,
+        None => TrendConfig {
+            level: 1,
+            center: Default::default(),
+        },
+    };
+    let partings = tanglism::get_tanglism_partings(prices, &parting_cfg)?;
+    let strokes = tanglism::get_tanglism_strokes(&partings, param.tick, stroke_cfg)?;
+    let segments = tanglism::get_tanglism_segments(&strokes)?;
+    let subtrends = tanglism::get_tanglism_subtrends(
+        &segments,
+        &strokes,
+        param.tick,
+        trend_cfg.level,
+        &trend_cfg.center,
+    )?;
+    let centers = tanglism::get_tanglism_centers(&subtrends, &trend_cfg.center)?;
+    let trends = tanglism::get_tanglism_trends(&centers)?;
+    Ok(OhlcAnalysis {
+        tick: param.tick,
+        bars: prices.len(),
+        start_ts: prices[0].ts,
+        end_ts: prices[prices.len() - 1].ts,
+        partings,
+        strokes,
+        segments: tanglism::brief_segments(&segments),
+        subtrends,
+        centers,
+        trends,
+    })
+}
+
+fn parse_csv(text: &str) -> Result<Vec<OhlcBar>> {
+    let mut bars = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        let number = |idx: usize| -> Result<BigDecimal> {
+            match fields.get(idx) {
+                Some(f) => parse_price(f).map_err(|_| {
+                    Error::custom(
+                        ErrorKind::BadRequest,
+                        format!("invalid number {} at line {}", f, i + 1),
+                    )
+                }),
+                None => Ok(BigDecimal::zero()),
+            }
+        };
+        // 首行的价格不是数字时视为表头
+        if i == 0 && fields.len() > 1 && parse_price(fields[1]).is_err() {
+            continue;
+        }
+        if fields.len() < 5 {
+            return Err(Error::custom(
+                ErrorKind::BadRequest,
+                format!("expect at least 5 fields at line {}", i + 1),
+            ));
+        }
+        bars.push(OhlcBar {
+            ts: fields[0].to_owned(),
+            open: number(1)?,
+            high: number(2)?,
+            low: number(3)?,
+            close: number(4)?,
+            volume: number(5)?,
+            amount: number(6)?,
+        });
+    }
+    Ok(bars)
+}
+
+// 日线只有日期时对齐到收盘时刻，与数据库中的日K线一致
+fn parse_bar_ts(s: &str, tick: Tick) -> Result<NaiveDateTime> {
+    let (ts, date_only) = parse_ts_from_str(s)
+        .map_err(|_| Error::custom(ErrorKind::BadRequest, format!("invalid timestamp {}", s)))?;
+    if date_only && tick == Tick::D1 {
+        return Ok(ts.date().and_time(*AFTERNOON_END));
+    }
+    Ok(ts)
+}
+
+fn validate_bars(prices: &[StockPrice]) -> Result<()> {
+    if prices.is_empty() {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            "empty ohlc series".to_owned(),
+        ));
+    }
+    if prices.len() > MAX_BARS {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            format!("too many bars: {} > {}", prices.len(), MAX_BARS),
+        ));
+    }
+    for (i, p) in prices.iter().enumerate() {
+        if p.high < p.low {
+            return Err(Error::custom(
+                ErrorKind::BadRequest,
+                format!("high is less than low at {}", p.ts),
+            ));
+        }
+        if i > 0 && prices[i - 1].ts >= p.ts {
+            return Err(Error::custom(
+                ErrorKind::BadRequest,
+                format!("timestamps not strictly ascending at {}", p.ts),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bars() {
+        let csv =
+            "ts,open,high,low,close\n2020-08-06,10,10.5,9.8,10.2\n2020-08-07,10.2,10.8,10.1,10.6\n";
+        let prices = parse_bars(csv.as_bytes(), Tick::D1).unwrap();
+        assert_eq!(2, prices.len());
+        assert_eq!("2020-08-07 15:00:00", prices[1].ts.to_string());
+        assert_eq!(parse_price("10.6").unwrap(), prices[1].close);
+        assert!(prices[1].volume.is_zero());
+
+        let json = r#"[{"ts":"2020-08-07 10:00","open":"1","high":"2","low":"1","close":"2","volume":"5"}]"#;
+        let prices = parse_bars(json.as_bytes(), Tick::M30).unwrap();
+        assert_eq!(BigDecimal::from(5), prices[0].volume);
+
+        let unordered = "2020-08-07,1,2,1,2\n2020-08-06,1,2,1,2";
+        assert!(parse_bars(unordered.as_bytes(), Tick::D1).is_err());
+        assert!(parse_bars(b"2020-08-07,1,2", Tick::D1).is_err());
+        assert!(parse_bars(b"", Tick::D1).is_err());
+
+        // 不接受指数形式的价格
+        assert!(parse_bars(b"2020-08-07,1,2,1,1e3", Tick::D1).is_err());
+        let json = r#"[{"ts":"2020-08-07 10:00","open":"1","high":"1e3","low":"1","close":"2"}]"#;
+        assert!(parse_bars(json.as_bytes(), Tick::M30).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! 响应裁剪
+//!
+//! 按输出配置处理序列化后的响应：按银行家舍入法限制小数位数，去除成交量及成交额，
+//! 去除原始形态等调试字段，用于减小移动端的数据量。
+//! 配置格式为precision:2,volume:false,debug:false，缺省时完整输出。
+//! 指定lang:en或lang:zh时追加枚举字段的本地化名称。
+
+use super::i18n::{self, Lang};
+use super::metrics::math;
+use crate::{Error, ErrorKind, Result};
+use bigdecimal::BigDecimal;
+use serde::Serialize;
+use serde_json::Value;
+use std::str::FromStr;
+
+// 成交量相关字段
+const VOLUME_FIELDS: [&str; 2] = ["volume", "amount"];
+// 调试字段，如包含关系处理前的原始K线及合并前的笔
+const DEBUG_FIELDS: [&str; 1] = ["orig"];
+
+/// 输出配置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputCfg {
+    // 小数位数，None表示不限制
+    pub precision: Option<usize>,
+    pub volume: bool,
+    pub debug: bool,
+    // 本地化语言，None表示不追加本地化名称
+    pub lang: Option<Lang>,
+}
+
+impl Default for OutputCfg {
+    fn default() -> Self {
+        OutputCfg {
+            precision: None,
+            volume: true,
+            debug: true,
+            lang: None,
+        }
+    }
+}
+
+pub fn parse_output_cfg(s: &str) -> Result<OutputCfg> {
+    let mut cfg = OutputCfg::default();
+    for c in s.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+        let invalid = || Error::custom(ErrorKind::BadRequest, format!("invalid output cfg: {}", c));
+        let mut kv = c.splitn(2, ':');
+        let k = kv.next().unwrap_or_default();
+        let v = kv.next().ok_or_else(invalid)?;
+        match k {
+            "precision" => cfg.precision = Some(v.parse().map_err(|_| invalid())?),
+            "volume" => cfg.volume = v.parse().map_err(|_| invalid())?,
+            "debug" => cfg.debug = v.parse().map_err(|_| invalid())?,
+            "lang" => cfg.lang = Some(v.parse().map_err(|_| invalid())?),
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(cfg)
+}
+
+impl OutputCfg {
+    pub fn is_full(&self) -> bool {
+        self == &OutputCfg::default()
+    }
+
+    /// 序列化并裁剪
+    pub fn to_value<T: Serialize>(&self, t: &T) -> Value {
+        let mut v = serde_json::to_value(t).unwrap_or(Value::Null);
+        if !self.is_full() {
+            self.apply(&mut v);
+        }
+        if let Some(lang) = self.lang {
+            i18n::localize(&mut v, lang);
+        }
+        v
+    }
+
+    pub fn apply(&self, v: &mut Value) {
+        match v {
+            Value::Object(m) => {
+                if !self.volume {
+                    for k in &VOLUME_FIELDS {
+                        m.remove(*k);
+                    }
+                }
+                if !self.debug {
+                    for k in &DEBUG_FIELDS {
+                        m.remove(*k);
+                    }
+                }
+                m.values_mut().for_each(|v| self.apply(v));
+            }
+            Value::Array(vs) => vs.iter_mut().for_each(|v| self.apply(v)),
+            Value::String(s) => {
+                if let Some(p) = self.precision {
+                    if let Some(r) = round_decimal(s, p) {
+                        *s = r;
+                    }
+                }
+            }
+            Value::Number(n) => {
+                // 整数不处理
+                let f = n.as_f64().filter(|_| n.is_f64());
+                if let (Some(p), Some(f)) = (self.precision, f) {
+                    let r = BigDecimal::from_str(&f.to_string())
+                        .ok()
+                        .map(|d| math::round_half_even(&d, p as i64).to_string())
+                        .and_then(|r| r.parse().ok())
+                        .and_then(serde_json::Number::from_f64);
+                    if let Some(r) = r {
+                        *n = r;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+// 小数位数超过精度的十进制字符串按银行家舍入，其他字符串（如代码、日期）不处理
+fn round_decimal(s: &str, precision: usize) -> Option<String> {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let (int_part, frac_part) = digits.split_once('.')?;
+    if int_part.is_empty()
+        || !int_part.bytes().all(|b| b.is_ascii_digit())
+        || !frac_part.bytes().all(|b| b.is_ascii_digit())
+        || frac_part.len() <= precision
+    {
+        return None;
+    }
+    let d = BigDecimal::from_str(s).ok()?;
+    Some(math::round_half_even(&d, precision as i64).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_cfg() -> Result<()> {
+        assert!(parse_output_cfg("")?.is_full());
+        let cfg = parse_output_cfg("precision:2,volume:false,debug:false")?;
+        assert_eq!(Some(2), cfg.precision);
+        assert!(parse_output_cfg("precision:abc").is_err());
+        assert!(parse_output_cfg("unknown:1").is_err());
+        assert_eq!(Some(Lang::En), parse_output_cfg("lang:en")?.lang);
+
+        let mut v = serde_json::json!([{
+            "ts": "2020-07-06T10:30:00",
+            "code": "600000.XSHG",
+            "close": "10.12567",
+            "low": "-3.005",
+            "open": "10.135",
+            "high": "10.1",
+            "volume": "12345.00",
+            "amount": "124999.5",
+            "ratio": 0.123456,
+            "orig": {"close": "10.1"},
+        }]);
+        cfg.apply(&mut v);
+        assert_eq!(
+            serde_json::json!([{
+                "ts": "2020-07-06T10:30:00",
+                "code": "600000.XSHG",
+                "close": "10.13",
+                "low": "-3.00",
+                "open": "10.14",
+                "high": "10.1",
+                "ratio": 0.12,
+            }]),
+            v
+        );
+        Ok(())
+    }
+}
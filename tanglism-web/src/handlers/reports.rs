@@ -0,0 +1,563 @@
+//! 走势结构周报
+//!
+//! 按周汇总自选股的结构变化：线段方向的转变、新形成的中枢及线段转折信号，
+//! 生成Markdown或HTML报告并存入数据库，代替人工逐只复盘。
+//! 周初的结构仅由周初之前的K线计算，避免使用未来数据。
+
+use super::audit::AnalysisConfig;
+use super::stock_prices::{self, ticks};
+use super::tanglism;
+use super::webhooks;
+use crate::models::{NewReport, Report};
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result};
+use bigdecimal::BigDecimal;
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use diesel::prelude::*;
+use serde_derive::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::str::FromStr;
+use tanglism_morph::{CenterConfig, PartingConfig, Segment, StrokeConfig, TrendConfig};
+use tanglism_utils::Tick;
+
+// 报告使用30分钟K线
+const REPORT_TICK: Tick = Tick::M30;
+// 回溯的自然日数，保证周初已形成足够的结构
+const REPORT_LOOKBACK_DAYS: i64 = 90;
+// 定时任务的检查间隔
+pub(crate) const REPORT_CHECK_INTERVAL_SECS: u64 = 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+impl ReportFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReportFormat::Markdown => "markdown",
+            ReportFormat::Html => "html",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ReportFormat::Markdown => "text/markdown; charset=utf-8",
+            ReportFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "markdown" | "md" => Ok(ReportFormat::Markdown),
+            "html" => Ok(ReportFormat::Html),
+            _ => Err(Error::custom(
+                ErrorKind::BadRequest,
+                format!("invalid report format: {}", s),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// 单只股票的周度汇总
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StockSummary {
+    pub code: String,
+    pub display_name: String,
+    // 周初及周末最后一段线段的方向，无线段时为空
+    pub trend_before: Option<Direction>,
+    pub trend_after: Option<Direction>,
+    pub new_centers: Vec<CenterSummary>,
+    pub signals: Vec<Signal>,
+    pub chart_link: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CenterSummary {
+    pub start_ts: NaiveDateTime,
+    pub end_ts: NaiveDateTime,
+    pub shared_low: BigDecimal,
+    pub shared_high: BigDecimal,
+    pub upward: bool,
+}
+
+/// 本周新完成的线段，其终点即转折信号
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Signal {
+    pub ts: NaiveDateTime,
+    // 向上线段结束于顶，向下线段结束于底
+    pub top: bool,
+    pub price: BigDecimal,
+}
+
+/// 报告列表项，不含正文
+#[derive(Debug, Clone, Queryable, Serialize, Deserialize)]
+pub struct ReportInfo {
+    pub id: i32,
+    pub week_start: NaiveDate,
+    pub week_end: NaiveDate,
+    pub format: String,
+    pub codes: Vec<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// 指定日期所在的周，周一至周日
+pub fn week_of(dt: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let week_start = dt - Duration::days(dt.weekday().num_days_from_monday() as i64);
+    (week_start, week_start + Duration::days(6))
+}
+
+/// 最近一个已收盘的完整周，周末当天即为本周
+pub fn last_complete_week(today: NaiveDate) -> (NaiveDate, NaiveDate) {
+    match today.weekday() {
+        Weekday::Sat | Weekday::Sun => week_of(today),
+        _ => week_of(today - Duration::days(7)),
+    }
+}
+
+/// 根据K线计算周度汇总，week_start之前的K线视为周初已知数据
+pub fn summarize_stock(
+    code: &str,
+    display_name: &str,
+    prices: &[ticks::StockPrice],
+    week_start: NaiveDate,
+    week_end: NaiveDate,
+) -> Result<StockSummary> {
+    let week_start_ts = week_start.and_time(NaiveTime::MIN);
+    let known = prices.iter().take_while(|p| p.ts < week_start_ts).count();
+    let (segments_before, centers_before) = analyze(&prices[..known])?;
+    let (segments_after, centers_after) = analyze(prices)?;
+
+    let centers_known: HashSet<NaiveDateTime> = centers_before.iter().map(|c| c.start_ts).collect();
+    let new_centers = centers_after
+        .into_iter()
+        .filter(|c| !centers_known.contains(&c.start_ts))
+        .collect();
+    let segments_known: HashSet<(NaiveDateTime, NaiveDateTime)> =
+        segments_before.iter().map(segment_key).collect();
+    let signals = segments_after
+        .iter()
+        .filter(|sg| !segments_known.contains(&segment_key(sg)))
+        .map(|sg| Signal {
+            ts: sg.end_pt.extremum_ts,
+            top: sg.end_pt.top,
+            price: sg.end_price().clone(),
+        })
+        .collect();
+    Ok(StockSummary {
+        code: code.to_owned(),
+        display_name: display_name.to_owned(),
+        trend_before: segments_before.last().map(segment_direction),
+        trend_after: segments_after.last().map(segment_direction),
+        new_centers,
+        signals,
+        chart_link: format!(
+            "/static/index.html?code={}&tick={}&start_dt={}&end_dt={}",
+            code, REPORT_TICK, week_start, week_end
+        ),
+    })
+}
+
+fn analyze(prices: &[ticks::StockPrice]) -> Result<(Vec<Segment>, Vec<CenterSummary>)> {
+    let pts = tanglism::get_tanglism_partings(prices, &PartingConfig::default())?;
+    let sks = tanglism::get_tanglism_strokes(&pts, REPORT_TICK, StrokeConfig::default())?;
+    let sgs = tanglism::get_tanglism_segments(&sks)?;
+    let center_cfg = CenterConfig::default();
+    let sts = tanglism::get_tanglism_subtrends(&sgs, &sks, REPORT_TICK, 1, &center_cfg)?;
+    let centers = tanglism::get_tanglism_centers(&sts, &center_cfg)?
+        .iter()
+        .filter_map(|ce| ce.center())
+        .map(|c| CenterSummary {
+            start_ts: c.start.ts,
+            end_ts: c.end.ts,
+            shared_low: c.shared_low.value.clone(),
+            shared_high: c.shared_high.value.clone(),
+            upward: c.upward,
+        })
+        .collect();
+    Ok((sgs, centers))
+}
+
+fn segment_key(sg: &Segment) -> (NaiveDateTime, NaiveDateTime) {
+    (sg.start_pt.extremum_ts, sg.end_pt.extremum_ts)
+}
+
+fn segment_direction(sg: &Segment) -> Direction {
+    if sg.end_price() > sg.start_price() {
+        Direction::Up
+    } else {
+        Direction::Down
+    }
+}
+
+fn direction_str(d: Option<Direction>) -> &'static str {
+    match d {
+        Some(Direction::Up) => "向上",
+        Some(Direction::Down) => "向下",
+        None => "无",
+    }
+}
+
+fn trend_line(s: &StockSummary) -> String {
+    if s.trend_before == s.trend_after {
+        format!("{}（未变）", direction_str(s.trend_after))
+    } else {
+        format!(
+            "{} → {}（转变）",
+            direction_str(s.trend_before),
+            direction_str(s.trend_after)
+        )
+    }
+}
+
+fn signal_str(sg: &Signal) -> String {
+    format!(
+        "{} {} {}",
+        sg.ts.format("%Y-%m-%d %H:%M"),
+        if sg.top { "线段顶" } else { "线段底" },
+        sg.price
+    )
+}
+
+fn center_str(c: &CenterSummary) -> String {
+    format!(
+        "{} ~ {} 区间 {} ~ {}",
+        c.start_ts.format("%Y-%m-%d %H:%M"),
+        c.end_ts.format("%Y-%m-%d %H:%M"),
+        c.shared_low,
+        c.shared_high
+    )
+}
+
+pub fn render_markdown(
+    week_start: NaiveDate,
+    week_end: NaiveDate,
+    summaries: &[StockSummary],
+) -> String {
+    let mut out = String::new();
+    writeln!(out, "# 走势周报 {} ~ {}", week_start, week_end).unwrap();
+    for s in summaries {
+        writeln!(out).unwrap();
+        writeln!(out, "## {} {}", s.code, s.display_name).unwrap();
+        writeln!(out).unwrap();
+        writeln!(out, "- 走势：{}", trend_line(s)).unwrap();
+        writeln!(out, "- 新中枢：{}个", s.new_centers.len()).unwrap();
+        for c in &s.new_centers {
+            writeln!(out, "  - {}", center_str(c)).unwrap();
+        }
+        writeln!(out, "- 信号：{}个", s.signals.len()).unwrap();
+        for sg in &s.signals {
+            writeln!(out, "  - {}", signal_str(sg)).unwrap();
+        }
+        writeln!(out, "- [图表]({})", s.chart_link).unwrap();
+    }
+    out
+}
+
+pub fn render_html(
+    week_start: NaiveDate,
+    week_end: NaiveDate,
+    summaries: &[StockSummary],
+) -> String {
+    let mut out = String::new();
+    let title = format!("走势周报 {} ~ {}", week_start, week_end);
+    writeln!(
+        out,
+        "<html><head><meta charset=\"utf-8\"><title>{}</title></head><body>",
+        html_escape(&title)
+    )
+    .unwrap();
+    writeln!(out, "<h1>{}</h1>", html_escape(&title)).unwrap();
+    for s in summaries {
+        writeln!(
+            out,
+            "<h2>{} {}</h2>",
+            html_escape(&s.code),
+            html_escape(&s.display_name)
+        )
+        .unwrap();
+        writeln!(out, "<ul>").unwrap();
+        writeln!(out, "<li>走势：{}</li>", html_escape(&trend_line(s))).unwrap();
+        writeln!(out, "<li>新中枢：{}个<ul>", s.new_centers.len()).unwrap();
+        for c in &s.new_centers {
+            writeln!(out, "<li>{}</li>", html_escape(&center_str(c))).unwrap();
+        }
+        writeln!(out, "</ul></li>").unwrap();
+        writeln!(out, "<li>信号：{}个<ul>", s.signals.len()).unwrap();
+        for sg in &s.signals {
+            writeln!(out, "<li>{}</li>", html_escape(&signal_str(sg))).unwrap();
+        }
+        writeln!(out, "</ul></li>").unwrap();
+        writeln!(
+            out,
+            "<li><a href=\"{}\">图表</a></li>",
+            html_escape(&s.chart_link)
+        )
+        .unwrap();
+        writeln!(out, "</ul>").unwrap();
+    }
+    writeln!(out, "</body></html>").unwrap();
+    out
+}
+
+// 转义文本及属性值，所有插入HTML的字符串均须经过此函数
+fn html_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// 生成指定周的报告并保存
+///
+/// 单只股票分析失败时跳过该股票，不影响整份报告
+pub async fn generate_weekly_report(
+    pool: &DbPool,
+    jq: &JqdataPool,
+    codes: &[String],
+    week_start: NaiveDate,
+    format: ReportFormat,
+) -> Result<Report> {
+    let (week_start, week_end) = week_of(week_start);
+    // 当天的数据尚未收盘
+    let yesterday = Local::now().naive_local().date() - Duration::days(1);
+    let data_end = week_end.min(yesterday);
+    if data_end < week_start {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            format!("week of {} has no closed trading day", week_start),
+        ));
+    }
+    let start_ts = (week_start - Duration::days(REPORT_LOOKBACK_DAYS)).and_time(NaiveTime::MIN);
+    let end_ts = (data_end + Duration::days(1)).and_time(NaiveTime::MIN) - Duration::seconds(1);
+    let names = display_names(pool.clone(), codes.to_vec()).await?;
+    let mut summaries = Vec::with_capacity(codes.len());
+    for code in codes {
+        let prices = match stock_prices::get_stock_tick_prices(
+            pool,
+            jq,
+            REPORT_TICK,
+            code,
+            start_ts,
+            end_ts,
+        )
+        .await
+        {
+            Ok(prices) => prices,
+            Err(e) => {
+                log::warn!("skip {} in weekly report: {}", code, e);
+                continue;
+            }
+        };
+        let name = names.get(code).map(String::as_str).unwrap_or("");
+        match summarize_stock(code, name, &prices, week_start, week_end) {
+            Ok(summary) => summaries.push(summary),
+            Err(e) => log::warn!("skip {} in weekly report: {}", code, e),
+        }
+    }
+    let content = match format {
+        ReportFormat::Markdown => render_markdown(week_start, week_end, &summaries),
+        ReportFormat::Html => render_html(week_start, week_end, &summaries),
+    };
+    // 与summarize_stock使用的配置一致
+    let mut config = AnalysisConfig::new(
+        summaries.iter().map(|s| s.code.clone()).collect(),
+        REPORT_TICK,
+        &PartingConfig::default(),
+        &StrokeConfig::default(),
+        &TrendConfig {
+            level: 1,
+            center: CenterConfig::default(),
+        },
+    );
+    config.data_end_ts = Some(end_ts);
+    let report = NewReport {
+        week_start,
+        week_end,
+        format: format.as_str().to_owned(),
+        codes: summaries.iter().map(|s| s.code.clone()).collect(),
+        content,
+        created_at: Local::now().naive_local(),
+        config: config.to_json()?,
+    };
+    let db = pool.clone();
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::reports;
+        let conn = db.get()?;
+        diesel::insert_into(reports::table)
+            .values(&report)
+            .get_result::<Report>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    // 推送失败不影响周报生成
+    let event = serde_json::json!({
+        "id": data.id,
+        "week_start": data.week_start,
+        "week_end": data.week_end,
+        "format": data.format,
+        "codes": data.codes,
+    });
+    if let Err(e) = webhooks::enqueue(pool.clone(), webhooks::EVENT_REPORT, &event).await {
+        log::warn!("failed to enqueue report webhook: {}", e);
+    }
+    Ok(data)
+}
+
+async fn display_names(pool: DbPool, input_codes: Vec<String>) -> Result<HashMap<String, String>> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::securities::dsl::*;
+        let conn = pool.get()?;
+        securities
+            .filter(code.eq_any(input_codes))
+            .select((code, display_name))
+            .load::<(String, String)>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data.into_iter().collect())
+}
+
+/// 列出已生成的报告，按周倒序
+pub async fn list_reports(pool: DbPool) -> Result<Vec<ReportInfo>> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::reports::dsl::*;
+        let conn = pool.get()?;
+        reports
+            .select((id, week_start, week_end, format, codes, created_at))
+            .order((week_start.desc(), id.desc()))
+            .load::<ReportInfo>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data)
+}
+
+pub async fn get_report(pool: DbPool, report_id: i32) -> Result<Report> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::reports::dsl::*;
+        let conn = pool.get()?;
+        reports
+            .find(report_id)
+            .first::<Report>(&conn)
+            .optional()
+            .map_err(Error::from)
+    })
+    .await??;
+    data.ok_or_else(|| {
+        Error::custom(
+            ErrorKind::NotFound,
+            format!("report {} not found", report_id),
+        )
+    })
+}
+
+/// 报告生成时的配置
+pub async fn get_report_config(pool: DbPool, report_id: i32) -> Result<AnalysisConfig> {
+    let report = get_report(pool, report_id).await?;
+    AnalysisConfig::from_json(&report.config)
+}
+
+pub(crate) async fn report_exists(
+    pool: DbPool,
+    input_week_start: NaiveDate,
+    input_format: &str,
+) -> Result<bool> {
+    let input_format = input_format.to_owned();
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::reports::dsl::*;
+        let conn = pool.get()?;
+        reports
+            .filter(week_start.eq(input_week_start).and(format.eq(input_format)))
+            .count()
+            .get_result::<i64>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(n > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dt(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn test_last_complete_week() {
+        // 2020-08-05为周三，取上一周
+        assert_eq!(
+            (dt("2020-07-27"), dt("2020-08-02")),
+            last_complete_week(dt("2020-08-05"))
+        );
+        // 周六取本周
+        assert_eq!(
+            (dt("2020-08-03"), dt("2020-08-09")),
+            last_complete_week(dt("2020-08-08"))
+        );
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        let ts = dt("2020-07-29").and_hms_opt(14, 0, 0).unwrap();
+        let summary = StockSummary {
+            code: "600000.XSHG".to_owned(),
+            display_name: "浦发银行".to_owned(),
+            trend_before: Some(Direction::Down),
+            trend_after: Some(Direction::Up),
+            new_centers: vec![],
+            signals: vec![Signal {
+                ts,
+                top: false,
+                price: BigDecimal::from(10),
+            }],
+            chart_link: "/static/index.html".to_owned(),
+        };
+        let md = render_markdown(dt("2020-07-27"), dt("2020-08-02"), &[summary]);
+        assert!(md.starts_with("# 走势周报 2020-07-27 ~ 2020-08-02\n"));
+        assert!(md.contains("- 走势：向下 → 向上（转变）\n"));
+        assert!(md.contains("  - 2020-07-29 14:00 线段底 10\n"));
+    }
+
+    #[test]
+    fn test_render_html_escaped() {
+        let summary = StockSummary {
+            code: "600000.XSHG'".to_owned(),
+            display_name: "<script>alert(\"x\")</script>&".to_owned(),
+            trend_before: None,
+            trend_after: None,
+            new_centers: vec![],
+            signals: vec![],
+            chart_link: "/static/index.html?code=a\"onclick=\"x".to_owned(),
+        };
+        let html = render_html(dt("2020-07-27"), dt("2020-08-02"), &[summary]);
+        assert!(!html.contains("<script>"));
+        assert!(html.contains(
+            "<h2>600000.XSHG&#39; &lt;script&gt;alert(&quot;x&quot;)&lt;/script&gt;&amp;</h2>"
+        ));
+        assert!(html.contains("href=\"/static/index.html?code=a&quot;onclick=&quot;x\""));
+    }
+}
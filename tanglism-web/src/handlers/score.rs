@@ -0,0 +1,449 @@
+//! 综合评分
+//!
+//! 将形态状态与指标合成单一分值，用于对关注列表排序。
+//! 各项分量为：最后线段方向、收盘价相对最后中枢的位置、最后线段的MACD背驰、
+//! 平均ATRP，以及收盘价相对52周最高价的距离，分值为分量的加权和。
+//! 权重按优先级取自请求参数weights、用户保存的权重及默认值，并随结果返回。
+
+use crate::handlers::metrics::atr::{self, AtrInput};
+use crate::handlers::metrics::math::{div_round, round_half_even, METRIC_SCALE};
+use crate::handlers::metrics::{self, macd, Metric};
+use crate::handlers::stock_prices::{get_stock_tick_prices, ticks::StockPrice};
+use crate::handlers::tanglism;
+use crate::models::ScoreWeight;
+use crate::{BasicCfg, DbPool, Error, ErrorKind, JqdataPool, Result};
+use bigdecimal::{BigDecimal, One, Zero};
+use chrono::{Duration, Local, NaiveDateTime, NaiveTime};
+use diesel::prelude::*;
+use serde_derive::*;
+use tanglism_morph::{CenterConfig, PartingConfig, Segment, StrokeConfig};
+use tanglism_utils::{parse_price, resolve_end_ts, Tick};
+
+/// 单次请求最多的股票数
+pub const MAX_CODES: usize = 50;
+// 52周最高价回溯的自然日数
+const HIGH_LOOKBACK_DAYS: i64 = 365;
+const MAX_SCORE_DAYS: i64 = 3650;
+
+/// 各分量的权重
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScoreWeights {
+    pub trend: BigDecimal,
+    pub center: BigDecimal,
+    pub divergence: BigDecimal,
+    pub atrp: BigDecimal,
+    pub high_distance: BigDecimal,
+}
+
+impl Default for ScoreWeights {
+    // 波动越大扣分越多，ATRP通常为百分之几，权重相应放大
+    fn default() -> Self {
+        ScoreWeights {
+            trend: BigDecimal::from(1),
+            center: BigDecimal::from(1),
+            divergence: BigDecimal::from(1),
+            atrp: BigDecimal::from(-10),
+            high_distance: BigDecimal::from(2),
+        }
+    }
+}
+
+impl ScoreWeights {
+    fn weighted(&self, c: &ScoreComponents) -> BigDecimal {
+        let sum = &self.trend * &c.trend
+            + &self.center * &c.center
+            + &self.divergence * &c.divergence
+            + &self.atrp * &c.atrp
+            + &self.high_distance * &c.high_distance;
+        round_half_even(&sum, METRIC_SCALE)
+    }
+}
+
+/// 解析形如trend:1,atrp:-5的权重，未指定的分量使用默认值
+///
+/// 权重按parse_price严格解析，不接受指数形式
+pub fn parse_weights(s: &str) -> Result<ScoreWeights> {
+    let mut weights = ScoreWeights::default();
+    for item in s.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+        let invalid = || Error::custom(ErrorKind::BadRequest, format!("invalid weight: {}", item));
+        let (name, value) = item.split_once(':').ok_or_else(invalid)?;
+        let value = parse_price(value.trim()).map_err(|_| invalid())?;
+        match name.trim() {
+            "trend" => weights.trend = value,
+            "center" => weights.center = value,
+            "divergence" => weights.divergence = value,
+            "atrp" => weights.atrp = value,
+            "high_distance" => weights.high_distance = value,
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(weights)
+}
+
+/// 生效权重的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightsSource {
+    Request,
+    User,
+    Default,
+}
+
+/// 评分分量
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScoreComponents {
+    // 最后线段向上为1，向下为-1，无线段为0
+    pub trend: BigDecimal,
+    // 收盘价高于最后中枢为1，低于为-1，位于中枢内或无中枢为0
+    pub center: BigDecimal,
+    // 最后向下线段底背驰为1，最后向上线段顶背驰为-1
+    pub divergence: BigDecimal,
+    // 平均ATRP
+    pub atrp: BigDecimal,
+    // 收盘价相对52周最高价的比例减1，不大于0
+    pub high_distance: BigDecimal,
+}
+
+/// 单只股票的评分，查询失败时记录原因，不影响其他股票
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StockScore {
+    pub code: String,
+    pub score: Option<BigDecimal>,
+    pub components: Option<ScoreComponents>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 按分值降序排列的评分，失败的股票排在最后
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoreBoard {
+    pub tick: Tick,
+    pub user: Option<String>,
+    pub weights: ScoreWeights,
+    pub weights_source: WeightsSource,
+    pub items: Vec<StockScore>,
+}
+
+/// 评分请求
+#[derive(Debug, Clone)]
+pub struct ScoreRequest {
+    pub codes: Vec<String>,
+    pub tick: Tick,
+    // 形态及指标回溯的自然日数
+    pub days: i64,
+    pub user: Option<String>,
+    pub weights: Option<ScoreWeights>,
+}
+
+/// 形态及指标默认回溯的自然日数
+pub fn default_days(tick: Tick) -> i64 {
+    if tick == Tick::D1 {
+        365
+    } else {
+        20
+    }
+}
+
+/// 批量评分并排序
+pub async fn score_stocks(pool: &DbPool, jq: &JqdataPool, req: ScoreRequest) -> Result<ScoreBoard> {
+    if req.codes.is_empty() || req.codes.len() > MAX_CODES {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            format!("score requires 1 to {} codes", MAX_CODES),
+        ));
+    }
+    if req.days < 1 || req.days > MAX_SCORE_DAYS {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            format!("score days must be within 1 to {}", MAX_SCORE_DAYS),
+        ));
+    }
+    let (weights, weights_source) = match (req.weights, &req.user) {
+        (Some(weights), _) => (weights, WeightsSource::Request),
+        (None, Some(user)) => match get_score_weights(pool.clone(), user.clone()).await? {
+            Some(weights) => (weights, WeightsSource::User),
+            None => (ScoreWeights::default(), WeightsSource::Default),
+        },
+        (None, None) => (ScoreWeights::default(), WeightsSource::Default),
+    };
+    let now = Local::now().naive_local();
+    let mut items = Vec::with_capacity(req.codes.len());
+    for code in &req.codes {
+        let item = match score_components(pool, jq, code, req.tick, req.days, now).await {
+            Ok(c) => StockScore {
+                code: code.clone(),
+                score: Some(weights.weighted(&c)),
+                components: Some(c),
+                error: None,
+            },
+            Err(e) => StockScore {
+                code: code.clone(),
+                score: None,
+                components: None,
+                error: Some(e.to_string()),
+            },
+        };
+        items.push(item);
+    }
+    rank(&mut items);
+    Ok(ScoreBoard {
+        tick: req.tick,
+        user: req.user,
+        weights,
+        weights_source,
+        items,
+    })
+}
+
+// 分值降序，同分按代码升序
+fn rank(items: &mut [StockScore]) {
+    items.sort_by(|a, b| match (&a.score, &b.score) {
+        (Some(x), Some(y)) => y.cmp(x).then_with(|| a.code.cmp(&b.code)),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a.code.cmp(&b.code),
+    });
+}
+
+async fn score_components(
+    pool: &DbPool,
+    jq: &JqdataPool,
+    code: &str,
+    tick: Tick,
+    days: i64,
+    now: NaiveDateTime,
+) -> Result<ScoreComponents> {
+    let end_ts = resolve_end_ts(None, tick, now)?;
+    // 回溯区间以最后一根K线为准，交易日历可能早于当前时刻结束
+    let start_ts = (end_ts - Duration::days(days))
+        .date()
+        .and_time(NaiveTime::MIN);
+    let prices = get_stock_tick_prices(pool, jq, tick, code, start_ts, end_ts).await?;
+    let daily_end_ts = resolve_end_ts(None, Tick::D1, now)?;
+    let daily_start_ts = (daily_end_ts - Duration::days(HIGH_LOOKBACK_DAYS))
+        .date()
+        .and_time(NaiveTime::MIN);
+    let daily =
+        get_stock_tick_prices(pool, jq, Tick::D1, code, daily_start_ts, daily_end_ts).await?;
+    let (macd_cfg, source) = macd::resolve_macd_cfg(pool, code, tick, None).await?;
+    let basic_cfg = BasicCfg {
+        tick,
+        code: code.to_owned(),
+        start_ts,
+        end_ts,
+        intraday: false,
+    };
+    let macd = metrics::get_metrics_macd(pool, jq, basic_cfg, macd_cfg, source).await?;
+    components_of(tick, &prices, &daily, &macd.macd)
+}
+
+/// 由K线、52周日线及MACD柱计算评分分量
+pub fn components_of(
+    tick: Tick,
+    prices: &[StockPrice],
+    daily: &[StockPrice],
+    macd: &[Metric],
+) -> Result<ScoreComponents> {
+    let close = match prices.last() {
+        Some(p) => &p.close,
+        None => {
+            return Err(Error::custom(
+                ErrorKind::NotFound,
+                "no prices to score".to_owned(),
+            ))
+        }
+    };
+    let pts = tanglism::get_tanglism_partings(prices, &PartingConfig::default())?;
+    let sks = tanglism::get_tanglism_strokes(&pts, tick, StrokeConfig::default())?;
+    let sgs = tanglism::get_tanglism_segments(&sks)?;
+    let trend = match sgs.last() {
+        Some(sg) if sg.end_price() > sg.start_price() => 1,
+        Some(sg) if sg.end_price() < sg.start_price() => -1,
+        _ => 0,
+    };
+    let center_cfg = CenterConfig::default();
+    let sts = tanglism::get_tanglism_subtrends(&sgs, &sks, tick, 1, &center_cfg)?;
+    let cts = tanglism::get_tanglism_centers(&sts, &center_cfg)?;
+    let center = match cts.iter().rev().find_map(|ce| ce.center()) {
+        Some(c) if *close > c.shared_high.value => 1,
+        Some(c) if *close < c.shared_low.value => -1,
+        _ => 0,
+    };
+    let atrp = atr::atrp_stats(prices.windows(2).map(|w| AtrInput {
+        ts: w[1].ts,
+        curr_high: w[1].high.clone(),
+        curr_low: w[1].low.clone(),
+        prev_close: w[0].close.clone(),
+    }))
+    .avg;
+    let high_distance = daily
+        .iter()
+        .map(|p| &p.high)
+        .max()
+        .filter(|h| !h.is_zero())
+        .map(|h| div_round(close, h, METRIC_SCALE) - BigDecimal::one())
+        .unwrap_or_default();
+    Ok(ScoreComponents {
+        trend: BigDecimal::from(trend),
+        center: BigDecimal::from(center),
+        divergence: BigDecimal::from(macd_divergence(&sgs, macd)),
+        atrp,
+        high_distance,
+    })
+}
+
+// 最后线段与前一条同向线段比较，创新低（高）而MACD柱面积缩小即为背驰
+fn macd_divergence(sgs: &[Segment], macd: &[Metric]) -> i32 {
+    let last = match sgs.last() {
+        Some(sg) => sg,
+        None => return 0,
+    };
+    let down = last.end_price() < last.start_price();
+    let prev = sgs[..sgs.len() - 1]
+        .iter()
+        .rev()
+        .find(|sg| (sg.end_price() < sg.start_price()) == down);
+    let prev = match prev {
+        Some(sg) => sg,
+        None => return 0,
+    };
+    let area = |sg: &Segment| -> BigDecimal {
+        macd.iter()
+            .filter(|m| m.ts >= sg.start_pt.extremum_ts && m.ts <= sg.end_pt.extremum_ts)
+            .map(|m| m.value.abs())
+            .sum()
+    };
+    if down && last.end_price() < prev.end_price() && area(last) < area(prev) {
+        1
+    } else if !down && last.end_price() > prev.end_price() && area(last) < area(prev) {
+        -1
+    } else {
+        0
+    }
+}
+
+/// 查询用户保存的权重
+pub async fn get_score_weights(pool: DbPool, input_user: String) -> Result<Option<ScoreWeights>> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::score_weights::dsl::*;
+        let conn = pool.get()?;
+        score_weights
+            .find(input_user)
+            .first::<ScoreWeight>(&conn)
+            .optional()
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data.map(|w| ScoreWeights {
+        trend: w.trend,
+        center: w.center,
+        divergence: w.divergence,
+        atrp: w.atrp,
+        high_distance: w.high_distance,
+    }))
+}
+
+/// 保存用户的权重
+pub async fn save_score_weights(
+    pool: DbPool,
+    input_user: String,
+    weights: ScoreWeights,
+) -> Result<ScoreWeights> {
+    let record = ScoreWeight {
+        user_name: input_user,
+        trend: weights.trend.clone(),
+        center: weights.center.clone(),
+        divergence: weights.divergence.clone(),
+        atrp: weights.atrp.clone(),
+        high_distance: weights.high_distance.clone(),
+        updated_at: Local::now().naive_local(),
+    };
+    tokio::task::spawn_blocking(move || {
+        use crate::schema::score_weights::dsl::*;
+        let conn = pool.get()?;
+        diesel::insert_into(score_weights)
+            .values(&record)
+            .on_conflict(user_name)
+            .do_update()
+            .set((
+                trend.eq(&record.trend),
+                center.eq(&record.center),
+                divergence.eq(&record.divergence),
+                atrp.eq(&record.atrp),
+                high_distance.eq(&record.high_distance),
+                updated_at.eq(record.updated_at),
+            ))
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(weights)
+}
+
+/// 删除用户的权重，此后使用默认值
+pub async fn delete_score_weights(pool: DbPool, input_user: String) -> Result<()> {
+    let not_found = format!("score weights of {} not found", input_user);
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::score_weights::dsl::*;
+        let conn = pool.get()?;
+        diesel::delete(score_weights.find(input_user))
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    if n == 0 {
+        return Err(Error::custom(ErrorKind::NotFound, not_found));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tanglism_utils::price;
+
+    #[test]
+    fn test_parse_weights() -> Result<()> {
+        let weights = parse_weights("trend:2, atrp:-5")?;
+        assert_eq!(BigDecimal::from(2), weights.trend);
+        assert_eq!(BigDecimal::from(-5), weights.atrp);
+        assert_eq!(ScoreWeights::default().center, weights.center);
+        assert!(parse_weights("volume:1").is_err());
+        assert!(parse_weights("trend:x").is_err());
+        assert!(parse_weights("trend:1e3").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_weighted_rank() {
+        let weights = ScoreWeights::default();
+        let score = |trend: i32, atrp: &str| StockScore {
+            code: format!("{}{}", trend, atrp),
+            score: Some(weights.weighted(&ScoreComponents {
+                trend: BigDecimal::from(trend),
+                center: BigDecimal::zero(),
+                divergence: BigDecimal::zero(),
+                atrp: parse_price(atrp).unwrap(),
+                high_distance: price!(-0.1),
+            })),
+            components: None,
+            error: None,
+        };
+        let mut items = vec![
+            StockScore {
+                code: "failed".to_owned(),
+                score: None,
+                components: None,
+                error: Some("no data".to_owned()),
+            },
+            score(1, "0.05"),
+            score(-1, "0.01"),
+            score(1, "0.02"),
+        ];
+        rank(&mut items);
+        // 1 - 0.2 - 0.2 = 0.6
+        assert_eq!(Some(price!(0.6)), items[0].score);
+        let codes: Vec<_> = items.iter().map(|s| s.code.as_str()).collect();
+        assert_eq!(vec!["10.02", "10.05", "-10.01", "failed"], codes);
+    }
+}
@@ -0,0 +1,203 @@
+//! 形态分布统计
+//!
+//! 对股票历史的笔、线段及中枢统计分布，以直方图返回，
+//! 用于检验配置是否合理以及比较不同股票。
+
+use super::stock_prices;
+use super::tanglism;
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result, ResultExt};
+use bigdecimal::{BigDecimal, ToPrimitive};
+use chrono::{Local, NaiveDate, NaiveTime};
+use serde_derive::*;
+use tanglism_morph::{CenterConfig, PartingConfig, StrokeConfig};
+use tanglism_utils::{resolve_end_ts, LocalTradingTimestamps, Tick, TradingTimestamps};
+
+const DEFAULT_BINS: usize = 10;
+const MAX_BINS: usize = 100;
+
+/// 统计查询参数
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatsParam {
+    pub tick: Tick,
+    pub start_dt: NaiveDate,
+    pub end_dt: Option<NaiveDate>,
+    pub stroke_cfg: Option<String>,
+    // 直方图的分组数，默认10
+    pub bins: Option<usize>,
+}
+
+/// 等宽直方图，edges比counts多一个元素
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub edges: Vec<f64>,
+    pub counts: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShapeStats {
+    pub code: String,
+    pub tick: Tick,
+    pub start_dt: NaiveDate,
+    pub end_dt: NaiveDate,
+    // 笔的K线数
+    pub stroke_bars: Histogram,
+    // 笔的幅度，百分比
+    pub stroke_amplitude: Histogram,
+    // 线段包含的笔数
+    pub segment_strokes: Histogram,
+    // 中枢持续的K线数
+    pub center_bars: Histogram,
+}
+
+pub async fn get_shape_stats(
+    db: &DbPool,
+    jq: &JqdataPool,
+    code: &str,
+    param: StatsParam,
+) -> Result<ShapeStats> {
+    let bins = param.bins.unwrap_or(DEFAULT_BINS);
+    if bins == 0 || bins > MAX_BINS {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            format!("bins {} out of range 1 ~ {}", bins, MAX_BINS),
+        ));
+    }
+    let stroke_cfg = match param.stroke_cfg {
+        Some(ref s) => tanglism::parse_stroke_cfg(s).context("invalid stroke cfg")?,
+        None => StrokeConfig::default(),
+    };
+    let end_ts = match param.end_dt {
+        Some(dt) => dt.and_time(NaiveTime::MIN) + chrono::Duration::days(1),
+        None => resolve_end_ts(None, param.tick, Local::now().naive_local())?,
+    };
+    let prices = stock_prices::get_stock_tick_prices(
+        db,
+        jq,
+        param.tick,
+        code,
+        param.start_dt.and_time(NaiveTime::MIN),
+        end_ts,
+    )
+    .await?;
+    let tts = LocalTradingTimestamps::new(param.tick);
+    let pts = tanglism::get_tanglism_partings(&prices, &PartingConfig::default())?;
+    let sks = tanglism::get_tanglism_strokes(&pts, param.tick, stroke_cfg)?;
+    let sgs = tanglism::get_tanglism_segments(&sks)?;
+    let center_cfg = CenterConfig::default();
+    let sts = tanglism::get_tanglism_subtrends(&sgs, &sks, param.tick, 1, &center_cfg)?;
+    let centers = tanglism::get_tanglism_centers(&sts, &center_cfg)?;
+
+    let stroke_bars: Vec<f64> = sks
+        .iter()
+        .map(|sk| {
+            tts.bars_between(sk.start_pt.extremum_ts, sk.end_pt.extremum_ts, param.tick) as f64
+        })
+        .collect();
+    let stroke_amplitude: Vec<f64> = sks
+        .iter()
+        .filter_map(|sk| amplitude_pct(sk.start_price(), sk.end_price()))
+        .collect();
+    let segment_strokes: Vec<f64> = sgs
+        .iter()
+        .filter_map(|sg| sg.stroke_range.map(|(s, e)| (e - s + 1) as f64))
+        .collect();
+    let center_bars: Vec<f64> = centers
+        .iter()
+        .filter_map(|ce| ce.center())
+        .map(|c| tts.bars_between(c.start.ts, c.end.ts, param.tick) as f64)
+        .collect();
+    Ok(ShapeStats {
+        code: code.to_owned(),
+        tick: param.tick,
+        start_dt: param.start_dt,
+        end_dt: prices.last().map(|p| p.ts.date()).unwrap_or(param.start_dt),
+        stroke_bars: histogram(&stroke_bars, bins),
+        stroke_amplitude: histogram(&stroke_amplitude, bins),
+        segment_strokes: histogram(&segment_strokes, bins),
+        center_bars: histogram(&center_bars, bins),
+    })
+}
+
+fn amplitude_pct(start: &BigDecimal, end: &BigDecimal) -> Option<f64> {
+    let start = start.to_f64()?;
+    let end = end.to_f64()?;
+    if start == 0.0 {
+        return None;
+    }
+    Some((end - start).abs() / start * 100.0)
+}
+
+/// 计算等宽直方图，最大值归入最后一组
+pub fn histogram(values: &[f64], bins: usize) -> Histogram {
+    if values.is_empty() || bins == 0 {
+        return Histogram::default();
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let n = sorted.len();
+    let min = sorted[0];
+    let max = sorted[n - 1];
+    let median = if n % 2 == 1 {
+        sorted[n / 2]
+    } else {
+        (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+    };
+    // 所有值相同时仅一组
+    let bins = if max > min { bins } else { 1 };
+    let width = (max - min) / bins as f64;
+    let edges = (0..=bins)
+        .map(|i| {
+            if i == bins {
+                max
+            } else {
+                min + width * i as f64
+            }
+        })
+        .collect();
+    let mut counts = vec![0; bins];
+    for v in &sorted {
+        let idx = if width > 0.0 {
+            (((v - min) / width) as usize).min(bins - 1)
+        } else {
+            0
+        };
+        counts[idx] += 1;
+    }
+    Histogram {
+        count: n,
+        min,
+        max,
+        mean: sorted.iter().sum::<f64>() / n as f64,
+        median,
+        edges,
+        counts,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let h = histogram(&[5.0, 1.0, 3.0, 2.0, 9.0, 4.0], 4);
+        assert_eq!(6, h.count);
+        assert_eq!(1.0, h.min);
+        assert_eq!(9.0, h.max);
+        assert_eq!(4.0, h.mean);
+        assert_eq!(3.5, h.median);
+        assert_eq!(vec![1.0, 3.0, 5.0, 7.0, 9.0], h.edges);
+        // 最大值归入最后一组
+        assert_eq!(vec![2, 2, 1, 1], h.counts);
+
+        let h = histogram(&[2.0, 2.0], 10);
+        assert_eq!(vec![2], h.counts);
+        assert_eq!(vec![2.0, 2.0], h.edges);
+        assert_eq!(Histogram::default(), histogram(&[], 10));
+    }
+}
@@ -1,19 +1,27 @@
+pub mod after_hours;
+pub mod anomaly;
+pub mod cache;
+pub mod continuous;
+pub mod epoch;
+pub mod invalidation;
+pub mod last_bar;
+pub mod synthetic;
 pub mod ticks;
+pub mod verify;
 
-use crate::models::{StockPriceTick, StockTickPrice};
-use crate::{DbPool, Error, ErrorKind, Result};
+use crate::models::{self, StockPriceTick, StockTickPrice};
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result};
 use chrono::{NaiveDate, NaiveDateTime};
-use jqdata::JqdataClient;
 use lazy_static::*;
 use log::{debug, warn};
 use serde_derive::*;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tanglism_utils::{parse_ts_from_str, TradingDates, LOCAL_DATES};
+use tanglism_utils::{parse_ts_from_str, Tick, TradingDates, LOCAL_DATES};
 use tokio::sync::Mutex;
 
-// 批量插入操作的数量限制，受限于SQL的变量绑定<=65535
-const MAX_DB_INSERT_BATCH_SIZE: i64 = 5000;
+// 单次抓取的K线数上限，插入时按交易日分块提交
+const MAX_FILL_SIZE: i64 = 50_000;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Response<T> {
@@ -47,24 +55,46 @@ impl PriceTickAccess {
     }
 }
 
+/// 查询K线，期货连续合约代码由各合约的K线拼接，合成代码由两条腿的K线计算
 pub async fn get_stock_tick_prices(
     pool: &DbPool,
-    jq: &JqdataClient,
-    tick: &str,
+    jq: &JqdataPool,
+    tick: Tick,
     code: &str,
     start_ts: NaiveDateTime,
     end_ts: NaiveDateTime,
 ) -> Result<Vec<ticks::StockPrice>> {
-    // 仅支持1m, 5m, 30m, 1d
-    let tick = match tick {
-        "1m" | "5m" | "30m" | "1d" => tick.to_owned(),
-        _ => {
-            return Err(Error::custom(
-                ErrorKind::BadRequest,
-                format!("Invalid tick: {}", tick),
-            ))
-        }
-    };
+    if synthetic::is_synthetic_code(code) {
+        return synthetic::get_synthetic_prices(pool, jq, tick, code, start_ts, end_ts).await;
+    }
+    get_underlying_prices(pool, jq, tick, code, start_ts, end_ts).await
+}
+
+// 查询非合成代码的K线
+async fn get_underlying_prices(
+    pool: &DbPool,
+    jq: &JqdataPool,
+    tick: Tick,
+    code: &str,
+    start_ts: NaiveDateTime,
+    end_ts: NaiveDateTime,
+) -> Result<Vec<ticks::StockPrice>> {
+    match continuous::parse_continuous_code(code) {
+        Some(cc) => continuous::get_continuous_prices(pool, jq, tick, &cc, start_ts, end_ts).await,
+        None => get_cached_tick_prices(pool, jq, tick, code, start_ts, end_ts).await,
+    }
+}
+
+// 查询单个代码的K线，缺失的区间从数据源抓取并写入数据库
+async fn get_cached_tick_prices(
+    pool: &DbPool,
+    jq: &JqdataPool,
+    tick: Tick,
+    code: &str,
+    start_ts: NaiveDateTime,
+    end_ts: NaiveDateTime,
+) -> Result<Vec<ticks::StockPrice>> {
+    let tick = tick.to_string();
     // 起始时间大于结束时间或当天
     if start_ts > end_ts {
         return Err(Error::custom(
@@ -92,6 +122,13 @@ pub async fn get_stock_tick_prices(
         let pool = pool.clone();
         query_db_period(&pool, &tick, &code).await?
     };
+    let mut filled = false;
+    // 离线模式下保留失效数据，待联网后再替换
+    if !jq.is_offline()
+        && invalidation::replace_invalidated(pool, jq, &tick, &code, period.as_ref()).await? > 0
+    {
+        filled = true;
+    }
     if let Some(period) = period {
         // 数据库中存在时间段，说明已进行过查询，则仅进行增量查询并插入
 
@@ -108,6 +145,7 @@ pub async fn get_stock_tick_prices(
                     UpdatePricePeriod::Lowerbound,
                 )
                 .await?;
+                filled = true;
             }
         }
 
@@ -124,6 +162,7 @@ pub async fn get_stock_tick_prices(
                     UpdatePricePeriod::Upperbound,
                 )
                 .await?;
+                filled = true;
             }
         }
     } else {
@@ -138,6 +177,12 @@ pub async fn get_stock_tick_prices(
             UpdatePricePeriod::Entire,
         )
         .await?;
+        filled = true;
+    }
+    if filled {
+        cache::record_miss(&tick, &code);
+    } else {
+        cache::record_hit(&tick, &code);
     }
     let data = {
         let start_dt = start_ts.date();
@@ -155,7 +200,7 @@ pub async fn get_stock_tick_prices(
 }
 
 async fn fill_prices(
-    jq: &JqdataClient,
+    jq: &JqdataPool,
     pool: &DbPool,
     tick: &str,
     code: &str,
@@ -163,8 +208,17 @@ async fn fill_prices(
     end_dt: NaiveDate,
     upd: UpdatePricePeriod,
 ) -> Result<()> {
+    if jq.is_offline() {
+        return Err(Error::custom(
+            ErrorKind::NotFound,
+            format!(
+                "data not cached: {} {} prices between {} and {}",
+                code, tick, start_dt, end_dt
+            ),
+        ));
+    }
     let estimated_batch_size = estimate_batch_size(start_dt, end_dt, &tick);
-    if estimated_batch_size >= MAX_DB_INSERT_BATCH_SIZE {
+    if estimated_batch_size >= MAX_FILL_SIZE {
         warn!(
             "Estimated db insertion batch size exceeds limitation for data from {} to {}: {} rows",
             start_dt, end_dt, estimated_batch_size
@@ -180,17 +234,31 @@ async fn fill_prices(
     );
     let resp = ticks::query_api_prices(jq, tick, code, start_dt, end_dt).await?;
     if !resp.is_empty() {
-        let mut prices = Vec::with_capacity(resp.len());
-        for p in resp.into_iter() {
-            let dp = jq_price_to_tick_price(&tick, &code, p)?;
-            prices.push(dp);
-        }
+        let prices = jq_prices_to_tick_prices(tick, code, resp)?;
+        let prices = anomaly::quarantine(pool, code, prices).await?;
         let pool = pool.clone();
         tokio::task::spawn_blocking(move || insert_tick_prices(&pool, &prices, upd)).await??;
     }
     Ok(())
 }
 
+// 转换数据源的K线，并按配置处理科创板盘后交易
+fn jq_prices_to_tick_prices(
+    tick: &str,
+    code: &str,
+    resp: Vec<jqdata::Price>,
+) -> Result<Vec<StockTickPrice>> {
+    let mut prices = Vec::with_capacity(resp.len());
+    for p in resp.into_iter() {
+        prices.push(jq_price_to_tick_price(tick, code, p)?);
+    }
+    Ok(after_hours::normalize(
+        code,
+        prices,
+        after_hours::include_after_hours(),
+    ))
+}
+
 #[inline]
 fn jq_price_to_tick_price(tick: &str, code: &str, p: jqdata::Price) -> Result<StockTickPrice> {
     let (ts, is_day) = parse_ts_from_str(&p.date)?;
@@ -245,16 +313,13 @@ enum UpdatePricePeriod {
 }
 
 fn estimate_batch_size(start_dt: NaiveDate, end_dt: NaiveDate, tick: &str) -> i64 {
-    let size_per_day = match tick {
-        "1d" => 1,
-        "30m" => 8,
-        "5m" => 48,
-        "1m" => 240,
-        _ => return std::i64::MAX,
+    let size_per_day = match tick.parse::<Tick>() {
+        Ok(tick) => tick.bars_per_day() as i64,
+        Err(_) => return std::i64::MAX,
     };
 
     let naive_size = ((end_dt - start_dt).num_days() + 1) * size_per_day;
-    if naive_size < MAX_DB_INSERT_BATCH_SIZE {
+    if naive_size < MAX_FILL_SIZE {
         return naive_size;
     }
     let mut start = start_dt;
@@ -285,54 +350,137 @@ fn insert_tick_prices(
     use diesel::prelude::*;
 
     let conn = pool.get()?;
-    conn.transaction::<_, Error, _>(|| {
-        // 插入价格数据
-        {
-            use crate::schema::stock_tick_prices::dsl::*;
-            diesel::insert_into(stock_tick_prices)
-                .values(prices)
-                .execute(&conn)?;
-            debug!(
-                "{} rows of stock tick[{}] prices inserted",
-                prices.len(),
-                input_tick
-            );
-        }
-        // 更新价格区间
-        {
+    let batch_size = models::tick_price_batch_size();
+    let mut chunks = models::split_by_day(prices, batch_size);
+    // 每块与价格区间在同一事务中提交，中断后已缓存的区间仍然连续
+    // 向前补齐时从后往前提交
+    if let UpdatePricePeriod::Lowerbound = upd {
+        chunks.reverse();
+    }
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        let chunk_start_dt = chunk[0].ts.date();
+        let chunk_end_dt = chunk[chunk.len() - 1].ts.date();
+        conn.transaction::<_, Error, _>(|| {
+            // 插入价格数据
+            let n = models::insert_tick_prices_batched(&conn, chunk, batch_size)?;
+            debug!("{} rows of stock tick[{}] prices inserted", n, input_tick);
+            // 更新价格区间
             use crate::schema::stock_price_ticks::dsl::*;
+            let period = stock_price_ticks.filter(code.eq(input_code).and(tick.eq(input_tick)));
             match upd {
-                UpdatePricePeriod::Upperbound => {
-                    let input_end_dt = prices.last().as_ref().unwrap().ts.date();
-                    diesel::update(
-                        stock_price_ticks.filter(code.eq(input_code).and(tick.eq(input_tick))),
-                    )
-                    .set(end_dt.eq(input_end_dt))
-                    .execute(&conn)?;
-                }
                 UpdatePricePeriod::Lowerbound => {
-                    let input_start_dt = prices.first().as_ref().unwrap().ts.date();
-                    diesel::update(
-                        stock_price_ticks.filter(code.eq(input_code).and(tick.eq(input_tick))),
-                    )
-                    .set(start_dt.eq(input_start_dt))
-                    .execute(&conn)?;
+                    diesel::update(period)
+                        .set(start_dt.eq(chunk_start_dt))
+                        .execute(&conn)?;
                 }
-                UpdatePricePeriod::Entire => {
-                    let input_start_dt = prices.first().as_ref().unwrap().ts.date();
-                    let input_end_dt = prices.last().as_ref().unwrap().ts.date();
+                UpdatePricePeriod::Entire if i == 0 => {
                     diesel::insert_into(stock_price_ticks)
                         .values(StockPriceTick {
                             code: input_code.to_owned(),
                             tick: input_tick.to_owned(),
-                            start_dt: input_start_dt,
-                            end_dt: input_end_dt,
+                            start_dt: chunk_start_dt,
+                            end_dt: chunk_end_dt,
                         })
                         .execute(&conn)?;
                 }
+                UpdatePricePeriod::Upperbound | UpdatePricePeriod::Entire => {
+                    diesel::update(period)
+                        .set(end_dt.eq(chunk_end_dt))
+                        .execute(&conn)?;
+                }
             }
-            debug!("stock price tick updated with state {:?}", upd);
+            Ok(())
+        })?;
+    }
+    debug!("stock price tick updated with state {:?}", upd);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_db_pool, REPLAY_FIXTURES};
+    use tanglism_utils::price;
+
+    fn dt(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    // 清除测试代码的K线及区间，保证可重复执行
+    fn clear_prices(pool: &DbPool, input_tick: &str, input_code: &str) {
+        use diesel::prelude::*;
+        let conn = pool.get().unwrap();
+        {
+            use crate::schema::stock_tick_prices::dsl::*;
+            diesel::delete(stock_tick_prices.filter(tick.eq(input_tick).and(code.eq(input_code))))
+                .execute(&conn)
+                .unwrap();
         }
+        {
+            use crate::schema::stock_price_ticks::dsl::*;
+            diesel::delete(stock_price_ticks.filter(tick.eq(input_tick).and(code.eq(input_code))))
+                .execute(&conn)
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_api_prices() -> Result<()> {
+        let jq = JqdataPool::replay(REPLAY_FIXTURES)?;
+        let resp =
+            ticks::query_api_prices(&jq, "1d", "600000.XSHG", dt("2020-08-03"), dt("2020-08-07"))
+                .await?;
+        let prices = jq_prices_to_tick_prices("1d", "600000.XSHG", resp)?;
+        assert_eq!(5, prices.len());
+        // 日线时刻为收盘时间
+        assert_eq!(
+            dt("2020-08-03").and_hms_opt(15, 0, 0).unwrap(),
+            prices[0].ts
+        );
+        assert_eq!(price!(10.62), prices[0].close);
+        assert_eq!(price!(10620000), prices[0].amount);
+        // 未录制的区间返回错误
+        assert!(ticks::query_api_prices(
+            &jq,
+            "1d",
+            "600000.XSHG",
+            dt("2020-08-10"),
+            dt("2020-08-14")
+        )
+        .await
+        .is_err());
         Ok(())
-    })
+    }
+
+    #[tokio::test]
+    async fn test_replay_stock_tick_prices() -> Result<()> {
+        let pool = match test_db_pool() {
+            Some(pool) => pool,
+            None => return Ok(()),
+        };
+        clear_prices(&pool, "1d", "600000.XSHG");
+        let jq = JqdataPool::replay(REPLAY_FIXTURES)?;
+        let start_ts = dt("2020-08-03").and_hms_opt(0, 0, 0).unwrap();
+        let end_ts = dt("2020-08-07").and_hms_opt(23, 59, 59).unwrap();
+        let prices =
+            get_stock_tick_prices(&pool, &jq, Tick::D1, "600000.XSHG", start_ts, end_ts).await?;
+        assert_eq!(5, prices.len());
+        assert_eq!(price!(10.68), prices[4].close);
+        let period = query_db_period(&pool, "1d", "600000.XSHG").await?.unwrap();
+        assert_eq!(
+            (dt("2020-08-03"), dt("2020-08-07")),
+            (period.start_dt, period.end_dt)
+        );
+        // 已缓存的区间不再访问上游，离线时仍可查询
+        let offline = JqdataPool::offline();
+        let cached =
+            get_stock_tick_prices(&pool, &offline, Tick::D1, "600000.XSHG", start_ts, end_ts)
+                .await?;
+        let closes = |ps: &[ticks::StockPrice]| -> Vec<_> {
+            ps.iter().map(|p| (p.ts, p.close.clone())).collect()
+        };
+        assert_eq!(closes(&prices), closes(&cached));
+        clear_prices(&pool, "1d", "600000.XSHG");
+        Ok(())
+    }
 }
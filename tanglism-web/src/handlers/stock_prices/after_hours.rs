@@ -0,0 +1,105 @@
+//! 科创板盘后固定价格交易
+//!
+//! 科创板股票在15:05至15:30进行盘后固定价格交易，数据源的分钟K线中可能出现收盘后的时刻，
+//! 与常规交易时刻不对齐。抓取时按配置剔除这些K线，或将其并入收盘K线。
+//! 配置仅影响此后抓取的数据，已缓存的数据需标记失效后重新抓取。
+
+use crate::models::StockTickPrice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tanglism_utils::{is_after_hours, is_star_market, LocalTradingTimestamps, TradingTimestamps};
+
+// 默认剔除盘后交易
+static INCLUDE_AFTER_HOURS: AtomicBool = AtomicBool::new(false);
+
+/// 设置是否包含盘后交易
+pub fn set_include_after_hours(include: bool) {
+    INCLUDE_AFTER_HOURS.store(include, Ordering::Relaxed);
+}
+
+pub fn include_after_hours() -> bool {
+    INCLUDE_AFTER_HOURS.load(Ordering::Relaxed)
+}
+
+/// 处理科创板的盘后K线，非科创板代码原样返回
+///
+/// 包含时盘后K线并入当日收盘K线，成交量及成交额累加，否则直接剔除
+pub fn normalize(code: &str, prices: Vec<StockTickPrice>, include: bool) -> Vec<StockTickPrice> {
+    if !is_star_market(code) || !prices.iter().any(|p| is_after_hours(p.ts.time())) {
+        return prices;
+    }
+    let mut rst: Vec<StockTickPrice> = Vec::with_capacity(prices.len());
+    for p in prices {
+        if !is_after_hours(p.ts.time()) {
+            rst.push(p);
+            continue;
+        }
+        let aligned = match p.tick.parse() {
+            Ok(tick) => LocalTradingTimestamps::new(tick)
+                .with_after_hours(include)
+                .aligned_tick(p.ts),
+            Err(_) => None,
+        };
+        let ts = match aligned {
+            Some(ts) => ts,
+            None => continue,
+        };
+        match rst.last_mut() {
+            Some(last) if last.ts == ts => {
+                if p.high > last.high {
+                    last.high = p.high;
+                }
+                if p.low < last.low {
+                    last.low = p.low;
+                }
+                last.close = p.close;
+                last.volume += p.volume;
+                last.amount += p.amount;
+            }
+            // 缺少收盘K线时以盘后K线代替
+            _ => rst.push(StockTickPrice { ts, ..p }),
+        }
+    }
+    rst
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::stock_prices::ticks::PriceBuilder;
+    use bigdecimal::BigDecimal;
+
+    #[test]
+    fn test_normalize_after_hours() {
+        let prices = |code: &str| {
+            vec![
+                PriceBuilder::new("2020-08-07 14:55", 10)
+                    .volume(100)
+                    .build_tick("5m", code),
+                PriceBuilder::new("2020-08-07 15:00", 11)
+                    .volume(100)
+                    .build_tick("5m", code),
+                PriceBuilder::new("2020-08-07 15:10", 11)
+                    .volume(20)
+                    .build_tick("5m", code),
+                PriceBuilder::new("2020-08-07 15:30", 11)
+                    .volume(30)
+                    .build_tick("5m", code),
+            ]
+        };
+        let stripped = normalize("688001.XSHG", prices("688001.XSHG"), false);
+        assert_eq!(2, stripped.len());
+        assert_eq!(BigDecimal::from(100), stripped[1].volume);
+
+        let merged = normalize("688001.XSHG", prices("688001.XSHG"), true);
+        assert_eq!(2, merged.len());
+        assert_eq!("2020-08-07 15:00:00", merged[1].ts.to_string());
+        assert_eq!(BigDecimal::from(150), merged[1].volume);
+        assert_eq!(BigDecimal::from(1650), merged[1].amount);
+
+        // 非科创板代码不处理
+        assert_eq!(
+            4,
+            normalize("600001.XSHG", prices("600001.XSHG"), false).len()
+        );
+    }
+}
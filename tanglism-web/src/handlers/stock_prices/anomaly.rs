@@ -0,0 +1,250 @@
+//! 异常K线隔离
+//!
+//! 数据源偶尔返回错误的K线，如最高价低于最低价、无成交却有价格变动、单根K线涨跌幅超过
+//! 任何涨跌停限制，或时刻不在交易时段内。这些K线进入分析后会产生虚假的分型及提醒，
+//! 因此在写入前筛除，保存至stock_price_anomalies表并推送，价格表中不再包含。
+
+use super::after_hours::include_after_hours;
+use crate::handlers::webhooks;
+use crate::models::{NewStockPriceAnomaly, StockPriceAnomaly, StockTickPrice};
+use crate::{DbPool, Error, Result};
+use bigdecimal::{Signed, Zero};
+use chrono::Local;
+use diesel::prelude::*;
+use log::warn;
+use serde_derive::*;
+use tanglism_utils::{price, LocalTradingTimestamps, Tick, TradingTimestamps};
+
+// 列表默认及最多返回的条目数
+const DEFAULT_LIST_LIMIT: i64 = 100;
+const MAX_LIST_LIMIT: i64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    // 最高价低于最低价
+    InvertedRange,
+    // 成交量为0但价格变动
+    ZeroVolumeMove,
+    // 相对前一K线收盘价的涨跌幅超过30%，高于任何涨跌停限制
+    ExcessiveMove,
+    // 时刻不在交易日或交易时段内
+    OffSession,
+}
+
+impl AnomalyKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AnomalyKind::InvertedRange => "inverted_range",
+            AnomalyKind::ZeroVolumeMove => "zero_volume_move",
+            AnomalyKind::ExcessiveMove => "excessive_move",
+            AnomalyKind::OffSession => "off_session",
+        }
+    }
+}
+
+/// 被筛除的K线及原因
+#[derive(Debug)]
+pub struct Anomaly {
+    pub price: StockTickPrice,
+    pub kind: AnomalyKind,
+    pub detail: String,
+}
+
+// 交易时段仅对沪深两市的代码检查，期货存在夜盘
+fn checks_session(code: &str) -> bool {
+    code.ends_with(".XSHG") || code.ends_with(".XSHE")
+}
+
+/// 筛除异常K线，返回正常的K线及异常K线
+///
+/// 涨跌幅相对前一根正常K线计算，首根K线不检查
+pub fn screen(code: &str, prices: Vec<StockTickPrice>) -> (Vec<StockTickPrice>, Vec<Anomaly>) {
+    let max_move = price!("0.3");
+    let tts = prices
+        .first()
+        .and_then(|p| p.tick.parse::<Tick>().ok())
+        .filter(|_| checks_session(code))
+        .map(|tick| LocalTradingTimestamps::new(tick).with_after_hours(include_after_hours()));
+    let mut normal: Vec<StockTickPrice> = Vec::with_capacity(prices.len());
+    let mut anomalies = Vec::new();
+    for p in prices {
+        let found = if p.high < p.low {
+            Some((
+                AnomalyKind::InvertedRange,
+                format!("high {} < low {}", p.high, p.low),
+            ))
+        } else if p.volume.is_zero() && (p.high != p.low || p.open != p.close) {
+            Some((
+                AnomalyKind::ZeroVolumeMove,
+                format!("zero volume with range {} to {}", p.low, p.high),
+            ))
+        } else if tts
+            .as_ref()
+            .is_some_and(|t| t.aligned_tick(p.ts) != Some(p.ts))
+        {
+            Some((
+                AnomalyKind::OffSession,
+                format!("{} outside trading sessions", p.ts),
+            ))
+        } else {
+            normal
+                .last()
+                .map(|prev| &prev.close)
+                .filter(|c| c.is_positive())
+                .and_then(|c| {
+                    let up = (&p.high - c).abs();
+                    let down = (&p.low - c).abs();
+                    let ratio = up.max(down) / c;
+                    if ratio > max_move {
+                        Some((
+                            AnomalyKind::ExcessiveMove,
+                            format!("moved {:.4} from previous close {}", ratio, c),
+                        ))
+                    } else {
+                        None
+                    }
+                })
+        };
+        match found {
+            Some((kind, detail)) => anomalies.push(Anomaly {
+                price: p,
+                kind,
+                detail,
+            }),
+            None => normal.push(p),
+        }
+    }
+    (normal, anomalies)
+}
+
+/// 筛除异常K线并写入隔离表，返回正常的K线
+pub(super) async fn quarantine(
+    pool: &DbPool,
+    code: &str,
+    prices: Vec<StockTickPrice>,
+) -> Result<Vec<StockTickPrice>> {
+    let (normal, anomalies) = screen(code, prices);
+    if anomalies.is_empty() {
+        return Ok(normal);
+    }
+    warn!("{} anomalous bars of {} quarantined", anomalies.len(), code);
+    let now = Local::now().naive_local();
+    let rows: Vec<NewStockPriceAnomaly> = anomalies
+        .into_iter()
+        .map(|a| NewStockPriceAnomaly {
+            tick: a.price.tick,
+            code: a.price.code,
+            ts: a.price.ts,
+            kind: a.kind.as_str().to_owned(),
+            detail: a.detail,
+            open: a.price.open,
+            close: a.price.close,
+            high: a.price.high,
+            low: a.price.low,
+            volume: a.price.volume,
+            amount: a.price.amount,
+            detected_at: now,
+        })
+        .collect();
+    let db = pool.clone();
+    let saved = tokio::task::spawn_blocking(move || {
+        use crate::schema::stock_price_anomalies::dsl::*;
+        let conn = db.get()?;
+        // 重复抓取时同一K线只记录一次
+        diesel::insert_into(stock_price_anomalies)
+            .values(&rows)
+            .on_conflict_do_nothing()
+            .get_results::<StockPriceAnomaly>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    if !saved.is_empty() {
+        if let Err(e) = webhooks::enqueue(pool.clone(), webhooks::EVENT_ANOMALY, &saved).await {
+            warn!("failed to enqueue anomaly webhook: {}", e);
+        }
+    }
+    Ok(normal)
+}
+
+/// 按检测时间倒序列出被隔离的K线
+pub async fn list_anomalies(
+    pool: DbPool,
+    input_code: Option<String>,
+    limit: Option<i64>,
+) -> Result<Vec<StockPriceAnomaly>> {
+    let limit = limit.unwrap_or(DEFAULT_LIST_LIMIT).clamp(1, MAX_LIST_LIMIT);
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::stock_price_anomalies::dsl::*;
+        let conn = pool.get()?;
+        let mut query = stock_price_anomalies.into_boxed();
+        if let Some(input_code) = input_code {
+            query = query.filter(code.eq(input_code));
+        }
+        query
+            .order(id.desc())
+            .limit(limit)
+            .load::<StockPriceAnomaly>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::stock_prices::ticks::PriceBuilder;
+
+    #[test]
+    fn test_screen_anomalies() {
+        let prices = vec![
+            PriceBuilder::new("2020-02-03 10:00", 10)
+                .range(9, 11)
+                .volume(100)
+                .build_tick("30m", "600000.XSHG"),
+            PriceBuilder::new("2020-02-03 10:30", 10)
+                .range(11, 9)
+                .volume(100)
+                .build_tick("30m", "600000.XSHG"),
+            PriceBuilder::new("2020-02-03 11:00", 10)
+                .range(9, 11)
+                .volume(0)
+                .build_tick("30m", "600000.XSHG"),
+            PriceBuilder::new("2020-02-03 12:00", 10)
+                .range(9, 11)
+                .volume(100)
+                .build_tick("30m", "600000.XSHG"),
+            PriceBuilder::new("2020-02-03 11:30", 14)
+                .range(10, 14)
+                .volume(100)
+                .build_tick("30m", "600000.XSHG"),
+            PriceBuilder::new("2020-02-03 13:30", 11)
+                .range(10, 11)
+                .volume(100)
+                .build_tick("30m", "600000.XSHG"),
+        ];
+        let (normal, anomalies) = screen("600000.XSHG", prices);
+        assert_eq!(2, normal.len());
+        let kinds: Vec<_> = anomalies.iter().map(|a| a.kind).collect();
+        assert_eq!(
+            vec![
+                AnomalyKind::InvertedRange,
+                AnomalyKind::ZeroVolumeMove,
+                AnomalyKind::OffSession,
+                AnomalyKind::ExcessiveMove,
+            ],
+            kinds
+        );
+
+        // 非沪深代码不检查交易时段
+        let (normal, _) = screen(
+            "RB2010.XSGE",
+            vec![PriceBuilder::new("2020-02-03 21:00", 10)
+                .range(9, 11)
+                .volume(100)
+                .build_tick("30m", "600000.XSHG")],
+        );
+        assert_eq!(1, normal.len());
+    }
+}
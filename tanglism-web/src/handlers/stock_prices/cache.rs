@@ -0,0 +1,183 @@
+//! 价格缓存管理
+//!
+//! 价格数据以数据库作为共享缓存，stock_price_ticks记录已抓取的区间。
+//! 数据源修正历史数据后，需要清除已缓存的错误数据，下次查询时重新抓取。
+
+use super::{epoch, PRICE_ACCESS};
+use crate::handlers::metrics::store::{DbMetricStore, MetricStore};
+use crate::{DbPool, Error, Result};
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use lazy_static::*;
+use serde_derive::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tanglism_utils::Tick;
+
+// 缓存命中统计，仅保存在内存中，重启后清零
+lazy_static! {
+    static ref CACHE_STATS: Mutex<HashMap<(String, String), CacheStats>> =
+        Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, Default)]
+struct CacheStats {
+    hits: u64,
+    misses: u64,
+    // 最近一次通过API填充的时间
+    filled_at: Option<NaiveDateTime>,
+}
+
+/// 记录一次缓存命中
+pub(super) fn record_hit(tick: &str, code: &str) {
+    let mut stats = CACHE_STATS.lock().unwrap();
+    let entry = stats.entry((tick.to_owned(), code.to_owned())).or_default();
+    entry.hits += 1;
+}
+
+/// 记录一次缓存未命中，即需要通过API填充数据
+pub(super) fn record_miss(tick: &str, code: &str) {
+    let mut stats = CACHE_STATS.lock().unwrap();
+    let entry = stats.entry((tick.to_owned(), code.to_owned())).or_default();
+    entry.misses += 1;
+    entry.filled_at = Some(Local::now().naive_local());
+}
+
+/// 缓存条目
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub tick: String,
+    pub code: String,
+    pub start_dt: NaiveDate,
+    pub end_dt: NaiveDate,
+    // 缓存的价格行数
+    pub rows: i64,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: Option<f64>,
+    // 距最近一次填充的秒数，服务启动后未填充过则为空
+    pub age_secs: Option<i64>,
+}
+
+/// 列出所有缓存条目
+pub async fn list_cache_entries(pool: DbPool) -> Result<Vec<CacheEntry>> {
+    let (periods, counts) = tokio::task::spawn_blocking(move || {
+        use crate::schema::{stock_price_ticks, stock_tick_prices};
+        let conn = pool.get().map_err(Error::from)?;
+        let periods = stock_price_ticks::table
+            .order((stock_price_ticks::tick, stock_price_ticks::code))
+            .load::<crate::models::StockPriceTick>(&conn)?;
+        let counts = stock_tick_prices::table
+            .group_by((stock_tick_prices::tick, stock_tick_prices::code))
+            .select((
+                stock_tick_prices::tick,
+                stock_tick_prices::code,
+                // diesel对分组聚合的类型检查过严，此处使用原生SQL
+                diesel::dsl::sql::<diesel::sql_types::BigInt>("count(*)"),
+            ))
+            .load::<(String, String, i64)>(&conn)?;
+        Ok::<_, Error>((periods, counts))
+    })
+    .await??;
+    let counts: HashMap<(String, String), i64> = counts
+        .into_iter()
+        .map(|(tick, code, n)| ((tick, code), n))
+        .collect();
+    let now = Local::now().naive_local();
+    let stats = CACHE_STATS.lock().unwrap();
+    let entries = periods
+        .into_iter()
+        .map(|p| {
+            let key = (p.tick, p.code);
+            let rows = counts.get(&key).cloned().unwrap_or(0);
+            let s = stats.get(&key).cloned().unwrap_or_default();
+            let total = s.hits + s.misses;
+            CacheEntry {
+                tick: key.0,
+                code: key.1,
+                start_dt: p.start_dt,
+                end_dt: p.end_dt,
+                rows,
+                hits: s.hits,
+                misses: s.misses,
+                hit_rate: if total == 0 {
+                    None
+                } else {
+                    Some(s.hits as f64 / total as f64)
+                },
+                age_secs: s.filled_at.map(|ts| (now - ts).num_seconds()),
+            }
+        })
+        .collect();
+    Ok(entries)
+}
+
+/// 使指定股票和周期的缓存失效，返回删除的价格行数
+pub async fn invalidate(pool: DbPool, input_tick: Tick, code: String) -> Result<usize> {
+    let tick = input_tick.to_string();
+    // 与价格查询使用同一把锁，避免删除与填充交错
+    let pa = {
+        let mut pas = PRICE_ACCESS.lock().await;
+        pas.get(&tick, &code)
+    };
+    let _pa_access = pa.lock().await;
+    let key = (tick.clone(), code.clone());
+    let metric_store = DbMetricStore(pool.clone());
+    let deleted = tokio::task::spawn_blocking(move || {
+        use crate::schema::{stock_price_ticks, stock_tick_prices};
+        let conn = pool.get().map_err(Error::from)?;
+        conn.transaction::<_, Error, _>(|| {
+            let n = diesel::delete(
+                stock_tick_prices::table.filter(
+                    stock_tick_prices::tick
+                        .eq(&tick)
+                        .and(stock_tick_prices::code.eq(&code)),
+                ),
+            )
+            .execute(&conn)?;
+            diesel::delete(
+                stock_price_ticks::table.filter(
+                    stock_price_ticks::tick
+                        .eq(&tick)
+                        .and(stock_price_ticks::code.eq(&code)),
+                ),
+            )
+            .execute(&conn)?;
+            Ok(n)
+        })
+    })
+    .await??;
+    // 基于旧价格计算的指标一并失效
+    metric_store
+        .invalidate(Some(key.1.clone()), Some(input_tick))
+        .await?;
+    CACHE_STATS.lock().unwrap().remove(&key);
+    epoch::bump_epoch(Some(key.1.as_str()));
+    Ok(deleted)
+}
+
+/// 清空所有缓存，返回删除的价格行数
+pub async fn flush_all(pool: DbPool) -> Result<usize> {
+    // 持有全局锁期间不会产生新的查询，再逐个等待进行中的查询结束
+    let pas = PRICE_ACCESS.lock().await;
+    let locks: Vec<_> = pas.0.values().cloned().collect();
+    let mut guards = Vec::with_capacity(locks.len());
+    for l in &locks {
+        guards.push(l.lock().await);
+    }
+    let metric_store = DbMetricStore(pool.clone());
+    let deleted = tokio::task::spawn_blocking(move || {
+        use crate::schema::{stock_price_ticks, stock_tick_prices};
+        let conn = pool.get().map_err(Error::from)?;
+        conn.transaction::<_, Error, _>(|| {
+            let n = diesel::delete(stock_tick_prices::table).execute(&conn)?;
+            diesel::delete(stock_price_ticks::table).execute(&conn)?;
+            Ok(n)
+        })
+    })
+    .await??;
+    metric_store.invalidate(None, None).await?;
+    CACHE_STATS.lock().unwrap().clear();
+    epoch::bump_epoch(None);
+    Ok(deleted)
+}
@@ -0,0 +1,271 @@
+//! 期货连续合约
+//!
+//! 由主力合约历史及各合约K线拼接连续序列，合成代码为品种加后缀：
+//! 888为差值后复权，889为比例后复权，88为不复权直接拼接，如AG888。
+//! 后复权以最新合约价格为基准，每次换月均会改写此前的全部价格，
+//! 因此仅缓存各合约的K线，连续序列在查询时拼接。
+
+use super::{get_cached_tick_prices, ticks::StockPrice};
+use crate::handlers::metrics::math::round_half_even;
+use crate::{DbPool, JqdataPool, Result};
+use bigdecimal::{BigDecimal, One, Zero};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use jqdata::GetDominantFuture;
+use tanglism_utils::{Tick, TradingDates, LOCAL_DATES};
+
+// 比例复权后价格保留的小数位数
+const ADJUSTED_SCALE: i64 = 4;
+
+/// 连续合约的复权方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Adjustment {
+    // 不复权
+    None,
+    // 换月时新旧合约的价差累加至此前的价格
+    Difference,
+    // 换月时新旧合约的价格比例累乘至此前的价格
+    Ratio,
+}
+
+/// 连续合约代码
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContinuousCode {
+    pub product: String,
+    pub adjustment: Adjustment,
+}
+
+/// 解析连续合约代码，品种为1至2位字母，其余代码返回None
+pub fn parse_continuous_code(code: &str) -> Option<ContinuousCode> {
+    let split = code.find(|c: char| !c.is_ascii_alphabetic())?;
+    let (product, suffix) = code.split_at(split);
+    if product.is_empty() || product.len() > 2 {
+        return None;
+    }
+    let adjustment = match suffix {
+        "88" => Adjustment::None,
+        "888" => Adjustment::Difference,
+        "889" => Adjustment::Ratio,
+        _ => return None,
+    };
+    Some(ContinuousCode {
+        product: product.to_ascii_uppercase(),
+        adjustment,
+    })
+}
+
+/// 单个合约作为主力期间的K线
+#[derive(Debug, Clone)]
+pub struct ContractBars {
+    pub contract: String,
+    // 成为主力合约的首个交易日
+    pub start_dt: NaiveDate,
+    // 可包含start_dt之前的K线，用于计算换月时的价差
+    pub bars: Vec<StockPrice>,
+}
+
+/// 查询连续合约的K线
+pub async fn get_continuous_prices(
+    pool: &DbPool,
+    jq: &JqdataPool,
+    tick: Tick,
+    code: &ContinuousCode,
+    start_ts: NaiveDateTime,
+    end_ts: NaiveDateTime,
+) -> Result<Vec<StockPrice>> {
+    let contracts = dominant_contracts(jq, &code.product, start_ts.date(), end_ts.date()).await?;
+    let mut segments = Vec::with_capacity(contracts.len());
+    for (i, (contract, start_dt, end_dt)) in contracts.into_iter().enumerate() {
+        // 换月后的合约多取前一个交易日，与原合约的最后一根K线对齐
+        let fetch_start = match LOCAL_DATES.prev_day(start_dt) {
+            Some(prev) if i > 0 => NaiveDateTime::new(prev, NaiveTime::MIN),
+            _ => start_ts.max(NaiveDateTime::new(start_dt, NaiveTime::MIN)),
+        };
+        let fetch_end = end_ts.min(NaiveDateTime::new(
+            end_dt,
+            NaiveTime::from_hms_opt(23, 59, 59).unwrap(),
+        ));
+        let bars =
+            get_cached_tick_prices(pool, jq, tick, &contract, fetch_start, fetch_end).await?;
+        segments.push(ContractBars {
+            contract,
+            start_dt,
+            bars,
+        });
+    }
+    let mut prices = stitch(&segments, code.adjustment);
+    prices.retain(|p| p.ts >= start_ts && p.ts <= end_ts);
+    Ok(prices)
+}
+
+/// 逐交易日查询主力合约，合并连续相同的合约为区间
+pub async fn dominant_contracts(
+    jq: &JqdataPool,
+    product: &str,
+    start_dt: NaiveDate,
+    end_dt: NaiveDate,
+) -> Result<Vec<(String, NaiveDate, NaiveDate)>> {
+    let mut rst: Vec<(String, NaiveDate, NaiveDate)> = Vec::new();
+    let mut dt = if LOCAL_DATES.contains_day(start_dt) {
+        Some(start_dt)
+    } else {
+        LOCAL_DATES.next_day(start_dt)
+    };
+    while let Some(d) = dt.filter(|d| *d <= end_dt) {
+        let lines = jq
+            .execute(|| GetDominantFuture {
+                code: product.to_owned(),
+                date: d.format("%Y-%m-%d").to_string(),
+            })
+            .await?;
+        if let Some(contract) = lines.into_iter().find(|l| !l.trim().is_empty()) {
+            let contract = contract.trim().to_owned();
+            match rst.last_mut() {
+                Some(last) if last.0 == contract => last.2 = d,
+                _ => rst.push((contract, d, d)),
+            }
+        }
+        dt = LOCAL_DATES.next_day(d);
+    }
+    Ok(rst)
+}
+
+/// 按主力期间拼接各合约的K线，后复权时以最后一个合约为基准
+pub fn stitch(segments: &[ContractBars], adjustment: Adjustment) -> Vec<StockPrice> {
+    // 各段主力期间内的K线
+    let owned: Vec<Vec<&StockPrice>> = segments
+        .iter()
+        .map(|s| {
+            s.bars
+                .iter()
+                .filter(|b| b.ts.date() >= s.start_dt)
+                .collect()
+        })
+        .collect();
+    // 换月时新旧合约的价差及比例，以原合约最后一根K线的时刻对齐，
+    // 新合约缺少该时刻时以新合约首根K线的开盘价代替
+    let mut rolls = Vec::with_capacity(segments.len());
+    for i in 1..segments.len() {
+        let old = match owned[i - 1].last() {
+            Some(b) => b,
+            None => {
+                rolls.push((BigDecimal::zero(), BigDecimal::one()));
+                continue;
+            }
+        };
+        let new = segments[i]
+            .bars
+            .iter()
+            .find(|b| b.ts == old.ts)
+            .map(|b| b.close.clone())
+            .or_else(|| owned[i].first().map(|b| b.open.clone()));
+        match new {
+            Some(new) if !old.close.is_zero() => {
+                rolls.push((&new - &old.close, &new / &old.close));
+            }
+            _ => rolls.push((BigDecimal::zero(), BigDecimal::one())),
+        }
+    }
+    let mut rst = Vec::new();
+    for (i, bars) in owned.iter().enumerate() {
+        // 此后各次换月的累计调整
+        let (delta, ratio) = rolls[i..].iter().fold(
+            (BigDecimal::zero(), BigDecimal::one()),
+            |(delta, ratio), (d, r)| (delta + d, ratio * r),
+        );
+        let adjust = |p: &BigDecimal| match adjustment {
+            Adjustment::None => p.clone(),
+            Adjustment::Difference => p + &delta,
+            Adjustment::Ratio => round_half_even(&(p * &ratio), ADJUSTED_SCALE),
+        };
+        for b in bars {
+            rst.push(StockPrice {
+                ts: b.ts,
+                open: adjust(&b.open),
+                close: adjust(&b.close),
+                high: adjust(&b.high),
+                low: adjust(&b.low),
+                volume: b.volume.clone(),
+                amount: b.amount.clone(),
+            });
+        }
+    }
+    rst
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::stock_prices::ticks::PriceBuilder;
+
+    fn dt(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn test_parse_continuous_code() {
+        assert_eq!(
+            Some(ContinuousCode {
+                product: "AG".to_owned(),
+                adjustment: Adjustment::Difference,
+            }),
+            parse_continuous_code("AG888")
+        );
+        assert_eq!(
+            Some(Adjustment::Ratio),
+            parse_continuous_code("i889").map(|c| c.adjustment)
+        );
+        assert_eq!(
+            Some(Adjustment::None),
+            parse_continuous_code("IF88").map(|c| c.adjustment)
+        );
+        assert_eq!(None, parse_continuous_code("600000.XSHG"));
+        assert_eq!(None, parse_continuous_code("AG2012.XSGE"));
+        assert_eq!(None, parse_continuous_code("888"));
+    }
+
+    #[test]
+    fn test_stitch() {
+        let segments = vec![
+            ContractBars {
+                contract: "AG2010.XSGE".to_owned(),
+                start_dt: dt("2020-08-03"),
+                bars: vec![
+                    PriceBuilder::new("2020-08-03 15:00", 100)
+                        .range(99, 101)
+                        .build(),
+                    PriceBuilder::new("2020-08-04 15:00", 110)
+                        .range(109, 111)
+                        .build(),
+                ],
+            },
+            ContractBars {
+                contract: "AG2012.XSGE".to_owned(),
+                start_dt: dt("2020-08-05"),
+                // 前一个交易日的K线用于对齐
+                bars: vec![
+                    PriceBuilder::new("2020-08-04 15:00", 121)
+                        .range(120, 122)
+                        .build(),
+                    PriceBuilder::new("2020-08-05 15:00", 125)
+                        .range(124, 126)
+                        .build(),
+                ],
+            },
+        ];
+        let raw = stitch(&segments, Adjustment::None);
+        assert_eq!(3, raw.len());
+        assert_eq!(BigDecimal::from(110), raw[1].close);
+        assert_eq!(BigDecimal::from(125), raw[2].close);
+
+        let diff = stitch(&segments, Adjustment::Difference);
+        assert_eq!(BigDecimal::from(111), diff[0].close);
+        assert_eq!(BigDecimal::from(122), diff[1].high);
+        assert_eq!(BigDecimal::from(125), diff[2].close);
+        assert_eq!(BigDecimal::from(100), diff[0].volume);
+
+        let ratio = stitch(&segments, Adjustment::Ratio);
+        assert_eq!(BigDecimal::from(110), ratio[0].close);
+        assert_eq!(BigDecimal::from(121), ratio[1].close);
+        assert_eq!(BigDecimal::from(125), ratio[2].close);
+    }
+}
@@ -0,0 +1,69 @@
+//! 价格数据的版本
+//!
+//! 已缓存的价格被删除或替换时递增对应股票的版本，查询据此判断计算所用的数据是否一致。
+//! 版本按股票记录，不区分周期，以覆盖本级别与次级别K线；仅追加新区间不改变版本。
+//! 版本仅在进程内有效，重启后从0开始。
+
+use lazy_static::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref EPOCHS: Mutex<Epochs> = Mutex::new(Epochs::default());
+}
+
+#[derive(Debug, Default)]
+struct Epochs {
+    last: u64,
+    // 清空全部缓存时的版本
+    flushed: u64,
+    codes: HashMap<String, u64>,
+}
+
+impl Epochs {
+    fn get(&self, code: &str) -> u64 {
+        self.codes.get(code).copied().unwrap_or(0).max(self.flushed)
+    }
+
+    fn bump(&mut self, code: Option<&str>) -> u64 {
+        self.last += 1;
+        match code {
+            Some(code) => {
+                self.codes.insert(code.to_owned(), self.last);
+            }
+            None => {
+                self.flushed = self.last;
+                self.codes.clear();
+            }
+        }
+        self.last
+    }
+}
+
+/// 股票价格数据的当前版本
+pub fn data_epoch(code: &str) -> u64 {
+    EPOCHS.lock().unwrap().get(code)
+}
+
+/// 递增股票价格数据的版本，未指定股票时递增全部股票
+pub fn bump_epoch(code: Option<&str>) -> u64 {
+    EPOCHS.lock().unwrap().bump(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epochs() {
+        let mut epochs = Epochs::default();
+        assert_eq!(0, epochs.get("600000.XSHG"));
+        let e1 = epochs.bump(Some("600000.XSHG"));
+        assert_eq!(e1, epochs.get("600000.XSHG"));
+        assert_eq!(0, epochs.get("000001.XSHE"));
+        let e2 = epochs.bump(None);
+        assert!(e2 > e1);
+        assert_eq!(e2, epochs.get("600000.XSHG"));
+        assert_eq!(e2, epochs.get("000001.XSHE"));
+    }
+}
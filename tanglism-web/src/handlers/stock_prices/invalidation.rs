@@ -0,0 +1,180 @@
+//! 价格区间失效及重新下载
+//!
+//! 数据源修正历史数据后，将对应区间标记为失效（软删除），
+//! 下次查询该股票时重新下载并替换，失效记录同时作为替换的审计记录。
+
+use super::{anomaly, epoch, estimate_batch_size, jq_prices_to_tick_prices, ticks, MAX_FILL_SIZE};
+use crate::handlers::metrics::store::{DbMetricStore, MetricStore};
+use crate::models::{self, NewStockPriceInvalidation, StockPriceInvalidation, StockPriceTick};
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result};
+use chrono::{Duration, Local, NaiveDate, NaiveTime};
+use diesel::prelude::*;
+use log::debug;
+use tanglism_utils::Tick;
+
+/// 将价格区间标记为失效
+pub async fn invalidate_range(
+    pool: DbPool,
+    input_tick: Tick,
+    code: String,
+    start_dt: NaiveDate,
+    end_dt: NaiveDate,
+    reason: Option<String>,
+) -> Result<StockPriceInvalidation> {
+    let tick = input_tick.to_string();
+    if start_dt > end_dt {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            format!("start_dt {} > end_dt {}", start_dt, end_dt),
+        ));
+    }
+    // 下次计算指标时将重新下载价格
+    DbMetricStore(pool.clone())
+        .invalidate(Some(code.clone()), Some(input_tick))
+        .await?;
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::stock_price_invalidations;
+        let conn = pool.get().map_err(Error::from)?;
+        diesel::insert_into(stock_price_invalidations::table)
+            .values(NewStockPriceInvalidation {
+                tick,
+                code,
+                start_dt,
+                end_dt,
+                reason,
+                created_at: Local::now().naive_local(),
+            })
+            .get_result::<StockPriceInvalidation>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    // 已计算的结果将重新查询，触发替换
+    epoch::bump_epoch(Some(data.code.as_str()));
+    Ok(data)
+}
+
+/// 列出失效记录，可按股票过滤
+pub async fn list_invalidations(
+    pool: DbPool,
+    input_code: Option<String>,
+) -> Result<Vec<StockPriceInvalidation>> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::stock_price_invalidations::dsl::*;
+        let conn = pool.get().map_err(Error::from)?;
+        let mut query = stock_price_invalidations.into_boxed();
+        if let Some(input_code) = input_code {
+            query = query.filter(code.eq(input_code));
+        }
+        query
+            .order(id.desc())
+            .load::<StockPriceInvalidation>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data)
+}
+
+/// 重新下载并替换待处理的失效区间，返回替换的区间数
+///
+/// 调用方需持有该股票的价格访问锁
+pub(super) async fn replace_invalidated(
+    pool: &DbPool,
+    jq: &JqdataPool,
+    tick: &str,
+    code: &str,
+    period: Option<&StockPriceTick>,
+) -> Result<usize> {
+    let pending = {
+        let pool = pool.clone();
+        let input_tick = tick.to_owned();
+        let input_code = code.to_owned();
+        tokio::task::spawn_blocking(move || {
+            use crate::schema::stock_price_invalidations::dsl::*;
+            let conn = pool.get().map_err(Error::from)?;
+            stock_price_invalidations
+                .filter(
+                    tick.eq(input_tick)
+                        .and(code.eq(input_code))
+                        .and(replaced_at.is_null()),
+                )
+                .order(id.asc())
+                .load::<StockPriceInvalidation>(&conn)
+                .map_err(Error::from)
+        })
+        .await??
+    };
+    for inv in &pending {
+        // 仅替换已缓存的区间，区间外的数据由常规查询补齐
+        let range = period
+            .map(|p| (inv.start_dt.max(p.start_dt), inv.end_dt.min(p.end_dt)))
+            .filter(|(start_dt, end_dt)| start_dt <= end_dt);
+        let prices = if let Some((start_dt, end_dt)) = range {
+            if estimate_batch_size(start_dt, end_dt, tick) >= MAX_FILL_SIZE {
+                return Err(Error::custom(
+                    ErrorKind::BadRequest,
+                    format!("Invalidated range of record {} exceeds query limit", inv.id),
+                ));
+            }
+            let resp = ticks::query_api_prices(jq, tick, code, start_dt, end_dt).await?;
+            let prices = jq_prices_to_tick_prices(tick, code, resp)?;
+            anomaly::quarantine(pool, code, prices).await?
+        } else {
+            Vec::new()
+        };
+        let pool = pool.clone();
+        let inv_id = inv.id;
+        let input_tick = tick.to_owned();
+        let input_code = code.to_owned();
+        tokio::task::spawn_blocking(move || {
+            let conn = pool.get().map_err(Error::from)?;
+            conn.transaction::<_, Error, _>(|| {
+                let replaced = if let Some((range_start, range_end)) = range {
+                    use crate::schema::stock_tick_prices::dsl::*;
+                    let range_end = range_end + Duration::days(1);
+                    let n = diesel::delete(
+                        stock_tick_prices.filter(
+                            tick.eq(&input_tick)
+                                .and(code.eq(&input_code))
+                                .and(ts.ge(range_start.and_time(NaiveTime::MIN)))
+                                .and(ts.lt(range_end.and_time(NaiveTime::MIN))),
+                        ),
+                    )
+                    .execute(&conn)?;
+                    // 替换须整体提交，仅分批插入
+                    models::insert_tick_prices_batched(
+                        &conn,
+                        &prices,
+                        models::tick_price_batch_size(),
+                    )?;
+                    n
+                } else {
+                    0
+                };
+                {
+                    use crate::schema::stock_price_invalidations::dsl::*;
+                    diesel::update(stock_price_invalidations.find(inv_id))
+                        .set((
+                            replaced_at.eq(Local::now().naive_local()),
+                            replaced_rows.eq(replaced as i32),
+                            inserted_rows.eq(prices.len() as i32),
+                        ))
+                        .execute(&conn)?;
+                }
+                debug!(
+                    "invalidation {} of {} {} replaced: {} rows deleted, {} rows inserted",
+                    inv_id,
+                    input_code,
+                    input_tick,
+                    replaced,
+                    prices.len()
+                );
+                Ok(())
+            })
+        })
+        .await??;
+    }
+    if !pending.is_empty() {
+        epoch::bump_epoch(Some(code));
+    }
+    Ok(pending.len())
+}
@@ -0,0 +1,163 @@
+//! 最新K线
+//!
+//! 供看板批量轮询多只股票的最后一根已完成K线及涨跌幅，
+//! 结果在内存中缓存一段时间，避免为每只股票建立websocket或拉取完整区间。
+
+use super::{get_stock_tick_prices, ticks::StockPrice};
+use crate::handlers::metrics::math::{div_round, round_half_even, METRIC_SCALE};
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result};
+use bigdecimal::{BigDecimal, Zero};
+use chrono::{Local, NaiveDateTime, NaiveTime};
+use lazy_static::*;
+use serde_derive::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tanglism_utils::{resolve_end_ts, Tick, TradingDates, LOCAL_DATES};
+
+/// 单次请求最多的股票数
+pub const MAX_CODES: usize = 50;
+// 结果缓存时长
+const CACHE_TTL: Duration = Duration::from_secs(60);
+// 日线向前查询的交易日数，确保取得前一根K线
+const DAILY_LOOKBACK_DAYS: usize = 5;
+
+lazy_static! {
+    static ref LAST_BARS: Mutex<HashMap<(Tick, String), (Instant, LastBar)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// 最新K线及相对前一根K线收盘价的涨跌幅
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LastBar {
+    pub code: String,
+    pub tick: Tick,
+    pub bar: Option<StockPrice>,
+    pub prev_close: Option<BigDecimal>,
+    // 百分数
+    pub change_pct: Option<BigDecimal>,
+    // 单只股票查询失败时的原因，不影响其他股票
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 批量查询最新K线
+pub async fn get_last_bars(
+    pool: &DbPool,
+    jq: &JqdataPool,
+    tick: Tick,
+    codes: &[String],
+) -> Result<Vec<LastBar>> {
+    if codes.len() > MAX_CODES {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            format!("too many codes: {} > {}", codes.len(), MAX_CODES),
+        ));
+    }
+    let now = Instant::now();
+    let mut rst = Vec::with_capacity(codes.len());
+    for code in codes {
+        let cached = {
+            let cache = LAST_BARS.lock().unwrap();
+            cache
+                .get(&(tick, code.clone()))
+                .filter(|(at, _)| now.saturating_duration_since(*at) < CACHE_TTL)
+                .map(|(_, lb)| lb.clone())
+        };
+        if let Some(lb) = cached {
+            rst.push(lb);
+            continue;
+        }
+        let lb = match query_last_bar(pool, jq, tick, code).await {
+            Ok(lb) => lb,
+            Err(e) => LastBar {
+                code: code.clone(),
+                tick,
+                bar: None,
+                prev_close: None,
+                change_pct: None,
+                error: Some(e.to_string()),
+            },
+        };
+        // 失败的结果不缓存
+        if lb.error.is_none() {
+            let mut cache = LAST_BARS.lock().unwrap();
+            cache.insert((tick, code.clone()), (now, lb.clone()));
+        }
+        rst.push(lb);
+    }
+    Ok(rst)
+}
+
+async fn query_last_bar(pool: &DbPool, jq: &JqdataPool, tick: Tick, code: &str) -> Result<LastBar> {
+    let end_ts = resolve_end_ts(None, tick, Local::now().naive_local())?;
+    let lookback = if tick == Tick::D1 {
+        DAILY_LOOKBACK_DAYS
+    } else {
+        1
+    };
+    let mut start_dt = end_ts.date();
+    for _ in 0..lookback {
+        start_dt = LOCAL_DATES.prev_day(start_dt).ok_or_else(|| {
+            Error::custom(
+                ErrorKind::BadRequest,
+                format!("no trade day before {}", start_dt),
+            )
+        })?;
+    }
+    let prices = get_stock_tick_prices(
+        pool,
+        jq,
+        tick,
+        code,
+        NaiveDateTime::new(start_dt, NaiveTime::MIN),
+        end_ts,
+    )
+    .await?;
+    Ok(last_bar_of(code, tick, &prices))
+}
+
+fn last_bar_of(code: &str, tick: Tick, prices: &[StockPrice]) -> LastBar {
+    let bar = prices.last().cloned();
+    let prev_close = prices.len().checked_sub(2).map(|i| prices[i].close.clone());
+    let change_pct = match (&bar, &prev_close) {
+        (Some(b), Some(p)) if !p.is_zero() => Some(round_half_even(
+            &(div_round(&(&b.close - p), p, METRIC_SCALE) * BigDecimal::from(100)),
+            2,
+        )),
+        _ => None,
+    };
+    LastBar {
+        code: code.to_owned(),
+        tick,
+        bar,
+        prev_close,
+        change_pct,
+        error: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::stock_prices::ticks::PriceBuilder;
+    use tanglism_utils::price;
+
+    #[test]
+    fn test_last_bar_of() {
+        let prices = vec![
+            PriceBuilder::new("2020-08-05 15:00", price!(9.8)).build(),
+            PriceBuilder::new("2020-08-06 15:00", price!(10)).build(),
+            PriceBuilder::new("2020-08-07 15:00", price!(10.37)).build(),
+        ];
+        let lb = last_bar_of("600000.XSHG", Tick::D1, &prices);
+        assert_eq!(Some(BigDecimal::from(10)), lb.prev_close);
+        assert_eq!(Some(price!(3.7)), lb.change_pct);
+        assert_eq!(Some(prices[2].ts), lb.bar.map(|b| b.ts));
+
+        let lb = last_bar_of("600000.XSHG", Tick::D1, &prices[..1]);
+        assert!(lb.bar.is_some());
+        assert_eq!(None, lb.change_pct);
+        assert!(last_bar_of("600000.XSHG", Tick::D1, &[]).bar.is_none());
+    }
+}
@@ -0,0 +1,290 @@
+//! 合成序列
+//!
+//! 两个代码的比值或价差构成的序列，如沪深300与中证500的比值，常用于风格轮动择时。
+//! 定义保存在synthetic_series表中，合成代码以.SYN结尾，与普通代码一样可查询K线、
+//! 计算形态及指标。两条腿各自缓存K线，合成序列在查询时按时刻对齐后计算。
+
+use super::{get_underlying_prices, ticks::StockPrice};
+use crate::handlers::metrics::math::round_half_even;
+use crate::handlers::stocks;
+use crate::models::SyntheticSeries;
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result};
+use bigdecimal::{BigDecimal, Zero};
+use chrono::{Local, NaiveDateTime};
+use diesel::prelude::*;
+use serde_derive::*;
+use std::collections::HashMap;
+use tanglism_utils::Tick;
+
+/// 合成代码的后缀
+pub const SYNTHETIC_SUFFIX: &str = ".SYN";
+// 比值保留的小数位数
+const RATIO_SCALE: i64 = 6;
+const MAX_CODE_LEN: usize = 32;
+
+/// 合成方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyntheticOp {
+    // 左腿除以右腿
+    Ratio,
+    // 左腿减去右腿
+    Spread,
+}
+
+impl SyntheticOp {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SyntheticOp::Ratio => "ratio",
+            SyntheticOp::Spread => "spread",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "ratio" => Some(SyntheticOp::Ratio),
+            "spread" => Some(SyntheticOp::Spread),
+            _ => None,
+        }
+    }
+}
+
+/// 合成序列的定义，保存时两条腿可为名称
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyntheticDef {
+    pub op: SyntheticOp,
+    pub left: String,
+    pub right: String,
+}
+
+/// 是否合成代码
+pub fn is_synthetic_code(code: &str) -> bool {
+    code.len() > SYNTHETIC_SUFFIX.len() && code.to_ascii_uppercase().ends_with(SYNTHETIC_SUFFIX)
+}
+
+/// 查询合成序列的K线，仅包含两条腿均有K线的时刻
+pub async fn get_synthetic_prices(
+    pool: &DbPool,
+    jq: &JqdataPool,
+    tick: Tick,
+    code: &str,
+    start_ts: NaiveDateTime,
+    end_ts: NaiveDateTime,
+) -> Result<Vec<StockPrice>> {
+    let def = get_synthetic(pool.clone(), code.to_owned()).await?;
+    let op = SyntheticOp::parse(&def.op).ok_or_else(|| {
+        Error::custom(
+            ErrorKind::InternalServerError,
+            format!("invalid synthetic op {} of {}", def.op, def.code),
+        )
+    })?;
+    let left = get_underlying_prices(pool, jq, tick, &def.left_code, start_ts, end_ts).await?;
+    let right = get_underlying_prices(pool, jq, tick, &def.right_code, start_ts, end_ts).await?;
+    Ok(combine(op, &left, &right))
+}
+
+/// 按时刻对齐两条腿并计算合成K线
+///
+/// 开盘及收盘价由两腿对应价格直接计算，最高及最低价取两腿同向极值的计算结果，
+/// 并保证覆盖开盘及收盘价。比值在右腿价格为0时跳过该时刻。成交量及成交额无意义，记为0
+pub fn combine(op: SyntheticOp, left: &[StockPrice], right: &[StockPrice]) -> Vec<StockPrice> {
+    let right: HashMap<NaiveDateTime, &StockPrice> = right.iter().map(|p| (p.ts, p)).collect();
+    let calc = |a: &BigDecimal, b: &BigDecimal| -> Option<BigDecimal> {
+        match op {
+            SyntheticOp::Ratio if b.is_zero() => None,
+            SyntheticOp::Ratio => Some(round_half_even(&(a / b), RATIO_SCALE)),
+            SyntheticOp::Spread => Some(a - b),
+        }
+    };
+    left.iter()
+        .filter_map(|l| {
+            let r = right.get(&l.ts)?;
+            let open = calc(&l.open, &r.open)?;
+            let close = calc(&l.close, &r.close)?;
+            let high = calc(&l.high, &r.high)?;
+            let low = calc(&l.low, &r.low)?;
+            let (oc_max, oc_min) = if open > close {
+                (&open, &close)
+            } else {
+                (&close, &open)
+            };
+            let high = high.max(low.clone()).max(oc_max.clone());
+            let low = low.min(high.clone()).min(oc_min.clone());
+            Some(StockPrice {
+                ts: l.ts,
+                open,
+                close,
+                high,
+                low,
+                volume: BigDecimal::zero(),
+                amount: BigDecimal::zero(),
+            })
+        })
+        .collect()
+}
+
+/// 查询合成序列的定义
+pub async fn get_synthetic(pool: DbPool, input_code: String) -> Result<SyntheticSeries> {
+    let input_code = input_code.to_ascii_uppercase();
+    let not_found = format!("synthetic series {} not found", input_code);
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::synthetic_series::dsl::*;
+        let conn = pool.get()?;
+        synthetic_series
+            .find(input_code)
+            .first::<SyntheticSeries>(&conn)
+            .optional()
+            .map_err(Error::from)
+    })
+    .await??;
+    data.ok_or_else(|| Error::custom(ErrorKind::NotFound, not_found))
+}
+
+/// 列出所有合成序列
+pub async fn list_synthetics(pool: DbPool) -> Result<Vec<SyntheticSeries>> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::synthetic_series::dsl::*;
+        let conn = pool.get()?;
+        synthetic_series
+            .order(code.asc())
+            .load::<SyntheticSeries>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data)
+}
+
+/// 保存合成序列，两条腿解析为代码，不可为合成代码
+pub async fn save_synthetic(
+    pool: DbPool,
+    input_code: String,
+    def: SyntheticDef,
+) -> Result<SyntheticSeries> {
+    let input_code = input_code.trim().to_ascii_uppercase();
+    if !is_synthetic_code(&input_code) || input_code.len() > MAX_CODE_LEN {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            format!(
+                "synthetic code must end with {} and be at most {} chars",
+                SYNTHETIC_SUFFIX, MAX_CODE_LEN
+            ),
+        ));
+    }
+    let mut legs = Vec::with_capacity(2);
+    for leg in [def.left, def.right] {
+        if is_synthetic_code(leg.trim()) {
+            return Err(Error::custom(
+                ErrorKind::BadRequest,
+                format!("synthetic series {} cannot be a leg", leg),
+            ));
+        }
+        legs.push(stocks::resolve_stock(pool.clone(), leg).await?);
+    }
+    if legs[0] == legs[1] {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            format!("legs of {} must differ", input_code),
+        ));
+    }
+    let right_leg = legs.pop().unwrap();
+    let left_leg = legs.pop().unwrap();
+    let record = SyntheticSeries {
+        code: input_code,
+        op: def.op.as_str().to_owned(),
+        left_code: left_leg,
+        right_code: right_leg,
+        updated_at: Local::now().naive_local(),
+    };
+    let rst = record.clone();
+    tokio::task::spawn_blocking(move || {
+        use crate::schema::synthetic_series::dsl::*;
+        let conn = pool.get()?;
+        diesel::insert_into(synthetic_series)
+            .values(&record)
+            .on_conflict(code)
+            .do_update()
+            .set((
+                op.eq(&record.op),
+                left_code.eq(&record.left_code),
+                right_code.eq(&record.right_code),
+                updated_at.eq(record.updated_at),
+            ))
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(rst)
+}
+
+/// 删除合成序列，已缓存的两腿K线保留
+pub async fn delete_synthetic(pool: DbPool, input_code: String) -> Result<()> {
+    let input_code = input_code.to_ascii_uppercase();
+    let not_found = format!("synthetic series {} not found", input_code);
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::synthetic_series::dsl::*;
+        let conn = pool.get()?;
+        diesel::delete(synthetic_series.find(input_code))
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    if n == 0 {
+        return Err(Error::custom(ErrorKind::NotFound, not_found));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::stock_prices::ticks::PriceBuilder;
+    use tanglism_utils::price;
+
+    #[test]
+    fn test_combine() {
+        let left = vec![
+            PriceBuilder::new("2020-08-03 15:00", 44)
+                .open(40)
+                .range(39, 45)
+                .build(),
+            PriceBuilder::new("2020-08-04 15:00", 42)
+                .open(44)
+                .range(40, 46)
+                .build(),
+            PriceBuilder::new("2020-08-05 15:00", 48)
+                .open(42)
+                .range(42, 48)
+                .build(),
+        ];
+        // 缺少08-04，且08-05的最高价比值低于收盘价比值
+        let right = vec![
+            PriceBuilder::new("2020-08-03 15:00", 22)
+                .open(20)
+                .range(19, 25)
+                .build(),
+            PriceBuilder::new("2020-08-05 15:00", 20)
+                .open(21)
+                .range(20, 24)
+                .build(),
+        ];
+        let ratio = combine(SyntheticOp::Ratio, &left, &right);
+        assert_eq!(2, ratio.len());
+        assert_eq!(BigDecimal::from(2), ratio[0].open);
+        assert_eq!(price!("2.052632"), ratio[0].high);
+        assert_eq!(BigDecimal::from(2), ratio[0].low);
+        // 最高价扩展至收盘价
+        assert_eq!(price!("2.4"), ratio[1].close);
+        assert_eq!(price!("2.4"), ratio[1].high);
+        assert_eq!(BigDecimal::from(2), ratio[1].low);
+        assert_eq!(BigDecimal::zero(), ratio[0].volume);
+
+        let spread = combine(SyntheticOp::Spread, &left, &right);
+        assert_eq!(BigDecimal::from(22), spread[0].close);
+        assert_eq!(BigDecimal::from(22), spread[0].high);
+        assert_eq!(BigDecimal::from(20), spread[0].low);
+
+        assert!(is_synthetic_code("HS300_ZZ500.syn"));
+        assert!(!is_synthetic_code(".SYN"));
+        assert!(!is_synthetic_code("000300.XSHG"));
+    }
+}
@@ -1,21 +1,27 @@
 use crate::models::StockTickPrice;
 use crate::schema::stock_tick_prices;
-use crate::{DbPool, Error, ErrorKind, Result};
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result};
 use bigdecimal::BigDecimal;
 use chrono::{NaiveDate, NaiveDateTime};
 use diesel::prelude::*;
-use jqdata::{GetPricePeriod, JqdataClient};
+use jqdata::GetPricePeriod;
 use serde_derive::*;
-use tanglism_utils::{end_of_day_str, start_of_day_str};
+use tanglism_utils::{deserialize_price, end_of_day_str, start_of_day_str};
 
 #[derive(Debug, Serialize, Deserialize, Queryable, Clone)]
 pub struct StockPrice {
     pub ts: NaiveDateTime,
+    #[serde(deserialize_with = "deserialize_price")]
     pub open: BigDecimal,
+    #[serde(deserialize_with = "deserialize_price")]
     pub close: BigDecimal,
+    #[serde(deserialize_with = "deserialize_price")]
     pub high: BigDecimal,
+    #[serde(deserialize_with = "deserialize_price")]
     pub low: BigDecimal,
+    #[serde(deserialize_with = "deserialize_price")]
     pub volume: BigDecimal,
+    #[serde(deserialize_with = "deserialize_price")]
     pub amount: BigDecimal,
 }
 
@@ -100,14 +106,14 @@ pub async fn query_db_multiple_prices(
 }
 
 pub async fn query_api_prices(
-    jq: &JqdataClient,
+    jq: &JqdataPool,
     tick: &str,
     code: &str,
     start_dt: NaiveDate,
     end_dt: NaiveDate,
 ) -> Result<Vec<jqdata::Price>> {
     let resp = jq
-        .execute(GetPricePeriod {
+        .execute(|| GetPricePeriod {
             code: code.to_owned(),
             unit: tick.to_owned(),
             date: start_of_day_str(start_dt),
@@ -117,3 +123,90 @@ pub async fn query_api_prices(
         .await?;
     Ok(resp)
 }
+
+/// 测试用的K线构造器
+///
+/// 默认开高低收均为收盘价，成交量100，成交额为成交量乘收盘价
+#[cfg(test)]
+pub(crate) struct PriceBuilder {
+    ts: NaiveDateTime,
+    open: Option<BigDecimal>,
+    close: BigDecimal,
+    high: Option<BigDecimal>,
+    low: Option<BigDecimal>,
+    volume: BigDecimal,
+    amount: Option<BigDecimal>,
+}
+
+#[cfg(test)]
+impl PriceBuilder {
+    // 时刻格式为"%Y-%m-%d %H:%M"
+    pub(crate) fn new(ts: &str, close: impl Into<BigDecimal>) -> Self {
+        PriceBuilder {
+            ts: NaiveDateTime::parse_from_str(ts, "%Y-%m-%d %H:%M").unwrap(),
+            open: None,
+            close: close.into(),
+            high: None,
+            low: None,
+            volume: BigDecimal::from(100),
+            amount: None,
+        }
+    }
+
+    pub(crate) fn open(mut self, open: impl Into<BigDecimal>) -> Self {
+        self.open = Some(open.into());
+        self
+    }
+
+    pub(crate) fn range(mut self, low: impl Into<BigDecimal>, high: impl Into<BigDecimal>) -> Self {
+        self.low = Some(low.into());
+        self.high = Some(high.into());
+        self
+    }
+
+    pub(crate) fn volume(mut self, volume: impl Into<BigDecimal>) -> Self {
+        self.volume = volume.into();
+        self
+    }
+
+    pub(crate) fn amount(mut self, amount: impl Into<BigDecimal>) -> Self {
+        self.amount = Some(amount.into());
+        self
+    }
+
+    pub(crate) fn build(self) -> StockPrice {
+        let PriceBuilder {
+            ts,
+            open,
+            close,
+            high,
+            low,
+            volume,
+            amount,
+        } = self;
+        StockPrice {
+            ts,
+            open: open.unwrap_or_else(|| close.clone()),
+            high: high.unwrap_or_else(|| close.clone()),
+            low: low.unwrap_or_else(|| close.clone()),
+            amount: amount.unwrap_or_else(|| &volume * &close),
+            close,
+            volume,
+        }
+    }
+
+    pub(crate) fn build_tick(self, tick: &str, code: &str) -> StockTickPrice {
+        let p = self.build();
+        StockTickPrice {
+            tick: tick.to_owned(),
+            code: code.to_owned(),
+            ts: p.ts,
+            open: p.open,
+            close: p.close,
+            high: p.high,
+            low: p.low,
+            volume: p.volume,
+            amount: p.amount,
+        }
+    }
+}
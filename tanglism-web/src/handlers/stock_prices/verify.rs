@@ -0,0 +1,187 @@
+//! 价格数据核对
+//!
+//! 重新下载已缓存区间内的K线并与数据库逐根比对，
+//! 用于发现数据源对历史数据的静默修正，修正可通过失效区间重新下载。
+
+use super::{jq_prices_to_tick_prices, query_db_period, ticks, ticks::StockPrice};
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result};
+use chrono::{NaiveDate, NaiveDateTime};
+use serde_derive::*;
+use std::collections::BTreeMap;
+use tanglism_utils::Tick;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiffKind {
+    // 数据源存在而数据库缺失
+    Missing,
+    // 数据库存在而数据源缺失
+    Extra,
+    // 价格或成交量不一致
+    Mismatch,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PriceDiff {
+    pub ts: NaiveDateTime,
+    pub kind: DiffKind,
+    // 不一致的字段
+    pub fields: Vec<&'static str>,
+}
+
+/// 核对结果
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub code: String,
+    pub tick: Tick,
+    pub start_dt: NaiveDate,
+    pub end_dt: NaiveDate,
+    pub db_bars: usize,
+    pub api_bars: usize,
+    pub diffs: Vec<PriceDiff>,
+}
+
+/// 核对区间内的价格，区间限制在已缓存的范围内
+pub async fn verify_prices(
+    pool: &DbPool,
+    jq: &JqdataPool,
+    tick: Tick,
+    code: &str,
+    start_dt: NaiveDate,
+    end_dt: NaiveDate,
+) -> Result<VerifyReport> {
+    let tick_str = tick.to_string();
+    let period = query_db_period(pool, &tick_str, code)
+        .await?
+        .ok_or_else(|| {
+            Error::custom(
+                ErrorKind::NotFound,
+                format!("no cached {} prices of {}", tick_str, code),
+            )
+        })?;
+    let start_dt = start_dt.max(period.start_dt);
+    let end_dt = end_dt.min(period.end_dt);
+    if start_dt > end_dt {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            format!("no cached {} prices of {} in range", tick_str, code),
+        ));
+    }
+    let db_prices = ticks::query_db_prices(
+        pool.clone(),
+        tick_str.clone(),
+        code.to_owned(),
+        start_dt,
+        end_dt,
+    )
+    .await?;
+    let resp = ticks::query_api_prices(jq, &tick_str, code, start_dt, end_dt).await?;
+    // 与写入数据库时的处理保持一致，避免盘后K线被误报为差异
+    let api_prices: Vec<_> = jq_prices_to_tick_prices(&tick_str, code, resp)?
+        .into_iter()
+        .map(|p| StockPrice {
+            ts: p.ts,
+            open: p.open,
+            close: p.close,
+            high: p.high,
+            low: p.low,
+            volume: p.volume,
+            amount: p.amount,
+        })
+        .collect();
+    Ok(VerifyReport {
+        code: code.to_owned(),
+        tick,
+        start_dt,
+        end_dt,
+        db_bars: db_prices.len(),
+        api_bars: api_prices.len(),
+        diffs: diff_prices(&db_prices, &api_prices),
+    })
+}
+
+/// 按时刻比对数据库与数据源的K线
+pub fn diff_prices(db: &[StockPrice], api: &[StockPrice]) -> Vec<PriceDiff> {
+    let mut bars: BTreeMap<NaiveDateTime, (Option<&StockPrice>, Option<&StockPrice>)> =
+        BTreeMap::new();
+    for p in db {
+        bars.entry(p.ts).or_default().0 = Some(p);
+    }
+    for p in api {
+        bars.entry(p.ts).or_default().1 = Some(p);
+    }
+    bars.into_iter()
+        .filter_map(|(ts, bar)| match bar {
+            (Some(d), Some(a)) => {
+                let fields: Vec<&'static str> = [
+                    ("open", d.open == a.open),
+                    ("close", d.close == a.close),
+                    ("high", d.high == a.high),
+                    ("low", d.low == a.low),
+                    ("volume", d.volume == a.volume),
+                    ("amount", d.amount == a.amount),
+                ]
+                .iter()
+                .filter(|(_, same)| !same)
+                .map(|(f, _)| *f)
+                .collect();
+                if fields.is_empty() {
+                    None
+                } else {
+                    Some(PriceDiff {
+                        ts,
+                        kind: DiffKind::Mismatch,
+                        fields,
+                    })
+                }
+            }
+            (None, Some(_)) => Some(PriceDiff {
+                ts,
+                kind: DiffKind::Missing,
+                fields: Vec::new(),
+            }),
+            (Some(_), None) => Some(PriceDiff {
+                ts,
+                kind: DiffKind::Extra,
+                fields: Vec::new(),
+            }),
+            (None, None) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::stock_prices::ticks::PriceBuilder;
+
+    // 开高低价及成交额固定，差异仅来自收盘价及成交量
+    fn price(ts: &str, close: i32, volume: i32) -> StockPrice {
+        PriceBuilder::new(ts, close)
+            .open(10)
+            .range(9, 12)
+            .volume(volume)
+            .amount(1000)
+            .build()
+    }
+
+    #[test]
+    fn test_diff_prices() {
+        let db = vec![
+            price("2020-07-06 10:00", 11, 100),
+            price("2020-07-06 10:30", 11, 100),
+            price("2020-07-06 11:00", 11, 100),
+        ];
+        let api = vec![
+            price("2020-07-06 10:00", 11, 100),
+            price("2020-07-06 10:30", 10, 120),
+            price("2020-07-06 11:30", 11, 100),
+        ];
+        let diffs = diff_prices(&db, &api);
+        assert_eq!(3, diffs.len());
+        assert_eq!(DiffKind::Mismatch, diffs[0].kind);
+        assert_eq!(vec!["close", "volume"], diffs[0].fields);
+        assert_eq!(DiffKind::Extra, diffs[1].kind);
+        assert_eq!(DiffKind::Missing, diffs[2].kind);
+        assert!(diff_prices(&db, &db).is_empty());
+    }
+}
@@ -1,8 +1,13 @@
-use crate::models::Security;
+use super::stock_prices::continuous::parse_continuous_code;
+use super::stock_prices::synthetic::is_synthetic_code;
+use crate::models::{Security, SecurityName};
 use crate::schema::securities;
-use crate::{DbPool, Result};
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result};
 use chrono::NaiveDate;
+use jqdata::{GetAllSecurities, SecurityKind};
 use serde_derive::*;
+use std::collections::HashMap;
+use tanglism_utils::SecurityCode;
 
 #[derive(Queryable, Debug, Serialize, Deserialize, Clone)]
 pub struct Stock {
@@ -27,8 +32,23 @@ const STOCK_COLUMNS: StockColumns = (
     securities::end_date,
 );
 
+// 当前名称的截止日期
+fn open_end() -> NaiveDate {
+    NaiveDate::from_ymd_opt(2200, 1, 1).unwrap()
+}
+
+/// 股票详情，附带按生效日期排序的名称历史
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StockDetail {
+    #[serde(flatten)]
+    pub stock: Stock,
+    pub names: Vec<SecurityName>,
+}
+
+// 关键字同时匹配历史简称，更名前的笔记及快照仍可解析
 pub async fn search_keyword_stocks(pool: DbPool, keyword: String) -> Result<Vec<Stock>> {
     use crate::schema::securities::dsl::*;
+    use crate::schema::security_names;
     use diesel::prelude::*;
     // 使用线程池执行阻塞查询
     let rs = tokio::task::spawn_blocking::<_, Result<Vec<Stock>>>(move || {
@@ -38,9 +58,13 @@ pub async fn search_keyword_stocks(pool: DbPool, keyword: String) -> Result<Vec<
             let code_prefix = format!("{}%", keyword);
             let name_prefix = format!("{}%", keyword);
             let all_match = format!("%{}%", keyword);
+            let renamed = security_names::table
+                .select(security_names::code)
+                .filter(security_names::display_name.ilike(all_match.clone()));
             query = query.filter(
                 code.ilike(code_prefix)
-                    .or(name.ilike(name_prefix).or(display_name.ilike(all_match))),
+                    .or(name.ilike(name_prefix).or(display_name.ilike(all_match)))
+                    .or(code.eq_any(renamed)),
             );
         }
         let data = query.select(STOCK_COLUMNS).load::<Stock>(&conn)?;
@@ -103,3 +127,298 @@ pub async fn search_prioritized_stocks(pool: DbPool) -> Result<Vec<Security>> {
     .await??;
     Ok(rs)
 }
+
+/// 查询股票详情，支持代码、名称或历史简称
+pub async fn get_stock_detail(pool: DbPool, input: String) -> Result<StockDetail> {
+    use diesel::prelude::*;
+    let input_code = resolve_stock(pool.clone(), input).await?;
+    let rs = tokio::task::spawn_blocking(move || {
+        use crate::schema::security_names::dsl::*;
+        let conn = pool.get()?;
+        let stock = securities::table
+            .filter(securities::code.eq(&input_code))
+            .select(STOCK_COLUMNS)
+            .first::<Stock>(&conn)
+            .optional()?
+            .ok_or_else(|| {
+                Error::custom(
+                    ErrorKind::NotFound,
+                    format!("stock {} not found", input_code),
+                )
+            })?;
+        let names = security_names
+            .filter(code.eq(&input_code))
+            .order(valid_from.asc())
+            .load::<SecurityName>(&conn)?;
+        Ok::<_, Error>(StockDetail { stock, names })
+    })
+    .await??;
+    Ok(rs)
+}
+
+/// 证券同步的结果
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SecuritiesSync {
+    pub added: usize,
+    pub renamed: usize,
+}
+
+// 证券主数据的变化
+#[derive(Debug, Clone)]
+enum SecurityChange {
+    Added(Stock),
+    Renamed(Stock),
+}
+
+// 与当前记录比较，简称或名称变化视为更名
+fn diff_securities(
+    current: &HashMap<String, (String, String)>,
+    fetched: Vec<Stock>,
+) -> Vec<SecurityChange> {
+    fetched
+        .into_iter()
+        .filter_map(|s| match current.get(&s.code) {
+            None => Some(SecurityChange::Added(s)),
+            Some((dn, n)) if dn != &s.display_name || n != &s.name => {
+                Some(SecurityChange::Renamed(s))
+            }
+            Some(_) => None,
+        })
+        .collect()
+}
+
+/// 同步股票主数据
+///
+/// 更名时不覆盖原名称：原记录的valid_to截止于同步日期，并追加新名称的记录
+pub async fn sync_securities(
+    pool: &DbPool,
+    jq: &JqdataPool,
+    dt: NaiveDate,
+) -> Result<SecuritiesSync> {
+    use diesel::prelude::*;
+    let parse_date = |s: &str| {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .map_err(|e| Error::custom(ErrorKind::Jqdata, format!("invalid date {}: {}", s, e)))
+    };
+    let fetched = jq
+        .execute(|| GetAllSecurities {
+            code: SecurityKind::Stock,
+            date: None,
+        })
+        .await?
+        .into_iter()
+        .map(|s| {
+            Ok(Stock {
+                start_date: parse_date(&s.start_date)?,
+                end_date: parse_date(&s.end_date)?,
+                code: s.code,
+                display_name: s.display_name,
+                name: s.name,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let pool = pool.clone();
+    let rs = tokio::task::spawn_blocking(move || {
+        use crate::schema::security_names::dsl::*;
+        let conn = pool.get()?;
+        conn.transaction::<_, Error, _>(|| {
+            let current: HashMap<String, (String, String)> = securities::table
+                .filter(securities::tp.eq("stock"))
+                .select((securities::code, securities::display_name, securities::name))
+                .load::<(String, String, String)>(&conn)?
+                .into_iter()
+                .map(|(c, dn, n)| (c, (dn, n)))
+                .collect();
+            let mut summary = SecuritiesSync::default();
+            for change in diff_securities(&current, fetched) {
+                match change {
+                    SecurityChange::Added(s) => {
+                        diesel::insert_into(securities::table)
+                            .values((
+                                securities::code.eq(&s.code),
+                                securities::display_name.eq(&s.display_name),
+                                securities::name.eq(&s.name),
+                                securities::start_date.eq(s.start_date),
+                                securities::end_date.eq(s.end_date),
+                                securities::tp.eq("stock"),
+                            ))
+                            .execute(&conn)?;
+                        diesel::insert_into(security_names)
+                            .values(&SecurityName {
+                                code: s.code,
+                                display_name: s.display_name,
+                                name: s.name,
+                                valid_from: s.start_date,
+                                valid_to: open_end(),
+                            })
+                            .on_conflict_do_nothing()
+                            .execute(&conn)?;
+                        summary.added += 1;
+                    }
+                    SecurityChange::Renamed(s) => {
+                        diesel::update(securities::table.filter(securities::code.eq(&s.code)))
+                            .set((
+                                securities::display_name.eq(&s.display_name),
+                                securities::name.eq(&s.name),
+                                securities::end_date.eq(s.end_date),
+                            ))
+                            .execute(&conn)?;
+                        // 同日多次更名时仅保留最后一次
+                        diesel::delete(
+                            security_names
+                                .filter(code.eq(&s.code))
+                                .filter(valid_from.eq(dt)),
+                        )
+                        .execute(&conn)?;
+                        diesel::update(
+                            security_names
+                                .filter(code.eq(&s.code))
+                                .filter(valid_to.gt(dt)),
+                        )
+                        .set(valid_to.eq(dt))
+                        .execute(&conn)?;
+                        diesel::insert_into(security_names)
+                            .values(&SecurityName {
+                                code: s.code,
+                                display_name: s.display_name,
+                                name: s.name,
+                                valid_from: dt,
+                                valid_to: open_end(),
+                            })
+                            .execute(&conn)?;
+                        summary.renamed += 1;
+                    }
+                }
+            }
+            Ok(summary)
+        })
+    })
+    .await??;
+    Ok(rs)
+}
+
+// 歧义时最多列出的候选数
+const MAX_CANDIDATES: usize = 10;
+
+/// 将代码、部分代码或名称解析为唯一的股票代码
+///
+/// 优先精确匹配代码、名称或简称，其次使用关键字搜索，期货连续合约代码直接返回
+///
+/// 数字代码及sh600000、600000.SH等格式先补全为完整代码，指数及场内基金直接返回
+pub async fn resolve_stock(pool: DbPool, input: String) -> Result<String> {
+    let keyword = input.trim().to_owned();
+    if parse_continuous_code(&keyword).is_some() || is_synthetic_code(&keyword) {
+        return Ok(keyword.to_ascii_uppercase());
+    }
+    if let Some(sc) = SecurityCode::autocomplete(&keyword) {
+        if !sc.is_stock() {
+            return Ok(sc.code().to_owned());
+        }
+        let candidates = search_keyword_stocks(pool.clone(), sc.code().to_owned()).await?;
+        if let Ok(code) = pick_stock(sc.code(), candidates) {
+            return Ok(code);
+        }
+    }
+    let candidates = search_keyword_stocks(pool, keyword.clone()).await?;
+    pick_stock(&keyword, candidates)
+}
+
+fn pick_stock(keyword: &str, candidates: Vec<Stock>) -> Result<String> {
+    if keyword.is_empty() {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            "stock code or name is empty".to_owned(),
+        ));
+    }
+    let exact: Vec<&Stock> = candidates
+        .iter()
+        .filter(|s| {
+            s.code == keyword || s.display_name == keyword || s.name.eq_ignore_ascii_case(keyword)
+        })
+        .collect();
+    let matched: Vec<&Stock> = if exact.is_empty() {
+        candidates.iter().collect()
+    } else {
+        exact
+    };
+    match matched.len() {
+        0 => Err(Error::custom(
+            ErrorKind::NotFound,
+            format!("stock {} not found", keyword),
+        )),
+        1 => Ok(matched[0].code.clone()),
+        n => {
+            let listed: Vec<String> = matched
+                .iter()
+                .take(MAX_CANDIDATES)
+                .map(|s| format!("{}({})", s.code, s.display_name))
+                .collect();
+            Err(Error::custom(
+                ErrorKind::BadRequest,
+                format!(
+                    "ambiguous stock {}, {} candidates: {}",
+                    keyword,
+                    n,
+                    listed.join(", ")
+                ),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stock(code: &str, display_name: &str, name: &str) -> Stock {
+        Stock {
+            code: code.to_owned(),
+            display_name: display_name.to_owned(),
+            name: name.to_owned(),
+            start_date: NaiveDate::from_ymd_opt(2010, 1, 1).unwrap(),
+            end_date: NaiveDate::from_ymd_opt(2200, 1, 1).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_pick_stock() {
+        let candidates = vec![
+            stock("000001.XSHE", "平安银行", "PAYH"),
+            stock("601318.XSHG", "中国平安", "ZGPA"),
+        ];
+        assert_eq!(
+            "601318.XSHG",
+            pick_stock("中国平安", candidates.clone()).unwrap()
+        );
+        assert_eq!(
+            "000001.XSHE",
+            pick_stock("payh", candidates.clone()).unwrap()
+        );
+        let err = pick_stock("平安", candidates).unwrap_err().to_string();
+        assert!(err.contains("ambiguous"));
+        assert!(err.contains("000001.XSHE(平安银行)"));
+        assert!(pick_stock("000002", Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_diff_securities() {
+        let current: HashMap<String, (String, String)> = vec![
+            ("000001.XSHE", "平安银行", "PAYH"),
+            ("600225.XSHG", "天津松江", "TJSJ"),
+        ]
+        .into_iter()
+        .map(|(c, dn, n)| (c.to_owned(), (dn.to_owned(), n.to_owned())))
+        .collect();
+        let changes = diff_securities(
+            &current,
+            vec![
+                stock("000001.XSHE", "平安银行", "PAYH"),
+                stock("600225.XSHG", "*ST松江", "TJSJ"),
+                stock("688001.XSHG", "华兴源创", "HXYC"),
+            ],
+        );
+        assert_eq!(2, changes.len());
+        assert!(matches!(&changes[0], SecurityChange::Renamed(s) if s.display_name == "*ST松江"));
+        assert!(matches!(&changes[1], SecurityChange::Added(s) if s.code == "688001.XSHG"));
+    }
+}
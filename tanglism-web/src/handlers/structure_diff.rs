@@ -0,0 +1,226 @@
+//! 形态结构变化
+//!
+//! 对同一股票和配置，分别计算截至日期A和日期B的笔、线段与中枢，
+//! 按起点对齐后汇总新增、消失以及终点被修改的结构。
+
+use super::stock_prices::{self, ticks};
+use super::tanglism;
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result, ResultExt};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use serde_derive::*;
+use std::collections::BTreeMap;
+use tanglism_morph::{CenterConfig, PartingConfig, StrokeConfig};
+use tanglism_utils::Tick;
+
+/// 结构变化查询参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffParam {
+    pub tick: Tick,
+    // 两次分析共同的起始日
+    pub start_dt: NaiveDate,
+    pub date_a: NaiveDate,
+    pub date_b: NaiveDate,
+    pub stroke_cfg: Option<String>,
+}
+
+/// 结构区间
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Span {
+    pub start_ts: NaiveDateTime,
+    pub end_ts: NaiveDateTime,
+}
+
+/// 起点相同而终点改变的结构
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Modified {
+    pub start_ts: NaiveDateTime,
+    pub end_ts_a: NaiveDateTime,
+    pub end_ts_b: NaiveDateTime,
+}
+
+/// 单层结构的变化
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LayerDiff {
+    pub added: Vec<Span>,
+    pub removed: Vec<Span>,
+    pub modified: Vec<Modified>,
+}
+
+impl LayerDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StructureDiff {
+    pub code: String,
+    pub tick: Tick,
+    pub date_a: NaiveDate,
+    pub date_b: NaiveDate,
+    pub strokes: LayerDiff,
+    pub segments: LayerDiff,
+    pub centers: LayerDiff,
+}
+
+#[derive(Debug, Default)]
+struct Structure {
+    strokes: Vec<Span>,
+    segments: Vec<Span>,
+    centers: Vec<Span>,
+}
+
+pub async fn diff_structure(
+    db: &DbPool,
+    jq: &JqdataPool,
+    code: &str,
+    param: DiffParam,
+) -> Result<StructureDiff> {
+    if param.start_dt > param.date_a || param.date_a > param.date_b {
+        return Err(Error::custom(
+            ErrorKind::BadRequest,
+            format!(
+                "require start_dt {} <= date_a {} <= date_b {}",
+                param.start_dt, param.date_a, param.date_b
+            ),
+        ));
+    }
+    let stroke_cfg = match param.stroke_cfg {
+        Some(ref s) => tanglism::parse_stroke_cfg(s).context("invalid stroke cfg")?,
+        None => StrokeConfig::default(),
+    };
+    let end_of_day = |dt: NaiveDate| dt.and_time(NaiveTime::MIN) + chrono::Duration::days(1);
+    let prices = stock_prices::get_stock_tick_prices(
+        db,
+        jq,
+        param.tick,
+        code,
+        param.start_dt.and_time(NaiveTime::MIN),
+        end_of_day(param.date_b),
+    )
+    .await?;
+    let ts_a = end_of_day(param.date_a);
+    let known = prices.iter().take_while(|p| p.ts < ts_a).count();
+    let a = analyze(&prices[..known], param.tick, &stroke_cfg)?;
+    let b = analyze(&prices, param.tick, &stroke_cfg)?;
+    Ok(StructureDiff {
+        code: code.to_owned(),
+        tick: param.tick,
+        date_a: param.date_a,
+        date_b: param.date_b,
+        strokes: diff_spans(&a.strokes, &b.strokes),
+        segments: diff_spans(&a.segments, &b.segments),
+        centers: diff_spans(&a.centers, &b.centers),
+    })
+}
+
+fn analyze(
+    prices: &[ticks::StockPrice],
+    tick: Tick,
+    stroke_cfg: &StrokeConfig,
+) -> Result<Structure> {
+    if prices.is_empty() {
+        return Ok(Structure::default());
+    }
+    let pts = tanglism::get_tanglism_partings(prices, &PartingConfig::default())?;
+    let sks = tanglism::get_tanglism_strokes(&pts, tick, stroke_cfg.clone())?;
+    let sgs = tanglism::get_tanglism_segments(&sks)?;
+    let center_cfg = CenterConfig::default();
+    let sts = tanglism::get_tanglism_subtrends(&sgs, &sks, tick, 1, &center_cfg)?;
+    let centers = tanglism::get_tanglism_centers(&sts, &center_cfg)?;
+    Ok(Structure {
+        strokes: sks
+            .iter()
+            .map(|sk| Span {
+                start_ts: sk.start_pt.extremum_ts,
+                end_ts: sk.end_pt.extremum_ts,
+            })
+            .collect(),
+        segments: sgs
+            .iter()
+            .map(|sg| Span {
+                start_ts: sg.start_pt.extremum_ts,
+                end_ts: sg.end_pt.extremum_ts,
+            })
+            .collect(),
+        centers: centers
+            .iter()
+            .filter_map(|ce| ce.center())
+            .map(|c| Span {
+                start_ts: c.start.ts,
+                end_ts: c.end.ts,
+            })
+            .collect(),
+    })
+}
+
+// 按起点对齐两组结构
+fn diff_spans(a: &[Span], b: &[Span]) -> LayerDiff {
+    let a: BTreeMap<NaiveDateTime, NaiveDateTime> =
+        a.iter().map(|s| (s.start_ts, s.end_ts)).collect();
+    let b: BTreeMap<NaiveDateTime, NaiveDateTime> =
+        b.iter().map(|s| (s.start_ts, s.end_ts)).collect();
+    let mut diff = LayerDiff::default();
+    for (start_ts, end_ts_b) in &b {
+        match a.get(start_ts) {
+            None => diff.added.push(Span {
+                start_ts: *start_ts,
+                end_ts: *end_ts_b,
+            }),
+            Some(end_ts_a) if end_ts_a != end_ts_b => diff.modified.push(Modified {
+                start_ts: *start_ts,
+                end_ts_a: *end_ts_a,
+                end_ts_b: *end_ts_b,
+            }),
+            _ => (),
+        }
+    }
+    for (start_ts, end_ts_a) in &a {
+        if !b.contains_key(start_ts) {
+            diff.removed.push(Span {
+                start_ts: *start_ts,
+                end_ts: *end_ts_a,
+            });
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: &str, end: &str) -> Span {
+        let ts = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        Span {
+            start_ts: ts(start),
+            end_ts: ts(end),
+        }
+    }
+
+    #[test]
+    fn test_diff_spans() {
+        let a = vec![
+            span("2020-02-03 10:00", "2020-02-03 14:00"),
+            span("2020-02-03 14:00", "2020-02-04 11:00"),
+            span("2020-02-04 11:00", "2020-02-04 14:30"),
+        ];
+        let b = vec![
+            span("2020-02-03 10:00", "2020-02-03 14:00"),
+            span("2020-02-03 14:00", "2020-02-05 10:30"),
+            span("2020-02-05 10:30", "2020-02-05 14:00"),
+        ];
+        let diff = diff_spans(&a, &b);
+        assert_eq!(
+            vec![span("2020-02-05 10:30", "2020-02-05 14:00")],
+            diff.added
+        );
+        assert_eq!(
+            vec![span("2020-02-04 11:00", "2020-02-04 14:30")],
+            diff.removed
+        );
+        assert_eq!(1, diff.modified.len());
+        assert_eq!(b[1].end_ts, diff.modified[0].end_ts_b);
+        assert!(diff_spans(&a, &a).is_empty());
+    }
+}
@@ -1,14 +1,23 @@
+use super::metrics::Metric;
 use super::stock_prices::ticks;
 use crate::{Error, ErrorKind, Result};
-use bigdecimal::BigDecimal;
 use chrono::NaiveDateTime;
 use serde_derive::*;
-use std::str::FromStr;
 use tanglism_morph::{
-    ks_to_pts, pts_to_sks, sks_to_sgs, trend_as_subtrend, unify_centers, unify_subtrends,
-    unify_trends, StrokeConfig, StrokeJudge, TrendConfig, K,
+    day_boundaries, ks_to_pts_partitioned, pts_to_sks_partitioned, sks_to_sgs_partitioned,
 };
-use tanglism_morph::{CenterElement, Parting, Segment, Stroke, SubTrend, Trend};
+use tanglism_morph::{
+    fill_stroke_ranges, ks_to_pts_with_cfg, pts_to_sks, pts_to_sks_traced, sks_to_sgs,
+    sks_to_sgs_traced, trend_as_subtrend, unify_centers_with_cfg, unify_subtrends, unify_trends,
+    CenterConfig, PartingConfig, StrokeAmplitude, StrokeConfig, StrokeJudge, TrendConfig, K,
+};
+use tanglism_morph::{find_divergences, Divergence, ValuePoint};
+use tanglism_morph::{
+    ks_to_pts_snapshot, pts_to_sks_snapshot, sks_to_sgs_snapshot, PartingAccSnapshot,
+    SegmentAccSnapshot, StrokeAccSnapshot,
+};
+use tanglism_morph::{CenterElement, Parting, Segment, Stroke, SubTrend, Trace, Trend};
+use tanglism_utils::{parse_price, price, Tick};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Response<T> {
@@ -26,11 +35,16 @@ pub struct Param {
     // 成笔的三种逻辑，默认使用独立K线成笔
     // 1. indep_k=true/false 包含1独立K线/不包含独立K线
     // 2. gap_opening=morning/all 开盘跳空/包含午盘
-    // 3. gap_ratio=0.01/.../0.10 缺口比例大于指定值
+    // 3. gap_ratio=0.01/.../0.10/2% 缺口比例大于指定值
+    // 同时指定2和3时默认任一满足即成笔，judge_op=and表示全部满足
+    // 另可指定min_amplitude=0.05/0.5% 最小笔幅度（绝对价差/百分比）
     pub stroke_cfg: Option<String>,
 }
 
-pub fn get_tanglism_partings(prices: &[ticks::StockPrice]) -> Result<Vec<Parting>> {
+pub fn get_tanglism_partings(
+    prices: &[ticks::StockPrice],
+    parting_cfg: &PartingConfig,
+) -> Result<Vec<Parting>> {
     let ks: Vec<K> = prices
         .iter()
         .map(|p| K {
@@ -39,27 +53,124 @@ pub fn get_tanglism_partings(prices: &[ticks::StockPrice]) -> Result<Vec<Parting
             high: p.high.clone(),
         })
         .collect();
-    ks_to_pts(&ks).map_err(|e| e.into())
+    ks_to_pts_with_cfg(&ks, parting_cfg.clone()).map_err(|e| e.into())
+}
+
+// 日内模式，各交易日独立计算分型，开盘时重新开始
+pub fn get_tanglism_partings_by_day(
+    prices: &[ticks::StockPrice],
+    parting_cfg: &PartingConfig,
+) -> Result<Vec<Parting>> {
+    let ks: Vec<K> = prices
+        .iter()
+        .map(|p| K {
+            ts: p.ts,
+            low: p.low.clone(),
+            high: p.high.clone(),
+        })
+        .collect();
+    let boundaries = day_boundaries(&ks, |k| k.ts);
+    ks_to_pts_partitioned(&ks, parting_cfg.clone(), &boundaries).map_err(Into::into)
 }
 
 pub fn get_tanglism_strokes(
     pts: &[Parting],
-    tick: &str,
+    tick: Tick,
     stroke_cfg: StrokeConfig,
 ) -> Result<Vec<Stroke>> {
     pts_to_sks(pts, tick, stroke_cfg).map_err(Into::into)
 }
 
+// 日内模式，笔不跨越交易日
+pub fn get_tanglism_strokes_by_day(
+    pts: &[Parting],
+    tick: Tick,
+    stroke_cfg: StrokeConfig,
+) -> Result<Vec<Stroke>> {
+    let boundaries = day_boundaries(pts, |pt| pt.start_ts);
+    pts_to_sks_partitioned(pts, tick, stroke_cfg, &boundaries).map_err(Into::into)
+}
+
+// 线段附带组成笔的下标区间，是否输出由调用方决定
 pub fn get_tanglism_segments(sks: &[Stroke]) -> Result<Vec<Segment>> {
-    sks_to_sgs(&sks).map_err(Into::into)
+    let mut sgs = sks_to_sgs(&sks)?;
+    fill_stroke_ranges(sks, &mut sgs);
+    Ok(sgs)
+}
+
+// 日内模式，线段不跨越交易日
+pub fn get_tanglism_segments_by_day(sks: &[Stroke]) -> Result<Vec<Segment>> {
+    let boundaries = day_boundaries(sks, |sk| sk.start_pt.start_ts);
+    let mut sgs = sks_to_sgs_partitioned(sks, &boundaries)?;
+    fill_stroke_ranges(sks, &mut sgs);
+    Ok(sgs)
+}
+
+// 去除线段的明细字段以减小输出
+pub fn brief_segments(sgs: &[Segment]) -> Vec<Segment> {
+    sgs.iter()
+        .map(|sg| Segment {
+            stroke_range: None,
+            ..sg.clone()
+        })
+        .collect()
+}
+
+// 笔的决策日志
+pub fn get_tanglism_stroke_traces(
+    pts: &[Parting],
+    tick: Tick,
+    stroke_cfg: StrokeConfig,
+) -> Result<Vec<Trace>> {
+    let (_, traces) = pts_to_sks_traced(pts, tick, stroke_cfg)?;
+    Ok(traces)
+}
+
+// 线段的决策日志
+pub fn get_tanglism_segment_traces(sks: &[Stroke]) -> Result<Vec<Trace>> {
+    let (_, traces) = sks_to_sgs_traced(sks)?;
+    Ok(traces)
+}
+
+/// 分型、笔及线段累加器结束时的内部状态，用于远程诊断
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccumulatorSnapshots {
+    pub parting: PartingAccSnapshot,
+    pub stroke: StrokeAccSnapshot,
+    pub segment: SegmentAccSnapshot,
+}
+
+pub fn get_tanglism_accumulators(
+    prices: &[ticks::StockPrice],
+    tick: Tick,
+    parting_cfg: &PartingConfig,
+    stroke_cfg: StrokeConfig,
+) -> Result<AccumulatorSnapshots> {
+    let ks: Vec<K> = prices
+        .iter()
+        .map(|p| K {
+            ts: p.ts,
+            low: p.low.clone(),
+            high: p.high.clone(),
+        })
+        .collect();
+    let (pts, parting) = ks_to_pts_snapshot(&ks, parting_cfg.clone())?;
+    let (sks, stroke) = pts_to_sks_snapshot(&pts, tick, stroke_cfg)?;
+    let (_, segment) = sks_to_sgs_snapshot(&sks)?;
+    Ok(AccumulatorSnapshots {
+        parting,
+        stroke,
+        segment,
+    })
 }
 
 // segments and strokes must be 1m ticked
 pub fn get_tanglism_subtrends(
     segments: &[Segment],
     strokes: &[Stroke],
-    tick: &str,
+    tick: Tick,
     level: i32,
+    center_cfg: &CenterConfig,
 ) -> Result<Vec<SubTrend>> {
     if level < 1 {
         return Err(Error::custom(
@@ -72,36 +183,89 @@ pub fn get_tanglism_subtrends(
         return Ok(subtrends);
     }
     log::debug!("unify subtrends with level {}", level);
-    let mut subtrends = unify_subtrends(segments, strokes, "1m")?;
+    let mut subtrends = unify_subtrends(segments, strokes, Tick::M1)?;
     for lv in 2..=level {
-        let centers = unify_centers(&subtrends);
+        let centers = unify_centers_with_cfg(&subtrends, center_cfg);
         let trends = unify_trends(&centers);
         subtrends.clear();
         for tr in &trends {
             subtrends.push(trend_as_subtrend(
                 tr,
-                if lv == level { tick } else { "1m" },
+                if lv == level { tick } else { Tick::M1 },
             )?);
         }
     }
     Ok(subtrends)
 }
 
-pub fn get_tanglism_centers(subtrends: &[SubTrend]) -> Result<Vec<CenterElement>> {
-    Ok(unify_centers(&subtrends))
+pub fn get_tanglism_centers(
+    subtrends: &[SubTrend],
+    center_cfg: &CenterConfig,
+) -> Result<Vec<CenterElement>> {
+    Ok(unify_centers_with_cfg(&subtrends, center_cfg))
 }
 
 pub fn get_tanglism_trends(centers: &[CenterElement]) -> Result<Vec<Trend>> {
     Ok(unify_trends(&centers))
 }
 
+// 背驰，macd为MACD柱
+pub fn get_tanglism_divergences(
+    subtrends: &[SubTrend],
+    centers: &[CenterElement],
+    macd: &[Metric],
+) -> Result<Vec<Divergence>> {
+    let macd: Vec<ValuePoint> = macd
+        .iter()
+        .map(|m| ValuePoint {
+            ts: m.ts,
+            value: m.value.clone(),
+        })
+        .collect();
+    Ok(find_divergences(subtrends, centers, &macd))
+}
+
+// 分型配置
+// 1. side_bars=1/2/... 极值两侧至少需要的K线数
+// 2. min_amplitude=0.001/... 极值相对两侧K线的最小振幅比例
+pub fn parse_parting_cfg(s: &str) -> Result<PartingConfig> {
+    let mut cfg = PartingConfig::default();
+    for c in s.split(',') {
+        if c.starts_with("side_bars") {
+            let bs: Vec<&str> = c.split(':').collect();
+            if bs.len() == 2 {
+                cfg.side_bars = bs[1].parse().map_err(|_| {
+                    Error::custom(
+                        ErrorKind::BadRequest,
+                        format!("invalid side bars: {}", bs[1]),
+                    )
+                })?;
+            }
+        } else if c.starts_with("min_amplitude") {
+            let ams: Vec<&str> = c.split(':').collect();
+            if ams.len() == 2 {
+                let amplitude = parse_price(ams[1]).map_err(|_| {
+                    Error::custom(
+                        ErrorKind::BadRequest,
+                        format!("invalid min amplitude: {}", ams[1]),
+                    )
+                })?;
+                cfg.min_amplitude.replace(amplitude);
+            }
+        }
+    }
+    Ok(cfg)
+}
+
 pub fn parse_stroke_cfg(s: &str) -> Result<StrokeConfig> {
     if s.is_empty() {
         return Ok(StrokeConfig::default());
     }
     let cfg_strs: Vec<&str> = s.split(',').collect();
     let mut indep_k = true;
-    let mut judge = StrokeJudge::None;
+    let mut judges = Vec::new();
+    let mut judge_all = false;
+    let mut min_amplitude = None;
     for c in &cfg_strs {
         if c.starts_with("indep_k") {
             let is: Vec<&str> = c.split(':').collect();
@@ -111,30 +275,79 @@ pub fn parse_stroke_cfg(s: &str) -> Result<StrokeConfig> {
         } else if c.starts_with("gap_opening") {
             let gs: Vec<&str> = c.split(':').collect();
             if gs.len() < 2 || gs[1] == "morning" {
-                judge = StrokeJudge::GapOpening(false);
+                judges.push(StrokeJudge::GapOpening(false));
             } else {
-                judge = StrokeJudge::GapOpening(true);
+                judges.push(StrokeJudge::GapOpening(true));
             }
         } else if c.starts_with("gap_ratio") {
             let gs: Vec<&str> = c.split(':').collect();
             if gs.len() < 2 {
-                judge = StrokeJudge::GapRatio(BigDecimal::from_str("0.01").unwrap());
+                judges.push(StrokeJudge::GapRatio(price!("0.01")));
             } else {
-                let ratio = BigDecimal::from_str(gs[1]).map_err(|_| {
+                let invalid = || {
                     Error::custom(
                         ErrorKind::BadRequest,
                         format!("invalid gap ratio: {}", gs[1]),
                     )
-                })?;
-                judge = StrokeJudge::GapRatio(ratio);
+                };
+                let ratio = if gs[1].ends_with('%') {
+                    parse_price(gs[1].trim_end_matches('%')).map_err(|_| invalid())? / 100
+                } else {
+                    parse_price(gs[1]).map_err(|_| invalid())?
+                };
+                judges.push(StrokeJudge::GapRatio(ratio));
+            }
+        } else if c.starts_with("judge_op") {
+            let os: Vec<&str> = c.split(':').collect();
+            match os.get(1) {
+                Some(&"and") => judge_all = true,
+                Some(&"or") => judge_all = false,
+                _ => {
+                    return Err(Error::custom(
+                        ErrorKind::BadRequest,
+                        format!("invalid judge op: {}", c),
+                    ))
+                }
+            }
+        } else if c.starts_with("min_amplitude") {
+            let ams: Vec<&str> = c.split(':').collect();
+            if ams.len() == 2 {
+                let invalid = || {
+                    Error::custom(
+                        ErrorKind::BadRequest,
+                        format!("invalid min amplitude: {}", ams[1]),
+                    )
+                };
+                if ams[1].ends_with('%') {
+                    let pct = parse_price(ams[1].trim_end_matches('%')).map_err(|_| invalid())?;
+                    min_amplitude.replace(StrokeAmplitude::Ratio(pct / 100));
+                } else {
+                    let amplitude = parse_price(ams[1]).map_err(|_| invalid())?;
+                    min_amplitude.replace(StrokeAmplitude::Absolute(amplitude));
+                }
             }
         }
     }
-    Ok(StrokeConfig { indep_k, judge })
+    // 单一条件时保持原有结构
+    let judge = match judges.len() {
+        0 => StrokeJudge::None,
+        1 => judges.pop().unwrap(),
+        _ if judge_all => StrokeJudge::All(judges),
+        _ => StrokeJudge::Any(judges),
+    };
+    Ok(StrokeConfig {
+        indep_k,
+        judge,
+        min_amplitude,
+    })
 }
 
+// 走势配置
+// 1. level=1/2/... 走势级别
+// 2. combination_seed=true/false 组合次级别走势是否可作为中枢起始段
 pub fn parse_trend_cfg(s: &str) -> Result<TrendConfig> {
     let mut level = 1;
+    let mut center = CenterConfig::default();
     for c in s.split(',') {
         if c.starts_with("level") {
             let ls: Vec<&str> = c.split(':').collect();
@@ -143,7 +356,72 @@ pub fn parse_trend_cfg(s: &str) -> Result<TrendConfig> {
                     level = lv;
                 }
             }
+        } else if c.starts_with("combination_seed") {
+            let cs: Vec<&str> = c.split(':').collect();
+            if cs.len() == 2 && cs[1] == "false" {
+                center.combination_seed = false;
+            }
+        }
+    }
+    Ok(TrendConfig { level, center })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stroke_judges() {
+        let cfg = parse_stroke_cfg("indep_k:true,gap_opening:morning").unwrap();
+        assert_eq!(StrokeJudge::GapOpening(false), cfg.judge);
+        let cfg = parse_stroke_cfg("gap_opening:morning,gap_ratio:2%").unwrap();
+        assert_eq!(
+            StrokeJudge::Any(vec![
+                StrokeJudge::GapOpening(false),
+                StrokeJudge::GapRatio(price!("0.02")),
+            ]),
+            cfg.judge
+        );
+        let cfg = parse_stroke_cfg("gap_opening:all,gap_ratio:0.05,judge_op:and").unwrap();
+        assert_eq!(
+            StrokeJudge::All(vec![
+                StrokeJudge::GapOpening(true),
+                StrokeJudge::GapRatio(price!("0.05")),
+            ]),
+            cfg.judge
+        );
+        assert!(parse_stroke_cfg("gap_ratio:1e-2").is_err());
+        assert!(parse_stroke_cfg("judge_op:xor").is_err());
+    }
+
+    #[test]
+    fn test_intraday_shapes() {
+        let times = [
+            "10:00", "10:30", "11:00", "11:30", "13:30", "14:00", "14:30", "15:00",
+        ];
+        // 首日探底后上涨至收盘，次日下跌至尾盘
+        let mids = [
+            ("2020-02-03", [12, 10, 12, 14, 16, 18, 20, 22]),
+            ("2020-02-04", [20, 18, 16, 14, 12, 10, 8, 10]),
+        ];
+        let mut prices = Vec::new();
+        for (day, ms) in &mids {
+            for (t, mid) in times.iter().zip(ms.iter()) {
+                prices.push(
+                    ticks::PriceBuilder::new(&format!("{} {}", day, t), *mid)
+                        .range(mid - 1, mid + 1)
+                        .build(),
+                );
+            }
         }
+        let cross_day =
+            |sk: &Stroke| sk.start_pt.extremum_ts.date() != sk.end_pt.extremum_ts.date();
+        let pts = get_tanglism_partings(&prices, &PartingConfig::default()).unwrap();
+        let sks = get_tanglism_strokes(&pts, Tick::M30, StrokeConfig::default()).unwrap();
+        assert!(sks.iter().any(cross_day));
+        let pts = get_tanglism_partings_by_day(&prices, &PartingConfig::default()).unwrap();
+        assert!(pts.iter().all(|pt| pt.start_ts.date() == pt.end_ts.date()));
+        let sks = get_tanglism_strokes_by_day(&pts, Tick::M30, StrokeConfig::default()).unwrap();
+        assert!(!sks.iter().any(cross_day));
     }
-    Ok(TrendConfig { level })
 }
@@ -0,0 +1,161 @@
+//! 共享缓存预热
+//!
+//! 开盘前为关注的股票抓取各周期的K线并计算MACD，写入数据库中的价格缓存及指标缓存，
+//! 当日首次查询无需等待数据源。单只股票失败不影响其他股票，失败原因随结果返回。
+
+use super::metrics::{self, macd};
+use super::stock_prices::get_stock_tick_prices;
+use super::stocks;
+use crate::{BasicCfg, DbPool, Error, ErrorKind, JqdataPool, Result};
+use chrono::{Duration, NaiveDateTime, NaiveTime};
+use serde_derive::*;
+use std::time::Instant;
+use tanglism_utils::{resolve_end_ts, Tick};
+
+// 单次预热最多的股票数
+const MAX_WARM_CODES: usize = 200;
+const MAX_WARM_DAYS: i64 = 3650;
+
+/// 预热参数
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WarmParam {
+    // 代码或名称
+    pub codes: Vec<String>,
+    pub ticks: Vec<Tick>,
+    // 回溯的自然日数
+    pub days: i64,
+}
+
+impl WarmParam {
+    pub fn validate(&self) -> Result<()> {
+        if self.codes.is_empty() || self.codes.len() > MAX_WARM_CODES {
+            return Err(Error::custom(
+                ErrorKind::BadRequest,
+                format!("warm requires 1 to {} codes", MAX_WARM_CODES),
+            ));
+        }
+        if self.ticks.is_empty() {
+            return Err(Error::custom(
+                ErrorKind::BadRequest,
+                "warm requires at least one tick".to_owned(),
+            ));
+        }
+        if self.days < 1 || self.days > MAX_WARM_DAYS {
+            return Err(Error::custom(
+                ErrorKind::BadRequest,
+                format!("warm days must be within 1 to {}", MAX_WARM_DAYS),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 单只股票单个周期的预热结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WarmItem {
+    pub code: String,
+    pub tick: Tick,
+    pub bars: usize,
+    pub elapsed_ms: u64,
+    pub error: Option<String>,
+}
+
+/// 预热结果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WarmSummary {
+    pub warmed: usize,
+    pub failed: usize,
+    pub items: Vec<WarmItem>,
+}
+
+impl WarmSummary {
+    pub fn push(&mut self, item: WarmItem) {
+        if item.error.is_some() {
+            self.failed += 1;
+        } else {
+            self.warmed += 1;
+        }
+        self.items.push(item);
+    }
+}
+
+/// 预热窗口的起始时刻，即回溯days个自然日的零点
+pub fn window_start(now: NaiveDateTime, days: i64) -> NaiveDateTime {
+    (now - Duration::days(days)).date().and_time(NaiveTime::MIN)
+}
+
+/// 预热单只股票的单个周期，失败原因记录在结果中
+pub async fn warm_item(
+    pool: &DbPool,
+    jq: &JqdataPool,
+    input: &str,
+    tick: Tick,
+    start_ts: NaiveDateTime,
+    now: NaiveDateTime,
+) -> WarmItem {
+    let begin = Instant::now();
+    let (code, rst) = match stocks::resolve_stock(pool.clone(), input.to_owned()).await {
+        Ok(code) => {
+            let rst = warm_one(pool, jq, &code, tick, start_ts, now).await;
+            (code, rst)
+        }
+        Err(e) => (input.to_owned(), Err(e)),
+    };
+    WarmItem {
+        code,
+        tick,
+        bars: *rst.as_ref().unwrap_or(&0),
+        elapsed_ms: begin.elapsed().as_millis() as u64,
+        error: rst.err().map(|e| e.to_string()),
+    }
+}
+
+// 抓取K线并计算MACD，返回K线数
+async fn warm_one(
+    pool: &DbPool,
+    jq: &JqdataPool,
+    code: &str,
+    tick: Tick,
+    start_ts: NaiveDateTime,
+    now: NaiveDateTime,
+) -> Result<usize> {
+    let end_ts = resolve_end_ts(None, tick, now)?;
+    let prices = get_stock_tick_prices(pool, jq, tick, code, start_ts, end_ts).await?;
+    let (macd_cfg, source) = macd::resolve_macd_cfg(pool, code, tick, None).await?;
+    let basic_cfg = BasicCfg {
+        tick,
+        code: code.to_owned(),
+        start_ts,
+        end_ts,
+        intraday: false,
+    };
+    metrics::get_metrics_macd(pool, jq, basic_cfg, macd_cfg, source).await?;
+    Ok(prices.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_warm_param() {
+        let param = WarmParam {
+            codes: vec!["600000.XSHG".to_owned()],
+            ticks: vec![Tick::D1, Tick::M30],
+            days: 365,
+        };
+        assert!(param.validate().is_ok());
+        assert!(WarmParam {
+            days: 0,
+            ..param.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(WarmParam {
+            ticks: Vec::new(),
+            ..param
+        }
+        .validate()
+        .is_err());
+    }
+}
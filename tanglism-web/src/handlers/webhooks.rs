@@ -0,0 +1,520 @@
+//! 信号的Webhook推送
+//!
+//! 事件提醒及走势周报等信号以JSON推送至配置的URL，供外部执行系统对接。
+//! 每次推送先写入推送记录，由后台任务发送，失败后按指数退避重试，超过次数后标记失败。
+//! 请求体以HMAC-SHA256签名，签名内容为"{时间戳}.{请求体}"，
+//! 接收方应以相同密钥校验签名，并拒绝时间戳过旧的请求以防重放。
+
+use super::events::{self, EventAlertRule};
+use crate::models::{Webhook, WebhookDelivery, WebhookForm};
+use crate::{DbPool, Error, ErrorKind, Result};
+use chrono::{Duration, Local, NaiveDateTime, NaiveTime};
+use diesel::prelude::*;
+use hmac::{Hmac, Mac, NewMac};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_derive::*;
+use sha2::Sha256;
+use tanglism_utils::{TradingDates, LOCAL_DATES};
+
+/// 事件提醒，每个交易日开盘前推送一次
+pub const EVENT_ALERT: &str = "event_alert";
+/// 走势周报生成
+pub const EVENT_REPORT: &str = "report";
+/// 手动触发的测试推送
+pub const EVENT_TEST: &str = "test";
+/// 抓取的K线被判定为异常并隔离
+pub const EVENT_ANOMALY: &str = "price_anomaly";
+
+const SUBSCRIBABLE_EVENTS: [&str; 3] = [EVENT_ALERT, EVENT_REPORT, EVENT_ANOMALY];
+
+pub const SIGNATURE_HEADER: &str = "x-tanglism-signature";
+pub const TIMESTAMP_HEADER: &str = "x-tanglism-timestamp";
+pub const EVENT_HEADER: &str = "x-tanglism-event";
+pub const DELIVERY_HEADER: &str = "x-tanglism-delivery";
+
+const STATUS_PENDING: &str = "pending";
+const STATUS_DELIVERED: &str = "delivered";
+const STATUS_FAILED: &str = "failed";
+
+// 含首次发送在内的最大尝试次数
+const MAX_ATTEMPTS: i32 = 6;
+// 首次重试的间隔，此后每次翻倍
+const BASE_RETRY_SECS: i64 = 30;
+const MAX_RETRY_SECS: i64 = 3600;
+const MIN_SECRET_LEN: usize = 16;
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+// 后台任务的检查间隔及每次发送的上限
+const WORKER_INTERVAL_SECS: u64 = 10;
+const WORKER_BATCH_SIZE: i64 = 50;
+const MAX_LIST_LIMIT: i64 = 200;
+
+lazy_static! {
+    // 事件提醒的推送时刻
+    static ref ALERT_TIME: NaiveTime = NaiveTime::from_hms_opt(8, 30, 0).unwrap();
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(REQUEST_TIMEOUT_SECS))
+        .build()
+        .expect("failed to build webhook client");
+}
+
+/// 推送的请求体
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Payload<T> {
+    pub event: String,
+    pub created_at: NaiveDateTime,
+    pub data: T,
+}
+
+pub async fn create_webhook(pool: DbPool, form: WebhookForm) -> Result<Webhook> {
+    validate_form(&form)?;
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::webhooks::dsl::*;
+        let conn = pool.get()?;
+        diesel::insert_into(webhooks)
+            .values((&form, created_at.eq(Local::now().naive_local())))
+            .get_result::<Webhook>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data)
+}
+
+pub async fn list_webhooks(pool: DbPool) -> Result<Vec<Webhook>> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::webhooks::dsl::*;
+        let conn = pool.get()?;
+        webhooks
+            .order(id.asc())
+            .load::<Webhook>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data)
+}
+
+/// 删除Webhook及其推送记录
+pub async fn delete_webhook(pool: DbPool, webhook_id: i32) -> Result<()> {
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::webhooks::dsl::*;
+        let conn = pool.get()?;
+        diesel::delete(webhooks.find(webhook_id))
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    if n == 0 {
+        return Err(Error::custom(
+            ErrorKind::NotFound,
+            format!("webhook {} not found", webhook_id),
+        ));
+    }
+    Ok(())
+}
+
+/// 最近的推送记录，按创建时间倒序
+pub async fn list_deliveries(
+    pool: DbPool,
+    input_webhook_id: i32,
+    limit: i64,
+) -> Result<Vec<WebhookDelivery>> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::webhook_deliveries::dsl::*;
+        let conn = pool.get()?;
+        webhook_deliveries
+            .filter(webhook_id.eq(input_webhook_id))
+            .order(id.desc())
+            .limit(limit.clamp(1, MAX_LIST_LIMIT))
+            .load::<WebhookDelivery>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data)
+}
+
+/// 立即向指定Webhook发送测试推送，失败时不重试
+pub async fn test_fire(pool: DbPool, webhook_id: i32) -> Result<WebhookDelivery> {
+    let hook = get_webhook(pool.clone(), webhook_id).await?;
+    let now = Local::now().naive_local();
+    let payload = to_payload(
+        EVENT_TEST,
+        now,
+        &serde_json::json!({ "webhook": hook.name }),
+    )?;
+    let delivery = insert_deliveries(pool.clone(), &[hook.id], EVENT_TEST, payload, now)
+        .await?
+        .pop()
+        .ok_or_else(|| {
+            Error::custom(
+                ErrorKind::InternalServerError,
+                "test delivery not created".to_owned(),
+            )
+        })?;
+    deliver(&pool, &hook, delivery, false).await
+}
+
+/// 为订阅该类型的已启用Webhook创建推送记录，由后台任务发送
+pub async fn enqueue<T: Serialize>(pool: DbPool, input_event: &str, data: &T) -> Result<usize> {
+    let now = Local::now().naive_local();
+    let payload = to_payload(input_event, now, data)?;
+    let hook_ids: Vec<i32> = subscribed_webhooks(pool.clone(), input_event)
+        .await?
+        .into_iter()
+        .map(|h| h.id)
+        .collect();
+    let deliveries = insert_deliveries(pool, &hook_ids, input_event, payload, now).await?;
+    Ok(deliveries.len())
+}
+
+/// 后台推送任务，发送到期的推送，并在每个交易日开盘前生成事件提醒
+pub async fn run_webhook_worker(pool: DbPool) {
+    loop {
+        let now = Local::now().naive_local();
+        if LOCAL_DATES.contains_day(now.date()) && now.time() >= *ALERT_TIME {
+            if let Err(e) = enqueue_event_alerts(&pool, now).await {
+                log::warn!("failed to enqueue event alerts: {}", e);
+            }
+        }
+        match send_due(&pool, now).await {
+            Ok(0) => (),
+            Ok(n) => log::debug!("{} webhook deliveries sent", n),
+            Err(e) => log::warn!("failed to send webhook deliveries: {}", e),
+        }
+        tokio::time::delay_for(std::time::Duration::from_secs(WORKER_INTERVAL_SECS)).await;
+    }
+}
+
+/// 请求体的签名，十六进制小写
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("hmac accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    to_hex(&mac.finalize().into_bytes())
+}
+
+/// 第n次尝试失败后的重试间隔
+pub fn retry_delay(attempts: i32) -> Duration {
+    let exp = (attempts.max(1) - 1).min(16) as u32;
+    Duration::seconds((BASE_RETRY_SECS * 2i64.pow(exp)).min(MAX_RETRY_SECS))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn to_payload<T: Serialize>(event: &str, created_at: NaiveDateTime, data: &T) -> Result<String> {
+    serde_json::to_string(&Payload {
+        event: event.to_owned(),
+        created_at,
+        data,
+    })
+    .map_err(|e| Error::custom(ErrorKind::InternalServerError, e.to_string()))
+}
+
+fn validate_form(form: &WebhookForm) -> Result<()> {
+    let invalid = |msg: String| Err(Error::custom(ErrorKind::BadRequest, msg));
+    if !form.url.starts_with("http://") && !form.url.starts_with("https://") {
+        return invalid(format!("invalid webhook url: {}", form.url));
+    }
+    if form.secret.len() < MIN_SECRET_LEN {
+        return invalid(format!(
+            "webhook secret should have at least {} characters",
+            MIN_SECRET_LEN
+        ));
+    }
+    if form.events.is_empty() {
+        return invalid("webhook events are empty".to_owned());
+    }
+    if let Some(e) = form
+        .events
+        .iter()
+        .find(|e| !SUBSCRIBABLE_EVENTS.contains(&e.as_str()))
+    {
+        return invalid(format!("invalid webhook event: {}", e));
+    }
+    if form.alert_days_before < 0 {
+        return invalid(format!("alert_days_before {} < 0", form.alert_days_before));
+    }
+    Ok(())
+}
+
+async fn get_webhook(pool: DbPool, webhook_id: i32) -> Result<Webhook> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::webhooks::dsl::*;
+        let conn = pool.get()?;
+        webhooks
+            .find(webhook_id)
+            .first::<Webhook>(&conn)
+            .optional()
+            .map_err(Error::from)
+    })
+    .await??;
+    data.ok_or_else(|| {
+        Error::custom(
+            ErrorKind::NotFound,
+            format!("webhook {} not found", webhook_id),
+        )
+    })
+}
+
+async fn subscribed_webhooks(pool: DbPool, input_event: &str) -> Result<Vec<Webhook>> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::webhooks::dsl::*;
+        let conn = pool.get()?;
+        webhooks
+            .filter(enabled.eq(true))
+            .order(id.asc())
+            .load::<Webhook>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data
+        .into_iter()
+        .filter(|h| h.events.iter().any(|e| e == input_event))
+        .collect())
+}
+
+async fn insert_deliveries(
+    pool: DbPool,
+    hook_ids: &[i32],
+    input_event: &str,
+    input_payload: String,
+    now: NaiveDateTime,
+) -> Result<Vec<WebhookDelivery>> {
+    if hook_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rows: Vec<_> = {
+        use crate::schema::webhook_deliveries::dsl::*;
+        hook_ids
+            .iter()
+            .map(|hook_id| {
+                (
+                    webhook_id.eq(*hook_id),
+                    event.eq(input_event.to_owned()),
+                    payload.eq(input_payload.clone()),
+                    status.eq(STATUS_PENDING),
+                    created_at.eq(now),
+                    next_attempt_at.eq(now),
+                )
+            })
+            .collect()
+    };
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::webhook_deliveries::dsl::*;
+        let conn = pool.get()?;
+        diesel::insert_into(webhook_deliveries)
+            .values(&rows)
+            .get_results::<WebhookDelivery>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data)
+}
+
+// 当天已生成事件提醒的Webhook不再重复生成，无事件时不推送
+async fn enqueue_event_alerts(pool: &DbPool, now: NaiveDateTime) -> Result<()> {
+    let today = now.date();
+    let hooks = subscribed_webhooks(pool.clone(), EVENT_ALERT).await?;
+    let alerted = alerted_webhooks(
+        pool.clone(),
+        NaiveDateTime::new(today, NaiveTime::from_hms_opt(0, 0, 0).unwrap()),
+    )
+    .await?;
+    for hook in hooks.into_iter().filter(|h| !alerted.contains(&h.id)) {
+        let rule = EventAlertRule {
+            days_before: hook.alert_days_before as i64,
+            kind: None,
+            code: None,
+        };
+        let alerts = events::event_alerts(pool.clone(), rule, today).await?;
+        if alerts.is_empty() {
+            continue;
+        }
+        let payload = to_payload(EVENT_ALERT, now, &alerts)?;
+        insert_deliveries(pool.clone(), &[hook.id], EVENT_ALERT, payload, now).await?;
+        log::info!(
+            "{} event alerts enqueued for webhook {}",
+            alerts.len(),
+            hook.id
+        );
+    }
+    Ok(())
+}
+
+async fn alerted_webhooks(pool: DbPool, since: NaiveDateTime) -> Result<Vec<i32>> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::webhook_deliveries::dsl::*;
+        let conn = pool.get()?;
+        webhook_deliveries
+            .filter(event.eq(EVENT_ALERT).and(created_at.ge(since)))
+            .select(webhook_id)
+            .distinct()
+            .load::<i32>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data)
+}
+
+async fn send_due(pool: &DbPool, now: NaiveDateTime) -> Result<usize> {
+    let due = {
+        let pool = pool.clone();
+        tokio::task::spawn_blocking(move || {
+            use crate::schema::webhook_deliveries;
+            use crate::schema::webhooks;
+            let conn = pool.get()?;
+            webhook_deliveries::table
+                .inner_join(webhooks::table)
+                .filter(webhook_deliveries::status.eq(STATUS_PENDING))
+                .filter(webhook_deliveries::next_attempt_at.le(now))
+                .order(webhook_deliveries::next_attempt_at.asc())
+                .limit(WORKER_BATCH_SIZE)
+                .load::<(WebhookDelivery, Webhook)>(&conn)
+                .map_err(Error::from)
+        })
+        .await??
+    };
+    let n = due.len();
+    for (delivery, hook) in due {
+        deliver(pool, &hook, delivery, true).await?;
+    }
+    Ok(n)
+}
+
+// 发送单条推送并更新记录，retry为false时失败即标记为失败
+async fn deliver(
+    pool: &DbPool,
+    hook: &Webhook,
+    mut delivery: WebhookDelivery,
+    retry: bool,
+) -> Result<WebhookDelivery> {
+    let local_now = Local::now();
+    let now = local_now.naive_local();
+    // 签名使用Unix时间戳
+    let timestamp = local_now.timestamp();
+    let resp = CLIENT
+        .post(&hook.url)
+        .header("content-type", "application/json")
+        .header(
+            SIGNATURE_HEADER,
+            format!(
+                "sha256={}",
+                sign(&hook.secret, timestamp, &delivery.payload)
+            ),
+        )
+        .header(TIMESTAMP_HEADER, timestamp.to_string())
+        .header(EVENT_HEADER, delivery.event.as_str())
+        .header(DELIVERY_HEADER, delivery.id.to_string())
+        .body(delivery.payload.clone())
+        .send()
+        .await;
+    delivery.attempts += 1;
+    let failure = match resp {
+        Ok(r) if r.status().is_success() => {
+            delivery.response_status = Some(r.status().as_u16() as i32);
+            None
+        }
+        Ok(r) => {
+            delivery.response_status = Some(r.status().as_u16() as i32);
+            Some(format!("unexpected status {}", r.status()))
+        }
+        Err(e) => {
+            delivery.response_status = None;
+            Some(e.to_string())
+        }
+    };
+    match failure {
+        None => {
+            delivery.status = STATUS_DELIVERED.to_owned();
+            delivery.last_error = String::new();
+            delivery.delivered_at = Some(now);
+        }
+        Some(err) => {
+            log::warn!(
+                "webhook delivery {} to {} failed at attempt {}: {}",
+                delivery.id,
+                hook.url,
+                delivery.attempts,
+                err
+            );
+            delivery.last_error = err;
+            if retry && delivery.attempts < MAX_ATTEMPTS {
+                delivery.next_attempt_at = now + retry_delay(delivery.attempts);
+            } else {
+                delivery.status = STATUS_FAILED.to_owned();
+            }
+        }
+    }
+    save_delivery(pool.clone(), delivery).await
+}
+
+async fn save_delivery(pool: DbPool, record: WebhookDelivery) -> Result<WebhookDelivery> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::webhook_deliveries::dsl::*;
+        let conn = pool.get()?;
+        diesel::update(webhook_deliveries.find(record.id))
+            .set((
+                status.eq(&record.status),
+                attempts.eq(record.attempts),
+                response_status.eq(record.response_status),
+                last_error.eq(&record.last_error),
+                next_attempt_at.eq(record.next_attempt_at),
+                delivered_at.eq(record.delivered_at),
+            ))
+            .get_result::<WebhookDelivery>(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        // 签名内容为"{timestamp}.{body}"的HMAC-SHA256
+        assert_eq!(
+            "137617d05ca5c461e2d8c5cc6a80b462f6e346ca6f168d2b6c6e49a57d6b7cbe",
+            sign("0123456789abcdef", 1596758400, r#"{"event":"test"}"#)
+        );
+        assert_ne!(
+            sign("0123456789abcdef", 1596758400, r#"{"event":"test"}"#),
+            sign("0123456789abcdef", 1596758401, r#"{"event":"test"}"#)
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(Duration::seconds(30), retry_delay(1));
+        assert_eq!(Duration::seconds(240), retry_delay(4));
+        assert_eq!(Duration::seconds(MAX_RETRY_SECS), retry_delay(20));
+    }
+
+    #[test]
+    fn test_validate_form() {
+        let form = WebhookForm {
+            name: "exec".to_owned(),
+            url: "https://example.com/hook".to_owned(),
+            secret: "0123456789abcdef".to_owned(),
+            events: vec![EVENT_ALERT.to_owned()],
+            alert_days_before: 3,
+            enabled: true,
+        };
+        assert!(validate_form(&form).is_ok());
+        assert!(validate_form(&WebhookForm {
+            events: vec![EVENT_TEST.to_owned()],
+            ..form.clone()
+        })
+        .is_err());
+        assert!(validate_form(&WebhookForm {
+            url: "ftp://example.com".to_owned(),
+            ..form
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,751 @@
+//! jqdata多账户客户端池
+//!
+//! 配置多个账户时，某账户触发配额或认证错误后自动切换至下一账户，
+//! 并记录各账户的使用情况供管理API查询。
+//!
+//! 可选开启请求日志，记录方法、参数（令牌脱敏）、响应大小及耗时，
+//! 并可将请求及解析结果写入目录用于排查。
+//!
+//! 可选设置单次请求的超时，超时的请求被取消并返回Timeout错误。
+//!
+//! 回放模式下从写入目录的记录中按方法及参数返回结果，不访问上游，
+//! 用于在没有账户的环境中进行确定性的集成测试。
+//!
+//! 各账户的请求经由进程内共享的HTTP客户端发送，见http模块。
+//!
+//! 可选设置请求间隔及每日的请求预算，见quota模块。
+//!
+//! 可选开启响应缓存，相同请求在有效期内不访问上游，见response_cache模块。
+//!
+//! 请求的发送方式可替换，用于录制及回放上游的原始响应，见transport模块。
+
+mod bars;
+mod fundamentals;
+mod http;
+mod quota;
+mod response_cache;
+mod transport;
+
+pub use bars::{Bar, GetBars, GetBarsPeriod};
+pub use fundamentals::{
+    Balance, CashFlow, GetBalance, GetCashFlow, GetIncome, GetValuation, Income, Valuation,
+};
+pub use http::{
+    http_stats, set_http_config, HttpConfig, HttpStats, HttpTransport, JqClient, Transport,
+};
+pub use quota::{QuotaAction, QuotaBudget, QuotaUsage};
+pub use response_cache::{CachePolicy, DiskCache, ResponseCache};
+pub use transport::{RecordTransport, ReplayTransport};
+
+use quota::{Admission, Quota};
+
+use crate::{Error, ErrorKind, Result};
+use chrono::{Local, NaiveDate};
+use jqdata::{BodyConsumer, GetQueryCount, HasMethod};
+use serde::{Deserialize, Serialize};
+use serde_derive::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// 日志中需脱敏的参数
+const REDACTED_KEYS: [&str; 3] = ["token", "mob", "pwd"];
+
+#[derive(Clone)]
+pub struct JqdataPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    accounts: Vec<Account>,
+    // 当前使用的账户下标
+    current: AtomicUsize,
+    // 离线模式下禁止访问上游，仅使用数据库中的数据
+    offline: bool,
+    request_log: Mutex<RequestLogConfig>,
+    request_stats: Mutex<RequestStats>,
+    // 写入目录的请求序号
+    request_seq: AtomicU64,
+    // 单次请求超时的毫秒数，0表示不限制
+    request_timeout_ms: AtomicU64,
+    // 回放的记录，键为方法及脱敏后的参数
+    replay: Option<HashMap<String, std::result::Result<serde_json::Value, String>>>,
+    quota: Quota,
+    cache: Mutex<Option<(Arc<dyn ResponseCache>, CachePolicy)>>,
+}
+
+struct Account {
+    mob: String,
+    client: JqClient,
+    stats: Mutex<AccountStats>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct AccountStats {
+    requests: u64,
+    failures: u64,
+    remaining: Option<i32>,
+    // 配额按自然日重置，记录耗尽的日期
+    exhausted_on: Option<NaiveDate>,
+}
+
+/// 请求日志配置
+#[derive(Debug, Clone, Default)]
+pub struct RequestLogConfig {
+    // 记录每次请求的方法、参数、响应大小及耗时
+    pub enabled: bool,
+    // 将请求参数及解析结果写入该目录
+    pub mirror_dir: Option<PathBuf>,
+    // 从该目录回放记录，不访问上游
+    pub replay_dir: Option<PathBuf>,
+    // 将上游的原始请求及响应写入该目录，供ReplayTransport回放
+    pub record_dir: Option<PathBuf>,
+}
+
+impl RequestLogConfig {
+    /// 账户登录及请求使用的发送方式
+    pub fn transport(&self) -> Arc<dyn Transport> {
+        match &self.record_dir {
+            Some(dir) => Arc::new(RecordTransport::new(Arc::new(HttpTransport), dir)),
+            None => Arc::new(HttpTransport),
+        }
+    }
+}
+
+/// 上游请求计数
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RequestStats {
+    pub requests: u64,
+    pub failures: u64,
+    // 仅在开启日志时统计
+    pub response_bytes: u64,
+    pub latency_ms: u64,
+    // 由响应缓存返回的请求数，不计入requests
+    pub cache_hits: u64,
+}
+
+/// 账户使用情况
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountUsage {
+    // 脱敏后的账户
+    pub account: String,
+    pub requests: u64,
+    pub failures: u64,
+    // 最近一次查询的剩余条数
+    pub remaining: Option<i32>,
+    pub exhausted: bool,
+    pub current: bool,
+}
+
+impl JqdataPool {
+    /// 使用多个账户创建客户端池，登录失败的账户被跳过
+    pub async fn with_credentials(credentials: Vec<(String, String)>) -> Result<Self> {
+        Self::with_transport(Arc::new(HttpTransport), credentials).await
+    }
+
+    /// 使用多个账户及指定的发送方式创建客户端池
+    pub async fn with_transport(
+        transport: Arc<dyn Transport>,
+        credentials: Vec<(String, String)>,
+    ) -> Result<Self> {
+        let mut clients = Vec::with_capacity(credentials.len());
+        let mut last_err = None;
+        for (mob, pwd) in credentials {
+            match JqClient::login_with(transport.clone(), &mob, &pwd).await {
+                Ok(client) => clients.push((mob, client)),
+                Err(e) => {
+                    log::warn!("jqdata account {} login failed: {}", mask_account(&mob), e);
+                    last_err = Some(e);
+                }
+            }
+        }
+        if clients.is_empty() {
+            return Err(match last_err {
+                Some(e) => Error::from(e),
+                None => Error::custom(ErrorKind::Jqdata, "no jqdata account".to_owned()),
+            });
+        }
+        Ok(Self::from_clients(clients))
+    }
+
+    /// 创建离线客户端池，所有上游请求均返回错误
+    pub fn offline() -> Self {
+        JqdataPool {
+            inner: Arc::new(PoolInner::new(Vec::new(), true)),
+        }
+    }
+
+    /// 创建回放客户端池，从目录中的请求记录返回结果
+    ///
+    /// 记录格式与mirror_dir写入的一致，相同请求以序号较大的为准
+    pub fn replay<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let mut records = HashMap::new();
+        for (path, s) in read_json_files(dir)? {
+            let (key, rst) = parse_record(&s).ok_or_else(|| {
+                Error::custom(
+                    ErrorKind::InternalServerError,
+                    format!("invalid replay record {:?}", path),
+                )
+            })?;
+            records.insert(key, rst);
+        }
+        let mut inner = PoolInner::new(Vec::new(), false);
+        inner.replay = Some(records);
+        Ok(JqdataPool {
+            inner: Arc::new(inner),
+        })
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.inner.offline
+    }
+
+    pub fn from_clients(clients: Vec<(String, JqClient)>) -> Self {
+        let accounts = clients
+            .into_iter()
+            .map(|(mob, client)| Account {
+                mob,
+                client,
+                stats: Mutex::new(AccountStats::default()),
+            })
+            .collect();
+        JqdataPool {
+            inner: Arc::new(PoolInner::new(accounts, false)),
+        }
+    }
+
+    /// 执行请求，服务端错误（配额或认证）时切换账户重试
+    ///
+    /// 请求可能执行多次，因此由闭包构造
+    pub async fn execute<T, C, F>(&self, command: F) -> Result<T>
+    where
+        T: for<'de> Deserialize<'de>,
+        T: Serialize + Send + 'static,
+        C: HasMethod + BodyConsumer<T> + Serialize + 'static,
+        F: Fn() -> C,
+    {
+        if self.inner.offline {
+            return Err(Error::custom(
+                ErrorKind::Jqdata,
+                "upstream fetch forbidden in offline mode".to_owned(),
+            ));
+        }
+        if let Some(ref records) = self.inner.replay {
+            let cmd = command();
+            let method = cmd.method();
+            let key = record_key(&method, &redacted_params(&cmd));
+            return match records.get(&key) {
+                Some(Ok(output)) => serde_json::from_value(output.clone()).map_err(|e| {
+                    Error::custom(
+                        ErrorKind::Jqdata,
+                        format!("invalid replayed {} response: {}", method, e),
+                    )
+                }),
+                Some(Err(msg)) => Err(Error::custom(ErrorKind::Jqdata, msg.clone())),
+                None => Err(Error::custom(
+                    ErrorKind::Jqdata,
+                    format!("no recorded response for {}", key),
+                )),
+            };
+        }
+        let cache = self.inner.cache.lock().unwrap().clone();
+        let cached = match cache {
+            Some((cache, policy)) => {
+                let cmd = command();
+                let method = cmd.method();
+                let ttl = policy.ttl(&method);
+                if ttl.as_secs() == 0 {
+                    None
+                } else {
+                    let key = record_key(&method, &redacted_params(&cmd));
+                    if let Some(output) =
+                        cache.get(&key).and_then(|v| serde_json::from_value(v).ok())
+                    {
+                        self.inner.request_stats.lock().unwrap().cache_hits += 1;
+                        return Ok(output);
+                    }
+                    Some((cache, key, ttl))
+                }
+            }
+            None => None,
+        };
+        let output = self.execute_upstream(command).await?;
+        if let Some((cache, key, ttl)) = cached {
+            match serde_json::to_value(&output) {
+                Ok(v) => cache.put(&key, &v, ttl),
+                Err(e) => log::warn!("failed to cache jqdata response: {}", e),
+            }
+        }
+        Ok(output)
+    }
+
+    // 依次尝试各账户执行请求
+    async fn execute_upstream<T, C, F>(&self, command: F) -> Result<T>
+    where
+        T: for<'de> Deserialize<'de>,
+        T: Serialize + Send + 'static,
+        C: HasMethod + BodyConsumer<T> + Serialize + 'static,
+        F: Fn() -> C,
+    {
+        self.admit().await?;
+        let reserve = self.inner.quota.budget().reserve;
+        let accounts = &self.inner.accounts;
+        let start = self.inner.current.load(Ordering::Relaxed);
+        let today = Local::now().naive_local().date();
+        let mut last_err = None;
+        for i in 0..accounts.len() {
+            let idx = (start + i) % accounts.len();
+            let account = &accounts[idx];
+            {
+                let stats = account.stats.lock().unwrap();
+                // 剩余条数低于保留值的账户留作他用
+                if stats.exhausted_on == Some(today) || stats.remaining.is_some_and(|r| r < reserve)
+                {
+                    continue;
+                }
+            }
+            let cmd = command();
+            let method = cmd.method();
+            let params = redacted_params(&cmd);
+            let started = Instant::now();
+            let rst = match self.request_timeout() {
+                Some(timeout) => {
+                    match tokio::time::timeout(timeout, account.client.execute(cmd)).await {
+                        Ok(rst) => rst,
+                        Err(_) => {
+                            // 超时与账户无关，不切换
+                            account.stats.lock().unwrap().failures += 1;
+                            self.inner.request_stats.lock().unwrap().failures += 1;
+                            return Err(Error::custom(
+                                ErrorKind::Timeout,
+                                format!(
+                                    "jqdata {} timed out after {}ms",
+                                    method,
+                                    timeout.as_millis()
+                                ),
+                            ));
+                        }
+                    }
+                }
+                None => account.client.execute(cmd).await,
+            };
+            self.record_request(&method, params, &rst, started.elapsed());
+            let mut stats = account.stats.lock().unwrap();
+            stats.requests += 1;
+            match rst {
+                Ok(output) => {
+                    if idx != start {
+                        log::info!("switched to jqdata account {}", mask_account(&account.mob));
+                        self.inner.current.store(idx, Ordering::Relaxed);
+                    }
+                    return Ok(output);
+                }
+                Err(jqdata::Error::Server(msg)) => {
+                    log::warn!(
+                        "jqdata account {} rejected: {}",
+                        mask_account(&account.mob),
+                        msg
+                    );
+                    stats.failures += 1;
+                    stats.exhausted_on = Some(today);
+                    last_err = Some(jqdata::Error::Server(msg));
+                }
+                Err(e) => {
+                    // 网络等错误与账户无关，不切换
+                    stats.failures += 1;
+                    return Err(Error::from(e));
+                }
+            }
+        }
+        Err(match last_err {
+            Some(e) => Error::from(e),
+            None => Error::custom(
+                ErrorKind::Jqdata,
+                "all jqdata accounts are exhausted".to_owned(),
+            ),
+        })
+    }
+
+    // 申请配额，需要时等待请求间隔或次日
+    async fn admit(&self) -> Result<()> {
+        loop {
+            let now = Local::now().naive_local();
+            match self.inner.quota.admit(now, Instant::now()) {
+                Admission::Wait(wait) => {
+                    if !wait.is_zero() {
+                        tokio::time::delay_for(wait).await;
+                    }
+                    return Ok(());
+                }
+                Admission::Exhausted { used, limit } => match self.inner.quota.budget().action {
+                    QuotaAction::Error => {
+                        return Err(Error::custom(
+                            ErrorKind::Jqdata,
+                            format!("jqdata daily budget exhausted: {} of {} calls", used, limit),
+                        ))
+                    }
+                    QuotaAction::Pause => {
+                        log::warn!("jqdata daily budget of {} calls exhausted, paused", limit);
+                        tokio::time::delay_for(quota::until_next_day(now)).await;
+                    }
+                },
+            }
+        }
+    }
+
+    /// 开启响应缓存
+    pub fn set_response_cache(&self, cache: Arc<dyn ResponseCache>, policy: CachePolicy) {
+        *self.inner.cache.lock().unwrap() = Some((cache, policy));
+    }
+
+    /// 修改配额预算
+    pub fn set_quota_budget(&self, budget: QuotaBudget) {
+        self.inner.quota.set_budget(budget);
+    }
+
+    /// 当日的配额使用情况
+    pub fn quota_usage(&self) -> QuotaUsage {
+        self.inner.quota.usage(Local::now().naive_local().date())
+    }
+
+    /// 修改请求日志配置
+    pub fn set_request_log(&self, cfg: RequestLogConfig) {
+        *self.inner.request_log.lock().unwrap() = cfg;
+    }
+
+    /// 修改单次请求超时，None表示不限制
+    pub fn set_request_timeout(&self, timeout: Option<Duration>) {
+        let ms = timeout.map(|t| t.as_millis() as u64).unwrap_or_default();
+        self.inner.request_timeout_ms.store(ms, Ordering::Relaxed);
+    }
+
+    fn request_timeout(&self) -> Option<Duration> {
+        match self.inner.request_timeout_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// 上游请求计数
+    pub fn request_stats(&self) -> RequestStats {
+        self.inner.request_stats.lock().unwrap().clone()
+    }
+
+    fn record_request<T: Serialize>(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+        rst: &std::result::Result<T, jqdata::Error>,
+        latency: Duration,
+    ) {
+        let cfg = self.inner.request_log.lock().unwrap().clone();
+        let latency_ms = latency.as_millis() as u64;
+        let output = match rst {
+            Ok(output) if cfg.enabled || cfg.mirror_dir.is_some() => {
+                serde_json::to_string(output).ok()
+            }
+            _ => None,
+        };
+        let size = output.as_ref().map(|s| s.len()).unwrap_or_default();
+        {
+            let mut stats = self.inner.request_stats.lock().unwrap();
+            stats.requests += 1;
+            stats.latency_ms += latency_ms;
+            stats.response_bytes += size as u64;
+            if rst.is_err() {
+                stats.failures += 1;
+            }
+        }
+        if cfg.enabled {
+            match rst {
+                Ok(_) => log::info!(
+                    "jqdata {} params={} size={} latency={}ms",
+                    method,
+                    params,
+                    size,
+                    latency_ms
+                ),
+                Err(e) => log::info!(
+                    "jqdata {} params={} error={} latency={}ms",
+                    method,
+                    params,
+                    e,
+                    latency_ms
+                ),
+            }
+        }
+        if let Some(dir) = cfg.mirror_dir {
+            let seq = self.inner.request_seq.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!("{:06}-{}.json", seq, method));
+            let body = serde_json::json!({
+                "method": method,
+                "params": params,
+                "latency_ms": latency_ms,
+                "output": output.and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok()),
+                "error": rst.as_ref().err().map(|e| e.to_string()),
+            });
+            if let Err(e) = std::fs::write(&path, body.to_string()) {
+                log::warn!("failed to mirror jqdata request to {:?}: {}", path, e);
+            }
+        }
+    }
+
+    /// 查询各账户剩余条数并返回使用情况
+    pub async fn refresh_usage(&self) -> Vec<AccountUsage> {
+        for account in &self.inner.accounts {
+            match account.client.execute(GetQueryCount {}).await {
+                Ok(count) => account.stats.lock().unwrap().remaining = Some(count),
+                Err(e) => log::warn!(
+                    "failed to query count of jqdata account {}: {}",
+                    mask_account(&account.mob),
+                    e
+                ),
+            }
+        }
+        self.usage()
+    }
+
+    /// 各账户使用情况
+    pub fn usage(&self) -> Vec<AccountUsage> {
+        let today = Local::now().naive_local().date();
+        let current = self.inner.current.load(Ordering::Relaxed);
+        self.inner
+            .accounts
+            .iter()
+            .enumerate()
+            .map(|(i, account)| {
+                let stats = account.stats.lock().unwrap().clone();
+                AccountUsage {
+                    account: mask_account(&account.mob),
+                    requests: stats.requests,
+                    failures: stats.failures,
+                    remaining: stats.remaining,
+                    exhausted: stats.exhausted_on == Some(today),
+                    current: i == current,
+                }
+            })
+            .collect()
+    }
+}
+
+impl PoolInner {
+    fn new(accounts: Vec<Account>, offline: bool) -> Self {
+        PoolInner {
+            accounts,
+            current: AtomicUsize::new(0),
+            offline,
+            request_log: Mutex::new(RequestLogConfig::default()),
+            request_stats: Mutex::new(RequestStats::default()),
+            request_seq: AtomicU64::new(0),
+            request_timeout_ms: AtomicU64::new(0),
+            replay: None,
+            quota: Quota::default(),
+            cache: Mutex::new(None),
+        }
+    }
+}
+
+// 按文件名顺序读取目录中的记录文件
+fn read_json_files<P: AsRef<Path>>(dir: P) -> Result<Vec<(PathBuf, String)>> {
+    let read_err = |e: std::io::Error| {
+        Error::custom(
+            ErrorKind::InternalServerError,
+            format!("failed to read replay dir: {}", e),
+        )
+    };
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(read_err)? {
+        let path = entry.map_err(read_err)?.path();
+        if path.extension().map(|e| e == "json").unwrap_or(false) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| {
+            let s = std::fs::read_to_string(&path).map_err(read_err)?;
+            Ok((path, s))
+        })
+        .collect()
+}
+
+fn record_key(method: &str, params: &serde_json::Value) -> String {
+    format!("{} {}", method, params)
+}
+
+// 解析写入目录的请求记录，返回键及结果
+fn parse_record(s: &str) -> Option<(String, std::result::Result<serde_json::Value, String>)> {
+    let v: serde_json::Value = serde_json::from_str(s).ok()?;
+    let method = v.get("method")?.as_str()?;
+    let key = record_key(method, v.get("params")?);
+    let rst = match v.get("error").and_then(|e| e.as_str()) {
+        Some(e) => Err(e.to_owned()),
+        None => Ok(v.get("output").cloned().unwrap_or(serde_json::Value::Null)),
+    };
+    Some((key, rst))
+}
+
+// 序列化请求参数并脱敏
+fn redacted_params<C: Serialize>(command: &C) -> serde_json::Value {
+    let mut params = serde_json::to_value(command).unwrap_or(serde_json::Value::Null);
+    redact(&mut params);
+    params
+}
+
+fn redact(v: &mut serde_json::Value) {
+    match v {
+        serde_json::Value::Object(m) => {
+            for (k, v) in m.iter_mut() {
+                if REDACTED_KEYS.contains(&k.as_str()) {
+                    *v = serde_json::Value::String("***".to_owned());
+                } else {
+                    redact(v);
+                }
+            }
+        }
+        serde_json::Value::Array(vs) => vs.iter_mut().for_each(redact),
+        _ => (),
+    }
+}
+
+/// 解析多个账户，以逗号分隔，每个账户格式为"手机号/密码"
+pub fn parse_jqaccounts(accounts: &str) -> Result<Vec<(String, String)>> {
+    accounts
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(crate::parse_jqaccount)
+        .collect()
+}
+
+// 账户脱敏，仅保留首尾各3位
+fn mask_account(mob: &str) -> String {
+    let chars: Vec<char> = mob.chars().collect();
+    if chars.len() <= 6 {
+        return "*".repeat(chars.len());
+    }
+    let head: String = chars[..3].iter().collect();
+    let tail: String = chars[chars.len() - 3..].iter().collect();
+    format!("{}{}{}", head, "*".repeat(chars.len() - 6), tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_jqaccounts() {
+        let accounts = parse_jqaccounts("13800000001/pwd1, 13800000002/pwd2").unwrap();
+        assert_eq!(
+            vec![
+                ("13800000001".to_owned(), "pwd1".to_owned()),
+                ("13800000002".to_owned(), "pwd2".to_owned()),
+            ],
+            accounts
+        );
+        assert!(parse_jqaccounts("13800000001/pwd1,invalid").is_err());
+        assert_eq!("138*****001", mask_account("13800000001"));
+    }
+
+    #[tokio::test]
+    async fn test_offline_forbids_fetch() {
+        let jq = JqdataPool::offline();
+        assert!(jq.is_offline());
+        assert!(jq.execute(|| GetQueryCount {}).await.is_err());
+        assert!(jq.refresh_usage().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let dir = std::env::temp_dir().join(format!("tanglism-replay-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let params = redacted_params(&GetQueryCount {});
+        let record = |output: serde_json::Value| {
+            serde_json::json!({
+                "method": "get_query_count",
+                "params": params,
+                "latency_ms": 10,
+                "output": output,
+                "error": null,
+            })
+            .to_string()
+        };
+        std::fs::write(dir.join("000000-get_query_count.json"), record(100.into())).unwrap();
+        // 相同请求以后写入的为准
+        std::fs::write(dir.join("000001-get_query_count.json"), record(99.into())).unwrap();
+        std::fs::write(dir.join("readme.txt"), "ignored").unwrap();
+        let jq = JqdataPool::replay(&dir).unwrap();
+        assert!(!jq.is_offline());
+        let count: i32 = jq.execute(|| GetQueryCount {}).await.unwrap();
+        assert_eq!(99, count);
+        assert!(jq
+            .execute(|| jqdata::GetSecurityInfo {
+                code: "600000.XSHG".to_owned(),
+            })
+            .await
+            .is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_response_cache() {
+        let dir = std::env::temp_dir().join(format!("tanglism-cache-{}", std::process::id()));
+        let cache = Arc::new(DiskCache::new(&dir).unwrap());
+        let cmd = || jqdata::GetSecurityInfo {
+            code: "600000.XSHG".to_owned(),
+        };
+        let key = record_key("get_security_info", &redacted_params(&cmd()));
+        let info = serde_json::json!([{
+            "code": "600000.XSHG",
+            "display_name": "浦发银行",
+            "name": "PFYH",
+            "start_date": "1999-11-10",
+            "end_date": "2200-01-01",
+            "type": "stock",
+            "parent": null,
+        }]);
+        cache.put(&key, &info, Duration::from_secs(60));
+        // 无可用账户，仅能由缓存返回
+        let jq = JqdataPool {
+            inner: Arc::new(PoolInner::new(Vec::new(), false)),
+        };
+        jq.set_response_cache(cache, CachePolicy::default());
+        let rst: Vec<jqdata::Security> = jq.execute(cmd).await.unwrap();
+        assert_eq!("浦发银行", rst[0].display_name);
+        assert_eq!(1, jq.request_stats().cache_hits);
+        // 剩余条数不缓存
+        assert!(jq.execute(|| GetQueryCount {}).await.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        let jq = JqdataPool::offline();
+        assert_eq!(None, jq.request_timeout());
+        jq.set_request_timeout(Some(Duration::from_secs(5)));
+        assert_eq!(Some(Duration::from_secs(5)), jq.request_timeout());
+        jq.set_request_timeout(None);
+        assert_eq!(None, jq.request_timeout());
+    }
+
+    #[test]
+    fn test_redact_params() {
+        let mut v = serde_json::json!({
+            "token": "abc",
+            "code": "000001.XSHE",
+            "nested": [{"pwd": "secret"}],
+        });
+        redact(&mut v);
+        assert_eq!(
+            serde_json::json!({
+                "token": "***",
+                "code": "000001.XSHE",
+                "nested": [{"pwd": "***"}],
+            }),
+            v
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! K线请求
+//!
+//! jqdata库中的GetPrice缺少代码参数，且返回行的部分字段为浮点数。
+//! 此处补充get_bars及get_bars_period请求，返回的价格、成交量及成交额均为BigDecimal，
+//! 可直接交由JqdataPool执行。
+
+use bigdecimal::BigDecimal;
+use jqdata::{BodyConsumer, CsvListBodyConsumer, HasMethod};
+use serde_derive::*;
+use std::io::Read;
+
+/// 截止时刻前的count根K线
+///
+/// unit支持1m, 5m, 15m, 30m, 60m, 120m, 1d, 1w, 1M，
+/// fq_ref_date为复权基准日期，为空时不复权
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetBars {
+    pub code: String,
+    pub count: u32,
+    pub unit: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fq_ref_date: Option<String>,
+    // 是否包含截止时刻所在的未完成K线
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_now: Option<bool>,
+}
+
+/// 起止时刻之间的K线
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetBarsPeriod {
+    pub code: String,
+    pub unit: String,
+    pub date: String,
+    pub end_date: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fq_ref_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_now: Option<bool>,
+}
+
+/// K线，money即成交额
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bar {
+    pub date: String,
+    pub open: BigDecimal,
+    pub close: BigDecimal,
+    pub high: BigDecimal,
+    pub low: BigDecimal,
+    pub volume: BigDecimal,
+    pub money: BigDecimal,
+}
+
+impl HasMethod for GetBars {
+    fn method(&self) -> String {
+        "get_bars".to_owned()
+    }
+}
+
+impl CsvListBodyConsumer for GetBars {
+    type Output = Bar;
+}
+
+impl BodyConsumer<Vec<Bar>> for GetBars {
+    fn consume_body<R: Read>(body: R) -> jqdata::Result<Vec<Bar>> {
+        <Self as CsvListBodyConsumer>::consume(body)
+    }
+}
+
+impl HasMethod for GetBarsPeriod {
+    fn method(&self) -> String {
+        "get_bars_period".to_owned()
+    }
+}
+
+impl CsvListBodyConsumer for GetBarsPeriod {
+    type Output = Bar;
+}
+
+impl BodyConsumer<Vec<Bar>> for GetBarsPeriod {
+    fn consume_body<R: Read>(body: R) -> jqdata::Result<Vec<Bar>> {
+        <Self as CsvListBodyConsumer>::consume(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_bars() {
+        let cmd = GetBars {
+            code: "600000.XSHG".to_owned(),
+            count: 2,
+            unit: "1d".to_owned(),
+            end_date: None,
+            fq_ref_date: Some("2020-08-03".to_owned()),
+            include_now: None,
+        };
+        assert_eq!("get_bars", cmd.method());
+        assert_eq!(
+            serde_json::json!({
+                "code": "600000.XSHG",
+                "count": 2,
+                "unit": "1d",
+                "fq_ref_date": "2020-08-03",
+            }),
+            serde_json::to_value(&cmd).unwrap()
+        );
+        let body = "date,open,close,high,low,volume,money\n\
+                    2020-07-31,10.51,10.56,10.62,10.48,45812300,484564789.21\n\
+                    2020-08-03,10.56,10.78,10.80,10.55,61233400,657301234.50\n";
+        let bars: Vec<Bar> = GetBars::consume_body(body.as_bytes()).unwrap();
+        assert_eq!(2, bars.len());
+        assert_eq!("10.78".parse::<BigDecimal>().unwrap(), bars[1].close);
+        assert_eq!("484564789.21".parse::<BigDecimal>().unwrap(), bars[0].money);
+        assert!(GetBarsPeriod::consume_body("error: invalid code".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+//! 财务数据请求
+//!
+//! 对应jqdata的get_fundamentals，每张表一种请求及返回行，包括市值估值、资产负债表、
+//! 利润表及现金流量表。请求的列固定为返回行中的字段，数值均为BigDecimal，缺失时为空。
+
+use bigdecimal::BigDecimal;
+use jqdata::{BodyConsumer, CsvListBodyConsumer, HasMethod};
+use serde_derive::*;
+use std::io::Read;
+
+// 定义单张表的请求及返回行，请求序列化时携带表名及列名
+macro_rules! fundamentals {
+    ($(#[$doc:meta])* $req:ident, $row:ident, $table:literal, [$($col:ident),+ $(,)?]) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, PartialEq, Serialize)]
+        pub struct $req {
+            table: &'static str,
+            columns: String,
+            pub code: String,
+            // 查询日期，返回该日可见的最近一期数据
+            pub date: String,
+            // 向前返回的期数
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub count: Option<u32>,
+        }
+
+        impl $req {
+            pub fn new(code: impl Into<String>, date: impl Into<String>) -> Self {
+                $req {
+                    table: $table,
+                    columns: [$(stringify!($col)),+].join(","),
+                    code: code.into(),
+                    date: date.into(),
+                    count: None,
+                }
+            }
+
+            pub fn with_count(mut self, count: u32) -> Self {
+                self.count = Some(count);
+                self
+            }
+        }
+
+        #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+        #[serde(default)]
+        pub struct $row {
+            pub code: String,
+            // 估值表为交易日，财务报表为公告日期及报告期
+            pub day: Option<String>,
+            pub pub_date: Option<String>,
+            pub stat_date: Option<String>,
+            $(pub $col: Option<BigDecimal>,)+
+        }
+
+        impl HasMethod for $req {
+            fn method(&self) -> String {
+                "get_fundamentals".to_owned()
+            }
+        }
+
+        impl CsvListBodyConsumer for $req {
+            type Output = $row;
+        }
+
+        impl BodyConsumer<Vec<$row>> for $req {
+            fn consume_body<R: Read>(body: R) -> jqdata::Result<Vec<$row>> {
+                <Self as CsvListBodyConsumer>::consume(body)
+            }
+        }
+    };
+}
+
+fundamentals!(
+    /// 市值估值
+    GetValuation,
+    Valuation,
+    "valuation",
+    [
+        pe_ratio,
+        pe_ratio_lyr,
+        pb_ratio,
+        ps_ratio,
+        pcf_ratio,
+        turnover_ratio,
+        capitalization,
+        circulating_cap,
+        market_cap,
+        circulating_market_cap,
+    ]
+);
+
+fundamentals!(
+    /// 资产负债表
+    GetBalance,
+    Balance,
+    "balance",
+    [
+        cash_equivalents,
+        account_receivable,
+        inventories,
+        total_assets,
+        total_liability,
+        total_owner_equities,
+    ]
+);
+
+fundamentals!(
+    /// 利润表
+    GetIncome,
+    Income,
+    "income",
+    [
+        total_operating_revenue,
+        operating_revenue,
+        operating_profit,
+        total_profit,
+        net_profit,
+        np_parent_company_owners,
+        basic_eps,
+    ]
+);
+
+fundamentals!(
+    /// 现金流量表
+    GetCashFlow,
+    CashFlow,
+    "cash_flow",
+    [
+        net_operate_cash_flow,
+        net_invest_cash_flow,
+        net_finance_cash_flow,
+        cash_equivalent_increase,
+    ]
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_valuation() {
+        let cmd = GetValuation::new("600000.XSHG", "2020-08-03").with_count(2);
+        assert_eq!("get_fundamentals", cmd.method());
+        let params = serde_json::to_value(&cmd).unwrap();
+        assert_eq!("valuation", params["table"]);
+        assert!(params["columns"]
+            .as_str()
+            .unwrap()
+            .starts_with("pe_ratio,pe_ratio_lyr,pb_ratio"));
+        assert_eq!(2, params["count"]);
+
+        // 返回的列可少于请求的列
+        let body = "code,day,pe_ratio,pb_ratio,market_cap\n\
+                    600000.XSHG,2020-07-31,5.12,0.55,3099.80\n\
+                    600000.XSHG,2020-08-03,5.23,,3165.48\n";
+        let rows: Vec<Valuation> = GetValuation::consume_body(body.as_bytes()).unwrap();
+        assert_eq!(2, rows.len());
+        assert_eq!(Some("2020-08-03".to_owned()), rows[1].day);
+        assert_eq!(
+            Some("5.12".parse::<BigDecimal>().unwrap()),
+            rows[0].pe_ratio
+        );
+        assert_eq!(None, rows[1].pb_ratio);
+        assert_eq!(None, rows[0].turnover_ratio);
+    }
+}
@@ -0,0 +1,241 @@
+//! jqdata的共享HTTP客户端
+//!
+//! jqdata库每次请求新建reqwest客户端，连接无法复用，选股等高并发场景下建立连接成为瓶颈。
+//! 此处所有账户及会话共用进程内唯一的客户端，连接池大小及保活时间可配置，
+//! HTTPS经ALPN协商，上游支持时使用HTTP/2多路复用。
+//!
+//! 响应体异步读取，较大的响应在阻塞线程中解析，长区间的K线不会占用异步工作线程。
+//!
+//! 请求的发送抽象为Transport，默认经共享客户端发往上游，
+//! 测试时可替换为录制或回放的实现，见transport模块。
+
+use async_trait::async_trait;
+use jqdata::{BodyConsumer, HasMethod, Request};
+use lazy_static::*;
+use reqwest::header::{HeaderValue, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use serde_derive::*;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const JQDATA_URL: &str = "https://dataapi.joinquant.com/apis";
+// 响应超过该字节数时在阻塞线程中解析
+const BLOCKING_PARSE_BYTES: usize = 64 * 1024;
+
+/// 共享客户端的连接配置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HttpConfig {
+    // 每个主机保留的空闲连接数
+    pub max_idle_per_host: usize,
+    pub idle_timeout_secs: u64,
+    // TCP保活间隔，0表示关闭
+    pub tcp_keepalive_secs: u64,
+    pub connect_timeout_secs: u64,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            max_idle_per_host: 32,
+            idle_timeout_secs: 90,
+            tcp_keepalive_secs: 60,
+            connect_timeout_secs: 10,
+        }
+    }
+}
+
+/// 共享客户端的使用情况
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpStats {
+    #[serde(flatten)]
+    pub config: HttpConfig,
+    pub requests: u64,
+    pub failures: u64,
+    // 正在进行的请求数及其峰值
+    pub in_flight: u64,
+    pub peak_in_flight: u64,
+    // 经HTTP/2返回的响应数
+    pub http2_responses: u64,
+    // 在阻塞线程中解析的响应数
+    pub blocking_parses: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: AtomicU64,
+    failures: AtomicU64,
+    in_flight: AtomicU64,
+    peak_in_flight: AtomicU64,
+    http2_responses: AtomicU64,
+    blocking_parses: AtomicU64,
+}
+
+lazy_static! {
+    static ref CONFIG: Mutex<HttpConfig> = Mutex::new(HttpConfig::default());
+    static ref CLIENT: reqwest::Client = {
+        BUILT.store(true, Ordering::Relaxed);
+        build_client(&CONFIG.lock().unwrap())
+    };
+    static ref COUNTERS: Counters = Counters::default();
+}
+
+static BUILT: AtomicBool = AtomicBool::new(false);
+
+/// 设置共享客户端的连接配置，需在首次请求前调用
+pub fn set_http_config(cfg: HttpConfig) {
+    if BUILT.load(Ordering::Relaxed) {
+        log::warn!("jqdata http client already built, config ignored");
+        return;
+    }
+    *CONFIG.lock().unwrap() = cfg;
+}
+
+/// 共享客户端的配置及计数
+pub fn http_stats() -> HttpStats {
+    HttpStats {
+        config: CONFIG.lock().unwrap().clone(),
+        requests: COUNTERS.requests.load(Ordering::Relaxed),
+        failures: COUNTERS.failures.load(Ordering::Relaxed),
+        in_flight: COUNTERS.in_flight.load(Ordering::Relaxed),
+        peak_in_flight: COUNTERS.peak_in_flight.load(Ordering::Relaxed),
+        http2_responses: COUNTERS.http2_responses.load(Ordering::Relaxed),
+        blocking_parses: COUNTERS.blocking_parses.load(Ordering::Relaxed),
+    }
+}
+
+fn build_client(cfg: &HttpConfig) -> reqwest::Client {
+    let keepalive = match cfg.tcp_keepalive_secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+    reqwest::Client::builder()
+        .pool_max_idle_per_host(cfg.max_idle_per_host)
+        .pool_idle_timeout(Duration::from_secs(cfg.idle_timeout_secs))
+        .tcp_keepalive(keepalive)
+        .connect_timeout(Duration::from_secs(cfg.connect_timeout_secs))
+        .build()
+        .expect("failed to build jqdata http client")
+}
+
+// 计入正在进行的请求，超时取消时随析构减少
+struct InFlight;
+
+impl InFlight {
+    fn enter() -> Self {
+        COUNTERS.requests.fetch_add(1, Ordering::Relaxed);
+        let n = COUNTERS.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        COUNTERS.peak_in_flight.fetch_max(n, Ordering::Relaxed);
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        COUNTERS.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+async fn post(body: String) -> jqdata::Result<String> {
+    let _guard = InFlight::enter();
+    let rst = async {
+        let resp = CLIENT
+            .post(JQDATA_URL)
+            .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+            .body(body)
+            .send()
+            .await?;
+        if resp.version() == reqwest::Version::HTTP_2 {
+            COUNTERS.http2_responses.fetch_add(1, Ordering::Relaxed);
+        }
+        resp.text().await
+    }
+    .await;
+    rst.map_err(|e| {
+        COUNTERS.failures.fetch_add(1, Ordering::Relaxed);
+        jqdata::Error::Client(e.to_string())
+    })
+}
+
+/// 请求的发送方式，发送JSON请求体并返回响应文本
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn send(&self, body: String) -> jqdata::Result<String>;
+}
+
+/// 经共享客户端发往上游
+pub struct HttpTransport;
+
+#[async_trait]
+impl Transport for HttpTransport {
+    async fn send(&self, body: String) -> jqdata::Result<String> {
+        post(body).await
+    }
+}
+
+/// 单个账户的客户端，默认所有账户共用同一连接池
+#[derive(Clone)]
+pub struct JqClient {
+    token: String,
+    transport: Arc<dyn Transport>,
+}
+
+impl JqClient {
+    /// 登录并获取令牌
+    pub async fn login(mob: &str, pwd: &str) -> jqdata::Result<Self> {
+        Self::login_with(Arc::new(HttpTransport), mob, pwd).await
+    }
+
+    /// 经指定的发送方式登录，后续请求使用同一发送方式
+    pub async fn login_with(
+        transport: Arc<dyn Transport>,
+        mob: &str,
+        pwd: &str,
+    ) -> jqdata::Result<Self> {
+        let body = serde_json::json!({
+            "method": "get_current_token",
+            "mob": mob,
+            "pwd": pwd,
+        });
+        let token = transport.send(body.to_string()).await?;
+        if token.starts_with("error") {
+            return Err(jqdata::Error::Server(token));
+        }
+        Ok(JqClient { token, transport })
+    }
+
+    pub async fn execute<T, C>(&self, command: C) -> jqdata::Result<T>
+    where
+        T: for<'de> Deserialize<'de>,
+        T: Serialize + Send + 'static,
+        C: HasMethod + BodyConsumer<T> + Serialize + 'static,
+    {
+        let body = serde_json::to_string(&Request::new(self.token.clone(), command))?;
+        let resp = self.transport.send(body).await?;
+        if resp.len() < BLOCKING_PARSE_BYTES {
+            return <C as BodyConsumer<T>>::consume_body(resp.as_bytes());
+        }
+        COUNTERS.blocking_parses.fetch_add(1, Ordering::Relaxed);
+        tokio::task::spawn_blocking(move || <C as BodyConsumer<T>>::consume_body(resp.as_bytes()))
+            .await
+            .map_err(|e| jqdata::Error::Client(e.to_string()))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_flight() {
+        let before = http_stats();
+        {
+            let _a = InFlight::enter();
+            let _b = InFlight::enter();
+            assert!(http_stats().peak_in_flight >= 2);
+        }
+        let after = http_stats();
+        assert_eq!(before.requests + 2, after.requests);
+        assert_eq!(before.in_flight, after.in_flight);
+    }
+}
@@ -0,0 +1,168 @@
+//! 上游请求的限速及配额预算
+//!
+//! jqdata按账户限制每日的查询条数，各使用方各自查询剩余条数并判断是否停止，容易遗漏。
+//! 此处在客户端池中统一限制请求间隔并统计当日的请求数，达到预算时按配置返回错误
+//! 或等待至次日；账户最近查询的剩余条数低于保留值时视为耗尽，切换至其他账户。
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use serde_derive::*;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 达到预算时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaAction {
+    // 返回错误
+    Error,
+    // 等待至次日预算重置
+    Pause,
+}
+
+/// 配额预算，各项为0表示不限制
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuotaBudget {
+    // 每个自然日最多的请求数
+    pub daily_calls: u64,
+    // 账户剩余条数低于该值时不再使用
+    pub reserve: i32,
+    // 两次请求的最小间隔毫秒数
+    pub min_interval_ms: u64,
+    pub action: QuotaAction,
+}
+
+impl Default for QuotaBudget {
+    fn default() -> Self {
+        QuotaBudget {
+            daily_calls: 0,
+            reserve: 0,
+            min_interval_ms: 0,
+            action: QuotaAction::Error,
+        }
+    }
+}
+
+/// 当日的配额使用情况
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    #[serde(flatten)]
+    pub budget: QuotaBudget,
+    pub day: Option<NaiveDate>,
+    pub used: u64,
+    // 当日剩余的请求数，不限制时为空
+    pub remaining: Option<u64>,
+}
+
+/// 申请请求的结果
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Admission {
+    // 等待给定时长后发送
+    Wait(Duration),
+    // 当日预算已用完
+    Exhausted { used: u64, limit: u64 },
+}
+
+#[derive(Debug, Default)]
+struct QuotaState {
+    day: Option<NaiveDate>,
+    used: u64,
+    // 下一次请求最早的发送时刻
+    next_slot: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Quota {
+    budget: Mutex<QuotaBudget>,
+    state: Mutex<QuotaState>,
+}
+
+impl Quota {
+    pub(super) fn set_budget(&self, budget: QuotaBudget) {
+        *self.budget.lock().unwrap() = budget;
+    }
+
+    pub(super) fn budget(&self) -> QuotaBudget {
+        self.budget.lock().unwrap().clone()
+    }
+
+    /// 申请一次请求，计入当日用量并预留发送时刻
+    pub(super) fn admit(&self, now: NaiveDateTime, instant: Instant) -> Admission {
+        let budget = self.budget();
+        let mut state = self.state.lock().unwrap();
+        if state.day != Some(now.date()) {
+            state.day = Some(now.date());
+            state.used = 0;
+        }
+        if budget.daily_calls > 0 && state.used >= budget.daily_calls {
+            return Admission::Exhausted {
+                used: state.used,
+                limit: budget.daily_calls,
+            };
+        }
+        state.used += 1;
+        let slot = state.next_slot.map_or(instant, |s| s.max(instant));
+        state.next_slot = Some(slot + Duration::from_millis(budget.min_interval_ms));
+        Admission::Wait(slot - instant)
+    }
+
+    pub(super) fn usage(&self, today: NaiveDate) -> QuotaUsage {
+        let budget = self.budget();
+        let state = self.state.lock().unwrap();
+        let used = if state.day == Some(today) {
+            state.used
+        } else {
+            0
+        };
+        QuotaUsage {
+            remaining: match budget.daily_calls {
+                0 => None,
+                limit => Some(limit.saturating_sub(used)),
+            },
+            budget,
+            day: state.day,
+            used,
+        }
+    }
+}
+
+/// 距次日零点的时长
+pub(super) fn until_next_day(now: NaiveDateTime) -> Duration {
+    let next = now.date().succ_opt().unwrap().and_time(NaiveTime::MIN);
+    (next - now).to_std().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admit() {
+        let quota = Quota::default();
+        quota.set_budget(QuotaBudget {
+            daily_calls: 2,
+            min_interval_ms: 100,
+            ..QuotaBudget::default()
+        });
+        let now = NaiveDateTime::parse_from_str("2020-08-03 10:00", "%Y-%m-%d %H:%M").unwrap();
+        let instant = Instant::now();
+        assert_eq!(Admission::Wait(Duration::ZERO), quota.admit(now, instant));
+        // 第二次请求需等待间隔
+        assert_eq!(
+            Admission::Wait(Duration::from_millis(100)),
+            quota.admit(now, instant)
+        );
+        assert_eq!(
+            Admission::Exhausted { used: 2, limit: 2 },
+            quota.admit(now, instant)
+        );
+        assert_eq!(Some(0), quota.usage(now.date()).remaining);
+        // 次日重置
+        let tomorrow = now + chrono::Duration::days(1);
+        assert_eq!(Some(2), quota.usage(tomorrow.date()).remaining);
+        assert!(matches!(
+            quota.admit(tomorrow, instant + Duration::from_secs(1)),
+            Admission::Wait(_)
+        ));
+        assert_eq!(Duration::from_secs(14 * 3600), until_next_day(now));
+    }
+}
@@ -0,0 +1,178 @@
+//! 上游响应缓存
+//!
+//! 相同方法及参数的请求在有效期内直接返回缓存的结果，不消耗配额。
+//! 缓存键与请求记录一致，为方法及脱敏后的参数；有效期按方法配置，为0时不缓存。
+//! 默认实现将每条响应写入目录中的单个文件，进程重启后仍然有效。
+
+use crate::{Error, ErrorKind, Result};
+use serde_derive::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 响应缓存
+pub trait ResponseCache: Send + Sync {
+    /// 查询未过期的响应
+    fn get(&self, key: &str) -> Option<serde_json::Value>;
+
+    /// 写入响应，ttl后过期
+    fn put(&self, key: &str, output: &serde_json::Value, ttl: Duration);
+}
+
+/// 各方法的缓存有效期
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachePolicy {
+    pub default_ttl: Duration,
+    pub method_ttls: HashMap<String, Duration>,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        let mut method_ttls = HashMap::new();
+        // 剩余条数及实时行情不缓存
+        for method in &["get_query_count", "get_current_tick", "get_current_ticks"] {
+            method_ttls.insert((*method).to_owned(), Duration::from_secs(0));
+        }
+        CachePolicy {
+            default_ttl: Duration::from_secs(3600),
+            method_ttls,
+        }
+    }
+}
+
+impl CachePolicy {
+    /// 解析形如get_price_period:86400,get_mtss:0的配置，覆盖默认的有效期秒数
+    pub fn parse(default_secs: u64, s: &str) -> Result<Self> {
+        let mut policy = CachePolicy {
+            default_ttl: Duration::from_secs(default_secs),
+            ..CachePolicy::default()
+        };
+        for item in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let secs = item
+                .split_once(':')
+                .and_then(|(m, secs)| secs.trim().parse::<u64>().ok().map(|secs| (m, secs)));
+            match secs {
+                Some((method, secs)) => {
+                    policy
+                        .method_ttls
+                        .insert(method.trim().to_owned(), Duration::from_secs(secs));
+                }
+                None => {
+                    return Err(Error::custom(
+                        ErrorKind::BadRequest,
+                        format!("invalid cache ttl {}, expect method:secs", item),
+                    ))
+                }
+            }
+        }
+        Ok(policy)
+    }
+
+    pub fn ttl(&self, method: &str) -> Duration {
+        self.method_ttls
+            .get(method)
+            .cloned()
+            .unwrap_or(self.default_ttl)
+    }
+}
+
+/// 基于目录的缓存，文件名为缓存键的摘要
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    key: String,
+    // 过期时刻的Unix秒数
+    expires_at: u64,
+    output: serde_json::Value,
+}
+
+impl DiskCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        std::fs::create_dir_all(dir.as_ref()).map_err(|e| {
+            Error::custom(
+                ErrorKind::InternalServerError,
+                format!("failed to create cache dir: {}", e),
+            )
+        })?;
+        Ok(DiskCache {
+            dir: dir.as_ref().to_owned(),
+        })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let digest = Sha256::digest(key.as_bytes());
+        let name: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        self.dir.join(format!("{}.json", name))
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl ResponseCache for DiskCache {
+    fn get(&self, key: &str) -> Option<serde_json::Value> {
+        let path = self.path(key);
+        let s = std::fs::read_to_string(&path).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&s).ok()?;
+        // 摘要碰撞时视为未命中
+        if entry.key != key {
+            return None;
+        }
+        if entry.expires_at <= unix_secs() {
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        Some(entry.output)
+    }
+
+    fn put(&self, key: &str, output: &serde_json::Value, ttl: Duration) {
+        let entry = CacheEntry {
+            key: key.to_owned(),
+            expires_at: unix_secs() + ttl.as_secs(),
+            output: output.clone(),
+        };
+        let path = self.path(key);
+        match serde_json::to_string(&entry) {
+            Ok(s) => {
+                if let Err(e) = std::fs::write(&path, s) {
+                    log::warn!("failed to write jqdata cache {:?}: {}", path, e);
+                }
+            }
+            Err(e) => log::warn!("failed to serialize jqdata cache: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disk_cache() {
+        let dir = std::env::temp_dir().join(format!("tanglism-jqcache-{}", std::process::id()));
+        let cache = DiskCache::new(&dir).unwrap();
+        let key = "get_price_period {\"code\":\"600000.XSHG\"}";
+        assert_eq!(None, cache.get(key));
+        cache.put(key, &serde_json::json!([1, 2]), Duration::from_secs(60));
+        assert_eq!(Some(serde_json::json!([1, 2])), cache.get(key));
+        // 已过期
+        cache.put(key, &serde_json::json!([3]), Duration::from_secs(0));
+        assert_eq!(None, cache.get(key));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let policy = CachePolicy::parse(600, "get_price_period:86400, get_mtss:0").unwrap();
+        assert_eq!(Duration::from_secs(86400), policy.ttl("get_price_period"));
+        assert_eq!(Duration::from_secs(0), policy.ttl("get_mtss"));
+        assert_eq!(Duration::from_secs(0), policy.ttl("get_query_count"));
+        assert_eq!(Duration::from_secs(600), policy.ttl("get_all_securities"));
+        assert!(CachePolicy::parse(600, "get_mtss").is_err());
+    }
+}
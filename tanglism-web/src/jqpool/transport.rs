@@ -0,0 +1,171 @@
+//! jqdata请求的录制及回放
+//!
+//! 录制时将脱敏后的请求体及上游的原始响应成对写入目录，
+//! 回放时按请求体返回记录的响应，不访问上游。
+//! 与回放客户端池不同，回放的是原始响应，登录及响应解析均照常执行。
+
+use super::http::Transport;
+use super::{read_json_files, redact};
+use crate::{Error, ErrorKind, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// 录制请求及响应至目录，请求经内层发送
+pub struct RecordTransport {
+    inner: Arc<dyn Transport>,
+    dir: PathBuf,
+    seq: AtomicU64,
+}
+
+impl RecordTransport {
+    pub fn new(inner: Arc<dyn Transport>, dir: impl Into<PathBuf>) -> Self {
+        RecordTransport {
+            inner,
+            dir: dir.into(),
+            seq: AtomicU64::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for RecordTransport {
+    async fn send(&self, body: String) -> jqdata::Result<String> {
+        let request = redacted_request(&body);
+        let rst = self.inner.send(body).await;
+        // 网络错误不录制
+        if let Ok(ref response) = rst {
+            let method = request
+                .get("method")
+                .and_then(|m| m.as_str())
+                .unwrap_or("unknown");
+            let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+            let path = self.dir.join(format!("{:06}-{}.json", seq, method));
+            // 登录的响应即令牌，同样脱敏
+            let response = if method == "get_current_token" {
+                "***"
+            } else {
+                response.as_str()
+            };
+            let record = serde_json::json!({
+                "request": request,
+                "response": response,
+            });
+            if let Err(e) = std::fs::write(&path, record.to_string()) {
+                log::warn!("failed to record jqdata request to {:?}: {}", path, e);
+            }
+        }
+        rst
+    }
+}
+
+/// 由目录中的录制返回响应，相同请求以序号较大的为准
+pub struct ReplayTransport {
+    records: HashMap<String, String>,
+}
+
+impl ReplayTransport {
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let mut records = HashMap::new();
+        for (path, s) in read_json_files(dir)? {
+            let record = serde_json::from_str::<serde_json::Value>(&s)
+                .ok()
+                .and_then(|v| {
+                    let response = v.get("response")?.as_str()?.to_owned();
+                    Some((v.get("request")?.to_string(), response))
+                })
+                .ok_or_else(|| {
+                    Error::custom(
+                        ErrorKind::InternalServerError,
+                        format!("invalid transport record {:?}", path),
+                    )
+                })?;
+            records.insert(record.0, record.1);
+        }
+        Ok(ReplayTransport { records })
+    }
+}
+
+#[async_trait]
+impl Transport for ReplayTransport {
+    async fn send(&self, body: String) -> jqdata::Result<String> {
+        let key = redacted_request(&body).to_string();
+        self.records
+            .get(&key)
+            .cloned()
+            .ok_or_else(|| jqdata::Error::Client(format!("no recorded response for {}", key)))
+    }
+}
+
+// 解析请求体并脱敏，令牌及账户不影响回放的匹配
+fn redacted_request(body: &str) -> serde_json::Value {
+    let mut request =
+        serde_json::from_str(body).unwrap_or_else(|_| serde_json::Value::String(body.to_owned()));
+    redact(&mut request);
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JqClient;
+
+    // 固定返回令牌或K线的上游
+    struct FakeUpstream;
+
+    #[async_trait]
+    impl Transport for FakeUpstream {
+        async fn send(&self, body: String) -> jqdata::Result<String> {
+            if body.contains("get_current_token") {
+                Ok("token-1".to_owned())
+            } else {
+                Ok(
+                    "date,open,close,high,low,volume,money\n2020-08-03,1,2,3,1,100,200\n"
+                        .to_owned(),
+                )
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let dir = std::env::temp_dir().join(format!("tanglism-transport-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cmd = || jqdata::GetPricePeriod {
+            code: "600000.XSHG".to_owned(),
+            unit: "1d".to_owned(),
+            date: "2020-08-03 00:00:00".to_owned(),
+            end_date: "2020-08-03 23:59:59".to_owned(),
+            fq_ref_date: None,
+        };
+        let record = Arc::new(RecordTransport::new(Arc::new(FakeUpstream), &dir));
+        let client = JqClient::login_with(record, "13800000001", "pwd")
+            .await
+            .unwrap();
+        let prices: Vec<jqdata::Price> = client.execute(cmd()).await.unwrap();
+        assert_eq!(1, prices.len());
+        // 录制中不含账户及密码
+        for (_, s) in read_json_files(&dir).unwrap() {
+            assert!(!s.contains("13800000001") && !s.contains("token-1"));
+        }
+
+        // 回放时账户不同也可匹配
+        let replay = Arc::new(ReplayTransport::load(&dir).unwrap());
+        let client = JqClient::login_with(replay, "13900000002", "other")
+            .await
+            .unwrap();
+        let replayed: Vec<jqdata::Price> = client.execute(cmd()).await.unwrap();
+        assert_eq!(prices[0].close, replayed[0].close);
+        let other = jqdata::GetPricePeriod {
+            code: "000001.XSHE".to_owned(),
+            ..cmd()
+        };
+        assert!(client
+            .execute::<Vec<jqdata::Price>, _>(other)
+            .await
+            .is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
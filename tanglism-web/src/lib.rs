@@ -1,58 +1,146 @@
 #![forbid(unsafe_code)]
+// 路由过滤器的组合类型嵌套较深，release构建需要更大的递归限制
+#![recursion_limit = "256"]
 
 #[macro_use]
 extern crate diesel;
 
 mod errors;
 pub mod handlers;
+mod jqpool;
 pub mod models;
 mod routes;
 pub mod schema;
 mod ws;
 
 use chrono::NaiveDateTime;
+use diesel::connection::SimpleConnection;
 use diesel::pg::PgConnection;
-use diesel::r2d2::{self, ConnectionManager};
-use jqdata::JqdataClient;
+use diesel::r2d2::{self, ConnectionManager, CustomizeConnection};
 use serde_derive::*;
+use std::path::PathBuf;
 use std::time::Duration;
+use tanglism_utils::Tick;
 use warp::http::Uri;
 use warp::Filter;
 
-pub use errors::{Error, ErrorKind};
+pub use errors::{Error, ErrorKind, ResultExt};
+pub use jqpool::{
+    http_stats, parse_jqaccounts, set_http_config, AccountUsage, Balance, Bar, CachePolicy,
+    CashFlow, DiskCache, GetBalance, GetBars, GetBarsPeriod, GetCashFlow, GetIncome, GetValuation,
+    HttpConfig, HttpStats, HttpTransport, Income, JqClient, JqdataPool, QuotaAction, QuotaBudget,
+    QuotaUsage, RecordTransport, ReplayTransport, RequestLogConfig, RequestStats, ResponseCache,
+    Transport, Valuation,
+};
+pub use ws::ThrottleConfig;
 pub type Result<T> = std::result::Result<T, Error>;
 
 // use r2d2 to manage Postgres connections
 pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
 
+/// 超时配置，None表示不限制
+#[derive(Debug, Clone, Default)]
+pub struct TimeoutConfig {
+    // 单次jqdata请求
+    pub jqdata: Option<Duration>,
+    // 单条数据库语句，通过statement_timeout由数据库取消
+    pub db_statement: Option<Duration>,
+}
+
+// 新建连接时设置语句超时
+#[derive(Debug)]
+struct StatementTimeout(Duration);
+
+impl CustomizeConnection<PgConnection, r2d2::Error> for StatementTimeout {
+    fn on_acquire(&self, conn: &mut PgConnection) -> std::result::Result<(), r2d2::Error> {
+        conn.batch_execute(&format!("SET statement_timeout = {}", self.0.as_millis()))
+            .map_err(r2d2::Error::QueryError)
+    }
+}
+
 // 股票基础配置
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[derive(Debug, PartialEq, Hash, Serialize, Deserialize, Clone)]
 pub struct BasicCfg {
-    tick: String,
+    tick: Tick,
     code: String,
     start_ts: NaiveDateTime,
     end_ts: NaiveDateTime,
+    // 日内模式，每个交易日开盘时重新开始分析
+    #[serde(default)]
+    intraday: bool,
 }
 
-pub async fn server(host: &str, port: u16, dburl: &str, jqaccount: &str) -> Result<()> {
+#[allow(clippy::too_many_arguments)]
+pub async fn server(
+    host: &str,
+    port: u16,
+    dburl: &str,
+    jqaccount: Option<&str>,
+    admin_token: Option<String>,
+    report_watchlist: Option<Vec<String>>,
+    heatmap_scheme: Option<String>,
+    jq_log: RequestLogConfig,
+    timeouts: TimeoutConfig,
+    throttle: ThrottleConfig,
+    quota: QuotaBudget,
+    jq_cache: Option<(PathBuf, CachePolicy)>,
+) -> Result<()> {
     let host: std::net::IpAddr = host.parse().expect("host must be string of IPv4");
     let manager = ConnectionManager::<PgConnection>::new(dburl);
-    let pool = r2d2::Pool::builder()
-        .connection_timeout(Duration::from_secs(3))
+    let mut builder = r2d2::Pool::builder().connection_timeout(Duration::from_secs(3));
+    if let Some(timeout) = timeouts.db_statement {
+        builder = builder.connection_customizer(Box::new(StatementTimeout(timeout)));
+    }
+    let pool = builder
         .build(manager)
         .expect("Failed to create db connection pool");
-    let (jqmob, jqpwd) = parse_jqaccount(jqaccount)?;
-    let jq = JqdataClient::with_credential(jqmob, jqpwd).await?;
+    // 支持以逗号分隔的多个账户，未指定账户时为离线模式，指定回放目录时忽略账户
+    let jq = match (&jq_log.replay_dir, jqaccount) {
+        (Some(dir), _) => JqdataPool::replay(dir)?,
+        (None, Some(jqaccount)) => {
+            JqdataPool::with_transport(jq_log.transport(), parse_jqaccounts(jqaccount)?).await?
+        }
+        (None, None) => JqdataPool::offline(),
+    };
+    jq.set_request_log(jq_log);
+    jq.set_request_timeout(timeouts.jqdata);
+    jq.set_quota_budget(quota);
+    if let Some((dir, policy)) = jq_cache {
+        jq.set_response_cache(std::sync::Arc::new(DiskCache::new(dir)?), policy);
+    }
+
+    // 配置自选股时，定时生成走势周报
+    let mut schedules = Vec::new();
+    if let Some(codes) = report_watchlist {
+        schedules.push(handlers::jobs::JobSchedule::WeeklyReport {
+            codes,
+            format: handlers::reports::ReportFormat::Markdown,
+        });
+    }
+    // 配置行业分类时，每个交易日收盘后生成市场热力图
+    if let Some(scheme) = heatmap_scheme {
+        schedules.push(handlers::jobs::JobSchedule::DailyHeatmap { scheme });
+    }
+
+    // 后台任务队列
+    tokio::spawn(handlers::jobs::run_job_worker(
+        pool.clone(),
+        jq.clone(),
+        schedules,
+    ));
+
+    // 信号的Webhook推送
+    tokio::spawn(handlers::webhooks::run_webhook_worker(pool.clone()));
 
     // 主页重定向
     let index = warp::get()
         .and(warp::path::end())
         .map(|| warp::redirect(Uri::from_static("/static/index.html")));
     // websocket
-    let ws_filter = ws::ws_filter(jq, pool.clone());
+    let ws_filter = ws::ws_filter(jq.clone(), pool.clone(), throttle);
 
     // API路由
-    let apis = routes::api_route(pool);
+    let apis = routes::api_route(pool, jq, admin_token);
 
     // 静态资源文件
     let files = warp::get()
@@ -74,3 +162,27 @@ pub fn parse_jqaccount(account: &str) -> Result<(String, String)> {
     }
     Ok((splits[0].to_owned(), splits[1].to_owned()))
 }
+
+/// 测试用的jqdata回放记录目录
+#[cfg(test)]
+pub(crate) const REPLAY_FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/replay");
+
+/// 测试用的数据库连接池
+///
+/// 由环境变量TEST_DATABASE_URL指定已执行迁移的数据库，未设置时返回None，依赖数据库的测试跳过
+#[cfg(test)]
+pub(crate) fn test_db_pool() -> Option<DbPool> {
+    let url = match std::env::var("TEST_DATABASE_URL") {
+        Ok(url) => url,
+        Err(_) => {
+            eprintln!("TEST_DATABASE_URL not set, skip test depending on database");
+            return None;
+        }
+    };
+    let manager = ConnectionManager::<PgConnection>::new(url);
+    let pool = r2d2::Pool::builder()
+        .connection_timeout(Duration::from_secs(3))
+        .build(manager)
+        .expect("failed to connect test database");
+    Some(pool)
+}
@@ -1,6 +1,28 @@
-use crate::schema::{stock_daily_prices, stock_price_ticks, stock_tick_prices};
+use crate::schema::{
+    annotation_rules, fund_holdings, fund_net_values, industry_stocks, jobs, macd_configs,
+    market_heatmaps, metric_caches, northbound_flows, northbound_holdings, notes, reports,
+    score_weights, security_names, snapshots, stock_daily_prices, stock_events,
+    stock_price_anomalies, stock_price_invalidations, stock_price_ticks, stock_tick_prices,
+    synthetic_series, webhook_deliveries, webhooks,
+};
 use bigdecimal::BigDecimal;
 use chrono::{NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use serde_derive::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// stock_tick_prices每行绑定9个变量，SQL的变量绑定<=65535
+const MAX_TICK_PRICE_BATCH_SIZE: usize = 65535 / 9;
+static TICK_PRICE_BATCH_SIZE: AtomicUsize = AtomicUsize::new(5000);
+
+/// 设置批量插入K线时每条INSERT的行数，超过变量绑定限制时截断
+pub fn set_tick_price_batch_size(n: usize) {
+    TICK_PRICE_BATCH_SIZE.store(n.clamp(1, MAX_TICK_PRICE_BATCH_SIZE), Ordering::Relaxed);
+}
+
+pub fn tick_price_batch_size() -> usize {
+    TICK_PRICE_BATCH_SIZE.load(Ordering::Relaxed)
+}
 
 #[allow(dead_code)]
 #[derive(Debug, Queryable)]
@@ -58,3 +80,442 @@ pub struct StockTickPrice {
     pub volume: BigDecimal,
     pub amount: BigDecimal,
 }
+
+/// 按交易日切分K线，每块不超过batch_size行，单日超过batch_size时独占一块
+///
+/// 以日为边界提交，中断后已提交的区间完整，可从下一交易日继续
+pub fn split_by_day(prices: &[StockTickPrice], batch_size: usize) -> Vec<&[StockTickPrice]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut day_start = 0;
+    for i in 1..=prices.len() {
+        if i < prices.len() && prices[i].ts.date() == prices[i - 1].ts.date() {
+            continue;
+        }
+        // [day_start, i)为同一交易日
+        if i - start > batch_size && day_start > start {
+            chunks.push(&prices[start..day_start]);
+            start = day_start;
+        }
+        day_start = i;
+    }
+    if start < prices.len() {
+        chunks.push(&prices[start..]);
+    }
+    chunks
+}
+
+/// 以多行VALUES批量插入K线，返回插入的行数
+pub fn insert_tick_prices_batched(
+    conn: &PgConnection,
+    prices: &[StockTickPrice],
+    batch_size: usize,
+) -> QueryResult<usize> {
+    let mut n = 0;
+    for batch in prices.chunks(batch_size.clamp(1, MAX_TICK_PRICE_BATCH_SIZE)) {
+        n += diesel::insert_into(stock_tick_prices::table)
+            .values(batch)
+            .execute(conn)?;
+    }
+    Ok(n)
+}
+
+/// 价格区间失效记录，同时作为替换的审计记录
+#[derive(Debug, Queryable, Identifiable, Serialize, Deserialize)]
+pub struct StockPriceInvalidation {
+    pub id: i32,
+    pub tick: String,
+    pub code: String,
+    pub start_dt: NaiveDate,
+    pub end_dt: NaiveDate,
+    pub reason: Option<String>,
+    pub created_at: NaiveDateTime,
+    // 重新下载并替换的时间，为空表示待替换
+    pub replaced_at: Option<NaiveDateTime>,
+    // 被替换的原数据行数
+    pub replaced_rows: Option<i32>,
+    // 重新下载插入的行数
+    pub inserted_rows: Option<i32>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "stock_price_invalidations"]
+pub struct NewStockPriceInvalidation {
+    pub tick: String,
+    pub code: String,
+    pub start_dt: NaiveDate,
+    pub end_dt: NaiveDate,
+    pub reason: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// 被隔离的异常K线，不参与分析
+#[derive(Debug, Queryable, Identifiable, Serialize, Deserialize, Clone)]
+#[table_name = "stock_price_anomalies"]
+pub struct StockPriceAnomaly {
+    pub id: i32,
+    pub tick: String,
+    pub code: String,
+    pub ts: NaiveDateTime,
+    // 异常类型，如inverted_range、zero_volume_move、excessive_move、off_session
+    pub kind: String,
+    pub detail: String,
+    pub open: BigDecimal,
+    pub close: BigDecimal,
+    pub high: BigDecimal,
+    pub low: BigDecimal,
+    pub volume: BigDecimal,
+    pub amount: BigDecimal,
+    pub detected_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "stock_price_anomalies"]
+pub struct NewStockPriceAnomaly {
+    pub tick: String,
+    pub code: String,
+    pub ts: NaiveDateTime,
+    pub kind: String,
+    pub detail: String,
+    pub open: BigDecimal,
+    pub close: BigDecimal,
+    pub high: BigDecimal,
+    pub low: BigDecimal,
+    pub volume: BigDecimal,
+    pub amount: BigDecimal,
+    pub detected_at: NaiveDateTime,
+}
+
+/// 交易笔记
+#[derive(Debug, Queryable, Identifiable, Serialize, Deserialize, Clone)]
+pub struct Note {
+    pub id: i32,
+    pub dt: NaiveDate,
+    pub code: Option<String>,
+    // 关联的信号
+    pub signal_id: Option<String>,
+    // 关联的图表快照
+    pub chart: Option<String>,
+    pub tags: Vec<String>,
+    pub content: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable, AsChangeset, Serialize, Deserialize, Clone)]
+#[table_name = "notes"]
+// 更新时空字段同样写入，以便取消关联
+#[changeset_options(treat_none_as_null = "true")]
+pub struct NoteForm {
+    pub dt: NaiveDate,
+    pub code: Option<String>,
+    pub signal_id: Option<String>,
+    pub chart: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub content: String,
+}
+
+/// 股票事件，如财报发布日
+#[derive(Debug, Queryable, Identifiable, Serialize, Deserialize, Clone)]
+pub struct StockEvent {
+    pub id: i32,
+    pub code: String,
+    pub event_dt: NaiveDate,
+    // 事件类型，如earnings, report
+    pub kind: String,
+    pub title: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable, Serialize, Deserialize, Clone)]
+#[table_name = "stock_events"]
+pub struct StockEventForm {
+    pub code: String,
+    pub event_dt: NaiveDate,
+    pub kind: String,
+    #[serde(default)]
+    pub title: String,
+}
+
+/// 周报
+#[derive(Debug, Queryable, Identifiable, Serialize, Deserialize, Clone)]
+pub struct Report {
+    pub id: i32,
+    pub week_start: NaiveDate,
+    pub week_end: NaiveDate,
+    // markdown或html
+    pub format: String,
+    pub codes: Vec<String>,
+    pub content: String,
+    pub created_at: NaiveDateTime,
+    // 生成时的分析配置，JSON格式，早期的报告为空
+    pub config: String,
+}
+
+#[derive(Debug, Insertable, Serialize, Deserialize, Clone)]
+#[table_name = "reports"]
+pub struct NewReport {
+    pub week_start: NaiveDate,
+    pub week_end: NaiveDate,
+    pub format: String,
+    pub codes: Vec<String>,
+    pub content: String,
+    pub created_at: NaiveDateTime,
+    // 生成时的分析配置，JSON格式，早期的报告为空
+    pub config: String,
+}
+
+/// 基金每日净值，refactor_net_value为复权净值
+#[derive(Debug, Queryable, Insertable, Serialize, Deserialize, Clone)]
+pub struct FundNetValue {
+    pub code: String,
+    pub dt: NaiveDate,
+    pub net_value: BigDecimal,
+    pub sum_value: BigDecimal,
+    pub refactor_net_value: BigDecimal,
+}
+
+/// 基金定期报告披露的持仓股票，market_cap单位为元，proportion为占净值比例（%）
+#[derive(Debug, Queryable, Insertable, Serialize, Deserialize, Clone)]
+pub struct FundHolding {
+    pub code: String,
+    pub period_end: NaiveDate,
+    pub symbol: String,
+    pub pub_date: NaiveDate,
+    pub name: String,
+    pub rank: i32,
+    pub shares: BigDecimal,
+    pub market_cap: BigDecimal,
+    pub proportion: BigDecimal,
+}
+
+/// 北向资金每日成交，金额单位为亿元
+#[derive(Debug, Queryable, Insertable, Serialize, Deserialize, Clone)]
+pub struct NorthboundFlow {
+    pub dt: NaiveDate,
+    // 310001沪股通，310002深股通
+    pub link_id: i32,
+    pub buy_amount: BigDecimal,
+    pub sell_amount: BigDecimal,
+}
+
+/// 北向资金个股持股
+#[derive(Debug, Queryable, Insertable, Serialize, Deserialize, Clone)]
+pub struct NorthboundHolding {
+    pub code: String,
+    pub dt: NaiveDate,
+    pub share_number: BigDecimal,
+    // 占流通股比例，百分数
+    pub share_ratio: BigDecimal,
+}
+
+/// 图表快照，state为分析配置，bundle为分析结果，config为解析后的完整配置，均为JSON
+#[derive(Debug, Queryable, Insertable, Serialize, Deserialize, Clone)]
+pub struct Snapshot {
+    pub id: String,
+    pub code: String,
+    pub tick: String,
+    pub state: String,
+    pub bundle: String,
+    pub created_at: NaiveDateTime,
+    pub config: String,
+}
+
+/// 图表标注规则集，rules为JSON
+#[derive(Debug, Queryable, Insertable, Serialize, Deserialize, Clone)]
+#[table_name = "annotation_rules"]
+pub struct AnnotationRuleSet {
+    pub name: String,
+    pub rules: String,
+    pub updated_at: NaiveDateTime,
+}
+
+/// 合成序列的定义，op为ratio或spread
+#[derive(Debug, Queryable, Insertable, Serialize, Deserialize, Clone, PartialEq)]
+#[table_name = "synthetic_series"]
+pub struct SyntheticSeries {
+    pub code: String,
+    pub op: String,
+    pub left_code: String,
+    pub right_code: String,
+    pub updated_at: NaiveDateTime,
+}
+
+/// 证券名称的历史记录，valid_to为下一名称生效的日期，当前名称为2200-01-01
+#[derive(Debug, Queryable, Insertable, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SecurityName {
+    pub code: String,
+    pub display_name: String,
+    pub name: String,
+    pub valid_from: NaiveDate,
+    pub valid_to: NaiveDate,
+}
+
+/// 行业成分股，scheme为行业分类，如sw_l1申万一级行业
+#[derive(Debug, Queryable, Insertable, Serialize, Deserialize, Clone)]
+pub struct IndustryStock {
+    pub scheme: String,
+    pub industry: String,
+    pub code: String,
+    pub industry_name: String,
+    // 同步成分股时的查询日期
+    pub synced_dt: NaiveDate,
+}
+
+/// 市场热力图，content为JSON
+#[derive(Debug, Queryable, Insertable, Serialize, Deserialize, Clone)]
+pub struct MarketHeatmap {
+    pub dt: NaiveDate,
+    pub scheme: String,
+    pub content: String,
+    pub created_at: NaiveDateTime,
+}
+
+/// 单只股票在指定周期上的MACD参数
+#[derive(Debug, Queryable, Insertable, Serialize, Deserialize, Clone)]
+pub struct MacdConfig {
+    pub code: String,
+    pub tick: String,
+    pub fast_ema_period: i32,
+    pub slow_ema_period: i32,
+    pub dea_period: i32,
+    pub updated_at: NaiveDateTime,
+}
+
+/// 用户的综合评分权重
+#[derive(Debug, Queryable, Insertable, Serialize, Deserialize, Clone)]
+pub struct ScoreWeight {
+    pub user_name: String,
+    pub trend: BigDecimal,
+    pub center: BigDecimal,
+    pub divergence: BigDecimal,
+    pub atrp: BigDecimal,
+    pub high_distance: BigDecimal,
+    pub updated_at: NaiveDateTime,
+}
+
+/// 指标缓存，value为JSON
+#[derive(Debug, Queryable, Insertable, Serialize, Deserialize, Clone)]
+pub struct MetricCache {
+    pub code: String,
+    pub tick: String,
+    pub metric: String,
+    pub params: String,
+    pub data_end_ts: NaiveDateTime,
+    pub value: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+/// 后台任务，params及result为JSON
+#[derive(Debug, Queryable, Identifiable, Serialize, Deserialize, Clone)]
+pub struct Job {
+    pub id: i32,
+    pub kind: String,
+    pub params: String,
+    // pending/running/succeeded/failed/cancelled
+    pub status: String,
+    // 0 ~ 100
+    pub progress: i32,
+    pub message: String,
+    pub result: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub started_at: Option<NaiveDateTime>,
+    pub finished_at: Option<NaiveDateTime>,
+}
+
+/// 信号推送的目标
+#[derive(Debug, Queryable, Identifiable, Serialize, Deserialize, Clone)]
+pub struct Webhook {
+    pub id: i32,
+    pub name: String,
+    pub url: String,
+    // HMAC-SHA256签名的密钥，不对外返回
+    #[serde(skip_serializing)]
+    pub secret: String,
+    // 订阅的推送类型，如event_alert、report
+    pub events: Vec<String>,
+    // 事件提醒的交易日数
+    pub alert_days_before: i32,
+    pub enabled: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable, Serialize, Deserialize, Clone)]
+#[table_name = "webhooks"]
+pub struct WebhookForm {
+    pub name: String,
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+    #[serde(default = "default_alert_days_before")]
+    pub alert_days_before: i32,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_alert_days_before() -> i32 {
+    3
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// 推送记录，payload为JSON
+#[derive(Debug, Queryable, Identifiable, Serialize, Deserialize, Clone)]
+#[table_name = "webhook_deliveries"]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event: String,
+    pub payload: String,
+    // pending/delivered/failed
+    pub status: String,
+    pub attempts: i32,
+    pub response_status: Option<i32>,
+    pub last_error: String,
+    pub created_at: NaiveDateTime,
+    pub next_attempt_at: NaiveDateTime,
+    pub delivered_at: Option<NaiveDateTime>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_split_by_day() {
+        let start = NaiveDate::from_ymd_opt(2020, 8, 3)
+            .unwrap()
+            .and_hms_opt(9, 31, 0)
+            .unwrap();
+        let price = |day: i64, minute: i64| StockTickPrice {
+            tick: "1m".to_owned(),
+            code: "600000.XSHG".to_owned(),
+            ts: start + Duration::days(day) + Duration::minutes(minute),
+            open: BigDecimal::from(1),
+            close: BigDecimal::from(1),
+            high: BigDecimal::from(1),
+            low: BigDecimal::from(1),
+            volume: BigDecimal::from(1),
+            amount: BigDecimal::from(1),
+        };
+        // 3个交易日，每日4根K线
+        let prices: Vec<_> = (0..3)
+            .flat_map(|d| (0..4).map(move |m| (d, m)))
+            .map(|(d, m)| price(d, m))
+            .collect();
+        let lens = |bs: usize| -> Vec<usize> {
+            split_by_day(&prices, bs).iter().map(|c| c.len()).collect()
+        };
+        assert_eq!(vec![12], lens(12));
+        assert_eq!(vec![8, 4], lens(10));
+        assert_eq!(vec![4, 4, 4], lens(5));
+        // 单日超过批量大小时不拆分
+        assert_eq!(vec![4, 4, 4], lens(2));
+        assert!(split_by_day(&[], 5).is_empty());
+    }
+}
@@ -1,26 +1,58 @@
-use crate::handlers::stock_prices::ticks;
-use crate::handlers::{choice, metrics, stocks};
-use crate::DbPool;
+use crate::handlers::i18n::{self, Lang};
+use crate::handlers::output::{self, OutputCfg};
+use crate::handlers::stock_prices::{anomaly, cache, invalidation, last_bar, synthetic};
+use crate::handlers::{
+    choice, clock, confirm, events, funds, heatmap, jobs, metrics, notes, ohlc, reports, score,
+    shape_stats, stocks, structure_diff, warm, webhooks,
+};
+use crate::models::{NoteForm, StockEventForm, WebhookForm};
+use crate::ws::{annotation, share};
+use crate::{BasicCfg, DbPool, Error, ErrorKind, JqdataPool};
 use bigdecimal::BigDecimal;
-use chrono::{Local, NaiveDate};
+use chrono::{Local, NaiveDate, NaiveTime};
 use serde_derive::*;
 use std::convert::Infallible;
-use tanglism_utils::{LocalTradingTimestamps, TradingDates};
+use subtle::ConstantTimeEq;
+use tanglism_utils::{
+    parse_ts_from_str, resolve_end_ts, LocalTradingTimestamps, Tick, TradingDates,
+};
 use warp::Filter;
 
+mod registry;
+pub use registry::{ADMIN_TOKEN_HEADER, API_VERSION, API_VERSION_HEADER};
+
 /// API入口
+///
+/// 以/api/v1为前缀，不带版本的旧路径/api仅兼容最初的接口
+/// 未配置管理令牌时，管理接口一律拒绝访问
+/// 指定语言时错误以本地化的JSON返回，否则交由warp默认处理
 pub fn api_route(
     db: DbPool,
+    jq: JqdataPool,
+    admin_token: Option<String>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    api_get_health()
-        .or(api_search_keyword_stocks(db.clone()))
-        .or(api_list_prioritized_stocks(db.clone()))
-        .or(api_list_choices(db))
+    let legacy = warp::path("api").and(registry::legacy(db.clone()));
+    let versioned = warp::path("api")
+        .and(warp::path(API_VERSION))
+        .and(registry::registered(db, jq, admin_token));
+    let apis = versioned
+        .or(legacy)
+        .map(|r| Ok::<_, warp::Rejection>(Box::new(r) as Box<dyn warp::Reply>))
+        .or_else(|rej| async move { Ok::<_, warp::Rejection>((Err(rej),)) });
+    with_lang()
+        .and(apis)
+        .and_then(|lang, resp| async move {
+            match resp {
+                Ok(reply) => Ok(reply),
+                Err(rej) => i18n::localize_rejection(lang, rej).await,
+            }
+        })
+        .with(warp::reply::with::header(API_VERSION_HEADER, API_VERSION))
 }
 
 /// REST API: 健康检查
 fn api_get_health() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    warp::path!("api" / "health").and(warp::get()).map(|| {
+    warp::path!("health").and(warp::get()).map(|| {
         warp::reply::json(&HealthResponse {
             status: "ok".into(),
             version: env!("CARGO_PKG_VERSION").into(),
@@ -28,21 +60,41 @@ fn api_get_health() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rej
     })
 }
 
+/// GET clock 当前市场阶段、距下一阶段的秒数及各周期正在形成的K线
+pub fn api_market_clock() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
+{
+    warp::path!("clock")
+        .and(warp::get())
+        .map(|| warp::reply::json(&clock::market_clock(Local::now().naive_local())))
+}
+
 /// REST API: 根据关键字搜索股票
 pub fn api_search_keyword_stocks(
     db: DbPool,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    warp::path!("api" / "keyword-stocks")
+    warp::path!("keyword-stocks")
         .and(warp::query::<SearchKeywordStocksParam>())
         .and(with_db(db))
         .and_then(search_keyword_stocks)
 }
 
+/// REST API: 查询股票详情及名称历史
+///
+/// GET stocks/{code}，code可为代码、名称或历史简称
+pub fn api_stock_detail(
+    db: DbPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("stocks" / String)
+        .and(warp::get())
+        .and(with_db(db))
+        .and_then(get_stock_detail)
+}
+
 /// REST API: 查询重点股票
 pub fn api_list_prioritized_stocks(
     db: DbPool,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    warp::path!("api" / "prioritized-stocks")
+    warp::path!("prioritized-stocks")
         .and(warp::query::<ListPrioritizedStocksParam>())
         .and(with_db(db))
         .and_then(list_prioritized_stocks)
@@ -52,12 +104,667 @@ pub fn api_list_prioritized_stocks(
 pub fn api_list_choices(
     db: DbPool,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    warp::path!("api" / "choices")
+    warp::path!("choices")
         .and(warp::query::<ListChoicesParam>())
         .and(with_db(db))
+        .and(with_output())
         .and_then(list_choices)
 }
 
+/// REST API: 交易笔记
+///
+/// GET notes?code=&start_dt=&end_dt=&tag=&signal_id=搜索，POST notes新建
+/// GET/PUT/DELETE notes/{id}查看、修改及删除
+pub fn api_notes(
+    db: DbPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let search = warp::path!("notes")
+        .and(warp::get())
+        .and(warp::query::<notes::NoteQuery>())
+        .and(with_db(db.clone()))
+        .and_then(search_notes);
+    let create = warp::path!("notes")
+        .and(warp::post())
+        .and(warp::body::json::<NoteForm>())
+        .and(with_db(db.clone()))
+        .and_then(create_note);
+    let get = warp::path!("notes" / i32)
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(get_note);
+    let update = warp::path!("notes" / i32)
+        .and(warp::put())
+        .and(warp::body::json::<NoteForm>())
+        .and(with_db(db.clone()))
+        .and_then(update_note);
+    let delete = warp::path!("notes" / i32)
+        .and(warp::delete())
+        .and(with_db(db))
+        .and_then(delete_note);
+    search.or(create).or(get).or(update).or(delete)
+}
+
+/// REST API: 股票事件及提醒
+///
+/// GET events?code=&kind=&start_dt=&end_dt=查询，POST events录入，DELETE events/{id}删除
+/// GET events/alerts?days_before=&kind=&code=查询距今不超过指定交易日数的事件
+pub fn api_events(
+    db: DbPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let list = warp::path!("events")
+        .and(warp::get())
+        .and(warp::query::<events::EventQuery>())
+        .and(with_db(db.clone()))
+        .and_then(list_events);
+    let create = warp::path!("events")
+        .and(warp::post())
+        .and(warp::body::json::<StockEventForm>())
+        .and(with_db(db.clone()))
+        .and_then(create_event);
+    let delete = warp::path!("events" / i32)
+        .and(warp::delete())
+        .and(with_db(db.clone()))
+        .and_then(delete_event);
+    let alerts = warp::path!("events" / "alerts")
+        .and(warp::get())
+        .and(warp::query::<events::EventAlertRule>())
+        .and(with_db(db))
+        .and_then(list_event_alerts);
+    list.or(create).or(delete).or(alerts)
+}
+
+/// REST API: 走势周报
+///
+/// GET reports列出报告，GET reports/{id}查询报告，GET reports/{id}/content返回报告正文，
+/// GET reports/{id}/config返回生成报告时的配置
+pub fn api_reports(
+    db: DbPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let list = warp::path!("reports")
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(list_reports);
+    let get = warp::path!("reports" / i32)
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(get_report);
+    let content = warp::path!("reports" / i32 / "content")
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(get_report_content);
+    let config = warp::path!("reports" / i32 / "config")
+        .and(warp::get())
+        .and(with_db(db))
+        .and_then(get_report_config);
+    list.or(get).or(content).or(config)
+}
+
+/// REST API: 后台任务
+///
+/// POST jobs提交任务，GET jobs?status=&kind=&limit=列出任务，
+/// GET jobs/{id}查询状态及结果，POST jobs/{id}/cancel取消任务
+pub fn api_jobs(
+    db: DbPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let submit = warp::path!("jobs")
+        .and(warp::post())
+        .and(warp::body::json::<jobs::PublicJobSpec>())
+        .map(jobs::JobSpec::from)
+        .and(with_db(db.clone()))
+        .and_then(submit_job);
+    let list = warp::path!("jobs")
+        .and(warp::get())
+        .and(warp::query::<jobs::JobQuery>())
+        .and(with_db(db.clone()))
+        .and_then(list_jobs);
+    let get = warp::path!("jobs" / i32)
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(get_job);
+    let cancel = warp::path!("jobs" / i32 / "cancel")
+        .and(warp::post())
+        .and(with_db(db))
+        .and_then(|id, db| cancel_job(id, db, false));
+    submit.or(list).or(get).or(cancel)
+}
+
+/// REST API: 两个日期间的形态结构变化
+///
+/// GET stocks/{code}/structure-diff?tick=&start_dt=&date_a=&date_b=&stroke_cfg=
+pub fn api_structure_diff(
+    db: DbPool,
+    jq: JqdataPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("stocks" / String / "structure-diff")
+        .and(warp::get())
+        .and(warp::query::<structure_diff::DiffParam>())
+        .and(with_db(db))
+        .and(warp::any().map(move || jq.clone()))
+        .and_then(diff_structure)
+}
+
+/// REST API: 形态分布统计
+///
+/// GET stocks/{code}/shape-stats?tick=&start_dt=&end_dt=&stroke_cfg=&bins=
+pub fn api_shape_stats(
+    db: DbPool,
+    jq: JqdataPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("stocks" / String / "shape-stats")
+        .and(warp::get())
+        .and(warp::query::<shape_stats::StatsParam>())
+        .and(with_db(db))
+        .and(warp::any().map(move || jq.clone()))
+        .and_then(get_shape_stats)
+}
+
+/// REST API: 期指基差
+///
+/// GET metrics/{index_code}/basis?tick=&start_dt=&end_dt=&output=
+/// 未指定end_dt时取最后一个已完成的交易时刻
+pub fn api_metrics_basis(
+    db: DbPool,
+    jq: JqdataPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("metrics" / String / "basis")
+        .and(warp::get())
+        .and(warp::query::<BasisParam>())
+        .and(with_db(db))
+        .and(warp::any().map(move || jq.clone()))
+        .and(with_output())
+        .and_then(get_metrics_basis)
+}
+
+/// 成交量加权均价API
+///
+/// GET metrics/{code}/vwap?tick=&start_dt=&end_dt=&anchor=&output=，
+/// anchor为锚定均价的起始时刻，可选
+pub fn api_metrics_vwap(
+    db: DbPool,
+    jq: JqdataPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("metrics" / String / "vwap")
+        .and(warp::get())
+        .and(warp::query::<VwapParam>())
+        .and(with_db(db))
+        .and(warp::any().map(move || jq.clone()))
+        .and(with_output())
+        .and_then(get_metrics_vwap)
+}
+
+/// 图表快照分享API
+///
+/// POST share提交分析配置，计算后保存并返回快照
+/// GET share/{id}?output=&annotations=只读访问已保存的快照，不重新计算，
+/// 指定annotations时按该名称的规则集附带标注
+/// GET share/{id}/config返回快照生成时的配置
+/// GET/PUT/DELETE annotation-rules/{name}查询、保存及删除标注规则集，
+/// 查询结果即导出格式，可原样保存到其他名称下
+pub fn api_share(
+    db: DbPool,
+    jq: JqdataPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let create = warp::path!("share")
+        .and(warp::post())
+        .and(warp::body::json::<share::ShareState>())
+        .and(with_db(db.clone()))
+        .and(warp::any().map(move || jq.clone()))
+        .and_then(create_snapshot);
+    let get = warp::path!("share" / String)
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and(warp::query::<SnapshotParam>())
+        .and(with_output())
+        .and_then(get_snapshot);
+    let config = warp::path!("share" / String / "config")
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(get_snapshot_config);
+    let get_rules = warp::path!("annotation-rules" / String)
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(get_annotation_rules);
+    let save_rules = warp::path!("annotation-rules" / String)
+        .and(warp::put())
+        .and(warp::body::json::<annotation::AnnotationRules>())
+        .and(with_db(db.clone()))
+        .and_then(save_annotation_rules);
+    let delete_rules = warp::path!("annotation-rules" / String)
+        .and(warp::delete())
+        .and(with_db(db))
+        .and_then(delete_annotation_rules);
+    create
+        .or(get)
+        .or(config)
+        .or(get_rules)
+        .or(save_rules)
+        .or(delete_rules)
+}
+
+/// 北向资金API
+///
+/// GET metrics/northbound/flow?start_dt=&end_dt=&output=查询每日成交合计
+/// GET metrics/{code}/northbound?start_dt=&end_dt=&output=查询个股持股
+pub fn api_metrics_northbound(
+    db: DbPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let flow = warp::path!("metrics" / "northbound" / "flow")
+        .and(warp::get())
+        .and(warp::query::<NorthboundParam>())
+        .and(with_db(db.clone()))
+        .and(with_output())
+        .and_then(get_northbound_flow);
+    let holdings = warp::path!("metrics" / String / "northbound")
+        .and(warp::get())
+        .and(warp::query::<NorthboundParam>())
+        .and(with_db(db))
+        .and(with_output())
+        .and_then(get_northbound_holdings);
+    flow.or(holdings)
+}
+
+/// 基金API
+///
+/// GET funds/{code}/nav?start_dt=&end_dt=&output=查询每日净值
+/// GET funds/{code}/holdings?start_dt=&end_dt=查询报告期截止日在区间内的持仓
+/// GET funds/{code}/analysis?start_dt=&end_dt=&parting_cfg=&stroke_cfg=&trend_cfg=&output=
+/// 以复权净值作为日K线进行形态分析
+pub fn api_funds(
+    db: DbPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let nav = warp::path!("funds" / String / "nav")
+        .and(warp::get())
+        .and(warp::query::<NorthboundParam>())
+        .and(with_db(db.clone()))
+        .and(with_output())
+        .and_then(get_fund_nav);
+    let holdings = warp::path!("funds" / String / "holdings")
+        .and(warp::get())
+        .and(warp::query::<NorthboundParam>())
+        .and(with_db(db.clone()))
+        .and_then(get_fund_holdings);
+    let analysis = warp::path!("funds" / String / "analysis")
+        .and(warp::get())
+        .and(warp::query::<FundAnalysisParam>())
+        .and(with_db(db))
+        .and(with_output())
+        .and_then(analyze_fund);
+    nav.or(holdings).or(analysis)
+}
+
+/// MACD参数API
+///
+/// GET metrics/{code}/macd-cfg/{tick}查询生效的参数及来源
+/// PUT保存单只股票在该周期上的参数，DELETE删除后恢复周期默认值
+pub fn api_metrics_macd_cfg(
+    db: DbPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let get = warp::path!("metrics" / String / "macd-cfg" / Tick)
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(get_macd_cfg);
+    let save = warp::path!("metrics" / String / "macd-cfg" / Tick)
+        .and(warp::put())
+        .and(warp::body::json::<metrics::MacdCfg>())
+        .and(with_db(db.clone()))
+        .and_then(save_macd_cfg);
+    let delete = warp::path!("metrics" / String / "macd-cfg" / Tick)
+        .and(warp::delete())
+        .and(with_db(db))
+        .and_then(delete_macd_cfg);
+    get.or(save).or(delete)
+}
+
+// 自定义K线请求体的大小上限
+const OHLC_BODY_LIMIT: u64 = 4 * 1024 * 1024;
+
+/// POST analysis?tick=&parting_cfg=&stroke_cfg=&trend_cfg=&output=分析自定义K线
+///
+/// 请求体为JSON数组或CSV，不访问数据库和jqdata
+pub fn api_ohlc_analysis(
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("analysis")
+        .and(warp::post())
+        .and(warp::query::<ohlc::OhlcParam>())
+        .and(warp::body::content_length_limit(OHLC_BODY_LIMIT))
+        .and(warp::body::bytes())
+        .and(with_output())
+        .and_then(analyze_ohlc)
+}
+
+/// GET last-bar?codes=&tick=&output=批量查询最新K线及涨跌幅
+///
+/// codes以逗号分隔，tick默认为1d
+pub fn api_last_bar(
+    db: DbPool,
+    jq: JqdataPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("last-bar")
+        .and(warp::get())
+        .and(warp::query::<LastBarParam>())
+        .and(with_db(db))
+        .and(warp::any().map(move || jq.clone()))
+        .and(with_output())
+        .and_then(get_last_bars)
+}
+
+/// 综合评分API
+///
+/// GET scores?codes=&tick=&days=&user=&weights=&output=批量评分并按分值降序排列，
+/// codes以逗号分隔，tick默认为1d，weights形如trend:1,atrp:-5
+/// GET/PUT/DELETE score-weights/{user}查询、保存及删除用户的权重
+pub fn api_scores(
+    db: DbPool,
+    jq: JqdataPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scores = warp::path!("scores")
+        .and(warp::get())
+        .and(warp::query::<ScoresParam>())
+        .and(with_db(db.clone()))
+        .and(warp::any().map(move || jq.clone()))
+        .and(with_output())
+        .and_then(get_scores);
+    let get = warp::path!("score-weights" / String)
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(get_score_weights);
+    let save = warp::path!("score-weights" / String)
+        .and(warp::put())
+        .and(warp::body::json::<score::ScoreWeights>())
+        .and(with_db(db.clone()))
+        .and_then(save_score_weights);
+    let delete = warp::path!("score-weights" / String)
+        .and(warp::delete())
+        .and(with_db(db))
+        .and_then(delete_score_weights);
+    scores.or(get).or(save).or(delete)
+}
+
+/// 合成序列API
+///
+/// GET synthetics列出所有合成序列
+/// GET/PUT/DELETE synthetics/{code}查询、保存及删除合成序列，code须以.SYN结尾，
+/// 保存后可作为代码用于K线、形态及指标的查询
+pub fn api_synthetics(
+    db: DbPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let list = warp::path!("synthetics")
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(list_synthetics);
+    let get = warp::path!("synthetics" / String)
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(get_synthetic);
+    let save = warp::path!("synthetics" / String)
+        .and(warp::put())
+        .and(warp::body::json::<synthetic::SyntheticDef>())
+        .and(with_db(db.clone()))
+        .and_then(save_synthetic);
+    let delete = warp::path!("synthetics" / String)
+        .and(warp::delete())
+        .and(with_db(db))
+        .and_then(delete_synthetic);
+    list.or(get).or(save).or(delete)
+}
+
+/// GET heatmap?scheme=&dt=&output=查询市场热力图
+///
+/// scheme默认为sw_l1，未指定日期时返回最近生成的热力图
+pub fn api_heatmap(
+    db: DbPool,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("heatmap")
+        .and(warp::get())
+        .and(warp::query::<HeatmapParam>())
+        .and(with_db(db))
+        .and(with_output())
+        .and_then(get_heatmap)
+}
+
+/// 管理API: 查看、失效及清空价格缓存
+///
+/// GET admin/cache列出缓存条目
+/// POST admin/cache/invalidate?tick=&code=使单个股票周期失效
+/// POST admin/cache/flush清空全部缓存
+pub fn api_admin_cache(
+    db: DbPool,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let list = warp::path!("admin" / "cache")
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(list_cache_entries);
+    let invalidate = warp::path!("admin" / "cache" / "invalidate")
+        .and(warp::post())
+        .and(warp::query::<InvalidateCacheParam>())
+        .and(with_db(db.clone()))
+        .and_then(invalidate_cache);
+    let flush = warp::path!("admin" / "cache" / "flush")
+        .and(warp::post())
+        .and(with_db(db))
+        .and_then(flush_cache);
+    with_admin(admin_token).and(list.or(invalidate).or(flush))
+}
+
+/// 管理API: 预热价格及指标缓存
+///
+/// POST admin/warm提交预热任务，请求体为{"codes":[],"ticks":[],"days":365}，
+/// 返回的任务可通过jobs接口查询进度，POST admin/warm/{id}/cancel取消任务
+pub fn api_admin_warm(
+    db: DbPool,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let submit = warp::path!("admin" / "warm")
+        .and(warp::post())
+        .and(warp::body::json::<warm::WarmParam>())
+        .map(jobs::JobSpec::Warm)
+        .and(with_db(db.clone()))
+        .and_then(submit_job);
+    let cancel = warp::path!("admin" / "warm" / i32 / "cancel")
+        .and(warp::post())
+        .and(with_db(db))
+        .and_then(|id, db| cancel_job(id, db, true));
+    with_admin(admin_token).and(submit.or(cancel))
+}
+
+/// 管理API: 价格区间失效
+///
+/// POST admin/prices/invalidate?tick=&code=&start_dt=&end_dt=&reason=标记失效，
+/// 下次查询时重新下载并替换
+/// GET admin/prices/invalidations?code=列出失效及替换记录
+/// GET admin/prices/anomalies?code=&limit=列出被隔离的异常K线
+pub fn api_admin_prices(
+    db: DbPool,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let invalidate = warp::path!("admin" / "prices" / "invalidate")
+        .and(warp::post())
+        .and(warp::query::<InvalidatePricesParam>())
+        .and(with_db(db.clone()))
+        .and_then(invalidate_prices);
+    let list = warp::path!("admin" / "prices" / "invalidations")
+        .and(warp::get())
+        .and(warp::query::<ListInvalidationsParam>())
+        .and(with_db(db.clone()))
+        .and_then(list_invalidations);
+    let anomalies = warp::path!("admin" / "prices" / "anomalies")
+        .and(warp::get())
+        .and(warp::query::<ListAnomaliesParam>())
+        .and(with_db(db))
+        .and_then(list_anomalies);
+    with_admin(admin_token).and(invalidate.or(list).or(anomalies))
+}
+
+/// 管理API: 查看jqdata各账户使用情况
+pub fn api_admin_jqdata(
+    jq: JqdataPool,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    with_admin(admin_token)
+        .and(warp::path!("admin" / "jqdata"))
+        .and(warp::get())
+        .and(warp::any().map(move || jq.clone()))
+        .and_then(|jq: JqdataPool| async move {
+            let usage = jq.refresh_usage().await;
+            Ok::<_, warp::Rejection>(warp::reply::json(&usage))
+        })
+}
+
+/// GET admin/jqdata/http 共享HTTP客户端的连接配置及并发计数
+pub fn api_admin_jqdata_http(
+    admin_token: Option<String>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    with_admin(admin_token)
+        .and(warp::path!("admin" / "jqdata" / "http"))
+        .and(warp::get())
+        .map(|| warp::reply::json(&crate::http_stats()))
+}
+
+/// GET admin/jqdata/quota 配额预算及当日用量
+pub fn api_admin_jqdata_quota(
+    jq: JqdataPool,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    with_admin(admin_token)
+        .and(warp::path!("admin" / "jqdata" / "quota"))
+        .and(warp::get())
+        .map(move || warp::reply::json(&jq.quota_usage()))
+}
+
+/// GET admin/jqdata/requests 上游请求计数
+pub fn api_admin_jqdata_requests(
+    jq: JqdataPool,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    with_admin(admin_token)
+        .and(warp::path!("admin" / "jqdata" / "requests"))
+        .and(warp::get())
+        .map(move || warp::reply::json(&jq.request_stats()))
+}
+
+/// 管理API: 累加器诊断
+///
+/// POST admin/debug/accumulators按提交的分享配置重放，
+/// GET admin/debug/share/{id}/accumulators按已保存的快照重放，
+/// 返回分型、笔及线段累加器结束时的内部状态
+pub fn api_admin_debug(
+    db: DbPool,
+    jq: JqdataPool,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let jq2 = jq.clone();
+    let replay = warp::path!("admin" / "debug" / "accumulators")
+        .and(warp::post())
+        .and(warp::body::json::<share::ShareState>())
+        .and(with_db(db.clone()))
+        .and(warp::any().map(move || jq.clone()))
+        .and_then(inspect_accumulators);
+    let snapshot = warp::path!("admin" / "debug" / "share" / String / "accumulators")
+        .and(warp::get())
+        .and(with_db(db))
+        .and(warp::any().map(move || jq2.clone()))
+        .and_then(inspect_snapshot_accumulators);
+    with_admin(admin_token).and(replay.or(snapshot))
+}
+
+/// 管理API: 信号推送的Webhook
+///
+/// GET admin/webhooks列出Webhook，POST admin/webhooks新增
+/// DELETE admin/webhooks/{id}删除Webhook及其推送记录
+/// POST admin/webhooks/{id}/test立即发送测试推送
+/// GET admin/webhooks/{id}/deliveries?limit=查看最近的推送记录
+pub fn api_admin_webhooks(
+    db: DbPool,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let list = warp::path!("admin" / "webhooks")
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(list_webhooks);
+    let create = warp::path!("admin" / "webhooks")
+        .and(warp::post())
+        .and(warp::body::json::<WebhookForm>())
+        .and(with_db(db.clone()))
+        .and_then(create_webhook);
+    let delete = warp::path!("admin" / "webhooks" / i32)
+        .and(warp::delete())
+        .and(with_db(db.clone()))
+        .and_then(delete_webhook);
+    let test = warp::path!("admin" / "webhooks" / i32 / "test")
+        .and(warp::post())
+        .and(with_db(db.clone()))
+        .and_then(test_webhook);
+    let deliveries = warp::path!("admin" / "webhooks" / i32 / "deliveries")
+        .and(warp::get())
+        .and(warp::query::<ListDeliveriesParam>())
+        .and(with_db(db))
+        .and_then(list_webhook_deliveries);
+    with_admin(admin_token).and(list.or(create).or(delete).or(test).or(deliveries))
+}
+
+/// 校验管理令牌的公共过滤器
+fn with_admin(
+    admin_token: Option<String>,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    // 非管理路径不校验令牌，避免未授权的拒绝掩盖其他接口的错误
+    warp::path::peek()
+        .and(warp::header::optional::<String>(ADMIN_TOKEN_HEADER))
+        .and_then(move |path: warp::path::Peek, token: Option<String>| {
+            let expected = admin_token.clone();
+            async move {
+                if path.segments().next() != Some("admin") {
+                    return Err(warp::reject::not_found());
+                }
+                // 逐字节比较的耗时与令牌内容无关，避免按耗时猜测令牌
+                match (expected, token) {
+                    (Some(expected), Some(token))
+                        if bool::from(expected.as_bytes().ct_eq(token.as_bytes())) =>
+                    {
+                        Ok(())
+                    }
+                    _ => Err(warp::reject::custom(Error::simple(ErrorKind::Unauthorized))),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// 解析输出配置的公共过滤器
+///
+/// 从查询参数output解析，未指定时完整输出，
+/// 输出配置未指定语言时沿用查询参数lang或Accept-Language头部
+fn with_output() -> impl Filter<Extract = (OutputCfg,), Error = warp::Rejection> + Clone {
+    warp::query::<OutputParam>().and(with_lang()).and_then(
+        |param: OutputParam, lang: Option<Lang>| async move {
+            let mut cfg = output::parse_output_cfg(param.output.as_deref().unwrap_or_default())
+                .map_err(warp::reject::custom)?;
+            if cfg.lang.is_none() {
+                cfg.lang = lang;
+            }
+            Ok::<_, warp::Rejection>(cfg)
+        },
+    )
+}
+
+// 查询参数lang优先于Accept-Language头部，不支持的语言视为未指定
+fn with_lang() -> impl Filter<Extract = (Option<Lang>,), Error = std::convert::Infallible> + Clone {
+    warp::query::<LangParam>()
+        .or(warp::any().map(LangParam::default))
+        .unify()
+        .and(
+            warp::header::optional::<String>("accept-language")
+                .or(warp::any().map(|| None))
+                .unify(),
+        )
+        .map(|param: LangParam, accept: Option<String>| {
+            param
+                .lang
+                .and_then(|l| l.parse().ok())
+                .or_else(|| accept.as_deref().and_then(i18n::parse_accept_language))
+        })
+}
+
 /// 注入db的公共过滤器
 fn with_db(db: DbPool) -> impl Filter<Extract = (DbPool,), Error = Infallible> + Clone {
     warp::any().map(move || db.clone())
@@ -73,6 +780,17 @@ async fn search_keyword_stocks(
     }
 }
 
+async fn get_stock_detail(code: String, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    // 路径中的中文名称经过百分号编码
+    let code = percent_encoding::percent_decode_str(&code)
+        .decode_utf8_lossy()
+        .into_owned();
+    match stocks::get_stock_detail(db, code).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
 async fn list_prioritized_stocks(
     param: ListPrioritizedStocksParam,
     db: DbPool,
@@ -83,9 +801,8 @@ async fn list_prioritized_stocks(
                 .await
                 .map_err(warp::reject::custom)?;
             let codes = rs.iter().map(|s| s.code.clone()).collect();
-            let tick = "1d".to_owned();
-            let today = Local::today().naive_local();
-            let tts = LocalTradingTimestamps::new("1d").unwrap();
+            let today = Local::now().naive_local().date();
+            let tts = LocalTradingTimestamps::new(Tick::D1);
             let end_dt = if tts.contains_day(today) {
                 today
             } else {
@@ -96,10 +813,10 @@ async fn list_prioritized_stocks(
             for _ in 0..atrp_days {
                 start_dt = tts.prev_day(start_dt).unwrap();
             }
-            let data = ticks::query_db_multiple_prices(db, tick, codes, start_dt, end_dt)
-                .await
-                .map_err(warp::reject::custom)?;
-            let atrp_stats = metrics::multi_atrp_stats(&data);
+            let atrp_stats =
+                metrics::cached_multi_atrp_stats(&db, codes, atrp_days, start_dt, end_dt)
+                    .await
+                    .map_err(warp::reject::custom)?;
             let rst: Vec<_> = rs
                 .into_iter()
                 .map(|s| {
@@ -149,56 +866,934 @@ async fn list_prioritized_stocks(
 async fn list_choices(
     param: ListChoicesParam,
     db: DbPool,
+    output_cfg: OutputCfg,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let rule = match param.rule {
+        Some(ref s) => match confirm::parse_rule(s) {
+            Ok(rule) => Some(rule),
+            Err(err) => return Err(warp::reject::custom(err)),
+        },
+        None => None,
+    };
+    match choice::list_choices(
+        db,
+        param.days.unwrap_or(22),
+        param.limit.unwrap_or(10),
+        rule,
+    )
+    .await
+    {
+        Ok(data) => Ok(warp::reply::json(&output_cfg.to_value(&data))),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn search_notes(
+    param: notes::NoteQuery,
+    db: DbPool,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    match choice::list_choices(db, param.days.unwrap_or(22), param.limit.unwrap_or(10)).await {
+    match notes::search_notes(db, param).await {
         Ok(data) => Ok(warp::reply::json(&data)),
         Err(err) => Err(warp::reject::custom(err)),
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub struct HealthResponse {
-    pub status: String,
-    pub version: String,
+async fn create_note(form: NoteForm, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match notes::create_note(db, form).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
 }
 
-/// 股票关键字搜索参数
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SearchKeywordStocksParam {
-    pub keyword: String,
+async fn get_note(id: i32, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match notes::get_note(db, id).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GetStockPricesParam {
-    pub start_dt: NaiveDate,
-    pub end_dt: NaiveDate,
+async fn update_note(
+    id: i32,
+    form: NoteForm,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match notes::update_note(db, id, form).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ListPrioritizedStocksParam {
-    pub atrp_days: Option<usize>,
+async fn delete_note(id: i32, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match notes::delete_note(db, id).await {
+        Ok(()) => Ok(warp::reply::json(&id)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct PrioritizedStock {
-    pub code: String,
-    pub display_name: String,
+async fn submit_job(spec: jobs::JobSpec, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match jobs::submit_job(db, spec).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct PrioritizedStockAtrpStats {
-    pub code: String,
-    pub display_name: String,
-    pub msci: bool,
-    pub hs300: bool,
-    pub atrp_days: usize,
-    pub atrp_max: Option<BigDecimal>,
-    pub atrp_min: Option<BigDecimal>,
-    pub atrp_avg: Option<BigDecimal>,
+async fn list_jobs(param: jobs::JobQuery, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match jobs::list_jobs(db, param).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ListChoicesParam {
-    pub days: Option<usize>,
-    pub limit: Option<usize>,
+async fn get_job(id: i32, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match jobs::get_job(db, id).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn cancel_job(id: i32, db: DbPool, admin: bool) -> Result<impl warp::Reply, warp::Rejection> {
+    match jobs::cancel_job(db, id, admin).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn list_events(
+    param: events::EventQuery,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match events::list_events(db, param).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn create_event(
+    form: StockEventForm,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match events::create_event(db, form).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn delete_event(id: i32, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match events::delete_event(db, id).await {
+        Ok(()) => Ok(warp::reply::json(&id)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn list_event_alerts(
+    rule: events::EventAlertRule,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let today = Local::now().naive_local().date();
+    match events::event_alerts(db, rule, today).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn list_reports(db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match reports::list_reports(db).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn diff_structure(
+    code: String,
+    param: structure_diff::DiffParam,
+    db: DbPool,
+    jq: JqdataPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match structure_diff::diff_structure(&db, &jq, &code, param).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_shape_stats(
+    code: String,
+    param: shape_stats::StatsParam,
+    db: DbPool,
+    jq: JqdataPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match shape_stats::get_shape_stats(&db, &jq, &code, param).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_metrics_basis(
+    code: String,
+    param: BasisParam,
+    db: DbPool,
+    jq: JqdataPool,
+    output_cfg: OutputCfg,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let end_ts = match param.end_dt {
+        Some(dt) => dt.and_time(*tanglism_utils::AFTERNOON_END),
+        None => resolve_end_ts(None, param.tick, Local::now().naive_local())
+            .map_err(|e| warp::reject::custom(Error::from(e)))?,
+    };
+    let basic_cfg = BasicCfg {
+        tick: param.tick,
+        code,
+        start_ts: param.start_dt.and_time(NaiveTime::MIN),
+        end_ts,
+        intraday: false,
+    };
+    match metrics::basis::get_metrics_basis(&db, &jq, basic_cfg).await {
+        Ok(data) => Ok(warp::reply::json(&output_cfg.to_value(&data))),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_metrics_vwap(
+    code: String,
+    param: VwapParam,
+    db: DbPool,
+    jq: JqdataPool,
+    output_cfg: OutputCfg,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let end_ts = match param.end_dt {
+        Some(dt) => dt.and_time(*tanglism_utils::AFTERNOON_END),
+        None => resolve_end_ts(None, param.tick, Local::now().naive_local())
+            .map_err(|e| warp::reject::custom(Error::from(e)))?,
+    };
+    let anchor = match param.anchor {
+        Some(ref s) => Some(
+            parse_ts_from_str(s)
+                .map_err(|e| warp::reject::custom(Error::from(e)))?
+                .0,
+        ),
+        None => None,
+    };
+    let basic_cfg = BasicCfg {
+        tick: param.tick,
+        code,
+        start_ts: param.start_dt.and_time(NaiveTime::MIN),
+        end_ts,
+        intraday: false,
+    };
+    match metrics::vwap::get_metrics_vwap(&db, &jq, basic_cfg, anchor).await {
+        Ok(data) => Ok(warp::reply::json(&output_cfg.to_value(&data))),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn analyze_ohlc(
+    param: ohlc::OhlcParam,
+    body: bytes::Bytes,
+    output_cfg: OutputCfg,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // 分析为CPU密集型计算，在阻塞线程池中执行
+    let rst = tokio::task::spawn_blocking(move || {
+        let prices = ohlc::parse_bars(&body, param.tick)?;
+        ohlc::analyze_bars(&prices, &param)
+    })
+    .await
+    .map_err(Error::from)
+    .and_then(|r| r);
+    match rst {
+        Ok(data) => Ok(warp::reply::json(&output_cfg.to_value(&data))),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_fund_nav(
+    code: String,
+    param: NorthboundParam,
+    db: DbPool,
+    output_cfg: OutputCfg,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let end_dt = param
+        .end_dt
+        .unwrap_or_else(|| Local::now().naive_local().date());
+    match funds::get_fund_net_values(db, code, param.start_dt, end_dt).await {
+        Ok(data) => Ok(warp::reply::json(&output_cfg.to_value(&data))),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_fund_holdings(
+    code: String,
+    param: NorthboundParam,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let end_dt = param
+        .end_dt
+        .unwrap_or_else(|| Local::now().naive_local().date());
+    match funds::get_fund_portfolios(db, code, param.start_dt, end_dt).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn analyze_fund(
+    code: String,
+    param: FundAnalysisParam,
+    db: DbPool,
+    output_cfg: OutputCfg,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let end_dt = param
+        .end_dt
+        .unwrap_or_else(|| Local::now().naive_local().date());
+    let ohlc_param = ohlc::OhlcParam {
+        tick: Tick::D1,
+        parting_cfg: param.parting_cfg,
+        stroke_cfg: param.stroke_cfg,
+        trend_cfg: param.trend_cfg,
+    };
+    match funds::analyze_fund(db, code, param.start_dt, end_dt, ohlc_param).await {
+        Ok(data) => Ok(warp::reply::json(&output_cfg.to_value(&data))),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_macd_cfg(
+    code: String,
+    tick: Tick,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match metrics::macd::resolve_macd_cfg(&db, &code, tick, None).await {
+        Ok((cfg, source)) => Ok(warp::reply::json(&metrics::macd::EffectiveMacdCfg {
+            cfg,
+            source,
+        })),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn save_macd_cfg(
+    code: String,
+    tick: Tick,
+    cfg: metrics::MacdCfg,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match metrics::macd::save_macd_config(db, code, tick, cfg).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn delete_macd_cfg(
+    code: String,
+    tick: Tick,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match metrics::macd::delete_macd_config(db, code.clone(), tick).await {
+        Ok(()) => Ok(warp::reply::json(&code)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn create_snapshot(
+    state: share::ShareState,
+    db: DbPool,
+    jq: JqdataPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match share::create_snapshot(db, jq, state).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_snapshot(
+    id: String,
+    db: DbPool,
+    param: SnapshotParam,
+    output_cfg: OutputCfg,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let rst = match param.annotations {
+        Some(rules_name) => share::get_annotated_snapshot(db, id, rules_name).await,
+        None => share::get_snapshot(db, id).await,
+    };
+    match rst {
+        Ok(data) => Ok(warp::reply::json(&output_cfg.to_value(&data))),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn inspect_accumulators(
+    state: share::ShareState,
+    db: DbPool,
+    jq: JqdataPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match share::inspect_accumulators(db, jq, state).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn inspect_snapshot_accumulators(
+    id: String,
+    db: DbPool,
+    jq: JqdataPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match share::inspect_snapshot_accumulators(db, jq, id).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_annotation_rules(
+    name: String,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match annotation::get_annotation_rules(db, name).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn save_annotation_rules(
+    name: String,
+    rules: annotation::AnnotationRules,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match annotation::save_annotation_rules(db, name, rules).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn delete_annotation_rules(
+    name: String,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match annotation::delete_annotation_rules(db, name.clone()).await {
+        Ok(()) => Ok(warp::reply::json(&name)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_snapshot_config(id: String, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match share::get_snapshot_config(db, id).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_northbound_flow(
+    param: NorthboundParam,
+    db: DbPool,
+    output_cfg: OutputCfg,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let end_dt = param
+        .end_dt
+        .unwrap_or_else(|| Local::now().naive_local().date());
+    match metrics::northbound::get_northbound_flow(db, param.start_dt, end_dt).await {
+        Ok(data) => Ok(warp::reply::json(&output_cfg.to_value(&data))),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_northbound_holdings(
+    code: String,
+    param: NorthboundParam,
+    db: DbPool,
+    output_cfg: OutputCfg,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let end_dt = param
+        .end_dt
+        .unwrap_or_else(|| Local::now().naive_local().date());
+    match metrics::northbound::get_northbound_holdings(db, code, param.start_dt, end_dt).await {
+        Ok(data) => Ok(warp::reply::json(&output_cfg.to_value(&data))),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_last_bars(
+    param: LastBarParam,
+    db: DbPool,
+    jq: JqdataPool,
+    output_cfg: OutputCfg,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let codes: Vec<String> = param
+        .codes
+        .split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(str::to_owned)
+        .collect();
+    let tick = param.tick.unwrap_or(Tick::D1);
+    match last_bar::get_last_bars(&db, &jq, tick, &codes).await {
+        Ok(data) => Ok(warp::reply::json(&output_cfg.to_value(&data))),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_scores(
+    param: ScoresParam,
+    db: DbPool,
+    jq: JqdataPool,
+    output_cfg: OutputCfg,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let weights = match param.weights.as_deref().map(score::parse_weights) {
+        Some(Ok(weights)) => Some(weights),
+        Some(Err(err)) => return Err(warp::reject::custom(err)),
+        None => None,
+    };
+    let tick = param.tick.unwrap_or(Tick::D1);
+    let req = score::ScoreRequest {
+        codes: param
+            .codes
+            .split(',')
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        tick,
+        days: param.days.unwrap_or_else(|| score::default_days(tick)),
+        user: param.user,
+        weights,
+    };
+    match score::score_stocks(&db, &jq, req).await {
+        Ok(data) => Ok(warp::reply::json(&output_cfg.to_value(&data))),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_score_weights(user: String, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match score::get_score_weights(db, user).await {
+        Ok(Some(weights)) => Ok(warp::reply::json(&ScoreWeightsResponse {
+            weights,
+            source: score::WeightsSource::User,
+        })),
+        Ok(None) => Ok(warp::reply::json(&ScoreWeightsResponse {
+            weights: score::ScoreWeights::default(),
+            source: score::WeightsSource::Default,
+        })),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn save_score_weights(
+    user: String,
+    weights: score::ScoreWeights,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match score::save_score_weights(db, user, weights).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn delete_score_weights(
+    user: String,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match score::delete_score_weights(db, user.clone()).await {
+        Ok(()) => Ok(warp::reply::json(&user)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn list_synthetics(db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match synthetic::list_synthetics(db).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_synthetic(code: String, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match synthetic::get_synthetic(db, code).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn save_synthetic(
+    code: String,
+    def: synthetic::SyntheticDef,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match synthetic::save_synthetic(db, code, def).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn delete_synthetic(code: String, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match synthetic::delete_synthetic(db, code.clone()).await {
+        Ok(()) => Ok(warp::reply::json(&code)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_heatmap(
+    param: HeatmapParam,
+    db: DbPool,
+    output_cfg: OutputCfg,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let scheme = param
+        .scheme
+        .unwrap_or_else(|| heatmap::DEFAULT_SCHEME.to_owned());
+    match heatmap::get_heatmap(db, scheme, param.dt).await {
+        Ok(data) => Ok(warp::reply::json(&output_cfg.to_value(&data))),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_report(id: i32, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match reports::get_report(db, id).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_report_config(id: i32, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match reports::get_report_config(db, id).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn get_report_content(id: i32, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    let report = reports::get_report(db, id)
+        .await
+        .map_err(warp::reject::custom)?;
+    let content_type = report
+        .format
+        .parse::<reports::ReportFormat>()
+        .map(|f| f.content_type())
+        .unwrap_or("text/plain; charset=utf-8");
+    Ok(warp::reply::with_header(
+        report.content,
+        "content-type",
+        content_type,
+    ))
+}
+
+async fn list_cache_entries(db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match cache::list_cache_entries(db).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn invalidate_cache(
+    param: InvalidateCacheParam,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match cache::invalidate(db, param.tick, param.code).await {
+        Ok(deleted) => Ok(warp::reply::json(&InvalidateCacheResponse { deleted })),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn flush_cache(db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match cache::flush_all(db).await {
+        Ok(deleted) => Ok(warp::reply::json(&InvalidateCacheResponse { deleted })),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn invalidate_prices(
+    param: InvalidatePricesParam,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match invalidation::invalidate_range(
+        db,
+        param.tick,
+        param.code,
+        param.start_dt,
+        param.end_dt,
+        param.reason,
+    )
+    .await
+    {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn list_invalidations(
+    param: ListInvalidationsParam,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match invalidation::list_invalidations(db, param.code).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn list_anomalies(
+    param: ListAnomaliesParam,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match anomaly::list_anomalies(db, param.code, param.limit).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn list_webhooks(db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match webhooks::list_webhooks(db).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn create_webhook(
+    form: WebhookForm,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match webhooks::create_webhook(db, form).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn delete_webhook(id: i32, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match webhooks::delete_webhook(db, id).await {
+        Ok(()) => Ok(warp::reply::json(&id)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn test_webhook(id: i32, db: DbPool) -> Result<impl warp::Reply, warp::Rejection> {
+    match webhooks::test_fire(db, id).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+async fn list_webhook_deliveries(
+    id: i32,
+    param: ListDeliveriesParam,
+    db: DbPool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match webhooks::list_deliveries(db, id, param.limit.unwrap_or(50)).await {
+        Ok(data) => Ok(warp::reply::json(&data)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HealthResponse {
+    pub status: String,
+    pub version: String,
+}
+
+/// 股票关键字搜索参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchKeywordStocksParam {
+    pub keyword: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetStockPricesParam {
+    pub start_dt: NaiveDate,
+    pub end_dt: NaiveDate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListPrioritizedStocksParam {
+    pub atrp_days: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrioritizedStock {
+    pub code: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrioritizedStockAtrpStats {
+    pub code: String,
+    pub display_name: String,
+    pub msci: bool,
+    pub hs300: bool,
+    pub atrp_days: usize,
+    pub atrp_max: Option<BigDecimal>,
+    pub atrp_min: Option<BigDecimal>,
+    pub atrp_avg: Option<BigDecimal>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListChoicesParam {
+    pub days: Option<usize>,
+    pub limit: Option<usize>,
+    // JSON形式的确认规则，默认寻找一买
+    pub rule: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputParam {
+    // 输出配置，如precision:2,volume:false,debug:false
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LangParam {
+    pub lang: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BasisParam {
+    pub tick: Tick,
+    pub start_dt: NaiveDate,
+    pub end_dt: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VwapParam {
+    pub tick: Tick,
+    pub start_dt: NaiveDate,
+    pub end_dt: Option<NaiveDate>,
+    pub anchor: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SnapshotParam {
+    // 标注规则集的名称
+    pub annotations: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NorthboundParam {
+    pub start_dt: NaiveDate,
+    pub end_dt: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FundAnalysisParam {
+    pub start_dt: NaiveDate,
+    pub end_dt: Option<NaiveDate>,
+    pub parting_cfg: Option<String>,
+    pub stroke_cfg: Option<String>,
+    pub trend_cfg: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LastBarParam {
+    pub codes: String,
+    pub tick: Option<Tick>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoresParam {
+    pub codes: String,
+    pub tick: Option<Tick>,
+    pub days: Option<i64>,
+    pub user: Option<String>,
+    pub weights: Option<String>,
+}
+
+/// 生效的评分权重
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoreWeightsResponse {
+    #[serde(flatten)]
+    pub weights: score::ScoreWeights,
+    pub source: score::WeightsSource,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeatmapParam {
+    pub scheme: Option<String>,
+    pub dt: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvalidateCacheParam {
+    pub tick: Tick,
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvalidateCacheResponse {
+    pub deleted: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InvalidatePricesParam {
+    pub tick: Tick,
+    pub code: String,
+    pub start_dt: NaiveDate,
+    pub end_dt: NaiveDate,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListInvalidationsParam {
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListAnomaliesParam {
+    pub code: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListDeliveriesParam {
+    pub limit: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::pg::PgConnection;
+    use diesel::r2d2::{ConnectionManager, Pool};
+
+    #[tokio::test]
+    async fn test_api_versioned_and_legacy() {
+        // 健康检查不访问数据库，连接池无需可用
+        let manager = ConnectionManager::<PgConnection>::new("postgres://localhost/test");
+        let db = Pool::builder().build_unchecked(manager);
+        let api = api_route(db, JqdataPool::from_clients(Vec::new()), None);
+        for path in &["/api/v1/health", "/api/health"] {
+            let resp = warp::test::request().path(path).reply(&api).await;
+            assert_eq!(200, resp.status());
+            assert_eq!(API_VERSION, resp.headers()[API_VERSION_HEADER]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_api_legacy_limited() {
+        // 旧路径不暴露新增接口，路径不匹配即返回，无需访问数据库
+        let manager = ConnectionManager::<PgConnection>::new("postgres://localhost/test");
+        let db = Pool::builder().build_unchecked(manager);
+        let api = api_route(db, JqdataPool::from_clients(Vec::new()), None);
+        let resp = warp::test::request().path("/api/jobs").reply(&api).await;
+        assert_eq!(404, resp.status());
+
+        let db = match crate::test_db_pool() {
+            Some(db) => db,
+            None => return,
+        };
+        let api = api_route(db, JqdataPool::from_clients(Vec::new()), None);
+        for path in &["/api/v1/jobs", "/api/keyword-stocks?keyword=600000"] {
+            let resp = warp::test::request().path(path).reply(&api).await;
+            assert_eq!(200, resp.status(), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_api_admin_requires_token() {
+        let manager = ConnectionManager::<PgConnection>::new("postgres://localhost/test");
+        let db = Pool::builder().build_unchecked(manager);
+        // 未配置令牌时拒绝
+        // 未处理的自定义拒绝返回500，路径不匹配则为404
+        let api = api_admin_cache(db.clone(), None);
+        let resp = warp::test::request()
+            .path("/admin/cache")
+            .header(ADMIN_TOKEN_HEADER, "secret")
+            .reply(&api)
+            .await;
+        assert_eq!(500, resp.status());
+        // 令牌不匹配时拒绝
+        let api = api_admin_cache(db, Some("secret".into()));
+        let resp = warp::test::request()
+            .path("/admin/cache")
+            .header(ADMIN_TOKEN_HEADER, "wrong")
+            .reply(&api)
+            .await;
+        assert_eq!(500, resp.status());
+    }
 }
@@ -0,0 +1,66 @@
+//! 路由注册表
+//!
+//! 所有REST API在此统一注册，路径均相对于版本前缀，
+//! 新增接口只需在registered中追加。
+//! 不带版本的旧路径仅保留最初的接口，见legacy。
+
+use super::*;
+
+/// 当前API版本
+pub const API_VERSION: &str = "v1";
+
+/// 响应中携带API版本的头部
+pub const API_VERSION_HEADER: &str = "x-api-version";
+
+/// 管理API携带令牌的头部
+pub const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
+/// 已注册的API，不含/api及版本前缀
+pub fn registered(
+    db: DbPool,
+    jq: JqdataPool,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    api_get_health()
+        .or(api_market_clock())
+        .or(api_search_keyword_stocks(db.clone()))
+        .or(api_stock_detail(db.clone()))
+        .or(api_list_prioritized_stocks(db.clone()))
+        .or(api_list_choices(db.clone()))
+        .or(api_notes(db.clone()))
+        .or(api_events(db.clone()))
+        .or(api_reports(db.clone()))
+        .or(api_jobs(db.clone()))
+        .or(api_structure_diff(db.clone(), jq.clone()))
+        .or(api_shape_stats(db.clone(), jq.clone()))
+        .or(api_metrics_basis(db.clone(), jq.clone()))
+        .or(api_metrics_vwap(db.clone(), jq.clone()))
+        .or(api_metrics_northbound(db.clone()))
+        .or(api_funds(db.clone()))
+        .or(api_metrics_macd_cfg(db.clone()))
+        .or(api_heatmap(db.clone()))
+        .or(api_last_bar(db.clone(), jq.clone()))
+        .or(api_scores(db.clone(), jq.clone()))
+        .or(api_synthetics(db.clone()))
+        .or(api_ohlc_analysis())
+        .or(api_share(db.clone(), jq.clone()))
+        .or(api_admin_cache(db.clone(), admin_token.clone()))
+        .or(api_admin_prices(db.clone(), admin_token.clone()))
+        .or(api_admin_warm(db.clone(), admin_token.clone()))
+        .or(api_admin_webhooks(db.clone(), admin_token.clone()))
+        .or(api_admin_debug(db, jq.clone(), admin_token.clone()))
+        .or(api_admin_jqdata(jq.clone(), admin_token.clone()))
+        .or(api_admin_jqdata_http(admin_token.clone()))
+        .or(api_admin_jqdata_quota(jq.clone(), admin_token.clone()))
+        .or(api_admin_jqdata_requests(jq, admin_token))
+}
+
+/// 兼容旧路径/api的接口，仅限引入版本前已有的接口，新增接口不应追加
+pub fn legacy(
+    db: DbPool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    api_get_health()
+        .or(api_search_keyword_stocks(db.clone()))
+        .or(api_list_prioritized_stocks(db.clone()))
+        .or(api_list_choices(db))
+}
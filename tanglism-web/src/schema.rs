@@ -1,3 +1,150 @@
+table! {
+    annotation_rules (name) {
+        name -> Varchar,
+        rules -> Text,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    fund_holdings (code, period_end, symbol) {
+        code -> Varchar,
+        period_end -> Date,
+        symbol -> Varchar,
+        pub_date -> Date,
+        name -> Varchar,
+        rank -> Int4,
+        shares -> Numeric,
+        market_cap -> Numeric,
+        proportion -> Numeric,
+    }
+}
+
+table! {
+    fund_net_values (code, dt) {
+        code -> Varchar,
+        dt -> Date,
+        net_value -> Numeric,
+        sum_value -> Numeric,
+        refactor_net_value -> Numeric,
+    }
+}
+
+table! {
+    industry_stocks (scheme, industry, code) {
+        scheme -> Varchar,
+        industry -> Varchar,
+        code -> Varchar,
+        industry_name -> Varchar,
+        synced_dt -> Date,
+    }
+}
+
+table! {
+    jobs (id) {
+        id -> Int4,
+        kind -> Varchar,
+        params -> Text,
+        status -> Varchar,
+        progress -> Int4,
+        message -> Text,
+        result -> Nullable<Text>,
+        created_at -> Timestamp,
+        started_at -> Nullable<Timestamp>,
+        finished_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    macd_configs (code, tick) {
+        code -> Varchar,
+        tick -> Varchar,
+        fast_ema_period -> Int4,
+        slow_ema_period -> Int4,
+        dea_period -> Int4,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    market_heatmaps (dt, scheme) {
+        dt -> Date,
+        scheme -> Varchar,
+        content -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    metric_caches (code, tick, metric, params, data_end_ts) {
+        code -> Varchar,
+        tick -> Varchar,
+        metric -> Varchar,
+        params -> Varchar,
+        data_end_ts -> Timestamp,
+        value -> Text,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    northbound_flows (dt, link_id) {
+        dt -> Date,
+        link_id -> Int4,
+        buy_amount -> Numeric,
+        sell_amount -> Numeric,
+    }
+}
+
+table! {
+    northbound_holdings (code, dt) {
+        code -> Varchar,
+        dt -> Date,
+        share_number -> Numeric,
+        share_ratio -> Numeric,
+    }
+}
+
+table! {
+    notes (id) {
+        id -> Int4,
+        dt -> Date,
+        code -> Nullable<Varchar>,
+        signal_id -> Nullable<Varchar>,
+        chart -> Nullable<Varchar>,
+        tags -> Array<Text>,
+        content -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    reports (id) {
+        id -> Int4,
+        week_start -> Date,
+        week_end -> Date,
+        format -> Varchar,
+        codes -> Array<Text>,
+        content -> Text,
+        created_at -> Timestamp,
+        config -> Text,
+    }
+}
+
+table! {
+    score_weights (user_name) {
+        user_name -> Varchar,
+        trend -> Numeric,
+        center -> Numeric,
+        divergence -> Numeric,
+        atrp -> Numeric,
+        high_distance -> Numeric,
+        updated_at -> Timestamp,
+    }
+}
+
 table! {
     securities (code) {
         code -> Varchar,
@@ -11,6 +158,28 @@ table! {
     }
 }
 
+table! {
+    security_names (code, valid_from) {
+        code -> Varchar,
+        display_name -> Varchar,
+        name -> Varchar,
+        valid_from -> Date,
+        valid_to -> Date,
+    }
+}
+
+table! {
+    snapshots (id) {
+        id -> Varchar,
+        code -> Varchar,
+        tick -> Varchar,
+        state -> Text,
+        bundle -> Text,
+        created_at -> Timestamp,
+        config -> Text,
+    }
+}
+
 table! {
     stock_daily_prices (code, dt) {
         code -> Varchar,
@@ -24,6 +193,50 @@ table! {
     }
 }
 
+table! {
+    stock_events (id) {
+        id -> Int4,
+        code -> Varchar,
+        event_dt -> Date,
+        kind -> Varchar,
+        title -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    stock_price_anomalies (id) {
+        id -> Int4,
+        tick -> Varchar,
+        code -> Varchar,
+        ts -> Timestamp,
+        kind -> Varchar,
+        detail -> Varchar,
+        open -> Numeric,
+        close -> Numeric,
+        high -> Numeric,
+        low -> Numeric,
+        volume -> Numeric,
+        amount -> Numeric,
+        detected_at -> Timestamp,
+    }
+}
+
+table! {
+    stock_price_invalidations (id) {
+        id -> Int4,
+        tick -> Varchar,
+        code -> Varchar,
+        start_dt -> Date,
+        end_dt -> Date,
+        reason -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        replaced_at -> Nullable<Timestamp>,
+        replaced_rows -> Nullable<Int4>,
+        inserted_rows -> Nullable<Int4>,
+    }
+}
+
 table! {
     stock_price_ticks (tick, code) {
         tick -> Varchar,
@@ -47,16 +260,78 @@ table! {
     }
 }
 
+table! {
+    synthetic_series (code) {
+        code -> Varchar,
+        op -> Varchar,
+        left_code -> Varchar,
+        right_code -> Varchar,
+        updated_at -> Timestamp,
+    }
+}
+
 table! {
     trade_days (dt) {
         dt -> Date,
     }
 }
 
+table! {
+    webhook_deliveries (id) {
+        id -> Int4,
+        webhook_id -> Int4,
+        event -> Varchar,
+        payload -> Text,
+        status -> Varchar,
+        attempts -> Int4,
+        response_status -> Nullable<Int4>,
+        last_error -> Text,
+        created_at -> Timestamp,
+        next_attempt_at -> Timestamp,
+        delivered_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    webhooks (id) {
+        id -> Int4,
+        name -> Varchar,
+        url -> Text,
+        secret -> Varchar,
+        events -> Array<Text>,
+        alert_days_before -> Int4,
+        enabled -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+joinable!(webhook_deliveries -> webhooks (webhook_id));
+
 allow_tables_to_appear_in_same_query!(
+    annotation_rules,
+    fund_holdings,
+    fund_net_values,
+    industry_stocks,
+    jobs,
+    macd_configs,
+    market_heatmaps,
+    metric_caches,
+    northbound_flows,
+    northbound_holdings,
+    notes,
+    reports,
+    score_weights,
     securities,
+    security_names,
+    snapshots,
     stock_daily_prices,
+    stock_events,
+    stock_price_anomalies,
+    stock_price_invalidations,
     stock_price_ticks,
     stock_tick_prices,
+    synthetic_series,
     trade_days,
+    webhook_deliveries,
+    webhooks,
 );
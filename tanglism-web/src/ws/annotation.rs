@@ -0,0 +1,345 @@
+//! 图表标注规则
+//!
+//! 规则将快照中识别出的事件映射为标注的文字及样式，前端按标注渲染，无需自行维护映射。
+//! 规则集以名称保存，名称可以是用户或关注列表，查询返回的JSON即导出格式，可直接保存到其他名称下。
+
+use super::session::Data;
+use crate::models::AnnotationRuleSet;
+use crate::{DbPool, Error, ErrorKind, Result};
+use bigdecimal::BigDecimal;
+use chrono::{Local, NaiveDateTime, NaiveTime};
+use diesel::prelude::*;
+use serde_derive::*;
+
+// 单个规则集最多的规则数
+const MAX_RULES: usize = 50;
+const MAX_LABEL_LEN: usize = 64;
+// 标注文字中替换为事件序号的占位符
+const ORDINAL_PLACEHOLDER: &str = "{n}";
+
+/// 可标注的事件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    // 笔结束于顶分型或底分型
+    StrokeTop,
+    StrokeBottom,
+    // 线段结束于顶分型或底分型
+    SegmentTop,
+    SegmentBottom,
+    // 中枢，标注于起点及共享最高点
+    Center,
+    // 公司事件，可按类型过滤
+    StockEvent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Position {
+    Above,
+    Below,
+}
+
+/// 标注样式，未指定位置时底分型标注于下方，其余标注于上方
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnnotationStyle {
+    pub shape: String,
+    pub color: String,
+    #[serde(default)]
+    pub position: Option<Position>,
+}
+
+/// 标注规则，every为n时仅标注每第n个事件
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnnotationRule {
+    pub on: EventKind,
+    // 公司事件的类型，如earnings
+    #[serde(default)]
+    pub kind: Option<String>,
+    #[serde(default = "default_every")]
+    pub every: usize,
+    // {n}替换为事件的序号
+    pub label: String,
+    pub style: AnnotationStyle,
+}
+
+fn default_every() -> usize {
+    1
+}
+
+/// 规则集
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AnnotationRules {
+    pub rules: Vec<AnnotationRule>,
+}
+
+impl AnnotationRules {
+    pub fn validate(&self) -> Result<()> {
+        if self.rules.len() > MAX_RULES {
+            return Err(Error::custom(
+                ErrorKind::BadRequest,
+                format!(
+                    "too many annotation rules: {} > {}",
+                    self.rules.len(),
+                    MAX_RULES
+                ),
+            ));
+        }
+        for (i, r) in self.rules.iter().enumerate() {
+            if r.every == 0 {
+                return Err(Error::custom(
+                    ErrorKind::BadRequest,
+                    format!("every of annotation rule {} must be positive", i),
+                ));
+            }
+            if r.label.chars().count() > MAX_LABEL_LEN {
+                return Err(Error::custom(
+                    ErrorKind::BadRequest,
+                    format!(
+                        "label of annotation rule {} exceeds {} chars",
+                        i, MAX_LABEL_LEN
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 图表标注，rule为规则的下标
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub ts: NaiveDateTime,
+    pub price: Option<BigDecimal>,
+    pub label: String,
+    pub shape: String,
+    pub color: String,
+    pub position: Position,
+    pub rule: usize,
+}
+
+// 快照中识别出的事件
+struct Event<'a> {
+    kind: EventKind,
+    ts: NaiveDateTime,
+    price: Option<&'a BigDecimal>,
+    // 公司事件的类型
+    event_kind: Option<&'a str>,
+}
+
+fn events(data: &[Data]) -> Vec<Event<'_>> {
+    let mut evs = Vec::new();
+    for d in data {
+        match d {
+            Data::Strokes(sks) => evs.extend(sks.iter().map(|sk| Event {
+                kind: if sk.end_pt.top {
+                    EventKind::StrokeTop
+                } else {
+                    EventKind::StrokeBottom
+                },
+                ts: sk.end_pt.extremum_ts,
+                price: Some(&sk.end_pt.extremum_price),
+                event_kind: None,
+            })),
+            Data::Segments(sgs) => evs.extend(sgs.iter().map(|sg| Event {
+                kind: if sg.end_pt.top {
+                    EventKind::SegmentTop
+                } else {
+                    EventKind::SegmentBottom
+                },
+                ts: sg.end_pt.extremum_ts,
+                price: Some(&sg.end_pt.extremum_price),
+                event_kind: None,
+            })),
+            Data::Centers(cts) => {
+                evs.extend(cts.iter().filter_map(|ce| ce.center()).map(|c| Event {
+                    kind: EventKind::Center,
+                    ts: c.start.ts,
+                    price: Some(&c.shared_high.value),
+                    event_kind: None,
+                }))
+            }
+            // 公司事件标注于当日收盘
+            Data::Events(ses) => evs.extend(ses.iter().map(|se| {
+                Event {
+                    kind: EventKind::StockEvent,
+                    ts: se
+                        .event_dt
+                        .and_time(NaiveTime::from_hms_opt(15, 0, 0).unwrap()),
+                    price: None,
+                    event_kind: Some(se.kind.as_str()),
+                }
+            })),
+            _ => (),
+        }
+    }
+    evs.sort_by_key(|e| e.ts);
+    evs
+}
+
+/// 按规则生成快照数据的标注，按时刻排序
+pub fn annotate(rules: &AnnotationRules, data: &[Data]) -> Vec<Annotation> {
+    let evs = events(data);
+    let mut rst = Vec::new();
+    for (idx, rule) in rules.rules.iter().enumerate() {
+        let matched = evs.iter().filter(|e| {
+            e.kind == rule.on && rule.kind.as_deref().is_none_or(|k| e.event_kind == Some(k))
+        });
+        for (i, e) in matched.enumerate() {
+            let n = i + 1;
+            if n % rule.every != 0 {
+                continue;
+            }
+            let position = rule.style.position.unwrap_or(match e.kind {
+                EventKind::StrokeBottom | EventKind::SegmentBottom => Position::Below,
+                _ => Position::Above,
+            });
+            rst.push(Annotation {
+                ts: e.ts,
+                price: e.price.cloned(),
+                label: rule.label.replace(ORDINAL_PLACEHOLDER, &n.to_string()),
+                shape: rule.style.shape.clone(),
+                color: rule.style.color.clone(),
+                position,
+                rule: idx,
+            });
+        }
+    }
+    // 稳定排序，同一时刻按规则顺序
+    rst.sort_by_key(|a| a.ts);
+    rst
+}
+
+/// 解析JSON形式的快照数据并生成标注
+pub fn annotate_bundle(
+    rules: &AnnotationRules,
+    bundle: &serde_json::Value,
+) -> Result<Vec<Annotation>> {
+    let data: Vec<Data> = serde_json::from_value(bundle.clone()).map_err(|e| {
+        Error::custom(
+            ErrorKind::InternalServerError,
+            format!("corrupted snapshot: {}", e),
+        )
+    })?;
+    Ok(annotate(rules, &data))
+}
+
+/// 查询规则集
+pub async fn get_annotation_rules(pool: DbPool, input_name: String) -> Result<AnnotationRules> {
+    let not_found = format!("annotation rules {} not found", input_name);
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::annotation_rules::dsl::*;
+        let conn = pool.get()?;
+        annotation_rules
+            .find(input_name)
+            .first::<AnnotationRuleSet>(&conn)
+            .optional()
+            .map_err(Error::from)
+    })
+    .await??;
+    let data = data.ok_or_else(|| Error::custom(ErrorKind::NotFound, not_found))?;
+    serde_json::from_str(&data.rules).map_err(|e| {
+        Error::custom(
+            ErrorKind::InternalServerError,
+            format!("corrupted annotation rules {}: {}", data.name, e),
+        )
+    })
+}
+
+/// 保存规则集，同名的规则集被覆盖
+pub async fn save_annotation_rules(
+    pool: DbPool,
+    input_name: String,
+    input_rules: AnnotationRules,
+) -> Result<AnnotationRules> {
+    input_rules.validate()?;
+    let record = AnnotationRuleSet {
+        name: input_name,
+        rules: serde_json::to_string(&input_rules)
+            .map_err(|e| Error::custom(ErrorKind::InternalServerError, e.to_string()))?,
+        updated_at: Local::now().naive_local(),
+    };
+    tokio::task::spawn_blocking(move || {
+        use crate::schema::annotation_rules::dsl::*;
+        let conn = pool.get()?;
+        diesel::insert_into(annotation_rules)
+            .values(&record)
+            .on_conflict(name)
+            .do_update()
+            .set((rules.eq(&record.rules), updated_at.eq(record.updated_at)))
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    Ok(input_rules)
+}
+
+/// 删除规则集
+pub async fn delete_annotation_rules(pool: DbPool, input_name: String) -> Result<()> {
+    let not_found = format!("annotation rules {} not found", input_name);
+    let n = tokio::task::spawn_blocking(move || {
+        use crate::schema::annotation_rules::dsl::*;
+        let conn = pool.get()?;
+        diesel::delete(annotation_rules.find(input_name))
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    if n == 0 {
+        return Err(Error::custom(ErrorKind::NotFound, not_found));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tanglism_morph::new_pt;
+    use tanglism_morph::{Parting, Stroke};
+
+    #[test]
+    fn test_annotate_every_nth() {
+        let rules: AnnotationRules = serde_json::from_str(
+            r#"{"rules":[
+                {"on":"stroke_bottom","every":2,"label":"B{n}","style":{"shape":"arrow_up","color":"red"}},
+                {"on":"stroke_top","label":"T","style":{"shape":"dot","color":"green","position":"below"}}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(rules.validate().is_ok());
+        // 笔依次结束于顶、底、顶、底、顶
+        let pts: Vec<Parting> = ["10:00", "10:30", "11:00", "11:30", "13:30", "14:00"]
+            .iter()
+            .enumerate()
+            .map(|(i, hm)| {
+                let top = i % 2 == 1;
+                new_pt(
+                    &format!("2020-02-03 {}", hm),
+                    if top { 11 } else { 10 },
+                    top,
+                )
+            })
+            .collect();
+        let sks: Vec<Stroke> = pts
+            .windows(2)
+            .map(|w| Stroke {
+                start_pt: w[0].clone(),
+                end_pt: w[1].clone(),
+            })
+            .collect();
+        let anns = annotate(&rules, &[Data::Strokes(sks)]);
+        let labels: Vec<_> = anns.iter().map(|a| a.label.as_str()).collect();
+        assert_eq!(vec!["T", "T", "B2", "T"], labels);
+        assert_eq!(Position::Below, anns[0].position);
+        assert_eq!(Position::Below, anns[2].position);
+        assert_eq!(0, anns[2].rule);
+
+        let invalid = AnnotationRules {
+            rules: vec![AnnotationRule {
+                every: 0,
+                ..rules.rules[0].clone()
+            }],
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,200 @@
+//! 会话缓存的依赖图
+//!
+//! 每层缓存记录其输入的指纹，即所依赖配置及上游层指纹的哈希。
+//! 配置变化后仅指纹不一致的层重新计算，未受影响的层继续复用。
+
+use serde_derive::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+
+/// 分析层
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Clone, Copy, PartialOrd, Ord)]
+pub enum Layer {
+    KLines,
+    Partings,
+    Strokes,
+    Segments,
+    // 次级别走势使用的1分钟K线
+    SubKLines,
+    // 1分钟K线上的笔及线段
+    SubStrokes,
+    SubTrends,
+    Centers,
+    Trends,
+    // 保持请求中的层名不变
+    #[serde(rename = "MACD")]
+    Macd,
+    // 背驰，比较中枢前后走势的MACD面积
+    Divergences,
+    // 期指基差
+    Basis,
+    // 成交量加权均价，锚点取自中枢时由指纹包含锚点
+    Vwap,
+}
+
+impl Layer {
+    /// 直接依赖的上游层
+    pub fn upstreams(self) -> &'static [Layer] {
+        match self {
+            Layer::KLines | Layer::SubKLines | Layer::Macd | Layer::Basis | Layer::Vwap => &[],
+            Layer::Partings => &[Layer::KLines],
+            Layer::Strokes => &[Layer::Partings],
+            Layer::Segments => &[Layer::Strokes],
+            Layer::SubStrokes => &[Layer::SubKLines],
+            Layer::SubTrends => &[Layer::SubStrokes],
+            Layer::Centers => &[Layer::SubTrends],
+            Layer::Trends => &[Layer::Centers],
+            Layer::Divergences => &[Layer::Centers, Layer::Macd],
+        }
+    }
+
+    /// 该层及所有下游层
+    pub fn with_downstreams(self) -> BTreeSet<Layer> {
+        let mut rst = BTreeSet::new();
+        rst.insert(self);
+        for down in Layer::ALL.iter() {
+            if down.upstreams().contains(&self) {
+                rst.extend(down.with_downstreams());
+            }
+        }
+        rst
+    }
+
+    pub const ALL: [Layer; 13] = [
+        Layer::KLines,
+        Layer::Partings,
+        Layer::Strokes,
+        Layer::Segments,
+        Layer::SubKLines,
+        Layer::SubStrokes,
+        Layer::SubTrends,
+        Layer::Centers,
+        Layer::Trends,
+        Layer::Macd,
+        Layer::Divergences,
+        Layer::Basis,
+        Layer::Vwap,
+    ];
+}
+
+/// 计算输入的指纹
+pub fn fingerprint<T: Hash + ?Sized>(input: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    input.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// 各层的指纹及本次请求中重新计算的层
+#[derive(Debug, Default)]
+pub struct LayerGraph {
+    fingerprints: HashMap<Layer, u64>,
+    recomputed: BTreeSet<Layer>,
+}
+
+impl LayerGraph {
+    /// 缓存是否仍然有效
+    pub fn fresh(&self, layer: Layer, fp: u64) -> bool {
+        self.fingerprints.get(&layer) == Some(&fp)
+    }
+
+    /// 上游层的指纹，下游层将其计入自身指纹
+    ///
+    /// 上游层未计算时返回None
+    pub fn upstream(&self, layer: Layer) -> Option<u64> {
+        let mut fps = Vec::with_capacity(layer.upstreams().len());
+        for up in layer.upstreams() {
+            fps.push(*self.fingerprints.get(up)?);
+        }
+        Some(fingerprint(&fps))
+    }
+
+    /// 该层当前的指纹，未计算时返回None
+    pub fn fingerprint_of(&self, layer: Layer) -> Option<u64> {
+        self.fingerprints.get(&layer).copied()
+    }
+
+    /// 记录重新计算后的指纹
+    pub fn update(&mut self, layer: Layer, fp: u64) {
+        self.fingerprints.insert(layer, fp);
+        self.recomputed.insert(layer);
+    }
+
+    /// 使该层及所有下游层失效
+    pub fn invalidate(&mut self, layer: Layer) {
+        for l in layer.with_downstreams() {
+            self.fingerprints.remove(&l);
+        }
+    }
+
+    /// 取出重新计算的层
+    pub fn take_recomputed(&mut self) -> Vec<Layer> {
+        std::mem::take(&mut self.recomputed).into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layer_graph_invalidate() {
+        let mut g = LayerGraph::default();
+        for layer in Layer::ALL.iter() {
+            g.update(*layer, 1);
+        }
+        g.invalidate(Layer::Strokes);
+        assert!(g.fresh(Layer::KLines, 1));
+        assert!(g.fresh(Layer::Partings, 1));
+        assert!(!g.fresh(Layer::Strokes, 1));
+        assert!(!g.fresh(Layer::Segments, 1));
+        assert!(g.fresh(Layer::SubTrends, 1));
+        assert!(g.upstream(Layer::Segments).is_none());
+        // MACD变化时背驰随之失效
+        g.invalidate(Layer::Macd);
+        assert!(!g.fresh(Layer::Divergences, 1));
+        assert!(g.fresh(Layer::Centers, 1));
+        assert_eq!(Layer::ALL.len(), g.take_recomputed().len());
+        assert!(g.take_recomputed().is_empty());
+    }
+
+    #[test]
+    fn test_layer_graph_fingerprint_of() {
+        let mut g = LayerGraph::default();
+        assert_eq!(None, g.fingerprint_of(Layer::Segments));
+        g.update(Layer::Segments, 7);
+        assert_eq!(Some(7), g.fingerprint_of(Layer::Segments));
+        g.invalidate(Layer::Segments);
+        assert_eq!(None, g.fingerprint_of(Layer::Segments));
+    }
+
+    #[test]
+    fn test_layer_with_downstreams() {
+        let downs: Vec<_> = Layer::SubKLines.with_downstreams().into_iter().collect();
+        assert_eq!(
+            vec![
+                Layer::SubKLines,
+                Layer::SubStrokes,
+                Layer::SubTrends,
+                Layer::Centers,
+                Layer::Trends,
+                Layer::Divergences
+            ],
+            downs
+        );
+        assert_eq!(
+            vec![Layer::Macd, Layer::Divergences],
+            Layer::Macd
+                .with_downstreams()
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_layer_serde() {
+        assert_eq!(r#""MACD""#, serde_json::to_string(&Layer::Macd).unwrap());
+        let layer: Layer = serde_json::from_str(r#""MACD""#).unwrap();
+        assert_eq!(Layer::Macd, layer);
+    }
+}
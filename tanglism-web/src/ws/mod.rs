@@ -1,27 +1,42 @@
+pub mod annotation;
+mod layers;
 mod session;
+pub mod share;
+mod throttle;
 
-use crate::DbPool;
+pub use throttle::ThrottleConfig;
+
+use crate::handlers::jobs;
+use crate::{DbPool, JqdataPool};
+use futures::future::{self, Either};
 use futures::{FutureExt, StreamExt};
-use jqdata::JqdataClient;
-use tokio::sync::mpsc;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Notify};
 use warp::filters::BoxedFilter;
 use warp::reply::Reply;
 use warp::ws::{Message, WebSocket};
 use warp::Filter;
 
-pub fn ws_filter(jq: JqdataClient, db: DbPool) -> BoxedFilter<(impl Reply,)> {
-    let deps = warp::any().map(move || (jq.clone(), db.clone())).boxed();
+pub fn ws_filter(
+    jq: JqdataPool,
+    db: DbPool,
+    throttle: ThrottleConfig,
+) -> BoxedFilter<(impl Reply,)> {
+    let deps = warp::any()
+        .map(move || (jq.clone(), db.clone(), throttle.clone()))
+        .boxed();
     warp::path("ws")
         .and(warp::ws())
         .and(deps)
-        .map(|ws: warp::ws::Ws, (jq, db)| {
-            ws.on_upgrade(move |socket| start_session(socket, jq, db))
+        .map(|ws: warp::ws::Ws, (jq, db, throttle)| {
+            ws.on_upgrade(move |socket| start_session(socket, jq, db, throttle))
         })
         .boxed()
 }
 
-async fn start_session(socket: WebSocket, jq: JqdataClient, db: DbPool) {
+async fn start_session(socket: WebSocket, jq: JqdataPool, db: DbPool, throttle: ThrottleConfig) {
     let mut sess = session::Session::new(jq, db);
+    sess.set_throttle(throttle);
     log::debug!("Session started");
 
     let (user_tx, mut user_rx) = socket.split();
@@ -34,32 +49,81 @@ async fn start_session(socket: WebSocket, jq: JqdataClient, db: DbPool) {
         }
     }));
 
-    // 接收用户消息并处理
-    while let Some(r) = user_rx.next().await {
-        let msg = match r {
-            Ok(msg) => msg,
-            Err(e) => {
-                log::warn!("websocket receive error: {}", e);
-                break;
+    // 接收用户消息，连接断开时通知正在处理的查询
+    let closed = Arc::new(Notify::new());
+    let (msg_tx, mut msg_rx) = mpsc::unbounded_channel();
+    {
+        let closed = closed.clone();
+        tokio::task::spawn(async move {
+            while let Some(r) = user_rx.next().await {
+                match r {
+                    Ok(msg) => {
+                        if msg_tx.send(msg).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("websocket receive error: {}", e);
+                        break;
+                    }
+                }
+            }
+            closed.notify();
+        });
+    }
+
+    // 处理用户消息，其间推送已订阅任务的进度
+    let mut job_rx = jobs::subscribe();
+    loop {
+        let msg = match future::select(Box::pin(msg_rx.recv()), Box::pin(job_rx.recv())).await {
+            Either::Left((Some(msg), _)) => msg,
+            Either::Left((None, _)) => break,
+            Either::Right((Ok(event), _)) => {
+                if let Some(resp) = sess.job_envelope(event) {
+                    let text_resp = sess.encode(&resp);
+                    if let Err(e) = tx.send(Ok(Message::text(text_resp))) {
+                        log::warn!("internal send error: {}", e);
+                    }
+                }
+                continue;
+            }
+            // 处理过慢时丢弃部分进度事件
+            Either::Right((Err(e), _)) => {
+                log::debug!("job event lagged: {:?}", e);
+                continue;
             }
         };
         // 具体逻辑
         if let Ok(s) = msg.to_str() {
             log::debug!("received text message: {}", s);
-            match serde_json::from_str(s) {
+            match serde_json::from_str::<session::RequestEnvelope>(s) {
                 Ok(req) => {
-                    // 得到响应列表
-                    let resp = sess.respond(req).await;
-                    let text_resp = serde_json::to_string(&resp).unwrap_or_default();
-                    if let Err(e) = tx.send(Ok(Message::text(text_resp))) {
-                        log::warn!("internal send error: {}", e);
+                    // 得到响应列表，客户端断开时丢弃未完成的查询，其中的上游请求随之取消
+                    let resp = match future::select(
+                        Box::pin(sess.respond_envelope(req)),
+                        Box::pin(closed.notified()),
+                    )
+                    .await
+                    {
+                        Either::Left((resp, _)) => resp,
+                        Either::Right(_) => {
+                            log::debug!("Session closed, pending query cancelled");
+                            break;
+                        }
+                    };
+                    // K线分块先于查询响应发送
+                    for chunk in sess.take_chunks().into_iter().chain(Some(resp)) {
+                        let text_resp = sess.encode(&chunk);
+                        if let Err(e) = tx.send(Ok(Message::text(text_resp))) {
+                            log::warn!("internal send error: {}", e);
+                        }
                     }
                 }
                 Err(e) => {
                     log::warn!("serde_json error: {}", e);
                     // also send to client
-                    let text_resp = serde_json::to_string(&session::Response::Error(e.to_string()))
-                        .unwrap_or_default();
+                    let resp = sess.error_envelope(e.to_string());
+                    let text_resp = sess.encode(&resp);
                     if let Err(e) = tx.send(Ok(Message::text(text_resp))) {
                         log::warn!("internal send error: {}", e);
                     }
@@ -69,8 +133,8 @@ async fn start_session(socket: WebSocket, jq: JqdataClient, db: DbPool) {
             let err_msg = "Non-text user message not supported";
             log::warn!("{}", err_msg);
             // also send to client
-            let text_resp = serde_json::to_string(&session::Response::Error(err_msg.to_owned()))
-                .unwrap_or_default();
+            let resp = sess.error_envelope(err_msg.to_owned());
+            let text_resp = sess.encode(&resp);
             if let Err(e) = tx.send(Ok(Message::text(text_resp))) {
                 log::warn!("internal send error: {}", e);
             }
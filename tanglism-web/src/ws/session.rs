@@ -1,31 +1,178 @@
+use super::layers::{fingerprint, Layer, LayerGraph};
+use super::throttle::{Throttle, ThrottleConfig};
+use crate::handlers::jobs::JobEvent;
+use crate::handlers::metrics::basis::{self, BasisMetric};
+use crate::handlers::metrics::vwap::{self, VwapAnchor, VwapMetric};
 use crate::handlers::metrics::{self, MacdMetric};
-use crate::handlers::stock_prices::{self, ticks};
-use crate::handlers::tanglism;
+use crate::handlers::output::{self, OutputCfg};
+use crate::handlers::stock_prices::{self, epoch, ticks};
+use crate::handlers::{events, i18n, stocks, tanglism};
+use crate::models::StockEvent;
 use crate::BasicCfg;
-use crate::{DbPool, Error, ErrorKind, Result};
-use jqdata::JqdataClient;
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result, ResultExt};
+use chrono::{Local, NaiveDateTime};
+use futures::future::{AbortHandle, Abortable, Aborted};
 use serde_derive::*;
-use std::collections::BTreeSet;
-use tanglism_morph::{CenterElement, Segment, Stroke, StrokeConfig, SubTrend, Trend, TrendConfig};
-use tanglism_utils::parse_ts_from_str;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::time::Instant;
+use tanglism_morph::{
+    CenterElement, Divergence, Parting, PartingConfig, ReplicaMessage, ReplicaPublisher, Segment,
+    ShapeWarning, Stroke, StrokeConfig, SubTrend, Trace, Trend, TrendConfig,
+};
+use tanglism_utils::{
+    parse_ts_from_str, resolve_end_ts, LocalTradingTimestamps, Tick, TradingTimestamps,
+};
+use tokio::task::JoinHandle;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 #[serde(tag = "type", content = "data")]
 pub enum Request {
     BasicCfg {
-        tick: String,
+        tick: Tick,
         code: String,
         start_dt: String,
+        // 为空或"latest"时取最后一个已完成的交易时刻
+        #[serde(default)]
         end_dt: String,
+        // 日内模式，各交易日独立分析，隔夜跳空不延续前一日的笔及线段
+        #[serde(default)]
+        intraday: bool,
     },
+    PartingCfg(String),
     StrokeCfg(String),
     MetricsCfg(String),
     TrendCfg(String),
+    // 窗口起点前额外参与计算的K线数，仅输出与窗口相交的笔及线段
+    WarmupCfg(usize),
+    // 历史回看时刻，仅使用该时刻及之前的数据进行分析，空字符串表示取消
+    AsOf(String),
+    // 输出配置，如precision:2,volume:false,debug:false,lang:en，空字符串表示完整输出
+    OutputCfg(String),
+    // K线分块推送的每块K线数，K线数超过时分块发送，0表示不分块
+    StreamCfg(usize),
+    // 将分析窗口向左或向右平移指定K线数，仅抓取新露出的K线
+    Pan {
+        direction: PanDirection,
+        bars: usize,
+    },
     Query {
         refresh: bool,
         objects: Vec<QueryObject>,
         requires: Vec<QueryObject>,
+        // 输出明细，如线段包含的笔下标区间，默认不输出以减小数据量
+        #[serde(default)]
+        detail: bool,
+        // 校验已计算的笔、线段及中枢，结果以警告返回
+        #[serde(default)]
+        validate: bool,
+        // 按对象覆盖配置，结果与会话配置的结果一同返回，用于配置对比
+        #[serde(default)]
+        overrides: Vec<QueryOverride>,
     },
+    // 客户端发现推送序号不连续时请求重新同步，
+    // 复制消息将在下次查询时重新发送快照
+    Resync,
+    // 强制指定层及其下游层在下次查询时重新计算
+    Recompute(Vec<Layer>),
+    // 订阅后台任务的进度推送，替换此前的订阅，空列表表示取消
+    WatchJobs(Vec<i32>),
+    // 释放全部缓存并清除分析配置，用于客户端关闭图表，输出配置及任务订阅保留
+    Reset,
+    // 释放查询对象的缓存及其下游层，下次查询时重新计算
+    DropLayer(QueryObject),
+}
+
+/// 查询对象的配置覆盖
+///
+/// 覆盖的配置为完整的配置字符串，格式与对应的配置请求相同，未指定的配置沿用会话配置。
+/// 分型及笔配置适用于笔及线段，走势配置适用于次级别走势、中枢及走势
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Clone)]
+pub struct QueryOverride {
+    // 客户端指定的标识，随结果返回
+    pub id: String,
+    pub object: QueryObject,
+    #[serde(default)]
+    pub parting_cfg: Option<String>,
+    #[serde(default)]
+    pub stroke_cfg: Option<String>,
+    #[serde(default)]
+    pub trend_cfg: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+pub enum PanDirection {
+    Left,
+    Right,
+}
+
+/// 请求信封
+///
+/// 在请求上附加关联ID，字段与请求平铺，不带ID时与原协议兼容
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct RequestEnvelope {
+    // 相同ID的重传请求不重复处理，直接返回原响应
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub request: Request,
+}
+
+/// 响应信封
+///
+/// 每个响应附带单调递增的序号，客户端据此检测遗漏的推送
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResponseEnvelope {
+    pub seq: u64,
+    // 对应请求的关联ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub response: Response,
+}
+
+// 查询期间价格数据持续变化时的最大计算次数
+const MAX_EPOCH_ATTEMPTS: usize = 3;
+
+// 保留最近处理的请求数，用于重传去重
+const MAX_HANDLED_REQUESTS: usize = 32;
+
+/// 响应序号分配及请求去重
+#[derive(Debug, Default)]
+pub struct Sequencer {
+    seq: u64,
+    handled: VecDeque<(String, ResponseEnvelope)>,
+}
+
+impl Sequencer {
+    /// 查找已处理请求的响应
+    pub fn handled(&self, id: &str) -> Option<&ResponseEnvelope> {
+        self.handled
+            .iter()
+            .find(|(k, _)| k == id)
+            .map(|(_, resp)| resp)
+    }
+
+    /// 分配序号并记录响应
+    pub fn wrap(&mut self, id: Option<String>, response: Response) -> ResponseEnvelope {
+        let resp = self.wrap_transient(id, response);
+        if let Some(ref id) = resp.id {
+            if self.handled.len() >= MAX_HANDLED_REQUESTS {
+                self.handled.pop_front();
+            }
+            self.handled.push_back((id.clone(), resp.clone()));
+        }
+        resp
+    }
+
+    /// 分配序号但不记录，重传时重新处理
+    pub fn wrap_transient(&mut self, id: Option<String>, response: Response) -> ResponseEnvelope {
+        self.seq += 1;
+        ResponseEnvelope {
+            seq: self.seq,
+            id,
+            response,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -34,6 +181,12 @@ pub enum Response {
     Ack,
     Error(String),
     Data(Vec<Data>),
+    // 查询过于频繁，客户端应在指定毫秒数后重试
+    Throttled { retry_after_ms: u64 },
+    // 已订阅的后台任务的进度
+    Job(JobEvent),
+    // 调试模式下释放缓存的结果，字节数为缓存元素大小的估算值，非调试模式返回Ack
+    Freed { layers: Vec<Layer>, bytes: usize },
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -53,9 +206,59 @@ pub enum Data {
     TrendsNoChange,
     MACD(MacdMetric),
     MACDNoChange,
+    Divergences(Vec<Divergence>),
+    DivergencesNoChange,
+    Basis(BasisMetric),
+    BasisNoChange,
+    Vwap(VwapMetric),
+    VwapNoChange,
+    StrokeTraces(Vec<Trace>),
+    SegmentTraces(Vec<Trace>),
+    StrokeReplica(Vec<ReplicaMessage<Stroke>>),
+    Events(Vec<StockEvent>),
+    SegmentReplica(Vec<ReplicaMessage<Segment>>),
+    // 本次查询重新计算的层
+    Recomputed(Vec<Layer>),
+    // 形态校验警告
+    Warnings(Vec<ShapeWarning>),
+    // 预热信息，设置了预热K线数时返回
+    Warmup(Warmup),
+    // 次级别数据缺失，次级别走势由本级别的笔及线段合成，精度降低，内容为缺失原因
+    SubTrendsDegraded(String),
+    // 配置覆盖的查询结果，id为覆盖项的标识
+    Overridden {
+        id: String,
+        data: Box<Data>,
+    },
+    // 分块推送的K线，index从0开始，progress为已发送的百分比
+    KLinesChunk {
+        index: usize,
+        total: usize,
+        progress: usize,
+        data: Vec<ticks::StockPrice>,
+    },
+    // 分块推送的结束标记，在查询响应中代替K线，其后为各层的结果
+    KLinesEnd {
+        bars: usize,
+        chunks: usize,
+    },
+    // 本次查询所用价格数据的版本，各层均由该版本的数据计算
+    Epoch(u64),
+}
+
+/// 预热信息
+///
+/// 输出的笔及线段中，开头strokes笔及segments个线段的真实起点早于窗口起点
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Warmup {
+    pub start_ts: NaiveDateTime,
+    // 实际取得的预热K线数
+    pub bars: usize,
+    pub strokes: usize,
+    pub segments: usize,
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Clone, PartialOrd, Ord)]
 pub enum QueryObject {
     // 笔
     Strokes,
@@ -69,53 +272,275 @@ pub enum QueryObject {
     Trends,
     // MACD指标
     MACD,
+    // 趋势背驰及盘整背驰
+    Divergences,
+    // 期指基差，仅适用于有股指期货的指数
+    Basis,
+    // 日内及锚定成交量加权均价，锚点由指标配置vwap_anchor指定
+    Vwap,
+    // 笔的决策日志
+    StrokeTraces,
+    // 线段的决策日志
+    SegmentTraces,
+    // 笔的复制消息，首次为快照，其后为带序号的变更
+    StrokeReplica,
+    // 线段的复制消息
+    SegmentReplica,
+    // 窗口起始日之后的事件，用于图表标记
+    Events,
+}
+
+impl QueryObject {
+    // 该对象独占的最上游缓存层，释放时连同下游层一起释放。
+    // 决策日志、复制消息及事件不单独缓存，返回None
+    fn cache_layer(&self) -> Option<Layer> {
+        match self {
+            QueryObject::Strokes => Some(Layer::Partings),
+            QueryObject::Segments => Some(Layer::Segments),
+            QueryObject::SubTrends => Some(Layer::SubKLines),
+            QueryObject::Centers => Some(Layer::Centers),
+            QueryObject::Trends => Some(Layer::Trends),
+            QueryObject::MACD => Some(Layer::Macd),
+            QueryObject::Divergences => Some(Layer::Divergences),
+            QueryObject::Basis => Some(Layer::Basis),
+            QueryObject::Vwap => Some(Layer::Vwap),
+            QueryObject::StrokeTraces
+            | QueryObject::SegmentTraces
+            | QueryObject::StrokeReplica
+            | QueryObject::SegmentReplica
+            | QueryObject::Events => None,
+        }
+    }
+}
+
+/// 配置覆盖的缓存槽，与会话缓存相互独立
+struct OverrideSlot {
+    // 输入数据及覆盖后配置的指纹
+    fp: u64,
+    data: Data,
+}
+
+/// 次级别K线的后台预取
+///
+/// 释放时取消尚未完成的预取
+struct SubPrefetch {
+    // 与SubKLines层相同的指纹
+    fp: u64,
+    abort: AbortHandle,
+    handle: JoinHandle<std::result::Result<Result<Vec<ticks::StockPrice>>, Aborted>>,
+}
+
+impl SubPrefetch {
+    // 等待预取结果，失败或被取消时返回None
+    async fn join(&mut self) -> Option<Vec<ticks::StockPrice>> {
+        match (&mut self.handle).await {
+            Ok(Ok(Ok(prices))) => Some(prices),
+            Ok(Ok(Err(e))) => {
+                log::warn!("sub-level prefetch failed: {}", e);
+                None
+            }
+            Ok(Err(Aborted)) => None,
+            Err(e) => {
+                log::warn!("sub-level prefetch task error: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for SubPrefetch {
+    fn drop(&mut self) {
+        self.abort.abort();
+    }
 }
 
 /// 会话中的临时数据
 pub struct Session {
-    jq: JqdataClient,
+    jq: JqdataPool,
     db: DbPool,
     // 缓存配置
     basic_cfg: Option<BasicCfg>,
+    // 未设置时使用默认分型配置
+    parting_cfg: PartingConfig,
     stroke_cfg: Option<StrokeConfig>,
     trend_cfg: Option<TrendConfig>,
     metrics_cfg: Option<String>,
-    // 缓存指标
+    warmup: usize,
+    as_of: Option<NaiveDateTime>,
+    output_cfg: OutputCfg,
+    // K线分块推送的每块K线数，0表示不分块
+    stream_bars: usize,
+    // 待发送的K线分块，先于对应的查询响应发送
+    pending_chunks: Vec<ResponseEnvelope>,
+    // K线被平移修改，下次查询需返回
+    ks_updated: bool,
+    // 缓存的K线所用价格数据的版本
+    epoch: u64,
+    // 缓存指标，有效性由layers中的指纹判断
     ks: Option<Vec<ticks::StockPrice>>,
+    // 窗口起点前的预热K线，仅参与分型、笔及线段的计算
+    warmup_ks: Option<Vec<ticks::StockPrice>>,
+    partings: Option<Vec<Parting>>,
     strokes: Option<Vec<Stroke>>,
     segments: Option<Vec<Segment>>,
+    sub_ks: Option<Vec<ticks::StockPrice>>,
+    // 设置基础配置后预取的次级别K线
+    sub_prefetch: Option<SubPrefetch>,
+    sub_strokes: Option<(Vec<Stroke>, Vec<Segment>)>,
+    // 次级别数据缺失的原因，此时次级别走势由本级别合成
+    sub_degraded: Option<String>,
     subtrends: Option<Vec<SubTrend>>,
     centers: Option<Vec<CenterElement>>,
     trends: Option<Vec<Trend>>,
     // DIF/DEA/MACD
     macd: Option<metrics::MacdMetric>,
+    divergences: Option<Vec<Divergence>>,
+    basis: Option<BasisMetric>,
+    vwap: Option<VwapMetric>,
+    // 配置覆盖的结果，以对象及覆盖配置的指纹为键，仅保留最近一次查询使用的槽
+    override_slots: HashMap<u64, OverrideSlot>,
+    layers: LayerGraph,
+    // 复制发布器，不随缓存清除，以便配置变化时仅发送变更
+    stroke_publisher: ReplicaPublisher<Stroke>,
+    segment_publisher: ReplicaPublisher<Segment>,
+    sequencer: Sequencer,
+    throttle: Throttle<Request>,
+    // 订阅进度的后台任务
+    watched_jobs: HashSet<i32>,
 }
 
 impl Session {
     /// 创建一个新会话
-    pub fn new(jq: JqdataClient, db: DbPool) -> Self {
+    pub fn new(jq: JqdataPool, db: DbPool) -> Self {
         Session {
             jq,
             db,
             basic_cfg: None,
+            parting_cfg: PartingConfig::default(),
             stroke_cfg: None,
             trend_cfg: None,
             metrics_cfg: None,
+            warmup: 0,
+            as_of: None,
+            output_cfg: OutputCfg::default(),
+            stream_bars: 0,
+            pending_chunks: Vec::new(),
+            ks_updated: false,
+            epoch: 0,
             ks: None,
+            warmup_ks: None,
+            partings: None,
             strokes: None,
             segments: None,
+            sub_ks: None,
+            sub_prefetch: None,
+            sub_strokes: None,
+            sub_degraded: None,
             subtrends: None,
             centers: None,
             trends: None,
             macd: None,
+            divergences: None,
+            basis: None,
+            vwap: None,
+            override_slots: HashMap::new(),
+            layers: LayerGraph::default(),
+            stroke_publisher: ReplicaPublisher::new(),
+            segment_publisher: ReplicaPublisher::new(),
+            sequencer: Sequencer::default(),
+            throttle: Throttle::new(ThrottleConfig::default()),
+            watched_jobs: HashSet::new(),
+        }
+    }
+
+    /// 处理带信封的请求，重传的请求返回原响应
+    pub async fn respond_envelope(&mut self, env: RequestEnvelope) -> ResponseEnvelope {
+        if let Some(ref id) = env.id {
+            if let Some(resp) = self.sequencer.handled(id) {
+                log::debug!("duplicate request {} ignored", id);
+                return resp.clone();
+            }
+        }
+        if let Request::Query { .. } = env.request {
+            if let Err(wait) = self.throttle.acquire(env.request.clone(), Instant::now()) {
+                log::debug!("query throttled, retry after {:?}", wait);
+                let retry_after_ms = wait.as_millis() as u64 + 1;
+                return self
+                    .sequencer
+                    .wrap_transient(env.id, Response::Throttled { retry_after_ms });
+            }
         }
+        let resp = self.respond(env.request).await;
+        let resp = self.stream_klines(env.id.as_ref(), resp);
+        self.sequencer.wrap(env.id, resp)
+    }
+
+    /// 取出待发送的K线分块
+    ///
+    /// 分块的序号小于对应的查询响应，重传的请求仅返回查询响应
+    pub fn take_chunks(&mut self) -> Vec<ResponseEnvelope> {
+        std::mem::take(&mut self.pending_chunks)
+    }
+
+    // 按配置将查询响应中的K线拆分为分块，响应中的K线替换为结束标记
+    fn stream_klines(&mut self, id: Option<&String>, resp: Response) -> Response {
+        let stream_bars = self.stream_bars;
+        let mut dataset = match resp {
+            Response::Data(dataset) if stream_bars > 0 => dataset,
+            resp => return resp,
+        };
+        let found = dataset.iter_mut().enumerate().find_map(|(i, d)| match d {
+            Data::KLines(ks) if ks.len() > stream_bars => Some((i, std::mem::take(ks))),
+            _ => None,
+        });
+        if let Some((i, ks)) = found {
+            let bars = ks.len();
+            let chunks = chunk_klines(ks, stream_bars);
+            dataset[i] = Data::KLinesEnd {
+                bars,
+                chunks: chunks.len(),
+            };
+            for chunk in chunks {
+                let env = self
+                    .sequencer
+                    .wrap_transient(id.cloned(), Response::Data(vec![chunk]));
+                self.pending_chunks.push(env);
+            }
+        }
+        Response::Data(dataset)
+    }
+
+    /// 修改查询限流配置
+    pub fn set_throttle(&mut self, cfg: ThrottleConfig) {
+        self.throttle = Throttle::new(cfg);
+    }
+
+    /// 按输出配置序列化响应，推送至客户端的响应均经过此处
+    pub fn encode(&self, resp: &ResponseEnvelope) -> String {
+        self.output_cfg.to_value(resp).to_string()
+    }
+
+    /// 包装已订阅任务的进度推送，未订阅的任务返回None
+    pub fn job_envelope(&mut self, event: JobEvent) -> Option<ResponseEnvelope> {
+        if !self.watched_jobs.contains(&event.id) {
+            return None;
+        }
+        if event.status.is_finished() {
+            self.watched_jobs.remove(&event.id);
+        }
+        Some(self.sequencer.wrap_transient(None, Response::Job(event)))
+    }
+
+    /// 包装无法解析的请求对应的错误响应
+    pub fn error_envelope(&mut self, err: String) -> ResponseEnvelope {
+        self.sequencer.wrap(None, Response::Error(err))
     }
 
     /// 处理请求并返回响应
     pub async fn respond(&mut self, req: Request) -> Response {
         match self.do_respond(req).await {
             Ok(resp) => resp,
-            Err(e) => Response::Error(e.to_string()),
+            Err(e) => Response::Error(i18n::error_message(&e, self.output_cfg.lang)),
         }
     }
 
@@ -126,14 +551,25 @@ impl Session {
                 code,
                 start_dt,
                 end_dt,
+                intraday,
             } => {
+                if intraday && tick == Tick::D1 {
+                    return Err(Error::custom(
+                        ErrorKind::BadRequest,
+                        "intraday mode requires intraday tick".to_owned(),
+                    ));
+                }
                 let (start_ts, _) = parse_ts_from_str(&start_dt)?;
-                let (end_ts, _) = parse_ts_from_str(&end_dt)?;
+                let now = Local::now().naive_local();
+                let end_ts = resolve_end_ts(Some(&end_dt), tick, now)?;
+                // 支持名称或部分代码
+                let code = stocks::resolve_stock(self.db.clone(), code).await?;
                 let new_cfg = BasicCfg {
                     tick,
                     code,
                     start_ts,
                     end_ts,
+                    intraday,
                 };
                 let diff = self
                     .basic_cfg
@@ -143,13 +579,18 @@ impl Session {
                 if diff {
                     log::debug!("replace basic cfg with new one: {:?}", new_cfg);
                     self.basic_cfg.replace(new_cfg);
-                    self.clear_k_cache();
-                    self.clear_tanglism_cache();
-                    self.clear_metrics_cache();
+                    self.schedule_sub_prefetch();
+                }
+            }
+            Request::PartingCfg(cfg) => {
+                let new_cfg = tanglism::parse_parting_cfg(&cfg).context("invalid parting cfg")?;
+                if self.parting_cfg != new_cfg {
+                    log::debug!("replace parting cfg with new one: {:?}", new_cfg);
+                    self.parting_cfg = new_cfg;
                 }
             }
             Request::StrokeCfg(cfg) => {
-                let new_cfg = tanglism::parse_stroke_cfg(&cfg)?;
+                let new_cfg = tanglism::parse_stroke_cfg(&cfg).context("invalid stroke cfg")?;
                 let diff = self
                     .stroke_cfg
                     .as_ref()
@@ -158,11 +599,10 @@ impl Session {
                 if diff {
                     log::debug!("replace stroke cfg with new one: {:?}", new_cfg);
                     self.stroke_cfg.replace(new_cfg);
-                    self.clear_tanglism_cache();
                 }
             }
             Request::TrendCfg(cfg) => {
-                let new_cfg = tanglism::parse_trend_cfg(&cfg)?;
+                let new_cfg = tanglism::parse_trend_cfg(&cfg).context("invalid trend cfg")?;
                 let diff = self
                     .trend_cfg
                     .as_ref()
@@ -171,7 +611,6 @@ impl Session {
                 if diff {
                     log::debug!("replace trend cfg with new one: {:?}", new_cfg);
                     self.trend_cfg.replace(new_cfg);
-                    self.clear_trend_cache();
                 }
             }
             Request::MetricsCfg(cfg) => {
@@ -183,15 +622,94 @@ impl Session {
                 if diff {
                     log::debug!("replace metrics cfg with new one: {:?}", cfg);
                     self.metrics_cfg.replace(cfg);
-                    self.clear_metrics_cache();
                 }
             }
+            Request::WarmupCfg(bars) => {
+                if self.warmup != bars {
+                    log::debug!("replace warmup bars with new one: {}", bars);
+                    self.warmup = bars;
+                }
+            }
+            Request::AsOf(as_of) => {
+                let new_as_of = if as_of.is_empty() {
+                    None
+                } else {
+                    let (ts, is_day) = parse_ts_from_str(&as_of)?;
+                    // 仅指定日期时，视为当日收盘后
+                    if is_day {
+                        Some(ts + chrono::Duration::seconds(24 * 3600 - 1))
+                    } else {
+                        Some(ts)
+                    }
+                };
+                if self.as_of != new_as_of {
+                    log::debug!("replace as-of with new one: {:?}", new_as_of);
+                    self.as_of = new_as_of;
+                }
+            }
+            Request::OutputCfg(cfg) => {
+                // 仅影响序列化，无需重新计算
+                self.output_cfg = output::parse_output_cfg(&cfg)?;
+            }
+            Request::StreamCfg(bars) => {
+                self.stream_bars = bars;
+            }
+            Request::Pan { direction, bars } => {
+                if bars > 0 {
+                    self.pan(direction, bars).await?;
+                }
+            }
+            Request::Resync => {
+                self.stroke_publisher.reset();
+                self.segment_publisher.reset();
+            }
+            Request::Recompute(layers) => {
+                for layer in layers {
+                    self.layers.invalidate(layer);
+                }
+            }
+            Request::WatchJobs(ids) => {
+                self.watched_jobs = ids.into_iter().collect();
+            }
+            Request::Reset => {
+                let resp = self.free_layers(Layer::ALL.iter().copied().collect());
+                self.layers = LayerGraph::default();
+                self.override_slots.clear();
+                self.stroke_publisher.reset();
+                self.segment_publisher.reset();
+                self.basic_cfg = None;
+                self.parting_cfg = PartingConfig::default();
+                self.stroke_cfg = None;
+                self.trend_cfg = None;
+                self.metrics_cfg = None;
+                self.warmup = 0;
+                self.as_of = None;
+                self.ks_updated = false;
+                self.epoch = 0;
+                self.sub_degraded = None;
+                return Ok(resp);
+            }
+            Request::DropLayer(object) => {
+                match object {
+                    QueryObject::StrokeReplica => self.stroke_publisher.reset(),
+                    QueryObject::SegmentReplica => self.segment_publisher.reset(),
+                    _ => (),
+                }
+                let layers = object
+                    .cache_layer()
+                    .map(Layer::with_downstreams)
+                    .unwrap_or_default();
+                return Ok(self.free_layers(layers));
+            }
             Request::Query {
                 refresh,
                 objects,
                 requires,
+                detail,
+                validate,
+                overrides,
             } => {
-                if objects.is_empty() {
+                if objects.is_empty() && overrides.is_empty() {
                     return Ok(Response::Ack);
                 }
                 let queries = {
@@ -208,245 +726,1612 @@ impl Session {
                     }
                     s
                 };
-                let mut dataset = Vec::new();
-                // 每次都检查K线
-                if self.ensure_ks().await? || refresh {
-                    let d = Data::KLines(self.ks.as_ref().cloned().unwrap_or_default());
-                    dataset.push(d);
-                } else {
-                    dataset.push(Data::KLinesNoChange);
-                }
-
-                if queries.contains(&QueryObject::Strokes) {
-                    if self.ensure_strokes()? || refresh || requires.contains(&QueryObject::Strokes)
-                    {
-                        let d = Data::Strokes(self.strokes.as_ref().cloned().unwrap_or_default());
-                        dataset.push(d);
-                    } else {
-                        dataset.push(Data::StrokesNoChange);
-                    }
-                }
-                if queries.contains(&QueryObject::Segments) {
-                    self.ensure_strokes()?;
-                    if self.ensure_segments()?
-                        || refresh
-                        || requires.contains(&QueryObject::Segments)
-                    {
-                        let d = Data::Segments(self.segments.as_ref().cloned().unwrap_or_default());
-                        dataset.push(d);
-                    } else {
-                        dataset.push(Data::SegmentsNoChange);
-                    }
-                }
-                if queries.contains(&QueryObject::SubTrends) {
-                    if self.ensure_subtrends().await?
-                        || refresh
-                        || requires.contains(&QueryObject::SubTrends)
-                    {
-                        let d =
-                            Data::SubTrends(self.subtrends.as_ref().cloned().unwrap_or_default());
-                        dataset.push(d);
-                    } else {
-                        dataset.push(Data::SubTrendsNoChange);
-                    }
-                }
-                if queries.contains(&QueryObject::Centers) {
-                    self.ensure_subtrends().await?;
-                    if self.ensure_centers()? || refresh || requires.contains(&QueryObject::Centers)
-                    {
-                        let d = Data::Centers(self.centers.as_ref().cloned().unwrap_or_default());
-                        dataset.push(d);
-                    } else {
-                        dataset.push(Data::CentersNoChange);
+                // 查询期间价格数据发生变化时重新计算，保证各层来自同一版本的数据，
+                // 丢弃的结果不含复制消息，避免发布器的序号与客户端不一致
+                for _ in 0..MAX_EPOCH_ATTEMPTS {
+                    let epoch = self.sync_epoch();
+                    let mut dataset = self
+                        .query_dataset(refresh, &queries, &requires, detail, validate, &overrides)
+                        .await?;
+                    if self.current_epoch() == epoch {
+                        dataset.extend(self.publish_replicas(&queries, refresh, detail)?);
+                        dataset.push(Data::Epoch(epoch));
+                        return Ok(Response::Data(dataset));
                     }
+                    log::debug!("data epoch changed during query, recompute");
                 }
-                if queries.contains(&QueryObject::Trends) {
-                    self.ensure_subtrends().await?;
-                    self.ensure_centers()?;
-                    if self.ensure_trends()? || refresh || requires.contains(&QueryObject::Trends) {
-                        let d = Data::Trends(self.trends.as_ref().cloned().unwrap_or_default());
-                        dataset.push(d);
-                    } else {
-                        dataset.push(Data::TrendsNoChange);
-                    }
-                }
-                if queries.contains(&QueryObject::MACD) {
-                    if self.ensure_macd().await? || refresh || requires.contains(&QueryObject::MACD)
-                    {
-                        let d = Data::MACD(self.macd.as_ref().cloned().unwrap_or_default());
-                        dataset.push(d);
-                    } else {
-                        dataset.push(Data::MACDNoChange);
-                    }
-                }
-                return Ok(Response::Data(dataset));
+                return Err(Error::custom(
+                    ErrorKind::Timeout,
+                    "price data keeps changing during query".to_owned(),
+                ));
             }
         }
         Ok(Response::Ack)
     }
 
-    #[inline]
-    fn clear_k_cache(&mut self) {
-        self.ks.take();
+    // 计算查询对象，K线每次都返回
+    async fn query_dataset(
+        &mut self,
+        refresh: bool,
+        queries: &BTreeSet<QueryObject>,
+        requires: &BTreeSet<QueryObject>,
+        detail: bool,
+        validate: bool,
+        overrides: &[QueryOverride],
+    ) -> Result<Vec<Data>> {
+        let mut dataset = Vec::new();
+        // 每次都检查K线
+        let ks_updated = std::mem::take(&mut self.ks_updated);
+        if self.ensure_ks().await? || refresh || ks_updated {
+            let d = Data::KLines(self.ks.as_ref().cloned().unwrap_or_default());
+            dataset.push(d);
+        } else {
+            dataset.push(Data::KLinesNoChange);
+        }
+
+        if queries.contains(&QueryObject::Strokes) {
+            if self.ensure_strokes()? || refresh || requires.contains(&QueryObject::Strokes) {
+                let d = Data::Strokes(self.emitted_strokes().to_vec());
+                dataset.push(d);
+            } else {
+                dataset.push(Data::StrokesNoChange);
+            }
+        }
+        if queries.contains(&QueryObject::Segments) {
+            if self.ensure_segments()? || refresh || requires.contains(&QueryObject::Segments) {
+                let segments = self.emitted_segments();
+                let d = if detail {
+                    Data::Segments(segments.to_vec())
+                } else {
+                    Data::Segments(tanglism::brief_segments(segments))
+                };
+                dataset.push(d);
+            } else {
+                dataset.push(Data::SegmentsNoChange);
+            }
+        }
+        if queries.contains(&QueryObject::SubTrends) {
+            if self.ensure_subtrends().await?
+                || refresh
+                || requires.contains(&QueryObject::SubTrends)
+            {
+                let d = Data::SubTrends(self.subtrends.as_ref().cloned().unwrap_or_default());
+                dataset.push(d);
+            } else {
+                dataset.push(Data::SubTrendsNoChange);
+            }
+        }
+        if queries.contains(&QueryObject::Centers) {
+            if self.ensure_centers().await? || refresh || requires.contains(&QueryObject::Centers) {
+                let d = Data::Centers(self.centers.as_ref().cloned().unwrap_or_default());
+                dataset.push(d);
+            } else {
+                dataset.push(Data::CentersNoChange);
+            }
+        }
+        if queries.contains(&QueryObject::Trends) {
+            if self.ensure_trends().await? || refresh || requires.contains(&QueryObject::Trends) {
+                let d = Data::Trends(self.trends.as_ref().cloned().unwrap_or_default());
+                dataset.push(d);
+            } else {
+                dataset.push(Data::TrendsNoChange);
+            }
+        }
+        if queries.contains(&QueryObject::MACD) {
+            if self.ensure_macd().await? || refresh || requires.contains(&QueryObject::MACD) {
+                let d = Data::MACD(self.macd.as_ref().cloned().unwrap_or_default());
+                dataset.push(d);
+            } else {
+                dataset.push(Data::MACDNoChange);
+            }
+        }
+        if queries.contains(&QueryObject::Divergences) {
+            if self.ensure_divergences().await?
+                || refresh
+                || requires.contains(&QueryObject::Divergences)
+            {
+                let d = Data::Divergences(self.divergences.as_ref().cloned().unwrap_or_default());
+                dataset.push(d);
+            } else {
+                dataset.push(Data::DivergencesNoChange);
+            }
+        }
+        if queries.contains(&QueryObject::Basis) {
+            if self.ensure_basis().await? || refresh || requires.contains(&QueryObject::Basis) {
+                let d = Data::Basis(self.basis.as_ref().cloned().unwrap_or_default());
+                dataset.push(d);
+            } else {
+                dataset.push(Data::BasisNoChange);
+            }
+        }
+        if queries.contains(&QueryObject::Vwap) {
+            if self.ensure_vwap().await? || refresh || requires.contains(&QueryObject::Vwap) {
+                let d = Data::Vwap(self.vwap.as_ref().cloned().unwrap_or_default());
+                dataset.push(d);
+            } else {
+                dataset.push(Data::VwapNoChange);
+            }
+        }
+        // 决策日志不缓存，每次重新计算
+        if queries.contains(&QueryObject::StrokeTraces) {
+            dataset.push(Data::StrokeTraces(self.stroke_traces()?));
+        }
+        if queries.contains(&QueryObject::SegmentTraces) {
+            self.ensure_strokes()?;
+            let traces = match self.strokes {
+                Some(ref strokes) => tanglism::get_tanglism_segment_traces(strokes)?,
+                None => Vec::new(),
+            };
+            dataset.push(Data::SegmentTraces(traces));
+        }
+        if queries.contains(&QueryObject::Events) {
+            if let Some(ref cfg) = self.basic_cfg {
+                let data = events::list_events(
+                    self.db.clone(),
+                    events::EventQuery {
+                        code: Some(cfg.code.clone()),
+                        start_dt: Some(cfg.start_ts.date()),
+                        ..Default::default()
+                    },
+                )
+                .await?;
+                dataset.push(Data::Events(data));
+            }
+        }
+        let mut used = BTreeSet::new();
+        for ov in overrides {
+            let key = override_key(ov);
+            if let Some(data) = self.respond_override(key, ov, refresh, detail).await? {
+                dataset.push(Data::Overridden {
+                    id: ov.id.clone(),
+                    data: Box::new(data),
+                });
+            }
+            used.insert(key);
+        }
+        self.override_slots.retain(|k, _| used.contains(k));
+        if validate {
+            dataset.push(Data::Warnings(self.validate()));
+        }
+        if let Some(warmup) = self.warmup_info() {
+            dataset.push(Data::Warmup(warmup));
+        }
+        let uses_subtrends = [
+            QueryObject::SubTrends,
+            QueryObject::Centers,
+            QueryObject::Trends,
+            QueryObject::Divergences,
+        ]
+        .iter()
+        .any(|o| queries.contains(o));
+        if let (true, Some(reason)) = (uses_subtrends, &self.sub_degraded) {
+            dataset.push(Data::SubTrendsDegraded(reason.clone()));
+        }
+        dataset.push(Data::Recomputed(self.layers.take_recomputed()));
+        Ok(dataset)
     }
 
-    #[inline]
-    fn clear_tanglism_cache(&mut self) {
-        self.strokes.take();
-        self.segments.take();
-        self.subtrends.take();
-        self.centers.take();
-        self.trends.take();
+    // 发布笔及线段的复制消息，发布器记录已发送的状态，因此仅在确认数据版本一致后调用，
+    // 刷新时重新发送快照
+    fn publish_replicas(
+        &mut self,
+        queries: &BTreeSet<QueryObject>,
+        refresh: bool,
+        detail: bool,
+    ) -> Result<Vec<Data>> {
+        let mut dataset = Vec::new();
+        if queries.contains(&QueryObject::StrokeReplica) {
+            self.ensure_strokes()?;
+            if refresh {
+                self.stroke_publisher.reset();
+            }
+            let strokes = window_shapes(
+                self.strokes.as_deref().unwrap_or_default(),
+                self.window_start(),
+                |sk| sk.end_pt.extremum_ts,
+            );
+            dataset.push(Data::StrokeReplica(self.stroke_publisher.publish(strokes)));
+        }
+        if queries.contains(&QueryObject::SegmentReplica) {
+            self.ensure_segments()?;
+            if refresh {
+                self.segment_publisher.reset();
+            }
+            let segments = window_shapes(
+                self.segments.as_deref().unwrap_or_default(),
+                self.window_start(),
+                |sg| sg.end_pt.extremum_ts,
+            );
+            let msgs = if detail {
+                self.segment_publisher.publish(segments)
+            } else {
+                self.segment_publisher
+                    .publish(&tanglism::brief_segments(segments))
+            };
+            dataset.push(Data::SegmentReplica(msgs));
+        }
+        Ok(dataset)
     }
 
-    #[inline]
-    fn clear_trend_cache(&mut self) {
-        self.subtrends.take();
-        self.centers.take();
-        self.trends.take();
+    // 当前股票的价格数据版本，未设置基础配置时为0
+    fn current_epoch(&self) -> u64 {
+        self.basic_cfg
+            .as_ref()
+            .map(|cfg| epoch::data_epoch(&cfg.code))
+            .unwrap_or(0)
     }
 
-    #[inline]
-    fn clear_metrics_cache(&mut self) {
-        self.macd.take();
+    // 价格数据的版本变化时，缓存的K线、次级别K线及下游层失效
+    fn sync_epoch(&mut self) -> u64 {
+        let epoch = self.current_epoch();
+        if self.epoch != epoch {
+            self.layers.invalidate(Layer::KLines);
+            self.layers.invalidate(Layer::SubKLines);
+            self.sub_prefetch.take();
+            self.epoch = epoch;
+        }
+        epoch
+    }
+
+    // 按覆盖的配置计算查询对象，输入及配置未变化时使用缓存槽
+    async fn respond_override(
+        &mut self,
+        key: u64,
+        ov: &QueryOverride,
+        refresh: bool,
+        detail: bool,
+    ) -> Result<Option<Data>> {
+        let updated = match ov.object {
+            QueryObject::Strokes | QueryObject::Segments => {
+                self.override_strokes(key, ov, detail)?
+            }
+            QueryObject::SubTrends | QueryObject::Centers | QueryObject::Trends => {
+                self.override_trends(key, ov).await?
+            }
+            _ => {
+                return Err(Error::custom(
+                    ErrorKind::BadRequest,
+                    format!("config override not supported for {:?}", ov.object),
+                ))
+            }
+        };
+        let data = match updated {
+            Some(updated) if updated || refresh => {
+                self.override_slots.get(&key).map(|slot| slot.data.clone())
+            }
+            Some(_) => Some(match ov.object {
+                QueryObject::Strokes => Data::StrokesNoChange,
+                QueryObject::Segments => Data::SegmentsNoChange,
+                QueryObject::SubTrends => Data::SubTrendsNoChange,
+                QueryObject::Centers => Data::CentersNoChange,
+                _ => Data::TrendsNoChange,
+            }),
+            None => None,
+        };
+        Ok(data)
+    }
+
+    // 检查缓存槽的指纹，不一致时移除
+    fn override_fresh(&mut self, key: u64, fp: u64) -> bool {
+        match self.override_slots.get(&key) {
+            Some(slot) if slot.fp == fp => true,
+            Some(_) => {
+                self.override_slots.remove(&key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    // 以覆盖的分型及笔配置计算笔或线段，返回更新标签，缺少配置或K线时返回None
+    fn override_strokes(
+        &mut self,
+        key: u64,
+        ov: &QueryOverride,
+        detail: bool,
+    ) -> Result<Option<bool>> {
+        if ov.trend_cfg.is_some() {
+            return Err(Error::custom(
+                ErrorKind::BadRequest,
+                format!("trend cfg cannot override {:?}", ov.object),
+            ));
+        }
+        let parting_cfg = match ov.parting_cfg {
+            Some(ref cfg) => tanglism::parse_parting_cfg(cfg).context("invalid parting cfg")?,
+            None => self.parting_cfg.clone(),
+        };
+        let stroke_cfg = match (&ov.stroke_cfg, &self.stroke_cfg) {
+            (Some(cfg), _) => tanglism::parse_stroke_cfg(cfg).context("invalid stroke cfg")?,
+            (None, Some(cfg)) => cfg.clone(),
+            (None, None) => return Ok(None),
+        };
+        let (tick, up) = match (&self.basic_cfg, self.layers.fingerprint_of(Layer::KLines)) {
+            (Some(bc), Some(up)) => (bc.tick, up),
+            _ => return Ok(None),
+        };
+        let fp = fingerprint(&(up, &parting_cfg, &stroke_cfg, detail));
+        if self.override_fresh(key, fp) {
+            return Ok(Some(false));
+        }
+        let ks = match self.ks {
+            Some(ref ks) => ks,
+            None => return Ok(None),
+        };
+        let intraday = self.intraday();
+        let partings = match self.warmup_ks {
+            Some(ref warmup_ks) if !warmup_ks.is_empty() => {
+                let all: Vec<_> = warmup_ks.iter().chain(ks.iter()).cloned().collect();
+                analyze_partings(&all, &parting_cfg, intraday)?
+            }
+            _ => analyze_partings(ks, &parting_cfg, intraday)?,
+        };
+        let strokes = analyze_strokes(&partings, tick, stroke_cfg, intraday)?;
+        let start_ts = self.window_start();
+        let data = if ov.object == QueryObject::Strokes {
+            Data::Strokes(window_shapes(&strokes, start_ts, |sk| sk.end_pt.extremum_ts).to_vec())
+        } else {
+            let segments = analyze_segments(&strokes, intraday)?;
+            let segments = window_shapes(&segments, start_ts, |sg| sg.end_pt.extremum_ts);
+            if detail {
+                Data::Segments(segments.to_vec())
+            } else {
+                Data::Segments(tanglism::brief_segments(segments))
+            }
+        };
+        self.override_slots.insert(key, OverrideSlot { fp, data });
+        Ok(Some(true))
+    }
+
+    // 以覆盖的走势配置计算次级别走势、中枢或走势，返回更新标签
+    //
+    // 次级别的笔及线段与会话共享，次级别数据缺失时同样由本级别合成
+    async fn override_trends(&mut self, key: u64, ov: &QueryOverride) -> Result<Option<bool>> {
+        if ov.parting_cfg.is_some() || ov.stroke_cfg.is_some() {
+            return Err(Error::custom(
+                ErrorKind::BadRequest,
+                format!("parting or stroke cfg cannot override {:?}", ov.object),
+            ));
+        }
+        let trend_cfg = match (&ov.trend_cfg, &self.trend_cfg) {
+            (Some(cfg), _) => tanglism::parse_trend_cfg(cfg).context("invalid trend cfg")?,
+            (None, Some(cfg)) => cfg.clone(),
+            (None, None) => return Ok(None),
+        };
+        self.ensure_subtrends().await?;
+        let tick = match self.basic_cfg {
+            Some(ref bc) => bc.tick,
+            None => return Ok(None),
+        };
+        let degraded = self.sub_degraded.is_some();
+        let up = if degraded {
+            self.layers.fingerprint_of(Layer::Segments)
+        } else {
+            self.layers.fingerprint_of(Layer::SubStrokes)
+        };
+        let fp = match up {
+            Some(up) => fingerprint(&(up, degraded, &tick, &trend_cfg)),
+            None => return Ok(None),
+        };
+        if self.override_fresh(key, fp) {
+            return Ok(Some(false));
+        }
+        let (strokes, segments) = if degraded {
+            match (&self.strokes, &self.segments) {
+                (Some(strokes), Some(segments)) => (strokes, segments),
+                _ => return Ok(None),
+            }
+        } else {
+            match self.sub_strokes {
+                Some((ref strokes, ref segments)) => (strokes, segments),
+                None => return Ok(None),
+            }
+        };
+        let subtrends = tanglism::get_tanglism_subtrends(
+            segments,
+            strokes,
+            tick,
+            trend_cfg.level,
+            &trend_cfg.center,
+        )?;
+        let data = match ov.object {
+            QueryObject::SubTrends => Data::SubTrends(subtrends),
+            _ => {
+                let centers = tanglism::get_tanglism_centers(&subtrends, &trend_cfg.center)
+                    .context("center analysis failed")?;
+                if ov.object == QueryObject::Centers {
+                    Data::Centers(centers)
+                } else {
+                    Data::Trends(
+                        tanglism::get_tanglism_trends(&centers).context("trend analysis failed")?,
+                    )
+                }
+            }
+        };
+        self.override_slots.insert(key, OverrideSlot { fp, data });
+        Ok(Some(true))
+    }
+
+    // 设置预热时的窗口起点
+    fn window_start(&self) -> Option<NaiveDateTime> {
+        match self.basic_cfg {
+            Some(ref cfg) if self.warmup > 0 => Some(cfg.start_ts),
+            _ => None,
+        }
+    }
+
+    // 输出的笔，不含完全位于预热区间的笔
+    fn emitted_strokes(&self) -> &[Stroke] {
+        window_shapes(
+            self.strokes.as_deref().unwrap_or_default(),
+            self.window_start(),
+            |sk| sk.end_pt.extremum_ts,
+        )
+    }
+
+    fn emitted_segments(&self) -> &[Segment] {
+        window_shapes(
+            self.segments.as_deref().unwrap_or_default(),
+            self.window_start(),
+            |sg| sg.end_pt.extremum_ts,
+        )
+    }
+
+    fn warmup_info(&self) -> Option<Warmup> {
+        let start_ts = self.window_start()?;
+        let warmup_ks = self.warmup_ks.as_deref().unwrap_or_default();
+        Some(Warmup {
+            start_ts: warmup_ks.first().map(|k| k.ts).unwrap_or(start_ts),
+            bars: warmup_ks.len(),
+            strokes: self
+                .emitted_strokes()
+                .iter()
+                .take_while(|sk| sk.start_pt.extremum_ts < start_ts)
+                .count(),
+            segments: self
+                .emitted_segments()
+                .iter()
+                .take_while(|sg| sg.start_pt.extremum_ts < start_ts)
+                .count(),
+        })
+    }
+
+    // 抓取窗口起点前的预热K线
+    async fn fetch_warmup(&self, cfg: &BasicCfg) -> Result<Vec<ticks::StockPrice>> {
+        if self.warmup == 0 {
+            return Ok(Vec::new());
+        }
+        let tts = LocalTradingTimestamps::new(cfg.tick);
+        let first_ts = match tts
+            .aligned_tick(cfg.start_ts)
+            .or_else(|| tts.next_tick(cfg.start_ts))
+        {
+            Some(ts) => ts,
+            None => return Ok(Vec::new()),
+        };
+        let start_ts = match shift_ticks(&tts, first_ts, self.warmup, PanDirection::Left) {
+            Some(ts) => ts,
+            None => return Ok(Vec::new()),
+        };
+        let mut ks = stock_prices::get_stock_tick_prices(
+            &self.db, &self.jq, cfg.tick, &cfg.code, start_ts, first_ts,
+        )
+        .await?;
+        // 按日期抓取的数据可能包含窗口内的K线
+        ks.retain(|k| k.ts >= start_ts && k.ts < cfg.start_ts);
+        Ok(ks)
+    }
+
+    // 校验已缓存的分析结果，未计算的层跳过
+    fn validate(&self) -> Vec<ShapeWarning> {
+        let mut warnings = Vec::new();
+        if let Some(ref strokes) = self.strokes {
+            warnings.extend(tanglism_morph::validate_strokes(strokes));
+            if let Some(ref segments) = self.segments {
+                warnings.extend(tanglism_morph::validate_segments(strokes, segments));
+            }
+        }
+        if let (Some(subtrends), Some(centers)) = (&self.subtrends, &self.centers) {
+            warnings.extend(tanglism_morph::validate_centers(subtrends, centers));
+        }
+        warnings
+    }
+
+    // 平移分析窗口
+    //
+    // 复用已缓存的K线，仅抓取新露出一侧的K线并剔除移出窗口的K线，
+    // 笔、线段等分析结果基于合并后的K线重新计算
+    async fn pan(&mut self, direction: PanDirection, bars: usize) -> Result<()> {
+        // 回看模式下窗口受回看时刻约束，直接重新抓取
+        if self.as_of.is_some() {
+            return self.pan_uncached(direction, bars);
+        }
+        self.ensure_ks().await?;
+        let (cfg, ks) = match (self.basic_cfg.as_ref(), self.ks.as_ref()) {
+            (Some(cfg), Some(ks)) if !ks.is_empty() => (cfg.clone(), ks),
+            _ => return self.pan_uncached(direction, bars),
+        };
+        let tts = LocalTradingTimestamps::new(cfg.tick);
+        let first_ts = ks.first().unwrap().ts;
+        let last_ts = ks.last().unwrap().ts;
+        let out_of_range = || {
+            Error::custom(
+                ErrorKind::BadRequest,
+                format!("cannot pan {:?} by {} bars", direction, bars),
+            )
+        };
+        let (start_ts, end_ts, edge) = match direction {
+            PanDirection::Right => {
+                let end_ts =
+                    shift_ticks(&tts, last_ts, bars, direction).ok_or_else(out_of_range)?;
+                let start_ts = match ks.get(bars) {
+                    Some(k) => k.ts,
+                    None => shift_ticks(&tts, last_ts, bars - ks.len() + 1, direction)
+                        .ok_or_else(out_of_range)?,
+                };
+                let edge_start = tts.next_tick(last_ts).ok_or_else(out_of_range)?;
+                (start_ts, end_ts, (edge_start.max(start_ts), end_ts))
+            }
+            PanDirection::Left => {
+                let start_ts =
+                    shift_ticks(&tts, first_ts, bars, direction).ok_or_else(out_of_range)?;
+                let end_ts = if bars < ks.len() {
+                    ks[ks.len() - 1 - bars].ts
+                } else {
+                    shift_ticks(&tts, first_ts, bars - ks.len() + 1, direction)
+                        .ok_or_else(out_of_range)?
+                };
+                let edge_end = tts.prev_tick(first_ts).ok_or_else(out_of_range)?;
+                (start_ts, end_ts, (start_ts, edge_end.min(end_ts)))
+            }
+        };
+        let mut edge_ks = stock_prices::get_stock_tick_prices(
+            &self.db, &self.jq, cfg.tick, &cfg.code, edge.0, edge.1,
+        )
+        .await?;
+        // 按日期抓取的数据可能包含窗口外的K线
+        edge_ks.retain(|k| k.ts >= edge.0 && k.ts <= edge.1);
+        let mut ks = self.ks.take().unwrap_or_default();
+        ks.retain(|k| k.ts >= start_ts && k.ts <= end_ts);
+        let ks = match direction {
+            PanDirection::Right => {
+                ks.extend(edge_ks.into_iter().filter(|k| k.ts > last_ts));
+                ks
+            }
+            PanDirection::Left => {
+                edge_ks.retain(|k| k.ts < first_ts);
+                edge_ks.extend(ks);
+                edge_ks
+            }
+        };
+        self.ks.replace(ks);
+        self.ks_updated = true;
+        let cfg = BasicCfg {
+            start_ts,
+            end_ts,
+            ..cfg
+        };
+        let warmup_ks = self.fetch_warmup(&cfg).await?;
+        self.warmup_ks.replace(warmup_ks);
+        // 合并后的K线即新窗口的K线，下游层随指纹变化重新计算
+        self.layers
+            .update(Layer::KLines, fingerprint(&(&cfg, self.warmup)));
+        self.basic_cfg.replace(cfg);
+        Ok(())
+    }
+
+    // 无可用缓存时平移配置的时刻，下次查询重新抓取
+    fn pan_uncached(&mut self, direction: PanDirection, bars: usize) -> Result<()> {
+        let cfg = match self.basic_cfg.as_ref() {
+            Some(cfg) => cfg.clone(),
+            None => {
+                return Err(Error::custom(
+                    ErrorKind::BadRequest,
+                    "basic cfg not exists".to_owned(),
+                ))
+            }
+        };
+        let tts = LocalTradingTimestamps::new(cfg.tick);
+        // 配置的时刻可能未对齐，先对齐再平移
+        let shift = |ts: NaiveDateTime| {
+            tts.aligned_tick(ts)
+                .and_then(|ts| shift_ticks(&tts, ts, bars, direction))
+                .ok_or_else(|| {
+                    Error::custom(
+                        ErrorKind::BadRequest,
+                        format!("cannot pan {:?} by {} bars from {}", direction, bars, ts),
+                    )
+                })
+        };
+        let start_ts = shift(cfg.start_ts)?;
+        let end_ts = shift(cfg.end_ts)?;
+        self.basic_cfg.replace(BasicCfg {
+            start_ts,
+            end_ts,
+            ..cfg
+        });
+        Ok(())
+    }
+
+    // 是否为日内模式
+    fn intraday(&self) -> bool {
+        self.basic_cfg
+            .as_ref()
+            .map(|bc| bc.intraday)
+            .unwrap_or(false)
+    }
+
+    // 以回看时刻截断结束时刻后的基础配置
+    fn analysis_cfg(&self) -> Result<Option<BasicCfg>> {
+        let mut cfg = match self.basic_cfg {
+            Some(ref cfg) => cfg.clone(),
+            None => return Ok(None),
+        };
+        if let Some(as_of) = self.as_of {
+            if as_of < cfg.start_ts {
+                return Err(Error::custom(
+                    ErrorKind::BadRequest,
+                    format!("as-of {} < start_ts {}", as_of, cfg.start_ts),
+                ));
+            }
+            if as_of < cfg.end_ts {
+                cfg.end_ts = as_of;
+            }
+        }
+        Ok(Some(cfg))
     }
 
     // 检查并更新K线，返回更新标签
     async fn ensure_ks(&mut self) -> Result<bool> {
-        if self.ks.is_none() {
-            if let Some(ref basic_cfg) = self.basic_cfg {
-                let ks = stock_prices::get_stock_tick_prices(
-                    &self.db,
-                    &self.jq,
-                    &basic_cfg.tick,
-                    &basic_cfg.code,
-                    basic_cfg.start_ts,
-                    basic_cfg.end_ts,
-                )
-                .await?;
-                self.ks.replace(ks);
-                return Ok(true);
+        let basic_cfg = match self.analysis_cfg()? {
+            Some(cfg) => cfg,
+            None => return Ok(false),
+        };
+        let fp = fingerprint(&(&basic_cfg, self.warmup));
+        if self.layers.fresh(Layer::KLines, fp) {
+            return Ok(false);
+        }
+        let warmup_ks = self.fetch_warmup(&basic_cfg).await?;
+        let mut ks = stock_prices::get_stock_tick_prices(
+            &self.db,
+            &self.jq,
+            basic_cfg.tick,
+            &basic_cfg.code,
+            basic_cfg.start_ts,
+            basic_cfg.end_ts,
+        )
+        .await?;
+        truncate_as_of(&mut ks, self.as_of, |k| k.ts);
+        self.ks.replace(ks);
+        self.warmup_ks.replace(warmup_ks);
+        self.layers.update(Layer::KLines, fp);
+        Ok(true)
+    }
+
+    // 释放指定层的缓存并使其失效，调试模式下返回估算的释放字节数
+    fn free_layers(&mut self, layers: BTreeSet<Layer>) -> Response {
+        let mut bytes = 0;
+        for layer in &layers {
+            bytes += self.free_layer(*layer);
+            self.layers.invalidate(*layer);
+        }
+        log::debug!("freed layers {:?}, about {} bytes", layers, bytes);
+        if self.output_cfg.debug {
+            Response::Freed {
+                layers: layers.into_iter().collect(),
+                bytes,
             }
+        } else {
+            Response::Ack
+        }
+    }
+
+    // 仅计入缓存元素本身的大小，不含元素内部的堆内存
+    fn free_layer(&mut self, layer: Layer) -> usize {
+        match layer {
+            Layer::KLines => vec_bytes(self.ks.take()) + vec_bytes(self.warmup_ks.take()),
+            Layer::Partings => vec_bytes(self.partings.take()),
+            Layer::Strokes => vec_bytes(self.strokes.take()),
+            Layer::Segments => vec_bytes(self.segments.take()),
+            Layer::SubKLines => {
+                // 释放时取消未完成的预取
+                self.sub_prefetch.take();
+                vec_bytes(self.sub_ks.take())
+            }
+            Layer::SubStrokes => self
+                .sub_strokes
+                .take()
+                .map(|(strokes, segments)| {
+                    std::mem::size_of_val(strokes.as_slice())
+                        + std::mem::size_of_val(segments.as_slice())
+                })
+                .unwrap_or(0),
+            Layer::SubTrends => vec_bytes(self.subtrends.take()),
+            Layer::Centers => vec_bytes(self.centers.take()),
+            Layer::Trends => vec_bytes(self.trends.take()),
+            Layer::Macd => self
+                .macd
+                .take()
+                .map(|m| {
+                    std::mem::size_of_val(m.dif.as_slice())
+                        + std::mem::size_of_val(m.dea.as_slice())
+                        + std::mem::size_of_val(m.macd.as_slice())
+                })
+                .unwrap_or(0),
+            Layer::Divergences => vec_bytes(self.divergences.take()),
+            Layer::Basis => self
+                .basis
+                .take()
+                .map(|b| std::mem::size_of_val(b.points.as_slice()))
+                .unwrap_or(0),
+            Layer::Vwap => self
+                .vwap
+                .take()
+                .map(|v| {
+                    std::mem::size_of_val(v.daily.as_slice())
+                        + std::mem::size_of_val(v.anchored.as_slice())
+                })
+                .unwrap_or(0),
+        }
+    }
+
+    // 检查并更新分型，返回更新标签
+    fn ensure_partings(&mut self) -> Result<bool> {
+        let fp = match self.layers.upstream(Layer::Partings) {
+            Some(up) => fingerprint(&(up, &self.parting_cfg)),
+            None => return Ok(false),
+        };
+        if self.layers.fresh(Layer::Partings, fp) {
+            return Ok(false);
+        }
+        if let Some(ref ks) = self.ks {
+            let intraday = self.intraday();
+            let partings = match self.warmup_ks {
+                Some(ref warmup_ks) if !warmup_ks.is_empty() => {
+                    let all: Vec<_> = warmup_ks.iter().chain(ks.iter()).cloned().collect();
+                    analyze_partings(&all, &self.parting_cfg, intraday)?
+                }
+                _ => analyze_partings(ks, &self.parting_cfg, intraday)?,
+            };
+            self.partings.replace(partings);
+            self.layers.update(Layer::Partings, fp);
+            return Ok(true);
         }
         Ok(false)
     }
 
     // 检查并更新笔，返回更新标签
     fn ensure_strokes(&mut self) -> Result<bool> {
-        if self.strokes.is_none() {
-            if let Some(ref stroke_cfg) = self.stroke_cfg {
-                let tick = match self.basic_cfg {
-                    Some(ref bc) => &bc.tick,
-                    None => {
-                        return Err(Error::custom(
-                            ErrorKind::InternalServerError,
-                            "basic cfg not exists".to_owned(),
-                        ))
-                    }
-                };
-                if let Some(ref ks) = self.ks {
-                    let partings = tanglism::get_tanglism_partings(ks)?;
-                    let strokes =
-                        tanglism::get_tanglism_strokes(&partings, tick, stroke_cfg.clone())?;
-                    self.strokes.replace(strokes);
-                    return Ok(true);
-                }
+        self.ensure_partings()?;
+        let stroke_cfg = match self.stroke_cfg {
+            Some(ref cfg) => cfg,
+            None => return Ok(false),
+        };
+        let fp = match self.layers.upstream(Layer::Strokes) {
+            Some(up) => fingerprint(&(up, stroke_cfg)),
+            None => return Ok(false),
+        };
+        if self.layers.fresh(Layer::Strokes, fp) {
+            return Ok(false);
+        }
+        let (tick, intraday) = match self.basic_cfg {
+            Some(ref bc) => (bc.tick, bc.intraday),
+            None => {
+                return Err(Error::custom(
+                    ErrorKind::InternalServerError,
+                    "basic cfg not exists".to_owned(),
+                ))
             }
+        };
+        if let Some(ref partings) = self.partings {
+            let strokes = analyze_strokes(partings, tick, stroke_cfg.clone(), intraday)?;
+            self.strokes.replace(strokes);
+            self.layers.update(Layer::Strokes, fp);
+            return Ok(true);
         }
         Ok(false)
     }
 
+    /// 以当前的K线及配置重新计算分型、笔及线段，返回各累加器的内部状态，
+    /// 尚未查询过K线时返回None
+    pub fn accumulator_snapshots(&self) -> Result<Option<tanglism::AccumulatorSnapshots>> {
+        let (basic_cfg, ks) = match (&self.basic_cfg, &self.ks) {
+            (Some(bc), Some(ks)) => (bc, ks),
+            _ => return Ok(None),
+        };
+        let all: Vec<_> = match self.warmup_ks {
+            Some(ref warmup_ks) => warmup_ks.iter().chain(ks.iter()).cloned().collect(),
+            None => ks.clone(),
+        };
+        let stroke_cfg = self.stroke_cfg.clone().unwrap_or_default();
+        tanglism::get_tanglism_accumulators(&all, basic_cfg.tick, &self.parting_cfg, stroke_cfg)
+            .map(Some)
+    }
+
+    // 重新计算笔的决策日志
+    fn stroke_traces(&mut self) -> Result<Vec<Trace>> {
+        self.ensure_partings()?;
+        if let (Some(ref basic_cfg), Some(ref stroke_cfg), Some(ref partings)) =
+            (&self.basic_cfg, &self.stroke_cfg, &self.partings)
+        {
+            return tanglism::get_tanglism_stroke_traces(
+                partings,
+                basic_cfg.tick,
+                stroke_cfg.clone(),
+            );
+        }
+        Ok(Vec::new())
+    }
+
     // 检查并更新线段，返回更新标签
     fn ensure_segments(&mut self) -> Result<bool> {
-        if self.segments.is_none() {
-            if let Some(ref strokes) = self.strokes {
-                let segments = tanglism::get_tanglism_segments(&strokes)?;
-                self.segments.replace(segments);
-                return Ok(true);
-            }
+        self.ensure_strokes()?;
+        let fp = match self.layers.upstream(Layer::Segments) {
+            Some(up) => up,
+            None => return Ok(false),
+        };
+        if self.layers.fresh(Layer::Segments, fp) {
+            return Ok(false);
+        }
+        if let Some(ref strokes) = self.strokes {
+            let segments = analyze_segments(strokes, self.intraday())?;
+            self.segments.replace(segments);
+            self.layers.update(Layer::Segments, fp);
+            return Ok(true);
         }
         Ok(false)
     }
 
-    // 检查并更新次级别走势，返回更新标签
-    async fn ensure_subtrends(&mut self) -> Result<bool> {
-        if self.subtrends.is_none() {
-            if let (Some(ref basic_cfg), Some(ref stroke_cfg), Some(ref trend_cfg)) =
-                (&self.basic_cfg, &self.stroke_cfg, &self.trend_cfg)
-            {
-                // 次级别K线
-                // 取次级别tick
-                let tick = basic_cfg.tick.as_ref();
-                // 次级别走势总是由1分钟K线递归而来
-                let subtick = "1m";
-                // 无法重用K线是因为级别不同
-                let prices = stock_prices::get_stock_tick_prices(
+    // 后台预取次级别K线，使后续次级别走势及中枢查询无需等待抓取
+    //
+    // 替换已有的预取任务，原任务随之取消
+    fn schedule_sub_prefetch(&mut self) {
+        self.sub_prefetch.take();
+        // 配置无效时不预取，错误留待查询时返回
+        let basic_cfg = match self.analysis_cfg() {
+            Ok(Some(cfg)) => cfg,
+            _ => return,
+        };
+        let fp = sub_ks_fingerprint(&basic_cfg);
+        if basic_cfg.tick == Tick::M1 || self.layers.fresh(Layer::SubKLines, fp) {
+            return;
+        }
+        let (db, jq) = (self.db.clone(), self.jq.clone());
+        let fut = async move {
+            stock_prices::get_stock_tick_prices(
+                &db,
+                &jq,
+                Tick::M1,
+                &basic_cfg.code,
+                basic_cfg.start_ts,
+                basic_cfg.end_ts,
+            )
+            .await
+        };
+        let (abort, reg) = AbortHandle::new_pair();
+        let handle = tokio::spawn(Abortable::new(fut, reg));
+        self.sub_prefetch.replace(SubPrefetch { fp, abort, handle });
+    }
+
+    // 检查并更新次级别走势使用的1分钟K线，返回更新标签
+    async fn ensure_sub_ks(&mut self) -> Result<bool> {
+        let basic_cfg = match self.analysis_cfg()? {
+            Some(cfg) => cfg,
+            None => return Ok(false),
+        };
+        let fp = sub_ks_fingerprint(&basic_cfg);
+        if self.layers.fresh(Layer::SubKLines, fp) {
+            return Ok(false);
+        }
+        // 配置未变化时使用预取结果，否则丢弃预取
+        let prefetched = match self.sub_prefetch.take() {
+            Some(mut p) if p.fp == fp => p.join().await,
+            _ => None,
+        };
+        let mut prices = match prefetched {
+            Some(prices) => prices,
+            None => {
+                stock_prices::get_stock_tick_prices(
                     &self.db,
                     &self.jq,
-                    subtick,
+                    Tick::M1,
                     &basic_cfg.code,
                     basic_cfg.start_ts,
                     basic_cfg.end_ts,
                 )
-                .await?;
-                let partings = tanglism::get_tanglism_partings(&prices)?;
-                let strokes =
-                    tanglism::get_tanglism_strokes(&partings, subtick, stroke_cfg.clone())?;
-                let segments = tanglism::get_tanglism_segments(&strokes)?;
-                let subtrends =
-                    tanglism::get_tanglism_subtrends(&segments, &strokes, &tick, trend_cfg.level)?;
+                .await?
+            }
+        };
+        truncate_as_of(&mut prices, self.as_of, |p| p.ts);
+        self.sub_ks.replace(prices);
+        self.layers.update(Layer::SubKLines, fp);
+        Ok(true)
+    }
+
+    // 检查并更新1分钟K线上的笔及线段，返回更新标签
+    async fn ensure_sub_strokes(&mut self) -> Result<bool> {
+        let stroke_cfg = match self.stroke_cfg {
+            Some(ref cfg) => cfg.clone(),
+            None => return Ok(false),
+        };
+        self.ensure_sub_ks().await?;
+        let fp = match self.layers.upstream(Layer::SubStrokes) {
+            Some(up) => fingerprint(&(up, &self.parting_cfg, &stroke_cfg, self.intraday())),
+            None => return Ok(false),
+        };
+        if self.layers.fresh(Layer::SubStrokes, fp) {
+            return Ok(false);
+        }
+        if let Some(ref prices) = self.sub_ks {
+            let intraday = self.intraday();
+            let partings = analyze_partings(prices, &self.parting_cfg, intraday)?;
+            let strokes = analyze_strokes(&partings, Tick::M1, stroke_cfg, intraday)?;
+            let segments = analyze_segments(&strokes, intraday)?;
+            self.sub_strokes.replace((strokes, segments));
+            self.layers.update(Layer::SubStrokes, fp);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    // 检查并更新次级别走势，返回更新标签
+    async fn ensure_subtrends(&mut self) -> Result<bool> {
+        let (tick, trend_cfg) = match (&self.basic_cfg, &self.trend_cfg) {
+            (Some(bc), Some(tc)) => (bc.tick, tc.clone()),
+            _ => return Ok(false),
+        };
+        // 次级别数据缺失时降级为由本级别合成，而非整个查询失败
+        let degraded = match self.ensure_sub_strokes().await {
+            Ok(_) => match self.sub_strokes {
+                Some((ref strokes, _)) if strokes.is_empty() => {
+                    Some("no strokes in sub-level data".to_owned())
+                }
+                _ => None,
+            },
+            Err(e) => {
+                log::warn!("failed to fetch sub-level data: {}", e);
+                self.layers.invalidate(Layer::SubKLines);
+                self.sub_ks.take();
+                self.sub_strokes.take();
+                Some(e.to_string())
+            }
+        };
+        // 仅递归合成高级别走势时使用中枢配置
+        let center_cfg = if trend_cfg.level > 1 {
+            Some(&trend_cfg.center)
+        } else {
+            None
+        };
+        if let Some(reason) = degraded {
+            self.ensure_segments()?;
+            let fp = match self.layers.fingerprint_of(Layer::Segments) {
+                Some(up) => fingerprint(&("degraded", up, &tick, trend_cfg.level, center_cfg)),
+                None => return Ok(false),
+            };
+            self.sub_degraded.replace(reason);
+            if self.layers.fresh(Layer::SubTrends, fp) {
+                return Ok(false);
+            }
+            if let (Some(ref strokes), Some(ref segments)) = (&self.strokes, &self.segments) {
+                let subtrends = tanglism::get_tanglism_subtrends(
+                    segments,
+                    strokes,
+                    tick,
+                    trend_cfg.level,
+                    &trend_cfg.center,
+                )?;
                 self.subtrends.replace(subtrends);
+                self.layers.update(Layer::SubTrends, fp);
                 return Ok(true);
             }
+            return Ok(false);
+        }
+        self.sub_degraded = None;
+        let fp = match self.layers.upstream(Layer::SubTrends) {
+            Some(up) => fingerprint(&(up, &tick, trend_cfg.level, center_cfg)),
+            None => return Ok(false),
+        };
+        if self.layers.fresh(Layer::SubTrends, fp) {
+            return Ok(false);
+        }
+        if let Some((ref strokes, ref segments)) = self.sub_strokes {
+            let subtrends = tanglism::get_tanglism_subtrends(
+                segments,
+                strokes,
+                tick,
+                trend_cfg.level,
+                &trend_cfg.center,
+            )?;
+            self.subtrends.replace(subtrends);
+            self.layers.update(Layer::SubTrends, fp);
+            return Ok(true);
         }
         Ok(false)
     }
 
     // 检查并更新中枢，返回更新标签。中枢依赖次级别走势
-    fn ensure_centers(&mut self) -> Result<bool> {
-        if self.centers.is_none() {
-            if let Some(ref subtrends) = self.subtrends {
-                let centers = tanglism::get_tanglism_centers(subtrends)?;
-                self.centers.replace(centers);
-                return Ok(true);
-            }
+    async fn ensure_centers(&mut self) -> Result<bool> {
+        self.ensure_subtrends().await?;
+        let fp = match (self.layers.upstream(Layer::Centers), &self.trend_cfg) {
+            (Some(up), Some(tc)) => fingerprint(&(up, &tc.center)),
+            _ => return Ok(false),
+        };
+        if self.layers.fresh(Layer::Centers, fp) {
+            return Ok(false);
+        }
+        if let (Some(ref subtrends), Some(ref trend_cfg)) = (&self.subtrends, &self.trend_cfg) {
+            let centers = tanglism::get_tanglism_centers(subtrends, &trend_cfg.center)
+                .context("center analysis failed")?;
+            self.centers.replace(centers);
+            self.layers.update(Layer::Centers, fp);
+            return Ok(true);
         }
         Ok(false)
     }
 
     // 检查并更新走势，返回更新标签。走势依赖中枢及次级别走势
-    fn ensure_trends(&mut self) -> Result<bool> {
-        if self.trends.is_none() {
-            if let Some(ref centers) = self.centers {
-                let trends = tanglism::get_tanglism_trends(centers)?;
-                self.trends.replace(trends);
-                return Ok(true);
-            }
+    async fn ensure_trends(&mut self) -> Result<bool> {
+        self.ensure_centers().await?;
+        let fp = match self.layers.upstream(Layer::Trends) {
+            Some(up) => up,
+            None => return Ok(false),
+        };
+        if self.layers.fresh(Layer::Trends, fp) {
+            return Ok(false);
+        }
+        if let Some(ref centers) = self.centers {
+            let trends = tanglism::get_tanglism_trends(centers).context("trend analysis failed")?;
+            self.trends.replace(trends);
+            self.layers.update(Layer::Trends, fp);
+            return Ok(true);
         }
         Ok(false)
     }
 
     async fn ensure_macd(&mut self) -> Result<bool> {
-        if self.macd.is_none() {
-            log::debug!("macd is none");
-            if let Some(ref basic_cfg) = self.basic_cfg {
-                log::debug!("basic cfg not null");
-                if let Some(ref metrics_cfg) = self.metrics_cfg {
-                    log::debug!("metrics cfg not null");
-                    let macd_cfg = metrics::parse_macd_cfg(metrics_cfg).unwrap_or_default();
-                    log::debug!("macd_cfg={:?}", macd_cfg);
-                    let macd = metrics::get_metrics_macd(
-                        &self.db,
-                        &self.jq,
-                        basic_cfg.clone(),
-                        macd_cfg.clone(),
-                    )
-                    .await?;
-                    self.macd.replace(macd);
-                    return Ok(true);
-                }
-            }
+        let (basic_cfg, metrics_cfg) = match (self.analysis_cfg()?, &self.metrics_cfg) {
+            (Some(bc), Some(mc)) => (bc, mc.clone()),
+            _ => return Ok(false),
+        };
+        // 数据库中的参数可能变化，以生效参数计算指纹
+        let (macd_cfg, cfg_source) = metrics::macd::resolve_macd_cfg(
+            &self.db,
+            &basic_cfg.code,
+            basic_cfg.tick,
+            metrics::parse_macd_cfg(&metrics_cfg),
+        )
+        .await?;
+        let fp = fingerprint(&(&basic_cfg, &macd_cfg));
+        if self.layers.fresh(Layer::Macd, fp) {
+            return Ok(false);
+        }
+        log::debug!("macd_cfg={:?}, source={:?}", macd_cfg, cfg_source);
+        let mut macd =
+            metrics::get_metrics_macd(&self.db, &self.jq, basic_cfg, macd_cfg, cfg_source).await?;
+        // EMA仅依赖历史数据，截断即可避免未来数据
+        truncate_as_of(&mut macd.dif, self.as_of, |m| m.ts);
+        truncate_as_of(&mut macd.dea, self.as_of, |m| m.ts);
+        truncate_as_of(&mut macd.macd, self.as_of, |m| m.ts);
+        self.macd.replace(macd);
+        self.layers.update(Layer::Macd, fp);
+        Ok(true)
+    }
+
+    // 检查并更新背驰，返回更新标签。背驰依赖中枢及MACD
+    async fn ensure_divergences(&mut self) -> Result<bool> {
+        self.ensure_centers().await?;
+        self.ensure_macd().await?;
+        let fp = match self.layers.upstream(Layer::Divergences) {
+            Some(up) => up,
+            None => return Ok(false),
+        };
+        if self.layers.fresh(Layer::Divergences, fp) {
+            return Ok(false);
+        }
+        if let (Some(subtrends), Some(centers), Some(macd)) =
+            (&self.subtrends, &self.centers, &self.macd)
+        {
+            let divergences = tanglism::get_tanglism_divergences(subtrends, centers, &macd.macd)
+                .context("divergence analysis failed")?;
+            self.divergences.replace(divergences);
+            self.layers.update(Layer::Divergences, fp);
+            return Ok(true);
         }
         Ok(false)
     }
+
+    // 检查并更新期指基差，返回更新标签
+    async fn ensure_basis(&mut self) -> Result<bool> {
+        let basic_cfg = match self.analysis_cfg()? {
+            Some(cfg) => cfg,
+            None => return Ok(false),
+        };
+        let fp = fingerprint(&basic_cfg);
+        if self.layers.fresh(Layer::Basis, fp) {
+            return Ok(false);
+        }
+        let mut basis = basis::get_metrics_basis(&self.db, &self.jq, basic_cfg).await?;
+        truncate_as_of(&mut basis.points, self.as_of, |p| p.ts);
+        self.basis.replace(basis);
+        self.layers.update(Layer::Basis, fp);
+        Ok(true)
+    }
+
+    // 检查并更新成交量加权均价，返回更新标签
+    async fn ensure_vwap(&mut self) -> Result<bool> {
+        let basic_cfg = match self.analysis_cfg()? {
+            Some(cfg) => cfg,
+            None => return Ok(false),
+        };
+        let anchor = match self.metrics_cfg {
+            Some(ref mc) => vwap::parse_vwap_anchor(mc)?,
+            None => None,
+        };
+        let anchor = match anchor {
+            Some(VwapAnchor::Ts(ts)) => Some(ts),
+            Some(VwapAnchor::LastCenter) => {
+                self.ensure_centers().await?;
+                self.centers
+                    .iter()
+                    .flatten()
+                    .filter_map(|ce| ce.center())
+                    .next_back()
+                    .map(|c| c.start.ts)
+            }
+            None => None,
+        };
+        let fp = fingerprint(&(&basic_cfg, anchor));
+        if self.layers.fresh(Layer::Vwap, fp) {
+            return Ok(false);
+        }
+        let mut vwap = vwap::get_metrics_vwap(&self.db, &self.jq, basic_cfg, anchor).await?;
+        truncate_as_of(&mut vwap.daily, self.as_of, |m| m.ts);
+        truncate_as_of(&mut vwap.anchored, self.as_of, |m| m.ts);
+        self.vwap.replace(vwap);
+        self.layers.update(Layer::Vwap, fp);
+        Ok(true)
+    }
+}
+
+// 去除终点早于窗口起点的形态，形态按时间排序
+fn window_shapes<T, F>(shapes: &[T], start_ts: Option<NaiveDateTime>, end_ts: F) -> &[T]
+where
+    F: Fn(&T) -> NaiveDateTime,
+{
+    match start_ts {
+        Some(start_ts) => {
+            let n = shapes.iter().take_while(|s| end_ts(s) < start_ts).count();
+            &shapes[n..]
+        }
+        None => shapes,
+    }
+}
+
+// 次级别K线的指纹
+//
+// 无法重用K线是因为级别不同，与tick无关
+// 覆盖项缓存槽的键，与客户端标识无关
+fn override_key(ov: &QueryOverride) -> u64 {
+    fingerprint(&(&ov.object, &ov.parting_cfg, &ov.stroke_cfg, &ov.trend_cfg))
+}
+
+// 日内模式下各交易日独立计算分型、笔及线段
+fn analyze_partings(
+    prices: &[ticks::StockPrice],
+    cfg: &PartingConfig,
+    intraday: bool,
+) -> Result<Vec<Parting>> {
+    if intraday {
+        tanglism::get_tanglism_partings_by_day(prices, cfg)
+    } else {
+        tanglism::get_tanglism_partings(prices, cfg)
+    }
+    .context("parting analysis failed")
+}
+
+fn analyze_strokes(
+    partings: &[Parting],
+    tick: Tick,
+    cfg: StrokeConfig,
+    intraday: bool,
+) -> Result<Vec<Stroke>> {
+    if intraday {
+        tanglism::get_tanglism_strokes_by_day(partings, tick, cfg)
+    } else {
+        tanglism::get_tanglism_strokes(partings, tick, cfg)
+    }
+    .context("stroke analysis failed")
+}
+
+fn analyze_segments(strokes: &[Stroke], intraday: bool) -> Result<Vec<Segment>> {
+    if intraday {
+        tanglism::get_tanglism_segments_by_day(strokes)
+    } else {
+        tanglism::get_tanglism_segments(strokes)
+    }
+    .context("segment analysis failed")
+}
+
+fn sub_ks_fingerprint(cfg: &BasicCfg) -> u64 {
+    fingerprint(&(&cfg.code, cfg.start_ts, cfg.end_ts))
+}
+
+// 按交易时刻平移n个tick
+fn shift_ticks<T: TradingTimestamps>(
+    tts: &T,
+    ts: NaiveDateTime,
+    n: usize,
+    direction: PanDirection,
+) -> Option<NaiveDateTime> {
+    let mut ts = ts;
+    for _ in 0..n {
+        ts = match direction {
+            PanDirection::Left => tts.prev_tick(ts)?,
+            PanDirection::Right => tts.next_tick(ts)?,
+        };
+    }
+    Some(ts)
+}
+
+fn vec_bytes<T>(data: Option<Vec<T>>) -> usize {
+    data.map(|d| std::mem::size_of_val(d.as_slice()))
+        .unwrap_or(0)
+}
+
+// 剔除回看时刻之后的数据，输入按时刻升序排列
+fn truncate_as_of<T, F>(data: &mut Vec<T>, as_of: Option<NaiveDateTime>, ts: F)
+where
+    F: Fn(&T) -> NaiveDateTime,
+{
+    if let Some(as_of) = as_of {
+        let n = data.iter().take_while(|d| ts(d) <= as_of).count();
+        data.truncate(n);
+    }
+}
+
+/// 将K线按每块K线数拆分为分块
+fn chunk_klines(ks: Vec<ticks::StockPrice>, bars: usize) -> Vec<Data> {
+    let total = ks.len().div_ceil(bars);
+    let mut rst = Vec::with_capacity(total);
+    let mut iter = ks.into_iter().peekable();
+    while iter.peek().is_some() {
+        let data: Vec<_> = iter.by_ref().take(bars).collect();
+        let index = rst.len();
+        rst.push(Data::KLinesChunk {
+            index,
+            total,
+            progress: (index + 1) * 100 / total,
+            data,
+        });
+    }
+    rst
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_compatible() {
+        // 不带ID的旧格式请求仍可解析
+        let env: RequestEnvelope =
+            serde_json::from_str(r#"{"type":"StrokeCfg","data":""}"#).unwrap();
+        assert_eq!(None, env.id);
+        assert_eq!(Request::StrokeCfg("".into()), env.request);
+        let env: RequestEnvelope = serde_json::from_str(r#"{"id":"r1","type":"Resync"}"#).unwrap();
+        assert_eq!(Some("r1".to_owned()), env.id);
+        assert_eq!(Request::Resync, env.request);
+        let mut sequencer = Sequencer::default();
+        let resp = sequencer.wrap(Some("r1".into()), Response::Ack);
+        let json = serde_json::to_value(&resp).unwrap();
+        assert_eq!(
+            serde_json::json!({"seq": 1, "id": "r1", "type": "Ack"}),
+            json
+        );
+    }
+
+    #[test]
+    fn test_free_messages() {
+        let req: Request =
+            serde_json::from_str(r#"{"type":"DropLayer","data":"SubTrends"}"#).unwrap();
+        assert_eq!(Request::DropLayer(QueryObject::SubTrends), req);
+        assert_eq!(Some(Layer::SubKLines), QueryObject::SubTrends.cache_layer());
+        assert_eq!(None, QueryObject::StrokeReplica.cache_layer());
+        let json = serde_json::to_value(&Response::Freed {
+            layers: vec![Layer::Centers, Layer::Trends],
+            bytes: 128,
+        })
+        .unwrap();
+        assert_eq!(
+            serde_json::json!({"type": "Freed", "data": {"layers": ["Centers", "Trends"], "bytes": 128}}),
+            json
+        );
+        assert_eq!(48, vec_bytes(Some(vec![0u64; 6])));
+    }
+
+    #[test]
+    fn test_query_overrides() {
+        // 不带覆盖项的查询仍可解析
+        let req: Request = serde_json::from_str(
+            r#"{"type":"Query","data":{"refresh":false,"objects":["Strokes"],"requires":[]}}"#,
+        )
+        .unwrap();
+        match req {
+            Request::Query { overrides, .. } => assert!(overrides.is_empty()),
+            _ => panic!("not a query"),
+        }
+        let req: Request = serde_json::from_str(
+            r#"{"type":"Query","data":{"refresh":false,"objects":[],"requires":[],
+            "overrides":[{"id":"a","object":"Centers","trend_cfg":"combination_seed:false"}]}}"#,
+        )
+        .unwrap();
+        let ov = match req {
+            Request::Query { mut overrides, .. } => overrides.pop().unwrap(),
+            _ => panic!("not a query"),
+        };
+        assert_eq!(QueryObject::Centers, ov.object);
+        assert_eq!(None, ov.stroke_cfg);
+        // 缓存槽与客户端标识无关，与对象及配置相关
+        let renamed = QueryOverride {
+            id: "b".into(),
+            ..ov.clone()
+        };
+        assert_eq!(override_key(&ov), override_key(&renamed));
+        let trends = QueryOverride {
+            object: QueryObject::Trends,
+            ..ov.clone()
+        };
+        assert_ne!(override_key(&ov), override_key(&trends));
+    }
+
+    #[test]
+    fn test_window_shapes() {
+        let ts = |s: &str| parse_ts_from_str(s).unwrap().0;
+        let spans = vec![
+            (ts("2020-02-03 10:00"), ts("2020-02-03 11:00")),
+            (ts("2020-02-03 11:00"), ts("2020-02-04 10:30")),
+            (ts("2020-02-04 10:30"), ts("2020-02-04 14:00")),
+        ];
+        let windowed = window_shapes(&spans, Some(ts("2020-02-04 00:00")), |s| s.1);
+        assert_eq!(&spans[1..], windowed);
+        assert!(windowed[0].0 < ts("2020-02-04 00:00"));
+        assert_eq!(3, window_shapes(&spans, None, |s| s.1).len());
+    }
+
+    #[test]
+    fn test_truncate_as_of() {
+        let ts = |s: &str| parse_ts_from_str(s).unwrap().0;
+        let mut data = vec![
+            ts("2020-02-03 10:00"),
+            ts("2020-02-03 10:30"),
+            ts("2020-02-03 11:00"),
+        ];
+        truncate_as_of(&mut data, None, |d| *d);
+        assert_eq!(3, data.len());
+        truncate_as_of(&mut data, Some(ts("2020-02-03 10:30")), |d| *d);
+        assert_eq!(vec![ts("2020-02-03 10:00"), ts("2020-02-03 10:30")], data);
+    }
+
+    #[test]
+    fn test_shift_ticks() {
+        let tts = LocalTradingTimestamps::new(Tick::M30);
+        let ts = |s: &str| parse_ts_from_str(s).unwrap().0;
+        assert_eq!(
+            Some(ts("2020-02-03 11:30")),
+            shift_ticks(&tts, ts("2020-02-03 10:00"), 3, PanDirection::Right)
+        );
+        assert_eq!(
+            Some(ts("2020-02-03 11:30")),
+            shift_ticks(&tts, ts("2020-02-03 13:30"), 1, PanDirection::Left)
+        );
+        // 跨越交易日
+        assert_eq!(
+            Some(ts("2020-02-03 15:00")),
+            shift_ticks(&tts, ts("2020-02-04 10:00"), 1, PanDirection::Left)
+        );
+    }
+
+    #[test]
+    fn test_sequencer_dedup() {
+        let mut sequencer = Sequencer::default();
+        sequencer.wrap(Some("r1".into()), Response::Ack);
+        sequencer.wrap(None, Response::Error("bad".into()));
+        assert_eq!(1, sequencer.handled("r1").unwrap().seq);
+        assert_eq!(3, sequencer.wrap(Some("r2".into()), Response::Ack).seq);
+        for i in 0..MAX_HANDLED_REQUESTS {
+            sequencer.wrap(Some(format!("x{}", i)), Response::Ack);
+        }
+        assert!(sequencer.handled("r1").is_none());
+    }
+
+    #[test]
+    fn test_stream_klines() {
+        use bigdecimal::BigDecimal;
+        use diesel::pg::PgConnection;
+        use diesel::r2d2::{ConnectionManager, Pool};
+
+        let manager = ConnectionManager::<PgConnection>::new("postgres://localhost/test");
+        let db = Pool::builder().build_unchecked(manager);
+        let mut sess = Session::new(JqdataPool::from_clients(Vec::new()), db);
+        let ks: Vec<_> = (0..5)
+            .map(|i| ticks::StockPrice {
+                ts: parse_ts_from_str("2020-02-03").unwrap().0 + chrono::Duration::minutes(i),
+                open: BigDecimal::from(10),
+                close: BigDecimal::from(10),
+                high: BigDecimal::from(10),
+                low: BigDecimal::from(10),
+                volume: BigDecimal::from(100),
+                amount: BigDecimal::from(1000),
+            })
+            .collect();
+        let resp = || Response::Data(vec![Data::KLines(ks.clone()), Data::StrokesNoChange]);
+        // 未配置时不分块
+        sess.stream_klines(None, resp());
+        assert!(sess.take_chunks().is_empty());
+
+        sess.stream_bars = 2;
+        let id = "q1".to_owned();
+        match sess.stream_klines(Some(&id), resp()) {
+            Response::Data(dataset) => match dataset[0] {
+                Data::KLinesEnd { bars, chunks } => assert_eq!((5, 3), (bars, chunks)),
+                ref d => panic!("unexpected data {:?}", d),
+            },
+            r => panic!("unexpected response {:?}", r),
+        }
+        let chunks = sess.take_chunks();
+        assert_eq!(
+            vec![1, 2, 3],
+            chunks.iter().map(|c| c.seq).collect::<Vec<_>>()
+        );
+        let last = serde_json::to_value(&chunks[2]).unwrap();
+        assert_eq!("q1", last["id"]);
+        assert_eq!(2, last["data"][0]["data"]["index"]);
+        assert_eq!(100, last["data"][0]["data"]["progress"]);
+        assert_eq!(1, last["data"][0]["data"]["data"].as_array().unwrap().len());
+    }
+
+    #[test]
+    fn test_encode_job_envelope() {
+        use crate::handlers::jobs::JobStatus;
+        use diesel::pg::PgConnection;
+        use diesel::r2d2::{ConnectionManager, Pool};
+
+        let manager = ConnectionManager::<PgConnection>::new("postgres://localhost/test");
+        let db = Pool::builder().build_unchecked(manager);
+        let mut sess = Session::new(JqdataPool::from_clients(Vec::new()), db);
+        sess.output_cfg = output::parse_output_cfg("precision:2").unwrap();
+        sess.watched_jobs.insert(1);
+        let event = JobEvent {
+            id: 1,
+            status: JobStatus::Running,
+            progress: 50,
+            message: "10.2345".to_owned(),
+        };
+        // 任务进度推送与查询响应一样按输出配置裁剪
+        let resp = sess.job_envelope(event).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&sess.encode(&resp)).unwrap();
+        assert_eq!("10.23", json["data"]["message"]);
+        assert_eq!(50, json["data"]["progress"]);
+    }
+
+    // 查询期间价格数据变化时丢弃的结果不影响复制消息，客户端仍可按序应用
+    #[tokio::test]
+    async fn test_replica_after_epoch_mismatch() -> Result<()> {
+        use diesel::pg::PgConnection;
+        use diesel::r2d2::{ConnectionManager, Pool};
+        use tanglism_morph::{ReplicaClient, Replicator};
+
+        let manager = ConnectionManager::<PgConnection>::new("postgres://localhost/test");
+        let db = Pool::builder().build_unchecked(manager);
+        let mut sess = Session::new(JqdataPool::from_clients(Vec::new()), db);
+        let ks = zigzag_prices(&[(40, 64), (64, 48), (48, 80), (80, 60), (60, 96), (96, 56)]);
+        sess.basic_cfg = Some(BasicCfg {
+            tick: Tick::M30,
+            code: "TEST_REPLICA_EPOCH.XSHG".to_owned(),
+            start_ts: ks[0].ts,
+            end_ts: ks.last().unwrap().ts,
+            intraday: false,
+        });
+        sess.stroke_cfg = Some(StrokeConfig::default());
+        let queries: BTreeSet<_> = vec![QueryObject::StrokeReplica].into_iter().collect();
+        let mut client = ReplicaClient::new();
+        // 模拟抓取到的K线
+        let load = |sess: &mut Session, ks: &[ticks::StockPrice]| {
+            let fp = fingerprint(&(sess.analysis_cfg().unwrap().unwrap(), sess.warmup));
+            sess.ks.replace(ks.to_vec());
+            sess.layers.invalidate(Layer::KLines);
+            sess.layers.update(Layer::KLines, fp);
+        };
+
+        // 首次查询期间价格数据变化，结果被丢弃
+        load(&mut sess, &ks[..16]);
+        let e0 = sess.sync_epoch();
+        let dataset = sess
+            .query_dataset(false, &queries, &BTreeSet::new(), false, false, &[])
+            .await?;
+        assert!(!dataset.iter().any(|d| matches!(d, Data::StrokeReplica(_))));
+        epoch::bump_epoch(Some("TEST_REPLICA_EPOCH.XSHG"));
+        assert_ne!(e0, sess.current_epoch());
+
+        // 重新计算后首先发送快照
+        sess.sync_epoch();
+        load(&mut sess, &ks[..16]);
+        sess.query_dataset(false, &queries, &BTreeSet::new(), false, false, &[])
+            .await?;
+        let msgs = match sess.publish_replicas(&queries, false, false)?.pop() {
+            Some(Data::StrokeReplica(msgs)) => msgs,
+            d => panic!("unexpected data {:?}", d),
+        };
+        assert!(matches!(
+            msgs[..],
+            [ReplicaMessage::Snapshot { seq: 0, .. }]
+        ));
+        for msg in msgs {
+            client.replicate(msg)?;
+        }
+        assert!(!client.state().is_empty());
+        assert_eq!(sess.emitted_strokes(), &client.state()[..]);
+
+        // 后续变更序号连续
+        load(&mut sess, &ks);
+        sess.query_dataset(false, &queries, &BTreeSet::new(), false, false, &[])
+            .await?;
+        let msgs = match sess.publish_replicas(&queries, false, false)?.pop() {
+            Some(Data::StrokeReplica(msgs)) => msgs,
+            d => panic!("unexpected data {:?}", d),
+        };
+        assert!(!msgs.is_empty());
+        for msg in msgs {
+            client.replicate(msg)?;
+        }
+        assert_eq!(sess.emitted_strokes(), &client.state()[..]);
+        Ok(())
+    }
+
+    // 每段由起止价格线性生成4根30分钟K线
+    fn zigzag_prices(waves: &[(i32, i32)]) -> Vec<ticks::StockPrice> {
+        let tts = LocalTradingTimestamps::new(Tick::M30);
+        let mut ts = parse_ts_from_str("2020-02-03 10:00").unwrap().0;
+        let mut ks = Vec::new();
+        for (start, end) in waves {
+            for i in 1..=4 {
+                let mid = start + (end - start) * i / 4;
+                let ts_str = ts.format("%Y-%m-%d %H:%M").to_string();
+                ks.push(
+                    ticks::PriceBuilder::new(&ts_str, mid)
+                        .range(mid - 2, mid + 2)
+                        .build(),
+                );
+                ts = tts.next_tick(ts).unwrap();
+            }
+        }
+        ks
+    }
+
+    #[test]
+    fn test_sync_epoch() {
+        use diesel::pg::PgConnection;
+        use diesel::r2d2::{ConnectionManager, Pool};
+
+        let manager = ConnectionManager::<PgConnection>::new("postgres://localhost/test");
+        let db = Pool::builder().build_unchecked(manager);
+        let mut sess = Session::new(JqdataPool::from_clients(Vec::new()), db);
+        let ts = parse_ts_from_str("2020-02-03").unwrap().0;
+        sess.basic_cfg = Some(BasicCfg {
+            tick: Tick::M30,
+            code: "TEST_EPOCH.XSHG".to_owned(),
+            start_ts: ts,
+            end_ts: ts,
+            intraday: false,
+        });
+        sess.layers.update(Layer::KLines, 1);
+        sess.layers.update(Layer::Strokes, 2);
+        let e0 = sess.sync_epoch();
+        assert!(sess.layers.fresh(Layer::KLines, 1));
+        // 价格数据变化后缓存的K线及下游层失效
+        let e1 = epoch::bump_epoch(Some("TEST_EPOCH.XSHG"));
+        assert!(e1 > e0);
+        assert_eq!(e1, sess.sync_epoch());
+        assert_eq!(None, sess.layers.fingerprint_of(Layer::KLines));
+        assert_eq!(None, sess.layers.fingerprint_of(Layer::Strokes));
+    }
 }
@@ -0,0 +1,290 @@
+//! 图表快照分享
+//!
+//! 按给定的分析配置在临时会话中完成一次完整查询，结果连同配置持久化。
+//! 之后通过快照ID只读访问，不再访问jqdata，也不重新计算。
+
+use super::annotation::{self, Annotation};
+use super::layers::fingerprint;
+use super::session::{Data, QueryObject, Request, Response, Session};
+use crate::handlers::audit::AnalysisConfig;
+use crate::handlers::{stocks, tanglism};
+use crate::models::Snapshot;
+use crate::{DbPool, Error, ErrorKind, JqdataPool, Result, ResultExt};
+use chrono::{Local, NaiveDateTime};
+use diesel::prelude::*;
+use serde_derive::*;
+use tanglism_utils::Tick;
+
+/// 快照的分析配置，各配置为空时使用默认值
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareState {
+    pub code: String,
+    pub tick: Tick,
+    pub start_dt: String,
+    #[serde(default)]
+    pub end_dt: String,
+    #[serde(default)]
+    pub intraday: bool,
+    #[serde(default)]
+    pub parting_cfg: String,
+    #[serde(default)]
+    pub stroke_cfg: String,
+    #[serde(default)]
+    pub trend_cfg: String,
+    #[serde(default)]
+    pub metrics_cfg: String,
+    #[serde(default)]
+    pub warmup: usize,
+    #[serde(default)]
+    pub as_of: String,
+    // 为空时包含笔、线段、次级别走势、中枢、走势及MACD
+    #[serde(default)]
+    pub objects: Vec<QueryObject>,
+}
+
+impl ShareState {
+    // 依次发送给会话的请求，最后一条为查询
+    fn requests(&self) -> Vec<Request> {
+        let objects = if self.objects.is_empty() {
+            vec![
+                QueryObject::Strokes,
+                QueryObject::Segments,
+                QueryObject::SubTrends,
+                QueryObject::Centers,
+                QueryObject::Trends,
+                QueryObject::MACD,
+            ]
+        } else {
+            self.objects.clone()
+        };
+        vec![
+            Request::BasicCfg {
+                tick: self.tick,
+                code: self.code.clone(),
+                start_dt: self.start_dt.clone(),
+                end_dt: self.end_dt.clone(),
+                intraday: self.intraday,
+            },
+            Request::PartingCfg(self.parting_cfg.clone()),
+            Request::StrokeCfg(self.stroke_cfg.clone()),
+            Request::TrendCfg(self.trend_cfg.clone()),
+            Request::MetricsCfg(self.metrics_cfg.clone()),
+            Request::WarmupCfg(self.warmup),
+            Request::AsOf(self.as_of.clone()),
+            Request::Query {
+                refresh: true,
+                objects,
+                requires: Vec::new(),
+                detail: false,
+                validate: false,
+                overrides: Vec::new(),
+            },
+        ]
+    }
+}
+
+/// 分享的快照，data与websocket查询返回的数据一致
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SharedSnapshot {
+    pub id: String,
+    pub code: String,
+    pub tick: String,
+    pub created_at: NaiveDateTime,
+    pub state: serde_json::Value,
+    pub data: serde_json::Value,
+    // 按指定的标注规则生成，未指定时不返回
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Vec<Annotation>>,
+}
+
+impl SharedSnapshot {
+    fn from_model(s: Snapshot) -> Result<Self> {
+        Ok(SharedSnapshot {
+            id: s.id,
+            code: s.code,
+            tick: s.tick,
+            created_at: s.created_at,
+            state: parse_json(&s.state)?,
+            data: parse_json(&s.bundle)?,
+            annotations: None,
+        })
+    }
+}
+
+fn parse_json(s: &str) -> Result<serde_json::Value> {
+    serde_json::from_str(s).map_err(|e| {
+        Error::custom(
+            ErrorKind::InternalServerError,
+            format!("corrupted snapshot: {}", e),
+        )
+    })
+}
+
+fn to_json<T: serde::Serialize>(t: &T) -> Result<String> {
+    serde_json::to_string(t)
+        .map_err(|e| Error::custom(ErrorKind::InternalServerError, e.to_string()))
+}
+
+/// 计算并保存快照，相同配置及结果得到相同的ID
+pub async fn create_snapshot(
+    db: DbPool,
+    jq: JqdataPool,
+    mut state: ShareState,
+) -> Result<SharedSnapshot> {
+    // 固定为解析后的代码，避免名称对应的股票变化
+    state.code = stocks::resolve_stock(db.clone(), state.code).await?;
+    let mut sess = Session::new(jq, db.clone());
+    let mut data = Vec::new();
+    for req in state.requests() {
+        match sess.respond(req).await {
+            // 直接调用respond不经过限流，任务进度仅通过推送返回
+            Response::Ack
+            | Response::Throttled { .. }
+            | Response::Job(_)
+            | Response::Freed { .. } => (),
+            Response::Data(d) => data = d,
+            Response::Error(e) => return Err(Error::custom(ErrorKind::BadRequest, e)),
+        }
+    }
+    let mut config = AnalysisConfig::new(
+        vec![state.code.clone()],
+        state.tick,
+        &tanglism::parse_parting_cfg(&state.parting_cfg).context("invalid parting cfg")?,
+        &tanglism::parse_stroke_cfg(&state.stroke_cfg).context("invalid stroke cfg")?,
+        &tanglism::parse_trend_cfg(&state.trend_cfg).context("invalid trend cfg")?,
+    );
+    config.metrics_cfg = state.metrics_cfg.clone();
+    config.data_end_ts = data.iter().find_map(|d| match d {
+        Data::KLines(ks) => ks.last().map(|k| k.ts),
+        _ => None,
+    });
+    let state_json = to_json(&state)?;
+    let bundle = to_json(&data)?;
+    let snapshot = Snapshot {
+        id: format!("{:016x}", fingerprint(&(&state_json, &bundle))),
+        code: state.code.clone(),
+        tick: state.tick.to_string(),
+        state: state_json,
+        bundle,
+        created_at: Local::now().naive_local(),
+        config: config.to_json()?,
+    };
+    let rst = snapshot.clone();
+    tokio::task::spawn_blocking(move || {
+        use crate::schema::snapshots::dsl::*;
+        let conn = db.get()?;
+        diesel::insert_into(snapshots)
+            .values(&snapshot)
+            .on_conflict_do_nothing()
+            .execute(&conn)
+            .map_err(Error::from)
+    })
+    .await??;
+    SharedSnapshot::from_model(rst)
+}
+
+/// 按分享配置重放会话，返回分型、笔及线段累加器的内部状态
+pub async fn inspect_accumulators(
+    db: DbPool,
+    jq: JqdataPool,
+    mut state: ShareState,
+) -> Result<tanglism::AccumulatorSnapshots> {
+    state.code = stocks::resolve_stock(db.clone(), state.code).await?;
+    let mut sess = Session::new(jq, db.clone());
+    for req in state.requests() {
+        if let Response::Error(e) = sess.respond(req).await {
+            return Err(Error::custom(ErrorKind::BadRequest, e));
+        }
+    }
+    sess.accumulator_snapshots()?.ok_or_else(|| {
+        Error::custom(
+            ErrorKind::BadRequest,
+            format!("no klines of {} loaded", state.code),
+        )
+    })
+}
+
+/// 重放已保存的快照，返回累加器的内部状态
+pub async fn inspect_snapshot_accumulators(
+    db: DbPool,
+    jq: JqdataPool,
+    input_id: String,
+) -> Result<tanglism::AccumulatorSnapshots> {
+    let snapshot = load_snapshot(db.clone(), input_id).await?;
+    let state: ShareState = serde_json::from_str(&snapshot.state).map_err(|e| {
+        Error::custom(
+            ErrorKind::InternalServerError,
+            format!("corrupted snapshot: {}", e),
+        )
+    })?;
+    inspect_accumulators(db, jq, state).await
+}
+
+pub async fn get_snapshot(pool: DbPool, input_id: String) -> Result<SharedSnapshot> {
+    SharedSnapshot::from_model(load_snapshot(pool, input_id).await?)
+}
+
+/// 读取快照并按指定名称的规则集生成标注
+pub async fn get_annotated_snapshot(
+    pool: DbPool,
+    input_id: String,
+    rules_name: String,
+) -> Result<SharedSnapshot> {
+    let rules = annotation::get_annotation_rules(pool.clone(), rules_name).await?;
+    let mut snapshot = get_snapshot(pool, input_id).await?;
+    snapshot.annotations = Some(annotation::annotate_bundle(&rules, &snapshot.data)?);
+    Ok(snapshot)
+}
+
+/// 快照生成时的配置
+pub async fn get_snapshot_config(pool: DbPool, input_id: String) -> Result<AnalysisConfig> {
+    let snapshot = load_snapshot(pool, input_id).await?;
+    AnalysisConfig::from_json(&snapshot.config)
+}
+
+async fn load_snapshot(pool: DbPool, input_id: String) -> Result<Snapshot> {
+    let data = tokio::task::spawn_blocking(move || {
+        use crate::schema::snapshots::dsl::*;
+        let conn = pool.get()?;
+        snapshots
+            .find(&input_id)
+            .first::<Snapshot>(&conn)
+            .optional()
+            .map_err(Error::from)
+            .and_then(|s| {
+                s.ok_or_else(|| {
+                    Error::custom(
+                        ErrorKind::NotFound,
+                        format!("snapshot {} not found", input_id),
+                    )
+                })
+            })
+    })
+    .await??;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_state_requests() {
+        let state: ShareState = serde_json::from_str(
+            r#"{"code":"600000.XSHG","tick":"30m","start_dt":"2020-07-01","stroke_cfg":"indep_k:false"}"#,
+        )
+        .unwrap();
+        let reqs = state.requests();
+        assert_eq!(8, reqs.len());
+        assert_eq!(Request::StrokeCfg("indep_k:false".to_owned()), reqs[2]);
+        assert_eq!(Request::WarmupCfg(0), reqs[5]);
+        assert_eq!(Request::AsOf(String::new()), reqs[6]);
+        match reqs.last() {
+            Some(Request::Query { objects, .. }) => {
+                assert_eq!(6, objects.len());
+                assert!(objects.contains(&QueryObject::Centers));
+            }
+            _ => panic!("last request should be query"),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! 会话查询限流
+//!
+//! 令牌桶限制每个会话的查询频率，桶容量即允许的突发查询数。
+//! 与上一次查询完全相同且间隔过短的查询直接拒绝，避免客户端抖动导致重复计算。
+
+use std::time::{Duration, Instant};
+
+/// 限流配置
+#[derive(Debug, Clone)]
+pub struct ThrottleConfig {
+    // 每秒补充的令牌数，0表示不限流
+    pub rate: f64,
+    // 桶容量
+    pub burst: usize,
+    // 相同查询的最小间隔
+    pub debounce: Duration,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        ThrottleConfig {
+            rate: 2.0,
+            burst: 5,
+            debounce: Duration::from_millis(300),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Throttle<T> {
+    cfg: ThrottleConfig,
+    tokens: f64,
+    refilled_at: Option<Instant>,
+    // 上一次放行的查询及时刻
+    last: Option<(T, Instant)>,
+}
+
+impl<T: PartialEq> Throttle<T> {
+    pub fn new(cfg: ThrottleConfig) -> Self {
+        Throttle {
+            tokens: cfg.burst as f64,
+            cfg,
+            refilled_at: None,
+            last: None,
+        }
+    }
+
+    /// 尝试放行查询，被拒绝时返回需等待的时长
+    pub fn acquire(&mut self, query: T, now: Instant) -> std::result::Result<(), Duration> {
+        if let Some((ref last, at)) = self.last {
+            let elapsed = now.saturating_duration_since(at);
+            if last == &query && elapsed < self.cfg.debounce {
+                return Err(self.cfg.debounce - elapsed);
+            }
+        }
+        if self.cfg.rate > 0.0 {
+            if let Some(at) = self.refilled_at {
+                let elapsed = now.saturating_duration_since(at).as_secs_f64();
+                self.tokens = (self.tokens + elapsed * self.cfg.rate).min(self.cfg.burst as f64);
+            }
+            self.refilled_at = Some(now);
+            if self.tokens < 1.0 {
+                let wait = (1.0 - self.tokens) / self.cfg.rate;
+                return Err(Duration::from_secs_f64(wait));
+            }
+            self.tokens -= 1.0;
+        }
+        self.last = Some((query, now));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle() {
+        let mut throttle = Throttle::new(ThrottleConfig {
+            rate: 2.0,
+            burst: 2,
+            debounce: Duration::from_millis(300),
+        });
+        let t0 = Instant::now();
+        assert_eq!(Ok(()), throttle.acquire(1, t0));
+        // 相同查询间隔过短
+        assert_eq!(
+            Err(Duration::from_millis(200)),
+            throttle.acquire(1, t0 + Duration::from_millis(100))
+        );
+        assert_eq!(Ok(()), throttle.acquire(2, t0 + Duration::from_millis(100)));
+        // 令牌耗尽，补充速率为每秒2个
+        let wait = throttle
+            .acquire(3, t0 + Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(400, (wait.as_secs_f64() * 1000.0).round() as u64);
+        assert_eq!(Ok(()), throttle.acquire(3, t0 + Duration::from_millis(600)));
+
+        let mut unlimited = Throttle::new(ThrottleConfig {
+            rate: 0.0,
+            burst: 0,
+            debounce: Duration::from_millis(0),
+        });
+        for _ in 0..10 {
+            assert_eq!(Ok(()), unlimited.acquire(1, t0));
+        }
+    }
+}
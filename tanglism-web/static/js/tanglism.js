@@ -6,7 +6,7 @@ $(document).ready(function() {
   $("#input_stock_code").autocomplete({
     source: function(req, callback) {
       $.ajax({
-        url: "/api/keyword-stocks?keyword=" + encodeURIComponent(req.term),
+        url: "/api/v1/keyword-stocks?keyword=" + encodeURIComponent(req.term),
         method: "GET",
         dataType: "json",
         success: function(resp) {
@@ -71,7 +71,7 @@ $(document).ready(function() {
   $("#atr_submit").click(function(){
     var atrp_days = $("#atr_days_input").val();
     $.ajax({
-      url: "/api/prioritized-stocks?atrp_days=" + encodeURIComponent(atrp_days),
+      url: "/api/v1/prioritized-stocks?atrp_days=" + encodeURIComponent(atrp_days),
       method: "GET",
       dataType: "json",
       success: function(resp) {